@@ -15,6 +15,7 @@ export default defineConfig({
 					items: [
 						'guides/getting-started',
 						'guides/raw-recordings',
+						'guides/json-reports',
 						'guides/known-issues'
 					]
 				},
@@ -0,0 +1,26 @@
+#!/bin/sh
+# Streams process lifecycle events from a private ftrace instance until it's killed.
+#
+# The instance is removed on exit so that the global tracing settings are untouched.
+set -e
+
+tracefs=/sys/kernel/tracing
+if [ ! -d "$tracefs/instances" ]; then
+  tracefs=/sys/kernel/debug/tracing
+fi
+instance="$tracefs/instances/proctrace-$$"
+mkdir "$instance"
+trap 'echo 0 > "$instance/tracing_on"; rmdir "$instance"' EXIT
+trap 'exit 0' INT TERM PIPE
+
+echo mono > "$instance/trace_clock"
+# Shows which process each thread belongs to, not every kernel supports this
+echo 1 > "$instance/options/record-tgid" 2>/dev/null || true
+# Skip new threads (CLONE_THREAD)
+echo '(clone_flags & 0x10000) == 0' > "$instance/events/task/task_newtask/filter"
+for event in task/task_newtask sched/sched_process_exec sched/sched_process_exit; do
+  echo 1 > "$instance/events/$event/enable"
+done
+
+echo "Tracing with $instance"
+cat "$instance/trace_pipe"
@@ -0,0 +1,453 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    path::Path,
+};
+
+use regex_lite::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::models::{Event, EventStore};
+
+/// How long each command ran for in a single recording.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunSummary {
+    /// The time from the first event to the last event in nanoseconds.
+    pub total: u128,
+    /// The total time spent running each command in nanoseconds, keyed by the file
+    /// name of the executable.
+    ///
+    /// A command starts when it's exec'd and stops when the process exits or execs
+    /// something else. The time of every invocation of a command is added up.
+    pub commands: BTreeMap<String, u128>,
+}
+
+impl RunSummary {
+    /// Summarizes the events of a single recording.
+    pub fn from_store(store: &EventStore) -> Self {
+        let mut summary = Self::default();
+        let mut first = None;
+        let mut last = None;
+        for (_, buffer) in store.iter() {
+            for event in buffer.iter() {
+                let ts = event.timestamp();
+                first = Some(first.map_or(ts, |first: u128| first.min(ts)));
+                last = Some(last.map_or(ts, |last: u128| last.max(ts)));
+            }
+            let execs = buffer
+                .iter()
+                .enumerate()
+                .filter_map(|(idx, event)| match event {
+                    Event::ExecFull { filename, .. } => Some((idx, command_name(filename))),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            for (n, (idx, name)) in execs.iter().enumerate() {
+                let start = buffer[*idx].timestamp();
+                let stop = match execs.get(n + 1) {
+                    Some((next_idx, _)) => buffer[*next_idx].timestamp(),
+                    None => buffer
+                        .back()
+                        .map(|event| event.timestamp())
+                        .unwrap_or(start),
+                };
+                *summary.commands.entry(name.clone()).or_default() += stop.saturating_sub(start);
+            }
+        }
+        summary.total = match (first, last) {
+            (Some(first), Some(last)) => last - first,
+            _ => 0,
+        };
+        summary
+    }
+}
+
+/// Returns the name used to group invocations of an executable.
+pub fn command_name(filename: &str) -> String {
+    Path::new(filename)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(filename)
+        .to_string()
+}
+
+/// Returns the command line of the last thing a process exec'd, or "(fork of <ppid>)"
+/// if it never exec'd anything.
+pub fn command_line(buffer: &VecDeque<Event>) -> String {
+    buffer
+        .iter()
+        .rev()
+        .find_map(|event| match event {
+            Event::ExecFull { args, .. } => Some(args.joined()),
+            _ => None,
+        })
+        .or_else(|| {
+            buffer
+                .front()
+                .and_then(|event| event.fork_parent())
+                .map(|ppid| format!("(fork of {ppid})"))
+        })
+        .unwrap_or_else(|| "(unknown)".to_string())
+}
+
+/// Summary statistics for a set of durations, in nanoseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Distribution {
+    /// The number of samples.
+    pub count: usize,
+    pub min: u128,
+    pub median: u128,
+    pub p95: u128,
+    pub max: u128,
+}
+
+impl Distribution {
+    /// Computes the distribution of the samples, returning `None` if there aren't any.
+    pub fn from_samples(samples: impl IntoIterator<Item = u128>) -> Option<Self> {
+        let mut samples = samples.into_iter().collect::<Vec<_>>();
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_unstable();
+        Some(Self {
+            count: samples.len(),
+            min: samples[0],
+            median: percentile(&samples, 50),
+            p95: percentile(&samples, 95),
+            max: samples[samples.len() - 1],
+        })
+    }
+}
+
+/// Returns the nearest-rank percentile of sorted, non-empty samples.
+fn percentile(sorted: &[u128], pct: usize) -> u128 {
+    let rank = (pct * sorted.len()).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+/// Duration distributions across several recordings of the same command.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BenchSummary {
+    /// The number of recordings.
+    pub runs: usize,
+    pub total: Distribution,
+    /// The distribution of the time spent in each command per run.
+    ///
+    /// Runs that never ran a command don't contribute a sample for it, so `count`
+    /// may be less than `runs`.
+    pub commands: BTreeMap<String, Distribution>,
+}
+
+impl BenchSummary {
+    /// Aggregates the summaries of several runs, returning `None` if there are none.
+    pub fn from_runs(runs: &[RunSummary]) -> Option<Self> {
+        let total = Distribution::from_samples(runs.iter().map(|run| run.total))?;
+        let mut samples: BTreeMap<&str, Vec<u128>> = BTreeMap::new();
+        for run in runs.iter() {
+            for (name, duration) in run.commands.iter() {
+                samples.entry(name).or_default().push(*duration);
+            }
+        }
+        let commands = samples
+            .into_iter()
+            .filter_map(|(name, samples)| {
+                Distribution::from_samples(samples).map(|dist| (name.to_string(), dist))
+            })
+            .collect();
+        Some(Self {
+            runs: runs.len(),
+            total,
+            commands,
+        })
+    }
+
+    /// Returns the median durations as if they were from a single run.
+    pub fn medians(&self) -> RunSummary {
+        RunSummary {
+            total: self.total.median,
+            commands: self
+                .commands
+                .iter()
+                .map(|(name, dist)| (name.clone(), dist.median))
+                .collect(),
+        }
+    }
+
+    /// Formats the summary as a table with one row per command.
+    pub fn to_table(&self) -> String {
+        let mut rows = vec![("total".to_string(), self.total)];
+        let mut commands = self
+            .commands
+            .iter()
+            .map(|(name, dist)| (name.clone(), *dist))
+            .collect::<Vec<_>>();
+        // Show the commands that take the most time first
+        commands.sort_by_key(|(_, dist)| std::cmp::Reverse(dist.median));
+        rows.extend(commands);
+        let width = rows
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0)
+            .max("command".len());
+        let mut table = format!(
+            "{:<width$}  {:>4}  {:>12}  {:>12}  {:>12}\n",
+            "command", "runs", "min", "median", "p95"
+        );
+        for (name, dist) in rows.iter() {
+            table.push_str(&format!(
+                "{:<width$}  {:>4}  {:>12}  {:>12}  {:>12}\n",
+                name,
+                dist.count,
+                format_nanos(dist.min),
+                format_nanos(dist.median),
+                format_nanos(dist.p95)
+            ));
+        }
+        table
+    }
+}
+
+/// A named group of commands, such as the hook scripts run while activating an
+/// environment.
+#[derive(Debug, Clone)]
+pub struct Phase {
+    pub name: String,
+    /// Matched against the file name and the joined arguments of each exec.
+    pub pattern: Regex,
+}
+
+impl Phase {
+    pub fn new(name: &str, pattern: &str) -> Result<Self, regex_lite::Error> {
+        Ok(Self {
+            name: name.to_string(),
+            pattern: Regex::new(pattern)?,
+        })
+    }
+
+    fn matches(&self, event: &Event) -> bool {
+        match event {
+            Event::ExecFull { filename, args, .. } => {
+                self.pattern.is_match(filename) || self.pattern.is_match(&args.joined())
+            }
+            _ => false,
+        }
+    }
+}
+
+/// How long the processes in a phase ran for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhaseTiming {
+    pub name: String,
+    /// The number of processes that were tagged with this phase.
+    pub count: usize,
+    /// The time spent in the phase in nanoseconds, added up across processes.
+    pub duration: u128,
+    /// The process that spent the longest in this phase.
+    pub slowest: Option<SlowestProcess>,
+}
+
+/// The process that spent the longest in a phase.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlowestProcess {
+    pub pid: i32,
+    pub command: String,
+    /// The time it spent in the phase in nanoseconds.
+    pub duration: u128,
+}
+
+/// The time spent in each phase of a recording.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhaseReport {
+    /// The time from the first event to the last event in nanoseconds.
+    pub total: u128,
+    /// The phases in the order they were given, including ones that never ran.
+    pub phases: Vec<PhaseTiming>,
+}
+
+impl PhaseReport {
+    /// Tags processes with the first phase that matches one of their execs.
+    ///
+    /// A process is in its phase from the matching exec until its last event, which
+    /// includes the time spent waiting on its children. Descendants tagged with the
+    /// same phase aren't counted again, but a process can be nested inside a
+    /// process from another phase (e.g. a hook run by an activation script), so
+    /// phases may overlap.
+    pub fn from_store(store: &EventStore, phases: &[Phase]) -> Self {
+        let total = RunSummary::from_store(store).total;
+        let mut tagged: HashMap<i32, (usize, String, u128)> = HashMap::new();
+        for (pid, buffer) in store.iter() {
+            let found = buffer.iter().find_map(|event| {
+                let idx = phases.iter().position(|phase| phase.matches(event))?;
+                Some((idx, event))
+            });
+            let Some((idx, exec)) = found else {
+                continue;
+            };
+            let command = match exec {
+                Event::ExecFull { args, .. } => args.joined(),
+                _ => unreachable!("only execs match a phase"),
+            };
+            let stop = buffer.back().map_or(0, |event| event.timestamp());
+            let duration = stop.saturating_sub(exec.timestamp());
+            tagged.insert(pid, (idx, command, duration));
+        }
+        let mut timings = phases
+            .iter()
+            .map(|phase| PhaseTiming {
+                name: phase.name.clone(),
+                count: 0,
+                duration: 0,
+                slowest: None,
+            })
+            .collect::<Vec<_>>();
+        for (pid, (idx, command, duration)) in tagged.iter() {
+            let nested = store
+                .ancestors(*pid)
+                .any(|ppid| tagged.get(&ppid).is_some_and(|(other, _, _)| other == idx));
+            let timing = &mut timings[*idx];
+            timing.count += 1;
+            if nested {
+                continue;
+            }
+            timing.duration += duration;
+            if timing
+                .slowest
+                .as_ref()
+                .is_none_or(|slowest| *duration > slowest.duration)
+            {
+                timing.slowest = Some(SlowestProcess {
+                    pid: *pid,
+                    command: command.clone(),
+                    duration: *duration,
+                });
+            }
+        }
+        Self {
+            total,
+            phases: timings,
+        }
+    }
+
+    /// Formats the report as a table with one row per phase.
+    pub fn to_table(&self) -> String {
+        let width = self
+            .phases
+            .iter()
+            .map(|phase| phase.name.len())
+            .max()
+            .unwrap_or(0)
+            .max("phase".len());
+        let mut table = format!(
+            "{:<width$}  {:>5}  {:>12}  {:>6}  slowest\n",
+            "phase", "procs", "time", "share"
+        );
+        for phase in self.phases.iter() {
+            let share = if self.total == 0 {
+                0.0
+            } else {
+                phase.duration as f64 / self.total as f64 * 100.0
+            };
+            let slowest = phase
+                .slowest
+                .as_ref()
+                .map(|slowest| format!("{} (PID {})", slowest.command, slowest.pid))
+                .unwrap_or_default();
+            table.push_str(&format!(
+                "{:<width$}  {:>5}  {:>12}  {:>5.1}%  {slowest}\n",
+                phase.name,
+                phase.count,
+                format_nanos(phase.duration),
+                share
+            ));
+        }
+        table.push_str(&format!("total: {}\n", format_nanos(self.total)));
+        table
+    }
+}
+
+/// Formats a duration in nanoseconds with a unit that keeps it readable.
+pub fn format_nanos(nanos: u128) -> String {
+    let nanos = nanos as f64;
+    if nanos >= 1e9 {
+        format!("{:.3}s", nanos / 1e9)
+    } else if nanos >= 1e6 {
+        format!("{:.3}ms", nanos / 1e6)
+    } else {
+        format!("{:.3}us", nanos / 1e3)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ingest::test::make_simple_events;
+
+    use super::*;
+
+    #[test]
+    fn computes_percentiles() {
+        let dist = Distribution::from_samples((1..=20).rev()).unwrap();
+        assert_eq!(dist.count, 20);
+        assert_eq!(dist.min, 1);
+        assert_eq!(dist.median, 10);
+        assert_eq!(dist.p95, 19);
+        assert_eq!(dist.max, 20);
+        assert!(Distribution::from_samples([]).is_none());
+    }
+
+    #[test]
+    fn summarizes_commands() {
+        let events = make_simple_events(
+            0,
+            0,
+            &[
+                ("fork", 1, 0),
+                ("exec_full", 1, 0),
+                ("exec_full", 1, 0),
+                ("exit", 1, 0),
+            ],
+        );
+        let mut store = EventStore::new();
+        store.add_many(1, events.iter().cloned());
+        let summary = RunSummary::from_store(&store);
+        let first = events.first().unwrap().timestamp();
+        let last = events.last().unwrap().timestamp();
+        assert_eq!(summary.total, last - first);
+        let exec_time = last - events[1].timestamp();
+        assert_eq!(summary.commands.values().sum::<u128>(), exec_time);
+
+        let bench = BenchSummary::from_runs(&[summary.clone(), summary]).unwrap();
+        assert_eq!(bench.runs, 2);
+        assert_eq!(bench.total.median, last - first);
+    }
+
+    #[test]
+    fn tags_phases() {
+        let events = make_simple_events(
+            0,
+            0,
+            &[
+                ("fork", 1, 0),
+                ("exec_full", 1, 0),
+                ("fork", 2, 1),
+                ("exec_full", 2, 1),
+                ("exit", 2, 1),
+                ("fork", 3, 1),
+                ("exit", 3, 1),
+                ("exit", 1, 0),
+            ],
+        );
+        let store = EventStore::from_events(events.iter().cloned());
+        let phases = [
+            Phase::new("bar", "/foo/bar").unwrap(),
+            Phase::new("never", "/never").unwrap(),
+        ];
+        let report = PhaseReport::from_store(&store, &phases);
+        let bar = &report.phases[0];
+        // PID 2 is nested inside PID 1, so only PID 1 contributes time
+        assert_eq!(bar.count, 2);
+        assert_eq!(bar.duration, events[7].timestamp() - events[1].timestamp());
+        assert_eq!(bar.slowest.as_ref().map(|slowest| slowest.pid), Some(1));
+        assert_eq!(report.phases[1].count, 0);
+        assert!(report.to_table().contains("never"));
+    }
+}
@@ -0,0 +1,201 @@
+//! Attributes the time spent by every process to the child of the root it descends
+//! from, which shows which part of a build (e.g. which `make` target) is slow.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    analyze::format_nanos,
+    models::{Event, EventStore},
+};
+
+/// A child of the root and everything it started.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Subtree {
+    pub pid: i32,
+    /// The last command line the child exec'd.
+    pub command: String,
+    /// The number of processes in the subtree, including the child.
+    pub processes: usize,
+    /// The time from the child's fork to the last exit in the subtree.
+    pub wall: u128,
+    /// The combined lifetime of every process in the subtree, which counts
+    /// processes that ran in parallel separately.
+    pub process_time: u128,
+}
+
+/// The subtrees of the root's children, and how much of the recording each one
+/// is responsible for.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlameReport {
+    /// The lifetime of the root process.
+    pub wall: u128,
+    /// The combined lifetime of every process below the root.
+    pub process_time: u128,
+    /// Sorted by process time, most first.
+    pub subtrees: Vec<Subtree>,
+}
+
+impl BlameReport {
+    /// Attributes every process below `root_pid` to the child of the root that it
+    /// descends from.
+    ///
+    /// Processes that aren't descendants of the root (e.g. adopted ones) aren't
+    /// counted. CPU time isn't recorded, so time is measured as how long processes
+    /// were alive.
+    pub fn from_store(store: &EventStore, root_pid: i32) -> Self {
+        let span = |buffer: &VecDeque<Event>| {
+            let start = buffer.front().map(|event| event.timestamp()).unwrap_or(0);
+            let stop = buffer
+                .back()
+                .map(|event| event.timestamp())
+                .unwrap_or(start);
+            (start, stop)
+        };
+        let mut subtrees: BTreeMap<i32, Subtree> = BTreeMap::new();
+        let mut spans: HashMap<i32, (u128, u128)> = HashMap::new();
+        let mut report = Self::default();
+        for (pid, buffer) in store.iter() {
+            let (start, stop) = span(buffer);
+            if pid == root_pid {
+                report.wall = stop - start;
+                continue;
+            }
+            let Some(child) = child_of_root(store, pid, root_pid) else {
+                continue;
+            };
+            let subtree = subtrees.entry(child).or_insert_with(|| Subtree {
+                pid: child,
+                command: String::new(),
+                processes: 0,
+                wall: 0,
+                process_time: 0,
+            });
+            subtree.processes += 1;
+            subtree.process_time += stop - start;
+            report.process_time += stop - start;
+            if pid == child {
+                subtree.command = buffer
+                    .iter()
+                    .rev()
+                    .find_map(|event| match event {
+                        Event::ExecFull { args, .. } => Some(args.joined()),
+                        _ => None,
+                    })
+                    .unwrap_or_else(|| format!("(fork of {root_pid})"));
+            }
+            let bounds = spans.entry(child).or_insert((start, stop));
+            *bounds = (bounds.0.min(start), bounds.1.max(stop));
+        }
+        report.subtrees = subtrees
+            .into_values()
+            .map(|mut subtree| {
+                let (start, stop) = spans[&subtree.pid];
+                subtree.wall = stop - start;
+                subtree
+            })
+            .collect();
+        report
+            .subtrees
+            .sort_by_key(|subtree| (std::cmp::Reverse(subtree.process_time), subtree.pid));
+        report
+    }
+
+    /// Formats the first `limit` subtrees as a table.
+    pub fn to_table(&self, limit: usize) -> String {
+        let share = |part: u128, whole: u128| {
+            if whole == 0 {
+                0.0
+            } else {
+                part as f64 / whole as f64 * 100.0
+            }
+        };
+        let mut table = format!(
+            "{:>8}  {:>6}  {:>12}  {:>6}  {:>12}  {:>6}  {}\n",
+            "pid", "procs", "wall", "wall%", "proc time", "share", "command"
+        );
+        for subtree in self.subtrees.iter().take(limit) {
+            table.push_str(&format!(
+                "{:>8}  {:>6}  {:>12}  {:>5.1}%  {:>12}  {:>5.1}%  {}\n",
+                subtree.pid,
+                subtree.processes,
+                format_nanos(subtree.wall),
+                share(subtree.wall, self.wall),
+                format_nanos(subtree.process_time),
+                share(subtree.process_time, self.process_time),
+                subtree.command
+            ));
+        }
+        if self.subtrees.len() > limit {
+            table.push_str(&format!("... and {} more\n", self.subtrees.len() - limit));
+        }
+        table.push_str(&format!(
+            "root ran for {} with {} of process time below it\n",
+            format_nanos(self.wall),
+            format_nanos(self.process_time)
+        ));
+        table
+    }
+}
+
+/// Returns the ancestor of `pid` that's a direct child of `root_pid`, if it's a
+/// descendant of the root at all.
+fn child_of_root(store: &EventStore, pid: i32, root_pid: i32) -> Option<i32> {
+    let mut child = pid;
+    for ancestor in store.ancestors(pid) {
+        if ancestor == root_pid {
+            return Some(child);
+        }
+        child = ancestor;
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn process(store: &mut EventStore, pid: i32, ppid: i32, start: u128, stop: u128) {
+        let fork = Event::Fork {
+            seq: start,
+            timestamp: start,
+            parent_pid: ppid,
+            child_pid: pid,
+            parent_pgid: ppid,
+            credentials: None,
+            comm: None,
+        };
+        let exit = Event::Exit {
+            seq: stop,
+            timestamp: stop,
+            pid,
+            ppid,
+            pgid: pid,
+            exit_code: Some(0),
+        };
+        store.add(pid, fork);
+        store.add(pid, exit);
+    }
+
+    #[test]
+    fn attributes_descendants_to_children_of_root() {
+        let mut store = EventStore::new();
+        process(&mut store, 1, 0, 0, 100);
+        // A short child and a long one with two parallel grandchildren
+        process(&mut store, 2, 1, 0, 10);
+        process(&mut store, 3, 1, 10, 50);
+        process(&mut store, 4, 3, 10, 90);
+        process(&mut store, 5, 3, 20, 40);
+        // Not part of the root's tree
+        process(&mut store, 6, 99, 0, 100);
+
+        let report = BlameReport::from_store(&store, 1);
+        assert_eq!(report.wall, 100);
+        assert_eq!(report.process_time, 10 + 40 + 80 + 20);
+        let pids = report.subtrees.iter().map(|s| s.pid).collect::<Vec<_>>();
+        assert_eq!(pids, vec![3, 2]);
+        let slow = &report.subtrees[0];
+        assert_eq!((slow.processes, slow.wall, slow.process_time), (3, 80, 140));
+    }
+}
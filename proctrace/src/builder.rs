@@ -0,0 +1,423 @@
+//! Builds synthetic recordings, e.g. fixtures for the tests of tools that read
+//! recordings, without running anything under a recorder.
+//!
+//! Events happen at the current time of the builder, which only moves forward when
+//! it's advanced, so processes that run side by side are built by interleaving their
+//! events:
+//!
+//! ```
+//! use proctrace::builder::TraceBuilder;
+//!
+//! let ms = 1_000_000;
+//! let events = TraceBuilder::new()
+//!     .fork(100, 1)
+//!     .exec(1, "make -j2")
+//!     .advance(5 * ms)
+//!     .fork(1, 2)
+//!     .exec(2, "cc -c a.c")
+//!     .fork(1, 3)
+//!     .exec(3, "cc -c b.c")
+//!     .advance(40 * ms)
+//!     .exit(2, 0)
+//!     .advance(10 * ms)
+//!     .exit(3, 1)
+//!     .exit(1, 2)
+//!     .build();
+//! assert_eq!(events.len(), 9);
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    models::{Credentials, Event, ExecArgsKind, Metadata, NamespaceChange, ResourceUsage},
+    writers::EventWrite,
+};
+
+type Error = anyhow::Error;
+
+/// Builds the events of a processed recording.
+#[derive(Debug, Clone, Default)]
+pub struct TraceBuilder {
+    events: Vec<Event>,
+    seq: u128,
+    timestamp: u128,
+    /// The parent and process group of each process that has been forked.
+    processes: HashMap<i32, (i32, i32)>,
+}
+
+impl TraceBuilder {
+    /// Creates a builder whose clock starts at 0.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a builder whose clock starts at `timestamp`, in nanoseconds.
+    pub fn starting_at(timestamp: u128) -> Self {
+        Self {
+            timestamp,
+            ..Self::default()
+        }
+    }
+
+    /// Moves the clock forward by `nanos` nanoseconds.
+    pub fn advance(mut self, nanos: u128) -> Self {
+        self.timestamp += nanos;
+        self
+    }
+
+    /// Forks `child` from `parent`, which puts it in the process group of `parent`.
+    ///
+    /// The first fork is the start of the recording, so `child` is the root process.
+    pub fn fork(mut self, parent: i32, child: i32) -> Self {
+        let parent_pgid = self.pgid_of(parent);
+        self.processes.insert(child, (parent, parent_pgid));
+        let (seq, timestamp) = self.tick();
+        self.events.push(Event::Fork {
+            seq,
+            timestamp,
+            parent_pid: parent,
+            child_pid: child,
+            parent_pgid,
+            credentials: None,
+            comm: None,
+        });
+        self
+    }
+
+    /// Forks `child` from `parent` like [TraceBuilder::fork], recording that it was
+    /// forked as the user `uid` and the group `gid`.
+    pub fn fork_as(mut self, parent: i32, child: i32, uid: u32, gid: u32) -> Self {
+        self = self.fork(parent, child);
+        if let Some(Event::Fork { credentials, .. }) = self.events.last_mut() {
+            *credentials = Some(Credentials { uid, gid });
+        }
+        self
+    }
+
+    /// Forks `child` from `parent` like [TraceBuilder::fork], recording the name
+    /// (`comm`) that it inherited from `parent`.
+    pub fn fork_named(mut self, parent: i32, child: i32, name: &str) -> Self {
+        self = self.fork(parent, child);
+        if let Some(Event::Fork { comm, .. }) = self.events.last_mut() {
+            *comm = Some(name.to_string());
+        }
+        self
+    }
+
+    /// Execs `command` in `pid`, whose first word is the file that's exec'd.
+    pub fn exec(mut self, pid: i32, command: &str) -> Self {
+        let filename = command.split_whitespace().next().unwrap_or_default();
+        let (ppid, pgid) = self.parent_and_pgid(pid);
+        let (seq, timestamp) = self.tick();
+        self.events.push(Event::ExecFull {
+            seq,
+            timestamp,
+            pid,
+            ppid,
+            pgid,
+            filename: filename.to_string(),
+            args: ExecArgsKind::Joined(command.to_string()),
+            credentials: None,
+        });
+        self
+    }
+
+    /// Fails to exec `filename` in `pid` with `errno`, e.g. 2 for `ENOENT`.
+    pub fn failed_exec(mut self, pid: i32, filename: &str, errno: i32) -> Self {
+        let (seq, timestamp) = self.tick();
+        self.events.push(Event::BadExec {
+            seq,
+            timestamp,
+            pid,
+            filename: Some(filename.to_string()),
+            errno: Some(errno),
+        });
+        self
+    }
+
+    /// Exits `pid` with `status`, e.g. 0 for success.
+    pub fn exit(self, pid: i32, status: u8) -> Self {
+        self.exit_with_wait_status(pid, (status as i32) << 8)
+    }
+
+    /// Kills `pid` with `signal`, e.g. 9 for `SIGKILL`.
+    pub fn killed(self, pid: i32, signal: i32) -> Self {
+        self.exit_with_wait_status(pid, signal & 0x7f)
+    }
+
+    /// Kills `pid` with `signal`, e.g. 11 for `SIGSEGV`, after the kernel dumped its
+    /// core.
+    pub fn dumped_core(self, pid: i32, signal: i32) -> Self {
+        self.exit_with_wait_status(pid, (signal & 0x7f) | 0x80)
+    }
+
+    fn exit_with_wait_status(mut self, pid: i32, wait_status: i32) -> Self {
+        let (ppid, pgid) = self.parent_and_pgid(pid);
+        let (seq, timestamp) = self.tick();
+        self.events.push(Event::Exit {
+            seq,
+            timestamp,
+            pid,
+            ppid,
+            pgid,
+            exit_code: Some(wait_status),
+        });
+        self
+    }
+
+    /// Starts a new session with `pid` as its leader, e.g. to daemonize.
+    pub fn setsid(mut self, pid: i32) -> Self {
+        let (ppid, _) = self.parent_and_pgid(pid);
+        self.processes.insert(pid, (ppid, pid));
+        let (seq, timestamp) = self.tick();
+        self.events.push(Event::SetSID {
+            seq,
+            timestamp,
+            pid,
+            ppid,
+            pgid: pid,
+            sid: pid,
+        });
+        self
+    }
+
+    /// Moves `pid` into the process group `pgid`, e.g. a command of a pipeline.
+    pub fn setpgid(mut self, pid: i32, pgid: i32) -> Self {
+        let (ppid, _) = self.parent_and_pgid(pid);
+        self.processes.insert(pid, (ppid, pgid));
+        let (seq, timestamp) = self.tick();
+        self.events.push(Event::SetPGID {
+            seq,
+            timestamp,
+            pid,
+            ppid,
+            pgid,
+            caller_pid: None,
+        });
+        self
+    }
+
+    /// Changes the working directory of `pid` to `path`.
+    pub fn chdir(mut self, pid: i32, path: &str) -> Self {
+        let (seq, timestamp) = self.tick();
+        self.events.push(Event::Chdir {
+            seq,
+            timestamp,
+            pid,
+            path: Some(path.to_string()),
+        });
+        self
+    }
+
+    /// Puts `pid` in new `namespaces` (e.g. "mnt" or "net") the way that `how` says.
+    pub fn namespace(mut self, pid: i32, how: NamespaceChange, namespaces: &[&str]) -> Self {
+        let (seq, timestamp) = self.tick();
+        self.events.push(Event::Namespace {
+            seq,
+            timestamp,
+            pid,
+            how,
+            namespaces: namespaces.iter().map(|name| name.to_string()).collect(),
+        });
+        self
+    }
+
+    /// Makes `pid` a child subreaper, so that orphans below it are reparented to it.
+    pub fn subreaper(mut self, pid: i32) -> Self {
+        let (seq, timestamp) = self.tick();
+        self.events.push(Event::Subreaper {
+            seq,
+            timestamp,
+            pid,
+        });
+        self
+    }
+
+    /// Samples `pid` while it's running on a CPU, see [`Metadata::cpu_sample_hz`].
+    pub fn on_cpu(mut self, pid: i32) -> Self {
+        let (seq, timestamp) = self.tick();
+        self.events.push(Event::CpuSample {
+            seq,
+            timestamp,
+            pid,
+        });
+        self
+    }
+
+    /// Samples the memory use of `pid`, which was `rss_kb` KiB resident.
+    pub fn rss(mut self, pid: i32, rss_kb: u64) -> Self {
+        let (seq, timestamp) = self.tick();
+        self.events.push(Event::RssSample {
+            seq,
+            timestamp,
+            pid,
+            rss_kb,
+        });
+        self
+    }
+
+    /// Records the page faults and context switches of `pid`, which the recorder does
+    /// right before it exits.
+    pub fn usage(mut self, pid: i32, usage: ResourceUsage) -> Self {
+        let (seq, timestamp) = self.tick();
+        self.events.push(Event::Usage {
+            seq,
+            timestamp,
+            pid,
+            usage,
+        });
+        self
+    }
+
+    /// Records the resources that one of the recorder's own processes used, which the
+    /// recorder does when the recording ends.
+    pub fn overhead(mut self, pid: i32, command: &str, usage: ResourceUsage) -> Self {
+        let (seq, timestamp) = self.tick();
+        self.events.push(Event::Overhead {
+            seq,
+            timestamp,
+            pid,
+            command: command.to_string(),
+            usage,
+        });
+        self
+    }
+
+    /// Adds a custom event to `pid`, which is rendered as a marker on its process.
+    pub fn custom<K, V>(
+        mut self,
+        pid: i32,
+        name: &str,
+        fields: impl IntoIterator<Item = (K, V)>,
+    ) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let (seq, timestamp) = self.tick();
+        self.events.push(Event::Custom {
+            seq,
+            timestamp,
+            pid,
+            name: name.to_string(),
+            fields: fields
+                .into_iter()
+                .map(|(key, value)| (key.into(), value.into()))
+                .collect::<BTreeMap<_, _>>(),
+        });
+        self
+    }
+
+    /// Returns the metadata of the recording, whose root is the first process that
+    /// was forked.
+    pub fn metadata(&self) -> Metadata {
+        let root_pid = self.events.iter().find_map(|event| match event {
+            Event::Fork { child_pid, .. } => Some(*child_pid),
+            _ => None,
+        });
+        let mut metadata = Metadata::new(root_pid);
+        metadata.command = self
+            .events
+            .iter()
+            .find_map(|event| match event {
+                Event::ExecFull { pid, args, .. } if Some(*pid) == root_pid => {
+                    Some(args.joined().split_whitespace().map(String::from).collect())
+                }
+                _ => None,
+            })
+            .unwrap_or_default();
+        metadata
+    }
+
+    /// Returns the events in the order they happened.
+    pub fn build(self) -> Vec<Event> {
+        self.events
+    }
+
+    /// Writes the metadata and events as a recording, then finalizes the writer.
+    pub fn write(self, mut writer: impl EventWrite) -> Result<(), Error> {
+        writer.write_metadata(&self.metadata())?;
+        for event in self.events.iter() {
+            writer.write_event(event)?;
+        }
+        writer.finalize()
+    }
+
+    /// Returns the sequence number and time of the next event.
+    fn tick(&mut self) -> (u128, u128) {
+        let seq = self.seq;
+        self.seq += 1;
+        (seq, self.timestamp)
+    }
+
+    /// Processes that weren't forked are their own process group, like the parent of
+    /// the root process usually is.
+    fn pgid_of(&self, pid: i32) -> i32 {
+        self.processes
+            .get(&pid)
+            .map(|(_, pgid)| *pgid)
+            .unwrap_or(pid)
+    }
+
+    fn parent_and_pgid(&self, pid: i32) -> (i32, i32) {
+        self.processes.get(&pid).copied().unwrap_or((0, pid))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        analyze::RunSummary,
+        render::{read_events, render, RenderOptions},
+        writers::JsonWriter,
+    };
+
+    use super::*;
+
+    #[test]
+    fn builds_readable_recordings() {
+        let ms = 1_000_000;
+        let builder = TraceBuilder::starting_at(1_000)
+            .fork(100, 1)
+            .exec(1, "/bin/sh -c make")
+            .exec(1, "make -j2")
+            .fork(1, 2)
+            .exec(2, "cc -c a.c")
+            .advance(40 * ms)
+            .custom(2, "phase", [("name", "link")])
+            .failed_exec(1, "/usr/local/bin/ld", 2)
+            .exit(2, 1)
+            .fork(1, 3)
+            .setsid(3)
+            .advance(10 * ms)
+            .exit(1, 2)
+            // Outliving its parent in a session of its own makes it a daemon
+            .advance(ms)
+            .killed(3, 9);
+        let metadata = builder.metadata();
+        assert_eq!(metadata.root_pid, Some(1));
+        assert_eq!(metadata.command, vec!["/bin/sh", "-c", "make"]);
+
+        let mut bytes = vec![];
+        builder.write(JsonWriter::new(&mut bytes)).unwrap();
+        let ingester = read_events(bytes.as_slice()).unwrap();
+        let store = ingester.tracked_events();
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.pid_start_time(1), Some(1_000));
+        assert_eq!(RunSummary::from_store(store).total, 51 * ms);
+        let exit_code = |pid| store.events_of(pid).unwrap().back().unwrap().exit_code();
+        assert_eq!(exit_code(2), Some(1 << 8));
+        assert_eq!(exit_code(3), Some(9));
+        assert_eq!(store.daemon_pids().into_iter().collect::<Vec<_>>(), vec![3]);
+
+        let mut chart = vec![];
+        render(
+            bytes.as_slice(),
+            &mut chart,
+            crate::cli::DisplayMode::Mermaid,
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        assert!(String::from_utf8(chart).unwrap().contains("cc -c a.c"));
+    }
+}
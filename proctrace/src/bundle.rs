@@ -0,0 +1,146 @@
+//! Recording bundles, which keep everything about a recording in a single directory so
+//! that a raw recording can't be mixed up with the processed one it produced.
+//!
+//! A bundle contains:
+//! - `metadata.json`: the [Metadata] of the recording.
+//! - `raw.txt`: the raw events, which can be processed again with `proctrace ingest`.
+//! - `events.json` (or `events.cbor`, `events.indexed`): the processed events.
+//! - `report.md`: a summary of the recording with a Mermaid chart.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+
+use crate::{
+    labels::Labels,
+    models::Metadata,
+    render::{read_events, GhaSummaryRenderer, ProcessTree, Renderer},
+    utils::new_buffered_input_stream,
+    writers::TraceFormat,
+};
+
+type Error = anyhow::Error;
+
+pub const METADATA_FILE: &str = "metadata.json";
+pub const RAW_FILE: &str = "raw.txt";
+pub const REPORT_FILE: &str = "report.md";
+
+/// A directory containing a recording and everything derived from it.
+#[derive(Debug, Clone)]
+pub struct Bundle {
+    dir: PathBuf,
+}
+
+impl Bundle {
+    /// Creates the directory for a new bundle, which must be empty if it exists.
+    pub fn create(dir: impl AsRef<Path>) -> Result<Self, Error> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create bundle directory {}", dir.display()))?;
+        let is_empty = std::fs::read_dir(&dir)
+            .with_context(|| format!("failed to read bundle directory {}", dir.display()))?
+            .next()
+            .is_none();
+        if !is_empty {
+            return Err(anyhow!(
+                "bundle directory {} already exists and isn't empty",
+                dir.display()
+            ));
+        }
+        Ok(Self { dir })
+    }
+
+    /// Opens an existing bundle.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, Error> {
+        let bundle = Self {
+            dir: dir.as_ref().to_path_buf(),
+        };
+        if !bundle.metadata_path().exists() {
+            return Err(anyhow!(
+                "{} isn't a recording bundle, it has no {METADATA_FILE}",
+                bundle.dir.display()
+            ));
+        }
+        Ok(bundle)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn metadata_path(&self) -> PathBuf {
+        self.dir.join(METADATA_FILE)
+    }
+
+    pub fn raw_path(&self) -> PathBuf {
+        self.dir.join(RAW_FILE)
+    }
+
+    pub fn report_path(&self) -> PathBuf {
+        self.dir.join(REPORT_FILE)
+    }
+
+    /// The path of the processed events when they're encoded as `format`.
+    pub fn events_path_for(&self, format: TraceFormat) -> PathBuf {
+        self.dir.join(format!("events.{format}"))
+    }
+
+    /// The path of the processed events in whichever format they were written.
+    pub fn events_path(&self) -> Result<PathBuf, Error> {
+        [
+            TraceFormat::Json,
+            TraceFormat::Cbor,
+            TraceFormat::Indexed,
+            TraceFormat::IndexedZstd,
+        ]
+        .into_iter()
+        .map(|format| self.events_path_for(format))
+        .find(|path| path.exists())
+        .ok_or_else(|| anyhow!("bundle {} has no processed events", self.dir.display()))
+    }
+
+    pub fn write_metadata(&self, metadata: &Metadata) -> Result<(), Error> {
+        let file = File::create(self.metadata_path()).context("failed to create metadata file")?;
+        serde_json::to_writer_pretty(file, metadata).context("failed to write metadata")
+    }
+
+    pub fn read_metadata(&self) -> Result<Metadata, Error> {
+        let file = File::open(self.metadata_path()).context("failed to open metadata file")?;
+        serde_json::from_reader(BufReader::new(file)).context("failed to parse metadata")
+    }
+
+    /// Renders the report from the processed events, replacing any existing report.
+    pub fn write_report(&self) -> Result<(), Error> {
+        let events = new_buffered_input_stream(self.events_path()?)?;
+        let ingester = read_events(events).context("failed to read events from bundle")?;
+        let tree = ProcessTree::prepare(ingester, &Labels::default())?;
+        let mut report = BufWriter::new(
+            File::create(self.report_path()).context("failed to create report file")?,
+        );
+        GhaSummaryRenderer::default().render(&tree, &mut report)?;
+        report.flush().context("failed to write report")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn only_creates_empty_bundles() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = Bundle::create(dir.path().join("bundle")).unwrap();
+        let metadata = Metadata::new(Some(1));
+        bundle.write_metadata(&metadata).unwrap();
+
+        assert!(Bundle::create(bundle.dir()).is_err());
+        let opened = Bundle::open(bundle.dir()).unwrap();
+        assert_eq!(opened.read_metadata().unwrap(), metadata);
+        assert!(opened.events_path().is_err());
+        assert!(Bundle::open(dir.path()).is_err());
+    }
+}
@@ -0,0 +1,297 @@
+//! Per-crate timings for recordings of `cargo build`.
+
+use std::{collections::BTreeMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    analyze::{command_name, format_nanos},
+    models::{Event, EventStore},
+};
+
+/// The executables that are treated as linkers when they're run by `rustc`.
+const LINKERS: &[&str] = &[
+    "cc", "c++", "gcc", "g++", "clang", "clang++", "collect2", "ld", "ld.bfd", "ld.gold", "ld.lld",
+    "lld", "ld64.lld", "mold", "ld.mold", "wild",
+];
+
+/// What a process was doing as part of a build.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Role {
+    /// Compiling a crate, or a build script when `build_script` is set.
+    Rustc {
+        krate: String,
+        build_script: bool,
+    },
+    /// Running a build script.
+    BuildScript {
+        krate: String,
+    },
+    Linker,
+}
+
+impl Role {
+    /// Classifies a process from one of its execs.
+    fn from_exec(filename: &str, args: &[&str]) -> Option<Self> {
+        let name = command_name(filename);
+        if name == "rustc" {
+            let krate = arg_value(args, "--crate-name")?;
+            if krate == "build_script_build" {
+                // The build script is compiled into its own directory under
+                // `target/*/build/`, which is named after the package
+                let out_dir = arg_value(args, "--out-dir")?;
+                let krate = package_from_dir(Path::new(out_dir).file_name()?.to_str()?)?;
+                return Some(Role::Rustc {
+                    krate,
+                    build_script: true,
+                });
+            }
+            return Some(Role::Rustc {
+                krate: krate.to_string(),
+                build_script: false,
+            });
+        }
+        if name.starts_with("build-script-") {
+            let dir = Path::new(filename).parent()?.file_name()?.to_str()?;
+            return Some(Role::BuildScript {
+                krate: package_from_dir(dir)?,
+            });
+        }
+        if LINKERS.contains(&name.as_str()) {
+            return Some(Role::Linker);
+        }
+        None
+    }
+}
+
+/// Returns the value following `flag` in `args`.
+fn arg_value<'a>(args: &[&'a str], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| *arg == flag)
+        .and_then(|idx| args.get(idx + 1))
+        .copied()
+}
+
+/// Turns a `<package>-<hash>` directory name into the name of the crate.
+fn package_from_dir(dir: &str) -> Option<String> {
+    let (package, _hash) = dir.rsplit_once('-')?;
+    Some(package.replace('-', "_"))
+}
+
+/// How long was spent building a single crate, in nanoseconds.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrateTiming {
+    /// The number of times `rustc` was run for the crate, e.g. once for the library
+    /// and once for its tests.
+    pub units: usize,
+    /// The time spent in `rustc`, including linking.
+    pub rustc: u128,
+    /// The time spent in linkers run by `rustc`.
+    pub link: u128,
+    /// The time spent compiling and running the crate's build script.
+    pub build_script: u128,
+}
+
+impl CrateTiming {
+    /// The total time spent on the crate.
+    pub fn total(&self) -> u128 {
+        self.rustc + self.build_script
+    }
+}
+
+/// The time spent building each crate in a recording of a `cargo` build.
+///
+/// Unlike `cargo build --timings` this includes the time spent running build scripts
+/// and linkers, since each one is its own process in the recording.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrateReport {
+    /// The time from the first event to the last event in nanoseconds.
+    pub total: u128,
+    pub crates: BTreeMap<String, CrateTiming>,
+}
+
+impl CrateReport {
+    /// Finds the `rustc` invocations, build scripts, and linkers in a recording.
+    ///
+    /// Linkers are attributed to the crate of the `rustc` that started them, and a
+    /// linker that was started by another linker (e.g. `cc` running `ld`) isn't
+    /// counted twice.
+    pub fn from_store(store: &EventStore) -> Self {
+        let mut roles = BTreeMap::new();
+        for (pid, buffer) in store.iter() {
+            // The last exec of a process is the one that does the work, e.g. `rustc`
+            // after the `rustup` proxy
+            let role = buffer.iter().rev().find_map(|event| match event {
+                Event::ExecFull { filename, args, .. } => {
+                    let joined = args.joined();
+                    let args = joined.split_whitespace().collect::<Vec<_>>();
+                    Some((Role::from_exec(filename, &args)?, event.timestamp()))
+                }
+                _ => None,
+            });
+            if let Some((role, start)) = role {
+                let stop = buffer.back().map_or(start, |event| event.timestamp());
+                roles.insert(pid, (role, stop.saturating_sub(start)));
+            }
+        }
+
+        let mut report = Self {
+            total: crate::analyze::RunSummary::from_store(store).total,
+            crates: BTreeMap::new(),
+        };
+        for (pid, (role, duration)) in roles.iter() {
+            match role {
+                Role::Rustc {
+                    krate,
+                    build_script: false,
+                } => {
+                    let timing = report.crates.entry(krate.clone()).or_default();
+                    timing.units += 1;
+                    timing.rustc += duration;
+                }
+                Role::Rustc {
+                    krate,
+                    build_script: true,
+                }
+                | Role::BuildScript { krate } => {
+                    report.crates.entry(krate.clone()).or_default().build_script += duration;
+                }
+                Role::Linker => {
+                    for ppid in store.ancestors(*pid) {
+                        match roles.get(&ppid) {
+                            Some((Role::Linker, _)) => break,
+                            Some((
+                                Role::Rustc {
+                                    krate,
+                                    build_script: false,
+                                },
+                                _,
+                            )) => {
+                                report.crates.entry(krate.clone()).or_default().link += duration;
+                                break;
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+        report
+    }
+
+    /// Formats the report as a table with the slowest crates first.
+    pub fn to_table(&self) -> String {
+        let mut crates = self.crates.iter().collect::<Vec<_>>();
+        crates.sort_by_key(|(_, timing)| std::cmp::Reverse(timing.total()));
+        let width = crates
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0)
+            .max("crate".len());
+        let mut table = format!(
+            "{:<width$}  {:>5}  {:>12}  {:>12}  {:>12}  {:>12}\n",
+            "crate", "units", "total", "rustc", "link", "build script"
+        );
+        for (name, timing) in crates {
+            table.push_str(&format!(
+                "{:<width$}  {:>5}  {:>12}  {:>12}  {:>12}  {:>12}\n",
+                name,
+                timing.units,
+                format_nanos(timing.total()),
+                format_nanos(timing.rustc),
+                format_nanos(timing.link),
+                format_nanos(timing.build_script)
+            ));
+        }
+        table.push_str(&format!("total: {}\n", format_nanos(self.total)));
+        table
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::models::ExecArgsKind;
+
+    use super::*;
+
+    fn exec(seq: u128, pid: i32, ppid: i32, args: &str) -> Event {
+        let filename = args.split_whitespace().next().unwrap().to_string();
+        Event::ExecFull {
+            seq,
+            timestamp: seq,
+            pid,
+            ppid,
+            pgid: pid,
+            filename,
+            args: ExecArgsKind::Joined(args.to_string()),
+            credentials: None,
+        }
+    }
+
+    fn fork(seq: u128, pid: i32, ppid: i32) -> Event {
+        Event::Fork {
+            seq,
+            timestamp: seq,
+            parent_pid: ppid,
+            child_pid: pid,
+            parent_pgid: ppid,
+            credentials: None,
+            comm: None,
+        }
+    }
+
+    fn exit(seq: u128, pid: i32, ppid: i32) -> Event {
+        Event::Exit {
+            seq,
+            timestamp: seq,
+            pid,
+            ppid,
+            pgid: pid,
+            exit_code: Some(0),
+        }
+    }
+
+    #[test]
+    fn attributes_processes_to_crates() {
+        let events = vec![
+            fork(0, 1, 0),
+            exec(1, 1, 0, "/bin/cargo build"),
+            fork(2, 2, 1),
+            exec(
+                3,
+                2,
+                1,
+                "/bin/rustc --crate-name build_script_build build.rs --out-dir /t/debug/build/foo-sys-0123abcd",
+            ),
+            exit(5, 2, 1),
+            fork(6, 3, 1),
+            exec(7, 3, 1, "/t/debug/build/foo-sys-4567ef01/build-script-build"),
+            exit(10, 3, 1),
+            fork(11, 4, 1),
+            exec(12, 4, 1, "/bin/rustc --crate-name foo_sys src/lib.rs"),
+            fork(13, 5, 4),
+            exec(14, 5, 4, "/usr/bin/cc -o foo"),
+            fork(15, 6, 5),
+            exec(16, 6, 5, "/usr/bin/ld -o foo"),
+            exit(18, 6, 5),
+            exit(19, 5, 4),
+            exit(20, 4, 1),
+            exit(21, 1, 0),
+        ];
+        let store = EventStore::from_events(events);
+        let report = CrateReport::from_store(&store);
+        assert_eq!(report.total, 21);
+        let timing = report.crates.get("foo_sys").unwrap();
+        assert_eq!(
+            timing,
+            &CrateTiming {
+                units: 1,
+                rustc: 8,
+                link: 5,
+                build_script: 5,
+            }
+        );
+        assert_eq!(report.crates.len(), 1);
+    }
+}
@@ -0,0 +1,187 @@
+//! Counts the short-lived processes in a recording, which quantifies the overhead of
+//! spawning lots of trivial processes (e.g. the shell snippets of a build system).
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    analyze::{command_name, format_nanos},
+    models::{Event, EventStore},
+};
+
+/// The default lifetime below which a process counts as churn, in milliseconds.
+pub const DEFAULT_CHURN_THRESHOLD_MS: u64 = 10;
+
+/// The processes of a single command.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandChurn {
+    /// The number of processes that ran the command.
+    pub processes: usize,
+    /// The number of them that lived for less than the threshold.
+    pub short_lived: usize,
+    /// The combined lifetime of the short-lived processes, in nanoseconds.
+    pub short_lived_time: u128,
+}
+
+/// The short-lived processes in a recording, grouped by command.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChurnReport {
+    /// The lifetime below which a process is short-lived, in nanoseconds.
+    pub threshold: u128,
+    /// Keyed by the file name of the last thing each process exec'd, or "(fork)"
+    /// for processes that never exec'd.
+    pub commands: BTreeMap<String, CommandChurn>,
+}
+
+impl ChurnReport {
+    /// Finds the processes in a recording that exited less than `threshold`
+    /// nanoseconds after they were forked.
+    ///
+    /// Processes without both a fork and an exit aren't counted.
+    pub fn from_store(store: &EventStore, threshold: u128) -> Self {
+        let mut report = Self {
+            threshold,
+            commands: BTreeMap::new(),
+        };
+        for (_, buffer) in store.iter() {
+            let (
+                Some(Event::Fork {
+                    timestamp: start, ..
+                }),
+                Some(Event::Exit {
+                    timestamp: stop, ..
+                }),
+            ) = (buffer.front(), buffer.back())
+            else {
+                continue;
+            };
+            let command = buffer
+                .iter()
+                .rev()
+                .find_map(|event| match event {
+                    Event::ExecFull { filename, .. } => Some(command_name(filename)),
+                    _ => None,
+                })
+                .unwrap_or_else(|| "(fork)".to_string());
+            let lifetime = stop.saturating_sub(*start);
+            let churn = report.commands.entry(command).or_default();
+            churn.processes += 1;
+            if lifetime < threshold {
+                churn.short_lived += 1;
+                churn.short_lived_time += lifetime;
+            }
+        }
+        report
+    }
+
+    /// The total number of processes that were counted.
+    pub fn processes(&self) -> usize {
+        self.commands.values().map(|churn| churn.processes).sum()
+    }
+
+    /// The total number of short-lived processes.
+    pub fn short_lived(&self) -> usize {
+        self.commands.values().map(|churn| churn.short_lived).sum()
+    }
+
+    /// Formats the report as a table of the commands with short-lived processes,
+    /// most short-lived processes first.
+    pub fn to_table(&self) -> String {
+        let mut commands = self
+            .commands
+            .iter()
+            .filter(|(_, churn)| churn.short_lived > 0)
+            .collect::<Vec<_>>();
+        commands.sort_by_key(|(_, churn)| std::cmp::Reverse(churn.short_lived));
+        let width = commands
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0)
+            .max("command".len());
+        let mut table = format!(
+            "{:<width$}  {:>8}  {:>8}  {:>6}  {:>12}\n",
+            "command", "short", "total", "short%", "short time"
+        );
+        for (name, churn) in commands {
+            table.push_str(&format!(
+                "{:<width$}  {:>8}  {:>8}  {:>5.1}%  {:>12}\n",
+                name,
+                churn.short_lived,
+                churn.processes,
+                churn.short_lived as f64 / churn.processes as f64 * 100.0,
+                format_nanos(churn.short_lived_time)
+            ));
+        }
+        let total = self.processes();
+        let short_lived = self.short_lived();
+        let short_lived_time = self
+            .commands
+            .values()
+            .map(|churn| churn.short_lived_time)
+            .sum::<u128>();
+        table.push_str(&format!(
+            "{short_lived} of {total} processes ({:.1}%) lived less than {}, {} in total\n",
+            if total == 0 {
+                0.0
+            } else {
+                short_lived as f64 / total as f64 * 100.0
+            },
+            format_nanos(self.threshold),
+            format_nanos(short_lived_time)
+        ));
+        table
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::builder::TraceBuilder;
+
+    use super::*;
+
+    #[test]
+    fn counts_short_lived_processes() {
+        let events = TraceBuilder::new()
+            .fork(1, 2)
+            .exec(2, "/bin/sed")
+            .advance(5)
+            .exit(2, 0)
+            .advance(5)
+            .fork(1, 3)
+            .exec(3, "/bin/sed")
+            .advance(4)
+            .exit(3, 0)
+            .advance(6)
+            .fork(1, 4)
+            .exec(4, "/bin/sed")
+            .advance(10)
+            // Never execs, so it's counted by its fork
+            .fork(1, 5)
+            .advance(2)
+            .exit(5, 0)
+            .advance(8)
+            .fork(1, 6)
+            .exec(6, "/bin/cc")
+            .advance(60)
+            .exit(4, 0)
+            .advance(100)
+            .exit(6, 0)
+            .build();
+        let store = EventStore::from_events(events);
+        let report = ChurnReport::from_store(&store, 10);
+        assert_eq!(
+            report.commands["sed"],
+            CommandChurn {
+                processes: 3,
+                short_lived: 2,
+                short_lived_time: 9,
+            }
+        );
+        assert_eq!(report.commands["(fork)"].short_lived, 1);
+        assert_eq!(report.commands["cc"].short_lived, 0);
+        assert_eq!((report.short_lived(), report.processes()), (3, 5));
+        assert!(!report.to_table().contains("cc "));
+    }
+}
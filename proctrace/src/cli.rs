@@ -1,6 +1,13 @@
-use std::path::PathBuf;
+use std::{ffi::OsStr, path::PathBuf};
 
-use clap::{Args, Parser, Subcommand, ValueEnum};
+use clap::{
+    builder::{EnumValueParser, PossibleValue, TypedValueParser},
+    error::ErrorKind,
+    Args, Parser, Subcommand, ValueEnum,
+};
+use serde::Deserialize;
+
+use crate::writers::TraceFormat;
 
 #[derive(Debug, Parser)]
 #[command(author, version)]
@@ -16,6 +23,15 @@ pub enum DisplayMode {
     Sequential,
     ByProcess,
     Mermaid,
+    MermaidMd,
+    GhaSummary,
+    D2,
+    Plantuml,
+    Table,
+    /// An external program that's given the processed events on stdin, written as
+    /// "exec:<PROGRAM>" (see [crate::plugin]).
+    #[value(skip)]
+    Exec(PathBuf),
 }
 
 impl std::fmt::Display for DisplayMode {
@@ -24,6 +40,150 @@ impl std::fmt::Display for DisplayMode {
             DisplayMode::Sequential => write!(f, "sequential"),
             DisplayMode::ByProcess => write!(f, "by-process"),
             DisplayMode::Mermaid => write!(f, "mermaid"),
+            DisplayMode::MermaidMd => write!(f, "mermaid-md"),
+            DisplayMode::GhaSummary => write!(f, "gha-summary"),
+            DisplayMode::D2 => write!(f, "d2"),
+            DisplayMode::Plantuml => write!(f, "plantuml"),
+            DisplayMode::Table => write!(f, "table"),
+            DisplayMode::Exec(program) => write!(f, "exec:{}", program.display()),
+        }
+    }
+}
+
+/// Parses a display mode, which is either a built-in one or "exec:<PROGRAM>".
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DisplayModeParser;
+
+impl TypedValueParser for DisplayModeParser {
+    type Value = DisplayMode;
+
+    fn parse_ref(
+        &self,
+        cmd: &clap::Command,
+        arg: Option<&clap::Arg>,
+        value: &OsStr,
+    ) -> Result<Self::Value, clap::Error> {
+        if let Some(program) = value.to_str().and_then(|s| s.strip_prefix("exec:")) {
+            if program.is_empty() {
+                return Err(clap::Error::raw(
+                    ErrorKind::InvalidValue,
+                    "the exec display mode needs a program, e.g. exec:./render.py\n",
+                ));
+            }
+            return Ok(DisplayMode::Exec(PathBuf::from(program)));
+        }
+        EnumValueParser::<DisplayMode>::new().parse_ref(cmd, arg, value)
+    }
+
+    fn possible_values(&self) -> Option<Box<dyn Iterator<Item = PossibleValue> + '_>> {
+        let builtin = DisplayMode::value_variants()
+            .iter()
+            .filter_map(|mode| mode.to_possible_value());
+        Some(Box::new(
+            builtin.chain([PossibleValue::new("exec:<PROGRAM>")]),
+        ))
+    }
+}
+
+/// The format of a raw recording.
+#[derive(Debug, Default, ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum RawFormat {
+    /// The output of the bundled `bpftrace` script, which the other backends mimic.
+    #[default]
+    Bpftrace,
+    /// One JSON object per line with a `type` and the fields of that type of event.
+    JsonlRaw,
+}
+
+impl std::fmt::Display for RawFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RawFormat::Bpftrace => write!(f, "bpftrace"),
+            RawFormat::JsonlRaw => write!(f, "jsonl-raw"),
+        }
+    }
+}
+
+/// The order of the rows of the "table" display mode.
+#[derive(Debug, Default, ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum TableSort {
+    /// Slowest first.
+    Duration,
+    /// In the order they started.
+    #[default]
+    Start,
+    Pid,
+}
+
+impl std::fmt::Display for TableSort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TableSort::Duration => write!(f, "duration"),
+            TableSort::Start => write!(f, "start"),
+            TableSort::Pid => write!(f, "pid"),
+        }
+    }
+}
+
+/// How the command lines of execs are shown.
+#[derive(Debug, Default, ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum ArgvStyle {
+    /// Every argument as it was passed.
+    #[default]
+    Full,
+    /// The file name of the program instead of its path, followed by every argument.
+    Basename,
+    /// The file name of the program and its first few arguments, followed by "..."
+    /// if there were more.
+    Smart,
+}
+
+impl std::fmt::Display for ArgvStyle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArgvStyle::Full => write!(f, "full"),
+            ArgvStyle::Basename => write!(f, "basename"),
+            ArgvStyle::Smart => write!(f, "smart"),
+        }
+    }
+}
+
+/// How an output stream should be compressed.
+#[derive(Debug, Default, ValueEnum, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Guesses the compression of a file from its extension.
+    pub fn from_path(path: impl AsRef<std::path::Path>) -> Self {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst" | "zstd") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// Recognizes compressed data from its first (up to 4) bytes.
+    pub fn from_magic(magic: &[u8]) -> Self {
+        match magic {
+            [0x1f, 0x8b, ..] => Compression::Gzip,
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Gzip => write!(f, "gzip"),
+            Compression::Zstd => write!(f, "zstd"),
         }
     }
 }
@@ -38,8 +198,18 @@ pub enum Command {
     /// may not be in the PATH of the superuser. If this is the case then you
     /// can use the `--bpftrace-path` flag to specify it manually. This is likely
     /// the case if you've installed `bpftrace` via `flox` or `nix profile install`.
-    #[cfg(target_os = "linux")]
-    Record(RecordArgs),
+    #[cfg(all(target_os = "linux", feature = "record"))]
+    Record(Box<RecordArgs>),
+
+    /// Record a command several times and summarize how long each command took.
+    ///
+    /// Each run is stored as a processed recording in the output directory along
+    /// with a "summary.json" containing the min, median, and p95 durations of the
+    /// whole run and of each command across all of the runs. A manifest of the runs
+    /// ("runs.manifest.json") lists when each one started and how it exited, and can
+    /// be passed to `render` or `analyze` to see all of the runs on one timeline.
+    #[cfg(all(target_os = "linux", feature = "record"))]
+    Bench(BenchArgs),
 
     /// Convert a raw recording into a processed recording that can be rendered.
     ///
@@ -48,11 +218,492 @@ pub enum Command {
     Ingest(IngestArgs),
 
     /// Render a recording in the specified display format.
-    Render(RenderArgs),
+    Render(Box<RenderArgs>),
+
+    /// Sort the events of a processed recording by sequence number.
+    ///
+    /// Recordings that were streamed or merged from several sources may not be in
+    /// order. Sorting runs in bounded memory, so it works on recordings that are
+    /// much larger than the available RAM.
+    #[cfg(feature = "fs")]
+    Sort(SortArgs),
+
+    /// Merge recordings from several hosts into a single recording.
+    ///
+    /// Each recording is placed on the timeline by when it started, and the roots of
+    /// all but the first process become adopted roots of the merged recording. PIDs
+    /// that appear in more than one recording are renumbered.
+    #[cfg(feature = "fs")]
+    Merge(MergeArgs),
+
+    /// Remove trivial processes from a processed recording.
+    ///
+    /// Processes that don't have children in the recording are removed if they're
+    /// shorter than `--min-duration` or their command line matches `--drop-cmd`,
+    /// which repeats for their parents once all of their children are gone. The
+    /// number of processes removed below each remaining process is kept in the
+    /// metadata.
+    #[cfg(feature = "fs")]
+    Prune(PruneArgs),
+
+    /// Compare a recording against a baseline and report how long each command
+    /// took in both.
+    ///
+    /// Either side can be a processed recording, or the output directory (or
+    /// "summary.json" or "runs.manifest.json") of `proctrace bench`, in which case
+    /// the median durations are compared. With `--fail-if` this exits with an error
+    /// when a regression exceeds its threshold, so it can be used as a check in CI.
+    #[cfg(feature = "fs")]
+    Compare(CompareArgs),
+
+    /// Stream the events of a recording to HTTP clients as server-sent events.
+    ///
+    /// Clients connect to `/events` and receive each event as a line of JSON in a
+    /// `data:` field, starting from the beginning of the recording. An `end` event
+    /// is sent once the recording is finished. With `--listen` the events come from
+    /// a recording that's in progress, which ships them here with
+    /// `proctrace record --ship tcp://<LISTEN>`.
+    #[cfg(feature = "fs")]
+    Serve(ServeArgs),
+
+    /// Find the processes that ran a command and the processes that started them.
+    ///
+    /// Each process whose file name or command line matched the pattern in any of its
+    /// execs is printed with its ancestry, from the root of the recording down to it,
+    /// e.g. `make[1] -> sh[12] -> curl[42]: curl -s https://example.com`. Exits with
+    /// an error if nothing matched.
+    #[cfg(feature = "fs")]
+    Search(SearchArgs),
+
+    /// Produce focused reports from a processed recording.
+    #[cfg(feature = "fs")]
+    #[command(subcommand)]
+    Analyze(AnalyzeCommand),
+
+    /// Show a recording bundle written by `proctrace record --bundle`.
+    ///
+    /// Prints the bundle's report, rendering it first if it's missing, or renders
+    /// its processed events in another display mode.
+    #[cfg(feature = "fs")]
+    Open(OpenArgs),
+
+    /// Show the version of `proctrace` and what this build of it supports.
+    ///
+    /// This lists the subcommands, recording backends, formats, display modes, and
+    /// exporters that were compiled in, along with the version of the recording
+    /// format, so that scripts can check for a feature instead of parsing `--help`.
+    Info(InfoArgs),
+}
+
+#[derive(Debug, Clone, Args, PartialEq, Eq)]
+#[cfg(feature = "fs")]
+pub struct SearchArgs {
+    /// The path to the processed recording to search, or '-' to read from stdin.
+    #[arg(value_name = "RECORDING")]
+    pub input_path: PathBuf,
+
+    /// A regex that's matched against the file name and command line of each exec.
+    #[arg(value_name = "REGEX")]
+    pub pattern: String,
+
+    /// Match the pattern regardless of case.
+    #[arg(short = 'i', long)]
+    pub ignore_case: bool,
+}
+
+#[derive(Debug, Clone, Args, PartialEq, Eq)]
+pub struct InfoArgs {
+    /// Print the information as JSON.
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Clone, Args, PartialEq, Eq)]
+#[cfg(feature = "fs")]
+pub struct OpenArgs {
+    /// The directory of the bundle.
+    #[arg(value_name = "DIR")]
+    pub dir: PathBuf,
+
+    /// Render the processed events in this display mode instead of printing the
+    /// report.
+    #[arg(short, long, value_name = "MODE")]
+    pub display_mode: Option<DisplayMode>,
+
+    /// Render the report again even if it already exists.
+    #[arg(long)]
+    pub rerender: bool,
+}
+
+#[derive(Debug, Clone, Args, PartialEq, Eq)]
+#[cfg(feature = "fs")]
+pub struct ServeArgs {
+    /// The address to serve events on.
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:7878")]
+    pub addr: std::net::SocketAddr,
+
+    /// Stream the events of recordings in progress, which recorders ship to this
+    /// address, instead of a finished one.
+    ///
+    /// Recordings are accepted one at a time. When one finishes, clients that
+    /// connect afterwards receive the next one.
+    #[arg(long, value_name = "ADDR", conflicts_with = "input_path")]
+    pub listen: Option<std::net::SocketAddr>,
+
+    /// The recording to serve.
+    ///
+    /// Must either be a path to a file or '-' to read from stdin. Input that's
+    /// compressed with gzip or zstd is decompressed automatically, the segments
+    /// listed in a manifest (".manifest.json") are read in order, and the runs listed
+    /// in the manifest of `proctrace bench` are merged onto one timeline.
+    #[arg(
+        short,
+        long = "input",
+        value_name = "PATH",
+        required_unless_present = "listen"
+    )]
+    pub input_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+#[cfg(feature = "fs")]
+pub enum AnalyzeCommand {
+    /// Report how long each phase of activating an environment took.
+    ///
+    /// Processes are tagged as building the environment, evaluating it, running
+    /// hooks, sourcing shell rc files, or running activation scripts, based on
+    /// what they exec. A phase includes the time spent in its children, so
+    /// phases can overlap (e.g. hooks run by an activation script).
+    Activation(AnalyzeArgs),
+
+    /// Report which children of the root process are responsible for the time
+    /// spent.
+    ///
+    /// Every process is attributed to the child of the root it descends from,
+    /// e.g. the recipe of a `make` target or a recipe of a `justfile`, and each
+    /// child is shown with how long its subtree ran and what share of the
+    /// combined process time it accounts for.
+    Blame(BlameArgs),
+
+    /// Report how long was spent building each crate in a `cargo` build.
+    ///
+    /// `rustc` invocations are matched to crates by their `--crate-name`, and
+    /// build scripts by the directory they're built in. Unlike `cargo build
+    /// --timings` this includes the time spent running build scripts, and shows
+    /// how much of each `rustc` invocation was spent in the linker.
+    Cargo(AnalyzeArgs),
+
+    /// Report how many processes exited shortly after they started, by command.
+    ///
+    /// Shell-heavy build systems spawn lots of trivial processes (`sed`, `dirname`,
+    /// subshells, ...) that each cost a fork and an exec, and this shows how many
+    /// there were and how much time they took up in total.
+    Churn(ChurnArgs),
+
+    /// Report how many processes were alive at once over the recording.
+    ///
+    /// This shows the average and peak number of processes, how long each number
+    /// of them were alive for, and a timeline, which shows whether a parallel
+    /// build kept the cores busy. Processes count from their fork to their exit
+    /// whether they were running or waiting.
+    Concurrency(ConcurrencyArgs),
+
+    /// Report the processes that crashed.
+    ///
+    /// A process crashed if it was killed by a signal that dumps core, e.g.
+    /// `SIGSEGV` or `SIGABRT`, whether or not a core was written. Each one is shown
+    /// with its signal, how long it ran, and its command line, which tells a test
+    /// that crashed apart from one that failed, e.g. in a flaky test suite.
+    Crashes(AnalyzeArgs),
+
+    /// Report the execs that failed in each process.
+    ///
+    /// Programs that are found by trying each directory of `PATH`, or wrappers
+    /// that try several candidates, fail to exec before they find one that works.
+    /// Each process is shown with the files it tried and how long it took from the
+    /// first failure to the exec that succeeded.
+    FailedExecs(FailedExecArgs),
+
+    /// Export the process time of the recording as folded stacks for a flamegraph.
+    ///
+    /// Each process is a stack that starts with its process group, followed by the
+    /// commands of its ancestors in the group, and is weighted by how long it was
+    /// alive in microseconds. Stacks are named after commands, so the output of two
+    /// runs can be passed to `inferno-diff-folded` for a differential flamegraph, or
+    /// use `--baseline` to produce its output directly.
+    Folded(FoldedArgs),
+
+    /// Report the periods where the root process had no running descendants.
+    ///
+    /// A script or build that runs its steps one after another leaves gaps between
+    /// them when it sleeps, waits on the network, or waits for a lock. Each gap is
+    /// shown with when it started, how long it lasted, and the processes that ran
+    /// right before and after it.
+    Gaps(GapArgs),
+
+    /// Report how long the processes of each group ran, e.g. per package of a
+    /// monorepo.
+    ///
+    /// Processes are grouped by the directory they ran in, or by the tags that
+    /// `--tags` gives them. Each group is shown with its number of processes, how
+    /// long at least one of them was running, and their combined lifetime.
+    Groups(GroupsArgs),
+
+    /// Report how much time was spent in shells, interpreters, and the JVM versus
+    /// native binaries.
+    ///
+    /// Each exec is put in a category by its file name, and a process counts
+    /// toward the category of the program it's running from one exec to the next,
+    /// so a subshell counts as a shell until it execs something. Processes that ran
+    /// in parallel are counted separately, so the times add up to more than the
+    /// length of the recording.
+    Interpreters(InterpreterArgs),
+
+    /// Report how much recording perturbed the recorded command.
+    ///
+    /// This shows how long the command was held back while `bpftrace` started, and
+    /// how much CPU time `proctrace`, `sudo`, and `bpftrace` used while recording,
+    /// also as a share of how long the recording lasted. Only recordings taken by
+    /// `proctrace record` have these.
+    Overhead(AnalyzeArgs),
+
+    /// Report how many jobs a `make -jN` or `ninja` build was running over time.
+    ///
+    /// The jobs are the children of each `make`, `ninja`, or `samu` process. This
+    /// shows how much of the build ran at each level of concurrency, how well the
+    /// job slots were used, and the longest chain of jobs that ran one after
+    /// another, which is usually what limits how much more jobs would help.
+    Parallelism(ParallelismArgs),
+
+    /// Report command lines that were run many times with the same arguments.
+    ///
+    /// Each one is shown with how often it ran, how long it took in total, and
+    /// how much time would be saved if only the first run did any work, which
+    /// makes them candidates for caching or hoisting out of a loop.
+    Repeats(RepeatArgs),
+
+    /// Report the subtrees of the process tree that forked pathologically fast.
+    ///
+    /// The forks in the recording are replayed with the same detector that
+    /// `record --storm-threshold` uses, so a recording can be checked with a
+    /// different threshold than it was recorded with.
+    Storms(StormArgs),
+
+    /// Report the CPU time, page faults, and context switches of each process.
+    ///
+    /// These are recorded when a process exits, and help tell why a slow process
+    /// was slow: a low share of CPU time means it was waiting rather than
+    /// computing, major faults mean it waited for the disk, voluntary switches
+    /// mean it waited for I/O or a lock, and involuntary switches mean it was
+    /// competing for a CPU. Processes are shown slowest first.
+    Usage(UsageArgs),
+}
+
+#[derive(Debug, Clone, Args, PartialEq, Eq)]
+#[cfg(feature = "fs")]
+pub struct FoldedArgs {
+    #[command(flatten)]
+    pub input: AnalyzeArgs,
+
+    /// A recording of an earlier run to compare against.
+    ///
+    /// Each stack is followed by its time in the baseline and then in the input, the
+    /// format that `inferno-flamegraph` renders as a differential flamegraph.
+    #[arg(long, value_name = "PATH")]
+    pub baseline: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Args, PartialEq, Eq)]
+#[cfg(feature = "fs")]
+pub struct BlameArgs {
+    #[command(flatten)]
+    pub input: AnalyzeArgs,
+
+    /// The number of children to show.
+    #[arg(short = 'n', long, value_name = "N", default_value_t = 20)]
+    pub limit: usize,
+}
+
+#[derive(Debug, Clone, Args, PartialEq, Eq)]
+#[cfg(feature = "fs")]
+pub struct UsageArgs {
+    #[command(flatten)]
+    pub input: AnalyzeArgs,
+
+    /// The number of processes to show.
+    #[arg(short = 'n', long, value_name = "N", default_value_t = 20)]
+    pub limit: usize,
+
+    /// Print every process as CSV instead of a table, e.g. for a spreadsheet.
+    #[arg(long, conflicts_with = "json")]
+    pub csv: bool,
+}
+
+#[derive(Debug, Clone, Args, PartialEq, Eq)]
+#[cfg(feature = "fs")]
+pub struct ChurnArgs {
+    #[command(flatten)]
+    pub input: AnalyzeArgs,
+
+    /// Count processes that lived for less than this many milliseconds.
+    #[arg(long, value_name = "MS", default_value_t = crate::churn::DEFAULT_CHURN_THRESHOLD_MS)]
+    pub threshold: u64,
+}
+
+#[derive(Debug, Clone, Args, PartialEq, Eq)]
+#[cfg(feature = "fs")]
+pub struct GapArgs {
+    #[command(flatten)]
+    pub input: AnalyzeArgs,
+
+    /// Report gaps that lasted at least this many milliseconds.
+    #[arg(long, value_name = "MS", default_value_t = crate::gaps::DEFAULT_MIN_GAP_MS)]
+    pub min_gap: u64,
+}
+
+#[derive(Debug, Clone, Args, PartialEq, Eq)]
+#[cfg(feature = "fs")]
+pub struct GroupsArgs {
+    #[command(flatten)]
+    pub input: AnalyzeArgs,
+
+    #[command(flatten)]
+    pub group: GroupArgs,
+}
+
+/// How to group processes, for the commands that can.
+#[derive(Debug, Clone, Default, Args, PartialEq, Eq)]
+#[cfg(feature = "fs")]
+pub struct GroupArgs {
+    /// Group processes by the directory they ran in.
+    ///
+    /// The directory is followed through the `chdir`s of each process and its
+    /// ancestors from the directory the recording started in. Processes whose
+    /// directory isn't known, e.g. in recordings that don't have `chdir` events,
+    /// aren't in any group.
+    #[arg(long, conflicts_with = "tags")]
+    pub group_by_dir: bool,
+
+    /// Group processes by the tags in this TOML file, e.g. one per package of a
+    /// monorepo.
+    ///
+    /// Each `[[tag]]` table has a `match` regex, which is matched against the
+    /// directory and the command line of each process, and the `name` of the tag,
+    /// which can refer to captured groups as "$1". The first matching tag wins, and
+    /// processes that don't match any inherit the tag of their parent.
+    #[arg(long, value_name = "PATH")]
+    pub tags: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Args, PartialEq, Eq)]
+#[cfg(feature = "fs")]
+pub struct InterpreterArgs {
+    #[command(flatten)]
+    pub input: AnalyzeArgs,
+
+    /// A TOML file of extra categories, which are tried before the built-in ones.
+    ///
+    /// Each `[[category]]` table has a `name` and a `match` regex, which is matched
+    /// against the file name of each exec. The built-in categories are "shell",
+    /// "python", "node", "jvm", "ruby", and "perl", and anything else is "native".
+    #[arg(long, value_name = "PATH")]
+    pub categories: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Args, PartialEq, Eq)]
+#[cfg(feature = "fs")]
+pub struct FailedExecArgs {
+    #[command(flatten)]
+    pub input: AnalyzeArgs,
+
+    /// The number of processes to show.
+    #[arg(short = 'n', long, value_name = "N", default_value_t = 20)]
+    pub limit: usize,
+}
+
+#[derive(Debug, Clone, Args, PartialEq, Eq)]
+#[cfg(feature = "fs")]
+pub struct RepeatArgs {
+    #[command(flatten)]
+    pub input: AnalyzeArgs,
+
+    /// Only report command lines that were run at least this many times.
+    #[arg(long, value_name = "N", default_value_t = crate::repeats::DEFAULT_MIN_REPEATS)]
+    pub min_count: usize,
+
+    /// The number of command lines to show.
+    #[arg(short = 'n', long, value_name = "N", default_value_t = 20)]
+    pub limit: usize,
+}
+
+#[derive(Debug, Clone, Args, PartialEq, Eq)]
+#[cfg(feature = "fs")]
+pub struct StormArgs {
+    #[command(flatten)]
+    pub input: AnalyzeArgs,
+
+    /// Report subtrees that fork more than this many times within `--window`.
+    #[arg(long, value_name = "FORKS", default_value_t = crate::storm::DEFAULT_STORM_THRESHOLD)]
+    pub threshold: usize,
+
+    /// The window to count forks over, in milliseconds.
+    #[arg(long, value_name = "MS", default_value_t = crate::storm::DEFAULT_STORM_WINDOW_MS)]
+    pub window: u64,
+}
+
+#[derive(Debug, Clone, Args, PartialEq, Eq)]
+#[cfg(feature = "fs")]
+pub struct ConcurrencyArgs {
+    #[command(flatten)]
+    pub input: AnalyzeArgs,
+
+    /// The number of cores the recording was taken on.
+    ///
+    /// If omitted the number of cores of this machine is used.
+    #[arg(long, value_name = "N")]
+    pub cores: Option<usize>,
+}
+
+#[derive(Debug, Clone, Args, PartialEq, Eq)]
+#[cfg(feature = "fs")]
+pub struct ParallelismArgs {
+    #[command(flatten)]
+    pub input: AnalyzeArgs,
+
+    /// The number of job slots the build had.
+    ///
+    /// If omitted this is taken from the `-j` argument of the build tool.
+    #[arg(short, long, value_name = "N")]
+    pub jobs: Option<usize>,
+
+    /// How soon a job has to start after another one finished to be considered
+    /// part of the same chain, in milliseconds.
+    #[arg(long, value_name = "MS", default_value_t = 10)]
+    pub max_gap_ms: u64,
+}
+
+#[derive(Debug, Clone, Args, PartialEq, Eq)]
+#[cfg(feature = "fs")]
+pub struct AnalyzeArgs {
+    /// The path to the processed recording to analyze.
+    ///
+    /// Must either be a path to a file or '-' to read from stdin. Input that's
+    /// compressed with gzip or zstd is decompressed automatically, the segments
+    /// listed in a manifest (".manifest.json") are read in order, and the runs listed
+    /// in the manifest of `proctrace bench` are merged onto one timeline.
+    #[arg(short, long = "input", help = "The path to the event data file")]
+    pub input_path: PathBuf,
+
+    /// Print the report as JSON instead of text, e.g. for a dashboard or a CI check.
+    ///
+    /// The JSON includes every row of the report, regardless of `--limit`, and
+    /// durations and timestamps are in nanoseconds.
+    #[arg(long)]
+    pub json: bool,
 }
 
 #[derive(Debug, Clone, Args, PartialEq, Eq)]
-#[cfg(target_os = "linux")]
+#[cfg(all(target_os = "linux", feature = "record"))]
 pub struct RecordArgs {
     /// The path to a `bpftrace` executable.
     ///
@@ -69,21 +720,81 @@ pub struct RecordArgs {
     )]
     pub bpftrace_path: PathBuf,
 
+    /// How process events are collected.
+    ///
+    /// `bpftrace` sees every event but needs root. `perf` reads the kernel's task
+    /// events with `perf_event_open`, which is allowed by the default
+    /// `perf_event_paranoid` setting but can't see exit codes, and may miss the
+    /// arguments and process groups of processes that exit right after they
+    /// exec. `ftrace` reads the same events from tracefs for kernels without BPF
+    /// support, which needs root and has the same limitations as `perf`. `procfs`
+    /// polls `/proc` every `--poll-interval` milliseconds, which needs no
+    /// privileges but also misses processes that start and exit between two
+    /// polls.
+    #[arg(long, value_name = "BACKEND", default_value_t = Backend::Bpftrace)]
+    pub backend: Backend,
+
+    /// How often the `procfs` backend polls `/proc`, in milliseconds.
+    #[arg(long, value_name = "MS", default_value_t = 10)]
+    pub poll_interval: u64,
+
     /// Show each line of output from `bpftrace` before it goes through filtering.
     ///
     /// This also displays which PIDs are being tracked but have not yet exited.
     #[arg(long, help = "Show debug output")]
     pub debug: bool,
 
-    /// Write the raw events from the `bpftrace` script instead of the processed
-    /// events.
+    /// Show a dashboard on stderr while recording, redrawn in place.
+    ///
+    /// It shows how long the recording has run, how many processes are tracked
+    /// and still running, how many events are read per second, how many lines
+    /// from the backend couldn't be parsed, and the processes that have been
+    /// running the longest. Needs stderr to be a terminal.
+    #[arg(long, conflicts_with = "debug")]
+    pub tui: bool,
+
+    /// Which recordings to write: the processed events, the raw events from the
+    /// backend, or both.
     ///
-    /// This will write events from processes outside of the target process
-    /// tree, but recording this raw output allows you to rerun analysis without
-    /// needing to collect another recording.
-    #[arg(short, long, help = "Record all of the raw events from bpftrace")]
+    /// A raw recording includes events from processes outside of the target process
+    /// tree, but keeping it allows you to rerun analysis without needing to collect
+    /// another recording. With "both", the raw events are written to `--raw-output`,
+    /// or next to the output with ".raw" added to its name, e.g. "trace.json.raw.zst"
+    /// for "trace.json.zst".
+    #[arg(long, value_name = "KIND", default_value_t = OutFormat::Processed)]
+    pub out_format: OutFormat,
+
+    /// Write the raw events instead of the processed events, the same as
+    /// `--out-format raw`.
+    #[arg(
+        short,
+        long,
+        help = "Record all of the raw events from bpftrace",
+        conflicts_with = "out_format"
+    )]
     pub raw: bool,
 
+    /// Also write the raw events from the `bpftrace` script to this path.
+    ///
+    /// The processed events are still written to `--output`, so a single
+    /// recording produces both a render-ready recording and a raw recording
+    /// that can be re-ingested later. Implies `--out-format both`.
+    #[arg(long, value_name = "PATH", conflicts_with = "raw")]
+    pub raw_output: Option<PathBuf>,
+
+    /// Write everything about the recording to a new directory instead of to
+    /// separate files.
+    ///
+    /// The directory contains the metadata of the recording, the raw events, the
+    /// processed events, and a Markdown report, which can be shown with
+    /// `proctrace open <DIR>`.
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = ["raw", "raw_output", "out_format", "output_path", "compress"]
+    )]
+    pub bundle: Option<PathBuf>,
+
     /// Where to write the output (default: stdout).
     #[arg(
         short,
@@ -93,6 +804,332 @@ pub struct RecordArgs {
     )]
     pub output_path: Option<PathBuf>,
 
+    /// How to compress the output.
+    ///
+    /// If omitted, the compression is chosen from the extension of the output
+    /// path (".gz" or ".zst"), and output to stdout is left uncompressed.
+    #[arg(long, value_name = "KIND")]
+    pub compress: Option<Compression>,
+
+    /// The encoding of the processed recording.
+    ///
+    /// This has no effect on raw recordings, which are always text. Both formats
+    /// can be read by every other subcommand, which detect the format
+    /// automatically.
+    #[arg(long, value_name = "FORMAT", default_value_t = TraceFormat::Json)]
+    pub format: TraceFormat,
+
+    /// Send the processed events to a socket as they're recorded.
+    ///
+    /// Accepts `tcp://<host>:<port>`, `udp://<host>:<port>`, or `unix://<path>`.
+    /// Each event is sent as a line of JSON (one datagram per event for UDP),
+    /// and shipping stops with a warning if the connection is lost. Events are
+    /// sent as they're observed, so exec arguments haven't been merged yet.
+    #[arg(long, value_name = "URL")]
+    pub ship: Option<crate::writers::ShipTarget>,
+
+    /// Write the processed events to stdout as they're recorded, as lines of JSON.
+    ///
+    /// The recording is still written to the output path, which is required, and the
+    /// output of the command goes to stderr instead. Pipe it to
+    /// `proctrace render -i - --follow` to watch the recording live. Events are
+    /// written as they're observed, so exec arguments haven't been merged yet.
+    #[arg(long)]
+    pub stream: bool,
+
+    /// Discard the output of the command so that it doesn't interleave with the
+    /// output of `proctrace`.
+    ///
+    /// The command's stdin is left alone, so interactive commands still work.
+    #[arg(long, conflicts_with_all = ["child_stdout", "child_stderr"])]
+    pub quiet_child: bool,
+
+    /// Write the stdout of the command to this file instead of the terminal.
+    #[arg(long, value_name = "PATH")]
+    pub child_stdout: Option<PathBuf>,
+
+    /// Write the stderr of the command to this file instead of the terminal.
+    ///
+    /// May be the same path as `--child-stdout` to keep both in one file.
+    #[arg(long, value_name = "PATH")]
+    pub child_stderr: Option<PathBuf>,
+
+    /// Log the processed events to the systemd journal as they're recorded.
+    ///
+    /// Each fork, exec, and exit is logged with the `SYSLOG_IDENTIFIER`
+    /// "proctrace", a `MESSAGE_ID` for its kind, and `PID`, `PPID`, `CMDLINE`,
+    /// and `EXIT_STATUS` fields, so they can be queried with e.g.
+    /// `journalctl SYSLOG_IDENTIFIER=proctrace PPID=1234`.
+    #[arg(long)]
+    pub journal: bool,
+
+    /// Split the output into numbered segments of roughly this many bytes.
+    ///
+    /// Segments are named after the output path (`trace.0001.json`, ...) and a
+    /// manifest (`trace.manifest.json`) lists them in order. The manifest can be
+    /// passed anywhere a recording is expected. Requires `--output`.
+    #[arg(long, value_name = "BYTES", requires = "output_path")]
+    pub rotate_size: Option<u64>,
+
+    /// Split the raw output into numbered segments covering this many seconds each.
+    ///
+    /// See `--rotate-size` for how segments are named. Processed events are written
+    /// all at once when recording finishes, so they're only split by size and this
+    /// can't be used with `--out-format processed`. Requires `--output`.
+    #[arg(long, value_name = "SECONDS", requires = "output_path")]
+    pub rotate_every: Option<u64>,
+
+    /// Apply the settings for a well-known kind of command.
+    ///
+    /// `nix-build` adopts the builders that `nix-daemon` forks for each derivation,
+    /// which otherwise aren't part of the process tree, and enables
+    /// `--rewrite-store-paths`. `activation` prints how long each phase of
+    /// activating an environment took once recording finishes (see
+    /// `proctrace analyze activation`), and `cargo` does the same for each crate
+    /// (see `proctrace analyze cargo`).
+    #[arg(long, value_name = "PRESET")]
+    pub preset: Option<Preset>,
+
+    /// Apply the settings in a `[profile.<NAME>]` table of the config file.
+    ///
+    /// A profile can set any of `bpftrace-path`, `backend`, `poll-interval`,
+    /// `debug`, `raw`, `out-format`, `compress`, `format`, `rotate-size`,
+    /// `rotate-every`, `preset`, `adopt-exec`, `rewrite-store-paths`,
+    /// `systemd-scope`, `cgroup`, `container`, `container-runtime`, `sample-host`,
+    /// `sample-rss`, `sample-cpu`, `metrics-addr`, `journal`, `storm-threshold`,
+    /// `storm-window`, `kill-on-storm`, `follow-daemons`, `trace-mounts`,
+    /// `buffer-window`, `sample-trees`, and `events`. Flags passed on the command
+    /// line take precedence, and lists from the profile are extended by the
+    /// command line. Profiles only apply to `record`, so flags of `ingest` like
+    /// `--adopt-parent` and `--keep-unmatched` can't be set from one.
+    #[arg(long, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// The config file to read profiles from.
+    ///
+    /// If omitted, `proctrace.toml` in the current directory is used if it
+    /// exists, followed by `$XDG_CONFIG_HOME/proctrace/config.toml` (or
+    /// `~/.config/proctrace/config.toml`).
+    #[arg(long, value_name = "PATH", requires = "profile")]
+    pub config: Option<PathBuf>,
+
+    /// Also record processes that exec something matching this regex, along with
+    /// their descendants.
+    ///
+    /// The pattern is matched against the exec'd file name and the joined arguments.
+    /// May be passed more than once.
+    #[arg(long, value_name = "REGEX")]
+    pub adopt_exec: Vec<String>,
+
+    /// Shorten Nix store paths in exec file names and arguments by replacing the
+    /// hash with `…`.
+    #[arg(long)]
+    pub rewrite_store_paths: bool,
+
+    /// Run the command in a transient systemd scope and record everything in it.
+    ///
+    /// The command is started with `systemd-run --user --scope`, and any process
+    /// that execs inside the scope's cgroup is recorded, even if it isn't a
+    /// descendant of the command (e.g. a service it started over a socket).
+    #[arg(long)]
+    pub systemd_scope: bool,
+
+    /// Also record any process that execs while in this cgroup.
+    ///
+    /// Paths are relative to `/sys/fs/cgroup` unless they're absolute, and only
+    /// cgroup v2 is supported. May be passed more than once.
+    #[arg(long, value_name = "PATH")]
+    pub cgroup: Vec<PathBuf>,
+
+    /// Also record every process that runs inside this container.
+    ///
+    /// The container must already be running. Its cgroup is looked up with
+    /// `<runtime> inspect` and every process that execs inside it is recorded, so
+    /// the command is typically something like `docker exec <ID> ./entrypoint.sh`.
+    /// The PIDs that processes see inside the container are stored in the
+    /// recording's metadata.
+    #[arg(long, value_name = "ID")]
+    pub container: Option<String>,
+
+    /// Sample the load average and memory use of the host this often, in
+    /// milliseconds.
+    ///
+    /// Samples are stored as `HostSample` events and shown above the process
+    /// spans by the "mermaid" display mode. Samples are taken at most every 100ms.
+    #[arg(long, value_name = "MS")]
+    pub sample_host: Option<u64>,
+
+    /// Sample the memory use of each recorded process this often, in milliseconds.
+    ///
+    /// The resident set size of each process that's still running is read from
+    /// `/proc/<pid>/status` and stored as an `RssSample` event. The "mermaid"
+    /// display mode shows the combined memory use of the recorded processes above
+    /// the process spans and labels each span with the peak of its process.
+    /// Samples are taken at most every 100ms.
+    #[arg(long, value_name = "MS")]
+    pub sample_rss: Option<u64>,
+
+    /// Serve counters describing the recorder at `http://<ADDR>/metrics` in the
+    /// Prometheus text format.
+    ///
+    /// The counters are the number of events ingested and dropped, and the number
+    /// of PIDs that are tracked or buffered. This is useful for monitoring long
+    /// recordings.
+    #[arg(long, value_name = "ADDR")]
+    pub metrics_addr: Option<std::net::SocketAddr>,
+
+    /// Listen for commands on a Unix socket at this path while recording.
+    ///
+    /// Each line sent to the socket is a command and is answered with "ok" or
+    /// "error: <reason>". `flush` makes everything written so far durable,
+    /// `mark <label>` adds a marker to the root process, `rotate` starts a new
+    /// segment (with `--rotate-size` or `--rotate-every`), and `stop` stops
+    /// recording. For example, `echo 'mark tests' | nc -U proctrace.sock`.
+    #[arg(long, value_name = "PATH")]
+    pub control: Option<PathBuf>,
+
+    /// Warn when a subtree of the process tree forks more than this many times
+    /// within `--storm-window`.
+    ///
+    /// Each fork counts towards every ancestor of the process that forked, and
+    /// the closest one that's over the threshold is reported. Warnings are
+    /// printed and stored as `ForkStorm` events. 0 turns detection off.
+    #[arg(long, value_name = "FORKS", default_value_t = crate::storm::DEFAULT_STORM_THRESHOLD)]
+    pub storm_threshold: usize,
+
+    /// The window that `--storm-threshold` counts forks over, in milliseconds.
+    #[arg(long, value_name = "MS", default_value_t = crate::storm::DEFAULT_STORM_WINDOW_MS)]
+    pub storm_window: u64,
+
+    /// Kill a subtree that exceeds `--storm-threshold`.
+    ///
+    /// The process group of the process at the root of the subtree is killed if
+    /// every process in it is part of the subtree. Otherwise, e.g. when it's the
+    /// same process group as `proctrace` or a process outside of the subtree
+    /// joined it, the processes in the subtree are killed one by one.
+    #[arg(long)]
+    pub kill_on_storm: bool,
+
+    /// Keep recording processes after they daemonize, which is the default.
+    #[arg(long, overrides_with = "no_follow_daemons")]
+    pub follow_daemons: bool,
+
+    /// Stop recording processes once they start a new session.
+    ///
+    /// Daemons start a new session to detach from the process that started
+    /// them, and since they outlive it they would otherwise keep the recording
+    /// going. The new session is recorded but nothing after it is.
+    #[arg(long, overrides_with = "follow_daemons")]
+    pub no_follow_daemons: bool,
+
+    /// Give up on processes whose parents haven't joined the process tree after
+    /// this long, e.g. "5s", and discard their events.
+    ///
+    /// A plain number is in milliseconds. This bounds the memory used on hosts
+    /// that run lots of unrelated processes, at the cost of missing subtrees whose
+    /// parents join the tree later than that.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub buffer_window: Option<u128>,
+
+    /// Only record the subtrees of a random fraction of the command's children,
+    /// e.g. 0.1 for one in ten.
+    ///
+    /// Every event of a chosen subtree is recorded, and the others are left out
+    /// entirely. This keeps recordings of workloads that fork tens of thousands of
+    /// nearly identical children, like test runners, down to a manageable size
+    /// when a sample of them is enough. The ratio and the seed that chose the
+    /// subtrees are kept in the metadata.
+    #[arg(long, value_name = "RATIO")]
+    pub sample_trees: Option<crate::models::SampleRatio>,
+
+    /// The seed that chooses which subtrees `--sample-trees` records, instead of a
+    /// random one.
+    ///
+    /// Which children are chosen also depends on the order they're forked in, so
+    /// this makes the choice repeatable for the same events rather than for every
+    /// run of the command.
+    #[arg(long, value_name = "SEED", requires = "sample_trees")]
+    pub sample_seed: Option<u64>,
+
+    /// Record every mount and unmount by a recorded process.
+    ///
+    /// Each one is stored as a `Mount` event and shown as a marker with the mount
+    /// target by the "mermaid" display mode, which shows how long a sandbox took to
+    /// set up (e.g. the bind mounts of a Nix build). Only the `bpftrace` backend
+    /// can trace mounts.
+    #[arg(long)]
+    pub trace_mounts: bool,
+
+    /// Only record these kinds of events, e.g. `fork,exec,exit`.
+    ///
+    /// The probes for the other kinds are left out of the `bpftrace` script, which
+    /// lowers the overhead of recording and the chance of losing events when a
+    /// command forks or changes process groups at a high rate. `fork` and `exit` are
+    /// always needed to follow the process tree. Every kind is recorded by
+    /// default, and only the `bpftrace` backend can leave any out.
+    #[arg(long, value_name = "KINDS", value_delimiter = ',')]
+    pub events: Vec<EventKind>,
+
+    /// Send every event on the machine to `proctrace` instead of filtering them in
+    /// the kernel.
+    ///
+    /// By default the `bpftrace` script only records the descendants of `proctrace`
+    /// (and the processes in `--cgroup` and `--container`), which keeps a busy host
+    /// from flooding the recorder and losing events. Every event is recorded anyway
+    /// with `--adopt-exec` and `--systemd-scope`, since the processes they adopt can
+    /// be anywhere. A raw recording of every event can be ingested again with a
+    /// root PID that isn't a descendant of `proctrace`.
+    #[arg(long)]
+    pub no_kernel_filter: bool,
+
+    /// Sample which recorded processes are running on a CPU this many times a
+    /// second, e.g. 99.
+    ///
+    /// Each sample is stored as a `CpuSample` event, and the "mermaid" display mode
+    /// labels each span with the share of its time that it spent on a CPU and draws
+    /// the ones that were mostly waiting as done. Only processes started by
+    /// `proctrace` and their descendants are sampled, and only the `bpftrace`
+    /// backend can sample them.
+    #[arg(long, value_name = "HZ", value_parser = clap::value_parser!(u32).range(1..))]
+    pub sample_cpu: Option<u32>,
+
+    /// The container runtime used to look up `--container`, e.g. `docker` or `podman`.
+    #[arg(
+        long,
+        value_name = "PATH",
+        default_value = "docker",
+        requires = "container"
+    )]
+    pub container_runtime: PathBuf,
+
+    /// Set an environment variable for the command, e.g. `CI=true`.
+    ///
+    /// May be given more than once, and takes precedence over `--env-file`.
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_env_var)]
+    pub env: Vec<(String, String)>,
+
+    /// Set the environment variables in this file for the command.
+    ///
+    /// Each line is a `KEY=VALUE` pair, optionally preceded by `export`. Blank lines
+    /// and lines starting with '#' are ignored, and a value in matching quotes has
+    /// them removed.
+    #[arg(long, value_name = "PATH")]
+    pub env_file: Option<PathBuf>,
+
+    /// Run the command in this directory.
+    #[arg(long, value_name = "DIR")]
+    pub cd: Option<PathBuf>,
+
+    /// Record a shell command line, e.g. 'make && make test | tee log', instead of a
+    /// command after `--`.
+    ///
+    /// The command line is run with `$SHELL -c` (or `/bin/sh -c` if `SHELL` isn't
+    /// set), and the shell is marked in the recording so that `render
+    /// --hide-shell-wrapper` can leave it out. Each command of a pipeline is a child
+    /// of the shell that starts at the same time as the others.
+    #[arg(long, value_name = "COMMAND_LINE", conflicts_with = "cmd")]
+    pub shell: Option<String>,
+
     /// The user-provided command that should be recorded.
     ///
     /// Note that this will print to the terminal if it has output. `proctrace`
@@ -102,25 +1139,315 @@ pub struct RecordArgs {
     pub cmd: Vec<String>,
 }
 
+#[cfg(all(target_os = "linux", feature = "record"))]
+impl RecordArgs {
+    /// Returns which recordings to write, which `--raw` and `--raw-output` imply.
+    pub fn resolved_out_format(&self) -> Result<OutFormat, anyhow::Error> {
+        let out_format = match (self.out_format, self.raw, self.raw_output.is_some()) {
+            (OutFormat::Raw, _, true) => {
+                return Err(anyhow::anyhow!(
+                    "--raw-output can't be used with --out-format raw, use --output instead"
+                ))
+            }
+            (_, true, _) => OutFormat::Raw,
+            (_, _, true) => OutFormat::Both,
+            (out_format, _, _) => out_format,
+        };
+        if out_format == OutFormat::Processed && self.rotate_every.is_some() {
+            return Err(anyhow::anyhow!(
+                "--rotate-every only splits raw output, use --rotate-size or --out-format both"
+            ));
+        }
+        Ok(out_format)
+    }
+
+    /// Returns where to write the raw events alongside the processed events.
+    ///
+    /// This is `--raw-output` if it was passed, otherwise the output path with ".raw"
+    /// added before any compression extension.
+    pub fn both_raw_output_path(&self) -> Option<PathBuf> {
+        if let Some(ref path) = self.raw_output {
+            return Some(path.clone());
+        }
+        let output = self.output_path.as_ref()?;
+        let name = output.file_name()?.to_string_lossy();
+        let name = match Compression::from_path(output) {
+            Compression::None => format!("{name}.raw"),
+            _ => {
+                let (stem, ext) = name.rsplit_once('.')?;
+                format!("{stem}.raw.{ext}")
+            }
+        };
+        Some(output.with_file_name(name))
+    }
+
+    /// Returns when the output should be split into a new segment.
+    pub fn rotation_policy(&self) -> crate::segments::RotationPolicy {
+        crate::segments::RotationPolicy {
+            max_bytes: self.rotate_size,
+            max_age: self.rotate_every.map(std::time::Duration::from_secs),
+        }
+    }
+}
+
+/// Which recordings `proctrace record` writes.
+#[derive(Debug, Default, Copy, Clone, ValueEnum, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutFormat {
+    /// The events of the process tree, ready to be rendered.
+    #[default]
+    Processed,
+    /// Every event from the backend, which has to be ingested before it's rendered.
+    Raw,
+    /// Both, with the raw events written as they're recorded.
+    Both,
+}
+
+impl std::fmt::Display for OutFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutFormat::Processed => write!(f, "processed"),
+            OutFormat::Raw => write!(f, "raw"),
+            OutFormat::Both => write!(f, "both"),
+        }
+    }
+}
+
+/// Where the events of a recording come from.
+#[derive(Debug, Default, Copy, Clone, ValueEnum, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    /// Trace the kernel with `bpftrace`, which must be run as root.
+    #[default]
+    Bpftrace,
+    /// Read task events with `perf_event_open`, which doesn't need root.
+    Perf,
+    /// Read scheduler events from tracefs, which needs root but not BPF.
+    Ftrace,
+    /// Poll `/proc`, which is lossy but needs no privileges.
+    Procfs,
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Backend::Bpftrace => write!(f, "bpftrace"),
+            Backend::Perf => write!(f, "perf"),
+            Backend::Ftrace => write!(f, "ftrace"),
+            Backend::Procfs => write!(f, "procfs"),
+        }
+    }
+}
+
+/// A kind of event that the `bpftrace` backend records, which `record --events`
+/// chooses from.
+#[derive(Debug, Copy, Clone, ValueEnum, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EventKind {
+    /// A process forking a child.
+    Fork,
+    /// A process exec'ing a program, including execs that failed.
+    Exec,
+    /// A process exiting, along with what it cost.
+    Exit,
+    /// A process starting a new session.
+    Setsid,
+    /// A process moving itself or a child into a process group.
+    Setpgid,
+    /// A process changing its working directory.
+    Chdir,
+    /// A process forking into, creating, or entering namespaces.
+    Namespace,
+    /// A process starting to trace another one.
+    Ptrace,
+    /// A process restricting itself with seccomp or Landlock.
+    Sandbox,
+    /// A process making itself a child subreaper.
+    Subreaper,
+}
+
+impl std::fmt::Display for EventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventKind::Fork => write!(f, "fork"),
+            EventKind::Exec => write!(f, "exec"),
+            EventKind::Exit => write!(f, "exit"),
+            EventKind::Setsid => write!(f, "setsid"),
+            EventKind::Setpgid => write!(f, "setpgid"),
+            EventKind::Chdir => write!(f, "chdir"),
+            EventKind::Namespace => write!(f, "namespace"),
+            EventKind::Ptrace => write!(f, "ptrace"),
+            EventKind::Sandbox => write!(f, "sandbox"),
+            EventKind::Subreaper => write!(f, "subreaper"),
+        }
+    }
+}
+
+/// Settings for recording well-known kinds of commands.
+#[derive(Debug, Copy, Clone, ValueEnum, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    /// Adopt the builders that `nix-daemon` forks for each derivation and shorten
+    /// Nix store paths.
+    NixBuild,
+    /// Tag the phases of activating a Flox environment or dev shell (environment
+    /// builds, hooks, shell rc files) and report how long each one took.
+    Activation,
+    /// Report how long was spent building each crate in a `cargo` build,
+    /// including build scripts and linkers.
+    Cargo,
+}
+
+impl std::fmt::Display for Preset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Preset::NixBuild => write!(f, "nix-build"),
+            Preset::Activation => write!(f, "activation"),
+            Preset::Cargo => write!(f, "cargo"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Args, PartialEq, Eq)]
+#[cfg(all(target_os = "linux", feature = "record"))]
+pub struct BenchArgs {
+    /// The path to a `bpftrace` executable.
+    ///
+    /// See `proctrace record --help` for when this is necessary.
+    #[arg(
+        short,
+        long,
+        help = "Path to a bpftrace executable",
+        value_name = "PATH",
+        default_value = "bpftrace"
+    )]
+    pub bpftrace_path: PathBuf,
+
+    /// How many times to record the command.
+    #[arg(short = 'n', long, value_name = "N", default_value_t = 10)]
+    pub runs: usize,
+
+    /// The directory to store the recordings and summary in.
+    ///
+    /// The directory is created if it doesn't exist. Runs are named
+    /// "run-0001.json", "run-0002.json", etc.
+    #[arg(short, long = "output", value_name = "DIR")]
+    pub output_dir: PathBuf,
+
+    /// The encoding of each recording.
+    #[arg(long, value_name = "FORMAT", default_value_t = TraceFormat::Json)]
+    pub format: TraceFormat,
+
+    /// Show debug output while recording.
+    #[arg(long)]
+    pub debug: bool,
+
+    /// The user-provided command that should be recorded.
+    #[arg(last = true, value_name = "CMD")]
+    pub cmd: Vec<String>,
+}
+
 #[derive(Debug, Clone, Args, PartialEq, Eq)]
+#[command(group(clap::ArgGroup::new("streaming").multiple(true)))]
 pub struct RenderArgs {
     /// How should the output be rendered.
     ///
     /// For "sequential" events will be shown in the order that they were received.
     /// For "by-process" events are shown in order for each process,
     /// and processes are separated by a blank line. For "mermaid" the output is the
-    /// syntax for a Mermaid.js Gantt chart.
+    /// syntax for a Mermaid.js Gantt chart, and "mermaid-md" wraps it in a
+    /// ```mermaid fence for pasting into Markdown. For "gha-summary" the output is a
+    /// Markdown summary of the slowest processes, failures, and a Mermaid.js chart
+    /// that's suitable for `$GITHUB_STEP_SUMMARY`. For "d2" and "plantuml" the spans of
+    /// the "mermaid" chart are drawn in D2 or as a PlantUML Gantt chart, for
+    /// documentation tools that don't support Mermaid. For "table" every process is a
+    /// row of aligned columns, which is easy to read and to feed to `awk`. For
+    /// "exec:<PROGRAM>" the processed events are written to the stdin of PROGRAM as a
+    /// JSON recording, and whatever it writes to stdout is the output.
     #[arg(short, long, help = "The output format")]
-    #[arg(default_value_t = DisplayMode::Sequential)]
+    #[arg(default_value_t = DisplayMode::Sequential, value_parser = DisplayModeParser)]
     pub display_mode: DisplayMode,
 
     /// The location where an event recording should be read from.
     ///
-    /// Must either be a path to a file or '-' to read from stdin.
-    #[arg(short, long = "input", help = "The path to the event data file")]
-    pub input_path: PathBuf,
+    /// Must either be a path to a file or '-' to read from stdin. Input that's
+    /// compressed with gzip or zstd is decompressed automatically, the segments
+    /// listed in a manifest (".manifest.json") are read in order, and the runs listed
+    /// in the manifest of `proctrace bench` are merged onto one timeline.
+    #[arg(
+        short,
+        long = "input",
+        help = "The path to the event data file",
+        required_unless_present = "overlay"
+    )]
+    pub input_path: Option<PathBuf>,
+
+    /// Render two recordings on the same timeline to compare them before and after
+    /// a change.
+    ///
+    /// Both recordings are aligned so that their root processes start at t=0, and
+    /// processes with the same command line are shown in adjacent lanes. Only the
+    /// "mermaid" display mode is supported.
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["BEFORE", "AFTER"],
+        conflicts_with_all = ["input_path", "stream", "baseline"]
+    )]
+    pub overlay: Option<Vec<PathBuf>>,
+
+    /// Only render the process tree rooted at this PID.
+    ///
+    /// Indexed recordings (see `record --format indexed`) are read by seeking to the
+    /// parts of the file that contain the tree, anything else is read in full.
+    #[arg(long, value_name = "PID", conflicts_with_all = ["overlay", "stream"])]
+    pub pid: Option<i32>,
+
+    /// Only render the processes that were still running this many milliseconds
+    /// after the recording started, along with their ancestors.
+    ///
+    /// Like `--pid`, indexed recordings don't have to be read in full.
+    #[arg(long, value_name = "MS", conflicts_with_all = ["overlay", "stream"])]
+    pub since: Option<u64>,
+
+    /// Only render the processes of these users (by user ID), along with their
+    /// ancestors.
+    ///
+    /// Processes whose user isn't in the recording, e.g. from older versions of
+    /// `proctrace`, are left out. The root process is always rendered.
+    #[arg(
+        long,
+        value_name = "UIDS",
+        value_delimiter = ',',
+        conflicts_with_all = ["overlay", "streaming"]
+    )]
+    pub include_uid: Vec<u32>,
+
+    /// Leave out the processes of these users (by user ID), along with their
+    /// descendants, e.g. other users' children of a shared parent.
+    ///
+    /// The root process is always rendered.
+    #[arg(
+        long,
+        value_name = "UIDS",
+        value_delimiter = ',',
+        conflicts_with_all = ["overlay", "streaming"]
+    )]
+    pub exclude_uid: Vec<u32>,
+
+    /// Read the input through a buffer instead of mapping it into memory.
+    ///
+    /// Uncompressed files are mapped by default, which is faster when the same
+    /// recording is rendered repeatedly. A mapped file that's truncated while it's
+    /// being read crashes `proctrace`, so use this if the input may be truncated.
+    #[arg(long)]
+    pub no_mmap: bool,
 
     /// Where to write the rendered output.
+    ///
+    /// If this is a directory (or ends with '/'), the "mermaid" chart is written to
+    /// `chart.mmd` inside it along with a Markdown and JSON page of details for each
+    /// process in `pids/`. Labels in the chart are truncated and link to those pages.
     #[arg(
         short,
         long = "output",
@@ -128,16 +1455,147 @@ pub struct RenderArgs {
         value_name = "PATH"
     )]
     pub output_path: Option<PathBuf>,
+
+    /// How to compress the output.
+    ///
+    /// If omitted, the compression is chosen from the extension of the output
+    /// path (".gz" or ".zst"), and output to stdout is left uncompressed.
+    #[arg(long, value_name = "KIND")]
+    pub compress: Option<Compression>,
+
+    /// Render events as they're read instead of reading the whole recording first.
+    ///
+    /// This keeps memory use constant for very large recordings. Only the
+    /// "sequential" and "by-process" display modes can be streamed.
+    #[arg(long, group = "streaming")]
+    pub stream: bool,
+
+    /// Render a recording that's still being written and print the output as soon as
+    /// it's ready, e.g. `proctrace record --stream -- <cmd> | proctrace render -i - --follow`.
+    ///
+    /// Implies `--stream`.
+    #[arg(long, group = "streaming")]
+    pub follow: bool,
+
+    /// How many events (or finished processes for "by-process") to hold back
+    /// while streaming so that out of order events can be put back in order.
+    ///
+    /// Defaults to 4096, or 64 with `--follow` so that the output doesn't fall far
+    /// behind the recording.
+    #[arg(long, value_name = "N", requires = "streaming")]
+    pub reorder_window: Option<usize>,
+
+    /// Render a process that exec'd through wrappers (e.g. `bash` -> `env` ->
+    /// `python` -> the actual tool) as a single span labeled with the last exec.
+    ///
+    /// The wrappers are listed in a comment after the span. Only affects the
+    /// "mermaid" and "gha-summary" display modes, "by-process" still shows every exec.
+    #[arg(long)]
+    pub collapse_exec_chains: bool,
+
+    /// Highlight spans that lasted longer than this many milliseconds so that slow
+    /// steps stand out.
+    ///
+    /// Highlighted spans are marked as critical, which Mermaid draws in red. Only
+    /// affects the "mermaid" and "gha-summary" display modes.
+    #[arg(long, value_name = "MS")]
+    pub highlight_over: Option<u64>,
+
+    /// Leave out the shell that a recording taken with `record --shell` ran the
+    /// command line with, so its commands are the top of the chart.
+    ///
+    /// A shell that exec'd the command in place of itself is still shown. Only
+    /// affects the "mermaid" and "gha-summary" display modes.
+    #[arg(long)]
+    pub hide_shell_wrapper: bool,
+
+    /// Group the processes of the "by-process" display mode by directory or by tag,
+    /// see `proctrace analyze groups`.
+    #[cfg(feature = "fs")]
+    #[command(flatten)]
+    pub group: GroupArgs,
+
+    /// The order of the rows of the "table" display mode.
+    #[arg(long, value_name = "ORDER", default_value_t = TableSort::Start)]
+    pub sort_by: TableSort,
+
+    /// Only show this many rows in the "table" display mode.
+    #[arg(long, value_name = "N")]
+    pub limit: Option<usize>,
+
+    /// Start the "mermaid-md" output with a heading that names the command, followed
+    /// by how long it ran and how many processes it started.
+    #[arg(long)]
+    pub heading: bool,
+
+    /// A TOML file of friendly names for commands, e.g. "gcc" for
+    /// "/nix/store/<hash>-gcc-12.3.0/bin/cc".
+    ///
+    /// Each `[[label]]` table has a `match` regex, which is matched against the
+    /// file name and the command line of each exec, and the `name` to show instead,
+    /// which can refer to captured groups as "$1". The first matching label wins.
+    /// Applies to every display mode.
+    #[cfg(feature = "fs")]
+    #[arg(long, value_name = "PATH")]
+    pub labels: Option<PathBuf>,
+
+    /// How to show the command line of each exec.
+    ///
+    /// "basename" replaces the path of the program with its file name, and "smart"
+    /// also cuts the arguments off after the first few. Commands that `--labels`
+    /// names are left alone. Applies to every display mode.
+    #[arg(long, value_name = "STYLE", default_value_t = ArgvStyle::Full)]
+    pub argv_style: ArgvStyle,
+
+    /// A recording or bench results to compare against in the "gha-summary" display
+    /// mode.
+    ///
+    /// The comparison is added to the summary. See `proctrace compare` for the
+    /// kinds of paths that are accepted.
+    #[cfg(feature = "fs")]
+    #[arg(long, value_name = "PATH")]
+    pub baseline: Option<PathBuf>,
+
+    /// Emit a `::warning::` annotation for each of these conditions that's met
+    /// when compared against `--baseline`.
+    ///
+    /// See `proctrace compare --help` for the syntax. The annotations are printed
+    /// to stderr so they don't end up in the summary.
+    #[cfg(feature = "fs")]
+    #[arg(long, value_name = "CONDITIONS", requires = "baseline")]
+    pub warn_if: Option<crate::compare::FailIf>,
 }
 
 #[derive(Debug, Clone, Args, PartialEq, Eq)]
 pub struct IngestArgs {
     /// The path to the raw recording to be processed.
     ///
-    /// Must either be a path to a file or '-' to read from stdin.
+    /// Must either be a path to a file or '-' to read from stdin. Input that's
+    /// compressed with gzip or zstd is decompressed automatically.
     #[arg(short, long = "input", help = "The path to the event data file")]
     pub input_path: PathBuf,
 
+    /// The format of the raw recording.
+    ///
+    /// Use "jsonl-raw" for events from a custom collector (e.g. another eBPF tool),
+    /// written as one JSON object per line such as
+    /// `{"type":"fork","ts":100,"pid":2,"ppid":1}`. The types are "fork" (with an
+    /// optional "comm" naming the child), "exec" (with "filename" and/or "argv"),
+    /// "badexec" for a failed exec (with an optional "filename" and "errno"), "exit"
+    /// (with an optional "exit_code"), "setsid", "setpgid" (with "pgid", and "caller"
+    /// when a parent moved "pid" into the group), "chdir" (with an optional "path"),
+    /// "namespace" (with "how", which is "clone", "unshare", or "setns", and
+    /// "namespaces" such as ["mnt", "net"]), "mount" (with "target", an optional
+    /// "fstype", and "unmount": true for an unmount), "ptrace" (with "request", which
+    /// is "traceme", "attach", or "seize", and an optional "target" and "errno"),
+    /// "sandbox" (with "mechanism", which is "seccomp" or "landlock", and an optional
+    /// "mode" and "flags"), "subreaper", "cpu", "rss" (with "rss_kb"), and "usage"
+    /// (with any of "minor_faults", "major_faults", "voluntary_switches",
+    /// "involuntary_switches", "user_ns", and "system_ns"). Timestamps are in
+    /// nanoseconds.
+    #[arg(long, value_name = "FORMAT", default_value_t = RawFormat::Bpftrace)]
+    pub input_format: RawFormat,
+
     /// Where to write the processed recording.
     #[arg(
         short,
@@ -147,6 +1605,20 @@ pub struct IngestArgs {
     )]
     pub output_path: Option<PathBuf>,
 
+    /// How to compress the output.
+    ///
+    /// If omitted, the compression is chosen from the extension of the output
+    /// path (".gz" or ".zst"), and output to stdout is left uncompressed.
+    #[arg(long, value_name = "KIND")]
+    pub compress: Option<Compression>,
+
+    /// The encoding of the processed recording.
+    ///
+    /// Both formats can be read by every other subcommand, which detect the
+    /// format automatically.
+    #[arg(long, value_name = "FORMAT", default_value_t = TraceFormat::Json)]
+    pub format: TraceFormat,
+
     /// Which PID to use as the root of the process tree.
     ///
     /// A raw recording contains events from the entire system,
@@ -158,4 +1630,255 @@ pub struct IngestArgs {
     /// Whether to display debug output while ingesting.
     #[arg(short, long)]
     pub debug: bool,
+
+    /// Keep recording processes after they daemonize, which is the default.
+    #[arg(long, overrides_with = "no_follow_daemons")]
+    pub follow_daemons: bool,
+
+    /// Stop recording processes once they start a new session.
+    ///
+    /// Daemons start a new session to detach from the process that started
+    /// them, and since they outlive it they would otherwise keep the recording
+    /// going. The new session is recorded but nothing after it is.
+    #[arg(long, overrides_with = "follow_daemons")]
+    pub no_follow_daemons: bool,
+
+    /// Write the events of processes that never joined the process tree to this
+    /// path, in the same encoding as the output.
+    ///
+    /// These are discarded otherwise. They help find out why a subtree is missing
+    /// from the recording, e.g. because a daemon forked it and so its parent isn't
+    /// part of the tree.
+    #[arg(long, value_name = "PATH")]
+    pub keep_unmatched: Option<PathBuf>,
+
+    /// Record the children of a process outside of the process tree as children
+    /// of the root, e.g. the builds that a daemon runs on behalf of the command.
+    ///
+    /// This is either a PID, or a regex that's matched against the name of the
+    /// parent and the last thing that it exec'd, e.g. "nix-daemon" or "dockerd".
+    /// Their descendants are recorded too, and the actual parent of each child is
+    /// kept in the metadata. Can be repeated.
+    #[arg(long, value_name = "PID|REGEX")]
+    pub adopt_parent: Vec<String>,
+
+    /// Give up on processes whose parents haven't joined the process tree after
+    /// this long, e.g. "5s", as measured by the timestamps of the events.
+    ///
+    /// A plain number is in milliseconds. Their events are written to
+    /// `--keep-unmatched` if it's given and discarded otherwise. This bounds the
+    /// memory used on hosts that run lots of unrelated processes, at the cost of
+    /// missing subtrees whose parents join the tree later than that.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub buffer_window: Option<u128>,
+}
+
+#[derive(Debug, Clone, Args, PartialEq, Eq)]
+#[cfg(feature = "fs")]
+pub struct SortArgs {
+    /// The path to the processed recording to sort.
+    ///
+    /// Must either be a path to a file or '-' to read from stdin. Input that's
+    /// compressed with gzip or zstd is decompressed automatically, the segments
+    /// listed in a manifest (".manifest.json") are read in order, and the runs listed
+    /// in the manifest of `proctrace bench` are merged onto one timeline.
+    #[arg(short, long = "input", help = "The path to the event data file")]
+    pub input_path: PathBuf,
+
+    /// Where to write the sorted recording.
+    #[arg(
+        short,
+        long = "output",
+        help = "Where to write the output (printed to stdout if omitted).",
+        value_name = "PATH"
+    )]
+    pub output_path: Option<PathBuf>,
+
+    /// How to compress the output.
+    ///
+    /// If omitted, the compression is chosen from the extension of the output
+    /// path (".gz" or ".zst"), and output to stdout is left uncompressed.
+    #[arg(long, value_name = "KIND")]
+    pub compress: Option<Compression>,
+
+    /// The encoding of the sorted recording.
+    #[arg(long, value_name = "FORMAT", default_value_t = TraceFormat::Json)]
+    pub format: TraceFormat,
+
+    /// How many events to sort in memory at a time.
+    ///
+    /// Larger recordings are sorted in chunks of this size that are spilled to
+    /// temporary files and then merged.
+    #[arg(long, value_name = "EVENTS", default_value_t = crate::sort::DEFAULT_CHUNK_SIZE)]
+    pub chunk_size: usize,
+}
+
+#[derive(Debug, Clone, Args, PartialEq, Eq)]
+#[cfg(feature = "fs")]
+pub struct MergeArgs {
+    /// The processed recordings to merge.
+    ///
+    /// The first one should be the recording that the others are part of (e.g. the
+    /// coordinator of a distributed build), since the others are expected to overlap
+    /// with it.
+    #[arg(required = true, num_args = 2.., value_name = "INPUT")]
+    pub inputs: Vec<PathBuf>,
+
+    /// Where to write the merged recording.
+    #[arg(
+        short,
+        long = "output",
+        help = "Where to write the output (printed to stdout if omitted).",
+        value_name = "PATH"
+    )]
+    pub output_path: Option<PathBuf>,
+
+    /// How to compress the output.
+    ///
+    /// If omitted, the compression is chosen from the extension of the output
+    /// path (".gz" or ".zst"), and output to stdout is left uncompressed.
+    #[arg(long, value_name = "KIND")]
+    pub compress: Option<Compression>,
+
+    /// The encoding of the merged recording.
+    #[arg(long, value_name = "FORMAT", default_value_t = TraceFormat::Json)]
+    pub format: TraceFormat,
+
+    /// Moves one of the inputs along the timeline, in milliseconds, e.g.
+    /// "remote.json=-250".
+    ///
+    /// Recordings are placed by the wall-clock time they started at, so this corrects
+    /// for a host whose clock was off. May be given once for each input.
+    #[arg(long, value_name = "INPUT=MS", value_parser = parse_clock_offset)]
+    pub clock_offset: Vec<(PathBuf, i64)>,
+}
+
+#[derive(Debug, Clone, Args, PartialEq, Eq)]
+#[cfg(feature = "fs")]
+pub struct PruneArgs {
+    /// The path to the processed recording to prune.
+    ///
+    /// Must either be a path to a file or '-' to read from stdin. Input that's
+    /// compressed with gzip or zstd is decompressed automatically, the segments
+    /// listed in a manifest (".manifest.json") are read in order, and the runs listed
+    /// in the manifest of `proctrace bench` are merged onto one timeline.
+    #[arg(short, long = "input", help = "The path to the event data file")]
+    pub input_path: PathBuf,
+
+    /// Where to write the pruned recording.
+    #[arg(
+        short,
+        long = "output",
+        help = "Where to write the output (printed to stdout if omitted).",
+        value_name = "PATH"
+    )]
+    pub output_path: Option<PathBuf>,
+
+    /// How to compress the output.
+    ///
+    /// If omitted, the compression is chosen from the extension of the output
+    /// path (".gz" or ".zst"), and output to stdout is left uncompressed.
+    #[arg(long, value_name = "KIND")]
+    pub compress: Option<Compression>,
+
+    /// The encoding of the pruned recording.
+    #[arg(long, value_name = "FORMAT", default_value_t = TraceFormat::Json)]
+    pub format: TraceFormat,
+
+    /// Remove processes that exited sooner than this, e.g. "5ms" or "1s".
+    ///
+    /// A plain number is in milliseconds. Processes that never exited are kept.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub min_duration: Option<u128>,
+
+    /// Remove processes whose last command line matches this regex, e.g.
+    /// '^\[kworker'. May be given more than once.
+    #[arg(long, value_name = "REGEX")]
+    pub drop_cmd: Vec<String>,
+}
+
+/// Parses a duration like "5ms" into nanoseconds, a plain number is in milliseconds.
+pub(crate) fn parse_duration(s: &str) -> Result<u128, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number = number
+        .parse::<u128>()
+        .map_err(|_| format!("'{s}' doesn't start with a number"))?;
+    let scale = match unit {
+        "ns" => 1,
+        "us" => 1_000,
+        "" | "ms" => 1_000_000,
+        "s" => 1_000_000_000,
+        "m" => 60_000_000_000,
+        _ => {
+            return Err(format!(
+                "unknown unit '{unit}', expected ns, us, ms, s, or m"
+            ))
+        }
+    };
+    number
+        .checked_mul(scale)
+        .ok_or_else(|| format!("'{s}' is too large"))
+}
+
+/// Parses a `KEY=VALUE` pair of an environment variable.
+pub fn parse_env_var(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE, found '{s}'"))?;
+    if key.is_empty() {
+        return Err(format!("'{s}' has an empty variable name"));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+#[cfg(feature = "fs")]
+fn parse_clock_offset(s: &str) -> Result<(PathBuf, i64), String> {
+    let (path, offset) = s
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected INPUT=MS, found '{s}'"))?;
+    let offset = offset
+        .parse()
+        .map_err(|_| format!("'{offset}' isn't a number of milliseconds"))?;
+    Ok((PathBuf::from(path), offset))
+}
+
+#[derive(Debug, Clone, Args, PartialEq, Eq)]
+#[cfg(feature = "fs")]
+pub struct CompareArgs {
+    /// The recording or bench results to compare against.
+    #[arg(long, value_name = "PATH")]
+    pub baseline: PathBuf,
+
+    /// The recording or bench results being checked.
+    #[arg(value_name = "PATH")]
+    pub current: PathBuf,
+
+    /// Fail if any of these conditions are met, e.g. "total>+10% || cmd:cargo>+20%".
+    ///
+    /// Each condition is a metric ("total" for the whole recording or
+    /// "cmd:<name>" for the time spent in an executable), followed by '>' or '<'
+    /// and a change relative to the baseline, either as a percentage ("+10%") or
+    /// as a duration ("+500ms", "-2s"). Conditions are joined with "||". A
+    /// command that doesn't appear in the baseline has grown by an infinite
+    /// percentage.
+    #[arg(long, value_name = "CONDITIONS")]
+    pub fail_if: Option<crate::compare::FailIf>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("250"), Ok(250_000_000));
+        assert_eq!(parse_duration("5s"), Ok(5_000_000_000));
+        assert_eq!(parse_duration("2m"), Ok(120_000_000_000));
+        assert!(parse_duration("5h").is_err());
+        assert!(parse_duration("s").is_err());
+        // Fits in a u128 as a number but not once it's scaled to nanoseconds
+        let err = parse_duration(&format!("{}m", u128::MAX / 1_000)).unwrap_err();
+        assert!(err.contains("too large"), "{err}");
+    }
 }
@@ -0,0 +1,294 @@
+use std::{collections::BTreeSet, str::FromStr};
+
+use anyhow::{anyhow, Context};
+
+use crate::analyze::{format_nanos, RunSummary};
+
+type Error = anyhow::Error;
+
+/// A measurement that can be compared between two recordings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Metric {
+    /// The duration of the whole recording.
+    Total,
+    /// The time spent running a command.
+    Command(String),
+}
+
+impl Metric {
+    /// Returns the value of the metric in nanoseconds, which is zero for commands
+    /// that never ran.
+    pub fn value(&self, summary: &RunSummary) -> u128 {
+        match self {
+            Metric::Total => summary.total,
+            Metric::Command(name) => summary.commands.get(name).copied().unwrap_or(0),
+        }
+    }
+}
+
+impl std::fmt::Display for Metric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Metric::Total => write!(f, "total"),
+            Metric::Command(name) => write!(f, "cmd:{name}"),
+        }
+    }
+}
+
+/// How much a metric is allowed to change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    /// A change relative to the baseline, in hundredths of a percent.
+    Relative(i64),
+    /// A change in nanoseconds.
+    Absolute(i128),
+}
+
+impl FromStr for Change {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_number = |s: &str| {
+            s.parse::<f64>()
+                .ok()
+                .filter(|n| n.is_finite())
+                .ok_or(anyhow!("invalid number '{s}'"))
+        };
+        if let Some(pct) = s.strip_suffix('%') {
+            let pct = parse_number(pct)?;
+            return Ok(Change::Relative((pct * 100.0).round() as i64));
+        }
+        // Check the longer suffixes first so that "ms" isn't mistaken for "s"
+        for (suffix, scale) in [("ns", 1.0), ("us", 1e3), ("ms", 1e6), ("s", 1e9)] {
+            if let Some(n) = s.strip_suffix(suffix) {
+                let n = parse_number(n)?;
+                return Ok(Change::Absolute((n * scale).round() as i128));
+            }
+        }
+        Err(anyhow!(
+            "expected a percentage or a duration (ns, us, ms, s), found '{s}'"
+        ))
+    }
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::Relative(bps) => write!(f, "{:+}%", *bps as f64 / 100.0),
+            Change::Absolute(nanos) => {
+                let sign = if *nanos < 0 { "-" } else { "+" };
+                write!(f, "{sign}{}", format_nanos(nanos.unsigned_abs()))
+            }
+        }
+    }
+}
+
+/// A single condition such as `cmd:cargo>+20%`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    pub metric: Metric,
+    /// `true` for `>` and `false` for `<`.
+    pub greater: bool,
+    pub limit: Change,
+}
+
+impl Condition {
+    /// Returns `true` if the change from `baseline` to `current` exceeds the limit.
+    ///
+    /// A command that's missing from the baseline has grown by an infinite percentage.
+    pub fn is_met(&self, baseline: &RunSummary, current: &RunSummary) -> bool {
+        let before = self.metric.value(baseline) as f64;
+        let after = self.metric.value(current) as f64;
+        let (change, limit) = match self.limit {
+            Change::Relative(bps) => {
+                let change = if before == 0.0 {
+                    if after == 0.0 {
+                        0.0
+                    } else {
+                        f64::INFINITY
+                    }
+                } else {
+                    (after - before) / before * 100.0
+                };
+                (change, bps as f64 / 100.0)
+            }
+            Change::Absolute(nanos) => (after - before, nanos as f64),
+        };
+        if self.greater {
+            change > limit
+        } else {
+            change < limit
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (idx, greater) = s
+            .find(['>', '<'])
+            .map(|idx| (idx, s.as_bytes()[idx] == b'>'))
+            .ok_or(anyhow!("condition '{s}' has no '>' or '<'"))?;
+        let metric = match s[..idx].trim() {
+            "total" => Metric::Total,
+            other => match other.strip_prefix("cmd:") {
+                Some(name) if !name.is_empty() => Metric::Command(name.to_string()),
+                _ => {
+                    return Err(anyhow!(
+                        "unknown metric '{other}', expected 'total' or 'cmd:<name>'"
+                    ))
+                }
+            },
+        };
+        let limit = s[idx + 1..]
+            .trim()
+            .parse()
+            .with_context(|| format!("invalid limit in condition '{s}'"))?;
+        Ok(Self {
+            metric,
+            greater,
+            limit,
+        })
+    }
+}
+
+impl std::fmt::Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = if self.greater { '>' } else { '<' };
+        write!(f, "{}{op}{}", self.metric, self.limit)
+    }
+}
+
+/// A set of conditions joined by `||`, any of which fails the comparison.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FailIf {
+    pub conditions: Vec<Condition>,
+}
+
+impl FailIf {
+    /// Returns the conditions that are met by the change from `baseline` to `current`.
+    pub fn failures(&self, baseline: &RunSummary, current: &RunSummary) -> Vec<&Condition> {
+        self.conditions
+            .iter()
+            .filter(|condition| condition.is_met(baseline, current))
+            .collect()
+    }
+}
+
+impl FromStr for FailIf {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let conditions = s
+            .split("||")
+            .map(|condition| condition.trim().parse())
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { conditions })
+    }
+}
+
+/// Formats the change in every metric from `baseline` to `current` as a table.
+pub fn comparison_table(baseline: &RunSummary, current: &RunSummary) -> String {
+    let names = baseline
+        .commands
+        .keys()
+        .chain(current.commands.keys())
+        .cloned()
+        .collect::<BTreeSet<_>>();
+    let mut rows = names.into_iter().map(Metric::Command).collect::<Vec<_>>();
+    // Show the biggest changes first
+    rows.sort_by_key(|metric| {
+        std::cmp::Reverse(metric.value(current).abs_diff(metric.value(baseline)))
+    });
+    rows.insert(0, Metric::Total);
+    let labels = rows.iter().map(|row| row.to_string()).collect::<Vec<_>>();
+    let width = labels
+        .iter()
+        .map(|label| label.len())
+        .max()
+        .unwrap_or(0)
+        .max("metric".len());
+    let mut table = format!(
+        "{:<width$}  {:>12}  {:>12}  {:>9}\n",
+        "metric", "baseline", "current", "change"
+    );
+    for (metric, label) in rows.iter().zip(labels) {
+        let before = metric.value(baseline);
+        let after = metric.value(current);
+        let change = if before == 0 {
+            "new".to_string()
+        } else {
+            format!(
+                "{:+.1}%",
+                (after as f64 - before as f64) / before as f64 * 100.0
+            )
+        };
+        table.push_str(&format!(
+            "{label:<width$}  {:>12}  {:>12}  {change:>9}\n",
+            format_nanos(before),
+            format_nanos(after)
+        ));
+    }
+    table
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn summary(total: u128, commands: &[(&str, u128)]) -> RunSummary {
+        RunSummary {
+            total,
+            commands: commands
+                .iter()
+                .map(|(name, nanos)| (name.to_string(), *nanos))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn parses_conditions() {
+        let fail_if: FailIf = "total>+10% || cmd:cargo>+20% || cmd:rustc < -1.5s"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            fail_if.conditions,
+            vec![
+                Condition {
+                    metric: Metric::Total,
+                    greater: true,
+                    limit: Change::Relative(1000),
+                },
+                Condition {
+                    metric: Metric::Command("cargo".to_string()),
+                    greater: true,
+                    limit: Change::Relative(2000),
+                },
+                Condition {
+                    metric: Metric::Command("rustc".to_string()),
+                    greater: false,
+                    limit: Change::Absolute(-1_500_000_000),
+                },
+            ]
+        );
+        assert!("total".parse::<FailIf>().is_err());
+        assert!("wall>10%".parse::<FailIf>().is_err());
+        assert!("total>10 parsecs".parse::<FailIf>().is_err());
+    }
+
+    #[test]
+    fn detects_regressions() {
+        let baseline = summary(100, &[("cargo", 50)]);
+        let current = summary(105, &[("cargo", 70), ("rustc", 10)]);
+        let fail_if: FailIf = "total>+10% || cmd:cargo>+20%".parse().unwrap();
+        let failures = fail_if.failures(&baseline, &current);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].to_string(), "cmd:cargo>+20%");
+
+        let new_command: FailIf = "cmd:rustc>+50%".parse().unwrap();
+        assert_eq!(new_command.failures(&baseline, &current).len(), 1);
+        let absolute: FailIf = "total>+10ns".parse().unwrap();
+        assert!(absolute.failures(&baseline, &current).is_empty());
+    }
+}
@@ -0,0 +1,439 @@
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+#[cfg(all(target_os = "linux", feature = "record"))]
+use clap::{parser::ValueSource, ArgMatches};
+use serde::{Deserialize, Deserializer};
+
+#[cfg(all(target_os = "linux", feature = "record"))]
+use crate::cli::RecordArgs;
+use crate::{
+    cli::{parse_duration, Backend, Compression, EventKind, OutFormat, Preset},
+    models::SampleRatio,
+    writers::TraceFormat,
+};
+
+type Error = anyhow::Error;
+
+/// The name of the config file that's looked for in the current directory.
+pub const LOCAL_CONFIG_FILE: &str = "proctrace.toml";
+
+/// The contents of a config file.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Named bundles of `record` settings, from the `[profile.<name>]` tables.
+    #[serde(default)]
+    pub profile: BTreeMap<String, Profile>,
+}
+
+impl Config {
+    /// Parses a config file.
+    pub fn parse(contents: &str) -> Result<Self, Error> {
+        toml::from_str(contents).context("failed to parse config file")
+    }
+
+    /// Reads the config file at `path`.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        Self::parse(&contents).with_context(|| format!("invalid config file {}", path.display()))
+    }
+
+    /// Returns the path of the config file that's used when one isn't specified.
+    ///
+    /// This is `proctrace.toml` in the current directory if it exists, otherwise
+    /// `$XDG_CONFIG_HOME/proctrace/config.toml` (or `~/.config/proctrace/config.toml`)
+    /// if that exists.
+    pub fn default_path() -> Option<PathBuf> {
+        let local = PathBuf::from(LOCAL_CONFIG_FILE);
+        if local.is_file() {
+            return Some(local);
+        }
+        let config_home = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config_home.join("proctrace").join("config.toml")).filter(|path| path.is_file())
+    }
+
+    /// Looks up a profile by name.
+    pub fn profile(&self, name: &str) -> Result<&Profile, Error> {
+        self.profile.get(name).ok_or_else(|| {
+            let known = self.profile.keys().cloned().collect::<Vec<_>>();
+            if known.is_empty() {
+                anyhow!("no profile named '{name}', the config file has no profiles")
+            } else {
+                anyhow!(
+                    "no profile named '{name}', expected one of: {}",
+                    known.join(", ")
+                )
+            }
+        })
+    }
+}
+
+/// A named bundle of `record` settings.
+///
+/// Each field corresponds to the `record` flag of the same name.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Profile {
+    pub bpftrace_path: Option<PathBuf>,
+    pub backend: Option<Backend>,
+    pub poll_interval: Option<u64>,
+    #[serde(default)]
+    pub debug: bool,
+    #[serde(default)]
+    pub raw: bool,
+    pub out_format: Option<OutFormat>,
+    pub compress: Option<Compression>,
+    pub format: Option<TraceFormat>,
+    pub rotate_size: Option<u64>,
+    pub rotate_every: Option<u64>,
+    pub preset: Option<Preset>,
+    #[serde(default)]
+    pub adopt_exec: Vec<String>,
+    #[serde(default)]
+    pub rewrite_store_paths: bool,
+    #[serde(default)]
+    pub systemd_scope: bool,
+    #[serde(default)]
+    pub cgroup: Vec<PathBuf>,
+    pub container: Option<String>,
+    pub container_runtime: Option<PathBuf>,
+    pub sample_host: Option<u64>,
+    pub sample_rss: Option<u64>,
+    pub metrics_addr: Option<SocketAddr>,
+    #[serde(default)]
+    pub journal: bool,
+    pub storm_threshold: Option<usize>,
+    pub storm_window: Option<u64>,
+    #[serde(default)]
+    pub kill_on_storm: bool,
+    pub follow_daemons: Option<bool>,
+    #[serde(default)]
+    pub trace_mounts: bool,
+    pub sample_cpu: Option<u32>,
+    #[serde(default)]
+    pub events: Vec<EventKind>,
+    /// Either a duration like "5s" or a number of milliseconds.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub buffer_window: Option<u128>,
+    pub sample_trees: Option<SampleRatio>,
+}
+
+/// Deserializes a duration the way it's parsed from the command line, accepting plain
+/// numbers of milliseconds too since TOML has them.
+fn deserialize_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u128>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Duration {
+        Millis(u64),
+        Text(String),
+    }
+
+    match Duration::deserialize(deserializer)? {
+        Duration::Millis(millis) => Ok(Some(u128::from(millis) * 1_000_000)),
+        Duration::Text(text) => parse_duration(&text)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+    }
+}
+
+// Profiles are only applied when recording, but they can be parsed without it
+#[cfg(all(target_os = "linux", feature = "record"))]
+impl Profile {
+    /// Fills in the settings of `args` that weren't passed on the command line.
+    ///
+    /// `matches` are the matches for the `record` subcommand, which are used to tell
+    /// explicit flags apart from default values. Lists from the profile come before
+    /// the ones from the command line.
+    pub fn apply(&self, args: &mut RecordArgs, matches: &ArgMatches) {
+        let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        if let Some(ref path) = self.bpftrace_path {
+            if !from_cli("bpftrace_path") {
+                args.bpftrace_path = path.clone();
+            }
+        }
+        if let Some(backend) = self.backend {
+            if !from_cli("backend") {
+                args.backend = backend;
+            }
+        }
+        if let Some(interval) = self.poll_interval {
+            if !from_cli("poll_interval") {
+                args.poll_interval = interval;
+            }
+        }
+        if let Some(threshold) = self.storm_threshold {
+            if !from_cli("storm_threshold") {
+                args.storm_threshold = threshold;
+            }
+        }
+        if let Some(window) = self.storm_window {
+            if !from_cli("storm_window") {
+                args.storm_window = window;
+            }
+        }
+        if let Some(format) = self.format {
+            if !from_cli("format") {
+                args.format = format;
+            }
+        }
+        if let Some(ref runtime) = self.container_runtime {
+            if !from_cli("container_runtime") {
+                args.container_runtime = runtime.clone();
+            }
+        }
+        args.debug |= self.debug;
+        // `--raw` conflicts with `--raw-output` and `--out-format`, so don't turn one on
+        // alongside the others
+        let out_format_from_cli = from_cli("out_format") || args.raw_output.is_some();
+        args.raw |= self.raw && !out_format_from_cli;
+        if let Some(out_format) = self.out_format {
+            if !out_format_from_cli && !args.raw {
+                args.out_format = out_format;
+            }
+        }
+        args.rewrite_store_paths |= self.rewrite_store_paths;
+        args.systemd_scope |= self.systemd_scope;
+        if let Some(follow) = self.follow_daemons {
+            if !from_cli("follow_daemons") && !from_cli("no_follow_daemons") {
+                args.follow_daemons = follow;
+                args.no_follow_daemons = !follow;
+            }
+        }
+        args.journal |= self.journal;
+        args.kill_on_storm |= self.kill_on_storm;
+        args.trace_mounts |= self.trace_mounts;
+        args.compress = args.compress.or(self.compress);
+        // Rotation needs an output path, which a profile can't provide
+        if args.output_path.is_some() {
+            args.rotate_size = args.rotate_size.or(self.rotate_size);
+            args.rotate_every = args.rotate_every.or(self.rotate_every);
+        }
+        args.preset = args.preset.or(self.preset);
+        args.container = args.container.take().or_else(|| self.container.clone());
+        args.sample_host = args.sample_host.or(self.sample_host);
+        args.sample_rss = args.sample_rss.or(self.sample_rss);
+        args.sample_cpu = args.sample_cpu.or(self.sample_cpu);
+        // Every kind is recorded when none are given, so the kinds from the command line
+        // replace the ones from the profile instead of extending them
+        if args.events.is_empty() {
+            args.events = self.events.clone();
+        }
+        args.metrics_addr = args.metrics_addr.or(self.metrics_addr);
+        args.buffer_window = args.buffer_window.or(self.buffer_window);
+        args.sample_trees = args.sample_trees.or(self.sample_trees);
+        args.adopt_exec = self
+            .adopt_exec
+            .iter()
+            .cloned()
+            .chain(args.adopt_exec.drain(..))
+            .collect();
+        args.cgroup = self
+            .cgroup
+            .iter()
+            .cloned()
+            .chain(args.cgroup.drain(..))
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(all(target_os = "linux", feature = "record"))]
+    use clap::{CommandFactory, FromArgMatches};
+
+    #[cfg(all(target_os = "linux", feature = "record"))]
+    use crate::cli::{Cli, Command};
+
+    use super::*;
+
+    const CONFIG: &str = r#"
+        [profile.ci]
+        format = "cbor"
+        compress = "zstd"
+        preset = "nix-build"
+        adopt-exec = ["nix-daemon"]
+        bpftrace-path = "/run/current-system/sw/bin/bpftrace"
+
+        [profile.deep]
+        debug = true
+        sample-host = 250
+        events = ["fork", "exec", "exit"]
+    "#;
+
+    #[cfg(all(target_os = "linux", feature = "record"))]
+    fn apply(profile: &Profile, cli_args: &[&str]) -> RecordArgs {
+        let matches = Cli::command()
+            .try_get_matches_from(["proctrace", "record"].iter().chain(cli_args))
+            .unwrap();
+        let Command::Record(mut args) = Cli::from_arg_matches(&matches).unwrap().command else {
+            panic!("expected the record command");
+        };
+        let (_, record_matches) = matches.subcommand().unwrap();
+        profile.apply(&mut args, record_matches);
+        *args
+    }
+
+    #[test]
+    fn parses_profiles() {
+        let config = Config::parse(CONFIG).unwrap();
+        assert_eq!(config.profile.len(), 2);
+        let deep = config.profile("deep").unwrap();
+        assert!(deep.debug);
+        assert_eq!(deep.sample_host, Some(250));
+        assert_eq!(
+            deep.events,
+            vec![EventKind::Fork, EventKind::Exec, EventKind::Exit]
+        );
+        assert!(config.profile("fast").is_err());
+        assert!(Config::parse("[profile.ci]\nprobes = [\"sched\"]").is_err());
+    }
+
+    #[test]
+    fn parses_buffer_windows() {
+        let window = |value: &str| {
+            Config::parse(&format!("[profile.ci]\nbuffer-window = {value}"))
+                .map(|config| config.profile["ci"].buffer_window)
+        };
+        assert_eq!(window("\"5s\"").unwrap(), Some(5_000_000_000));
+        assert_eq!(window("250").unwrap(), Some(250_000_000));
+        assert!(window("\"5 parsecs\"").is_err());
+        assert!(Config::parse("[profile.ci]\nsample-trees = 2.0").is_err());
+    }
+
+    #[test]
+    #[cfg(all(target_os = "linux", feature = "record"))]
+    fn command_line_takes_precedence() {
+        let config = Config::parse(CONFIG).unwrap();
+        let ci = config.profile("ci").unwrap();
+
+        let args = apply(ci, &["--adopt-exec", "make", "--", "true"]);
+        assert_eq!(args.format, TraceFormat::Cbor);
+        assert_eq!(args.compress, Some(Compression::Zstd));
+        assert_eq!(args.preset, Some(Preset::NixBuild));
+        assert_eq!(args.adopt_exec, vec!["nix-daemon", "make"]);
+        assert_eq!(
+            args.bpftrace_path,
+            PathBuf::from("/run/current-system/sw/bin/bpftrace")
+        );
+
+        let args = apply(
+            ci,
+            &[
+                "--format",
+                "json",
+                "--compress",
+                "gzip",
+                "-b",
+                "bpftrace",
+                "--",
+                "true",
+            ],
+        );
+        assert_eq!(args.format, TraceFormat::Json);
+        assert_eq!(args.compress, Some(Compression::Gzip));
+        assert_eq!(args.bpftrace_path, PathBuf::from("bpftrace"));
+
+        let deep = config.profile("deep").unwrap();
+        let args = apply(deep, &["--events", "fork,exit", "--", "true"]);
+        assert_eq!(args.events, vec![EventKind::Fork, EventKind::Exit]);
+        let args = apply(deep, &["--", "true"]);
+        assert_eq!(args.events.len(), 3);
+
+        let sampled = Config::parse("[profile.tests]\nbuffer-window = \"2s\"\nsample-trees = 0.1")
+            .unwrap()
+            .profile["tests"]
+            .clone();
+        let args = apply(&sampled, &["--", "true"]);
+        assert_eq!(args.buffer_window, Some(2_000_000_000));
+        assert_eq!(args.sample_trees, Some("0.1".parse().unwrap()));
+        let args = apply(
+            &sampled,
+            &[
+                "--buffer-window",
+                "1s",
+                "--sample-trees",
+                "0.5",
+                "--",
+                "true",
+            ],
+        );
+        assert_eq!(args.buffer_window, Some(1_000_000_000));
+        assert_eq!(args.sample_trees, Some("0.5".parse().unwrap()));
+    }
+
+    #[test]
+    #[cfg(all(target_os = "linux", feature = "record"))]
+    fn resolves_out_format() {
+        let none = Profile::default();
+        let args = apply(
+            &none,
+            &["-o", "trace.json.zst", "--out-format", "both", "--", "true"],
+        );
+        assert_eq!(args.resolved_out_format().unwrap(), OutFormat::Both);
+        assert_eq!(
+            args.both_raw_output_path(),
+            Some(PathBuf::from("trace.json.raw.zst"))
+        );
+        let args = apply(&none, &["--raw", "--", "true"]);
+        assert_eq!(args.resolved_out_format().unwrap(), OutFormat::Raw);
+        let args = apply(&none, &["--raw-output", "raw.txt", "--", "true"]);
+        assert_eq!(args.resolved_out_format().unwrap(), OutFormat::Both);
+        assert_eq!(args.both_raw_output_path(), Some(PathBuf::from("raw.txt")));
+        let args = apply(
+            &none,
+            &[
+                "--out-format",
+                "raw",
+                "--raw-output",
+                "raw.txt",
+                "--",
+                "true",
+            ],
+        );
+        assert!(args.resolved_out_format().is_err());
+        let args = apply(
+            &none,
+            &["-o", "trace.json", "--rotate-every", "60", "--", "true"],
+        );
+        assert!(args.resolved_out_format().is_err());
+        let args = apply(
+            &none,
+            &[
+                "-o",
+                "trace.json",
+                "--rotate-every",
+                "60",
+                "--out-format",
+                "both",
+                "--",
+                "true",
+            ],
+        );
+        assert_eq!(args.resolved_out_format().unwrap(), OutFormat::Both);
+
+        // A profile that records raw events doesn't override the command line
+        let raw = Profile {
+            raw: true,
+            ..Default::default()
+        };
+        let args = apply(
+            &raw,
+            &["--out-format", "both", "-o", "t.json", "--", "true"],
+        );
+        assert_eq!(args.resolved_out_format().unwrap(), OutFormat::Both);
+        let args = apply(&raw, &["--", "true"]);
+        assert_eq!(args.resolved_out_format().unwrap(), OutFormat::Raw);
+    }
+}
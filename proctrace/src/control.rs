@@ -0,0 +1,213 @@
+//! A Unix socket for managing a recording while it's running, e.g. from a CI step or a
+//! test harness that wants to mark where each of its phases starts.
+//!
+//! Each line sent to the socket is a command, and each command is answered with a line
+//! that's either "ok" or "error: <reason>". The commands are:
+//! - `flush`: make everything written so far durable
+//! - `mark <label>`: add a custom event named "mark" to the root process
+//! - `rotate`: start a new segment of a segmented recording
+//! - `stop`: stop recording, like an interrupt does
+//!
+//! Commands are handled by the recorder between events, or after a short wait if the
+//! backend is quiet.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::mpsc::{channel, Receiver, Sender},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context};
+
+type Error = anyhow::Error;
+
+/// How long a client waits for the recorder to handle a command.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A command sent to the control socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlCommand {
+    Flush,
+    Mark(String),
+    Rotate,
+    Stop,
+}
+
+impl FromStr for ControlCommand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (command, argument) = s.split_once(' ').unwrap_or((s, ""));
+        let argument = argument.trim();
+        match (command, argument) {
+            ("flush", "") => Ok(Self::Flush),
+            ("mark", "") => Err(anyhow!("mark needs a label")),
+            ("mark", label) => Ok(Self::Mark(label.to_string())),
+            ("rotate", "") => Ok(Self::Rotate),
+            ("stop", "") => Ok(Self::Stop),
+            ("flush" | "rotate" | "stop", _) => Err(anyhow!("{command} doesn't take arguments")),
+            _ => Err(anyhow!("unknown command '{command}'")),
+        }
+    }
+}
+
+/// A command that's waiting to be handled by the recorder.
+#[derive(Debug)]
+pub struct ControlRequest {
+    pub command: ControlCommand,
+    reply: Sender<Result<(), String>>,
+}
+
+impl ControlRequest {
+    /// Tells the client whether the command worked.
+    pub fn reply(self, result: Result<(), Error>) {
+        // The client may have given up waiting
+        let _ = self.reply.send(result.map_err(|err| format!("{err:#}")));
+    }
+}
+
+/// A control socket that's being listened on from a background thread.
+///
+/// The socket file is removed when this is dropped.
+#[derive(Debug)]
+pub struct ControlSocket {
+    path: PathBuf,
+    requests: Receiver<ControlRequest>,
+}
+
+impl ControlSocket {
+    /// Listens for commands on a Unix socket at `path`.
+    ///
+    /// A socket that's left over from an earlier recording is replaced, anything else
+    /// at `path` is an error.
+    pub fn bind(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        if let Ok(existing) = std::fs::symlink_metadata(&path) {
+            if !existing.file_type().is_socket() {
+                return Err(anyhow!("{} exists and isn't a socket", path.display()));
+            }
+            std::fs::remove_file(&path).context("failed to remove old control socket")?;
+        }
+        let listener = UnixListener::bind(&path)
+            .with_context(|| format!("failed to listen on {}", path.display()))?;
+        let (sender, requests) = channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let sender = sender.clone();
+                std::thread::spawn(move || {
+                    // A misbehaving client shouldn't stop the recording
+                    let _ = handle_client(stream, sender);
+                });
+            }
+        });
+        Ok(Self { path, requests })
+    }
+
+    /// Returns the next command that's waiting to be handled, if there is one.
+    pub fn try_next(&self) -> Option<ControlRequest> {
+        self.requests.try_recv().ok()
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Reads commands from a client until it disconnects, answering each one once the
+/// recorder has handled it.
+fn handle_client(stream: UnixStream, requests: Sender<ControlRequest>) -> Result<(), Error> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = match line.parse::<ControlCommand>() {
+            Ok(command) => {
+                let (reply, replies) = channel();
+                requests
+                    .send(ControlRequest { command, reply })
+                    .map_err(|_| "the recording has finished".to_string())
+                    .and_then(|_| {
+                        replies
+                            .recv_timeout(REPLY_TIMEOUT)
+                            .map_err(|_| "the recorder didn't respond".to_string())?
+                    })
+            }
+            Err(err) => Err(err.to_string()),
+        };
+        match result {
+            Ok(()) => writeln!(writer, "ok")?,
+            Err(err) => writeln!(writer, "error: {err}")?,
+        }
+    }
+    Ok(())
+}
+
+/// Formats a mark as a line of raw output, so that it ends up in raw recordings too.
+pub fn mark_line(seq: u128, timestamp: u128, pid: i32, label: &str) -> String {
+    // Commas separate the fields of a raw line
+    let label = label.replace(',', ";");
+    format!("CUSTOM: seq={seq},ts={timestamp},pid={pid},name=mark,label={label}")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(
+            "mark tests started".parse::<ControlCommand>().unwrap(),
+            ControlCommand::Mark("tests started".to_string())
+        );
+        assert_eq!(
+            "stop\n".parse::<ControlCommand>().unwrap(),
+            ControlCommand::Stop
+        );
+        assert!("mark".parse::<ControlCommand>().is_err());
+        assert!("flush now".parse::<ControlCommand>().is_err());
+        assert!("pause".parse::<ControlCommand>().is_err());
+    }
+
+    #[test]
+    fn answers_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("control.sock");
+        let socket = ControlSocket::bind(&path).unwrap();
+        let handler = std::thread::spawn(move || loop {
+            if let Some(request) = socket.try_next() {
+                let result = match request.command {
+                    ControlCommand::Rotate => Err(anyhow!("the output isn't segmented")),
+                    _ => Ok(()),
+                };
+                request.reply(result);
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        });
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"pause\nrotate\n").unwrap();
+        let mut replies = BufReader::new(client).lines();
+        assert_eq!(
+            replies.next().unwrap().unwrap(),
+            "error: unknown command 'pause'"
+        );
+        assert_eq!(
+            replies.next().unwrap().unwrap(),
+            "error: the output isn't segmented"
+        );
+        handler.join().unwrap();
+        // The socket is removed once the recording is done with it
+        assert!(!path.exists());
+    }
+}
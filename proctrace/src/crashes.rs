@@ -0,0 +1,137 @@
+//! Lists the processes that crashed, i.e. were killed by a signal that dumps core, so
+//! that a flaky test that segfaults or aborts stands out from ones that failed cleanly.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    analyze::{command_line, format_nanos},
+    models::{crash_signal, dumped_core, signal_name, EventStore},
+};
+
+/// A process that crashed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Crash {
+    pub pid: i32,
+    /// The command line of the last thing the process exec'd, or "(fork of <ppid>)" if
+    /// it never exec'd anything.
+    pub command: String,
+    pub signal: i32,
+    /// Whether the kernel wrote a core dump, which it doesn't do with `ulimit -c 0`.
+    pub core_dumped: bool,
+    /// How long the process ran before it crashed, in nanoseconds.
+    pub runtime: u128,
+}
+
+/// The crashes in a recording, in the order they happened.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrashReport {
+    pub crashes: Vec<Crash>,
+}
+
+impl CrashReport {
+    /// Collects the processes in a recording that crashed.
+    pub fn from_store(store: &EventStore) -> Self {
+        let mut crashes = vec![];
+        for (pid, buffer) in store.iter() {
+            let (Some(first), Some(exit)) = (buffer.front(), buffer.back()) else {
+                continue;
+            };
+            let Some(code) = exit.exit_code() else {
+                continue;
+            };
+            let Some(signal) = crash_signal(code) else {
+                continue;
+            };
+            let crash = Crash {
+                pid,
+                command: command_line(buffer),
+                signal,
+                core_dumped: dumped_core(code),
+                runtime: exit.timestamp().saturating_sub(first.timestamp()),
+            };
+            crashes.push((exit.seq(), crash));
+        }
+        crashes.sort_by_key(|(seq, _)| *seq);
+        Self {
+            crashes: crashes.into_iter().map(|(_, crash)| crash).collect(),
+        }
+    }
+
+    /// Formats the report as a table of the crashes in the order they happened.
+    pub fn to_table(&self) -> String {
+        if self.crashes.is_empty() {
+            return "no crashes\n".to_string();
+        }
+        let mut table = format!(
+            "{:>8}  {:<20}  {:>12}  {}\n",
+            "pid", "signal", "ran for", "command"
+        );
+        for crash in self.crashes.iter() {
+            let mut signal = signal_name(crash.signal);
+            if crash.core_dumped {
+                signal.push_str(" (core)");
+            }
+            table.push_str(&format!(
+                "{:>8}  {:<20}  {:>12}  {}\n",
+                crash.pid,
+                signal,
+                format_nanos(crash.runtime),
+                crash.command
+            ));
+        }
+        let dumped = self
+            .crashes
+            .iter()
+            .filter(|crash| crash.core_dumped)
+            .count();
+        let plural = if self.crashes.len() == 1 { "" } else { "es" };
+        table.push_str(&format!(
+            "{} process{plural} crashed, {dumped} dumped core\n",
+            self.crashes.len()
+        ));
+        table
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{builder::TraceBuilder, render::read_events, writers::JsonWriter};
+
+    use super::*;
+
+    #[test]
+    fn lists_crashed_processes() {
+        let ms = 1_000_000;
+        let mut bytes = vec![];
+        TraceBuilder::new()
+            .fork(0, 1)
+            .exec(1, "cargo test")
+            .fork(1, 2)
+            .exec(2, "target/debug/deps/flaky-1234 --test-threads 4")
+            .fork(1, 3)
+            .advance(5 * ms)
+            // Killed on purpose, which isn't a crash
+            .killed(3, 9)
+            .advance(5 * ms)
+            .dumped_core(2, 11)
+            .exit(1, 101)
+            .write(JsonWriter::new(&mut bytes))
+            .unwrap();
+        let ingester = read_events(bytes.as_slice()).unwrap();
+        let report = CrashReport::from_store(ingester.tracked_events());
+        assert_eq!(
+            report.crashes,
+            vec![Crash {
+                pid: 2,
+                command: "target/debug/deps/flaky-1234 --test-threads 4".to_string(),
+                signal: 11,
+                core_dumped: true,
+                runtime: 10 * ms,
+            }]
+        );
+        let table = report.to_table();
+        assert!(table.contains("SIGSEGV (core)"), "{table}");
+        assert!(table.ends_with("1 process crashed, 1 dumped core\n"));
+        assert_eq!(CrashReport::default().to_table(), "no crashes\n");
+    }
+}
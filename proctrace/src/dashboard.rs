@@ -0,0 +1,237 @@
+//! A live view of a recording in progress, redrawn in place on stderr, see
+//! `record --tui`.
+
+use std::{
+    io::Write,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    analyze::format_nanos,
+    ingest::{exec_description, EventIngester},
+    models::EventStore,
+};
+
+type Error = anyhow::Error;
+
+/// How often the dashboard is redrawn.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+/// The number of long-running processes that are shown.
+const LONGEST_RUNNING: usize = 5;
+
+/// A process that hasn't exited yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunningProcess {
+    pub pid: i32,
+    /// What it's running, or what it was forked from if it hasn't exec'd.
+    pub command: String,
+    /// How long it has been running, in nanoseconds.
+    pub running_for: u128,
+}
+
+/// What the dashboard shows at one point in the recording.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub elapsed: Duration,
+    pub tracked_pids: usize,
+    pub unfinished_pids: usize,
+    pub events_per_sec: f64,
+    /// Lines that couldn't be parsed into events.
+    pub dropped: u64,
+    /// The processes that have been running the longest, longest first.
+    pub longest_running: Vec<RunningProcess>,
+}
+
+impl Snapshot {
+    /// Formats the snapshot as the lines of the dashboard.
+    pub fn to_lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!(
+                "elapsed {}  tracked {}  running {}  events/s {:.0}  dropped {}",
+                format_nanos(self.elapsed.as_nanos()),
+                self.tracked_pids,
+                self.unfinished_pids,
+                self.events_per_sec,
+                self.dropped
+            ),
+            "longest running:".to_string(),
+        ];
+        for process in self.longest_running.iter() {
+            lines.push(format!(
+                "  {:>10}  {:>8}  {}",
+                format_nanos(process.running_for),
+                process.pid,
+                process.command
+            ));
+        }
+        lines
+    }
+}
+
+/// Returns the unfinished processes in `store` that have been running the longest as of
+/// `now`, a timestamp from the recording, longest first.
+pub fn longest_running(
+    store: &EventStore,
+    unfinished: &[i32],
+    now: u128,
+    limit: usize,
+) -> Vec<RunningProcess> {
+    let mut running = unfinished
+        .iter()
+        .filter_map(|pid| {
+            let events = store.events_of(*pid)?;
+            let start = events.front()?.timestamp();
+            let command = events
+                .iter()
+                .rev()
+                .find_map(exec_description)
+                .or_else(|| {
+                    let parent_pid = events.front()?.fork_parent()?;
+                    Some(format!("(fork of {parent_pid})"))
+                })
+                .unwrap_or_else(|| "(unknown)".to_string());
+            Some(RunningProcess {
+                pid: *pid,
+                command,
+                running_for: now.saturating_sub(start),
+            })
+        })
+        .collect::<Vec<_>>();
+    running.sort_by_key(|process| (std::cmp::Reverse(process.running_for), process.pid));
+    running.truncate(limit);
+    running
+}
+
+/// Redraws a [`Snapshot`] of the recording in place every [`REFRESH_INTERVAL`].
+#[derive(Debug)]
+pub struct Dashboard {
+    started: Instant,
+    last_refresh: Instant,
+    /// The number of events when the dashboard was last redrawn.
+    last_events: u64,
+    events: u64,
+    dropped: u64,
+    /// The number of lines drawn last time, which are cleared before redrawing.
+    lines_drawn: usize,
+}
+
+impl Default for Dashboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Dashboard {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            started: now,
+            last_refresh: now,
+            last_events: 0,
+            events: 0,
+            dropped: 0,
+            lines_drawn: 0,
+        }
+    }
+
+    /// Counts an event that was read.
+    pub fn count_event(&mut self) {
+        self.events += 1;
+    }
+
+    /// Counts a line that couldn't be parsed into an event.
+    pub fn count_dropped(&mut self) {
+        self.dropped += 1;
+    }
+
+    /// Redraws the dashboard if it's due, with the processes tracked by `ingester` as
+    /// of `now`, a timestamp from the recording.
+    pub fn refresh<W>(
+        &mut self,
+        ingester: &EventIngester<W>,
+        now: u128,
+        out: &mut impl Write,
+    ) -> Result<(), Error> {
+        let since_refresh = self.last_refresh.elapsed();
+        if self.lines_drawn > 0 && since_refresh < REFRESH_INTERVAL {
+            return Ok(());
+        }
+        let unfinished = ingester.unfinished_pids();
+        let snapshot = Snapshot {
+            elapsed: self.started.elapsed(),
+            tracked_pids: ingester.tracked_pid_count(),
+            unfinished_pids: unfinished.len(),
+            events_per_sec: (self.events - self.last_events) as f64
+                / since_refresh.as_secs_f64().max(f64::EPSILON),
+            dropped: self.dropped,
+            longest_running: longest_running(
+                ingester.tracked_events(),
+                &unfinished,
+                now,
+                LONGEST_RUNNING,
+            ),
+        };
+        self.last_refresh = Instant::now();
+        self.last_events = self.events;
+        self.draw(&snapshot.to_lines(), out)
+    }
+
+    /// Replaces the lines drawn last time with `lines`.
+    fn draw(&mut self, lines: &[String], out: &mut impl Write) -> Result<(), Error> {
+        if self.lines_drawn > 0 {
+            // Move back up to the first line that was drawn
+            write!(out, "\x1b[{}A", self.lines_drawn)?;
+        }
+        for line in lines {
+            writeln!(out, "\x1b[2K{line}")?;
+        }
+        // Clear what's left over from a longer frame
+        for _ in lines.len()..self.lines_drawn {
+            writeln!(out, "\x1b[2K")?;
+        }
+        self.lines_drawn = lines.len().max(self.lines_drawn);
+        out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{builder::TraceBuilder, writers::NoOpWriter};
+
+    use super::*;
+
+    #[test]
+    fn shows_the_longest_running_processes_first() {
+        let events = TraceBuilder::new()
+            .fork(0, 1)
+            .exec(1, "make")
+            .advance(1_000)
+            .fork(1, 2)
+            .exec(2, "cc main.c")
+            .fork(1, 3)
+            .exit(3, 0)
+            .fork(1, 4)
+            .build();
+        let mut ingester = EventIngester::new(Some(1), Some(NoOpWriter));
+        for event in events {
+            ingester.observe_event(event).unwrap();
+        }
+        let unfinished = ingester.unfinished_pids();
+        let now = 1_000_000_000;
+        let running = longest_running(ingester.tracked_events(), &unfinished, now, 2);
+        let summary = running
+            .iter()
+            .map(|process| (process.pid, process.command.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(summary, vec![(1, "make"), (2, "cc main.c")]);
+        assert!(running[0].running_for > running[1].running_for);
+
+        let mut dashboard = Dashboard::new();
+        let mut out = vec![];
+        dashboard.refresh(&ingester, now, &mut out).unwrap();
+        let drawn = String::from_utf8(out).unwrap();
+        assert!(drawn.contains("tracked 4  running 3"), "{drawn}");
+        assert!(drawn.contains("(fork of 1)"), "{drawn}");
+    }
+}
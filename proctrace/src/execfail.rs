@@ -0,0 +1,217 @@
+//! Counts the execs that failed in each process. A program that's looked up by trying
+//! each directory of `PATH` (or each candidate of a wrapper script) in turn fails to
+//! exec several times before it runs, and those attempts show up as startup time.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    analyze::{command_name, format_nanos},
+    models::{errno_name, Event, EventStore},
+};
+
+/// The failed execs of a single process.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessFailedExecs {
+    /// The file name of the last thing the process exec'd, or "(fork)" if it never
+    /// exec'd anything.
+    pub command: String,
+    /// The files that failed to exec in the order they were tried.
+    pub attempts: Vec<FailedExec>,
+    /// The time from each run of failed execs to the exec that finally succeeded, in
+    /// nanoseconds.
+    ///
+    /// Failures that weren't followed by a successful exec don't count.
+    pub delay: u128,
+}
+
+/// A file that a process failed to exec.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FailedExec {
+    pub filename: String,
+    /// The name of the error, e.g. "ENOENT", if it's known.
+    pub error: Option<String>,
+}
+
+/// The failed execs in a recording, by process.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FailedExecReport {
+    /// Only processes with at least one failed exec.
+    pub processes: BTreeMap<i32, ProcessFailedExecs>,
+}
+
+impl FailedExecReport {
+    /// Collects the failed execs of every process in a recording.
+    pub fn from_store(store: &EventStore) -> Self {
+        let mut report = Self::default();
+        for (pid, buffer) in store.iter() {
+            let mut process = ProcessFailedExecs::default();
+            // When the current run of failures started
+            let mut failing_since = None;
+            for event in buffer.iter() {
+                match event {
+                    Event::BadExec {
+                        timestamp,
+                        filename,
+                        errno,
+                        ..
+                    } => {
+                        process.attempts.push(FailedExec {
+                            filename: filename.clone().unwrap_or_else(|| "<unknown>".to_string()),
+                            error: errno.map(errno_name),
+                        });
+                        failing_since.get_or_insert(*timestamp);
+                    }
+                    Event::ExecFull { timestamp, .. } => {
+                        if let Some(since) = failing_since.take() {
+                            process.delay += timestamp.saturating_sub(since);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            if process.attempts.is_empty() {
+                continue;
+            }
+            process.command = buffer
+                .iter()
+                .rev()
+                .find_map(|event| match event {
+                    Event::ExecFull { filename, .. } => Some(command_name(filename)),
+                    _ => None,
+                })
+                .unwrap_or_else(|| "(fork)".to_string());
+            report.processes.insert(pid, process);
+        }
+        report
+    }
+
+    /// The total number of failed execs.
+    pub fn failures(&self) -> usize {
+        self.processes
+            .values()
+            .map(|process| process.attempts.len())
+            .sum()
+    }
+
+    /// Formats the report as a table of the processes with the most failed execs,
+    /// followed by the files they tried.
+    pub fn to_table(&self, limit: usize) -> String {
+        if self.processes.is_empty() {
+            return "no failed execs\n".to_string();
+        }
+        let mut processes = self.processes.iter().collect::<Vec<_>>();
+        processes.sort_by_key(|(pid, process)| (std::cmp::Reverse(process.attempts.len()), **pid));
+        let mut table = format!(
+            "{:>8}  {:>6}  {:>12}  {}\n",
+            "pid", "failed", "delay", "command"
+        );
+        for (pid, process) in processes.iter().take(limit) {
+            table.push_str(&format!(
+                "{:>8}  {:>6}  {:>12}  {}\n",
+                pid,
+                process.attempts.len(),
+                format_nanos(process.delay),
+                process.command
+            ));
+            // Indented to line up with the command
+            for attempt in process.attempts.iter() {
+                let filename = &attempt.filename;
+                match attempt.error {
+                    Some(ref error) => table.push_str(&format!("{:>32}{filename} ({error})\n", "")),
+                    None => table.push_str(&format!("{:>32}{filename}\n", "")),
+                }
+            }
+        }
+        if processes.len() > limit {
+            table.push_str(&format!("... and {} more\n", processes.len() - limit));
+        }
+        let delay = self
+            .processes
+            .values()
+            .map(|process| process.delay)
+            .sum::<u128>();
+        table.push_str(&format!(
+            "{} failed execs in {} processes, {} before falling back to an exec that worked\n",
+            self.failures(),
+            self.processes.len(),
+            format_nanos(delay)
+        ));
+        table
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::models::ExecArgsKind;
+
+    use super::*;
+
+    #[test]
+    fn counts_failed_execs_per_process() {
+        let bad_exec = |timestamp: u128, filename: &str| Event::BadExec {
+            seq: timestamp,
+            timestamp,
+            pid: 2,
+            filename: Some(filename.to_string()),
+            errno: Some(2),
+        };
+        let events = [
+            Event::Fork {
+                seq: 0,
+                timestamp: 0,
+                parent_pid: 1,
+                child_pid: 2,
+                parent_pgid: 1,
+                credentials: None,
+                comm: None,
+            },
+            Event::Fork {
+                seq: 1,
+                timestamp: 1,
+                parent_pid: 1,
+                child_pid: 3,
+                parent_pgid: 1,
+                credentials: None,
+                comm: None,
+            },
+            bad_exec(10, "/usr/local/bin/git"),
+            bad_exec(20, "/usr/bin/git"),
+            Event::ExecFull {
+                seq: 50,
+                timestamp: 50,
+                pid: 2,
+                ppid: 1,
+                pgid: 1,
+                filename: "/bin/git".to_string(),
+                args: ExecArgsKind::Joined("git status".to_string()),
+                credentials: None,
+            },
+            Event::Exit {
+                seq: 100,
+                timestamp: 100,
+                pid: 2,
+                ppid: 1,
+                pgid: 1,
+                exit_code: Some(0),
+            },
+        ];
+        let store = EventStore::from_events(events);
+        let report = FailedExecReport::from_store(&store);
+        assert_eq!(report.processes.len(), 1);
+        let process = &report.processes[&2];
+        assert_eq!(process.command, "git");
+        assert_eq!(process.delay, 40);
+        assert_eq!(
+            process.attempts[0],
+            FailedExec {
+                filename: "/usr/local/bin/git".to_string(),
+                error: Some("ENOENT".to_string())
+            }
+        );
+        assert!(report.to_table(10).ends_with(
+            "2 failed execs in 1 processes, 0.040us before falling back to an exec that worked\n"
+        ));
+    }
+}
@@ -0,0 +1,242 @@
+//! Exports the process time of a recording as folded stacks, the input format of
+//! `flamegraph.pl` and `inferno`, so that the process tree can be viewed as a
+//! flamegraph and two runs can be compared with a differential flamegraph
+//! (`inferno-diff-folded before.folded after.folded | inferno-flamegraph`).
+//!
+//! Each stack starts with the process group that a process was in, followed by the
+//! commands of its ancestors in that group down to the process itself, and is weighted
+//! by how long the process was alive. Frames are named after commands rather than
+//! PIDs so that the stacks of different runs line up.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    analyze::command_name,
+    models::{Event, EventStore},
+};
+
+/// The combined lifetime of the processes with each stack.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FoldedStacks {
+    /// Frames separated by ';', mapped to nanoseconds.
+    pub stacks: BTreeMap<String, u128>,
+}
+
+impl FoldedStacks {
+    /// Folds every process in a recording into a stack rooted at its process group.
+    ///
+    /// A process is in the group it last joined with `setpgid` or `setsid`, or else
+    /// the group of its parent, since not every backend reports the group on other
+    /// events. CPU time isn't recorded, so processes are weighted by how long they
+    /// were alive, which counts processes that ran in parallel separately.
+    pub fn from_store(store: &EventStore) -> Self {
+        let mut groups = HashMap::new();
+        let mut stacks = Self::default();
+        for (pid, buffer) in store.iter() {
+            let start = buffer.front().map(|event| event.timestamp()).unwrap_or(0);
+            let stop = buffer
+                .back()
+                .map(|event| event.timestamp())
+                .unwrap_or(start);
+            let group = group_of(store, pid, &mut groups);
+            let mut frames = vec![frame_name(buffer)];
+            for parent in stored_ancestors(store, pid) {
+                if group_of(store, parent, &mut groups) != group {
+                    break;
+                }
+                frames.push(store_frame_name(store, parent));
+            }
+            // The group is named after its leader, or the outermost process in it
+            // if the leader wasn't recorded
+            let leader = if store.pid_is_tracked(group) {
+                store_frame_name(store, group)
+            } else {
+                frames.last().cloned().unwrap_or_default()
+            };
+            frames.push(format!("pgrp {leader}"));
+            frames.reverse();
+            *stacks.stacks.entry(frames.join(";")).or_default() += stop - start;
+        }
+        stacks
+    }
+
+    /// Formats the stacks in the folded format, one stack per line followed by the
+    /// time in microseconds.
+    pub fn to_folded(&self) -> String {
+        self.stacks
+            .iter()
+            .map(|(stack, nanos)| (stack, nanos / 1_000))
+            .filter(|(_, micros)| *micros > 0)
+            .map(|(stack, micros)| format!("{stack} {micros}\n"))
+            .collect()
+    }
+
+    /// Formats the stacks of two recordings in the format that `inferno-diff-folded`
+    /// produces, one stack per line followed by the time in microseconds in `before`
+    /// and then in `self`, which `inferno-flamegraph` renders as a differential
+    /// flamegraph.
+    pub fn to_diff_folded(&self, before: &Self) -> String {
+        let mut stacks: BTreeMap<&str, (u128, u128)> = BTreeMap::new();
+        for (stack, nanos) in before.stacks.iter() {
+            stacks.entry(stack).or_default().0 = nanos / 1_000;
+        }
+        for (stack, nanos) in self.stacks.iter() {
+            stacks.entry(stack).or_default().1 = nanos / 1_000;
+        }
+        stacks
+            .into_iter()
+            .filter(|(_, (before, after))| *before > 0 || *after > 0)
+            .map(|(stack, (before, after))| format!("{stack} {before} {after}\n"))
+            .collect()
+    }
+}
+
+/// Returns the process group of `pid`, remembering it in `groups`.
+fn group_of(store: &EventStore, pid: i32, groups: &mut HashMap<i32, i32>) -> i32 {
+    if let Some(group) = groups.get(&pid) {
+        return *group;
+    }
+    // Walk up to the nearest process whose group is known, then fill in its
+    // descendants on the way back down
+    let mut chain = vec![];
+    let mut group = None;
+    for current in std::iter::once(pid).chain(stored_ancestors(store, pid)) {
+        if let Some(known) = groups.get(&current) {
+            group = Some(*known);
+            break;
+        }
+        chain.push(current);
+        if let Some(joined) = joined_group(store, current) {
+            group = Some(joined);
+            break;
+        }
+    }
+    // The outermost process leads its own group as far as the recording knows
+    let group = group.or(chain.last().copied()).unwrap_or(pid);
+    for pid in chain {
+        groups.insert(pid, group);
+    }
+    group
+}
+
+/// Returns the ancestors of `pid` that are part of the recording too.
+fn stored_ancestors(store: &EventStore, pid: i32) -> impl Iterator<Item = i32> + '_ {
+    store
+        .ancestors(pid)
+        .take_while(|ancestor| store.pid_is_tracked(*ancestor))
+}
+
+/// Returns the last process group that `pid` joined itself, if any.
+fn joined_group(store: &EventStore, pid: i32) -> Option<i32> {
+    store
+        .events_of(pid)?
+        .iter()
+        .rev()
+        .find_map(|event| match event {
+            Event::SetPGID { pgid, .. } | Event::SetSID { pgid, .. } => Some(*pgid),
+            _ => None,
+        })
+}
+
+fn store_frame_name(store: &EventStore, pid: i32) -> String {
+    store
+        .events_of(pid)
+        .map(frame_name)
+        .unwrap_or_else(|| "(fork)".to_string())
+}
+
+/// The command that a process exec'd last, without anything that would break the
+/// folded format.
+fn frame_name(buffer: &VecDeque<Event>) -> String {
+    buffer
+        .iter()
+        .rev()
+        .find_map(|event| match event {
+            Event::ExecFull { filename, .. } => Some(command_name(filename)),
+            _ => None,
+        })
+        .unwrap_or_else(|| "(fork)".to_string())
+        .replace([';', ' ', '\n'], "_")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::models::ExecArgsKind;
+
+    use super::*;
+
+    fn process(store: &mut EventStore, pid: i32, ppid: i32, command: &str, stop: u128) {
+        let events = [
+            Event::Fork {
+                seq: 0,
+                timestamp: 0,
+                parent_pid: ppid,
+                child_pid: pid,
+                parent_pgid: ppid,
+                credentials: None,
+                comm: None,
+            },
+            Event::ExecFull {
+                seq: 1,
+                timestamp: 0,
+                pid,
+                ppid,
+                pgid: pid,
+                filename: format!("/usr/bin/{command}"),
+                args: ExecArgsKind::Joined(command.to_string()),
+                credentials: None,
+            },
+            Event::Exit {
+                seq: 2,
+                timestamp: stop,
+                pid,
+                ppid,
+                pgid: pid,
+                exit_code: Some(0),
+            },
+        ];
+        for event in events {
+            store.add(pid, event);
+        }
+    }
+
+    #[test]
+    fn folds_processes_by_group() {
+        let mut store = EventStore::new();
+        process(&mut store, 1, 0, "bash", 10_000_000);
+        process(&mut store, 2, 1, "make", 8_000_000);
+        process(&mut store, 3, 2, "cc", 3_000_000);
+        process(&mut store, 4, 2, "cc", 2_000_000);
+        // A pipeline that the shell put in its own group
+        process(&mut store, 5, 1, "grep", 1_000_000);
+        store.add(
+            5,
+            Event::SetPGID {
+                seq: 1,
+                timestamp: 0,
+                pid: 5,
+                ppid: 1,
+                pgid: 5,
+                caller_pid: Some(1),
+            },
+        );
+        let stacks = FoldedStacks::from_store(&store);
+        assert_eq!(
+            stacks.to_folded(),
+            "pgrp bash;bash 10000\n\
+             pgrp bash;bash;make 8000\n\
+             pgrp bash;bash;make;cc 5000\n\
+             pgrp grep;grep 1000\n"
+        );
+
+        let mut before = EventStore::new();
+        process(&mut before, 10, 0, "bash", 12_000_000);
+        process(&mut before, 11, 10, "make", 11_000_000);
+        let before = FoldedStacks::from_store(&before);
+        let diff = stacks.to_diff_folded(&before);
+        assert!(diff.starts_with("pgrp bash;bash 12000 10000\n"));
+        assert!(diff.contains("pgrp bash;bash;make;cc 0 5000\n"));
+    }
+}
@@ -0,0 +1,776 @@
+//! An indexed container for processed recordings.
+//!
+//! Recordings in the other formats have to be read from start to end, so looking at a
+//! single process tree or a slice of time means reading all of it. An indexed recording
+//! stores its events in chunks followed by an index of which chunks hold the events of
+//! each PID, so that only those chunks need to be read. It can still be read from start
+//! to end like the other formats, e.g. when it's piped or compressed.
+//!
+//! The layout is:
+//! - the magic bytes [MAGIC]
+//! - optionally, the metadata as a CBOR `{"Metadata": ...}` item
+//! - chunks, each a `C` byte, a little-endian `u32` length, and that many bytes of CBOR
+//!   encoded events, or a `Z` byte, a length, and the CBOR encoded events compressed as
+//!   a single zstd frame
+//! - an `I` byte, a little-endian `u32` length, and the CBOR encoded [Index]
+//! - a footer of the little-endian `u64` offset of the index and the magic bytes again
+//!
+//! A recording that was cut short has no index, but its complete chunks can still be
+//! read from start to end. Since every compressed chunk is a separate zstd frame, only
+//! the chunks that are read have to be decompressed. Compressed chunks can only be
+//! written and read with the `fs` feature.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    io::{BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom, Write},
+};
+
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    models::{Event, Metadata, MetadataItem},
+    reader::CBOR_METADATA_PREFIX,
+    writers::EventWrite,
+};
+
+type Error = anyhow::Error;
+
+/// The bytes at the start and end of an indexed recording.
+pub const MAGIC: &[u8; 8] = b"PTRCIDX1";
+/// The default number of events in each chunk.
+pub const DEFAULT_CHUNK_EVENTS: usize = 4096;
+
+const CHUNK_TAG: u8 = b'C';
+const ZSTD_CHUNK_TAG: u8 = b'Z';
+const INDEX_TAG: u8 = b'I';
+/// The length of a tag and the length that follows it.
+const TAG_LEN: usize = 5;
+/// The length of the footer, which is the offset of the index and the magic bytes.
+const FOOTER_LEN: i64 = 16;
+
+/// Where a chunk of events is in the recording.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkInfo {
+    /// The offset of the chunk's tag from the start of the recording.
+    pub offset: u64,
+    /// The length of the encoded events.
+    pub len: u32,
+    pub events: u32,
+    /// Whether the events are compressed with zstd.
+    #[serde(default)]
+    pub compressed: bool,
+}
+
+/// What's known about the events of a single process.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PidEntry {
+    /// The PID that forked it, if its fork was recorded.
+    pub parent: Option<i32>,
+    /// The timestamp of its first event.
+    pub start: u128,
+    /// The timestamp of its last event.
+    pub stop: u128,
+    /// The chunks that contain its events, in order.
+    pub chunks: Vec<u32>,
+}
+
+/// The index at the end of an indexed recording.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Index {
+    pub chunks: Vec<ChunkInfo>,
+    /// The processes that had each PID, in the order they started. There's more than
+    /// one when a PID is reused.
+    ///
+    /// Events that don't belong to a process (e.g. host samples) are under PID 0.
+    pub pids: BTreeMap<i32, Vec<PidEntry>>,
+}
+
+/// A process in an [`Index`], as its PID and its position among the processes that
+/// had that PID.
+pub type ProcessKey = (i32, usize);
+
+/// The part of a recording to read.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Selection {
+    /// Only the process tree rooted at this PID.
+    pub root_pid: Option<i32>,
+    /// Only the processes that were still running this many nanoseconds after the
+    /// recording started, along with their ancestors so that the tree stays connected.
+    pub since: Option<u128>,
+}
+
+impl Selection {
+    /// Returns `true` if the whole recording is selected.
+    pub fn is_everything(&self) -> bool {
+        self.root_pid.is_none() && self.since.is_none()
+    }
+}
+
+impl Index {
+    /// Records that an event was written to the chunk at index `chunk`.
+    pub fn observe(&mut self, event: &Event, chunk: u32) {
+        let ts = event.timestamp();
+        let entries = self.pids.entry(event.pid()).or_default();
+        // A fork is the first event of a process, so a fork of a PID that's already
+        // in the index is a new process that reused it
+        if entries.is_empty() || event.fork_parent().is_some() {
+            entries.push(PidEntry {
+                parent: event.fork_parent(),
+                start: ts,
+                stop: ts,
+                chunks: vec![],
+            });
+        }
+        let entry = entries.last_mut().expect("just made sure there's an entry");
+        entry.start = entry.start.min(ts);
+        entry.stop = entry.stop.max(ts);
+        if entry.chunks.last() != Some(&chunk) {
+            entry.chunks.push(chunk);
+        }
+    }
+
+    /// Returns the process that had `pid` at `ts`, which is the last one to start
+    /// with that PID by then.
+    pub fn process_at(&self, pid: i32, ts: u128) -> Option<ProcessKey> {
+        let entries = self.pids.get(&pid)?;
+        let started = entries.partition_point(|entry| entry.start <= ts);
+        Some((pid, started.saturating_sub(1)))
+    }
+
+    /// Returns the process that `event` belongs to.
+    pub fn process_of(&self, event: &Event) -> Option<ProcessKey> {
+        self.process_at(event.pid(), event.timestamp())
+    }
+
+    fn entry(&self, (pid, n): ProcessKey) -> Option<&PidEntry> {
+        self.pids.get(&pid).and_then(|entries| entries.get(n))
+    }
+
+    /// Returns the process that forked the process `key`, if its fork was recorded.
+    fn parent(&self, key: ProcessKey) -> Option<ProcessKey> {
+        let entry = self.entry(key)?;
+        self.process_at(entry.parent?, entry.start)
+    }
+
+    /// Returns every process in the index.
+    fn processes(&self) -> impl Iterator<Item = (ProcessKey, &PidEntry)> {
+        self.pids.iter().flat_map(|(pid, entries)| {
+            entries
+                .iter()
+                .enumerate()
+                .map(move |(n, entry)| ((*pid, n), entry))
+        })
+    }
+
+    /// The timestamp of the first event of a process.
+    pub fn start(&self) -> Option<u128> {
+        self.processes()
+            .filter(|((pid, _), _)| *pid != 0)
+            .map(|(_, entry)| entry.start)
+            .min()
+    }
+
+    /// Returns the processes whose events are part of `selection`, which always
+    /// includes the events that don't belong to a process.
+    ///
+    /// Fails if the selection is rooted at a PID that isn't in the recording.
+    pub fn select(&self, selection: &Selection) -> Result<HashSet<ProcessKey>, Error> {
+        let mut selected = match selection.root_pid {
+            Some(root_pid) => {
+                let roots = self
+                    .pids
+                    .get(&root_pid)
+                    .ok_or_else(|| anyhow!("PID {root_pid} isn't in the recording"))?;
+                self.descendants((0..roots.len()).map(|n| (root_pid, n)))
+            }
+            None => self.processes().map(|(key, _)| key).collect(),
+        };
+        if let Some(since) = selection.since {
+            let threshold = self.start().unwrap_or(0) + since;
+            let mut kept = HashSet::new();
+            for key in selected.iter() {
+                if self.entry(*key).is_none_or(|entry| entry.stop < threshold) {
+                    continue;
+                }
+                // Stops at anything that's already kept, which also stops the ancestry
+                // from looping if the recording is missing a PID's exit
+                let mut current = Some(*key);
+                while let Some(key) =
+                    current.filter(|key| selected.contains(key) && kept.insert(*key))
+                {
+                    current = self.parent(key);
+                }
+            }
+            selected = kept;
+        }
+        selected.insert((0, 0));
+        Ok(selected)
+    }
+
+    /// Returns `roots` and every process that descends from them.
+    fn descendants(&self, roots: impl IntoIterator<Item = ProcessKey>) -> HashSet<ProcessKey> {
+        let mut children: HashMap<ProcessKey, Vec<ProcessKey>> = HashMap::new();
+        for (child, _) in self.processes() {
+            if let Some(parent) = self.parent(child) {
+                children.entry(parent).or_default().push(child);
+            }
+        }
+        let mut found = HashSet::new();
+        let mut worklist = vec![];
+        for root in roots {
+            if found.insert(root) {
+                worklist.push(root);
+            }
+        }
+        while let Some(key) = worklist.pop() {
+            for child in children.get(&key).into_iter().flatten() {
+                if found.insert(*child) {
+                    worklist.push(*child);
+                }
+            }
+        }
+        found
+    }
+}
+
+/// Returns the selected events of a recording without an index, in order.
+pub fn select_events(
+    events: impl IntoIterator<Item = Result<Event, Error>>,
+    selection: &Selection,
+) -> Result<Vec<Event>, Error> {
+    let events = events.into_iter().collect::<Result<Vec<_>, _>>()?;
+    let mut index = Index::default();
+    for event in events.iter() {
+        index.observe(event, 0);
+    }
+    let processes = index.select(selection)?;
+    let mut selected = events
+        .into_iter()
+        .filter(|event| {
+            index
+                .process_of(event)
+                .is_some_and(|key| processes.contains(&key))
+        })
+        .collect::<Vec<_>>();
+    selected.sort();
+    Ok(selected)
+}
+
+/// Writes an indexed recording.
+///
+/// The index is only written by [`EventWrite::finalize`].
+#[derive(Debug)]
+pub struct IndexedWriter<W> {
+    inner: W,
+    /// The number of bytes written so far.
+    offset: u64,
+    started: bool,
+    finished: bool,
+    chunk: Vec<u8>,
+    chunk_events: u32,
+    max_chunk_events: usize,
+    /// The zstd compression level of chunks, if they're compressed.
+    zstd_level: Option<i32>,
+    index: Index,
+}
+
+impl<W: Write> IndexedWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self::with_chunk_events(inner, DEFAULT_CHUNK_EVENTS)
+    }
+
+    /// Creates a writer that starts a new chunk every `max_chunk_events` events.
+    pub fn with_chunk_events(inner: W, max_chunk_events: usize) -> Self {
+        Self {
+            inner,
+            offset: 0,
+            started: false,
+            finished: false,
+            chunk: vec![],
+            chunk_events: 0,
+            max_chunk_events: max_chunk_events.max(1),
+            zstd_level: None,
+            index: Index::default(),
+        }
+    }
+
+    /// Compresses each chunk with zstd at `level` (0 is zstd's default level).
+    pub fn with_zstd_chunks(mut self, level: i32) -> Self {
+        self.zstd_level = Some(level);
+        self
+    }
+
+    /// Returns the stream that the recording is written to.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.inner.write_all(bytes).context("write failed")?;
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> Result<(), Error> {
+        if !self.started {
+            self.started = true;
+            self.write_bytes(MAGIC)?;
+        }
+        Ok(())
+    }
+
+    fn write_tagged(&mut self, tag: u8, bytes: &[u8]) -> Result<(), Error> {
+        let len = u32::try_from(bytes.len()).context("chunk was too large")?;
+        self.write_bytes(&[tag])?;
+        self.write_bytes(&len.to_le_bytes())?;
+        self.write_bytes(bytes)
+    }
+
+    fn write_chunk(&mut self) -> Result<(), Error> {
+        if self.chunk_events == 0 {
+            return Ok(());
+        }
+        let mut chunk = std::mem::take(&mut self.chunk);
+        let tag = match self.zstd_level {
+            Some(level) => {
+                chunk = compress_chunk(&chunk, level)?;
+                ZSTD_CHUNK_TAG
+            }
+            None => CHUNK_TAG,
+        };
+        let info = ChunkInfo {
+            offset: self.offset,
+            len: chunk.len() as u32,
+            events: self.chunk_events,
+            compressed: self.zstd_level.is_some(),
+        };
+        self.write_tagged(tag, &chunk)?;
+        self.index.chunks.push(info);
+        self.chunk_events = 0;
+        Ok(())
+    }
+}
+
+impl<W: Write> EventWrite for IndexedWriter<W> {
+    fn write_raw(&mut self, _line: &[u8]) -> Result<(), Error> {
+        Err(anyhow!(
+            "raw events can't be written to an indexed recording"
+        ))
+    }
+
+    fn write_event(&mut self, event: &Event) -> Result<(), Error> {
+        self.write_header()?;
+        self.index.observe(event, self.index.chunks.len() as u32);
+        ciborium::into_writer(event, &mut self.chunk).context("failed to encode event")?;
+        self.chunk_events += 1;
+        if self.chunk_events as usize >= self.max_chunk_events {
+            self.write_chunk()?;
+        }
+        Ok(())
+    }
+
+    fn write_metadata(&mut self, metadata: &Metadata) -> Result<(), Error> {
+        self.write_header()?;
+        let mut bytes = vec![];
+        let item = MetadataItem::Metadata(metadata.clone());
+        ciborium::into_writer(&item, &mut bytes).context("failed to encode metadata")?;
+        self.write_bytes(&bytes)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.write_chunk()?;
+        self.inner.flush().context("flush failed")
+    }
+
+    fn finalize(&mut self) -> Result<(), Error> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.write_header()?;
+        self.write_chunk()?;
+        let index_offset = self.offset;
+        let mut bytes = vec![];
+        ciborium::into_writer(&self.index, &mut bytes).context("failed to encode index")?;
+        self.write_tagged(INDEX_TAG, &bytes)?;
+        self.write_bytes(&index_offset.to_le_bytes())?;
+        self.write_bytes(MAGIC)?;
+        self.inner.flush().context("flush failed")
+    }
+}
+
+#[cfg(feature = "fs")]
+fn compress_chunk(bytes: &[u8], level: i32) -> Result<Vec<u8>, Error> {
+    zstd::stream::encode_all(bytes, level).context("failed to compress chunk")
+}
+
+#[cfg(not(feature = "fs"))]
+fn compress_chunk(_bytes: &[u8], _level: i32) -> Result<Vec<u8>, Error> {
+    Err(anyhow!("compressed chunks require the fs feature"))
+}
+
+#[cfg(feature = "fs")]
+fn decompress_chunk(bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    zstd::stream::decode_all(bytes).context("failed to decompress chunk")
+}
+
+#[cfg(not(feature = "fs"))]
+fn decompress_chunk(_bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    Err(anyhow!("compressed chunks require the fs feature"))
+}
+
+/// Decodes the events in a chunk with the tag `tag`.
+fn decode_tagged_chunk(tag: u8, bytes: &[u8]) -> Result<Vec<Event>, Error> {
+    match tag {
+        CHUNK_TAG => decode_chunk(bytes),
+        ZSTD_CHUNK_TAG => decode_chunk(&decompress_chunk(bytes)?),
+        _ => Err(anyhow!("unknown tag {tag:#x} in indexed recording")),
+    }
+}
+
+/// Decodes the events in an uncompressed chunk.
+fn decode_chunk(mut bytes: &[u8]) -> Result<Vec<Event>, Error> {
+    let mut events = vec![];
+    while !bytes.is_empty() {
+        events.push(ciborium::from_reader(&mut bytes).context("failed to decode event")?);
+    }
+    Ok(events)
+}
+
+/// Reads a tag and the length that follows it, returning `None` at the end of the input.
+fn read_tag(reader: &mut impl Read) -> Result<Option<(u8, u32)>, Error> {
+    let mut tag = [0; TAG_LEN];
+    match reader.read_exact(&mut tag) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    let len = u32::from_le_bytes(tag[1..].try_into().unwrap());
+    Ok(Some((tag[0], len)))
+}
+
+/// Reads the events of an indexed recording from start to end.
+pub struct ChunkReader<R> {
+    reader: R,
+    pending: VecDeque<Event>,
+    started: bool,
+    done: bool,
+}
+
+impl<R: BufRead> ChunkReader<R> {
+    /// Creates a reader, which skips the header if the reader is positioned at it.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            pending: VecDeque::new(),
+            started: false,
+            done: false,
+        }
+    }
+
+    fn skip_header(&mut self) -> Result<(), Error> {
+        if self.reader.fill_buf()?.starts_with(MAGIC) {
+            self.reader.consume(MAGIC.len());
+        }
+        if self.reader.fill_buf()?.starts_with(CBOR_METADATA_PREFIX) {
+            ciborium::from_reader::<MetadataItem, _>(&mut self.reader)
+                .context("failed to decode metadata")?;
+        }
+        Ok(())
+    }
+
+    /// Reads the next chunk, returning `false` once there are no more.
+    fn read_chunk(&mut self) -> Result<bool, Error> {
+        if !self.started {
+            self.started = true;
+            self.skip_header()?;
+        }
+        match read_tag(&mut self.reader)? {
+            None | Some((INDEX_TAG, _)) => Ok(false),
+            Some((tag, len)) => {
+                let mut bytes = vec![0; len as usize];
+                self.reader
+                    .read_exact(&mut bytes)
+                    .context("recording ended in the middle of a chunk")?;
+                self.pending.extend(decode_tagged_chunk(tag, &bytes)?);
+                Ok(true)
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for ChunkReader<R> {
+    type Item = Result<Event, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(Ok(event));
+            }
+            if self.done {
+                return None;
+            }
+            match self.read_chunk() {
+                Ok(true) => continue,
+                Ok(false) => {
+                    self.done = true;
+                    return None;
+                }
+                Err(err) => {
+                    // The stream can't be resynchronized after a bad chunk
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+/// Reads parts of an indexed recording without reading all of it.
+pub struct IndexedReader<R> {
+    reader: BufReader<R>,
+    metadata: Option<Metadata>,
+    index: Index,
+}
+
+impl<R: Read + Seek> IndexedReader<R> {
+    /// Reads the header and index of a recording, failing if it isn't an indexed
+    /// recording or it has no index.
+    pub fn open(reader: R) -> Result<Self, Error> {
+        let mut reader = BufReader::new(reader);
+        reader.seek(SeekFrom::Start(0))?;
+        let mut magic = [0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(anyhow!("not an indexed recording"));
+        }
+        let metadata = if reader.fill_buf()?.starts_with(CBOR_METADATA_PREFIX) {
+            let MetadataItem::Metadata(metadata) = ciborium::from_reader(&mut reader)?;
+            Some(metadata)
+        } else {
+            None
+        };
+
+        reader
+            .seek(SeekFrom::End(-FOOTER_LEN))
+            .context("recording has no index")?;
+        let mut footer = [0; FOOTER_LEN as usize];
+        reader.read_exact(&mut footer)?;
+        if &footer[8..] != MAGIC {
+            return Err(anyhow!(
+                "recording has no index, it may have been cut short"
+            ));
+        }
+        let index_offset = u64::from_le_bytes(footer[..8].try_into().unwrap());
+        reader.seek(SeekFrom::Start(index_offset))?;
+        let Some((INDEX_TAG, len)) = read_tag(&mut reader)? else {
+            return Err(anyhow!("footer doesn't point at the index"));
+        };
+        let mut bytes = vec![0; len as usize];
+        reader.read_exact(&mut bytes)?;
+        let index = ciborium::from_reader(bytes.as_slice()).context("failed to decode index")?;
+        Ok(Self {
+            reader,
+            metadata,
+            index,
+        })
+    }
+
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+
+    /// Reads the selected events in order, only reading the chunks that contain them.
+    pub fn read_selection(&mut self, selection: &Selection) -> Result<Vec<Event>, Error> {
+        let processes = self.index.select(selection)?;
+        let chunks = processes
+            .iter()
+            .filter_map(|key| self.index.entry(*key))
+            .flat_map(|entry| entry.chunks.iter().copied())
+            .collect::<BTreeSet<_>>();
+        let mut events = vec![];
+        for chunk in chunks {
+            let info = self
+                .index
+                .chunks
+                .get(chunk as usize)
+                .ok_or_else(|| anyhow!("index refers to missing chunk {chunk}"))?;
+            self.reader.seek(SeekFrom::Start(info.offset))?;
+            let Some((tag, len)) = read_tag(&mut self.reader)? else {
+                return Err(anyhow!("index refers to chunk {chunk} past the end"));
+            };
+            let mut bytes = vec![0; len as usize];
+            self.reader.read_exact(&mut bytes)?;
+            events.extend(
+                decode_tagged_chunk(tag, &bytes)?
+                    .into_iter()
+                    .filter(|event| {
+                        self.index
+                            .process_of(event)
+                            .is_some_and(|key| processes.contains(&key))
+                    }),
+            );
+        }
+        events.sort();
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use crate::ingest::test::make_simple_events;
+
+    use super::*;
+
+    fn tree() -> Vec<Event> {
+        make_simple_events(
+            0,
+            0,
+            &[
+                ("fork", 1, 0),
+                ("fork", 2, 1),
+                ("fork", 3, 2),
+                ("exit", 3, 2),
+                ("exit", 2, 1),
+                ("fork", 4, 1),
+                ("exit", 4, 1),
+                ("exit", 1, 0),
+            ],
+        )
+    }
+
+    fn encode(events: &[Event], chunk_events: usize, zstd: bool) -> Vec<u8> {
+        let mut buf = vec![];
+        let mut writer = IndexedWriter::with_chunk_events(&mut buf, chunk_events);
+        if zstd {
+            writer = writer.with_zstd_chunks(0);
+        }
+        writer.write_metadata(&Metadata::new(Some(1))).unwrap();
+        for event in events.iter() {
+            writer.write_event(event).unwrap();
+        }
+        writer.finalize().unwrap();
+        drop(writer);
+        buf
+    }
+
+    #[test]
+    fn reads_chunks_in_order() {
+        let events = tree();
+        let buf = encode(&events, 3, false);
+        let read = ChunkReader::new(buf.as_slice())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(read, events);
+
+        // A recording that was cut short still has its complete chunks
+        let cut = &buf[..buf.len() / 2];
+        let read = ChunkReader::new(cut).filter_map(Result::ok).count();
+        assert!(read > 0 && read < events.len());
+    }
+
+    #[test]
+    fn reads_selected_processes() {
+        let events = tree();
+        let mut reader = IndexedReader::open(Cursor::new(encode(&events, 2, false))).unwrap();
+        assert_eq!(reader.metadata().unwrap().root_pid, Some(1));
+        assert_eq!(reader.index().chunks.len(), 4);
+
+        let selection = Selection {
+            root_pid: Some(2),
+            since: None,
+        };
+        let pids = reader
+            .read_selection(&selection)
+            .unwrap()
+            .iter()
+            .map(|event| event.pid())
+            .collect::<Vec<_>>();
+        assert_eq!(pids, vec![2, 3, 3, 2]);
+        // PID 3 only has events in the second chunk
+        assert_eq!(reader.index().pids[&3][0].chunks, vec![1]);
+
+        // Processes that exited before the cutoff are left out, but the ancestors of
+        // the ones that didn't are kept
+        let selection = Selection {
+            root_pid: None,
+            since: Some(5),
+        };
+        let selected = reader.read_selection(&selection).unwrap();
+        let scanned = select_events(events.iter().cloned().map(Ok), &selection).unwrap();
+        assert_eq!(selected, scanned);
+        let mut pids = selected.iter().map(|event| event.pid()).collect::<Vec<_>>();
+        pids.dedup();
+        assert_eq!(pids, vec![1, 4, 1]);
+
+        let selection = Selection {
+            root_pid: Some(99),
+            since: None,
+        };
+        assert!(reader.read_selection(&selection).is_err());
+    }
+
+    #[test]
+    fn keeps_reused_pids_apart() {
+        let events = make_simple_events(
+            0,
+            0,
+            &[
+                ("fork", 1, 0),
+                ("fork", 2, 1),
+                ("exit", 2, 1),
+                ("fork", 3, 1),
+                ("fork", 2, 3),
+                ("exit", 2, 3),
+                ("exit", 3, 1),
+                ("exit", 1, 0),
+            ],
+        );
+        let mut reader = IndexedReader::open(Cursor::new(encode(&events, 2, false))).unwrap();
+        let entries = &reader.index().pids[&2];
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].parent, Some(1));
+        assert_eq!(entries[0].chunks, vec![0, 1]);
+        assert_eq!(entries[1].parent, Some(3));
+        assert_eq!(entries[1].chunks, vec![2]);
+
+        // Only the process that reused PID 2 descends from PID 3
+        let selection = Selection {
+            root_pid: Some(3),
+            since: None,
+        };
+        let selected = reader.read_selection(&selection).unwrap();
+        let scanned = select_events(events.iter().cloned().map(Ok), &selection).unwrap();
+        assert_eq!(selected, scanned);
+        let pids = selected.iter().map(|event| event.pid()).collect::<Vec<_>>();
+        assert_eq!(pids, vec![3, 2, 2, 3]);
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn reads_compressed_chunks() {
+        let events = tree();
+        let buf = encode(&events, 2, true);
+        let read = ChunkReader::new(buf.as_slice())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(read, events);
+
+        let mut reader = IndexedReader::open(Cursor::new(buf)).unwrap();
+        assert!(reader.index().chunks.iter().all(|chunk| chunk.compressed));
+        let selection = Selection {
+            root_pid: Some(4),
+            since: None,
+        };
+        let selected = reader.read_selection(&selection).unwrap();
+        assert_eq!(selected.len(), 2);
+        assert!(selected.iter().all(|event| event.pid() == 4));
+    }
+}
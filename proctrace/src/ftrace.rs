@@ -0,0 +1,359 @@
+//! Recording with ftrace, for kernels that don't support BPF.
+//!
+//! A script enables the `task_newtask`, `sched_process_exec`, and `sched_process_exit`
+//! events in a private tracefs instance and prints its `trace_pipe`. Each line is parsed
+//! here and converted into lines in the same format as the output of `bpftrace`, so they
+//! go through the same parsing and ingestion. Exit codes aren't reported and exec'd
+//! arguments are read from `/proc`, so they're missing for processes that exit right away.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::Context;
+use regex_lite::Regex;
+
+use crate::{
+    perf::{monotonic_nanos, Converter, TaskRecord},
+    poll::{read_exec, read_pgid},
+};
+
+type Error = anyhow::Error;
+
+const SCRIPT: &str = include_str!("../assets/ftrace.sh");
+/// Set in the clone flags of a new thread, as opposed to a new process.
+const CLONE_THREAD: u64 = 0x10000;
+/// How often a `TICK` line is sent so that host samples can be taken while it's quiet.
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// An event from `trace_pipe`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FtraceEvent {
+    NewTask {
+        /// The thread that created the task.
+        parent_tid: i32,
+        /// The process that created the task, if the kernel recorded it.
+        parent_tgid: Option<i32>,
+        pid: i32,
+        clone_flags: u64,
+        time: u64,
+    },
+    Exec {
+        pid: i32,
+        filename: String,
+        time: u64,
+    },
+    Exit {
+        pid: i32,
+        time: u64,
+    },
+}
+
+/// Parses the lines of `trace_pipe`.
+#[derive(Debug, Clone)]
+pub struct FtraceParser {
+    line: Regex,
+    new_task: Regex,
+    exec: Regex,
+    exit: Regex,
+}
+
+impl Default for FtraceParser {
+    fn default() -> Self {
+        FtraceParser::new()
+    }
+}
+
+impl FtraceParser {
+    pub fn new() -> Self {
+        Self {
+            line: Regex::new(
+                r"^\s*.*-(?<tid>\d+)\s+(?:\(\s*(?<tgid>[\d-]+)\)\s+)?\[\d+\]\s+(?:\S+\s+)?(?<secs>\d+)\.(?<frac>\d+): (?<event>\w+): (?<fields>.*)$",
+            )
+            .unwrap(),
+            new_task: Regex::new(
+                r"^pid=(?<pid>\d+) comm=.* clone_flags=(?<flags>[0-9a-fA-F]+) ",
+            )
+            .unwrap(),
+            exec: Regex::new(r"^filename=(?<filename>.*) pid=(?<pid>\d+) old_pid=\d+").unwrap(),
+            exit: Regex::new(r"^comm=.* pid=(?<pid>\d+) prio=").unwrap(),
+        }
+    }
+
+    /// Parses a line of `trace_pipe`, returning `None` for anything that isn't one of
+    /// the enabled events.
+    ///
+    /// Timestamps are in nanoseconds, which requires the `mono` trace clock.
+    pub fn parse_line(&self, line: &str) -> Option<FtraceEvent> {
+        let caps = self.line.captures(line)?;
+        let tid = caps["tid"].parse().ok()?;
+        let parent_tgid = caps
+            .name("tgid")
+            .and_then(|tgid| tgid.as_str().parse().ok());
+        // The fraction is usually in microseconds, so pad it out to nanoseconds
+        let frac = &caps["frac"];
+        let nanos = format!("{frac:0<9}").get(..9)?.parse::<u64>().ok()?;
+        let time = caps["secs"]
+            .parse::<u64>()
+            .ok()?
+            .checked_mul(1_000_000_000)?
+            .checked_add(nanos)?;
+        let fields = &caps["fields"];
+        match &caps["event"] {
+            "task_newtask" => {
+                let fields = self.new_task.captures(fields)?;
+                Some(FtraceEvent::NewTask {
+                    parent_tid: tid,
+                    parent_tgid,
+                    pid: fields["pid"].parse().ok()?,
+                    clone_flags: u64::from_str_radix(&fields["flags"], 16).ok()?,
+                    time,
+                })
+            }
+            "sched_process_exec" => {
+                let fields = self.exec.captures(fields)?;
+                Some(FtraceEvent::Exec {
+                    pid: fields["pid"].parse().ok()?,
+                    filename: fields["filename"].to_string(),
+                    time,
+                })
+            }
+            "sched_process_exit" => {
+                let fields = self.exit.captures(fields)?;
+                Some(FtraceEvent::Exit {
+                    pid: fields["pid"].parse().ok()?,
+                    time,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Turns ftrace events into task records, skipping threads.
+#[derive(Debug, Default)]
+pub struct TaskTracker {
+    /// The parents of the processes that have been forked, since exits don't report
+    /// them.
+    processes: HashMap<i32, i32>,
+}
+
+impl TaskTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the task record for an event, if it's about a process.
+    ///
+    /// `tgid_of` looks up the process of a thread, which is needed when the kernel
+    /// didn't record which process created a task.
+    pub fn track(
+        &mut self,
+        event: FtraceEvent,
+        tgid_of: impl Fn(i32) -> Option<i32>,
+    ) -> Option<TaskRecord> {
+        match event {
+            FtraceEvent::NewTask {
+                parent_tid,
+                parent_tgid,
+                pid,
+                clone_flags,
+                time,
+            } => {
+                if clone_flags & CLONE_THREAD != 0 {
+                    return None;
+                }
+                let ppid = parent_tgid
+                    .or_else(|| tgid_of(parent_tid))
+                    .unwrap_or(parent_tid);
+                self.processes.insert(pid, ppid);
+                Some(TaskRecord::Fork { pid, ppid, time })
+            }
+            FtraceEvent::Exec {
+                pid,
+                filename,
+                time,
+            } => Some(TaskRecord::Exec {
+                pid,
+                program: filename,
+                time,
+            }),
+            // Threads exit too, but only processes were forked
+            FtraceEvent::Exit { pid, time } => self
+                .processes
+                .remove(&pid)
+                .map(|ppid| TaskRecord::Exit { pid, ppid, time }),
+        }
+    }
+}
+
+/// Looks up the process that a thread belongs to.
+fn tgid_of(tid: i32) -> Option<i32> {
+    let status = procfs::process::Process::new(tid).ok()?.status().ok()?;
+    Some(status.tgid)
+}
+
+/// Starts tracing with ftrace as root, sending lines of raw output from a background
+/// thread.
+///
+/// The first line is the one that the tracing script prints once the events are
+/// enabled. The threads stop once the receiver is dropped or `shutdown_flag` is set,
+/// and the script cleans up its tracing instance once its output is closed.
+pub fn spawn_tracer(shutdown_flag: Arc<AtomicBool>) -> Result<Receiver<String>, Error> {
+    let mut tracer = Command::new("sudo")
+        .arg("sh")
+        .arg("-c")
+        .arg(SCRIPT)
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("failed to spawn ftrace script")?;
+    let stdout = tracer.stdout.take().unwrap();
+    let converter = Arc::new(Mutex::new(Converter::new(monotonic_nanos())));
+    let (tx, rx) = channel();
+
+    std::thread::spawn(move || {
+        let parser = FtraceParser::new();
+        let mut tracker = TaskTracker::new();
+        let mut started = false;
+        for line in BufReader::new(stdout).lines() {
+            if shutdown_flag.load(Ordering::SeqCst) {
+                break;
+            }
+            let Ok(line) = line else {
+                continue;
+            };
+            let lines = if !started {
+                // Ticks can't be sent until tracing has started, since the first line
+                // is what starts the command
+                started = true;
+                spawn_ticker(converter.clone(), tx.clone(), shutdown_flag.clone());
+                vec![line]
+            } else {
+                let Some(record) = parser
+                    .parse_line(&line)
+                    .and_then(|event| tracker.track(event, tgid_of))
+                else {
+                    continue;
+                };
+                converter
+                    .lock()
+                    .unwrap()
+                    .convert(&record, read_exec, read_pgid)
+            };
+            if lines.into_iter().any(|line| tx.send(line).is_err()) {
+                break;
+            }
+        }
+    });
+    Ok(rx)
+}
+
+/// Sends a `TICK` line every `TICK_INTERVAL` until the receiver is dropped.
+fn spawn_ticker(
+    converter: Arc<Mutex<Converter>>,
+    tx: Sender<String>,
+    shutdown_flag: Arc<AtomicBool>,
+) {
+    std::thread::spawn(move || {
+        while !shutdown_flag.load(Ordering::SeqCst) {
+            std::thread::sleep(TICK_INTERVAL);
+            let tick = converter.lock().unwrap().tick(monotonic_nanos());
+            if tx.send(tick).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_trace_pipe() {
+        let parser = FtraceParser::new();
+        let mut tracker = TaskTracker::new();
+        let lines = [
+            "       proctrace-100     (    99) [003] ..... 5012.000100: task_newtask: pid=200 comm=proctrace clone_flags=1200000 oom_score_adj=0",
+            "  cargo worker-150     [001] d..3. 5012.000200: task_newtask: pid=201 comm=cargo worker clone_flags=3d0f00 oom_score_adj=0",
+            "              sh-200     [002] ..... 5012.000300: sched_process_exec: filename=/usr/bin/my tool pid=200 old_pid=200",
+            "  cargo worker-201     [001] ..... 5012.000400: sched_process_exit: comm=cargo worker pid=201 prio=120",
+            "              sh-200     [002] ..... 5012.000500: sched_process_exit: comm=sh pid=200 prio=120 group_dead=1",
+            "              sh-200     [002] ..... 5012.000600: sched_switch: prev_comm=sh",
+        ];
+        let records = lines
+            .iter()
+            .filter_map(|line| parser.parse_line(line))
+            .filter_map(|event| tracker.track(event, |_| Some(1)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            records,
+            vec![
+                TaskRecord::Fork {
+                    pid: 200,
+                    ppid: 99,
+                    time: 5_012_000_100_000,
+                },
+                TaskRecord::Exec {
+                    pid: 200,
+                    program: "/usr/bin/my tool".to_string(),
+                    time: 5_012_000_300_000,
+                },
+                TaskRecord::Exit {
+                    pid: 200,
+                    ppid: 99,
+                    time: 5_012_000_500_000,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_malformed_lines() {
+        let parser = FtraceParser::new();
+        let lines = [
+            "",
+            "CPU:3 [LOST 12 EVENTS]",
+            // Cut off part way through
+            "              sh-200     [002] ..... 5012.000300: sched_process_ex",
+            "              sh-200     [002] ..... 5012.000300: sched_process_exec: filename=/bin/sh",
+            // Fields that aren't numbers
+            "              sh-abc     [002] ..... 5012.000300: sched_process_exit: comm=sh pid=200 prio=120",
+            "       proctrace-100     [003] ..... 5012.000100: task_newtask: pid=200 comm=sh clone_flags=xyz oom_score_adj=0",
+            "              sh-200     [002] ..... 5012.000500: sched_process_exit: comm=sh pid=-1 prio=120",
+            // Too far in the future to be in nanoseconds
+            "              sh-200     [002] ..... 18446744074.000500: sched_process_exit: comm=sh pid=200 prio=120",
+        ];
+        for line in lines {
+            assert_eq!(parser.parse_line(line), None, "{line}");
+        }
+
+        // The process that created a task is missing when the kernel didn't record it
+        let line = "       proctrace-100     (-------) [003] ..... 5012.000100: task_newtask: pid=200 comm=sh clone_flags=1200000 oom_score_adj=0";
+        let event = parser.parse_line(line).unwrap();
+        assert!(matches!(
+            event,
+            FtraceEvent::NewTask {
+                parent_tid: 100,
+                parent_tgid: None,
+                ..
+            }
+        ));
+        assert_eq!(
+            TaskTracker::new().track(event, |_| Some(99)),
+            Some(TaskRecord::Fork {
+                pid: 200,
+                ppid: 99,
+                time: 5_012_000_100_000,
+            })
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! Finds the periods where nothing but the root process was running, i.e. the time
+//! between one step of a build or script and the next, which points at sleeps, network
+//! waits, or lock contention rather than at slow commands.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    analyze::{command_line, format_nanos},
+    models::EventStore,
+};
+
+/// The default length of the shortest gap that's reported, in milliseconds.
+pub const DEFAULT_MIN_GAP_MS: u64 = 100;
+
+/// A process on either side of an idle gap.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GapNeighbor {
+    pub pid: i32,
+    /// The command line of the last thing the process exec'd, or "(fork of <ppid>)"
+    /// if it never exec'd anything.
+    pub command: String,
+}
+
+impl std::fmt::Display for GapNeighbor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] {}", self.pid, self.command)
+    }
+}
+
+/// A period where the root process had no running descendants.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdleGap {
+    /// When the gap started, relative to the start of the root process, in
+    /// nanoseconds.
+    pub start: u128,
+    /// How long the gap lasted, in nanoseconds.
+    pub duration: u128,
+    /// The last process to exit before the gap.
+    pub before: GapNeighbor,
+    /// The first process to start after the gap.
+    pub after: GapNeighbor,
+}
+
+/// The idle gaps in a recording, in the order they happened.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdleGapReport {
+    /// The length of the shortest gap that's reported, in nanoseconds.
+    pub min_gap: u128,
+    pub gaps: Vec<IdleGap>,
+}
+
+impl IdleGapReport {
+    /// Finds the gaps of at least `min_gap` nanoseconds between the descendants of
+    /// `root_pid`.
+    ///
+    /// Only the gaps between descendants are reported, not the time before the first
+    /// one started or after the last one exited. Processes that never exited run until
+    /// the end of the recording.
+    pub fn from_store(store: &EventStore, root_pid: i32, min_gap: u128) -> Self {
+        let end_of_trace = store
+            .iter()
+            .flat_map(|(_, buffer)| buffer.iter())
+            .map(|event| event.timestamp())
+            .max()
+            .unwrap_or(0);
+        let root_start = store.pid_start_time(root_pid).unwrap_or(0);
+        let mut spans = store
+            .iter()
+            .filter(|(pid, _)| *pid != root_pid)
+            .filter_map(|(pid, buffer)| {
+                let start = buffer.front()?.timestamp();
+                let stop = match buffer.back() {
+                    Some(event) if event.is_exit() => event.timestamp(),
+                    _ => end_of_trace,
+                };
+                Some((start, stop, pid))
+            })
+            .collect::<Vec<_>>();
+        spans.sort();
+        let neighbor = |pid: i32| GapNeighbor {
+            pid,
+            command: store
+                .events_of(pid)
+                .map(command_line)
+                .unwrap_or_else(|| "(unknown)".to_string()),
+        };
+        let mut gaps = vec![];
+        // When the descendants that have started so far stop running, and which one
+        // stops last
+        let mut busy: Option<(u128, i32)> = None;
+        for (start, stop, pid) in spans {
+            match busy {
+                Some((busy_until, last)) if start > busy_until => {
+                    if start - busy_until >= min_gap {
+                        gaps.push(IdleGap {
+                            start: busy_until.saturating_sub(root_start),
+                            duration: start - busy_until,
+                            before: neighbor(last),
+                            after: neighbor(pid),
+                        });
+                    }
+                    busy = Some((stop, pid));
+                }
+                Some((busy_until, _)) if stop <= busy_until => {}
+                _ => busy = Some((stop, pid)),
+            }
+        }
+        Self { min_gap, gaps }
+    }
+
+    /// The combined length of the gaps, in nanoseconds.
+    pub fn idle_time(&self) -> u128 {
+        self.gaps.iter().map(|gap| gap.duration).sum()
+    }
+
+    /// Formats the report as a table of the gaps in the order they happened.
+    pub fn to_table(&self) -> String {
+        if self.gaps.is_empty() {
+            return format!("no idle gaps of at least {}\n", format_nanos(self.min_gap));
+        }
+        let mut table = format!(
+            "{:>12}  {:>12}  {:<40}  {}\n",
+            "start", "idle for", "before", "after"
+        );
+        for gap in self.gaps.iter() {
+            table.push_str(&format!(
+                "{:>12}  {:>12}  {:<40}  {}\n",
+                format_nanos(gap.start),
+                format_nanos(gap.duration),
+                truncate(&gap.before.to_string(), 40),
+                gap.after
+            ));
+        }
+        let plural = if self.gaps.len() == 1 { "" } else { "s" };
+        table.push_str(&format!(
+            "{} gap{plural} of at least {}, {} idle in total\n",
+            self.gaps.len(),
+            format_nanos(self.min_gap),
+            format_nanos(self.idle_time())
+        ));
+        table
+    }
+}
+
+/// Cuts a command line off at `len` characters so that the next column lines up.
+fn truncate(command: &str, len: usize) -> String {
+    if command.chars().count() <= len {
+        return command.to_string();
+    }
+    let mut truncated = command.chars().take(len - 3).collect::<String>();
+    truncated.push_str("...");
+    truncated
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{builder::TraceBuilder, render::read_events, writers::JsonWriter};
+
+    use super::*;
+
+    #[test]
+    fn finds_gaps_between_children() {
+        let ms = 1_000_000;
+        let mut bytes = vec![];
+        TraceBuilder::new()
+            .fork(0, 1)
+            .exec(1, "sh ci.sh")
+            .fork(1, 2)
+            .exec(2, "make")
+            .fork(2, 3)
+            .exec(3, "cc -c a.c")
+            .advance(10 * ms)
+            .exit(3, 0)
+            .advance(5 * ms)
+            .exit(2, 0)
+            // The script sleeps before the next step
+            .advance(500 * ms)
+            .fork(1, 4)
+            .exec(4, "curl https://example.com")
+            .advance(20 * ms)
+            .exit(4, 0)
+            // Too short to count
+            .advance(ms)
+            .fork(1, 5)
+            .exec(5, "true")
+            .exit(5, 0)
+            .exit(1, 0)
+            .write(JsonWriter::new(&mut bytes))
+            .unwrap();
+        let ingester = read_events(bytes.as_slice()).unwrap();
+        let report = IdleGapReport::from_store(ingester.tracked_events(), 1, 100 * ms);
+        assert_eq!(
+            report.gaps,
+            vec![IdleGap {
+                start: 15 * ms,
+                duration: 500 * ms,
+                before: GapNeighbor {
+                    pid: 2,
+                    command: "make".to_string(),
+                },
+                after: GapNeighbor {
+                    pid: 4,
+                    command: "curl https://example.com".to_string(),
+                },
+            }]
+        );
+        let table = report.to_table();
+        assert!(
+            table.ends_with("1 gap of at least 100.000ms, 500.000ms idle in total\n"),
+            "{table}"
+        );
+        assert_eq!(
+            IdleGapReport::from_store(ingester.tracked_events(), 1, 600 * ms).to_table(),
+            "no idle gaps of at least 600.000ms\n"
+        );
+    }
+}
@@ -0,0 +1,304 @@
+//! Groups processes by tag, e.g. by the package of a monorepo that they built, so that
+//! the time of one big build can be broken down per package.
+//!
+//! Processes are tagged with the directory they ran in, or by rules read from a TOML
+//! file with a `[[tag]]` table for each rule:
+//!
+//! ```toml
+//! [[tag]]
+//! match = '^/src/monorepo/packages/([^/]+)'
+//! name = "$1"
+//!
+//! [[tag]]
+//! match = 'cargo build -p (\S+)'
+//! name = "$1"
+//! ```
+//!
+//! A rule matches a process if its pattern matches either the directory it ran in or
+//! the command line it last exec'd, and the first rule that matches wins. The name can
+//! refer to the groups that the pattern captured. Processes that no rule matches
+//! inherit the tag of their parent.
+
+use std::collections::BTreeMap;
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+#[cfg(feature = "fs")]
+use anyhow::Context;
+use regex_lite::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    analyze::format_nanos,
+    models::{Event, EventStore},
+};
+
+#[cfg(feature = "fs")]
+type Error = anyhow::Error;
+
+/// The group of the processes that don't have a tag.
+pub const UNTAGGED: &str = "(untagged)";
+
+/// How processes are tagged.
+#[derive(Debug, Clone)]
+pub enum Tagger {
+    /// With the directory they ran in.
+    Directory,
+    /// With the name of the first rule whose pattern matches them, or the tag of their
+    /// parent.
+    Rules(Vec<(Regex, String)>),
+}
+
+/// The contents of a tags file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "fs"), allow(dead_code))]
+struct TagsFile {
+    #[serde(default)]
+    tag: Vec<TagRule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "fs"), allow(dead_code))]
+struct TagRule {
+    #[serde(rename = "match")]
+    pattern: String,
+    name: String,
+}
+
+impl Tagger {
+    /// Parses a tags file.
+    #[cfg(feature = "fs")]
+    pub fn parse(contents: &str) -> Result<Self, Error> {
+        let file: TagsFile = toml::from_str(contents).context("failed to parse tags")?;
+        let rules = file
+            .tag
+            .into_iter()
+            .map(|rule| {
+                let pattern = Regex::new(&rule.pattern)
+                    .with_context(|| format!("invalid tag pattern '{}'", rule.pattern))?;
+                Ok((pattern, rule.name))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(Self::Rules(rules))
+    }
+
+    /// Reads the tags file at `path`.
+    #[cfg(feature = "fs")]
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read tags file {}", path.display()))?;
+        Self::parse(&contents).with_context(|| format!("invalid tags file {}", path.display()))
+    }
+
+    /// Returns the tag of a process, if it has one.
+    ///
+    /// `cwd` is the directory the recording started in, see
+    /// [`EventStore::directory_of`].
+    pub fn tag(&self, store: &EventStore, pid: i32, cwd: Option<&str>) -> Option<String> {
+        let rules = match self {
+            Tagger::Directory => return store.directory_of(pid, cwd),
+            Tagger::Rules(rules) => rules,
+        };
+        for current in std::iter::once(pid).chain(store.ancestors(pid)) {
+            let buffer = store.events_of(current)?;
+            let directory = store.directory_of(current, cwd);
+            let command = buffer.iter().rev().find_map(|event| match event {
+                Event::ExecFull { args, .. } => Some(args.joined()),
+                _ => None,
+            });
+            let tag = rules.iter().find_map(|(pattern, name)| {
+                let captures = directory
+                    .as_deref()
+                    .and_then(|directory| pattern.captures(directory))
+                    .or_else(|| {
+                        command
+                            .as_deref()
+                            .and_then(|command| pattern.captures(command))
+                    })?;
+                let mut tag = String::new();
+                captures.expand(name, &mut tag);
+                Some(tag)
+            });
+            if tag.is_some() {
+                return tag;
+            }
+        }
+        None
+    }
+
+    /// Returns the tag of every process that has one, keyed by PID.
+    pub fn tags(&self, store: &EventStore, cwd: Option<&str>) -> BTreeMap<i32, String> {
+        store
+            .iter()
+            .filter_map(|(pid, _)| Some((pid, self.tag(store, pid, cwd)?)))
+            .collect()
+    }
+}
+
+/// The processes with a single tag.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupTiming {
+    /// The number of processes with the tag.
+    pub processes: usize,
+    /// How long at least one of them was running, in nanoseconds.
+    pub wall: u128,
+    /// The combined lifetime of the processes, in nanoseconds.
+    pub process_time: u128,
+}
+
+/// The time spent by the processes of each tag.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupReport {
+    /// Keyed by tag, with the processes that don't have one under "(untagged)".
+    pub groups: BTreeMap<String, GroupTiming>,
+}
+
+impl GroupReport {
+    /// Adds up the time of the processes in a recording by their tags.
+    ///
+    /// Processes that never exited are counted until their last event.
+    pub fn from_store(store: &EventStore, tags: &BTreeMap<i32, String>) -> Self {
+        let mut spans: BTreeMap<String, Vec<(u128, u128)>> = BTreeMap::new();
+        for (pid, buffer) in store.iter() {
+            let (Some(first), Some(last)) = (buffer.front(), buffer.back()) else {
+                continue;
+            };
+            let tag = tags.get(&pid).map(String::as_str).unwrap_or(UNTAGGED);
+            spans
+                .entry(tag.to_string())
+                .or_default()
+                .push((first.timestamp(), last.timestamp()));
+        }
+        let groups = spans
+            .into_iter()
+            .map(|(tag, mut spans)| {
+                spans.sort();
+                let mut timing = GroupTiming {
+                    processes: spans.len(),
+                    ..Default::default()
+                };
+                let mut covered: Option<(u128, u128)> = None;
+                for (start, stop) in spans {
+                    timing.process_time += stop.saturating_sub(start);
+                    covered = match covered {
+                        Some((from, to)) if start <= to => Some((from, to.max(stop))),
+                        Some((from, to)) => {
+                            timing.wall += to - from;
+                            Some((start, stop))
+                        }
+                        None => Some((start, stop)),
+                    };
+                }
+                if let Some((from, to)) = covered {
+                    timing.wall += to - from;
+                }
+                (tag, timing)
+            })
+            .collect();
+        Self { groups }
+    }
+
+    /// Formats the report as a table of the tags, longest wall time first.
+    pub fn to_table(&self) -> String {
+        let mut groups = self.groups.iter().collect::<Vec<_>>();
+        groups.sort_by_key(|(_, timing)| std::cmp::Reverse(timing.wall));
+        let width = groups
+            .iter()
+            .map(|(tag, _)| tag.len())
+            .max()
+            .unwrap_or(0)
+            .max("group".len());
+        let mut table = format!(
+            "{:<width$}  {:>9}  {:>12}  {:>12}\n",
+            "group", "processes", "wall", "process time"
+        );
+        for (tag, timing) in groups {
+            table.push_str(&format!(
+                "{:<width$}  {:>9}  {:>12}  {:>12}\n",
+                tag,
+                timing.processes,
+                format_nanos(timing.wall),
+                format_nanos(timing.process_time)
+            ));
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::builder::TraceBuilder;
+
+    use super::*;
+
+    fn store() -> EventStore {
+        let ms = 1_000_000;
+        let events = TraceBuilder::new()
+            .fork(0, 1)
+            .exec(1, "make -C packages")
+            .chdir(1, "packages")
+            .fork(1, 2)
+            .chdir(2, "foo")
+            .exec(2, "make")
+            .fork(2, 3)
+            .exec(3, "cc -c foo.c")
+            .fork(1, 4)
+            .chdir(4, "/src/monorepo/packages/bar/../baz")
+            .exec(4, "make")
+            .advance(10 * ms)
+            .exit(3, 0)
+            .exit(2, 0)
+            .advance(10 * ms)
+            .exit(4, 0)
+            .exit(1, 0)
+            .build();
+        EventStore::from_events(events)
+    }
+
+    #[test]
+    fn tags_processes_by_directory() {
+        let store = store();
+        let tags = Tagger::Directory.tags(&store, Some("/src/monorepo"));
+        assert_eq!(tags[&1], "/src/monorepo/packages");
+        assert_eq!(tags[&3], "/src/monorepo/packages/foo");
+        assert_eq!(tags[&4], "/src/monorepo/packages/baz");
+        // Relative directories can't be resolved without knowing where it started
+        let tags = Tagger::Directory.tags(&store, None);
+        assert_eq!(tags.keys().collect::<Vec<_>>(), vec![&4]);
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn groups_processes_by_rules() {
+        let store = store();
+        let tagger = Tagger::parse(
+            r#"
+            [[tag]]
+            match = '^/src/monorepo/packages/([^/]+)'
+            name = "$1"
+            "#,
+        )
+        .unwrap();
+        let tags = tagger.tags(&store, Some("/src/monorepo"));
+        assert_eq!(tags.get(&1), None);
+        assert_eq!(tags[&3], "foo");
+
+        let report = GroupReport::from_store(&store, &tags);
+        let ms = 1_000_000;
+        assert_eq!(
+            report.groups["foo"],
+            GroupTiming {
+                processes: 2,
+                wall: 10 * ms,
+                process_time: 20 * ms,
+            }
+        );
+        assert_eq!(report.groups["baz"].wall, 20 * ms);
+        assert_eq!(report.groups[UNTAGGED].processes, 1);
+        let table = report.to_table();
+        assert!(table.lines().last().unwrap().starts_with("foo "), "{table}");
+    }
+}
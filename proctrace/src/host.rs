@@ -0,0 +1,137 @@
+//! Samples of how busy the host and the recorded processes were while recording.
+
+use crate::models::ResourceUsage;
+
+/// Parses the contents of `/proc/loadavg` into the 1, 5, and 15 minute load averages
+/// multiplied by 100.
+pub fn parse_loadavg(loadavg: &str) -> Option<[u32; 3]> {
+    let mut fields = loadavg.split_whitespace().map(|field| {
+        field
+            .parse::<f64>()
+            .ok()
+            .map(|load| (load * 100.0).round() as u32)
+    });
+    Some([fields.next()??, fields.next()??, fields.next()??])
+}
+
+/// Parses the contents of `/proc/meminfo` into the total and available memory in kB.
+pub fn parse_meminfo(meminfo: &str) -> Option<(u64, u64)> {
+    let field = |name: &str| {
+        meminfo.lines().find_map(|line| {
+            line.strip_prefix(name)?
+                .strip_prefix(':')?
+                .split_whitespace()
+                .next()?
+                .parse::<u64>()
+                .ok()
+        })
+    };
+    Some((field("MemTotal")?, field("MemAvailable")?))
+}
+
+/// Formats a host sample as a line in the same format as the output of `bpftrace`, so
+/// that it can be stored in raw recordings and re-ingested.
+pub fn host_sample_line(seq: u128, ts: u128, loadavg: &str, meminfo: &str) -> Option<String> {
+    let [load1, load5, load15] = parse_loadavg(loadavg)?;
+    let (total, available) = parse_meminfo(meminfo)?;
+    Some(format!(
+        "HOST: seq={seq},ts={ts},load1={load1},load5={load5},load15={load15},mem_total={total},mem_available={available}"
+    ))
+}
+
+/// Parses the resident set size in kB out of the contents of `/proc/<pid>/status`.
+///
+/// Kernel threads and zombies don't have one.
+pub fn parse_vm_rss(status: &str) -> Option<u64> {
+    status.lines().find_map(|line| {
+        line.strip_prefix("VmRSS:")?
+            .split_whitespace()
+            .next()?
+            .parse::<u64>()
+            .ok()
+    })
+}
+
+/// Formats the memory use of a process as a line in the same format as the output of
+/// `bpftrace`, like [`host_sample_line`].
+pub fn rss_sample_line(seq: u128, ts: u128, pid: i32, status: &str) -> Option<String> {
+    let rss = parse_vm_rss(status)?;
+    Some(format!("RSS: seq={seq},ts={ts},pid={pid},rss_kb={rss}"))
+}
+
+/// Formats the resources used by one of the recorder's own processes as a line in the
+/// same format as the output of `bpftrace`, like [`host_sample_line`].
+pub fn overhead_line(seq: u128, ts: u128, pid: i32, comm: &str, usage: &ResourceUsage) -> String {
+    format!(
+        "OVERHEAD: seq={seq},ts={ts},pid={pid},minor_faults={},major_faults={},voluntary_switches={},involuntary_switches={},user_ns={},system_ns={},comm={comm}",
+        usage.minor_faults,
+        usage.major_faults,
+        usage.voluntary_switches,
+        usage.involuntary_switches,
+        usage.user_ns,
+        usage.system_ns
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{ingest::EventParser, models::Event};
+
+    use super::*;
+
+    #[test]
+    fn formats_parseable_lines() {
+        let loadavg = "1.25 0.50 0.05 2/1234 5678\n";
+        let meminfo =
+            "MemTotal:       16384 kB\nMemFree:         1000 kB\nMemAvailable:    8192 kB\n";
+        let line = host_sample_line(1, 2, loadavg, meminfo).unwrap();
+        let event = EventParser::new().parse_line(line).unwrap();
+        assert_eq!(
+            event,
+            Event::HostSample {
+                seq: 1,
+                timestamp: 2,
+                load: [125, 50, 5],
+                mem_total_kb: 16384,
+                mem_available_kb: 8192,
+            }
+        );
+        assert!(host_sample_line(1, 2, "", meminfo).is_none());
+        assert!(host_sample_line(1, 2, loadavg, "MemTotal: 1 kB").is_none());
+
+        let status = "Name:\tcc1\nVmPeak:\t  90000 kB\nVmRSS:\t   51200 kB\nThreads:\t1\n";
+        let line = rss_sample_line(3, 4, 10, status).unwrap();
+        let event = EventParser::new().parse_line(line).unwrap();
+        assert_eq!(
+            event,
+            Event::RssSample {
+                seq: 3,
+                timestamp: 4,
+                pid: 10,
+                rss_kb: 51200,
+            }
+        );
+        assert!(rss_sample_line(3, 4, 10, "Name:\tkthreadd\n").is_none());
+
+        let usage = ResourceUsage {
+            minor_faults: 1,
+            major_faults: 2,
+            voluntary_switches: 3,
+            involuntary_switches: 4,
+            user_ns: 5_000_000,
+            system_ns: 6_000_000,
+        };
+        let line = overhead_line(5, 6, 20, "bpftrace", &usage);
+        let event = EventParser::new().parse_line(line).unwrap();
+        assert_eq!(
+            event,
+            Event::Overhead {
+                seq: 5,
+                timestamp: 6,
+                pid: 20,
+                command: "bpftrace".to_string(),
+                usage,
+            }
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! What this build of `proctrace` can do, so that wrapper scripts can check for a
+//! feature instead of parsing `--help`.
+
+use clap::{CommandFactory, ValueEnum};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cli::{Backend, Cli, Compression, DisplayMode, OutFormat, RawFormat},
+    models::SCHEMA_VERSION,
+    writers::TraceFormat,
+};
+
+/// The version and capabilities of this build.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    pub version: String,
+    /// The version of the format of processed recordings.
+    pub schema_version: u32,
+    /// The cargo features that this build was compiled with.
+    pub features: Vec<String>,
+    /// The subcommands, including the ones under `analyze` as "analyze <name>".
+    pub commands: Vec<String>,
+    /// The backends that `record --backend` accepts, empty if recording isn't
+    /// supported.
+    pub backends: Vec<String>,
+    /// What `record --out-format` accepts.
+    pub out_formats: Vec<String>,
+    /// The encodings of processed recordings, which are all read and written.
+    pub trace_formats: Vec<String>,
+    /// The formats of raw recordings that `ingest` reads.
+    pub raw_formats: Vec<String>,
+    pub compression: Vec<String>,
+    pub display_modes: Vec<String>,
+    /// Where a recording in progress can be sent besides its output.
+    pub exporters: Vec<String>,
+}
+
+impl Capabilities {
+    /// Describes this build.
+    pub fn current() -> Self {
+        let mut features = vec![];
+        if cfg!(feature = "fs") {
+            features.push("fs".to_string());
+        }
+        if cfg!(feature = "record") {
+            features.push("record".to_string());
+        }
+        let recording = cfg!(all(target_os = "linux", feature = "record"));
+        let mut exporters = vec![];
+        if recording {
+            exporters.extend(["ship", "journal", "metrics", "stream", "control"].map(String::from));
+        }
+        let mut display_modes = value_names::<DisplayMode>();
+        if cfg!(feature = "fs") {
+            exporters.push("serve".to_string());
+            display_modes.push("exec:<PROGRAM>".to_string());
+        }
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            schema_version: SCHEMA_VERSION,
+            features,
+            commands: commands(),
+            backends: if recording {
+                value_names::<Backend>()
+            } else {
+                vec![]
+            },
+            out_formats: if recording {
+                value_names::<OutFormat>()
+            } else {
+                vec![]
+            },
+            trace_formats: value_names::<TraceFormat>(),
+            raw_formats: value_names::<RawFormat>(),
+            compression: value_names::<Compression>(),
+            display_modes,
+            exporters,
+        }
+    }
+
+    /// Formats the capabilities as a line for each kind, e.g. "backends: perf, procfs".
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "version: {}\nschema version: {}\n",
+            self.version, self.schema_version
+        );
+        for (name, values) in [
+            ("features", &self.features),
+            ("commands", &self.commands),
+            ("backends", &self.backends),
+            ("out formats", &self.out_formats),
+            ("trace formats", &self.trace_formats),
+            ("raw formats", &self.raw_formats),
+            ("compression", &self.compression),
+            ("display modes", &self.display_modes),
+            ("exporters", &self.exporters),
+        ] {
+            let values = if values.is_empty() {
+                "(none)".to_string()
+            } else {
+                values.join(", ")
+            };
+            text.push_str(&format!("{name}: {values}\n"));
+        }
+        text
+    }
+}
+
+/// The names of the values of an enum as they're given on the command line.
+fn value_names<T: ValueEnum>() -> Vec<String> {
+    T::value_variants()
+        .iter()
+        .filter_map(|value| value.to_possible_value())
+        .map(|value| value.get_name().to_string())
+        .collect()
+}
+
+/// The names of the subcommands that this build has.
+fn commands() -> Vec<String> {
+    let cli = Cli::command();
+    let mut commands = vec![];
+    for command in cli.get_subcommands() {
+        let name = command.get_name();
+        if command.has_subcommands() {
+            commands.extend(
+                command
+                    .get_subcommands()
+                    .map(|subcommand| format!("{name} {}", subcommand.get_name())),
+            );
+        } else {
+            commands.push(name.to_string());
+        }
+    }
+    commands
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn describes_this_build() {
+        let capabilities = Capabilities::current();
+        assert_eq!(capabilities.version, env!("CARGO_PKG_VERSION"));
+        assert!(capabilities.commands.contains(&"render".to_string()));
+        assert!(capabilities
+            .display_modes
+            .contains(&"mermaid-md".to_string()));
+        assert!(capabilities
+            .trace_formats
+            .contains(&"indexed-zstd".to_string()));
+        #[cfg(feature = "fs")]
+        assert!(capabilities
+            .commands
+            .contains(&"analyze interpreters".to_string()));
+        let json = serde_json::to_string(&capabilities).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Capabilities>(&json).unwrap(),
+            capabilities
+        );
+    }
+}
@@ -1,10 +1,18 @@
 use std::{
-    collections::{HashSet, VecDeque},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     io::{BufRead, BufReader, Read},
 };
 
 use crate::{
-    models::{Event, EventStore, ExecArgsKind},
+    cli::RawFormat,
+    jsonl::JsonlParser,
+    labels::Labels,
+    models::{
+        namespace_names, sandbox_flag_names, Credentials, Event, EventStore, ExecArgsKind,
+        Metadata, NamespaceChange, PtraceRequest, ResourceUsage, SampleRatio, SandboxMechanism,
+        UserFilter, UNKNOWN_PGID,
+    },
+    storm::StormDetector,
     writers::EventWrite,
 };
 use anyhow::{anyhow, Context};
@@ -18,10 +26,24 @@ pub struct EventParser {
     exec: Regex,
     badexec: Regex,
     exec_args: Regex,
+    exec_arg: Regex,
     exec_filename: Regex,
     exit: Regex,
     setsid: Regex,
     setpgid: Regex,
+    chdir: Regex,
+    namespace: Regex,
+    mount: Regex,
+    ptrace: Regex,
+    sandbox: Regex,
+    subreaper: Regex,
+    cpu: Regex,
+    rss: Regex,
+    usage: Regex,
+    overhead: Regex,
+    host: Regex,
+    tick: Regex,
+    custom: Regex,
 }
 
 impl Default for EventParser {
@@ -33,14 +55,16 @@ impl Default for EventParser {
 impl EventParser {
     pub fn new() -> Self {
         let fork_regex = Regex::new(
-        r"FORK: seq=(?<seq>\d+),ts=(?<ts>\d+),parent_pid=(?<ppid>[\-\d]+),child_pid=(?<cpid>[\-\d]+),parent_pgid=(?<pgid>[\-\d]+)",
+        r"FORK: seq=(?<seq>\d+),ts=(?<ts>\d+),parent_pid=(?<ppid>[\-\d]+),child_pid=(?<cpid>[\-\d]+)(,parent_pgid=(?<pgid>[\-\d]+))?(,uid=(?<uid>\d+),gid=(?<gid>\d+))?(,comm=(?<comm>.*))?",
     ).unwrap();
         let exec_regex = Regex::new(
-            r"EXEC: seq=(?<seq>\d+),ts=(?<ts>\d+),pid=(?<pid>[\-\d]+),ppid=(?<ppid>[\-\d]+),pgid=(?<pgid>[\-\d]+)",
+            r"EXEC: seq=(?<seq>\d+),ts=(?<ts>\d+),pid=(?<pid>[\-\d]+),ppid=(?<ppid>[\-\d]+)(,pgid=(?<pgid>[\-\d]+))?(,cgroup=(?<cgroup>\d+))?(,nspid=(?<nspid>[\-\d]+))?(,uid=(?<uid>\d+),gid=(?<gid>\d+))?",
+        )
+        .unwrap();
+        let badexec_regex = Regex::new(
+            r"BADEXEC: seq=(?<seq>\d+),ts=(?<ts>\d+),pid=(?<pid>[\-\d]+)(,ret=(?<ret>[\-\d]+))?",
         )
         .unwrap();
-        let badexec_regex =
-            Regex::new(r"BADEXEC: seq=(?<seq>\d+),ts=(?<ts>\d+),pid=(?<pid>[\-\d]+)").unwrap();
         let exec_filename_regex = Regex::new(
             r"EXEC_FILENAME: seq=(?<seq>\d+),ts=(?<ts>\d+),pid=(?<pid>[\-\d]+),filename=(?<filename>.*)",
         )
@@ -49,32 +73,135 @@ impl EventParser {
             r"EXEC_ARGS: seq=(?<seq>\d+),ts=(?<ts>\d+),pid=(?<pid>[\-\d]+),(?<exec_args>.*)",
         )
         .unwrap();
+        // Anchored like custom events, since an argument can contain anything
+        let exec_arg_regex = Regex::new(
+            r"^EXEC_ARG: seq=(?<seq>\d+),ts=(?<ts>\d+),pid=(?<pid>[\-\d]+),index=(?<index>\d+),arg=(?<arg>.*)",
+        )
+        .unwrap();
         let exit_regex = Regex::new(
-            r"EXIT: seq=(?<seq>\d+),ts=(?<ts>\d+),pid=(?<pid>[\-\d]+),ppid=(?<ppid>[\-\d]+),pgid=(?<pgid>[\-\d]+)",
+            r"EXIT: seq=(?<seq>\d+),ts=(?<ts>\d+),pid=(?<pid>[\-\d]+),ppid=(?<ppid>[\-\d]+)(,pgid=(?<pgid>[\-\d]+))?(,exit_code=(?<code>[\-\d]+))?",
         )
         .unwrap();
         let setsid_regex = Regex::new(
         r"SETSID: seq=(?<seq>\d+),ts=(?<ts>\d+),pid=(?<pid>[\-\d]+),ppid=(?<ppid>[\-\d]+),pgid=(?<pgid>[\-\d]+),sid=(?<sid>[\-\d]+)",
     ).unwrap();
         let setpgid_regex = Regex::new(
-        r"SETPGID: seq=(?<seq>\d+),ts=(?<ts>\d+),pid=(?<pid>[\-\d]+),ppid=(?<ppid>[\-\d]+),pgid=(?<pgid>[\-\d]+)",
+        r"SETPGID: seq=(?<seq>\d+),ts=(?<ts>\d+),pid=(?<pid>[\-\d]+),ppid=(?<ppid>[\-\d]+),pgid=(?<pgid>[\-\d]+)(,caller=(?<caller>[\-\d]+))?",
     )
     .unwrap();
+        // Anchored like custom events, since a path can contain anything
+        let chdir_regex = Regex::new(
+            r"^CHDIR: seq=(?<seq>\d+),ts=(?<ts>\d+),pid=(?<pid>[\-\d]+)(,path=(?<path>.*))?",
+        )
+        .unwrap();
+        let namespace_regex = Regex::new(
+            r"NAMESPACE: seq=(?<seq>\d+),ts=(?<ts>\d+),pid=(?<pid>[\-\d]+),how=(?<how>clone|unshare|setns),flags=(?<flags>\d+)",
+        )
+        .unwrap();
+        // Anchored like custom events, since a path can contain anything
+        let mount_regex = Regex::new(
+            r"^MOUNT: seq=(?<seq>\d+),ts=(?<ts>\d+),pid=(?<pid>[\-\d]+),op=(?<op>mount|umount),fstype=(?<fstype>[^,]*),target=(?<target>.*)",
+        )
+        .unwrap();
+        let ptrace_regex = Regex::new(
+            r"PTRACE: seq=(?<seq>\d+),ts=(?<ts>\d+),pid=(?<pid>[\-\d]+),request=(?<request>\d+),target=(?<target>[\-\d]+),ret=(?<ret>[\-\d]+)",
+        )
+        .unwrap();
+        let sandbox_regex = Regex::new(
+            r"SANDBOX: seq=(?<seq>\d+),ts=(?<ts>\d+),pid=(?<pid>[\-\d]+),mechanism=(?<mechanism>seccomp|landlock),mode=(?<mode>\d+),flags=(?<flags>\d+)",
+        )
+        .unwrap();
+        let subreaper_regex =
+            Regex::new(r"SUBREAPER: seq=(?<seq>\d+),ts=(?<ts>\d+),pid=(?<pid>[\-\d]+)").unwrap();
+        let cpu_regex =
+            Regex::new(r"^CPU: seq=(?<seq>\d+),ts=(?<ts>\d+),pid=(?<pid>[\-\d]+)").unwrap();
+        let rss_regex = Regex::new(
+            r"^RSS: seq=(?<seq>\d+),ts=(?<ts>\d+),pid=(?<pid>[\-\d]+),rss_kb=(?<rss>\d+)",
+        )
+        .unwrap();
+        let usage_regex = Regex::new(
+            r"^USAGE: seq=(?<seq>\d+),ts=(?<ts>\d+),pid=(?<pid>[\-\d]+),minor_faults=(?<minor>\d+),major_faults=(?<major>\d+),voluntary_switches=(?<voluntary>\d+),involuntary_switches=(?<involuntary>\d+),user_ns=(?<user>\d+),system_ns=(?<system>\d+)",
+        )
+        .unwrap();
+        // The name is last since it can contain commas
+        let overhead_regex = Regex::new(
+            r"^OVERHEAD: seq=(?<seq>\d+),ts=(?<ts>\d+),pid=(?<pid>[\-\d]+),minor_faults=(?<minor>\d+),major_faults=(?<major>\d+),voluntary_switches=(?<voluntary>\d+),involuntary_switches=(?<involuntary>\d+),user_ns=(?<user>\d+),system_ns=(?<system>\d+),comm=(?<comm>.*)",
+        )
+        .unwrap();
+        let host_regex = Regex::new(
+            r"HOST: seq=(?<seq>\d+),ts=(?<ts>\d+),load1=(?<load1>\d+),load5=(?<load5>\d+),load15=(?<load15>\d+),mem_total=(?<total>\d+),mem_available=(?<available>\d+)",
+        )
+        .unwrap();
+        let tick_regex = Regex::new(r"^TICK: seq=(?<seq>\d+),ts=(?<ts>\d+)").unwrap();
+        // Anchored so that the fields of a custom event can't be mistaken for
+        // another kind of event
+        let custom_regex = Regex::new(
+            r"^CUSTOM: seq=(?<seq>\d+),ts=(?<ts>\d+),pid=(?<pid>[\-\d]+),name=(?<name>[^,]+)(,(?<fields>.*))?",
+        )
+        .unwrap();
         Self {
             fork: fork_regex,
             exec: exec_regex,
             badexec: badexec_regex,
             exec_filename: exec_filename_regex,
             exec_args: exec_args_regex,
+            exec_arg: exec_arg_regex,
             exit: exit_regex,
             setsid: setsid_regex,
             setpgid: setpgid_regex,
+            chdir: chdir_regex,
+            namespace: namespace_regex,
+            mount: mount_regex,
+            ptrace: ptrace_regex,
+            sandbox: sandbox_regex,
+            subreaper: subreaper_regex,
+            cpu: cpu_regex,
+            rss: rss_regex,
+            usage: usage_regex,
+            overhead: overhead_regex,
+            host: host_regex,
+            tick: tick_regex,
+            custom: custom_regex,
         }
     }
 
+    /// Parses a `TICK` line, which `bpftrace` prints periodically so that host samples
+    /// can be taken on the same clock as the events, returning its seq and timestamp.
+    pub fn parse_tick(&self, line: impl AsRef<str>) -> Option<(u128, u128)> {
+        let caps = self.tick.captures(line.as_ref())?;
+        let seq = caps.name("seq")?.as_str().parse().ok()?;
+        let ts = caps.name("ts")?.as_str().parse().ok()?;
+        Some((seq, ts))
+    }
+
     pub fn parse_line(&self, line: impl AsRef<str>) -> Result<Event, Error> {
         let line = line.as_ref();
-        if let Some(caps) = self.fork.captures(line) {
+        if let Some(caps) = self.custom.captures(line) {
+            // Every group but the fields is required by the regex
+            let field = |name: &str| caps.name(name).unwrap().as_str();
+            // Fields are `key=value` pairs separated by commas, like everything else
+            let fields = caps
+                .name("fields")
+                .map(|fields| fields.as_str())
+                .unwrap_or_default()
+                .split(',')
+                .filter(|pair| !pair.is_empty())
+                .map(|pair| {
+                    let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                    (key.to_string(), value.to_string())
+                })
+                .collect();
+            let event = Event::Custom {
+                seq: field("seq").parse().context("failed to parse custom seq")?,
+                timestamp: field("ts")
+                    .parse()
+                    .context("failed to parse custom timestamp")?,
+                pid: field("pid").parse().context("failed to parse custom pid")?,
+                name: field("name").to_string(),
+                fields,
+            };
+            Ok(event)
+        } else if let Some(caps) = self.fork.captures(line) {
             let seq = caps
                 .name("seq")
                 .ok_or(anyhow!("FORK line had no seq: {}", line))?
@@ -91,10 +218,6 @@ impl EventParser {
                 .name("cpid")
                 .ok_or(anyhow!("FORK line had no child_pid: {}", line))?
                 .as_str();
-            let parent_pgid = caps
-                .name("pgid")
-                .ok_or(anyhow!("FORK line had no parent_pgid: {}", line))?
-                .as_str();
             let event = Event::Fork {
                 seq: seq.parse().context("failed to parse fork seq")?,
                 timestamp: ts.parse().context("failed to parse fork timestamp")?,
@@ -104,9 +227,10 @@ impl EventParser {
                 child_pid: child_pid
                     .parse()
                     .context("failed to parse fork child_pid")?,
-                parent_pgid: parent_pgid
-                    .parse()
-                    .context("failed to parse fork parent_pgid")?,
+                parent_pgid: parse_pgid(&caps).context("failed to parse fork parent_pgid")?,
+                credentials: parse_credentials(&caps)
+                    .context("failed to parse fork credentials")?,
+                comm: caps.name("comm").map(|comm| comm.as_str().to_string()),
             };
             Ok(event)
         } else if let Some(caps) = self.exec.captures(line) {
@@ -126,17 +250,25 @@ impl EventParser {
                 .name("ppid")
                 .ok_or(anyhow!("EXEC line had no ppid: {}", line))?
                 .as_str();
-            let pgid = caps
-                .name("pgid")
-                .ok_or(anyhow!("EXEC line had no pgid: {}", line))?
-                .as_str();
             let event = Event::Exec {
                 seq: seq.parse().context("failed to parse exec seq")?,
                 timestamp: ts.parse().context("failed to parse exec timestamp")?,
                 pid: pid.parse().context("failed to parse exec pid")?,
                 ppid: ppid.parse().context("failed to parse exec ppid")?,
-                pgid: pgid.parse().context("failed to parse exec pgid")?,
+                pgid: parse_pgid(&caps).context("failed to parse exec pgid")?,
                 cmdline: None,
+                cgroup: caps
+                    .name("cgroup")
+                    .map(|cgroup| cgroup.as_str().parse())
+                    .transpose()
+                    .context("failed to parse exec cgroup")?,
+                ns_pid: caps
+                    .name("nspid")
+                    .map(|ns_pid| ns_pid.as_str().parse())
+                    .transpose()
+                    .context("failed to parse exec namespace pid")?,
+                credentials: parse_credentials(&caps)
+                    .context("failed to parse exec credentials")?,
             };
             Ok(event)
         } else if let Some(caps) = self.badexec.captures(line) {
@@ -152,10 +284,18 @@ impl EventParser {
                 .name("pid")
                 .ok_or(anyhow!("BADEXEC line had no pid: {}", line))?
                 .as_str();
+            let ret: Option<i32> = caps
+                .name("ret")
+                .map(|ret| ret.as_str().parse())
+                .transpose()
+                .context("failed to parse badexec return value")?;
             let event = Event::BadExec {
                 seq: seq.parse().context("failed to parse badexec seq")?,
                 timestamp: ts.parse().context("failed to parse badexec timestamp")?,
                 pid: pid.parse().context("failed to parse badexec pid")?,
+                filename: None,
+                // The script also reports successful execs whose start it missed
+                errno: ret.filter(|ret| *ret < 0).map(|ret| -ret),
             };
             Ok(event)
         } else if let Some(caps) = self.exec_filename.captures(line) {
@@ -206,6 +346,25 @@ impl EventParser {
                 args: ExecArgsKind::Joined(args.parse().context("failed to parse exec args")?),
             };
             Ok(event)
+        } else if let Some(caps) = self.exec_arg.captures(line) {
+            // Every group is required by the regex
+            let field = |name: &str| caps.name(name).unwrap().as_str();
+            let event = Event::ExecArg {
+                seq: field("seq")
+                    .parse()
+                    .context("failed to parse exec arg seq")?,
+                timestamp: field("ts")
+                    .parse()
+                    .context("failed to parse exec arg timestamp")?,
+                pid: field("pid")
+                    .parse()
+                    .context("failed to parse exec arg pid")?,
+                index: field("index")
+                    .parse()
+                    .context("failed to parse exec arg index")?,
+                arg: field("arg").to_string(),
+            };
+            Ok(event)
         } else if let Some(caps) = self.exit.captures(line) {
             let seq = caps
                 .name("seq")
@@ -223,16 +382,18 @@ impl EventParser {
                 .name("ppid")
                 .ok_or(anyhow!("EXIT line had no ppid: {}", line))?
                 .as_str();
-            let pgid = caps
-                .name("pgid")
-                .ok_or(anyhow!("EXIT line had no pgid: {}", line))?
-                .as_str();
             let event = Event::Exit {
                 seq: seq.parse().context("failed to parse exit seq")?,
                 timestamp: ts.parse().context("failed to parse exit timestamp")?,
                 pid: pid.parse().context("failed to parse exit pid")?,
                 ppid: ppid.parse().context("failed to parse exit ppid")?,
-                pgid: pgid.parse().context("failed to parse exit pgid")?,
+                pgid: parse_pgid(&caps).context("failed to parse exit pgid")?,
+                // Older versions of the script didn't record the exit code
+                exit_code: caps
+                    .name("code")
+                    .map(|code| code.as_str().parse())
+                    .transpose()
+                    .context("failed to parse exit code")?,
             };
             Ok(event)
         } else if let Some(caps) = self.setsid.captures(line) {
@@ -290,12 +451,222 @@ impl EventParser {
                 .name("pgid")
                 .ok_or(anyhow!("SETPGID line had no pgid: {}", line))?
                 .as_str();
+            let pid = pid.parse().context("failed to parse setpgid pid")?;
+            // Older recordings don't say who made the call, which was always `pid`
+            let caller_pid = caps
+                .name("caller")
+                .map(|caller| caller.as_str().parse::<i32>())
+                .transpose()
+                .context("failed to parse setpgid caller")?
+                .filter(|caller| *caller != pid);
             let event = Event::SetPGID {
                 seq: seq.parse().context("failed to parse setpgid seq")?,
                 timestamp: ts.parse().context("failed to parse setpgid timestamp")?,
-                pid: pid.parse().context("failed to parse setpgid pid")?,
+                pid,
                 ppid: ppid.parse().context("failed to parse setpgid ppid")?,
                 pgid: pgid.parse().context("failed to parse setpgid pgid")?,
+                caller_pid,
+            };
+            Ok(event)
+        } else if let Some(caps) = self.chdir.captures(line) {
+            // Every group but the path is required by the regex
+            let field = |name: &str| caps.name(name).unwrap().as_str();
+            let event = Event::Chdir {
+                seq: field("seq").parse().context("failed to parse chdir seq")?,
+                timestamp: field("ts")
+                    .parse()
+                    .context("failed to parse chdir timestamp")?,
+                pid: field("pid").parse().context("failed to parse chdir pid")?,
+                path: caps.name("path").map(|path| path.as_str().to_string()),
+            };
+            Ok(event)
+        } else if let Some(caps) = self.namespace.captures(line) {
+            // Every group is required by the regex, so they're all present
+            let field = |name: &str| caps.name(name).unwrap().as_str();
+            let how = match field("how") {
+                "clone" => NamespaceChange::Clone,
+                "unshare" => NamespaceChange::Unshare,
+                _ => NamespaceChange::Setns,
+            };
+            let event = Event::Namespace {
+                seq: field("seq")
+                    .parse()
+                    .context("failed to parse namespace seq")?,
+                timestamp: field("ts")
+                    .parse()
+                    .context("failed to parse namespace timestamp")?,
+                pid: field("pid")
+                    .parse()
+                    .context("failed to parse namespace pid")?,
+                how,
+                namespaces: namespace_names(
+                    field("flags")
+                        .parse()
+                        .context("failed to parse namespace flags")?,
+                ),
+            };
+            Ok(event)
+        } else if let Some(caps) = self.mount.captures(line) {
+            // Every group is required by the regex, so they're all present
+            let field = |name: &str| caps.name(name).unwrap().as_str();
+            let event = Event::Mount {
+                seq: field("seq").parse().context("failed to parse mount seq")?,
+                timestamp: field("ts")
+                    .parse()
+                    .context("failed to parse mount timestamp")?,
+                pid: field("pid").parse().context("failed to parse mount pid")?,
+                target: field("target").to_string(),
+                fstype: Some(field("fstype"))
+                    .filter(|fstype| !fstype.is_empty())
+                    .map(String::from),
+                unmount: field("op") == "umount",
+            };
+            Ok(event)
+        } else if let Some(caps) = self.ptrace.captures(line) {
+            // Every group is required by the regex, so they're all present
+            let field = |name: &str| caps.name(name).unwrap().as_str();
+            let request = field("request")
+                .parse()
+                .context("failed to parse ptrace request")?;
+            let request = PtraceRequest::from_number(request)
+                .ok_or_else(|| anyhow!("unexpected ptrace request {request}: {line}"))?;
+            let ret: i32 = field("ret").parse().context("failed to parse ptrace ret")?;
+            let event = Event::Ptrace {
+                seq: field("seq").parse().context("failed to parse ptrace seq")?,
+                timestamp: field("ts")
+                    .parse()
+                    .context("failed to parse ptrace timestamp")?,
+                pid: field("pid").parse().context("failed to parse ptrace pid")?,
+                request,
+                // The target is ignored by `traceme`
+                target_pid: Some(
+                    field("target")
+                        .parse()
+                        .context("failed to parse ptrace target")?,
+                )
+                .filter(|_| request != PtraceRequest::Traceme),
+                errno: (ret < 0).then_some(-ret),
+            };
+            Ok(event)
+        } else if let Some(caps) = self.sandbox.captures(line) {
+            // Every group is required by the regex, so they're all present
+            let field = |name: &str| caps.name(name).unwrap().as_str();
+            let mechanism = if field("mechanism") == "seccomp" {
+                SandboxMechanism::Seccomp
+            } else {
+                SandboxMechanism::Landlock
+            };
+            // The mode is the operation of seccomp(2)
+            let mode = match (mechanism, field("mode")) {
+                (SandboxMechanism::Landlock, _) => None,
+                (_, "0") => Some("strict".to_string()),
+                (_, "1") => Some("filter".to_string()),
+                (_, mode) => return Err(anyhow!("unexpected seccomp mode {mode}: {line}")),
+            };
+            let flags = field("flags")
+                .parse()
+                .context("failed to parse sandbox flags")?;
+            let event = Event::Sandbox {
+                seq: field("seq")
+                    .parse()
+                    .context("failed to parse sandbox seq")?,
+                timestamp: field("ts")
+                    .parse()
+                    .context("failed to parse sandbox timestamp")?,
+                pid: field("pid")
+                    .parse()
+                    .context("failed to parse sandbox pid")?,
+                mechanism,
+                mode,
+                flags: sandbox_flag_names(mechanism, flags),
+            };
+            Ok(event)
+        } else if let Some(caps) = self.subreaper.captures(line) {
+            // Every group is required by the regex, so they're all present
+            let field = |name: &str| caps.name(name).unwrap().as_str();
+            let event = Event::Subreaper {
+                seq: field("seq")
+                    .parse()
+                    .context("failed to parse subreaper seq")?,
+                timestamp: field("ts")
+                    .parse()
+                    .context("failed to parse subreaper timestamp")?,
+                pid: field("pid")
+                    .parse()
+                    .context("failed to parse subreaper pid")?,
+            };
+            Ok(event)
+        } else if let Some(caps) = self.cpu.captures(line) {
+            // Every group is required by the regex, so they're all present
+            let field = |name: &str| caps.name(name).unwrap().as_str();
+            let event = Event::CpuSample {
+                seq: field("seq").parse().context("failed to parse cpu seq")?,
+                timestamp: field("ts")
+                    .parse()
+                    .context("failed to parse cpu timestamp")?,
+                pid: field("pid").parse().context("failed to parse cpu pid")?,
+            };
+            Ok(event)
+        } else if let Some(caps) = self.rss.captures(line) {
+            // Every group is required by the regex, so they're all present
+            let field = |name: &str| caps.name(name).unwrap().as_str();
+            let event = Event::RssSample {
+                seq: field("seq").parse().context("failed to parse rss seq")?,
+                timestamp: field("ts")
+                    .parse()
+                    .context("failed to parse rss timestamp")?,
+                pid: field("pid").parse().context("failed to parse rss pid")?,
+                rss_kb: field("rss").parse().context("failed to parse rss")?,
+            };
+            Ok(event)
+        } else if let Some(caps) = self.usage.captures(line) {
+            // Every group is required by the regex, so they're all present
+            let field = |name: &str| caps.name(name).unwrap().as_str();
+            let event = Event::Usage {
+                seq: field("seq").parse().context("failed to parse usage seq")?,
+                timestamp: field("ts")
+                    .parse()
+                    .context("failed to parse usage timestamp")?,
+                pid: field("pid").parse().context("failed to parse usage pid")?,
+                usage: parse_usage(&caps)?,
+            };
+            Ok(event)
+        } else if let Some(caps) = self.overhead.captures(line) {
+            // Every group is required by the regex, so they're all present
+            let field = |name: &str| caps.name(name).unwrap().as_str();
+            let event = Event::Overhead {
+                seq: field("seq")
+                    .parse()
+                    .context("failed to parse overhead seq")?,
+                timestamp: field("ts")
+                    .parse()
+                    .context("failed to parse overhead timestamp")?,
+                pid: field("pid")
+                    .parse()
+                    .context("failed to parse overhead pid")?,
+                command: field("comm").to_string(),
+                usage: parse_usage(&caps)?,
+            };
+            Ok(event)
+        } else if let Some(caps) = self.host.captures(line) {
+            // Every group is required by the regex, so they're all present
+            let field = |name: &str| caps.name(name).unwrap().as_str();
+            let event = Event::HostSample {
+                seq: field("seq").parse().context("failed to parse host seq")?,
+                timestamp: field("ts")
+                    .parse()
+                    .context("failed to parse host timestamp")?,
+                load: [
+                    field("load1").parse().context("failed to parse load1")?,
+                    field("load5").parse().context("failed to parse load5")?,
+                    field("load15").parse().context("failed to parse load15")?,
+                ],
+                mem_total_kb: field("total")
+                    .parse()
+                    .context("failed to parse total memory")?,
+                mem_available_kb: field("available")
+                    .parse()
+                    .context("failed to parse available memory")?,
             };
             Ok(event)
         } else {
@@ -304,6 +675,136 @@ impl EventParser {
     }
 }
 
+/// Parses the resource usage of a USAGE or OVERHEAD line, whose groups are required by
+/// their regexes.
+fn parse_usage(caps: &regex_lite::Captures) -> Result<ResourceUsage, Error> {
+    let field = |name: &str| caps.name(name).unwrap().as_str();
+    Ok(ResourceUsage {
+        minor_faults: field("minor")
+            .parse()
+            .context("failed to parse minor faults")?,
+        major_faults: field("major")
+            .parse()
+            .context("failed to parse major faults")?,
+        voluntary_switches: field("voluntary")
+            .parse()
+            .context("failed to parse voluntary context switches")?,
+        involuntary_switches: field("involuntary")
+            .parse()
+            .context("failed to parse involuntary context switches")?,
+        user_ns: field("user").parse().context("failed to parse user time")?,
+        system_ns: field("system")
+            .parse()
+            .context("failed to parse system time")?,
+    })
+}
+
+/// Parses the process group of a FORK, EXEC, or EXIT line, which backends that can't
+/// tell which group a process was in leave out.
+fn parse_pgid(caps: &regex_lite::Captures) -> Result<i32, Error> {
+    match caps.name("pgid") {
+        Some(pgid) => Ok(pgid.as_str().parse()?),
+        None => Ok(UNKNOWN_PGID),
+    }
+}
+
+/// Parses the optional `uid` and `gid` of a FORK or EXEC line, which older versions of
+/// the script didn't print.
+fn parse_credentials(caps: &regex_lite::Captures) -> Result<Option<Credentials>, Error> {
+    let (Some(uid), Some(gid)) = (caps.name("uid"), caps.name("gid")) else {
+        return Ok(None);
+    };
+    Ok(Some(Credentials {
+        uid: uid.as_str().parse()?,
+        gid: gid.as_str().parse()?,
+    }))
+}
+
+/// Which processes outside of the root's process tree should be recorded too.
+#[derive(Debug, Clone, Default)]
+pub struct Adoption {
+    /// Adopt processes that exec something matching one of these patterns.
+    pub exec: Vec<Regex>,
+    /// Adopt processes that exec while in one of these cgroups.
+    pub cgroups: Vec<u64>,
+    /// Adopt the cgroup that the root PID moves into before it execs.
+    pub follow_root_cgroup: bool,
+    /// Record the children of processes outside of the tree that match one of these
+    /// as children of the root.
+    pub parents: Vec<ParentMatcher>,
+}
+
+/// A process outside of the root's process tree whose children are recorded as
+/// children of the root, e.g. a daemon that the recorded command hands work to.
+#[derive(Debug, Clone)]
+pub enum ParentMatcher {
+    /// The process with this PID.
+    Pid(i32),
+    /// Processes whose name, or the last thing they exec'd, matches this pattern.
+    Command(Regex),
+}
+
+impl std::str::FromStr for ParentMatcher {
+    type Err = Error;
+
+    /// Parses a PID, or a pattern if it isn't one.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse() {
+            Ok(pid) => Ok(Self::Pid(pid)),
+            Err(_) => Regex::new(s)
+                .map(Self::Command)
+                .with_context(|| format!("invalid parent pattern '{s}'")),
+        }
+    }
+}
+
+/// A process that may have been meant as the root of the process tree, suggested when
+/// the root PID never shows up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RootCandidate {
+    pub pid: i32,
+    /// The number of processes in its process tree, including itself.
+    pub processes: usize,
+    /// The first thing it exec'd, if it exec'd anything.
+    pub command: Option<String>,
+}
+
+impl std::fmt::Display for RootCandidate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let plural = if self.processes == 1 { "" } else { "es" };
+        write!(f, "PID {} ({} process{plural})", self.pid, self.processes)?;
+        if let Some(ref command) = self.command {
+            write!(f, ": {command}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A writer for the events that never joined the process tree.
+struct UnmatchedSink {
+    writer: Box<dyn EventWrite>,
+    /// The number of events written so far.
+    written: usize,
+}
+
+impl UnmatchedSink {
+    fn write_events(&mut self, events: &[Event]) -> Result<(), Error> {
+        for event in events {
+            self.writer.write_event(event)?;
+        }
+        self.written += events.len();
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for UnmatchedSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UnmatchedSink")
+            .field("written", &self.written)
+            .finish_non_exhaustive()
+    }
+}
+
 #[derive(Debug)]
 pub struct EventIngester<T> {
     /// The PID that will be the root of the process tree.
@@ -312,6 +813,73 @@ pub struct EventIngester<T> {
     tracked_events: EventStore,
     /// Events that we are unsure about being part of the process tree.
     buffered_events: EventStore,
+    /// The buffered children of each PID, taken from buffered fork events.
+    buffered_children: HashMap<i32, Vec<i32>>,
+    /// PIDs whose buffered events (and the buffered events of their descendants)
+    /// should be moved into the process tree the next time the buffer is drained.
+    pids_to_unbuffer: Vec<i32>,
+    /// Whether to keep every tracked event, or only the latest event for each PID
+    /// once it has been written.
+    retain_events: bool,
+    /// PIDs outside of the root's process tree that are tracked along with their
+    /// descendants, in the order they were adopted.
+    adopted_pids: Vec<i32>,
+    /// Processes that exec something matching one of these patterns are adopted.
+    adopt_exec: Vec<Regex>,
+    /// Processes that exec while in one of these cgroups are adopted.
+    adopt_cgroups: Vec<u64>,
+    /// The children of out-of-tree processes that match one of these are recorded as
+    /// children of the root.
+    adopt_parents: Vec<ParentMatcher>,
+    /// The actual parents of the processes that were recorded as children of the
+    /// root because of `adopt_parents`, keyed by PID.
+    reparented: BTreeMap<i32, i32>,
+    /// Whether to adopt the cgroup that the root PID moves into, see
+    /// [`EventIngester::follow_root_cgroup`].
+    follow_root_cgroup: bool,
+    /// The cgroup of the root PID's first exec.
+    launcher_cgroup: Option<u64>,
+    /// The PIDs of tracked processes in their own PID namespace, for processes where
+    /// it differs from their PID on the host.
+    ns_pids: BTreeMap<i32, i32>,
+    /// Samples of how busy the host was and the overhead of the recorder, which aren't
+    /// tied to any recorded process.
+    host_samples: Vec<Event>,
+    /// Whether to keep recording processes after they start a new session.
+    follow_daemons: bool,
+    /// Processes that started a new session while `follow_daemons` was off, and
+    /// their descendants, whose events are dropped.
+    detached_pids: HashSet<i32>,
+    /// Tracked processes that made themselves child subreapers.
+    subreaper_pids: HashSet<i32>,
+    /// Processes forked under a subreaper after it became one, which can't detach
+    /// from the process tree since they'd be reparented to the subreaper.
+    reaped_pids: HashSet<i32>,
+    /// Watches the tracked forks for fork storms, if enabled.
+    storm_detector: Option<StormDetector>,
+    /// Warnings about fork storms, which aren't tied to any process either.
+    fork_storms: Vec<Event>,
+    /// The fraction of the root's children whose subtrees are recorded, if only a
+    /// sample of them is.
+    sample_trees: Option<SampleRatio>,
+    /// Mixed into the random draw for each child of the root.
+    sample_seed: u64,
+    /// Processes in subtrees that were left out of the sample, whose events are
+    /// dropped.
+    sampled_out_pids: HashSet<i32>,
+    /// Whether the root process is a shell that only wraps the recorded command.
+    shell_wrapper: bool,
+    /// The metadata of the recording that the events were read from, if it had any.
+    metadata: Option<Metadata>,
+    /// The number of events whose timestamps went backwards and were repaired.
+    adjusted_timestamps: u64,
+    /// How long, in nanoseconds of event time, a PID stays buffered before it's given
+    /// up on, if ever.
+    buffer_window: Option<u128>,
+    /// The timestamp of the last time the buffer was swept for expired PIDs.
+    last_buffer_sweep: u128,
+    /// Where the events of PIDs that never joined the process tree are written.
+    unmatched_sink: Option<UnmatchedSink>,
     /// The writer for events and raw output.
     pub(crate) writer: Option<T>,
 }
@@ -341,227 +909,865 @@ impl<T> EventIngester<T> {
         }
     }
 
-    /// Returns the configured `root_pid` if one has been set.
-    #[allow(dead_code)]
-    pub fn root_pid(&self) -> Option<i32> {
-        self.root_pid
-    }
-
-    /// Returns `Some(true)` if the event is the initial fork of the process at the root
-    /// of the process tree or `Some(false)` if it isn't. Returns `None` if the root pid
-    /// has not yet been set.
-    fn is_initial_fork(&self, event: &Event) -> Option<bool> {
-        self.root_pid
-            .as_ref()
-            .map(|pid| (event.pid() == *pid) && event.is_fork())
-    }
-
-    /// Returns `true` if we've seen the initial fork of the process at the root
-    /// of the process tree. Returns `false` if the root pid is unset or it's set
-    /// and we haven't been the fork.
-    #[allow(dead_code)]
-    fn have_seen_initial_fork(&self) -> bool {
-        !self.tracked_events.is_empty()
+    /// Configures which processes outside of the root's process tree are adopted.
+    pub fn set_adoption(&mut self, adoption: Adoption) {
+        for pattern in adoption.exec {
+            self.adopt_execs_matching(pattern);
+        }
+        for cgroup in adoption.cgroups {
+            self.adopt_cgroup(cgroup);
+        }
+        if adoption.follow_root_cgroup {
+            self.follow_root_cgroup();
+        }
+        for matcher in adoption.parents {
+            self.adopt_children_of(matcher);
+        }
     }
 
-    /// Adds the event to the backlog of outstanding events that we've seen and
-    /// might want to keep.
-    fn buffer_event(&mut self, event: &Event) {
-        self.buffered_events.add(event.pid(), event);
+    /// Marks the root process as a shell that only wraps the recorded command, see
+    /// `record --shell`.
+    pub fn mark_shell_wrapper(&mut self) {
+        self.shell_wrapper = true;
     }
 
-    /// Adds the event to the tracked process tree.
-    fn store_event(&mut self, event: &Event) {
-        self.tracked_events.add(event.pid(), event);
+    /// Returns `true` if the root process is a shell that only wraps the recorded
+    /// command.
+    pub fn has_shell_wrapper(&self) -> bool {
+        self.shell_wrapper
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.tracked_events.is_empty()
+    /// Keeps the metadata of the recording that the events are read from.
+    pub fn set_metadata(&mut self, metadata: Metadata) {
+        self.metadata = Some(metadata);
     }
 
-    pub fn prepare_for_rendering(&mut self) {
-        self.tracked_events.collapse_execs();
+    /// Returns the metadata of the recording that the events were read from, if it
+    /// had any.
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
     }
 
-    pub fn post_process_buffers(&mut self) {
-        self.tracked_events.post_process_buffers();
+    /// Returns the PIDs that were adopted into the recording, in the order they
+    /// were adopted.
+    pub fn adopted_pids(&self) -> &[i32] {
+        &self.adopted_pids
     }
-}
 
-impl<T: EventWrite> EventIngester<T> {
-    /// Create a new ingester.
+    /// Adopt any process that execs something whose file name or arguments match
+    /// `pattern`, along with its descendants.
     ///
-    /// If initialized without a `root_pid` it will buffer events until one is set.
-    /// If initialized with a writer, events will be written to it as they are identified
-    /// to be part of the process tree rooted at `root_pid`.
-    pub fn new(root_pid: Option<i32>, writer: Option<T>) -> Self {
-        Self {
-            root_pid,
-            tracked_events: EventStore::new(),
-            buffered_events: EventStore::new(),
-            writer,
-        }
+    /// This is for processes that are part of the work being recorded but aren't
+    /// descendants of the root, e.g. builders forked by a daemon.
+    pub fn adopt_execs_matching(&mut self, pattern: Regex) {
+        self.adopt_exec.push(pattern);
     }
 
-    /// Write a line of raw output from the script.
-    pub fn write_raw(&mut self, line: &str) -> Result<(), Error> {
-        if let Some(ref mut writer) = self.writer {
-            writer.write_raw(line)?;
+    /// Track a PID and its descendants even though it isn't part of the root's
+    /// process tree.
+    pub fn adopt_pid(&mut self, pid: i32) {
+        if !self.adopted_pids.contains(&pid) && !self.tracked_events.pid_is_tracked(pid) {
+            self.adopted_pids.push(pid);
+            self.pids_to_unbuffer.push(pid);
         }
-        Ok(())
     }
 
-    /// Walk the buffer collecting any new PIDs to track and writing out any buffered
-    /// events that belong to new PIDs to track.
+    /// Record the children that processes outside of the tree matching `matcher` fork
+    /// from now on as children of the root, along with their descendants.
     ///
-    /// If this ingester has not been configured with a writer, the events will be stored
-    /// internally but they won't be written anywhere.
-    fn drain_buffer(&mut self) -> Result<(), Error> {
-        // Grab any PIDs that are already tracked or that are direct children of PIDs that are already
-        // tracked.
-        let pids_currently_tracked = self.tracked_events.pids();
-        let pids_currently_buffered = self.buffered_events.pids();
-
-        let mut pids_to_unbuffer = HashSet::new();
-        for pid in pids_currently_buffered.iter() {
-            // Mark this PID to unbuffer if it's the child of a currently tracked PID,
-            // or if the PID is already tracked.
-            if let Some(parent_pid) = self.buffered_events.parent_of_pid_if_stored(*pid) {
-                if pids_currently_tracked.contains(&parent_pid) {
-                    pids_to_unbuffer.insert(pid);
-                }
-            } else if pids_currently_tracked.contains(pid) {
-                pids_to_unbuffer.insert(pid);
-            }
-        }
+    /// Unlike adopted PIDs, which are rendered as roots of their own, these show up
+    /// under the root as if it had forked them, e.g. builds that `nix-daemon` runs for
+    /// the recorded `nix build`.
+    pub fn adopt_children_of(&mut self, matcher: ParentMatcher) {
+        self.adopt_parents.push(matcher);
+    }
 
-        // At this point we've marked buffered PIDs that are children of already tracked PIDs,
-        // but the buffer may also contain children of those children, etc, so we need to iteratively
-        // mark PIDs that form a parent-child relationship with other marked PIDs.
+    /// Returns the actual parents of the processes that were recorded as children of
+    /// the root, see [`EventIngester::adopt_children_of`], keyed by PID.
+    pub fn reparented(&self) -> &BTreeMap<i32, i32> {
+        &self.reparented
+    }
 
-        loop {
-            let more_to_unbuffer = {
-                let mut more = HashSet::new();
-                for pid in pids_currently_buffered.iter() {
-                    if pids_to_unbuffer.contains(pid) {
-                        // We've already recorded this PID
-                        continue;
-                    }
-                    // If the parent is already tracked or has been recorded, record the child.
-                    if let Some(parent_pid) = self.buffered_events.parent_of_pid_if_stored(*pid) {
-                        if pids_currently_tracked.contains(&parent_pid)
-                            || pids_to_unbuffer.contains(&parent_pid)
-                        {
-                            more.insert(pid);
-                        }
-                    } else if pids_currently_tracked.contains(pid) {
-                        more.insert(pid);
-                    }
-                }
-                more
-            };
-            if more_to_unbuffer.is_empty() {
-                break;
-            } else {
-                for pid in more_to_unbuffer.iter() {
-                    pids_to_unbuffer.insert(*pid);
-                }
-            }
+    /// Adopt any process that execs while it's in the cgroup with this ID.
+    pub fn adopt_cgroup(&mut self, cgroup: u64) {
+        if !self.adopt_cgroups.contains(&cgroup) {
+            self.adopt_cgroups.push(cgroup);
         }
+    }
 
-        // Now that we know which PIDs to drain from the store, remove those individual
-        // event buffers so we can write out their events.
-        let mut drained_events = vec![];
-        for pid in pids_to_unbuffer.iter() {
-            let buffer = self
-                .buffered_events
-                .remove(**pid)
-                .ok_or(anyhow!("buffered PID {pid} not found"))?;
-            drained_events.push((*pid, buffer));
-        }
-        drained_events.sort_by_key(|(_, events)| {
-            events
-                .front()
-                .expect("expected events but found none")
-                .timestamp()
-        });
-        // Track this pid from now on
-        for (pid, events) in drained_events.iter() {
-            self.tracked_events.add_many(**pid, events.iter());
-        }
+    /// Adopt the cgroup that the root PID is in when it execs, if it's different
+    /// from the cgroup of its first exec.
+    ///
+    /// This is for launchers like `systemd-run --scope` that move themselves into
+    /// a new cgroup before exec'ing the command, so that processes that escape the
+    /// process tree (e.g. daemons started via a socket) are still recorded.
+    pub fn follow_root_cgroup(&mut self) {
+        self.follow_root_cgroup = true;
+    }
 
-        Ok(())
+    /// Returns the PIDs of tracked processes in their own PID namespace, keyed by
+    /// their PID on the host, for processes running in a container.
+    pub fn ns_pids(&self) -> &BTreeMap<i32, i32> {
+        &self.ns_pids
     }
 
-    pub fn observe_event(&mut self, event: &Event) -> Result<(), Error> {
-        if self.tracked_events.pid_is_tracked(event.pid()) {
-            // We're already tracking this PID, so just store the latest event
-            self.store_event(event);
-        } else if self.is_initial_fork(event).unwrap_or(false) {
-            // We aren't tracking any PIDs yet, and this will be the first
-            self.store_event(event);
-        } else {
-            // We can't tell if we need this event yet, so buffer it and maybe
-            // it will get drained later.
-            // TODO: decide on a garbage collection scheme for these events
-            self.buffer_event(event);
-        }
-        self.drain_buffer()?;
-        Ok(())
+    /// Returns the host samples in the order they were observed.
+    pub fn host_samples(&self) -> &[Event] {
+        &self.host_samples
     }
-}
 
-#[derive(Debug, Default)]
-struct ExecState {
-    exec_filename: Option<Event>,
-    exec_args: Option<Event>,
-    exec: Option<Event>,
-}
+    /// Returns the resources used by the recorder's own processes, see
+    /// [`Event::Overhead`].
+    pub fn overhead(&self) -> impl Iterator<Item = &Event> {
+        self.host_samples.iter().filter(|event| event.is_overhead())
+    }
 
-impl std::fmt::Display for ExecState {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let filename = if self.exec_filename.is_some() {
-            "some"
-        } else {
-            "none"
-        };
-        let args = if self.exec_args.is_some() {
-            "some"
-        } else {
-            "none"
-        };
-        let exec = if self.exec.is_some() { "some" } else { "none" };
-        write!(f, "ExecState(filename:{filename},args:{args},exec:{exec})")
+    /// Returns the fork storm warnings in the order they were raised.
+    pub fn fork_storms(&self) -> &[Event] {
+        &self.fork_storms
     }
-}
 
-impl ExecState {
-    fn is_empty(&self) -> bool {
-        self.exec_filename.is_none() && self.exec_args.is_none() && self.exec.is_none()
+    /// Warn about subtrees of the process tree that fork faster than `detector`
+    /// allows.
+    ///
+    /// A `ForkStorm` event is written and stored when a storm is detected.
+    pub fn detect_storms(&mut self, detector: StormDetector) {
+        self.storm_detector = Some(detector);
     }
 
-    fn is_full(&self) -> bool {
-        self.exec_filename.is_some() && self.exec_args.is_some() && self.exec.is_some()
+    /// Stop recording processes once they start a new session, which is how
+    /// daemons detach from the process that started them.
+    ///
+    /// The new session is recorded, but nothing after it is, and the process
+    /// isn't waited on.
+    pub fn stop_following_daemons(&mut self) {
+        self.follow_daemons = false;
     }
 
-    fn clear(&mut self) {
-        self.exec_filename = None;
-        self.exec_args = None;
-        self.exec = None;
+    /// Only record the subtrees of a random `ratio` of the root's children, with every
+    /// event of the subtrees that are chosen.
+    ///
+    /// Whether a child is chosen depends on `seed` and the sequence number of its fork,
+    /// so ingesting the same events with the same seed chooses the same subtrees.
+    pub fn sample_trees(&mut self, ratio: SampleRatio, seed: u64) {
+        self.sample_trees = Some(ratio);
+        self.sample_seed = seed;
     }
 
-    fn ready_for_args(&self) -> bool {
-        self.exec_filename.is_some() && self.exec_args.is_none() && self.exec.is_none()
+    /// Returns the PIDs of tracked processes that haven't exited yet, excluding
+    /// ones that detached and are no longer followed.
+    pub fn unfinished_pids(&self) -> Vec<i32> {
+        self.tracked_events
+            .unfinished_pids()
+            .filter(|pid| !self.detached_pids.contains(pid))
+            .collect()
     }
 
-    fn ready_for_exec(&self) -> bool {
-        self.exec_filename.is_some() && self.exec_args.is_some() && self.exec.is_none()
+    /// Returns the IDs of the cgroups whose processes are adopted.
+    pub fn adopted_cgroups(&self) -> &[u64] {
+        &self.adopt_cgroups
     }
 
-    fn to_exec_full(&mut self) -> Event {
-        let Event::ExecFilename { filename, .. } = self.exec_filename.take().unwrap() else {
-            panic!("expected exec_filename event");
-        };
+    /// Starts adopting the root PID's new cgroup if it has moved into one.
+    fn check_root_cgroup(&mut self, event: &Event) {
+        let Event::Exec {
+            pid,
+            cgroup: Some(cgroup),
+            ..
+        } = event
+        else {
+            return;
+        };
+        if !self.follow_root_cgroup || Some(*pid) != self.root_pid {
+            return;
+        }
+        match self.launcher_cgroup {
+            None => self.launcher_cgroup = Some(*cgroup),
+            Some(launcher) if launcher != *cgroup => self.adopt_cgroup(*cgroup),
+            Some(_) => {}
+        }
+    }
+
+    /// Returns `true` if the event is an exec that matches one of the adoption patterns
+    /// or happened in one of the adopted cgroups.
+    fn should_adopt(&self, event: &Event) -> bool {
+        if let Event::Exec {
+            cgroup: Some(cgroup),
+            ..
+        } = event
+        {
+            if self.adopt_cgroups.contains(cgroup) {
+                return true;
+            }
+        }
+        if self.adopt_exec.is_empty() {
+            return false;
+        }
+        let matches = |text: &str| self.adopt_exec.iter().any(|re| re.is_match(text));
+        match event {
+            Event::ExecFilename { filename, .. } => matches(filename),
+            Event::ExecArgs { args, .. } => matches(&args.joined()),
+            // Arguments printed one at a time are only matched one at a time
+            Event::ExecArg { arg, .. } => matches(arg),
+            Event::ExecFull { filename, args, .. } => matches(filename) || matches(&args.joined()),
+            Event::Exec {
+                cmdline: Some(args),
+                ..
+            } => matches(&args.joined()),
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the event is a fork by a process outside of the tree whose
+    /// children are recorded as children of the root.
+    fn is_fork_by_adopted_parent(&self, event: &Event) -> bool {
+        let Event::Fork {
+            parent_pid, comm, ..
+        } = event
+        else {
+            return false;
+        };
+        let tracked =
+            |pid: i32| self.tracked_events.pid_is_tracked(pid) || self.adopted_pids.contains(&pid);
+        if self.adopt_parents.is_empty()
+            || tracked(*parent_pid)
+            || tracked(event.pid())
+            || !self
+                .root_pid
+                .is_some_and(|root| self.tracked_events.pid_is_tracked(root))
+        {
+            return false;
+        }
+        // A forked child has the name of its parent until it execs
+        let command = self
+            .buffered_events
+            .events_of(*parent_pid)
+            .and_then(|buffer| {
+                buffer.iter().rev().find_map(|event| match event {
+                    Event::ExecFull { args, .. } => Some(args.joined()),
+                    Event::Exec {
+                        cmdline: Some(args),
+                        ..
+                    } => Some(args.joined()),
+                    Event::ExecFilename { filename, .. } => Some(filename.clone()),
+                    _ => None,
+                })
+            });
+        self.adopt_parents.iter().any(|matcher| match matcher {
+            ParentMatcher::Pid(pid) => pid == parent_pid,
+            ParentMatcher::Command(pattern) => {
+                comm.as_deref().is_some_and(|comm| pattern.is_match(comm))
+                    || command
+                        .as_deref()
+                        .is_some_and(|command| pattern.is_match(command))
+            }
+        })
+    }
+
+    /// Returns `true` if the event belongs to a process that detached, marking
+    /// children that it forks and tracked processes that start a new session as
+    /// detached too.
+    ///
+    /// Processes under a tracked child subreaper don't detach by starting a new
+    /// session, since they're reparented to the subreaper rather than leaving the tree.
+    fn is_detached(&mut self, event: &Event) -> bool {
+        if let Some(parent_pid) = event.fork_parent() {
+            if self.detached_pids.contains(&parent_pid) {
+                self.detached_pids.insert(event.pid());
+                return true;
+            }
+            if self.subreaper_pids.contains(&parent_pid) || self.reaped_pids.contains(&parent_pid) {
+                self.reaped_pids.insert(event.pid());
+            }
+        }
+        if self.detached_pids.contains(&event.pid()) {
+            return true;
+        }
+        match event {
+            Event::Subreaper { pid, .. } if self.tracked_events.pid_is_tracked(*pid) => {
+                self.subreaper_pids.insert(*pid);
+            }
+            // The root starting a new session doesn't detach it from anything
+            Event::SetSID { pid, .. }
+                if Some(*pid) != self.root_pid
+                    && self.tracked_events.pid_is_tracked(*pid)
+                    && !self.reaped_pids.contains(pid) =>
+            {
+                self.detached_pids.insert(*pid);
+            }
+            _ => {}
+        }
+        false
+    }
+
+    /// Returns `true` if the event belongs to a subtree that was left out of the sample,
+    /// choosing whether to keep each child of the root as it's forked.
+    fn is_sampled_out(&mut self, event: &Event) -> bool {
+        let Some(ratio) = self.sample_trees else {
+            return false;
+        };
+        if let Some(parent_pid) = event.fork_parent() {
+            let sampled_out = if self.sampled_out_pids.contains(&parent_pid) {
+                true
+            } else if Some(parent_pid) == self.root_pid
+                && self.tracked_events.pid_is_tracked(parent_pid)
+            {
+                !ratio.includes(splitmix64(self.sample_seed ^ event.seq() as u64))
+            } else {
+                false
+            };
+            // The PID may have been reused since it was left out
+            if sampled_out {
+                self.sampled_out_pids.insert(event.pid());
+            } else {
+                self.sampled_out_pids.remove(&event.pid());
+            }
+            return sampled_out;
+        }
+        if let Event::Exit { pid, .. } = event {
+            return self.sampled_out_pids.remove(pid);
+        }
+        self.sampled_out_pids.contains(&event.pid())
+    }
+
+    /// Returns the configured `root_pid` if one has been set.
+    #[allow(dead_code)]
+    pub fn root_pid(&self) -> Option<i32> {
+        self.root_pid
+    }
+
+    /// Returns `Some(true)` if the event is the initial fork of the process at the root
+    /// of the process tree or `Some(false)` if it isn't. Returns `None` if the root pid
+    /// has not yet been set.
+    fn is_initial_fork(&self, event: &Event) -> Option<bool> {
+        self.root_pid
+            .as_ref()
+            .map(|pid| (event.pid() == *pid) && event.is_fork())
+    }
+
+    /// Returns `true` if we've seen the initial fork of the process at the root
+    /// of the process tree. Returns `false` if the root pid is unset or it's set
+    /// and we haven't been the fork.
+    #[allow(dead_code)]
+    fn have_seen_initial_fork(&self) -> bool {
+        !self.tracked_events.is_empty()
+    }
+
+    /// Adds the event to the backlog of outstanding events that we've seen and
+    /// might want to keep.
+    fn buffer_event(&mut self, event: Event) {
+        if let Some(parent_pid) = event.fork_parent() {
+            if self.tracked_events.pid_is_tracked(parent_pid) {
+                self.pids_to_unbuffer.push(event.pid());
+            } else {
+                self.buffered_children
+                    .entry(parent_pid)
+                    .or_default()
+                    .push(event.pid());
+            }
+        }
+        self.buffered_events.add(event.pid(), event);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tracked_events.is_empty()
+    }
+
+    /// Suggests buffered processes that could be the root of the process tree, with
+    /// the largest process trees first and the earliest forks breaking ties.
+    pub fn root_candidates(&self, limit: usize) -> Vec<RootCandidate> {
+        let mut children: HashMap<i32, Vec<i32>> = HashMap::new();
+        let mut forked = vec![];
+        for (pid, buffer) in self.buffered_events.iter() {
+            if let Some(Event::Fork {
+                parent_pid,
+                timestamp,
+                ..
+            }) = buffer.front()
+            {
+                children.entry(*parent_pid).or_default().push(pid);
+                let command = buffer.iter().find_map(exec_description);
+                forked.push((pid, *timestamp, command));
+            }
+        }
+        let subtree_size = |root: i32| {
+            let mut size = 0;
+            let mut visited = HashSet::new();
+            let mut worklist = vec![root];
+            while let Some(pid) = worklist.pop() {
+                if visited.insert(pid) {
+                    size += 1;
+                    worklist.extend(children.get(&pid).into_iter().flatten());
+                }
+            }
+            size
+        };
+        let mut candidates = forked
+            .into_iter()
+            .map(|(pid, timestamp, command)| {
+                let candidate = RootCandidate {
+                    pid,
+                    processes: subtree_size(pid),
+                    command,
+                };
+                (candidate, timestamp)
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|(candidate, timestamp)| {
+            (std::cmp::Reverse(candidate.processes), *timestamp)
+        });
+        candidates
+            .into_iter()
+            .take(limit)
+            .map(|(candidate, _)| candidate)
+            .collect()
+    }
+
+    /// Returns the number of PIDs that are part of the recording.
+    pub fn tracked_pid_count(&self) -> usize {
+        self.tracked_events.len()
+    }
+
+    /// Returns the number of PIDs whose events are buffered until it's known whether
+    /// they're part of the recording.
+    pub fn buffered_pid_count(&self) -> usize {
+        self.buffered_events.len()
+    }
+
+    /// Removes and returns the buffered events of processes that never joined the
+    /// recording, in the order they were recorded, e.g. to find out why a subtree is
+    /// missing.
+    pub fn take_unmatched_events(&mut self) -> Vec<Event> {
+        self.buffered_children.clear();
+        std::mem::replace(&mut self.buffered_events, EventStore::new())
+            .events_ordered()
+            .collect()
+    }
+
+    /// Gives up on buffered PIDs whose first event is older than `window` nanoseconds,
+    /// as measured by the timestamps of the events, so that the buffer doesn't grow
+    /// without bound when lots of unrelated processes are running.
+    ///
+    /// Their events are written to the unmatched sink if there is one, see
+    /// [`EventIngester::set_unmatched_sink`], and dropped otherwise.
+    pub fn set_buffer_window(&mut self, window: u128) {
+        self.buffer_window = Some(window);
+    }
+
+    /// Writes the events of PIDs that never join the process tree to `sink`, both
+    /// when they expire and when [`EventIngester::finalize_unmatched`] is called.
+    pub fn set_unmatched_sink(&mut self, sink: Box<dyn EventWrite>) {
+        self.unmatched_sink = Some(UnmatchedSink {
+            writer: sink,
+            written: 0,
+        });
+    }
+
+    /// Writes the events that are still buffered to the unmatched sink and finalizes
+    /// it, returning the number of events that were written to it in total.
+    ///
+    /// Nothing is written if there isn't an unmatched sink.
+    pub fn finalize_unmatched(&mut self) -> Result<usize, Error> {
+        let Some(mut sink) = self.unmatched_sink.take() else {
+            return Ok(0);
+        };
+        sink.write_events(&self.take_unmatched_events())?;
+        sink.writer.finalize()?;
+        Ok(sink.written)
+    }
+
+    /// Removes the buffered PIDs that have been waiting for longer than the buffer
+    /// window as of `now`, writing their events to the unmatched sink.
+    ///
+    /// The buffer is swept at most once per window so that the cost of a sweep is
+    /// spread over the events that arrived in between.
+    fn expire_buffered(&mut self, now: u128) -> Result<(), Error> {
+        let Some(window) = self.buffer_window else {
+            return Ok(());
+        };
+        if now < self.last_buffer_sweep.saturating_add(window) {
+            return Ok(());
+        }
+        self.last_buffer_sweep = now;
+        let cutoff = now.saturating_sub(window);
+        let expired_pids = self
+            .buffered_events
+            .iter()
+            .filter(|(_, buffer)| buffer.front().is_some_and(|e| e.timestamp() < cutoff))
+            .map(|(pid, _)| pid)
+            .collect::<Vec<_>>();
+        let mut expired = vec![];
+        for pid in expired_pids {
+            let Some(buffer) = self.buffered_events.remove(pid) else {
+                continue;
+            };
+            self.buffered_children.remove(&pid);
+            if let Some(parent_pid) = buffer.front().and_then(|e| e.fork_parent()) {
+                if let Some(siblings) = self.buffered_children.get_mut(&parent_pid) {
+                    siblings.retain(|child_pid| *child_pid != pid);
+                    if siblings.is_empty() {
+                        self.buffered_children.remove(&parent_pid);
+                    }
+                }
+            }
+            expired.extend(buffer);
+        }
+        if let Some(ref mut sink) = self.unmatched_sink {
+            expired.sort_by_key(|event| event.order_key());
+            sink.write_events(&expired)?;
+        }
+        Ok(())
+    }
+
+    pub fn prepare_for_rendering(&mut self) {
+        self.tracked_events.collapse_execs();
+    }
+
+    pub fn post_process_buffers(&mut self) {
+        self.adjusted_timestamps += self.tracked_events.post_process_buffers();
+    }
+
+    /// Returns the number of events whose timestamps went backwards and were repaired
+    /// by [`EventIngester::post_process_buffers`].
+    pub fn adjusted_timestamps(&self) -> u64 {
+        self.adjusted_timestamps
+    }
+
+    /// Rewrites the file names and arguments of every tracked exec.
+    pub fn rewrite_exec_strings(&mut self, f: impl Fn(&str) -> String) {
+        self.tracked_events.rewrite_exec_strings(f);
+    }
+
+    /// Replaces the commands of every tracked exec that `labels` has a name for.
+    pub fn label_execs(&mut self, labels: &Labels) {
+        self.tracked_events.label_execs(labels);
+    }
+
+    /// Stops tracking the processes that `filter` leaves out, see
+    /// [`EventStore::retain_users`].
+    pub fn retain_users(&mut self, filter: &UserFilter) {
+        if let Some(root_pid) = self.root_pid {
+            self.tracked_events.retain_users(root_pid, filter);
+        }
+    }
+}
+
+impl<T: EventWrite> EventIngester<T> {
+    /// Create a new ingester.
+    ///
+    /// If initialized without a `root_pid` it will buffer events until one is set.
+    /// If initialized with a writer, events will be written to it as they are identified
+    /// to be part of the process tree rooted at `root_pid`.
+    pub fn new(root_pid: Option<i32>, writer: Option<T>) -> Self {
+        Self {
+            root_pid,
+            tracked_events: EventStore::new(),
+            buffered_events: EventStore::new(),
+            buffered_children: HashMap::new(),
+            pids_to_unbuffer: vec![],
+            retain_events: true,
+            adopted_pids: vec![],
+            adopt_exec: vec![],
+            adopt_parents: vec![],
+            reparented: BTreeMap::new(),
+            adopt_cgroups: vec![],
+            follow_root_cgroup: false,
+            launcher_cgroup: None,
+            ns_pids: BTreeMap::new(),
+            host_samples: vec![],
+            follow_daemons: true,
+            detached_pids: HashSet::new(),
+            subreaper_pids: HashSet::new(),
+            reaped_pids: HashSet::new(),
+            storm_detector: None,
+            fork_storms: vec![],
+            sample_trees: None,
+            sample_seed: 0,
+            sampled_out_pids: HashSet::new(),
+            shell_wrapper: false,
+            metadata: None,
+            adjusted_timestamps: 0,
+            buffer_window: None,
+            last_buffer_sweep: 0,
+            unmatched_sink: None,
+            writer,
+        }
+    }
+
+    /// Create an ingester that only writes events and doesn't keep them.
+    ///
+    /// Only the most recent event for each tracked PID is kept so that memory use
+    /// doesn't grow with the length of the recording, which means that
+    /// [`EventIngester::tracked_events`] won't contain the whole process tree.
+    pub fn streaming(root_pid: Option<i32>, writer: T) -> Self {
+        Self {
+            retain_events: false,
+            ..Self::new(root_pid, Some(writer))
+        }
+    }
+
+    /// Write a line of raw output from the script.
+    pub fn write_raw(&mut self, line: &str) -> Result<(), Error> {
+        if let Some(ref mut writer) = self.writer {
+            writer.write_raw(line.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Write the metadata for the recording.
+    pub fn write_metadata(&mut self, metadata: &Metadata) -> Result<(), Error> {
+        if let Some(ref mut writer) = self.writer {
+            writer.write_metadata(metadata)?;
+        }
+        Ok(())
+    }
+
+    /// Make everything written so far durable.
+    pub fn flush_writer(&mut self) -> Result<(), Error> {
+        if let Some(ref mut writer) = self.writer {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Flush the writer and write any trailing output.
+    ///
+    /// Nothing should be written to this ingester afterwards.
+    pub fn finalize_writer(&mut self) -> Result<(), Error> {
+        if let Some(ref mut writer) = self.writer {
+            writer.finalize()?;
+        }
+        Ok(())
+    }
+
+    /// Adds the event to the tracked process tree and writes it out.
+    fn store_event(&mut self, event: Event) -> Result<(), Error> {
+        if let Some(ref mut writer) = self.writer {
+            writer.write_event(&event)?;
+        }
+        self.check_for_storm(&event)?;
+        if !self.tracked_events.pid_is_tracked(event.pid()) {
+            // Anything buffered for this PID or its descendants is now part of the tree
+            self.pids_to_unbuffer.push(event.pid());
+        }
+        if let Event::Exec {
+            pid,
+            ns_pid: Some(ns_pid),
+            ..
+        } = event
+        {
+            if ns_pid != pid {
+                self.ns_pids.insert(pid, ns_pid);
+            }
+        }
+        if self.retain_events {
+            self.tracked_events.add(event.pid(), event);
+        } else {
+            self.tracked_events.replace(event.pid(), event);
+        }
+        Ok(())
+    }
+
+    /// Feeds a tracked event to the storm detector, writing a warning if it raises one.
+    fn check_for_storm(&mut self, event: &Event) -> Result<(), Error> {
+        let Some(ref mut detector) = self.storm_detector else {
+            return Ok(());
+        };
+        let storm = match event {
+            Event::Fork {
+                parent_pid,
+                child_pid,
+                timestamp,
+                ..
+            } => detector.observe_fork(*parent_pid, *child_pid, *timestamp),
+            Event::Exit { pid, .. } => {
+                detector.observe_exit(*pid);
+                None
+            }
+            _ => None,
+        };
+        if let Some(storm) = storm {
+            // Storms share the sequence number of the fork that caused them
+            let warning = detector.storm_event(&storm, event.seq());
+            if let Some(ref mut writer) = self.writer {
+                writer.write_event(&warning)?;
+            }
+            self.fork_storms.push(warning);
+        }
+        Ok(())
+    }
+
+    /// Move the buffered events of any PIDs that have joined the process tree into the
+    /// tracked events, writing them out as they're moved.
+    ///
+    /// Only the PIDs that joined the process tree since the last drain and their
+    /// buffered descendants are visited, so the work done is proportional to the
+    /// number of events that are unbuffered rather than the size of the buffer.
+    ///
+    /// If this ingester has not been configured with a writer, the events will be stored
+    /// internally but they won't be written anywhere.
+    fn drain_buffer(&mut self) -> Result<(), Error> {
+        let mut worklist = std::mem::take(&mut self.pids_to_unbuffer);
+        let mut visited = HashSet::new();
+        let mut drained_events = vec![];
+        while let Some(pid) = worklist.pop() {
+            if !visited.insert(pid) {
+                continue;
+            }
+            if let Some(buffer) = self.buffered_events.remove(pid) {
+                drained_events.push(buffer);
+            }
+            // The index may refer to PIDs that were reused, so make sure the buffered
+            // fork still says that this is the parent.
+            for child_pid in self.buffered_children.remove(&pid).unwrap_or_default() {
+                if self.buffered_events.parent_of_pid_if_stored(child_pid) == Some(pid) {
+                    worklist.push(child_pid);
+                }
+            }
+        }
+        drained_events.sort_by_key(|events| {
+            events
+                .front()
+                .expect("expected events but found none")
+                .order_key()
+        });
+        // Track this pid from now on
+        for events in drained_events.into_iter() {
+            for event in events.into_iter() {
+                self.store_event(event)?;
+            }
+        }
+        // Storing the drained events queued their PIDs, but they've already been visited
+        self.pids_to_unbuffer.clear();
+
+        Ok(())
+    }
+
+    pub fn observe_event(&mut self, mut event: Event) -> Result<(), Error> {
+        if !event.is_process_event() {
+            // Host samples, warnings, and the recorder's overhead are always part of the
+            // recording
+            if let Some(ref mut writer) = self.writer {
+                writer.write_event(&event)?;
+            }
+            if event.is_fork_storm() {
+                self.fork_storms.push(event);
+            } else {
+                self.host_samples.push(event);
+            }
+            return Ok(());
+        }
+        self.expire_buffered(event.timestamp())?;
+        if !self.follow_daemons && self.is_detached(&event) {
+            return Ok(());
+        }
+        // Samples are too many to buffer, and a tracked process is known to be tracked
+        // by the time it's sampled unless it's adopted later
+        if matches!(event, Event::CpuSample { .. } | Event::RssSample { .. })
+            && !self.tracked_events.pid_is_tracked(event.pid())
+        {
+            return Ok(());
+        }
+        if self.is_fork_by_adopted_parent(&event) {
+            if let (
+                Event::Fork {
+                    parent_pid,
+                    child_pid,
+                    ..
+                },
+                Some(root_pid),
+            ) = (&mut event, self.root_pid)
+            {
+                self.reparented.insert(*child_pid, *parent_pid);
+                *parent_pid = root_pid;
+            }
+        }
+        if self.is_sampled_out(&event) {
+            return Ok(());
+        }
+        self.check_root_cgroup(&event);
+        if !self.tracked_events.pid_is_tracked(event.pid()) && self.should_adopt(&event) {
+            self.adopt_pid(event.pid());
+        }
+        if self.tracked_events.pid_is_tracked(event.pid()) {
+            // We're already tracking this PID, so just store the latest event
+            self.store_event(event)?;
+        } else if self.adopted_pids.contains(&event.pid()) {
+            // This is the first event we've seen from an adopted PID
+            self.store_event(event)?;
+        } else if self.is_initial_fork(&event).unwrap_or(false) {
+            // We aren't tracking any PIDs yet, and this will be the first
+            self.store_event(event)?;
+        } else {
+            // We can't tell if we need this event yet, so buffer it and maybe
+            // it will get drained later.
+            self.buffer_event(event);
+        }
+        self.drain_buffer()?;
+        Ok(())
+    }
+}
+
+/// Scrambles `x` into a well-distributed random draw, see
+/// <https://prng.di.unimi.it/splitmix64.c>.
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[derive(Debug, Default)]
+struct ExecState {
+    exec_filename: Option<Event>,
+    exec_args: Option<Event>,
+    exec: Option<Event>,
+}
+
+impl std::fmt::Display for ExecState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let filename = if self.exec_filename.is_some() {
+            "some"
+        } else {
+            "none"
+        };
+        let args = if self.exec_args.is_some() {
+            "some"
+        } else {
+            "none"
+        };
+        let exec = if self.exec.is_some() { "some" } else { "none" };
+        write!(f, "ExecState(filename:{filename},args:{args},exec:{exec})")
+    }
+}
+
+impl ExecState {
+    fn is_empty(&self) -> bool {
+        self.exec_filename.is_none() && self.exec_args.is_none() && self.exec.is_none()
+    }
+
+    fn is_full(&self) -> bool {
+        self.exec_filename.is_some() && self.exec_args.is_some() && self.exec.is_some()
+    }
+
+    fn clear(&mut self) {
+        self.exec_filename = None;
+        self.exec_args = None;
+        self.exec = None;
+    }
+
+    fn ready_for_args(&self) -> bool {
+        self.exec_filename.is_some() && self.exec_args.is_none() && self.exec.is_none()
+    }
+
+    fn ready_for_exec(&self) -> bool {
+        self.exec_filename.is_some() && self.exec_args.is_some() && self.exec.is_none()
+    }
+
+    fn take_exec_full(&mut self) -> Event {
+        let Event::ExecFilename { filename, .. } = self.exec_filename.take().unwrap() else {
+            panic!("expected exec_filename event");
+        };
         let Event::ExecArgs { args, .. } = self.exec_args.take().unwrap() else {
             panic!("expected exec_args event");
         };
@@ -571,6 +1777,7 @@ impl ExecState {
             pid,
             ppid,
             pgid,
+            credentials,
             ..
         } = self.exec.take().unwrap()
         else {
@@ -584,63 +1791,144 @@ impl ExecState {
             pgid,
             filename,
             args,
+            credentials,
         };
         self.clear();
         event
     }
 }
 
-pub(crate) fn clean_exec_sequences(events: &[Event]) -> VecDeque<Event> {
+/// The number of processes to suggest when the root PID isn't found.
+const ROOT_CANDIDATES: usize = 5;
+
+/// Explains that the root PID never forked, and suggests PIDs that did.
+fn root_not_found<T>(ingester: &EventIngester<T>, root_pid: i32) -> Error {
+    let candidates = ingester.root_candidates(ROOT_CANDIDATES);
+    let mut message = format!("PID {root_pid} was never forked in the recording");
+    if candidates.is_empty() {
+        message.push_str(", and no other processes were either");
+    } else {
+        message.push_str(", these processes were (largest process trees first):");
+        for candidate in candidates {
+            message.push_str(&format!("\n  {candidate}"));
+        }
+    }
+    anyhow!(message)
+}
+
+/// Describes what an exec event ran, preferring the arguments.
+pub(crate) fn exec_description(event: &Event) -> Option<String> {
+    match event {
+        Event::ExecArgs { args, .. }
+        | Event::ExecFull { args, .. }
+        | Event::Exec {
+            cmdline: Some(args),
+            ..
+        } => Some(args.joined()),
+        Event::ExecFilename { filename, .. } => Some(filename.clone()),
+        _ => None,
+    }
+}
+
+pub(crate) fn clean_exec_sequences(events: impl IntoIterator<Item = Event>) -> VecDeque<Event> {
     let mut cleaned = VecDeque::new();
     let mut state = ExecState::default();
-    for event in events.iter() {
+    for event in events {
         match event {
             Event::ExecFilename { .. } => {
                 if state.is_full() {
-                    cleaned.push_back(state.to_exec_full());
+                    cleaned.push_back(state.take_exec_full());
                 } else if !state.is_empty() {
                     state.clear();
                 }
-                state.exec_filename = Some(event.clone());
+                state.exec_filename = Some(event);
             }
             Event::ExecArgs { .. } => {
                 if state.ready_for_args() {
-                    state.exec_args = Some(event.clone());
+                    state.exec_args = Some(event);
                 } else {
                     state.clear();
                 }
             }
+            Event::ExecArg {
+                seq,
+                timestamp,
+                pid,
+                index,
+                arg,
+            } => match state.exec_args {
+                // The arguments of an exec are printed in order, so they're collected
+                // until a gap, after which the rest of the command line is lost
+                None if state.ready_for_args() && index == 0 => {
+                    state.exec_args = Some(Event::ExecArgs {
+                        seq,
+                        timestamp,
+                        pid,
+                        args: ExecArgsKind::Args(vec![arg]),
+                    });
+                }
+                Some(Event::ExecArgs {
+                    args: ExecArgsKind::Args(ref mut args),
+                    ..
+                }) if state.exec.is_none() && args.len() == index => args.push(arg),
+                _ => {}
+            },
             Event::Exec { .. } => {
                 if state.ready_for_exec() {
-                    state.exec = Some(event.clone());
+                    state.exec = Some(event);
                 }
                 if state.is_full() {
-                    cleaned.push_back(state.to_exec_full());
+                    cleaned.push_back(state.take_exec_full());
                 }
             }
-            Event::BadExec { .. } => {
+            Event::BadExec {
+                seq,
+                timestamp,
+                pid,
+                filename,
+                errno,
+            } => {
+                // Keep the failure, with the file it tried to exec if that's known
+                let attempted = match state.exec_filename.take() {
+                    Some(Event::ExecFilename { filename, .. }) => Some(filename),
+                    _ => None,
+                };
                 state.clear();
+                cleaned.push_back(Event::BadExec {
+                    seq,
+                    timestamp,
+                    pid,
+                    filename: filename.or(attempted),
+                    errno,
+                });
             }
             _ => {
                 if state.is_full() {
-                    cleaned.push_back(state.to_exec_full());
+                    cleaned.push_back(state.take_exec_full());
                 }
-                cleaned.push_back(event.clone());
+                cleaned.push_back(event);
             }
         }
     }
     cleaned
 }
 
+/// Feeds the events of a raw recording to `ingester`, which must have a root PID, until
+/// every process in the tree has exited.
+///
+/// The buffers of the returned ingester have already been post-processed.
 pub fn ingest_raw<W: EventWrite>(
     debug: bool,
-    root_pid: i32,
+    mut ingester: EventIngester<W>,
+    format: RawFormat,
     input: impl Read,
-    writer: W,
 ) -> Result<EventIngester<W>, Error> {
+    let root_pid = ingester
+        .root_pid()
+        .ok_or(anyhow!("a root PID is needed to ingest a raw recording"))?;
     let reader = BufReader::new(input);
     let event_parser = EventParser::new();
-    let mut ingester = EventIngester::new(Some(root_pid), Some(writer));
+    let mut jsonl_parser = JsonlParser::new();
 
     for line in reader.lines() {
         if line.is_err() {
@@ -650,21 +1938,29 @@ pub fn ingest_raw<W: EventWrite>(
             continue;
         }
         let line = line.unwrap();
-        match event_parser.parse_line(&line) {
-            Ok(event) => {
+        let parsed = match format {
+            RawFormat::Bpftrace => {
+                if event_parser.parse_tick(&line).is_some() {
+                    // Ticks only matter while recording, where they trigger host samples
+                    continue;
+                }
+                event_parser.parse_line(&line).map(Some)
+            }
+            RawFormat::JsonlRaw => jsonl_parser.parse_line(&line),
+        };
+        match parsed {
+            Ok(Some(event)) => {
                 ingester
-                    .observe_event(&event)
+                    .observe_event(event)
                     .context("failed to ingest event")?;
             }
+            Ok(None) => continue,
             Err(err) => {
                 eprintln!("{}", err);
             }
         }
 
-        let unfinished = ingester
-            .tracked_events()
-            .unfinished_pids()
-            .collect::<Vec<_>>();
+        let unfinished = ingester.unfinished_pids();
 
         // Print the outstanding processes in debug mode
         if debug {
@@ -684,7 +1980,13 @@ pub fn ingest_raw<W: EventWrite>(
         }
     }
 
+    if ingester.is_empty() {
+        return Err(root_not_found(&ingester, root_pid));
+    }
     ingester.post_process_buffers();
+    ingester
+        .finalize_writer()
+        .context("failed to finalize writer")?;
 
     Ok(ingester)
 }
@@ -721,6 +2023,8 @@ pub(crate) mod test {
                         parent_pid: *ppid,
                         child_pid: *pid,
                         parent_pgid: *ppid,
+                        credentials: None,
+                        comm: None,
                     };
                     seq += 1;
                     timestamp += 1;
@@ -734,6 +2038,9 @@ pub(crate) mod test {
                         ppid: *ppid,
                         pgid: *pid,
                         cmdline: None,
+                        cgroup: None,
+                        ns_pid: None,
+                        credentials: None,
                     };
                     seq += 1;
                     timestamp += 1;
@@ -750,6 +2057,21 @@ pub(crate) mod test {
                     timestamp += 1;
                     events.push(event);
                 }
+                "exec_full" => {
+                    let event = Event::ExecFull {
+                        seq,
+                        timestamp,
+                        pid: *pid,
+                        ppid: *ppid,
+                        pgid: *pid,
+                        filename: "/foo/bar".to_string(),
+                        args: ExecArgsKind::Joined("/foo/bar".to_string()),
+                        credentials: None,
+                    };
+                    seq += 1;
+                    timestamp += 1;
+                    events.push(event);
+                }
                 "exec_filename" => {
                     let event = Event::ExecFilename {
                         seq,
@@ -766,6 +2088,8 @@ pub(crate) mod test {
                         seq,
                         timestamp,
                         pid: *pid,
+                        filename: None,
+                        errno: None,
                     };
                     seq += 1;
                     timestamp += 1;
@@ -778,6 +2102,20 @@ pub(crate) mod test {
                         pid: *pid,
                         ppid: *ppid,
                         pgid: *pid,
+                        exit_code: None,
+                    };
+                    seq += 1;
+                    timestamp += 1;
+                    events.push(event);
+                }
+                "exit_fail" => {
+                    let event = Event::Exit {
+                        seq,
+                        timestamp,
+                        pid: *pid,
+                        ppid: *ppid,
+                        pgid: *pid,
+                        exit_code: Some(1 << 8),
                     };
                     seq += 1;
                     timestamp += 1;
@@ -790,6 +2128,7 @@ pub(crate) mod test {
                         pid: *pid,
                         ppid: *ppid,
                         pgid: *pid,
+                        caller_pid: None,
                     };
                     seq += 1;
                     timestamp += 1;
@@ -808,100 +2147,451 @@ pub(crate) mod test {
                     timestamp += 1;
                     events.push(event);
                 }
+                "subreaper" => {
+                    let event = Event::Subreaper {
+                        seq,
+                        timestamp,
+                        pid: *pid,
+                    };
+                    seq += 1;
+                    timestamp += 1;
+                    events.push(event);
+                }
+                "cpu" => {
+                    let event = Event::CpuSample {
+                        seq,
+                        timestamp,
+                        pid: *pid,
+                    };
+                    seq += 1;
+                    timestamp += 1;
+                    events.push(event);
+                }
                 _ => {}
             }
         }
         events
     }
 
-    /// Returns a new [EventIngester] for use in tests
-    fn mock_ingester(root_pid: Option<i32>) -> EventIngester<MockWriter> {
-        let writer = MockWriter::new();
-        EventIngester::new(root_pid, Some(writer))
+    /// Returns a new [EventIngester] for use in tests
+    fn mock_ingester(root_pid: Option<i32>) -> EventIngester<MockWriter> {
+        let writer = MockWriter::new();
+        EventIngester::new(root_pid, Some(writer))
+    }
+
+    #[test]
+    fn it_works() {}
+
+    #[test]
+    fn parses_fork_line() {
+        let parser = EventParser::new();
+        let parsed = parser
+            .parse_line("FORK: seq=0,ts=0,parent_pid=1,child_pid=2,parent_pgid=1")
+            .unwrap();
+        let expected = Event::Fork {
+            seq: 0,
+            timestamp: 0,
+            parent_pid: 1,
+            child_pid: 2,
+            parent_pgid: 1,
+            credentials: None,
+            comm: None,
+        };
+        assert_eq!(parsed, expected);
+
+        let parsed = parser
+            .parse_line(
+                "FORK: seq=0,ts=0,parent_pid=1,child_pid=2,parent_pgid=1,uid=1000,gid=100,comm=a, b",
+            )
+            .unwrap();
+        let Event::Fork {
+            credentials, comm, ..
+        } = parsed
+        else {
+            panic!("expected fork event");
+        };
+        assert_eq!(comm.as_deref(), Some("a, b"));
+        assert_eq!(
+            credentials,
+            Some(Credentials {
+                uid: 1000,
+                gid: 100
+            })
+        );
+
+        // Backends that can't read the process group leave it out
+        let parsed = parser
+            .parse_line("FORK: seq=0,ts=0,parent_pid=1,child_pid=2,comm=sh")
+            .unwrap();
+        assert!(matches!(
+            parsed,
+            Event::Fork {
+                parent_pgid: UNKNOWN_PGID,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn parses_exec_line() {
+        let parser = EventParser::new();
+        let parsed = parser
+            .parse_line("EXEC: seq=0,ts=0,pid=2,ppid=1,pgid=1")
+            .unwrap();
+        let expected = Event::Exec {
+            seq: 0,
+            timestamp: 0,
+            pid: 2,
+            ppid: 1,
+            pgid: 1,
+            cmdline: None,
+            cgroup: None,
+            ns_pid: None,
+            credentials: None,
+        };
+        assert_eq!(parsed, expected);
+
+        let parsed = parser
+            .parse_line("EXEC: seq=0,ts=0,pid=2,ppid=1,pgid=1,cgroup=1234,nspid=7,uid=0,gid=0")
+            .unwrap();
+        let Event::Exec {
+            cgroup,
+            ns_pid,
+            credentials,
+            ..
+        } = parsed
+        else {
+            panic!("expected exec event");
+        };
+        assert_eq!(cgroup, Some(1234));
+        assert_eq!(ns_pid, Some(7));
+        assert_eq!(credentials, Some(Credentials { uid: 0, gid: 0 }));
+
+        let parsed = parser
+            .parse_line("EXEC: seq=0,ts=0,pid=2,ppid=1,cgroup=1234")
+            .unwrap();
+        assert!(matches!(
+            parsed,
+            Event::Exec {
+                pgid: UNKNOWN_PGID,
+                cgroup: Some(1234),
+                ..
+            }
+        ));
+        let parsed = parser
+            .parse_line("EXIT: seq=1,ts=1,pid=2,ppid=1,exit_code=0")
+            .unwrap();
+        assert!(matches!(
+            parsed,
+            Event::Exit {
+                pgid: UNKNOWN_PGID,
+                exit_code: Some(0),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn parses_host_lines() {
+        let parser = EventParser::new();
+        let parsed = parser
+            .parse_line(
+                "HOST: seq=3,ts=10,load1=125,load5=100,load15=50,mem_total=2048,mem_available=1024",
+            )
+            .unwrap();
+        let expected = Event::HostSample {
+            seq: 3,
+            timestamp: 10,
+            load: [125, 100, 50],
+            mem_total_kb: 2048,
+            mem_available_kb: 1024,
+        };
+        assert_eq!(parsed, expected);
+        assert_eq!(parser.parse_tick("TICK: seq=4,ts=20"), Some((4, 20)));
+        assert_eq!(parser.parse_tick("EXIT: seq=4,ts=20"), None);
+    }
+
+    #[test]
+    fn parses_exec_args_line() {
+        let parser = EventParser::new();
+        let parsed = parser
+            .parse_line("EXEC_ARGS: seq=0,ts=0,pid=1,foo")
+            .unwrap();
+        let expected = Event::ExecArgs {
+            seq: 0,
+            timestamp: 0,
+            pid: 1,
+            args: ExecArgsKind::Joined("foo".to_string()),
+        };
+        assert_eq!(parsed, expected);
+    }
+
+    #[test]
+    fn parses_setsid_line() {
+        let parser = EventParser::new();
+        let parsed = parser
+            .parse_line("SETSID: seq=0,ts=0,pid=1,ppid=0,pgid=1,sid=1")
+            .unwrap();
+        let expected = Event::SetSID {
+            seq: 0,
+            timestamp: 0,
+            pid: 1,
+            pgid: 1,
+            sid: 1,
+            ppid: 0,
+        };
+        assert_eq!(parsed, expected);
+    }
+
+    #[test]
+    fn parses_setpgid_line() {
+        let parser = EventParser::new();
+        let parsed = parser
+            .parse_line("SETPGID: seq=0,ts=0,pid=1,ppid=0,pgid=1")
+            .unwrap();
+        let expected = Event::SetPGID {
+            seq: 0,
+            timestamp: 0,
+            pid: 1,
+            ppid: 0,
+            pgid: 1,
+            caller_pid: None,
+        };
+        assert_eq!(parsed, expected);
+        // A shell moving its child into another group
+        let parsed = parser
+            .parse_line("SETPGID: seq=1,ts=5,pid=3,ppid=1,pgid=2,caller=1")
+            .unwrap();
+        let expected = Event::SetPGID {
+            seq: 1,
+            timestamp: 5,
+            pid: 3,
+            ppid: 1,
+            pgid: 2,
+            caller_pid: Some(1),
+        };
+        assert_eq!(parsed, expected);
+    }
+
+    #[test]
+    fn parses_chdir_line() {
+        let parser = EventParser::new();
+        let parsed = parser
+            .parse_line("CHDIR: seq=3,ts=7,pid=2,path=../build, release")
+            .unwrap();
+        let expected = Event::Chdir {
+            seq: 3,
+            timestamp: 7,
+            pid: 2,
+            path: Some("../build, release".to_string()),
+        };
+        assert_eq!(parsed, expected);
+        // Changed with fchdir
+        let parsed = parser.parse_line("CHDIR: seq=4,ts=8,pid=2").unwrap();
+        assert!(matches!(parsed, Event::Chdir { path: None, .. }));
     }
 
     #[test]
-    fn it_works() {}
+    fn parses_namespace_line() {
+        let parser = EventParser::new();
+        // CLONE_NEWNS | CLONE_NEWNET
+        let parsed = parser
+            .parse_line("NAMESPACE: seq=3,ts=7,pid=2,how=unshare,flags=1073872896")
+            .unwrap();
+        let expected = Event::Namespace {
+            seq: 3,
+            timestamp: 7,
+            pid: 2,
+            how: NamespaceChange::Unshare,
+            namespaces: vec!["mnt".to_string(), "net".to_string()],
+        };
+        assert_eq!(parsed, expected);
+    }
 
     #[test]
-    fn parses_fork_line() {
+    fn parses_mount_line() {
         let parser = EventParser::new();
         let parsed = parser
-            .parse_line("FORK: seq=0,ts=0,parent_pid=1,child_pid=2,parent_pgid=1")
+            .parse_line("MOUNT: seq=3,ts=7,pid=2,op=mount,fstype=overlay,target=/build/root")
             .unwrap();
-        let expected = Event::Fork {
-            seq: 0,
-            timestamp: 0,
-            parent_pid: 1,
-            child_pid: 2,
-            parent_pgid: 1,
+        let expected = Event::Mount {
+            seq: 3,
+            timestamp: 7,
+            pid: 2,
+            target: "/build/root".to_string(),
+            fstype: Some("overlay".to_string()),
+            unmount: false,
         };
         assert_eq!(parsed, expected);
+        // Bind mounts don't have a type
+        let parsed = parser
+            .parse_line("MOUNT: seq=4,ts=8,pid=2,op=umount,fstype=,target=/build/root")
+            .unwrap();
+        assert!(matches!(
+            parsed,
+            Event::Mount {
+                fstype: None,
+                unmount: true,
+                ..
+            }
+        ));
     }
 
     #[test]
-    fn parses_exec_line() {
+    fn parses_ptrace_line() {
         let parser = EventParser::new();
         let parsed = parser
-            .parse_line("EXEC: seq=0,ts=0,pid=2,ppid=1,pgid=1")
+            .parse_line("PTRACE: seq=3,ts=7,pid=2,request=16,target=5,ret=0")
             .unwrap();
-        let expected = Event::Exec {
-            seq: 0,
-            timestamp: 0,
+        let expected = Event::Ptrace {
+            seq: 3,
+            timestamp: 7,
             pid: 2,
-            ppid: 1,
-            pgid: 1,
-            cmdline: None,
+            request: PtraceRequest::Attach,
+            target_pid: Some(5),
+            errno: None,
         };
         assert_eq!(parsed, expected);
+        // Already being traced
+        let parsed = parser
+            .parse_line("PTRACE: seq=4,ts=8,pid=2,request=0,target=0,ret=-1")
+            .unwrap();
+        assert!(matches!(
+            parsed,
+            Event::Ptrace {
+                request: PtraceRequest::Traceme,
+                target_pid: None,
+                errno: Some(1),
+                ..
+            }
+        ));
     }
 
     #[test]
-    fn parses_exec_args_line() {
+    fn parses_sandbox_line() {
         let parser = EventParser::new();
         let parsed = parser
-            .parse_line("EXEC_ARGS: seq=0,ts=0,pid=1,foo")
+            .parse_line("SANDBOX: seq=3,ts=7,pid=2,mechanism=seccomp,mode=1,flags=9")
             .unwrap();
-        let expected = Event::ExecArgs {
-            seq: 0,
-            timestamp: 0,
+        let expected = Event::Sandbox {
+            seq: 3,
+            timestamp: 7,
+            pid: 2,
+            mechanism: SandboxMechanism::Seccomp,
+            mode: Some("filter".to_string()),
+            flags: vec!["tsync".to_string(), "new_listener".to_string()],
+        };
+        assert_eq!(parsed, expected);
+        let parsed = parser
+            .parse_line("SANDBOX: seq=4,ts=8,pid=2,mechanism=landlock,mode=0,flags=64")
+            .unwrap();
+        let Event::Sandbox { mode, flags, .. } = parsed else {
+            panic!("expected a sandbox event");
+        };
+        assert_eq!((mode, flags), (None, vec!["0x40".to_string()]));
+    }
+
+    #[test]
+    fn parses_subreaper_line() {
+        let parser = EventParser::new();
+        let parsed = parser.parse_line("SUBREAPER: seq=5,ts=9,pid=1").unwrap();
+        let expected = Event::Subreaper {
+            seq: 5,
+            timestamp: 9,
             pid: 1,
-            args: ExecArgsKind::Joined("foo".to_string()),
         };
         assert_eq!(parsed, expected);
     }
 
     #[test]
-    fn parses_setsid_line() {
+    fn parses_cpu_line() {
+        let parser = EventParser::new();
+        let parsed = parser.parse_line("CPU: seq=6,ts=10,pid=2").unwrap();
+        let expected = Event::CpuSample {
+            seq: 6,
+            timestamp: 10,
+            pid: 2,
+        };
+        assert_eq!(parsed, expected);
+    }
+
+    #[test]
+    fn parses_usage_line() {
         let parser = EventParser::new();
         let parsed = parser
-            .parse_line("SETSID: seq=0,ts=0,pid=1,ppid=0,pgid=1,sid=1")
+            .parse_line("USAGE: seq=7,ts=11,pid=2,minor_faults=1500,major_faults=3,voluntary_switches=40,involuntary_switches=12,user_ns=900000,system_ns=100000")
             .unwrap();
-        let expected = Event::SetSID {
-            seq: 0,
-            timestamp: 0,
+        let expected = Event::Usage {
+            seq: 7,
+            timestamp: 11,
+            pid: 2,
+            usage: ResourceUsage {
+                minor_faults: 1500,
+                major_faults: 3,
+                voluntary_switches: 40,
+                involuntary_switches: 12,
+                user_ns: 900_000,
+                system_ns: 100_000,
+            },
+        };
+        assert_eq!(parsed, expected);
+    }
+
+    #[test]
+    fn parses_custom_line() {
+        let parser = EventParser::new();
+        let parsed = parser
+            .parse_line("CUSTOM: seq=3,ts=10,pid=1,name=cache-miss,key=abc,size=3")
+            .unwrap();
+        let expected = Event::Custom {
+            seq: 3,
+            timestamp: 10,
             pid: 1,
-            pgid: 1,
-            sid: 1,
-            ppid: 0,
+            name: "cache-miss".to_string(),
+            fields: [("key", "abc"), ("size", "3")]
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
         };
         assert_eq!(parsed, expected);
+        let without_fields = parser
+            .parse_line("CUSTOM: seq=3,ts=10,pid=1,name=tick")
+            .unwrap();
+        assert!(matches!(without_fields, Event::Custom { fields, .. } if fields.is_empty()));
     }
 
     #[test]
-    fn parses_setpgid_line() {
+    fn parses_badexec_line() {
         let parser = EventParser::new();
         let parsed = parser
-            .parse_line("SETPGID: seq=0,ts=0,pid=1,ppid=0,pgid=1")
+            .parse_line("BADEXEC: seq=0,ts=0,pid=1,ret=-2")
             .unwrap();
-        let expected = Event::SetPGID {
+        assert!(matches!(parsed, Event::BadExec { errno: Some(2), .. }));
+        // Recordings from older versions don't have the return value
+        let parsed = parser.parse_line("BADEXEC: seq=0,ts=0,pid=1").unwrap();
+        assert!(matches!(parsed, Event::BadExec { errno: None, .. }));
+    }
+
+    #[test]
+    fn parses_exit_line() {
+        let parser = EventParser::new();
+        let with_code = parser
+            .parse_line("EXIT: seq=0,ts=0,pid=1,ppid=0,pgid=1,exit_code=256")
+            .unwrap();
+        assert_eq!(with_code.exit_code(), Some(256));
+        // Recordings from older versions don't have the exit code
+        let without_code = parser
+            .parse_line("EXIT: seq=0,ts=0,pid=1,ppid=0,pgid=1")
+            .unwrap();
+        let expected = Event::Exit {
             seq: 0,
             timestamp: 0,
             pid: 1,
             ppid: 0,
             pgid: 1,
+            exit_code: None,
         };
-        assert_eq!(parsed, expected);
+        assert_eq!(without_code, expected);
     }
 
     #[test]
@@ -913,7 +2603,7 @@ pub(crate) mod test {
             make_simple_events(0, 0, &[("exec", 1, 0), ("fork", 2, 1), ("fork", 4, 2)]);
         let mut ingester = mock_ingester(Some(root_pid));
         for event in dummy_events.iter() {
-            ingester.observe_event(event).unwrap();
+            ingester.observe_event(event.clone()).unwrap();
         }
 
         // All of the previous events should have been buffered since we haven't seen
@@ -929,6 +2619,8 @@ pub(crate) mod test {
                 parent_pid: 2,
                 child_pid: root_pid,
                 parent_pgid: 2,
+                credentials: None,
+                comm: None,
             })
             .unwrap())
     }
@@ -948,7 +2640,7 @@ pub(crate) mod test {
 
         let mut ingester = mock_ingester(Some(root_pid));
         for event in dummy_events.iter() {
-            ingester.observe_event(event).unwrap();
+            ingester.observe_event(event.clone()).unwrap();
         }
 
         // All of the previous events should have been buffered since we haven't seen
@@ -962,8 +2654,10 @@ pub(crate) mod test {
             parent_pid: 0,
             child_pid: root_pid,
             parent_pgid: 0,
+            credentials: None,
+            comm: None,
         };
-        ingester.observe_event(&fork).unwrap();
+        ingester.observe_event(fork.clone()).unwrap();
 
         // Assert that the PID is now being tracked
         let root_events = ingester.tracked_events.remove(root_pid).unwrap();
@@ -991,7 +2685,7 @@ pub(crate) mod test {
         ingester.tracked_events.register_root(root_pid);
 
         for event in events.iter() {
-            ingester.observe_event(event).unwrap();
+            ingester.observe_event(event.clone()).unwrap();
         }
 
         // Assert that the PID is now being tracked
@@ -1014,7 +2708,7 @@ pub(crate) mod test {
 
         let mut ingester = mock_ingester(Some(root_pid));
         for event in events.iter() {
-            ingester.observe_event(event).unwrap();
+            ingester.observe_event(event.clone()).unwrap();
         }
 
         // Assert that the PID is now being tracked
@@ -1026,6 +2720,135 @@ pub(crate) mod test {
         ));
     }
 
+    #[test]
+    fn drains_buffered_descendants() {
+        let root_pid = 1;
+        // The grandchild and child fork events are seen before the root fork
+        let events = make_simple_events(
+            0,
+            0,
+            &[
+                ("fork", 3, 2),
+                ("fork", 2, root_pid),
+                ("fork", 100, 99),
+                ("fork", root_pid, 0),
+            ],
+        );
+        let mut ingester = mock_ingester(Some(root_pid));
+        for event in events.iter() {
+            ingester.observe_event(event.clone()).unwrap();
+        }
+
+        let tracked = ingester.tracked_events().pids();
+        assert_eq!(tracked, HashSet::from([1, 2, 3]));
+        assert!(ingester.buffered_events.pid_is_tracked(100));
+        assert_eq!(ingester.writer.as_ref().unwrap().events.len(), 3);
+
+        let unmatched = ingester.take_unmatched_events();
+        assert_eq!(unmatched, vec![events[2].clone()]);
+        assert_eq!(ingester.buffered_pid_count(), 0);
+    }
+
+    #[test]
+    fn expires_buffered_events_after_the_window() {
+        let events = make_simple_events(
+            0,
+            0,
+            &[
+                ("fork", 100, 99),
+                ("fork", 101, 100),
+                ("fork", 1, 0),
+                ("fork", 2, 1),
+                ("fork", 3, 2),
+            ],
+        );
+        for keep_unmatched in [false, true] {
+            let mut ingester = mock_ingester(Some(1));
+            ingester.set_buffer_window(2);
+            if keep_unmatched {
+                ingester.set_unmatched_sink(Box::new(MockWriter::new()));
+            }
+            for event in events.iter() {
+                ingester.observe_event(event.clone()).unwrap();
+            }
+            assert_eq!(ingester.tracked_events().pids(), HashSet::from([1, 2, 3]));
+            assert_eq!(ingester.buffered_pid_count(), 0);
+            assert!(ingester.buffered_children.is_empty());
+            let written = ingester.finalize_unmatched().unwrap();
+            assert_eq!(written, if keep_unmatched { 2 } else { 0 });
+        }
+    }
+
+    #[test]
+    fn samples_subtrees_of_the_roots_children() {
+        let mut builder = crate::builder::TraceBuilder::new().fork(0, 1);
+        for child_pid in 100..200 {
+            builder = builder
+                .fork(1, child_pid)
+                .fork(child_pid, child_pid + 1000)
+                .exit(child_pid + 1000, 0)
+                .exit(child_pid, 0);
+        }
+        let events = builder.exit(1, 0).build();
+        let sample = |seed| {
+            let mut ingester = mock_ingester(Some(1));
+            ingester.sample_trees("0.5".parse().unwrap(), seed);
+            for event in events.iter() {
+                ingester.observe_event(event.clone()).unwrap();
+            }
+            ingester.tracked_events().pids()
+        };
+        let tracked = sample(7);
+        let children = (100..200).filter(|pid| tracked.contains(pid)).count();
+        assert!((30..70).contains(&children), "{children} children sampled");
+        for child_pid in 100..200 {
+            assert_eq!(
+                tracked.contains(&child_pid),
+                tracked.contains(&(child_pid + 1000))
+            );
+        }
+        assert!(tracked.contains(&1));
+        assert_eq!(sample(7), tracked);
+        assert!("0".parse::<SampleRatio>().is_err());
+        assert!("1.5".parse::<SampleRatio>().is_err());
+    }
+
+    #[test]
+    fn adopts_children_of_out_of_tree_parents() {
+        let events = crate::builder::TraceBuilder::new()
+            .fork(0, 1)
+            .exec(1, "nix build")
+            .fork_named(50, 51, "nix-daemon")
+            .exec(51, "builder")
+            .fork(51, 52)
+            .fork_named(60, 61, "sshd")
+            .exit(52, 0)
+            .exit(51, 0)
+            .exit(1, 0)
+            .build();
+        for parent in ["nix-daemon", "50"] {
+            let mut ingester = mock_ingester(Some(1));
+            ingester.adopt_children_of(parent.parse().unwrap());
+            for event in events.iter() {
+                ingester.observe_event(event.clone()).unwrap();
+            }
+            assert_eq!(
+                ingester.tracked_events().pids(),
+                HashSet::from([1, 51, 52]),
+                "{parent}"
+            );
+            assert_eq!(ingester.reparented(), &BTreeMap::from([(51, 50)]));
+            assert_eq!(
+                ingester.tracked_events().parent_of_pid_if_stored(51),
+                Some(1)
+            );
+            assert_eq!(
+                ingester.tracked_events().parent_of_pid_if_stored(52),
+                Some(51)
+            );
+        }
+    }
+
     #[test]
     fn follows_new_forks() {
         let root_pid = 1;
@@ -1041,7 +2864,7 @@ pub(crate) mod test {
 
         let mut ingester = mock_ingester(Some(root_pid));
         for event in events.iter() {
-            ingester.observe_event(event).unwrap();
+            ingester.observe_event(event.clone()).unwrap();
         }
 
         let new_events = make_simple_events(
@@ -1054,13 +2877,156 @@ pub(crate) mod test {
             ],
         );
         for event in new_events.iter() {
-            ingester.observe_event(event).unwrap();
+            ingester.observe_event(event.clone()).unwrap();
         }
 
         let recorded_new_events = ingester.tracked_events.remove(2).unwrap();
         assert_eq!(recorded_new_events.len(), 3);
     }
 
+    #[test]
+    fn drops_cpu_samples_of_other_processes() {
+        let root_pid = 1;
+        let events = make_simple_events(
+            0,
+            0,
+            &[
+                ("fork", root_pid, 0),
+                ("cpu", root_pid, 0),
+                ("cpu", 7, 0),
+                ("fork", 2, root_pid),
+                ("cpu", 2, root_pid),
+                ("cpu", 2, root_pid),
+            ],
+        );
+        let mut ingester = mock_ingester(Some(root_pid));
+        for event in events.iter() {
+            ingester.observe_event(event.clone()).unwrap();
+        }
+
+        assert_eq!(ingester.tracked_events.remove(root_pid).unwrap().len(), 2);
+        assert_eq!(ingester.tracked_events.remove(2).unwrap().len(), 3);
+        assert!(ingester.buffered_events.is_empty());
+    }
+
+    #[test]
+    fn stops_following_daemons() {
+        let root_pid = 1;
+        let events = make_simple_events(
+            0,
+            0,
+            &[
+                ("fork", root_pid, 0),
+                ("fork", 2, root_pid),
+                ("setsid", 2, root_pid),
+                ("exec", 2, root_pid),
+                ("fork", 3, 2),
+                ("exit", root_pid, 0),
+            ],
+        );
+        let mut ingester = mock_ingester(Some(root_pid));
+        ingester.stop_following_daemons();
+        for event in events.iter() {
+            ingester.observe_event(event.clone()).unwrap();
+        }
+
+        // The new session is recorded but nothing after it, and the daemon isn't
+        // waited on
+        assert_eq!(ingester.tracked_events.remove(2).unwrap().len(), 2);
+        assert!(!ingester.tracked_events.pid_is_tracked(3));
+        assert!(ingester.unfinished_pids().is_empty());
+    }
+
+    #[test]
+    fn keeps_following_daemons_under_subreapers() {
+        let root_pid = 1;
+        let events = make_simple_events(
+            0,
+            0,
+            &[
+                ("fork", root_pid, 0),
+                ("subreaper", root_pid, 0),
+                ("fork", 2, root_pid),
+                ("fork", 3, 2),
+                ("setsid", 3, 2),
+                ("fork", 4, 3),
+                ("exit", 3, 2),
+                ("exec", 4, root_pid),
+            ],
+        );
+        let mut ingester = mock_ingester(Some(root_pid));
+        ingester.stop_following_daemons();
+        for event in events.iter() {
+            ingester.observe_event(event.clone()).unwrap();
+        }
+
+        // The double fork only moved the daemon under the subreaper
+        assert!(ingester.tracked_events.pid_is_tracked(4));
+        assert_eq!(ingester.tracked_events.reaper_of(4), Some(root_pid));
+        assert_eq!(ingester.unfinished_pids(), vec![root_pid, 2, 4]);
+    }
+
+    #[test]
+    fn adopts_matching_execs() {
+        let root_pid = 1;
+        let mut ingester = mock_ingester(Some(root_pid));
+        ingester.adopt_execs_matching(Regex::new(r"/foo/bar").unwrap());
+        let events = make_simple_events(
+            0,
+            0,
+            &[
+                ("fork", root_pid, 0),
+                ("fork", 20, 10),
+                ("exec_full", 20, 10),
+                ("fork", 21, 20),
+                ("exit", 21, 20),
+                ("fork", 30, 10),
+                ("exit", 30, 10),
+            ],
+        );
+        for event in events {
+            ingester.observe_event(event).unwrap();
+        }
+        assert_eq!(ingester.adopted_pids(), &[20]);
+        assert_eq!(ingester.tracked_events.remove(20).unwrap().len(), 2);
+        assert_eq!(ingester.tracked_events.remove(21).unwrap().len(), 2);
+        assert!(!ingester.tracked_events.pid_is_tracked(30));
+    }
+
+    #[test]
+    fn adopts_root_cgroup() {
+        let root_pid = 1;
+        let mut ingester = mock_ingester(Some(root_pid));
+        ingester.follow_root_cgroup();
+        let exec_in = |pid, seq, cgroup| Event::Exec {
+            seq,
+            timestamp: seq,
+            pid,
+            ppid: 0,
+            pgid: pid,
+            cmdline: None,
+            cgroup: Some(cgroup),
+            ns_pid: None,
+            credentials: None,
+        };
+        let events = make_simple_events(0, 0, &[("fork", root_pid, 0), ("fork", 5, 0)]);
+        for event in events {
+            ingester.observe_event(event).unwrap();
+        }
+        // The launcher's own exec, and an unrelated process in the same cgroup
+        ingester.observe_event(exec_in(root_pid, 2, 10)).unwrap();
+        ingester.observe_event(exec_in(5, 3, 10)).unwrap();
+        assert!(ingester.adopted_pids().is_empty());
+        // The launcher moves into a new cgroup and execs the command
+        ingester.observe_event(exec_in(root_pid, 4, 20)).unwrap();
+        ingester.observe_event(exec_in(5, 5, 20)).unwrap();
+        assert_eq!(ingester.adopted_cgroups(), &[20]);
+        assert!(ingester.ns_pids().is_empty());
+        assert_eq!(ingester.adopted_pids(), &[5]);
+        // The buffered fork is drained along with the exec
+        assert_eq!(ingester.tracked_events.remove(5).unwrap().len(), 3);
+    }
+
     #[test]
     fn cleans_simple_exec_seq() {
         let ppid = 1;
@@ -1074,7 +3040,7 @@ pub(crate) mod test {
                 ("exec", pid, ppid),
             ],
         );
-        let mut cleaned = clean_exec_sequences(&events);
+        let mut cleaned = clean_exec_sequences(events);
         assert_eq!(cleaned.len(), 1);
         assert!(matches!(
             cleaned.pop_front().unwrap(),
@@ -1096,7 +3062,7 @@ pub(crate) mod test {
                 ("exec", pid, ppid),
             ],
         );
-        let mut cleaned = clean_exec_sequences(&events);
+        let mut cleaned = clean_exec_sequences(events);
         assert_eq!(cleaned.len(), 2);
         assert!(matches!(cleaned.pop_front().unwrap(), Event::Fork { .. }));
         assert!(matches!(
@@ -1119,9 +3085,43 @@ pub(crate) mod test {
                 ("badexec", pid, ppid),
             ],
         );
-        let mut cleaned = clean_exec_sequences(&events);
-        assert_eq!(cleaned.len(), 1);
+        let mut cleaned = clean_exec_sequences(events);
+        assert_eq!(cleaned.len(), 2);
         assert!(matches!(cleaned.pop_front().unwrap(), Event::Fork { .. }));
+        let Some(Event::BadExec { filename, .. }) = cleaned.pop_front() else {
+            panic!("expected the failed exec to be kept");
+        };
+        assert_eq!(filename.as_deref(), Some("/foo/bar"));
+    }
+
+    #[test]
+    fn reassembles_exec_args() {
+        let parser = EventParser::new();
+        let events = [
+            "EXEC_FILENAME: seq=1,ts=1,pid=2,filename=/usr/bin/cc",
+            "EXEC_ARG: seq=2,ts=1,pid=2,index=0,arg=cc",
+            "EXEC_ARG: seq=2,ts=1,pid=2,index=1,arg=-DGREETING=hello, world",
+            "EXEC_ARG: seq=2,ts=1,pid=2,index=2,arg=a.c",
+            // A gap, after which the rest of the arguments are dropped
+            "EXEC_ARG: seq=2,ts=1,pid=2,index=4,arg=b.c",
+            "EXEC: seq=3,ts=1,pid=2,ppid=1,pgid=1",
+        ]
+        .iter()
+        .map(|line| parser.parse_line(line).unwrap())
+        .collect::<Vec<_>>();
+        let mut cleaned = clean_exec_sequences(events);
+        assert_eq!(cleaned.len(), 1);
+        let Some(Event::ExecFull { args, .. }) = cleaned.pop_front() else {
+            panic!("expected the exec to be reassembled");
+        };
+        assert_eq!(
+            args,
+            ExecArgsKind::Args(vec![
+                "cc".to_string(),
+                "-DGREETING=hello, world".to_string(),
+                "a.c".to_string(),
+            ])
+        );
     }
 
     #[test]
@@ -1138,7 +3138,7 @@ pub(crate) mod test {
                 ("exec", pid, ppid),
             ],
         );
-        let mut cleaned = clean_exec_sequences(&events);
+        let mut cleaned = clean_exec_sequences(events);
         assert_eq!(cleaned.len(), 2);
         assert!(matches!(cleaned.pop_front().unwrap(), Event::Fork { .. }));
         assert!(matches!(
@@ -1146,4 +3146,26 @@ pub(crate) mod test {
             Event::ExecFull { .. }
         ));
     }
+
+    #[test]
+    fn suggests_roots_when_root_is_missing() {
+        let input = [
+            r#"{"type":"fork","ts":1,"pid":10,"ppid":1}"#,
+            r#"{"type":"exec","ts":2,"pid":10,"argv":["make","all"]}"#,
+            r#"{"type":"fork","ts":3,"pid":11,"ppid":10}"#,
+            r#"{"type":"fork","ts":4,"pid":20,"ppid":1}"#,
+        ]
+        .join("\n");
+        let err = ingest_raw(
+            false,
+            EventIngester::new(Some(99), Some(MockWriter::new())),
+            RawFormat::JsonlRaw,
+            input.as_bytes(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "PID 99 was never forked in the recording, these processes were (largest process trees first):\n  PID 10 (2 processes): make all\n  PID 11 (1 process)\n  PID 20 (1 process)"
+        );
+    }
 }
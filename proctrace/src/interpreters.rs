@@ -0,0 +1,337 @@
+//! Classifies the time spent in a recording by the kind of program that was running,
+//! e.g. shells, Python, Node, or the JVM versus native binaries, to answer questions
+//! like "how much of my build is bash?".
+//!
+//! Categories are matched against the file name of each exec. The built-in ones can be
+//! extended with a TOML file that has a `[[category]]` table for each one, which are
+//! tried before the built-in ones:
+//!
+//! ```toml
+//! [[category]]
+//! name = "make"
+//! match = '(^|/)g?make$'
+//! ```
+
+use std::collections::{BTreeMap, VecDeque};
+
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+#[cfg(feature = "fs")]
+use anyhow::Context;
+use regex_lite::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    analyze::format_nanos,
+    models::{Event, EventStore},
+};
+
+#[cfg(feature = "fs")]
+type Error = anyhow::Error;
+
+/// The category of programs that no rule matches.
+pub const NATIVE: &str = "native";
+
+/// The built-in categories and the patterns of the file names that belong to them.
+const DEFAULT_CATEGORIES: &[(&str, &str)] = &[
+    ("shell", r"(^|/)(sh|bash|dash|zsh|ksh|mksh|fish|busybox)$"),
+    ("python", r"(^|/)(python|pypy)[0-9.]*$"),
+    ("node", r"(^|/)(node|nodejs|deno|bun)$"),
+    ("jvm", r"(^|/)(java|kotlin|scala|clojure)$"),
+    ("ruby", r"(^|/)ruby[0-9.]*$"),
+    ("perl", r"(^|/)perl[0-9.]*$"),
+];
+
+/// A kind of program, e.g. "shell".
+#[derive(Debug, Clone)]
+pub struct Category {
+    pub name: String,
+    /// Matched against the file name of each exec.
+    pub pattern: Regex,
+}
+
+impl Category {
+    pub fn new(name: &str, pattern: &str) -> Result<Self, regex_lite::Error> {
+        Ok(Self {
+            name: name.to_string(),
+            pattern: Regex::new(pattern)?,
+        })
+    }
+}
+
+/// The contents of a categories file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "fs"), allow(dead_code))]
+struct CategoriesFile {
+    #[serde(default)]
+    category: Vec<CategoryRule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "fs"), allow(dead_code))]
+struct CategoryRule {
+    name: String,
+    #[serde(rename = "match")]
+    pattern: String,
+}
+
+/// The categories that programs are sorted into, tried in order.
+#[derive(Debug, Clone)]
+pub struct Categories(Vec<Category>);
+
+impl Default for Categories {
+    fn default() -> Self {
+        Self(
+            DEFAULT_CATEGORIES
+                .iter()
+                .map(|(name, pattern)| {
+                    Category::new(name, pattern).expect("built-in patterns are valid")
+                })
+                .collect(),
+        )
+    }
+}
+
+impl Categories {
+    /// Parses a categories file, whose categories are tried before the built-in ones.
+    #[cfg(feature = "fs")]
+    pub fn parse(contents: &str) -> Result<Self, Error> {
+        let file: CategoriesFile =
+            toml::from_str(contents).context("failed to parse categories")?;
+        let mut categories = file
+            .category
+            .into_iter()
+            .map(|rule| {
+                Category::new(&rule.name, &rule.pattern)
+                    .with_context(|| format!("invalid category pattern '{}'", rule.pattern))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        categories.extend(Self::default().0);
+        Ok(Self(categories))
+    }
+
+    /// Reads the categories file at `path`.
+    #[cfg(feature = "fs")]
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read categories file {}", path.display()))?;
+        Self::parse(&contents)
+            .with_context(|| format!("invalid categories file {}", path.display()))
+    }
+
+    /// Returns the name of the first category that `filename` belongs to.
+    pub fn classify(&self, filename: &str) -> &str {
+        self.0
+            .iter()
+            .find(|category| category.pattern.is_match(filename))
+            .map(|category| category.name.as_str())
+            .unwrap_or(NATIVE)
+    }
+}
+
+/// The time spent running the programs of a category.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CategoryTime {
+    /// The number of execs of programs in the category.
+    pub execs: usize,
+    /// The wall time that processes spent running them in nanoseconds, added up
+    /// across processes.
+    pub time: u128,
+}
+
+/// The time spent in each category of programs in a recording.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InterpreterReport {
+    pub categories: BTreeMap<String, CategoryTime>,
+}
+
+impl InterpreterReport {
+    /// Splits the life of every process at its execs and adds up the time spent
+    /// running each program by its category.
+    ///
+    /// A process runs the same program as its parent until it execs something else,
+    /// so a subshell counts as a shell. Time before the first exec of a process
+    /// whose parent isn't in the recording counts as native.
+    pub fn from_store(store: &EventStore, categories: &Categories) -> Self {
+        let mut report = Self::default();
+        for (pid, buffer) in store.iter() {
+            let Some(first) = buffer.front() else {
+                continue;
+            };
+            let stop = buffer.back().map(|event| event.timestamp()).unwrap_or(0);
+            let mut current = store
+                .parent_of_pid_if_stored(pid)
+                .and_then(|parent| store.events_of(parent))
+                .and_then(|parent| program_at(parent, first.timestamp()))
+                .map(|filename| categories.classify(filename))
+                .unwrap_or(NATIVE);
+            let mut since = first.timestamp();
+            for event in buffer.iter() {
+                let Event::ExecFull { filename, .. } = event else {
+                    continue;
+                };
+                report.add(current, event.timestamp().saturating_sub(since));
+                current = categories.classify(filename);
+                report
+                    .categories
+                    .entry(current.to_string())
+                    .or_default()
+                    .execs += 1;
+                since = event.timestamp();
+            }
+            report.add(current, stop.saturating_sub(since));
+        }
+        report
+    }
+
+    fn add(&mut self, category: &str, time: u128) {
+        if time > 0 {
+            self.categories
+                .entry(category.to_string())
+                .or_default()
+                .time += time;
+        }
+    }
+
+    /// Formats the categories as a table, most time first.
+    pub fn to_table(&self) -> String {
+        if self.categories.is_empty() {
+            return "no processes\n".to_string();
+        }
+        let mut categories = self.categories.iter().collect::<Vec<_>>();
+        categories.sort_by_key(|(_, category)| std::cmp::Reverse(category.time));
+        let total = categories
+            .iter()
+            .map(|(_, category)| category.time)
+            .sum::<u128>()
+            .max(1);
+        let width = categories
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0)
+            .max("category".len());
+        let mut table = format!(
+            "{:<width$}  {:>8}  {:>12}  {:>6}\n",
+            "category", "execs", "time", "share"
+        );
+        for (name, category) in categories {
+            table.push_str(&format!(
+                "{:<width$}  {:>8}  {:>12}  {:>5.1}%\n",
+                name,
+                category.execs,
+                format_nanos(category.time),
+                category.time as f64 / total as f64 * 100.0
+            ));
+        }
+        table
+    }
+}
+
+/// The file name of the program that a process was running at `timestamp`, if it had
+/// exec'd anything by then.
+fn program_at(buffer: &VecDeque<Event>, timestamp: u128) -> Option<&str> {
+    buffer
+        .iter()
+        .take_while(|event| event.timestamp() <= timestamp)
+        .filter_map(|event| match event {
+            Event::ExecFull { filename, .. } => Some(filename.as_str()),
+            _ => None,
+        })
+        .last()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::models::ExecArgsKind;
+
+    use super::*;
+
+    fn process(store: &mut EventStore, pid: i32, ppid: i32, start: u128, execs: &[(u128, &str)]) {
+        store.add(
+            pid,
+            Event::Fork {
+                seq: start,
+                timestamp: start,
+                parent_pid: ppid,
+                child_pid: pid,
+                parent_pgid: ppid,
+                credentials: None,
+                comm: None,
+            },
+        );
+        for (timestamp, filename) in execs {
+            store.add(
+                pid,
+                Event::ExecFull {
+                    seq: *timestamp,
+                    timestamp: *timestamp,
+                    pid,
+                    ppid,
+                    pgid: ppid,
+                    filename: filename.to_string(),
+                    args: ExecArgsKind::Joined(filename.to_string()),
+                    credentials: None,
+                },
+            );
+        }
+    }
+
+    fn exit(store: &mut EventStore, pid: i32, ppid: i32, stop: u128) {
+        store.add(
+            pid,
+            Event::Exit {
+                seq: stop,
+                timestamp: stop,
+                pid,
+                ppid,
+                pgid: ppid,
+                exit_code: Some(0),
+            },
+        );
+    }
+
+    #[test]
+    fn classifies_time_by_program() {
+        let mut store = EventStore::new();
+        process(&mut store, 1, 0, 0, &[(0, "/bin/bash")]);
+        // A subshell that runs python after a while
+        process(&mut store, 2, 1, 10, &[(30, "/usr/bin/python3.12")]);
+        exit(&mut store, 2, 1, 80);
+        process(&mut store, 3, 1, 80, &[(80, "/usr/bin/cc")]);
+        exit(&mut store, 3, 1, 95);
+        exit(&mut store, 1, 0, 100);
+
+        let report = InterpreterReport::from_store(&store, &Categories::default());
+        assert_eq!(
+            report.categories["shell"],
+            CategoryTime {
+                execs: 1,
+                time: 120
+            }
+        );
+        assert_eq!(
+            report.categories["python"],
+            CategoryTime { execs: 1, time: 50 }
+        );
+        assert_eq!(
+            report.categories[NATIVE],
+            CategoryTime { execs: 1, time: 15 }
+        );
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn custom_categories_come_first() {
+        let categories =
+            Categories::parse("[[category]]\nname = \"make\"\nmatch = '(^|/)g?make$'\n").unwrap();
+        assert_eq!(categories.classify("/usr/bin/gmake"), "make");
+        assert_eq!(categories.classify("/bin/sh"), "shell");
+        assert_eq!(categories.classify("/usr/bin/ld"), NATIVE);
+        assert!(Categories::parse("[[category]]\nname = \"x\"\npattern = 'x'\n").is_err());
+    }
+}
@@ -0,0 +1,713 @@
+//! Raw events written as JSON lines, for collectors other than the bundled `bpftrace`
+//! script (e.g. other eBPF tools or in-house agents) that don't want to mimic its
+//! output.
+//!
+//! Every line is an object with a `type` and the fields of that type of event:
+//!
+//! ```json
+//! {"type":"fork","ts":100,"pid":2,"ppid":1}
+//! {"type":"exec","ts":150,"pid":2,"filename":"/bin/ls","argv":["ls","-l"]}
+//! {"type":"exit","ts":200,"pid":2,"exit_code":0}
+//! ```
+//!
+//! `ts` is in nanoseconds from any fixed point in time. `seq` orders events with the
+//! same timestamp and defaults to the order of the lines. `ppid` and `pgid` are only
+//! required for forks (and `pgid` not even then), other events default to what's
+//! known about the process. The other fields of each `type` are:
+//!
+//! - `fork`: `pid` is the child and `ppid` is the parent, with an optional `pgid` and
+//!   the `comm` of the child.
+//! - `exec`: the `filename` and `argv` that were exec'd, at least one of which is
+//!   required.
+//! - `badexec`: an exec that failed, with the `filename` it tried and the `errno` it
+//!   failed with if they're known.
+//! - `exit`: the `exit_code`, a wait status like the ones the `bpftrace` script
+//!   records.
+//! - `setsid`: the `sid` of the new session, which defaults to `pid`.
+//! - `setpgid`: the `pgid` that `pid` joined, and the `caller` if it was moved by its
+//!   parent.
+//! - `chdir`: the `path` of the new working directory, which is left out if only a
+//!   file descriptor was given.
+//! - `namespace`: `pid` entering the `namespaces` (e.g. `["mnt","net"]`) with `how`,
+//!   which is one of `clone`, `unshare`, or `setns`.
+//! - `mount`: a filesystem of an optional `fstype` mounted on `target`, or unmounted
+//!   from it if `unmount` is `true`.
+//! - `ptrace`: a `request` of `traceme`, `attach`, or `seize`, the `target` that was
+//!   traced, and the `errno` it failed with if it did.
+//! - `sandbox`: a `mechanism` of `seccomp` (with a `mode` of `strict` or `filter`) or
+//!   `landlock`, optionally with the names of the `flags` it was applied with.
+//! - `subreaper`: `pid` making itself a child subreaper, with no other fields.
+//! - `cpu`: a sample that found `pid` running on a CPU, with no other fields.
+//! - `rss`: a sample of the resident set size of `pid` in `rss_kb`.
+//! - `usage`: what `pid` cost the kernel by the time it exited, with any of
+//!   `minor_faults`, `major_faults`, `voluntary_switches`, `involuntary_switches`,
+//!   `user_ns`, and `system_ns`.
+//!
+//! Lines with any other `type` are kept as custom events of that name, with every field
+//! other than `seq`, `ts`, and `pid` kept as a string.
+
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+
+use crate::models::{
+    Event, ExecArgsKind, NamespaceChange, PtraceRequest, ResourceUsage, SandboxMechanism,
+};
+
+type Error = anyhow::Error;
+
+/// A single line of input.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum RawEvent {
+    Fork {
+        #[serde(flatten)]
+        common: Common,
+        ppid: i32,
+        pgid: Option<i32>,
+        /// The name of the child before it execs, if it ever does.
+        comm: Option<String>,
+    },
+    Exec {
+        #[serde(flatten)]
+        common: Common,
+        ppid: Option<i32>,
+        pgid: Option<i32>,
+        filename: Option<String>,
+        argv: Option<Vec<String>>,
+    },
+    /// An exec that failed.
+    Badexec {
+        #[serde(flatten)]
+        common: Common,
+        filename: Option<String>,
+        errno: Option<i32>,
+    },
+    Exit {
+        #[serde(flatten)]
+        common: Common,
+        ppid: Option<i32>,
+        pgid: Option<i32>,
+        /// A wait status, like the ones the `bpftrace` script records.
+        exit_code: Option<i32>,
+    },
+    Setsid {
+        #[serde(flatten)]
+        common: Common,
+        ppid: Option<i32>,
+        sid: Option<i32>,
+    },
+    /// `pid` joined a process group, by its own call or its parent's (`caller`).
+    Setpgid {
+        #[serde(flatten)]
+        common: Common,
+        ppid: Option<i32>,
+        pgid: i32,
+        caller: Option<i32>,
+    },
+    /// `pid` changed its working directory.
+    Chdir {
+        #[serde(flatten)]
+        common: Common,
+        path: Option<String>,
+    },
+    /// `pid` created or entered namespaces.
+    Namespace {
+        #[serde(flatten)]
+        common: Common,
+        how: NamespaceChange,
+        #[serde(default)]
+        namespaces: Vec<String>,
+    },
+    /// `pid` mounted or unmounted a filesystem.
+    Mount {
+        #[serde(flatten)]
+        common: Common,
+        target: String,
+        fstype: Option<String>,
+        #[serde(default)]
+        unmount: bool,
+    },
+    /// `pid` started tracing `target`, or asked to be traced.
+    Ptrace {
+        #[serde(flatten)]
+        common: Common,
+        request: PtraceRequest,
+        target: Option<i32>,
+        errno: Option<i32>,
+    },
+    /// `pid` restricted itself with seccomp or Landlock.
+    Sandbox {
+        #[serde(flatten)]
+        common: Common,
+        mechanism: SandboxMechanism,
+        mode: Option<String>,
+        #[serde(default)]
+        flags: Vec<String>,
+    },
+    /// `pid` made itself a child subreaper.
+    Subreaper {
+        #[serde(flatten)]
+        common: Common,
+    },
+    /// `pid` was running on a CPU when it was sampled.
+    Cpu {
+        #[serde(flatten)]
+        common: Common,
+    },
+    /// How much memory `pid` was using when it was sampled.
+    Rss {
+        #[serde(flatten)]
+        common: Common,
+        rss_kb: u64,
+    },
+    /// The page faults and context switches of `pid` by the time it exited.
+    Usage {
+        #[serde(flatten)]
+        common: Common,
+        #[serde(flatten)]
+        usage: ResourceUsage,
+    },
+}
+
+/// The types of [RawEvent], anything else is a custom event.
+const KNOWN_TYPES: [&str; 15] = [
+    "fork",
+    "exec",
+    "badexec",
+    "exit",
+    "setsid",
+    "setpgid",
+    "chdir",
+    "namespace",
+    "mount",
+    "ptrace",
+    "sandbox",
+    "subreaper",
+    "cpu",
+    "rss",
+    "usage",
+];
+
+/// The fields of every event. These are `u64` rather than `u128` like in [Event]
+/// because serde can't buffer a `u128` while it looks for the `type`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+struct Common {
+    seq: Option<u64>,
+    ts: u64,
+    pid: i32,
+}
+
+/// Turns JSON lines into events.
+///
+/// The parser remembers the parent and process group of every process so that they
+/// don't have to be repeated on every line.
+#[derive(Debug, Default)]
+pub struct JsonlParser {
+    /// The number of lines that have been parsed, which is the default `seq`.
+    lines: u128,
+    /// The parent PID and process group of each process.
+    processes: HashMap<i32, (i32, i32)>,
+}
+
+impl JsonlParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a line, returning `None` for blank lines.
+    pub fn parse_line(&mut self, line: impl AsRef<str>) -> Result<Option<Event>, Error> {
+        let line = line.as_ref().trim();
+        if line.is_empty() {
+            return Ok(None);
+        }
+        self.lines += 1;
+        let default_seq = self.lines;
+        let value: serde_json::Value =
+            serde_json::from_str(line).with_context(|| format!("line was not JSON: {line}"))?;
+        let kind = value.get("type").and_then(|kind| kind.as_str());
+        if kind.is_some_and(|kind| !KNOWN_TYPES.contains(&kind)) {
+            return parse_custom(value, default_seq)
+                .with_context(|| format!("line was not a valid custom event: {line}"))
+                .map(Some);
+        }
+        let raw: RawEvent = serde_json::from_value(value)
+            .with_context(|| format!("line was not a valid raw event: {line}"))?;
+        let seq_ts = |common: &Common| {
+            let seq = common.seq.map(u128::from).unwrap_or(default_seq);
+            (seq, u128::from(common.ts))
+        };
+        let event = match raw {
+            RawEvent::Fork {
+                common,
+                ppid,
+                pgid,
+                comm,
+            } => {
+                let (seq, timestamp) = seq_ts(&common);
+                let parent_pgid = pgid
+                    .or_else(|| self.processes.get(&ppid).map(|(_, pgid)| *pgid))
+                    .unwrap_or(ppid);
+                self.processes.insert(common.pid, (ppid, parent_pgid));
+                Event::Fork {
+                    seq,
+                    timestamp,
+                    parent_pid: ppid,
+                    child_pid: common.pid,
+                    parent_pgid,
+                    credentials: None,
+                    comm,
+                }
+            }
+            RawEvent::Exec {
+                common,
+                ppid,
+                pgid,
+                filename,
+                argv,
+            } => {
+                let (seq, timestamp) = seq_ts(&common);
+                let (ppid, pgid) = self.update(common.pid, ppid, pgid);
+                let argv = argv.unwrap_or_default();
+                let filename = filename
+                    .or_else(|| argv.first().cloned())
+                    .ok_or_else(|| anyhow!("exec had neither a filename nor argv: {line}"))?;
+                Event::ExecFull {
+                    seq,
+                    timestamp,
+                    pid: common.pid,
+                    ppid,
+                    pgid,
+                    filename,
+                    args: ExecArgsKind::Args(argv),
+                    credentials: None,
+                }
+            }
+            RawEvent::Badexec {
+                common,
+                filename,
+                errno,
+            } => {
+                let (seq, timestamp) = seq_ts(&common);
+                Event::BadExec {
+                    seq,
+                    timestamp,
+                    pid: common.pid,
+                    filename,
+                    errno,
+                }
+            }
+            RawEvent::Exit {
+                common,
+                ppid,
+                pgid,
+                exit_code,
+            } => {
+                let (seq, timestamp) = seq_ts(&common);
+                let (ppid, pgid) = self.update(common.pid, ppid, pgid);
+                Event::Exit {
+                    seq,
+                    timestamp,
+                    pid: common.pid,
+                    ppid,
+                    pgid,
+                    exit_code,
+                }
+            }
+            RawEvent::Setsid { common, ppid, sid } => {
+                let (seq, timestamp) = seq_ts(&common);
+                // A new session is also a new process group led by the process
+                let (ppid, _) = self.update(common.pid, ppid, Some(common.pid));
+                Event::SetSID {
+                    seq,
+                    timestamp,
+                    pid: common.pid,
+                    ppid,
+                    pgid: common.pid,
+                    sid: sid.unwrap_or(common.pid),
+                }
+            }
+            RawEvent::Setpgid {
+                common,
+                ppid,
+                pgid,
+                caller,
+            } => {
+                let (seq, timestamp) = seq_ts(&common);
+                let caller_pid = caller.filter(|caller| *caller != common.pid);
+                // Only a parent can change the group of another process
+                let (ppid, pgid) = self.update(common.pid, ppid.or(caller_pid), Some(pgid));
+                Event::SetPGID {
+                    seq,
+                    timestamp,
+                    pid: common.pid,
+                    ppid,
+                    pgid,
+                    caller_pid,
+                }
+            }
+            RawEvent::Chdir { common, path } => {
+                let (seq, timestamp) = seq_ts(&common);
+                Event::Chdir {
+                    seq,
+                    timestamp,
+                    pid: common.pid,
+                    path,
+                }
+            }
+            RawEvent::Namespace {
+                common,
+                how,
+                namespaces,
+            } => {
+                let (seq, timestamp) = seq_ts(&common);
+                Event::Namespace {
+                    seq,
+                    timestamp,
+                    pid: common.pid,
+                    how,
+                    namespaces,
+                }
+            }
+            RawEvent::Mount {
+                common,
+                target,
+                fstype,
+                unmount,
+            } => {
+                let (seq, timestamp) = seq_ts(&common);
+                Event::Mount {
+                    seq,
+                    timestamp,
+                    pid: common.pid,
+                    target,
+                    fstype,
+                    unmount,
+                }
+            }
+            RawEvent::Ptrace {
+                common,
+                request,
+                target,
+                errno,
+            } => {
+                let (seq, timestamp) = seq_ts(&common);
+                Event::Ptrace {
+                    seq,
+                    timestamp,
+                    pid: common.pid,
+                    request,
+                    target_pid: target,
+                    errno,
+                }
+            }
+            RawEvent::Sandbox {
+                common,
+                mechanism,
+                mode,
+                flags,
+            } => {
+                let (seq, timestamp) = seq_ts(&common);
+                Event::Sandbox {
+                    seq,
+                    timestamp,
+                    pid: common.pid,
+                    mechanism,
+                    mode,
+                    flags,
+                }
+            }
+            RawEvent::Subreaper { common } => {
+                let (seq, timestamp) = seq_ts(&common);
+                Event::Subreaper {
+                    seq,
+                    timestamp,
+                    pid: common.pid,
+                }
+            }
+            RawEvent::Cpu { common } => {
+                let (seq, timestamp) = seq_ts(&common);
+                Event::CpuSample {
+                    seq,
+                    timestamp,
+                    pid: common.pid,
+                }
+            }
+            RawEvent::Rss { common, rss_kb } => {
+                let (seq, timestamp) = seq_ts(&common);
+                Event::RssSample {
+                    seq,
+                    timestamp,
+                    pid: common.pid,
+                    rss_kb,
+                }
+            }
+            RawEvent::Usage { common, usage } => {
+                let (seq, timestamp) = seq_ts(&common);
+                Event::Usage {
+                    seq,
+                    timestamp,
+                    pid: common.pid,
+                    usage,
+                }
+            }
+        };
+        Ok(Some(event))
+    }
+
+    /// Fills in the parent and process group of `pid` from what's already known, and
+    /// remembers any that were given.
+    fn update(&mut self, pid: i32, ppid: Option<i32>, pgid: Option<i32>) -> (i32, i32) {
+        let known = self.processes.get(&pid).copied();
+        let ppid = ppid.or(known.map(|(ppid, _)| ppid)).unwrap_or(0);
+        let pgid = pgid.or(known.map(|(_, pgid)| pgid)).unwrap_or(pid);
+        self.processes.insert(pid, (ppid, pgid));
+        (ppid, pgid)
+    }
+}
+
+/// Turns an object with an unknown `type` into a custom event.
+fn parse_custom(value: serde_json::Value, default_seq: u128) -> Result<Event, Error> {
+    let serde_json::Value::Object(object) = value else {
+        return Err(anyhow!("event wasn't an object"));
+    };
+    let mut name = String::new();
+    let mut common = serde_json::Map::new();
+    let mut fields = BTreeMap::new();
+    for (key, value) in object {
+        match (key.as_str(), value) {
+            ("type", serde_json::Value::String(kind)) => name = kind,
+            ("seq" | "ts" | "pid", value) => {
+                common.insert(key, value);
+            }
+            (_, serde_json::Value::String(value)) => {
+                fields.insert(key, value);
+            }
+            (_, value) => {
+                fields.insert(key, value.to_string());
+            }
+        }
+    }
+    let common: Common = serde_json::from_value(serde_json::Value::Object(common))?;
+    Ok(Event::Custom {
+        seq: common.seq.map(u128::from).unwrap_or(default_seq),
+        timestamp: u128::from(common.ts),
+        pid: common.pid,
+        name,
+        fields,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FORK: &str = r#"{"type":"fork","ts":100,"pid":2,"ppid":1,"pgid":1}"#;
+
+    /// Parses `line` after the fork of PID 2 from PID 1.
+    fn parse_after_fork(line: &str) -> Event {
+        let mut parser = JsonlParser::new();
+        parser.parse_line(FORK).unwrap();
+        parser.parse_line(line).unwrap().unwrap()
+    }
+
+    #[test]
+    fn parses_forks() {
+        let mut parser = JsonlParser::new();
+        assert_eq!(parser.parse_line("").unwrap(), None);
+        assert_eq!(
+            parser.parse_line(FORK).unwrap(),
+            Some(Event::Fork {
+                seq: 1,
+                timestamp: 100,
+                parent_pid: 1,
+                child_pid: 2,
+                parent_pgid: 1,
+                credentials: None,
+                comm: None,
+            })
+        );
+    }
+
+    #[test]
+    fn parses_execs() {
+        let exec = parse_after_fork(r#"{"type":"exec","ts":150,"pid":2,"argv":["ls","-l"]}"#);
+        assert_eq!(
+            exec,
+            Event::ExecFull {
+                seq: 2,
+                timestamp: 150,
+                pid: 2,
+                ppid: 1,
+                pgid: 1,
+                filename: "ls".to_string(),
+                args: ExecArgsKind::Args(vec!["ls".to_string(), "-l".to_string()]),
+                credentials: None,
+            }
+        );
+    }
+
+    #[test]
+    fn parses_failed_execs() {
+        let badexec =
+            parse_after_fork(r#"{"type":"badexec","ts":150,"pid":2,"filename":"/x","errno":2}"#);
+        assert!(matches!(
+            badexec,
+            Event::BadExec { filename: Some(filename), errno: Some(2), .. } if filename == "/x"
+        ));
+    }
+
+    #[test]
+    fn parses_exits() {
+        let exit = parse_after_fork(r#"{"type":"exit","seq":10,"ts":200,"pid":2,"exit_code":256}"#);
+        let Event::Exit {
+            seq,
+            ppid,
+            exit_code,
+            ..
+        } = exit
+        else {
+            panic!("expected an exit");
+        };
+        assert_eq!((seq, ppid, exit_code), (10, 1, Some(256)));
+    }
+
+    #[test]
+    fn parses_sessions() {
+        let setsid = parse_after_fork(r#"{"type":"setsid","ts":150,"pid":2}"#);
+        assert!(matches!(
+            setsid,
+            Event::SetSID {
+                ppid: 1,
+                pgid: 2,
+                sid: 2,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn parses_process_groups() {
+        let setpgid =
+            parse_after_fork(r#"{"type":"setpgid","ts":150,"pid":2,"pgid":5,"caller":1}"#);
+        assert!(matches!(
+            setpgid,
+            Event::SetPGID {
+                ppid: 1,
+                pgid: 5,
+                caller_pid: Some(1),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn parses_chdirs() {
+        let chdir = parse_after_fork(r#"{"type":"chdir","ts":160,"pid":2,"path":"/tmp"}"#);
+        assert!(matches!(chdir, Event::Chdir { path: Some(path), .. } if path == "/tmp"));
+    }
+
+    #[test]
+    fn parses_namespaces() {
+        let namespace = parse_after_fork(
+            r#"{"type":"namespace","ts":170,"pid":2,"how":"clone","namespaces":["pid"]}"#,
+        );
+        assert!(matches!(
+            namespace,
+            Event::Namespace { how: NamespaceChange::Clone, namespaces, .. } if namespaces == ["pid"]
+        ));
+    }
+
+    #[test]
+    fn parses_mounts() {
+        let mount = parse_after_fork(
+            r#"{"type":"mount","ts":180,"pid":2,"target":"/mnt","fstype":"tmpfs"}"#,
+        );
+        assert!(matches!(mount, Event::Mount { unmount: false, .. }));
+    }
+
+    #[test]
+    fn parses_ptrace_calls() {
+        let ptrace =
+            parse_after_fork(r#"{"type":"ptrace","ts":190,"pid":2,"request":"seize","target":3}"#);
+        assert!(matches!(
+            ptrace,
+            Event::Ptrace {
+                request: PtraceRequest::Seize,
+                target_pid: Some(3),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn parses_sandboxes() {
+        let sandbox =
+            parse_after_fork(r#"{"type":"sandbox","ts":185,"pid":2,"mechanism":"landlock"}"#);
+        assert!(matches!(
+            sandbox,
+            Event::Sandbox {
+                mechanism: SandboxMechanism::Landlock,
+                mode: None,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn parses_subreapers() {
+        let subreaper = parse_after_fork(r#"{"type":"subreaper","ts":187,"pid":2}"#);
+        assert!(matches!(subreaper, Event::Subreaper { pid: 2, .. }));
+    }
+
+    #[test]
+    fn parses_cpu_samples() {
+        let cpu = parse_after_fork(r#"{"type":"cpu","ts":188,"pid":2}"#);
+        assert!(matches!(cpu, Event::CpuSample { pid: 2, .. }));
+    }
+
+    #[test]
+    fn parses_rss_samples() {
+        let rss = parse_after_fork(r#"{"type":"rss","ts":189,"pid":2,"rss_kb":2048}"#);
+        assert!(matches!(rss, Event::RssSample { rss_kb: 2048, .. }));
+    }
+
+    #[test]
+    fn parses_usage() {
+        let usage = parse_after_fork(r#"{"type":"usage","ts":189,"pid":2,"major_faults":5}"#);
+        let Event::Usage { usage, .. } = usage else {
+            panic!("expected a usage event, got {usage}");
+        };
+        assert_eq!(
+            usage,
+            ResourceUsage {
+                major_faults: 5,
+                ..ResourceUsage::default()
+            }
+        );
+    }
+
+    #[test]
+    fn parses_custom_events() {
+        let custom =
+            parse_after_fork(r#"{"type":"cache-miss","ts":300,"pid":2,"key":"abc","size":3}"#);
+        let Event::Custom { name, fields, .. } = custom else {
+            panic!("expected a custom event");
+        };
+        assert_eq!(name, "cache-miss");
+        assert_eq!(
+            (fields["key"].as_str(), fields["size"].as_str()),
+            ("abc", "3")
+        );
+    }
+
+    #[test]
+    fn rejects_incomplete_events() {
+        let mut parser = JsonlParser::new();
+        assert!(parser.parse_line(r#"{"type":"fork","ts":1}"#).is_err());
+        assert!(parser
+            .parse_line(r#"{"type":"exec","ts":1,"pid":3}"#)
+            .is_err());
+    }
+}
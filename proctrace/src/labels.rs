@@ -0,0 +1,255 @@
+//! Friendly names for commands, e.g. `gcc` instead of
+//! `/nix/store/<hash>-gcc-12.3.0/bin/cc -O2 -c main.c`, so that charts are readable
+//! and stay the same across machines and store paths.
+//!
+//! Labels are read from a TOML file with a `[[label]]` table for each rule:
+//!
+//! ```toml
+//! [[label]]
+//! match = '/nix/store/[^/]*-gcc-[^/]*/bin/cc$'
+//! name = "gcc"
+//!
+//! [[label]]
+//! match = '^python3? -m (\w+)'
+//! name = "python: $1"
+//! ```
+//!
+//! A rule matches an exec if its pattern matches either the file name or the command
+//! line, and the first rule that matches wins. The name can refer to the groups that
+//! the pattern captured. Commands that no rule matches are shown in an [ArgvStyle].
+
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+#[cfg(feature = "fs")]
+use anyhow::Context;
+use regex_lite::Regex;
+use serde::Deserialize;
+
+use crate::{
+    cli::ArgvStyle,
+    models::{Event, ExecArgsKind},
+};
+
+#[cfg(feature = "fs")]
+type Error = anyhow::Error;
+
+/// Rules for replacing commands with friendly names.
+#[derive(Debug, Default, Clone)]
+pub struct Labels {
+    rules: Vec<(Regex, String)>,
+    argv_style: ArgvStyle,
+}
+
+/// How many arguments the "smart" [ArgvStyle] keeps after the program.
+const SMART_ARGS: usize = 3;
+
+/// The contents of a labels file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "fs"), allow(dead_code))]
+struct LabelsFile {
+    #[serde(default)]
+    label: Vec<LabelRule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "fs"), allow(dead_code))]
+struct LabelRule {
+    #[serde(rename = "match")]
+    pattern: String,
+    name: String,
+}
+
+impl Labels {
+    /// Creates labels from pairs of patterns and names, in the order they're tried.
+    pub fn new(rules: impl IntoIterator<Item = (Regex, String)>) -> Self {
+        Self {
+            rules: rules.into_iter().collect(),
+            argv_style: ArgvStyle::default(),
+        }
+    }
+
+    /// Shows the commands that no rule matches in `style`.
+    pub fn with_argv_style(mut self, style: ArgvStyle) -> Self {
+        self.argv_style = style;
+        self
+    }
+
+    /// Parses a labels file.
+    #[cfg(feature = "fs")]
+    pub fn parse(contents: &str) -> Result<Self, Error> {
+        let file: LabelsFile = toml::from_str(contents).context("failed to parse labels")?;
+        let rules = file
+            .label
+            .into_iter()
+            .map(|rule| {
+                let pattern = Regex::new(&rule.pattern)
+                    .with_context(|| format!("invalid label pattern '{}'", rule.pattern))?;
+                Ok((pattern, rule.name))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(Self::new(rules))
+    }
+
+    /// Reads the labels file at `path`.
+    #[cfg(feature = "fs")]
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read labels file {}", path.display()))?;
+        Self::parse(&contents).with_context(|| format!("invalid labels file {}", path.display()))
+    }
+
+    /// Returns `true` if there are no rules and commands are shown in full, i.e.
+    /// applying the labels doesn't change anything.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty() && self.argv_style == ArgvStyle::Full
+    }
+
+    /// Returns the name for an exec of `filename` with the command line `cmdline`,
+    /// if a rule matches it.
+    pub fn label(&self, filename: &str, cmdline: &str) -> Option<String> {
+        self.rules.iter().find_map(|(pattern, name)| {
+            let captures = pattern
+                .captures(filename)
+                .or_else(|| pattern.captures(cmdline))?;
+            let mut label = String::new();
+            captures.expand(name, &mut label);
+            Some(label)
+        })
+    }
+
+    /// Replaces the file name and arguments of an exec with its name if a rule
+    /// matches it, otherwise restyles its arguments.
+    pub fn apply(&self, event: &mut Event) {
+        if let Event::ExecFull { filename, args, .. } = event {
+            if let Some(label) = self.label(filename, &args.joined()) {
+                *args = ExecArgsKind::Joined(label.clone());
+                *filename = label;
+            } else {
+                *args = style_argv(args, self.argv_style);
+            }
+        }
+    }
+}
+
+/// Shows the arguments of an exec in `style`.
+///
+/// Joined arguments are split on whitespace, since that's all that's known about
+/// where one ends and the next begins.
+pub fn style_argv(args: &ExecArgsKind, style: ArgvStyle) -> ExecArgsKind {
+    let restyle = |args: &[&str]| -> Vec<String> {
+        let mut styled = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        if let Some(program) = styled.first_mut() {
+            if let Some((_, name)) = program
+                .rsplit_once('/')
+                .filter(|(_, name)| !name.is_empty())
+            {
+                *program = name.to_string();
+            }
+        }
+        if style == ArgvStyle::Smart && styled.len() > SMART_ARGS + 1 {
+            styled.truncate(SMART_ARGS + 1);
+            styled.push("...".to_string());
+        }
+        styled
+    };
+    match (style, args) {
+        (ArgvStyle::Full, _) => args.clone(),
+        (_, ExecArgsKind::Joined(joined)) => {
+            ExecArgsKind::Joined(restyle(&joined.split_whitespace().collect::<Vec<_>>()).join(" "))
+        }
+        (_, ExecArgsKind::Args(args)) => ExecArgsKind::Args(restyle(
+            &args.iter().map(|arg| arg.as_str()).collect::<Vec<_>>(),
+        )),
+    }
+}
+
+#[cfg(all(test, feature = "fs"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn labels_matching_commands() {
+        let labels = Labels::parse(
+            r#"
+            [[label]]
+            match = '/nix/store/[^/]*-gcc-[^/]*/bin/cc$'
+            name = "gcc"
+
+            [[label]]
+            match = '^python3? -m (\w+)'
+            name = "python: $1"
+
+            [[label]]
+            match = 'python'
+            name = "never used"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            labels.label("/nix/store/abc-gcc-12.3.0/bin/cc", "cc -c main.c"),
+            Some("gcc".to_string())
+        );
+        assert_eq!(
+            labels.label("/usr/bin/python3", "python3 -m pytest tests/"),
+            Some("python: pytest".to_string())
+        );
+        assert_eq!(labels.label("/usr/bin/ld", "ld -o main main.o"), None);
+        assert!(Labels::parse("[[label]]\nmatch = '('\nname = 'x'").is_err());
+        assert!(Labels::parse("[[label]]\npattern = 'cc'\nname = 'x'").is_err());
+    }
+
+    #[test]
+    fn styles_unlabeled_commands() {
+        let args = ExecArgsKind::Args(
+            ["/usr/bin/cc", "-O2", "-c", "main.c", "-o", "main.o"]
+                .map(String::from)
+                .to_vec(),
+        );
+        assert_eq!(style_argv(&args, ArgvStyle::Full), args);
+        assert_eq!(
+            style_argv(&args, ArgvStyle::Basename).joined(),
+            "cc -O2 -c main.c -o main.o"
+        );
+        assert_eq!(
+            style_argv(&args, ArgvStyle::Smart).joined(),
+            "cc -O2 -c main.c ..."
+        );
+        let joined = ExecArgsKind::Joined("./configure --prefix=/usr".to_string());
+        assert_eq!(
+            style_argv(&joined, ArgvStyle::Smart),
+            ExecArgsKind::Joined("configure --prefix=/usr".to_string())
+        );
+
+        // Labeled commands keep their names
+        let labels = Labels::parse("[[label]]\nmatch = '^/usr/bin/ld$'\nname = 'linker'")
+            .unwrap()
+            .with_argv_style(ArgvStyle::Smart);
+        assert!(!labels.is_empty());
+        let exec = |filename: &str, args: &ExecArgsKind| Event::ExecFull {
+            seq: 0,
+            timestamp: 0,
+            pid: 1,
+            ppid: 0,
+            pgid: 1,
+            filename: filename.to_string(),
+            args: args.clone(),
+            credentials: None,
+        };
+        let mut event = exec("/usr/bin/ld", &args);
+        labels.apply(&mut event);
+        assert_eq!(
+            event,
+            exec("linker", &ExecArgsKind::Joined("linker".to_string()))
+        );
+        let mut event = exec("/usr/bin/cc", &args);
+        labels.apply(&mut event);
+        assert_eq!(
+            event,
+            exec("/usr/bin/cc", &style_argv(&args, ArgvStyle::Smart))
+        );
+    }
+}
@@ -1,10 +1,71 @@
+//! The core of `proctrace`.
+//!
+//! The `models`, `ingest`, and `render` modules only operate on readers and writers,
+//! so they compile for targets without a filesystem or process spawning
+//! (e.g. `wasm32-unknown-unknown`) when built with `--no-default-features`.
+//! Filesystem helpers are gated behind the `fs` feature and recording is gated behind
+//! the `record` feature.
+
+pub mod analyze;
+pub mod blame;
+pub mod builder;
+#[cfg(feature = "fs")]
+pub mod bundle;
+pub mod cargo;
+pub mod churn;
 pub mod cli;
+pub mod compare;
+#[cfg(feature = "fs")]
+pub mod config;
+#[cfg(all(unix, feature = "record"))]
+pub mod control;
+pub mod crashes;
+#[cfg(feature = "record")]
+pub mod dashboard;
+pub mod execfail;
+pub mod folded;
+pub mod format;
+#[cfg(all(target_os = "linux", feature = "record"))]
+pub mod ftrace;
+pub mod gaps;
+pub mod groups;
+pub mod host;
+pub mod info;
 pub mod ingest;
+pub mod interpreters;
+pub mod jsonl;
+pub mod labels;
+pub mod merge;
+#[cfg(feature = "record")]
+pub mod metrics;
+#[cfg(all(target_os = "linux", feature = "fs"))]
+pub mod mmap;
 pub mod models;
+pub mod overhead;
+pub mod parallelism;
+#[cfg(all(target_os = "linux", feature = "record"))]
+pub mod perf;
+#[cfg(feature = "fs")]
+pub mod plugin;
+#[cfg(all(target_os = "linux", feature = "record"))]
+pub mod poll;
+pub mod presets;
+pub mod prune;
+pub mod reader;
 pub mod record;
 pub mod render;
+pub mod repeats;
+#[cfg(all(target_os = "linux", feature = "record"))]
+pub mod script;
+pub mod search;
+#[cfg(feature = "fs")]
+pub mod segments;
+#[cfg(feature = "fs")]
+pub mod serve;
+#[cfg(feature = "fs")]
+pub mod sort;
+pub mod storm;
+pub mod usage;
+#[cfg(feature = "fs")]
 pub mod utils;
 pub mod writers;
-
-#[cfg(target_os = "linux")]
-const SCRIPT: &'static str = include_str!("../assets/proctrace.bt");
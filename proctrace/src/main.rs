@@ -1,60 +1,307 @@
-use crate::cli::Cli;
-use clap::Parser;
-use cli::Command;
-use ingest::ingest_raw;
-#[cfg(target_os = "linux")]
-use record::record;
-use render::{render, render_sequential};
+use std::{net::TcpListener, path::Path, sync::Arc};
 
-#[cfg(target_os = "linux")]
-use std::sync::{atomic::AtomicBool, Arc};
+use clap::{CommandFactory, FromArgMatches};
+use proctrace::{
+    analyze::{BenchSummary, PhaseReport, RunSummary},
+    blame::BlameReport,
+    bundle::Bundle,
+    cargo::CrateReport,
+    churn::ChurnReport,
+    cli::{AnalyzeCommand, Cli, Command, Compression, DisplayMode, GroupArgs, Preset},
+    compare::comparison_table,
+    crashes::CrashReport,
+    execfail::FailedExecReport,
+    folded::FoldedStacks,
+    format::Selection,
+    gaps::IdleGapReport,
+    groups::{GroupReport, Tagger},
+    info::Capabilities,
+    ingest::{ingest_raw, Adoption, EventIngester},
+    interpreters::{Categories, InterpreterReport},
+    labels::Labels,
+    merge::{merge, Source},
+    models::{EventStore, Metadata, UserFilter},
+    overhead::OverheadReport,
+    parallelism::{ConcurrencyReport, ParallelismReport, TIMELINE_BUCKETS},
+    presets::rewrite_store_paths,
+    prune::{prune, PruneOptions},
+    reader::TraceReader,
+    render::{
+        read_events, read_events_from_slice, read_selected_events, render, render_events,
+        render_overlay, render_sequential, render_streaming, render_to_dir, scan_selected_events,
+        GhaSummaryRenderer, ProcessTree, RenderOptions, Renderer, DEFAULT_REORDER_WINDOW,
+        FOLLOW_REORDER_WINDOW,
+    },
+    repeats::RepeatReport,
+    search::search,
+    segments::{is_manifest_path, Manifest},
+    serve::{publish_recording, publish_shipped, serve as serve_events, Broadcaster},
+    sort::external_sort,
+    storm::{StormDetector, StormReport},
+    usage::UsageReport,
+    utils::{
+        file_compression, make_path_absolute, new_buffered_input_stream, new_buffered_output_stream,
+    },
+    writers::{new_event_writer, NoOpWriter, OutputStream},
+};
 
-use utils::{new_buffered_input_stream, new_buffered_output_stream};
-use writers::NoOpWriter;
+#[cfg(all(target_os = "linux", feature = "record"))]
+use proctrace::{
+    cli::{BenchArgs, OutFormat},
+    config::Config,
+    control::ControlSocket,
+    metrics::{serve as serve_metrics, RecorderMetrics},
+    record::{record, wall_clock_now, RecordOptions},
+    segments::{RotationPolicy, Run, SegmentedWriter, BENCH_MANIFEST},
+    utils::{new_output_stream, read_env_file},
+    writers::{
+        EventWrite, FinishingWriter, JournalWriter, RawWriter, SocketWriter, StreamWriter,
+        TeeWriter,
+    },
+};
+#[cfg(all(target_os = "linux", feature = "record"))]
+use std::{
+    io::IsTerminal,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
-#[cfg(target_os = "linux")]
 use anyhow::Context;
 
 type Error = anyhow::Error;
 
-#[cfg(target_os = "linux")]
-const SCRIPT: &'static str = include_str!("../assets/proctrace.bt");
-
-mod cli;
-mod ingest;
-mod models;
-mod record;
-mod render;
-mod utils;
-mod writers;
-
 fn main() -> Result<(), Error> {
-    let args = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let args = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
 
     match args.command {
-        #[cfg(target_os = "linux")]
-        Command::Record(args) => {
+        #[cfg(all(target_os = "linux", feature = "record"))]
+        Command::Record(mut args) => {
+            if let Some(ref command_line) = args.shell {
+                let shell = std::env::var("SHELL")
+                    .ok()
+                    .filter(|shell| !shell.is_empty())
+                    .unwrap_or_else(|| "/bin/sh".to_string());
+                args.cmd = vec![shell, "-c".to_string(), command_line.clone()];
+            }
             if args.cmd.is_empty() {
                 anyhow::bail!("must provide a command to run");
             }
+            if args.tui && !std::io::stderr().is_terminal() {
+                anyhow::bail!("--tui needs stderr to be a terminal");
+            }
+            if let Some(ref name) = args.profile {
+                let config_path = args
+                    .config
+                    .clone()
+                    .or_else(Config::default_path)
+                    .context("--profile was passed but no config file was found")?;
+                let config = Config::read(&config_path)?;
+                let (_, record_matches) = matches.subcommand().context("missing subcommand")?;
+                config.profile(name)?.apply(&mut args, record_matches);
+            }
+            let rotation = args.rotation_policy();
             let shutdown_flag = Arc::new(AtomicBool::new(false));
             let _ = signal_hook::flag::register(nix::libc::SIGINT, Arc::clone(&shutdown_flag))
                 .context("failed to install signal handler")?;
-            let mut user_cmd = std::process::Command::new(&args.cmd[0]);
-            user_cmd.args(&args.cmd[1..]);
-
-            let writer = new_buffered_output_stream(&args.output_path)?;
-            let mut ingester = record(
-                user_cmd,
-                args.bpftrace_path,
-                shutdown_flag.clone(),
-                args.debug,
-                args.raw,
-                writer,
-            )
-            .context("failed while recording events")?;
+            let systemd_unit = args
+                .systemd_scope
+                .then(|| format!("proctrace-{}", std::process::id()));
+            let mut user_cmd = match systemd_unit {
+                Some(ref unit) => {
+                    let mut cmd = std::process::Command::new("systemd-run");
+                    cmd.args(["--user", "--scope", "--quiet", "--collect"])
+                        .arg(format!("--unit={unit}"))
+                        .arg("--")
+                        .args(&args.cmd);
+                    cmd
+                }
+                None => {
+                    let mut cmd = std::process::Command::new(&args.cmd[0]);
+                    cmd.args(&args.cmd[1..]);
+                    cmd
+                }
+            };
+
+            let bundle = args.bundle.as_ref().map(Bundle::create).transpose()?;
+            if let Some(ref bundle) = bundle {
+                args.raw_output = Some(bundle.raw_path());
+                args.output_path = Some(bundle.events_path_for(args.format));
+            }
+            if args.stream {
+                if args.output_path.is_none() {
+                    anyhow::bail!(
+                        "--stream writes events to stdout, so an output path is required"
+                    );
+                }
+                // Keep stdout for the events
+                user_cmd.stdout(std::io::stderr());
+            }
+            if let Some(ref path) = args.env_file {
+                user_cmd.envs(read_env_file(path)?);
+            }
+            user_cmd.envs(args.env.iter().cloned());
+            if let Some(ref dir) = args.cd {
+                if !dir.is_dir() {
+                    anyhow::bail!("--cd: {} isn't a directory", dir.display());
+                }
+                user_cmd.current_dir(dir);
+            }
+            if args.quiet_child {
+                user_cmd
+                    .stdout(std::process::Stdio::null())
+                    .stderr(std::process::Stdio::null());
+            }
+            let child_stdout = args
+                .child_stdout
+                .as_ref()
+                .map(|path| {
+                    std::fs::File::create(path)
+                        .with_context(|| format!("failed to create {}", path.display()))
+                })
+                .transpose()?;
+            let child_stderr = match (&args.child_stderr, &child_stdout) {
+                // Shared so that the two streams don't overwrite each other
+                (Some(path), Some(stdout)) if Some(path) == args.child_stdout.as_ref() => Some(
+                    stdout
+                        .try_clone()
+                        .context("failed to share --child-stderr")?,
+                ),
+                (Some(path), _) => Some(
+                    std::fs::File::create(path)
+                        .with_context(|| format!("failed to create {}", path.display()))?,
+                ),
+                (None, _) => None,
+            };
+            if let Some(file) = child_stdout {
+                user_cmd.stdout(file);
+            }
+            if let Some(file) = child_stderr {
+                user_cmd.stderr(file);
+            }
+            // Only open the output for the raw events if we're going to write them,
+            // otherwise the processed events are written once recording finishes.
+            // `Some(None)` writes the raw events to stdout.
+            let out_format = args.resolved_out_format()?;
+            let raw_output_path = match out_format {
+                OutFormat::Processed => None,
+                OutFormat::Raw => Some(args.output_path.clone()),
+                OutFormat::Both => Some(Some(
+                    args.both_raw_output_path()
+                        .context("--out-format both needs an output path or --raw-output")?,
+                )),
+            };
+            let mut live_writer: Box<dyn EventWrite> = Box::new(NoOpWriter);
+            let mut rotation_requested = None;
+            if let Some(ref raw_output_path) = raw_output_path {
+                let output: Box<dyn OutputStream> = match (args.control.as_ref(), raw_output_path) {
+                    // The control socket can rotate segments on demand
+                    (Some(_), Some(path)) if rotation.is_enabled() => {
+                        let requested = Arc::new(AtomicBool::new(false));
+                        rotation_requested = Some(requested.clone());
+                        let writer = SegmentedWriter::new(path, args.compress, rotation)?
+                            .with_rotation_requests(requested);
+                        Box::new(writer)
+                    }
+                    _ => new_output_stream(raw_output_path, args.compress, rotation)?,
+                };
+                let raw_writer = FinishingWriter::new(RawWriter::new(output), RawWriter::get_mut);
+                live_writer = Box::new(TeeWriter::new(live_writer, raw_writer));
+            }
+            let control = args
+                .control
+                .as_ref()
+                .map(|path| ControlSocket::bind(path).map(Arc::new))
+                .transpose()?;
+            if let Some(ref target) = args.ship {
+                let socket_writer = SocketWriter::connect(target)?;
+                live_writer = Box::new(TeeWriter::new(live_writer, socket_writer));
+            }
+            if args.journal {
+                let journal_writer = JournalWriter::connect()?;
+                live_writer = Box::new(TeeWriter::new(live_writer, journal_writer));
+            }
+            if args.stream {
+                let stream_writer = StreamWriter::new(std::io::stdout());
+                live_writer = Box::new(TeeWriter::new(live_writer, stream_writer));
+            }
+            let settings = args.preset.map(|p| p.settings()).unwrap_or_default();
+            let adoption = Adoption {
+                exec: settings
+                    .adopt_exec
+                    .iter()
+                    .map(|pattern| pattern.to_string())
+                    .chain(args.adopt_exec.iter().cloned())
+                    .map(|pattern| {
+                        regex_lite::Regex::new(&pattern)
+                            .with_context(|| format!("invalid --adopt-exec pattern '{pattern}'"))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+                cgroups: args
+                    .cgroup
+                    .iter()
+                    .map(|path| cgroup_id(path))
+                    .chain(
+                        args.container
+                            .iter()
+                            .map(|id| container_cgroup_id(&args.container_runtime, id)),
+                    )
+                    .collect::<Result<Vec<_>, _>>()?,
+                follow_root_cgroup: args.systemd_scope,
+                parents: vec![],
+            };
+            let metrics = match args.metrics_addr {
+                Some(addr) => {
+                    let metrics = Arc::new(RecorderMetrics::default());
+                    let addr = serve_metrics(addr, metrics.clone())?;
+                    eprintln!("Serving metrics at http://{addr}/metrics");
+                    Some(metrics)
+                }
+                None => None,
+            };
+            let started_at = wall_clock_now();
+            let options = RecordOptions {
+                backend: args.backend,
+                bpftrace_path: args.bpftrace_path,
+                poll_interval: std::time::Duration::from_millis(args.poll_interval.max(1)),
+                debug: args.debug,
+                record_raw: raw_output_path.is_some(),
+                adoption,
+                metrics,
+                host_sample_interval: args.sample_host.map(std::time::Duration::from_millis),
+                rss_sample_interval: args.sample_rss.map(std::time::Duration::from_millis),
+                storm_detector: (args.storm_threshold > 0)
+                    .then(|| StormDetector::new(args.storm_threshold, args.storm_window)),
+                kill_on_storm: args.kill_on_storm,
+                follow_daemons: !args.no_follow_daemons,
+                buffer_window: args.buffer_window,
+                sample_trees: args.sample_trees,
+                sample_seed: args.sample_seed,
+                tui: args.tui,
+                trace_mounts: args.trace_mounts,
+                sample_cpu: args.sample_cpu,
+                events: args.events,
+                filter_in_kernel: !args.no_kernel_filter,
+                started_at: Some(started_at),
+                control,
+                rotation_requested,
+            };
+            let mut ingester = record(user_cmd, shutdown_flag.clone(), options, live_writer)
+                .context("failed while recording events")?;
             ingester.post_process_buffers();
-            if args.raw {
+            if args.rewrite_store_paths || settings.rewrite_store_paths {
+                ingester.rewrite_exec_strings(rewrite_store_paths);
+            }
+            if let Some(phases) = settings.phases() {
+                let report = PhaseReport::from_store(ingester.tracked_events(), &phases);
+                eprint!("{}", report.to_table());
+            }
+            if settings.crate_report {
+                eprint!(
+                    "{}",
+                    CrateReport::from_store(ingester.tracked_events()).to_table()
+                );
+            }
+            if raw_output_path.is_some() {
                 eprintln!(
                     "Process tree root was PID {}",
                     ingester
@@ -62,25 +309,705 @@ fn main() -> Result<(), Error> {
                         .map(|pid| format!("{pid}"))
                         .unwrap_or("UNSET".to_string())
                 );
-            } else {
-                let writer = new_buffered_output_stream(&args.output_path)?;
+            }
+            if out_format != OutFormat::Raw {
+                // Written all at once, so splitting it by time would leave one segment
+                let rotation = RotationPolicy {
+                    max_age: None,
+                    ..rotation
+                };
+                let writer = new_output_stream(&args.output_path, args.compress, rotation)?;
+                let mut writer = new_event_writer(writer, args.format);
+                let mut metadata = Metadata::new(ingester.root_pid());
+                metadata.command = args.cmd.clone();
+                metadata.adopted_pids = ingester.adopted_pids().to_vec();
+                metadata.cgroups = ingester.adopted_cgroups().to_vec();
+                metadata.systemd_unit = systemd_unit;
+                metadata.container = args.container.clone();
+                metadata.ns_pids = ingester.ns_pids().clone();
+                metadata.started_at = Some(started_at);
+                metadata.shell_wrapper = args.shell.is_some();
+                metadata.adjusted_timestamps = ingester.adjusted_timestamps();
+                let cwd = match args.cd {
+                    Some(ref dir) => make_path_absolute(dir),
+                    None => std::env::current_dir().map_err(Error::from),
+                };
+                metadata.cwd = cwd.ok().map(|dir| dir.display().to_string());
+                writer.write_metadata(&metadata)?;
                 render_sequential(ingester, writer)?;
+                if let Some(bundle) = bundle {
+                    bundle.write_metadata(&metadata)?;
+                    bundle.write_report()?;
+                    eprintln!("Wrote recording bundle to {}", bundle.dir().display());
+                }
             }
         }
+        #[cfg(all(target_os = "linux", feature = "record"))]
+        Command::Bench(args) => bench(args)?,
         Command::Render(args) => {
-            let reader = new_buffered_input_stream(&args.input_path)?;
-            let writer = new_buffered_output_stream(&args.output_path)?;
-            render(reader, writer, args.display_mode)?;
+            let reader = || match args.input_path {
+                Some(ref input_path) => new_buffered_input_stream(input_path),
+                None => Err(anyhow::anyhow!("an input path is required")),
+            };
+            let writer = || new_buffered_output_stream(&args.output_path, args.compress);
+            let selection = Selection {
+                root_pid: args.pid,
+                since: args.since.map(|ms| ms as u128 * 1_000_000),
+            };
+            let users = UserFilter {
+                include: args.include_uid.clone(),
+                exclude: args.exclude_uid.clone(),
+            };
+            let ingest = || {
+                // Only plain files can be mapped or seeked through
+                let plain_file = args.input_path.as_ref().filter(|path| {
+                    path.is_file()
+                        && !is_manifest_path(path)
+                        && file_compression(path).is_ok_and(|c| c == Compression::None)
+                });
+                match (plain_file, selection.is_everything()) {
+                    (Some(path), true) if !args.no_mmap => read_mapped_events(path),
+                    (_, true) => read_events(reader()?),
+                    (Some(path), false) => {
+                        let file =
+                            std::fs::File::open(path).context("failed to open input file")?;
+                        read_selected_events(std::io::BufReader::new(file), &selection)
+                    }
+                    (None, false) => scan_selected_events(reader()?, &selection),
+                }
+                .context("failed to read events from input")
+                .map(|mut ingester| {
+                    ingester.retain_users(&users);
+                    ingester
+                })
+            };
+            let options = RenderOptions {
+                collapse_exec_chains: args.collapse_exec_chains,
+                highlight_over: args.highlight_over.map(|ms| ms as u128 * 1_000_000),
+                hide_shell_wrapper: args.hide_shell_wrapper,
+                heading: args.heading,
+                sort_by: args.sort_by,
+                limit: args.limit,
+                labels: args
+                    .labels
+                    .as_ref()
+                    .map(Labels::read)
+                    .transpose()?
+                    .unwrap_or_default()
+                    .with_argv_style(args.argv_style),
+                tagger: tagger(&args.group)?,
+                ..Default::default()
+            };
+            if options.tagger.is_some() && (args.stream || args.follow) {
+                anyhow::bail!("--group-by-dir and --tags can't be used with --stream or --follow");
+            }
+            let output_dir = args
+                .output_path
+                .as_ref()
+                .filter(|path| path.is_dir() || path.to_string_lossy().ends_with('/'));
+            if let Some(ref overlay) = args.overlay {
+                if args.display_mode != DisplayMode::Mermaid {
+                    anyhow::bail!("--overlay can only be used with the mermaid display mode");
+                }
+                let before = new_buffered_input_stream(&overlay[0])?;
+                let after = new_buffered_input_stream(&overlay[1])?;
+                let mut output = writer()?;
+                render_overlay(before, after, &mut output, &options)?;
+                output.finish()?;
+            } else if let Some(dir) = output_dir {
+                if args.compress.is_some() || args.stream || args.follow || args.baseline.is_some()
+                {
+                    anyhow::bail!(
+                        "--compress, --stream, and --baseline can't be used when rendering to a directory"
+                    );
+                }
+                render_to_dir(ingest()?, dir, args.display_mode, &options)?;
+            } else if let Some(ref baseline_path) = args.baseline {
+                if args.display_mode != DisplayMode::GhaSummary {
+                    anyhow::bail!("--baseline can only be used with the gha-summary display mode");
+                }
+                let renderer = GhaSummaryRenderer {
+                    options: options.clone(),
+                    baseline: Some((load_summary(baseline_path)?, args.warn_if.clone())),
+                };
+                let tree = ProcessTree::prepare(ingest()?, &options.labels)?;
+                let mut output = writer()?;
+                renderer.render(&tree, &mut output)?;
+                output.finish()?;
+            } else if args.follow {
+                let mut output = writer()?;
+                // Flushed line by line so that the output keeps up with the recording
+                let writer = std::io::LineWriter::new(&mut output);
+                let window = args.reorder_window.unwrap_or(FOLLOW_REORDER_WINDOW);
+                render_streaming(reader()?, writer, args.display_mode, window, &options)?;
+                output.finish()?;
+            } else if args.stream {
+                let mut output = writer()?;
+                let window = args.reorder_window.unwrap_or(DEFAULT_REORDER_WINDOW);
+                render_streaming(reader()?, &mut output, args.display_mode, window, &options)?;
+                output.finish()?;
+            } else {
+                let mut output = writer()?;
+                render_events(ingest()?, &mut output, args.display_mode, &options)?;
+                output.finish()?;
+            }
         }
         Command::Ingest(args) => {
             let reader = new_buffered_input_stream(&args.input_path)?;
-            let write_stream = new_buffered_output_stream(&args.output_path)?;
-            let dummy_writer = NoOpWriter;
-            let mut ingester = ingest_raw(args.debug, args.root_pid, reader, dummy_writer)?;
-            ingester.post_process_buffers();
-            render_sequential(ingester, write_stream)?;
+            let write_stream = new_buffered_output_stream(&args.output_path, args.compress)?;
+            let adoption = Adoption {
+                parents: args
+                    .adopt_parent
+                    .iter()
+                    .map(|parent| parent.parse())
+                    .collect::<Result<Vec<_>, _>>()
+                    .context("invalid --adopt-parent")?,
+                ..Default::default()
+            };
+            let mut ingester = EventIngester::new(Some(args.root_pid), Some(NoOpWriter));
+            if args.no_follow_daemons {
+                ingester.stop_following_daemons();
+            }
+            ingester.set_adoption(adoption);
+            if let Some(window) = args.buffer_window {
+                ingester.set_buffer_window(window);
+            }
+            if let Some(ref path) = args.keep_unmatched {
+                let stream = new_buffered_output_stream(&Some(path), None)?;
+                ingester.set_unmatched_sink(new_event_writer(stream, args.format));
+            }
+            let mut ingester = ingest_raw(args.debug, ingester, args.input_format, reader)?;
+            let unmatched = ingester.finalize_unmatched()?;
+            if args.debug && args.keep_unmatched.is_some() {
+                eprintln!("{unmatched} unmatched events written");
+            }
+            let mut writer = new_event_writer(write_stream, args.format);
+            let mut metadata = Metadata::new(Some(args.root_pid));
+            metadata.adjusted_timestamps = ingester.adjusted_timestamps();
+            metadata.reparented = ingester.reparented().clone();
+            writer.write_metadata(&metadata)?;
+            render_sequential(ingester, writer)?;
+        }
+        Command::Sort(args) => {
+            let reader = new_buffered_input_stream(&args.input_path)?;
+            let reader = TraceReader::detect(std::io::BufReader::new(reader))?;
+            let write_stream = new_buffered_output_stream(&args.output_path, args.compress)?;
+            let mut writer = new_event_writer(write_stream, args.format);
+            if let Some(metadata) = reader.metadata() {
+                writer.write_metadata(metadata)?;
+            }
+            external_sort(reader, writer, args.chunk_size)?;
+        }
+        Command::Merge(args) => {
+            if let Some((path, _)) = args
+                .clock_offset
+                .iter()
+                .find(|(path, _)| !args.inputs.contains(path))
+            {
+                anyhow::bail!(
+                    "--clock-offset names {}, which isn't an input",
+                    path.display()
+                );
+            }
+            let mut sources = vec![];
+            for path in args.inputs.iter() {
+                let clock_offset = args
+                    .clock_offset
+                    .iter()
+                    .filter(|(offset_path, _)| offset_path == path)
+                    .map(|(_, ms)| *ms as i128 * 1_000_000)
+                    .sum();
+                let reader = new_buffered_input_stream(path)?;
+                let source = Source::read(
+                    path.display().to_string(),
+                    std::io::BufReader::new(reader),
+                    clock_offset,
+                )
+                .with_context(|| format!("failed to read {}", path.display()))?;
+                sources.push(source);
+            }
+            let merged = merge(sources)?;
+            for warning in merged.warnings.iter() {
+                eprintln!("warning: {warning}");
+            }
+            let write_stream = new_buffered_output_stream(&args.output_path, args.compress)?;
+            let mut writer = new_event_writer(write_stream, args.format);
+            writer.write_metadata(&merged.metadata)?;
+            for event in merged.events.iter() {
+                writer.write_event(event)?;
+            }
+            writer.finalize()?;
+        }
+        Command::Prune(args) => {
+            let options = PruneOptions {
+                min_duration: args.min_duration,
+                drop_cmd: args
+                    .drop_cmd
+                    .iter()
+                    .map(|pattern| {
+                        regex_lite::Regex::new(pattern)
+                            .with_context(|| format!("invalid --drop-cmd pattern '{pattern}'"))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+            };
+            let reader = new_buffered_input_stream(&args.input_path)?;
+            let reader = TraceReader::detect(std::io::BufReader::new(reader))?;
+            let mut metadata = reader.metadata().cloned();
+            let mut store = EventStore::new();
+            // Host samples and warnings don't belong to a process, so they're kept as is
+            let mut other_events = vec![];
+            for event in reader {
+                let event = event?;
+                if event.is_process_event() {
+                    store.add(event.pid(), event);
+                } else {
+                    other_events.push(event);
+                }
+            }
+            let keep = metadata
+                .iter()
+                .flat_map(|metadata| metadata.root_pid.iter().chain(metadata.adopted_pids.iter()))
+                .copied()
+                .collect::<Vec<_>>();
+            let pruned = prune(&mut store, &keep, &options);
+            let write_stream = new_buffered_output_stream(&args.output_path, args.compress)?;
+            let mut writer = new_event_writer(write_stream, args.format);
+            if let Some(ref mut metadata) = metadata {
+                for (pid, count) in pruned {
+                    *metadata.pruned.entry(pid).or_default() += count;
+                }
+                writer.write_metadata(metadata)?;
+            }
+            let mut events = store
+                .events_ordered()
+                .chain(other_events)
+                .collect::<Vec<_>>();
+            events.sort();
+            for event in events.iter() {
+                writer.write_event(event)?;
+            }
+            writer.finalize()?;
+        }
+        Command::Compare(args) => {
+            let baseline = load_summary(&args.baseline)?;
+            let current = load_summary(&args.current)?;
+            print!("{}", comparison_table(&baseline, &current));
+            if let Some(fail_if) = args.fail_if {
+                let failures = fail_if.failures(&baseline, &current);
+                if !failures.is_empty() {
+                    let failures = failures
+                        .iter()
+                        .map(|condition| condition.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    anyhow::bail!("regression thresholds exceeded: {failures}");
+                }
+            }
         }
+        Command::Search(args) => {
+            let pattern = if args.ignore_case {
+                format!("(?i){}", args.pattern)
+            } else {
+                args.pattern.clone()
+            };
+            let pattern = regex_lite::Regex::new(&pattern)
+                .with_context(|| format!("invalid pattern '{}'", args.pattern))?;
+            let reader = new_buffered_input_stream(&args.input_path)?;
+            let ingester = read_events(reader).context("failed to read events from input")?;
+            let matches = search(ingester.tracked_events(), &pattern);
+            if matches.is_empty() {
+                anyhow::bail!("no processes matched '{}'", args.pattern);
+            }
+            for search_match in matches.iter() {
+                println!("{}", search_match.to_line());
+            }
+        }
+        Command::Analyze(AnalyzeCommand::Activation(args)) => {
+            let reader = new_buffered_input_stream(&args.input_path)?;
+            let mut ingester = read_events(reader).context("failed to read events from input")?;
+            let settings = Preset::Activation.settings();
+            if settings.rewrite_store_paths {
+                ingester.rewrite_exec_strings(rewrite_store_paths);
+            }
+            let phases = settings.phases().expect("the activation preset has phases");
+            let report = PhaseReport::from_store(ingester.tracked_events(), &phases);
+            print_report(&report, args.json, PhaseReport::to_table)?;
+        }
+        Command::Serve(args) => {
+            let broadcaster = Arc::new(Broadcaster::new());
+            if let Some(listen) = args.listen {
+                let listener = TcpListener::bind(listen)
+                    .with_context(|| format!("failed to listen on {listen}"))?;
+                eprintln!("Waiting for recordings shipped to tcp://{listen}");
+                let broadcaster = broadcaster.clone();
+                std::thread::spawn(move || publish_shipped(listener, &broadcaster));
+            } else if let Some(ref input_path) = args.input_path {
+                let reader = new_buffered_input_stream(input_path)?;
+                publish_recording(std::io::BufReader::new(reader), &broadcaster)?;
+            }
+            let listener = TcpListener::bind(args.addr)
+                .with_context(|| format!("failed to listen on {}", args.addr))?;
+            eprintln!("Serving events at http://{}/events", args.addr);
+            serve_events(listener, broadcaster)?;
+        }
+        Command::Info(args) => {
+            let capabilities = Capabilities::current();
+            if args.json {
+                println!("{}", serde_json::to_string_pretty(&capabilities)?);
+            } else {
+                print!("{}", capabilities.to_text());
+            }
+        }
+        Command::Open(args) => {
+            let bundle = Bundle::open(&args.dir)?;
+            if let Some(mode) = args.display_mode {
+                let reader = new_buffered_input_stream(&bundle.events_path()?)?;
+                let writer = new_buffered_output_stream(&None::<&Path>, None)?;
+                render(reader, writer, mode, &RenderOptions::default())?;
+            } else {
+                if args.rerender || !bundle.report_path().exists() {
+                    bundle.write_report()?;
+                }
+                let report = std::fs::read_to_string(bundle.report_path())
+                    .context("failed to read report")?;
+                print!("{report}");
+            }
+        }
+        Command::Analyze(AnalyzeCommand::Concurrency(args)) => {
+            let reader = new_buffered_input_stream(&args.input.input_path)?;
+            let ingester = read_events(reader).context("failed to read events from input")?;
+            let cores = args.cores.or_else(|| {
+                std::thread::available_parallelism()
+                    .ok()
+                    .map(|cores| cores.get())
+            });
+            let report =
+                ConcurrencyReport::from_store(ingester.tracked_events(), cores, TIMELINE_BUCKETS);
+            print_report(&report, args.input.json, ConcurrencyReport::to_text)?;
+        }
+        Command::Analyze(AnalyzeCommand::Overhead(args)) => {
+            let reader = new_buffered_input_stream(&args.input_path)?;
+            let ingester = read_events(reader).context("failed to read events from input")?;
+            let report = OverheadReport::from_store(
+                ingester.metadata(),
+                ingester.tracked_events(),
+                ingester.overhead(),
+            );
+            print_report(&report, args.json, OverheadReport::to_table)?;
+        }
+        Command::Analyze(AnalyzeCommand::Parallelism(args)) => {
+            let reader = new_buffered_input_stream(&args.input.input_path)?;
+            let ingester = read_events(reader).context("failed to read events from input")?;
+            let report = ParallelismReport::from_store(
+                ingester.tracked_events(),
+                args.jobs,
+                args.max_gap_ms as u128 * 1_000_000,
+                TIMELINE_BUCKETS,
+            );
+            print_report(&report, args.input.json, ParallelismReport::to_text)?;
+        }
+        Command::Analyze(AnalyzeCommand::Cargo(args)) => {
+            let reader = new_buffered_input_stream(&args.input_path)?;
+            let ingester = read_events(reader).context("failed to read events from input")?;
+            let report = CrateReport::from_store(ingester.tracked_events());
+            print_report(&report, args.json, CrateReport::to_table)?;
+        }
+        Command::Analyze(AnalyzeCommand::Blame(args)) => {
+            let reader = new_buffered_input_stream(&args.input.input_path)?;
+            let ingester = read_events(reader).context("failed to read events from input")?;
+            let root_pid = ingester
+                .root_pid()
+                .context("recording has no root process")?;
+            let report = BlameReport::from_store(ingester.tracked_events(), root_pid);
+            print_report(&report, args.input.json, |report| {
+                report.to_table(args.limit)
+            })?;
+        }
+        Command::Analyze(AnalyzeCommand::Folded(args)) => {
+            let folded = |path: &Path| -> Result<FoldedStacks, anyhow::Error> {
+                let reader = new_buffered_input_stream(path)?;
+                let ingester = read_events(reader)
+                    .with_context(|| format!("failed to read events from {}", path.display()))?;
+                Ok(FoldedStacks::from_store(ingester.tracked_events()))
+            };
+            let stacks = folded(&args.input.input_path)?;
+            match args.baseline {
+                Some(ref baseline) if args.input.json => {
+                    let diff = serde_json::json!({
+                        "baseline": folded(baseline)?,
+                        "input": stacks,
+                    });
+                    println!("{}", serde_json::to_string_pretty(&diff)?);
+                }
+                Some(ref baseline) => print!("{}", stacks.to_diff_folded(&folded(baseline)?)),
+                None => print_report(&stacks, args.input.json, FoldedStacks::to_folded)?,
+            }
+        }
+        Command::Analyze(AnalyzeCommand::Churn(args)) => {
+            let reader = new_buffered_input_stream(&args.input.input_path)?;
+            let ingester = read_events(reader).context("failed to read events from input")?;
+            let report = ChurnReport::from_store(
+                ingester.tracked_events(),
+                args.threshold as u128 * 1_000_000,
+            );
+            print_report(&report, args.input.json, ChurnReport::to_table)?;
+        }
+        Command::Analyze(AnalyzeCommand::Gaps(args)) => {
+            let reader = new_buffered_input_stream(&args.input.input_path)?;
+            let ingester = read_events(reader).context("failed to read events from input")?;
+            let root_pid = ingester
+                .root_pid()
+                .ok_or(anyhow::anyhow!("the recording has no root process"))?;
+            let report = IdleGapReport::from_store(
+                ingester.tracked_events(),
+                root_pid,
+                args.min_gap as u128 * 1_000_000,
+            );
+            print_report(&report, args.input.json, IdleGapReport::to_table)?;
+        }
+        Command::Analyze(AnalyzeCommand::Groups(args)) => {
+            let tagger = tagger(&args.group)?.unwrap_or(Tagger::Directory);
+            let reader = new_buffered_input_stream(&args.input.input_path)?;
+            let ingester = read_events(reader).context("failed to read events from input")?;
+            let cwd = ingester
+                .metadata()
+                .and_then(|metadata| metadata.cwd.as_deref());
+            let tags = tagger.tags(ingester.tracked_events(), cwd);
+            let report = GroupReport::from_store(ingester.tracked_events(), &tags);
+            print_report(&report, args.input.json, GroupReport::to_table)?;
+        }
+        Command::Analyze(AnalyzeCommand::Interpreters(args)) => {
+            let categories = match args.categories {
+                Some(ref path) => Categories::read(path)?,
+                None => Categories::default(),
+            };
+            let reader = new_buffered_input_stream(&args.input.input_path)?;
+            let ingester = read_events(reader).context("failed to read events from input")?;
+            let report = InterpreterReport::from_store(ingester.tracked_events(), &categories);
+            print_report(&report, args.input.json, InterpreterReport::to_table)?;
+        }
+        Command::Analyze(AnalyzeCommand::Usage(args)) => {
+            let reader = new_buffered_input_stream(&args.input.input_path)?;
+            let ingester = read_events(reader).context("failed to read events from input")?;
+            let report = UsageReport::from_store(ingester.tracked_events());
+            if args.csv {
+                print!("{}", report.to_csv());
+            } else {
+                print_report(&report, args.input.json, |report| {
+                    report.to_table(args.limit)
+                })?;
+            }
+        }
+        Command::Analyze(AnalyzeCommand::Crashes(args)) => {
+            let reader = new_buffered_input_stream(&args.input_path)?;
+            let ingester = read_events(reader).context("failed to read events from input")?;
+            let report = CrashReport::from_store(ingester.tracked_events());
+            print_report(&report, args.json, CrashReport::to_table)?;
+        }
+        Command::Analyze(AnalyzeCommand::FailedExecs(args)) => {
+            let reader = new_buffered_input_stream(&args.input.input_path)?;
+            let ingester = read_events(reader).context("failed to read events from input")?;
+            let report = FailedExecReport::from_store(ingester.tracked_events());
+            print_report(&report, args.input.json, |report| {
+                report.to_table(args.limit)
+            })?;
+        }
+        Command::Analyze(AnalyzeCommand::Repeats(args)) => {
+            let reader = new_buffered_input_stream(&args.input.input_path)?;
+            let ingester = read_events(reader).context("failed to read events from input")?;
+            let report = RepeatReport::from_store(ingester.tracked_events(), args.min_count);
+            print_report(&report, args.input.json, |report| {
+                report.to_table(args.limit)
+            })?;
+        }
+        Command::Analyze(AnalyzeCommand::Storms(args)) => {
+            let reader = new_buffered_input_stream(&args.input.input_path)?;
+            let ingester = read_events(reader).context("failed to read events from input")?;
+            let detector = StormDetector::new(args.threshold, args.window);
+            let report = StormReport::from_store(ingester.tracked_events(), detector);
+            print_report(&report, args.input.json, StormReport::to_table)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the ID of a cgroup, which is the inode number of its directory.
+#[cfg(all(target_os = "linux", feature = "record"))]
+fn cgroup_id(path: &Path) -> Result<u64, Error> {
+    use std::os::unix::fs::MetadataExt;
+
+    let path = Path::new("/sys/fs/cgroup").join(path);
+    let metadata = std::fs::metadata(&path)
+        .with_context(|| format!("failed to find cgroup {}", path.display()))?;
+    Ok(metadata.ino())
+}
+
+/// Returns the ID of the cgroup of a running container.
+#[cfg(all(target_os = "linux", feature = "record"))]
+fn container_cgroup_id(runtime: &Path, container: &str) -> Result<u64, Error> {
+    let output = std::process::Command::new(runtime)
+        .args(["inspect", "--format", "{{.State.Pid}}", container])
+        .output()
+        .with_context(|| format!("failed to run {}", runtime.display()))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "failed to inspect container {container}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let pid = String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse::<i32>()
+        .with_context(|| format!("container {container} has no PID"))?;
+    if pid == 0 {
+        anyhow::bail!("container {container} isn't running");
+    }
+    // On cgroup v2 there's a single hierarchy, listed as "0::<path>"
+    let cgroups = std::fs::read_to_string(format!("/proc/{pid}/cgroup"))
+        .with_context(|| format!("failed to read the cgroup of PID {pid}"))?;
+    let path = cgroups
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .ok_or(anyhow::anyhow!(
+            "container {container} isn't in a cgroup v2 hierarchy"
+        ))?;
+    cgroup_id(Path::new(path.trim_start_matches('/')))
+}
+
+/// Reads a whole recording from a plain file, mapping it into memory where that's
+/// supported so that it isn't copied through a buffer.
+fn read_mapped_events(path: &Path) -> Result<EventIngester<NoOpWriter>, Error> {
+    #[cfg(target_os = "linux")]
+    {
+        let mapped = proctrace::mmap::MappedFile::open(path)?;
+        read_events_from_slice(mapped.as_slice())
+    }
+    #[cfg(not(target_os = "linux"))]
+    read_events(new_buffered_input_stream(path)?)
+}
+
+/// Loads the durations from a processed recording or the results of `proctrace bench`.
+fn load_summary(path: &Path) -> Result<RunSummary, Error> {
+    let summary_path = if path.is_dir() {
+        Some(path.join("summary.json"))
+    } else if path.file_name().is_some_and(|name| name == "summary.json") {
+        Some(path.to_path_buf())
+    } else {
+        None
+    };
+    if is_manifest_path(path) {
+        let manifest = Manifest::read(path)?;
+        if !manifest.runs.is_empty() {
+            let mut summaries = vec![];
+            for run_path in manifest.run_paths(make_path_absolute(path)?) {
+                let reader = new_buffered_input_stream(&run_path)?;
+                let ingester = read_events(reader).with_context(|| {
+                    format!("failed to read events from {}", run_path.display())
+                })?;
+                summaries.push(RunSummary::from_store(ingester.tracked_events()));
+            }
+            let summary = BenchSummary::from_runs(&summaries).expect("there is at least one run");
+            return Ok(summary.medians());
+        }
+    }
+    if let Some(summary_path) = summary_path {
+        let file = std::fs::File::open(&summary_path)
+            .with_context(|| format!("failed to open {}", summary_path.display()))?;
+        let summary: BenchSummary =
+            serde_json::from_reader(file).context("failed to parse bench summary")?;
+        return Ok(summary.medians());
+    }
+    let reader = new_buffered_input_stream(path)?;
+    let ingester = read_events(reader)
+        .with_context(|| format!("failed to read events from {}", path.display()))?;
+    Ok(RunSummary::from_store(ingester.tracked_events()))
+}
+
+/// Returns how the processes should be grouped, if at all.
+fn tagger(args: &GroupArgs) -> Result<Option<Tagger>, Error> {
+    match args.tags {
+        Some(ref path) => Tagger::read(path).map(Some),
+        None if args.group_by_dir => Ok(Some(Tagger::Directory)),
+        None => Ok(None),
+    }
+}
+
+/// Prints the report of an `analyze` command, as JSON if `--json` was passed.
+fn print_report<T: serde::Serialize>(
+    report: &T,
+    json: bool,
+    to_text: impl FnOnce(&T) -> String,
+) -> Result<(), Error> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(report)?);
+    } else {
+        print!("{}", to_text(report));
+    }
+    Ok(())
+}
+
+/// Records the command several times, storing each run and a summary of the runs.
+#[cfg(all(target_os = "linux", feature = "record"))]
+fn bench(args: BenchArgs) -> Result<(), Error> {
+    if args.cmd.is_empty() {
+        anyhow::bail!("must provide a command to run");
+    }
+    if args.runs == 0 {
+        anyhow::bail!("must record at least one run");
+    }
+    std::fs::create_dir_all(&args.output_dir).context("failed to create output directory")?;
+    let shutdown_flag = Arc::new(AtomicBool::new(false));
+    let _ = signal_hook::flag::register(nix::libc::SIGINT, Arc::clone(&shutdown_flag))
+        .context("failed to install signal handler")?;
+
+    let mut summaries = vec![];
+    let mut manifest = Manifest::default();
+    for run in 1..=args.runs {
+        eprintln!("Recording run {run}/{}", args.runs);
+        let mut user_cmd = std::process::Command::new(&args.cmd[0]);
+        user_cmd.args(&args.cmd[1..]);
+        let mut options = RecordOptions::new(args.bpftrace_path.clone());
+        options.debug = args.debug;
+        let started_at = wall_clock_now();
+        options.started_at = Some(started_at);
+        let mut ingester = record(user_cmd, shutdown_flag.clone(), options, NoOpWriter)
+            .with_context(|| format!("failed while recording run {run}"))?;
+        // An interrupted run would skew the results, so it's discarded
+        if shutdown_flag.load(Ordering::SeqCst) {
+            eprintln!("Interrupted, discarding run {run}");
+            break;
+        }
+        ingester.post_process_buffers();
+        summaries.push(RunSummary::from_store(ingester.tracked_events()));
+
+        let file_name = format!("run-{run:04}.{}", args.format);
+        manifest.runs.push(Run {
+            index: run,
+            path: file_name.clone().into(),
+            started_at: Some(started_at),
+            exit_code: ingester
+                .root_pid()
+                .and_then(|pid| ingester.tracked_events().events_of(pid))
+                .and_then(|events| events.iter().rev().find_map(|event| event.exit_code())),
+        });
+        let path = args.output_dir.join(file_name);
+        let writer = new_buffered_output_stream(&Some(path), None)?;
+        let mut writer = new_event_writer(writer, args.format);
+        let mut metadata = Metadata::new(ingester.root_pid());
+        metadata.command = args.cmd.clone();
+        metadata.started_at = Some(started_at);
+        metadata.adjusted_timestamps = ingester.adjusted_timestamps();
+        writer.write_metadata(&metadata)?;
+        render_sequential(ingester, writer)?;
     }
 
+    manifest.write(args.output_dir.join(BENCH_MANIFEST))?;
+    let summary =
+        BenchSummary::from_runs(&summaries).ok_or(anyhow::anyhow!("no runs were recorded"))?;
+    let summary_file = std::fs::File::create(args.output_dir.join("summary.json"))
+        .context("failed to create summary file")?;
+    serde_json::to_writer_pretty(summary_file, &summary).context("failed to write summary")?;
+    print!("{}", summary.to_table());
     Ok(())
 }
@@ -0,0 +1,354 @@
+//! Merges recordings from several hosts (e.g. the local and remote workers of a
+//! distributed build) into a single recording on one timeline.
+//!
+//! The timestamps of a recording count from when it started, so each one is moved by
+//! the difference between its start time (`started_at` in its metadata) and the start
+//! time of the earliest recording. Clocks on different hosts don't agree exactly, so an
+//! extra offset can be given for each recording, and recordings that still don't
+//! overlap with the first one are warned about. PIDs that are used by more than one
+//! recording are renumbered.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::BufRead,
+};
+
+use anyhow::anyhow;
+
+use crate::{
+    models::{Event, MergedSource, Metadata},
+    reader::TraceReader,
+};
+
+type Error = anyhow::Error;
+
+/// A recording to merge.
+#[derive(Debug, Clone)]
+pub struct Source {
+    /// How the recording is referred to in warnings and the merged metadata.
+    pub name: String,
+    pub metadata: Option<Metadata>,
+    pub events: Vec<Event>,
+    /// Added to the timestamps of the recording on top of what its start time says,
+    /// in nanoseconds, to correct for a clock that was off.
+    pub clock_offset: i128,
+}
+
+impl Source {
+    /// Reads a processed recording in any format.
+    pub fn read(
+        name: impl Into<String>,
+        reader: impl BufRead,
+        clock_offset: i128,
+    ) -> Result<Self, Error> {
+        let reader = TraceReader::detect(reader)?;
+        let metadata = reader.metadata().cloned();
+        let events = reader.collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            name: name.into(),
+            metadata,
+            events,
+            clock_offset,
+        })
+    }
+
+    /// The root PID from the metadata, or the PID of the first process otherwise.
+    fn root_pid(&self) -> Option<i32> {
+        self.metadata
+            .as_ref()
+            .and_then(|metadata| metadata.root_pid)
+            .or_else(|| {
+                self.events
+                    .iter()
+                    .find(|event| event.is_process_event())
+                    .map(|event| event.pid())
+            })
+    }
+}
+
+/// The result of merging recordings.
+#[derive(Debug, Clone)]
+pub struct Merged {
+    pub metadata: Metadata,
+    /// Ordered by timestamp, and numbered in that order.
+    pub events: Vec<Event>,
+    /// Problems with the clocks of the recordings that may have put them in the wrong
+    /// place on the timeline.
+    pub warnings: Vec<String>,
+}
+
+/// Merges recordings onto a single timeline.
+///
+/// The root of the merged recording is the process that was seen first, and the
+/// roots of the other recordings are adopted, so they're rendered next to it.
+pub fn merge(mut sources: Vec<Source>) -> Result<Merged, Error> {
+    if sources.is_empty() {
+        return Err(anyhow!("there are no recordings to merge"));
+    }
+    let mut warnings = vec![];
+    let started_at = |source: &Source| {
+        source
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.started_at)
+    };
+    let earliest_start = sources.iter().filter_map(started_at).min();
+    let mut offsets = vec![];
+    for source in sources.iter() {
+        let offset = match (started_at(source), earliest_start) {
+            (Some(start), Some(earliest)) => (start - earliest) as i128,
+            (None, Some(_)) => {
+                warnings.push(format!(
+                    "{} doesn't say when it started, so it's assumed to have started with the earliest recording",
+                    source.name
+                ));
+                0
+            }
+            (_, None) => 0,
+        };
+        offsets.push(offset + source.clock_offset);
+    }
+    // Negative offsets would move events before the start of the timeline, so the
+    // whole timeline is moved later instead
+    let shift = sources
+        .iter()
+        .zip(offsets.iter())
+        .filter_map(|(source, offset)| {
+            let first = source.events.iter().map(|event| event.timestamp()).min()?;
+            Some(first as i128 + offset)
+        })
+        .min()
+        .unwrap_or(0)
+        .min(0);
+    for offset in offsets.iter_mut() {
+        *offset -= shift;
+    }
+    warn_about_gaps(&sources, &offsets, &mut warnings);
+
+    let mut next_pid = sources
+        .iter()
+        .flat_map(|source| source.events.iter())
+        .flat_map(|event| {
+            let mut pids = vec![];
+            event.clone().map_pids(|pid| {
+                pids.push(pid);
+                pid
+            });
+            pids
+        })
+        .max()
+        .unwrap_or(0)
+        .saturating_add(1);
+    let mut used_pids = BTreeSet::new();
+    let mut metadata = Metadata::new(None);
+    metadata.started_at = earliest_start.map(|start| start.saturating_sub((-shift) as u128));
+    let mut roots = vec![];
+    let mut ordered = vec![];
+    for (idx, (source, offset)) in sources.iter_mut().zip(offsets).enumerate() {
+        // PIDs that another recording already used are renumbered, which includes
+        // parents and process groups outside of the recording so that they can't be
+        // mistaken for a process of another recording
+        let original_root = source.root_pid();
+        let mut renumbered = HashMap::new();
+        let mut source_pids = BTreeSet::new();
+        for event in source.events.iter_mut() {
+            event.map_pids(|pid| {
+                if pid <= 0 {
+                    return pid;
+                }
+                let new_pid = *renumbered.entry(pid).or_insert_with(|| {
+                    if used_pids.contains(&pid) {
+                        next_pid += 1;
+                        next_pid - 1
+                    } else {
+                        pid
+                    }
+                });
+                source_pids.insert(new_pid);
+                new_pid
+            });
+        }
+        used_pids.extend(source_pids);
+        let renumber = |pid: i32| renumbered.get(&pid).copied().unwrap_or(pid);
+        let root_pid = original_root.map(renumber);
+        if let Some(ref source_metadata) = source.metadata {
+            if metadata.command.is_empty() {
+                metadata.command = source_metadata.command.clone();
+            }
+            roots.extend(
+                source_metadata
+                    .adopted_pids
+                    .iter()
+                    .map(|pid| renumber(*pid)),
+            );
+            metadata
+                .cgroups
+                .extend(source_metadata.cgroups.iter().copied());
+            for (pid, ns_pid) in source_metadata.ns_pids.iter() {
+                metadata.ns_pids.insert(renumber(*pid), *ns_pid);
+            }
+            for (pid, count) in source_metadata.pruned.iter() {
+                metadata.pruned.insert(renumber(*pid), *count);
+            }
+        }
+        roots.extend(root_pid);
+        metadata.sources.push(MergedSource {
+            name: source.name.clone(),
+            root_pid,
+            clock_offset: offset,
+        });
+        for event in std::mem::take(&mut source.events) {
+            let timestamp = (event.timestamp() as i128 + offset).max(0) as u128;
+            ordered.push((timestamp, idx, event.seq(), event));
+        }
+    }
+    ordered.sort_by_key(|(timestamp, idx, seq, _)| (*timestamp, *idx, *seq));
+
+    // A recording has to start with an event of its root process, so anything from
+    // before the first process is dropped
+    let first_process = ordered
+        .iter()
+        .position(|(_, _, _, event)| event.is_process_event())
+        .ok_or(anyhow!("none of the recordings have any processes"))?;
+    let events = ordered
+        .into_iter()
+        .skip(first_process)
+        .enumerate()
+        .map(|(seq, (timestamp, _, _, mut event))| {
+            event.set_seq_and_timestamp(seq as u128, timestamp);
+            event
+        })
+        .collect::<Vec<_>>();
+    let root_pid = events[0].pid();
+    metadata.root_pid = Some(root_pid);
+    let mut adopted = BTreeSet::new();
+    metadata.adopted_pids = roots
+        .into_iter()
+        .filter(|pid| *pid != root_pid && adopted.insert(*pid))
+        .collect();
+    metadata.cgroups.sort();
+    metadata.cgroups.dedup();
+    Ok(Merged {
+        metadata,
+        events,
+        warnings,
+    })
+}
+
+/// Warns about recordings that don't overlap with the first recording once they've
+/// been moved by `offsets`, which usually means that a clock was off. The first
+/// recording is usually the one that started the others, e.g. the coordinator of a
+/// distributed build, so the others should happen while it's running.
+fn warn_about_gaps(sources: &[Source], offsets: &[i128], warnings: &mut Vec<String>) {
+    let spans = sources
+        .iter()
+        .zip(offsets)
+        .map(|(source, offset)| {
+            let timestamps = source.events.iter().map(|event| event.timestamp() as i128);
+            let first = timestamps.clone().min()?;
+            let last = timestamps.max()?;
+            Some((first + offset, last + offset))
+        })
+        .collect::<Vec<_>>();
+    let Some(Some((reference_first, reference_last))) = spans.first().copied() else {
+        return;
+    };
+    for (source, span) in sources.iter().zip(spans.iter()).skip(1) {
+        let Some((first, last)) = *span else {
+            continue;
+        };
+        let gap = if last < reference_first {
+            reference_first - last
+        } else if first > reference_last {
+            first - reference_last
+        } else {
+            continue;
+        };
+        warnings.push(format!(
+            "{} doesn't overlap with {} ({:.3}s apart), its clock may be off",
+            source.name,
+            sources[0].name,
+            gap as f64 / 1e9
+        ));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn source(name: &str, started_at: Option<u128>, root_pid: i32) -> Source {
+        let mut metadata = Metadata::new(Some(root_pid));
+        metadata.started_at = started_at;
+        let events = vec![
+            Event::Fork {
+                seq: 1,
+                timestamp: 1_000,
+                parent_pid: 1,
+                child_pid: root_pid,
+                parent_pgid: 1,
+                credentials: None,
+                comm: None,
+            },
+            Event::Exit {
+                seq: 2,
+                timestamp: 5_000,
+                pid: root_pid,
+                ppid: 1,
+                pgid: 1,
+                exit_code: Some(0),
+            },
+        ];
+        Source {
+            name: name.to_string(),
+            metadata: Some(metadata),
+            events,
+            clock_offset: 0,
+        }
+    }
+
+    #[test]
+    fn merges_onto_one_timeline() {
+        let local = source("local", Some(10_000), 100);
+        let remote = source("remote", Some(12_000), 100);
+        let merged = merge(vec![local, remote]).unwrap();
+        assert!(merged.warnings.is_empty());
+        assert_eq!(merged.metadata.started_at, Some(10_000));
+        assert_eq!(merged.metadata.root_pid, Some(100));
+        // The remote root collided with the local root, so it was renumbered
+        assert_eq!(merged.metadata.adopted_pids, vec![102]);
+        let timeline = merged
+            .events
+            .iter()
+            .map(|event| (event.seq(), event.timestamp(), event.pid()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            timeline,
+            vec![
+                (0, 1_000, 100),
+                (1, 3_000, 102),
+                (2, 5_000, 100),
+                (3, 7_000, 102)
+            ]
+        );
+        let Event::Fork { parent_pid, .. } = merged.events[1] else {
+            panic!("expected the remote fork");
+        };
+        assert_eq!(parent_pid, 101);
+    }
+
+    #[test]
+    fn warns_about_clocks() {
+        let local = source("local", Some(10_000), 100);
+        let mut remote = source("remote", None, 200);
+        remote.clock_offset = -1_000_000_000;
+        let merged = merge(vec![local, remote]).unwrap();
+        assert_eq!(merged.warnings.len(), 2);
+        assert!(merged.warnings[0].starts_with("remote doesn't say when it started"));
+        assert!(merged.warnings[1].starts_with("remote doesn't overlap"));
+        // The remote recording was moved before the start, so it comes first
+        assert_eq!(merged.metadata.root_pid, Some(200));
+        assert_eq!(merged.events[0].timestamp(), 0);
+        assert_eq!(merged.metadata.adopted_pids, vec![100]);
+    }
+}
@@ -0,0 +1,140 @@
+//! Counters describing the recorder itself, served in the Prometheus text format.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::Context;
+
+type Error = anyhow::Error;
+
+/// Counters that are updated while recording.
+#[derive(Debug, Default)]
+pub struct RecorderMetrics {
+    /// Lines from `bpftrace` that were parsed into events.
+    pub events_ingested: AtomicU64,
+    /// Lines from `bpftrace` that couldn't be parsed.
+    pub events_dropped: AtomicU64,
+    /// PIDs that are part of the recording.
+    pub tracked_pids: AtomicU64,
+    /// PIDs whose events are buffered until it's known whether they're part of the
+    /// recording.
+    pub buffered_pids: AtomicU64,
+}
+
+impl RecorderMetrics {
+    /// Formats the metrics in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut text = String::new();
+        for (name, kind, help, value) in [
+            (
+                "proctrace_events_ingested_total",
+                "counter",
+                "Events read from bpftrace.",
+                &self.events_ingested,
+            ),
+            (
+                "proctrace_events_dropped_total",
+                "counter",
+                "Lines from bpftrace that could not be parsed.",
+                &self.events_dropped,
+            ),
+            (
+                "proctrace_tracked_pids",
+                "gauge",
+                "PIDs that are part of the recording.",
+                &self.tracked_pids,
+            ),
+            (
+                "proctrace_buffered_pids",
+                "gauge",
+                "PIDs whose events are buffered until they join the recording.",
+                &self.buffered_pids,
+            ),
+        ] {
+            text.push_str(&format!(
+                "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {}\n",
+                value.load(Ordering::Relaxed)
+            ));
+        }
+        text
+    }
+}
+
+/// Serves `metrics` at `/metrics` on `addr` from a background thread.
+///
+/// Returns the address that's being listened on, which is useful when binding to
+/// port 0.
+pub fn serve(addr: SocketAddr, metrics: Arc<RecorderMetrics>) -> Result<SocketAddr, Error> {
+    let listener =
+        TcpListener::bind(addr).with_context(|| format!("failed to listen on {addr}"))?;
+    let local_addr = listener.local_addr()?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // A misbehaving client shouldn't stop the recording
+            let _ = respond(stream, &metrics);
+        }
+    });
+    Ok(local_addr)
+}
+
+/// Answers a single HTTP request.
+fn respond(stream: TcpStream, metrics: &RecorderMetrics) -> Result<(), Error> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip the headers, the response doesn't depend on them
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let mut stream = reader.into_inner();
+    let mut parts = request_line.split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let body = metrics.to_prometheus();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+    stream.write_all(response.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn serves_metrics() {
+        let metrics = Arc::new(RecorderMetrics::default());
+        metrics.events_ingested.store(42, Ordering::Relaxed);
+        let addr = serve("127.0.0.1:0".parse().unwrap(), metrics.clone()).unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("proctrace_events_ingested_total 42\n"));
+        assert!(response.contains("# TYPE proctrace_tracked_pids gauge"));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 404"));
+    }
+}
@@ -0,0 +1,103 @@
+//! Reads recordings by mapping them into memory instead of copying them through a
+//! buffer, which makes reading the same large recording over and over (e.g. rendering
+//! it with different options) much cheaper once it's in the page cache.
+
+use std::{ffi::c_void, fs::File, num::NonZeroUsize, path::Path, ptr::NonNull};
+
+use anyhow::{anyhow, Context};
+use nix::sys::mman::{madvise, mmap, munmap, MapFlags, MmapAdvise, ProtFlags};
+
+type Error = anyhow::Error;
+
+/// A read-only mapping of a whole file.
+///
+/// The file must not be truncated while it's mapped, reading the missing pages
+/// would crash the process with `SIGBUS`. Appending to it is fine, the new data just
+/// isn't visible.
+#[derive(Debug)]
+pub struct MappedFile {
+    /// `None` for an empty file, which can't be mapped.
+    mapping: Option<(NonNull<c_void>, usize)>,
+}
+
+// The mapping is read-only and owned by this struct
+unsafe impl Send for MappedFile {}
+unsafe impl Sync for MappedFile {}
+
+impl MappedFile {
+    /// Maps the regular file at `path` into memory.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .with_context(|| format!("failed to open {} for mapping", path.display()))?;
+        let file_metadata = file.metadata().context("failed to read file metadata")?;
+        if !file_metadata.is_file() {
+            return Err(anyhow!("{} isn't a regular file", path.display()));
+        }
+        let len = usize::try_from(file_metadata.len()).context("file is too large to map")?;
+        let Some(length) = NonZeroUsize::new(len) else {
+            return Ok(Self { mapping: None });
+        };
+        // Safety: the mapping is private and read-only, and is unmapped when this
+        // struct is dropped.
+        let ptr = unsafe {
+            mmap(
+                None,
+                length,
+                ProtFlags::PROT_READ,
+                MapFlags::MAP_PRIVATE,
+                &file,
+                0,
+            )
+        }
+        .with_context(|| format!("failed to map {}", path.display()))?;
+        // Recordings are read from start to end, so let the kernel read ahead. This is
+        // only a hint, so a failure doesn't matter.
+        let _ = unsafe { madvise(ptr, len, MmapAdvise::MADV_SEQUENTIAL) };
+        Ok(Self {
+            mapping: Some((ptr, len)),
+        })
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        match self.mapping {
+            // Safety: the mapping is `len` bytes long and lives as long as `self`.
+            Some((ptr, len)) => unsafe {
+                std::slice::from_raw_parts(ptr.as_ptr() as *const u8, len)
+            },
+            None => &[],
+        }
+    }
+}
+
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        if let Some((ptr, len)) = self.mapping.take() {
+            // Safety: nothing can borrow the mapping once it's being dropped.
+            let _ = unsafe { munmap(ptr, len) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn maps_file_contents() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"{\"Fork\":{}}\n").unwrap();
+        file.flush().unwrap();
+        let mapped = MappedFile::open(file.path()).unwrap();
+        assert_eq!(mapped.as_slice(), b"{\"Fork\":{}}\n");
+
+        let empty = tempfile::NamedTempFile::new().unwrap();
+        assert!(MappedFile::open(empty.path())
+            .unwrap()
+            .as_slice()
+            .is_empty());
+        assert!(MappedFile::open(std::env::temp_dir()).is_err());
+    }
+}
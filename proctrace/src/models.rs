@@ -1,11 +1,12 @@
 use std::{
-    collections::{BTreeMap, HashSet, VecDeque},
+    collections::{BTreeMap, BTreeSet, HashSet, VecDeque},
     fmt::Display,
+    path::{Component, Path, PathBuf},
 };
 
 use serde::{Deserialize, Serialize};
 
-use crate::ingest::clean_exec_sequences;
+use crate::{ingest::clean_exec_sequences, labels::Labels};
 
 type Error = anyhow::Error;
 
@@ -27,6 +28,14 @@ impl ExecArgsKind {
             ExecArgsKind::Args(args) => args.join(" "),
         }
     }
+
+    /// Applies `f` to each of the arguments.
+    pub fn map(&self, f: impl Fn(&str) -> String) -> Self {
+        match self {
+            ExecArgsKind::Joined(args) => ExecArgsKind::Joined(f(args)),
+            ExecArgsKind::Args(args) => ExecArgsKind::Args(args.iter().map(|arg| f(arg)).collect()),
+        }
+    }
 }
 
 impl Display for ExecArgsKind {
@@ -38,6 +47,578 @@ impl Display for ExecArgsKind {
     }
 }
 
+/// The real user and group IDs of a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Credentials {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Display for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.uid, self.gid)
+    }
+}
+
+/// Which users' processes to keep, by user ID.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct UserFilter {
+    /// Only keep the processes of these users, along with their ancestors, or every
+    /// process if it's empty.
+    pub include: Vec<u32>,
+    /// Leave out the processes of these users, along with their descendants.
+    pub exclude: Vec<u32>,
+}
+
+impl UserFilter {
+    /// Returns `true` if every process is kept.
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+}
+
+/// Describes an exit code, which is in the format of a wait status.
+pub fn describe_exit_code(code: i32) -> String {
+    let signal = code & 0x7f;
+    if signal == 0 {
+        format!("exited with status {}", (code >> 8) & 0xff)
+    } else if dumped_core(code) {
+        format!("killed by signal {signal} (core dumped)")
+    } else {
+        format!("killed by signal {signal}")
+    }
+}
+
+/// The signals whose default action is to dump core, numbered as on Linux.
+const CORE_DUMP_SIGNALS: [i32; 10] = [3, 4, 5, 6, 7, 8, 11, 24, 25, 31];
+
+/// Returns the signal that killed a process if it crashed, i.e. it was killed by a
+/// signal that dumps core, whether or not a core was written (e.g. with `ulimit -c 0`).
+pub fn crash_signal(code: i32) -> Option<i32> {
+    let signal = code & 0x7f;
+    CORE_DUMP_SIGNALS.contains(&signal).then_some(signal)
+}
+
+/// Returns `true` if the kernel wrote a core dump before the process exited, which it
+/// flags in the wait status.
+pub fn dumped_core(code: i32) -> bool {
+    code & 0x7f != 0 && code & 0x80 != 0
+}
+
+/// Describes a crash, e.g. `crashed with SIGSEGV (core dumped)`.
+pub fn describe_crash(code: i32) -> Option<String> {
+    let signal = crash_signal(code)?;
+    let mut description = format!("crashed with {}", signal_name(signal));
+    if dumped_core(code) {
+        description.push_str(" (core dumped)");
+    }
+    Some(description)
+}
+
+/// Names the signals that can kill a process, numbered as on Linux.
+pub fn signal_name(signal: i32) -> String {
+    match signal {
+        1 => "SIGHUP".to_string(),
+        2 => "SIGINT".to_string(),
+        3 => "SIGQUIT".to_string(),
+        4 => "SIGILL".to_string(),
+        5 => "SIGTRAP".to_string(),
+        6 => "SIGABRT".to_string(),
+        7 => "SIGBUS".to_string(),
+        8 => "SIGFPE".to_string(),
+        9 => "SIGKILL".to_string(),
+        10 => "SIGUSR1".to_string(),
+        11 => "SIGSEGV".to_string(),
+        12 => "SIGUSR2".to_string(),
+        13 => "SIGPIPE".to_string(),
+        14 => "SIGALRM".to_string(),
+        15 => "SIGTERM".to_string(),
+        24 => "SIGXCPU".to_string(),
+        25 => "SIGXFSZ".to_string(),
+        31 => "SIGSYS".to_string(),
+        signal => format!("signal {signal}"),
+    }
+}
+
+/// Describes a failed exec, e.g. `exec failed: /usr/local/bin/git (ENOENT)`.
+pub fn describe_failed_exec(filename: Option<&str>, errno: Option<i32>) -> String {
+    let mut description = format!("exec failed: {}", filename.unwrap_or("<unknown>"));
+    if let Some(errno) = errno {
+        description.push_str(&format!(" ({})", errno_name(errno)));
+    }
+    description
+}
+
+/// Describes a mount, e.g. `mount overlay on /nix/store` or `umount /tmp/sandbox`.
+pub fn describe_mount(target: &str, fstype: Option<&str>, unmount: bool) -> String {
+    match (unmount, fstype) {
+        (true, _) => format!("umount {target}"),
+        (false, Some(fstype)) => format!("mount {fstype} on {target}"),
+        (false, None) => format!("mount {target}"),
+    }
+}
+
+/// Names the errors that `execve` can return, numbered as on Linux.
+pub fn errno_name(errno: i32) -> String {
+    match errno {
+        1 => "EPERM".to_string(),
+        2 => "ENOENT".to_string(),
+        7 => "E2BIG".to_string(),
+        8 => "ENOEXEC".to_string(),
+        12 => "ENOMEM".to_string(),
+        13 => "EACCES".to_string(),
+        14 => "EFAULT".to_string(),
+        20 => "ENOTDIR".to_string(),
+        26 => "ETXTBSY".to_string(),
+        36 => "ENAMETOOLONG".to_string(),
+        40 => "ELOOP".to_string(),
+        errno => format!("errno {errno}"),
+    }
+}
+
+/// The `CLONE_NEW*` flags of each kind of namespace, named like the files in
+/// `/proc/<pid>/ns/`.
+const NAMESPACE_FLAGS: [(u64, &str); 8] = [
+    (0x0002_0000, "mnt"),
+    (0x0200_0000, "cgroup"),
+    (0x0400_0000, "uts"),
+    (0x0800_0000, "ipc"),
+    (0x1000_0000, "user"),
+    (0x2000_0000, "pid"),
+    (0x4000_0000, "net"),
+    (0x0000_0080, "time"),
+];
+
+/// Names the kinds of namespaces in the `CLONE_NEW*` flags given to `clone`,
+/// `unshare`, or `setns`, e.g. `["mnt", "net"]`.
+pub fn namespace_names(flags: u64) -> Vec<String> {
+    NAMESPACE_FLAGS
+        .iter()
+        .filter(|(flag, _)| flags & flag != 0)
+        .map(|(_, name)| name.to_string())
+        .collect()
+}
+
+/// What a process cost over its lifetime, from the counters that `getrusage` reports.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResourceUsage {
+    /// Page faults that were served without any I/O, e.g. from the page cache.
+    pub minor_faults: u64,
+    /// Page faults that had to read from disk.
+    pub major_faults: u64,
+    /// Times the process gave up a CPU to wait, e.g. for I/O or a lock.
+    pub voluntary_switches: u64,
+    /// Times the process was taken off a CPU to run something else.
+    pub involuntary_switches: u64,
+    /// Time spent running on a CPU in user mode, in nanoseconds.
+    pub user_ns: u64,
+    /// Time spent running on a CPU in the kernel, in nanoseconds.
+    pub system_ns: u64,
+}
+
+impl ResourceUsage {
+    /// The time the process spent running on a CPU, in nanoseconds.
+    pub fn cpu_ns(&self) -> u128 {
+        self.user_ns as u128 + self.system_ns as u128
+    }
+
+    /// The share of `wall` nanoseconds that the process spent on a CPU, which is over
+    /// 100% when several of its threads were busy at once.
+    pub fn cpu_percent(&self, wall: u128) -> Option<u128> {
+        (wall > 0).then(|| self.cpu_ns() * 100 / wall)
+    }
+}
+
+/// How a process ended up in other namespaces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NamespaceChange {
+    /// It was forked into new namespaces.
+    Clone,
+    /// It moved itself into new namespaces.
+    Unshare,
+    /// It joined namespaces that already existed.
+    Setns,
+}
+
+impl Display for NamespaceChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NamespaceChange::Clone => write!(f, "clone"),
+            NamespaceChange::Unshare => write!(f, "unshare"),
+            NamespaceChange::Setns => write!(f, "setns"),
+        }
+    }
+}
+
+/// The `ptrace` requests that start tracing a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PtraceRequest {
+    /// The process asked to be traced by its parent, which is also how some programs
+    /// check whether they're being debugged.
+    Traceme,
+    Attach,
+    Seize,
+}
+
+impl PtraceRequest {
+    /// Returns the request with the number `request` on Linux, if it starts tracing.
+    pub fn from_number(request: i64) -> Option<Self> {
+        match request {
+            0 => Some(PtraceRequest::Traceme),
+            16 => Some(PtraceRequest::Attach),
+            0x4206 => Some(PtraceRequest::Seize),
+            _ => None,
+        }
+    }
+}
+
+impl Display for PtraceRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PtraceRequest::Traceme => write!(f, "traceme"),
+            PtraceRequest::Attach => write!(f, "attach"),
+            PtraceRequest::Seize => write!(f, "seize"),
+        }
+    }
+}
+
+/// Describes a call to `ptrace`, e.g. `ptrace attach to 42` or
+/// `ptrace traceme failed (EPERM)`.
+pub fn describe_ptrace(request: PtraceRequest, target: Option<i32>, errno: Option<i32>) -> String {
+    let mut description = format!("ptrace {request}");
+    if let Some(target) = target {
+        description.push_str(&format!(" to {target}"));
+    }
+    if let Some(errno) = errno {
+        description.push_str(&format!(" failed ({})", errno_name(errno)));
+    }
+    description
+}
+
+/// How a process restricted what it and its descendants can do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SandboxMechanism {
+    Seccomp,
+    Landlock,
+}
+
+impl Display for SandboxMechanism {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SandboxMechanism::Seccomp => write!(f, "seccomp"),
+            SandboxMechanism::Landlock => write!(f, "landlock"),
+        }
+    }
+}
+
+/// The flags of `seccomp(SECCOMP_SET_MODE_FILTER, ...)`, without their
+/// `SECCOMP_FILTER_FLAG_` prefix.
+const SECCOMP_FLAGS: [(u64, &str); 6] = [
+    (1, "tsync"),
+    (2, "log"),
+    (4, "spec_allow"),
+    (8, "new_listener"),
+    (16, "tsync_esrch"),
+    (32, "wait_killable_recv"),
+];
+
+/// The flags of `landlock_restrict_self`, without their `LANDLOCK_RESTRICT_SELF_`
+/// prefix.
+const LANDLOCK_FLAGS: [(u64, &str); 3] = [
+    (1, "log_same_exec_off"),
+    (2, "log_new_exec_on"),
+    (4, "log_subdomains_off"),
+];
+
+/// Names the flags given to `mechanism`, e.g. `["tsync"]`, keeping the ones it
+/// doesn't know as a hex number.
+pub fn sandbox_flag_names(mechanism: SandboxMechanism, flags: u64) -> Vec<String> {
+    let known: &[(u64, &str)] = match mechanism {
+        SandboxMechanism::Seccomp => &SECCOMP_FLAGS,
+        SandboxMechanism::Landlock => &LANDLOCK_FLAGS,
+    };
+    let mut names = known
+        .iter()
+        .filter(|(flag, _)| flags & flag != 0)
+        .map(|(_, name)| name.to_string())
+        .collect::<Vec<_>>();
+    let unknown = known.iter().fold(flags, |flags, (flag, _)| flags & !flag);
+    if unknown != 0 {
+        names.push(format!("{unknown:#x}"));
+    }
+    names
+}
+
+/// Describes a sandbox, e.g. `seccomp filter (tsync)` or `landlock`.
+pub fn describe_sandbox(
+    mechanism: SandboxMechanism,
+    mode: Option<&str>,
+    flags: &[String],
+) -> String {
+    let mut description = mechanism.to_string();
+    if let Some(mode) = mode {
+        description.push_str(&format!(" {mode}"));
+    }
+    if !flags.is_empty() {
+        description.push_str(&format!(" ({})", flags.join(", ")));
+    }
+    description
+}
+
+/// The process group of events whose backend couldn't tell which group the process
+/// was in, e.g. because it exited before it could be looked up.
+pub const UNKNOWN_PGID: i32 = 0;
+
+/// The version of the format of processed recordings, which is increased when a
+/// change to it would stop older versions of `proctrace` from reading them.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Information about a recording as a whole.
+///
+/// Writers that support it emit this before any events, and it's serialized as
+/// `{"Metadata": {...}}` so that it can't be confused with an event.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metadata {
+    /// The version of `proctrace` that produced the recording.
+    #[serde(default)]
+    pub proctrace_version: String,
+    /// The PID at the root of the process tree.
+    #[serde(default)]
+    pub root_pid: Option<i32>,
+    /// The command that was recorded, if it was started by `proctrace`.
+    #[serde(default)]
+    pub command: Vec<String>,
+    /// PIDs outside of the root's process tree whose process trees were recorded too.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub adopted_pids: Vec<i32>,
+    /// The IDs of the cgroups whose processes were recorded too.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cgroups: Vec<u64>,
+    /// The transient systemd unit that the command was run in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub systemd_unit: Option<String>,
+    /// The ID of the container whose processes were recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+    /// The PIDs of recorded processes in their own PID namespace, keyed by their PID
+    /// on the host, for processes where the two differ.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub ns_pids: BTreeMap<i32, i32>,
+    /// The wall-clock time of timestamp 0, in nanoseconds since the Unix epoch, which
+    /// puts recordings from different hosts on the same timeline.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<u128>,
+    /// The recordings that this one was merged from.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<MergedSource>,
+    /// The number of processes that `proctrace prune` removed below each process.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub pruned: BTreeMap<i32, u64>,
+    /// Whether the root process is the shell that `record --shell` ran the command
+    /// with, which charts can leave out.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub shell_wrapper: bool,
+    /// The number of events whose timestamps went backwards and were moved up to the
+    /// timestamp of the event before them.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub adjusted_timestamps: u64,
+    /// How many times a second each CPU was sampled for a recorded process running
+    /// on it, if it was.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_sample_hz: Option<u32>,
+    /// The directory the command was started in, which the directories that recorded
+    /// processes `chdir` to are relative to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    /// Processes whose parent was outside of the process tree that were recorded as
+    /// children of the root, keyed by PID with their actual parent.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub reparented: BTreeMap<i32, i32>,
+    /// The processes that recorded the command and how long they took to start.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instrumentation: Option<Instrumentation>,
+    /// The fraction of the root's children whose subtrees were recorded, if only a
+    /// sample of them was.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sampled_trees: Option<SampleRatio>,
+    /// The seed that chose which subtrees were recorded when only a sample of them
+    /// was.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample_seed: Option<u64>,
+}
+
+/// Resolves the `.` and `..` components of an absolute path without touching the
+/// filesystem, since the recording may have been taken on another machine.
+fn normalize_path(path: &Path) -> String {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized.display().to_string()
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+/// A recording that was merged with others by `proctrace merge`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MergedSource {
+    /// The path it was read from.
+    pub name: String,
+    /// Its root PID in the merged recording.
+    pub root_pid: Option<i32>,
+    /// What was added to its timestamps to put them on the merged timeline, in
+    /// nanoseconds.
+    pub clock_offset: i128,
+}
+
+/// An iterator over the ancestors of a process, see [`EventStore::ancestors`].
+#[derive(Debug)]
+pub struct Ancestors<'a> {
+    store: &'a EventStore,
+    current: i32,
+    visited: HashSet<i32>,
+}
+
+impl Iterator for Ancestors<'_> {
+    type Item = i32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let parent = self.store.parent_of_pid_if_stored(self.current)?;
+        if !self.visited.insert(parent) {
+            return None;
+        }
+        self.current = parent;
+        Some(parent)
+    }
+}
+
+/// The processes that `proctrace` used to record a command, which aren't part of
+/// the recording themselves.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Instrumentation {
+    /// The PID of `proctrace` itself.
+    pub recorder_pid: i32,
+    /// The processes that it spawned to record, e.g. `sudo` and `bpftrace`, keyed by
+    /// PID with their command names.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub pids: BTreeMap<i32, String>,
+    /// When it started spawning them, in nanoseconds since the Unix epoch.
+    pub spawned_at: u128,
+    /// When the command was spawned once they were ready, in nanoseconds since the
+    /// Unix epoch.
+    pub command_spawned_at: u128,
+}
+
+impl Instrumentation {
+    /// How long the command was held back while the instrumentation started, in
+    /// nanoseconds.
+    pub fn startup_delay(&self) -> u128 {
+        self.command_spawned_at.saturating_sub(self.spawned_at)
+    }
+}
+
+/// The fraction of the root's children whose subtrees are recorded, see
+/// `record --sample-trees`.
+///
+/// It's kept in parts per million so that it can be compared exactly, and it's
+/// serialized as a number between 0 and 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(into = "f64", try_from = "f64")]
+pub struct SampleRatio(u32);
+
+impl SampleRatio {
+    const PARTS: u32 = 1_000_000;
+
+    /// Returns `true` if a subtree whose random draw is `draw` is part of the sample.
+    pub fn includes(&self, draw: u64) -> bool {
+        draw % u64::from(Self::PARTS) < u64::from(self.0)
+    }
+}
+
+impl From<SampleRatio> for f64 {
+    fn from(ratio: SampleRatio) -> Self {
+        f64::from(ratio.0) / f64::from(SampleRatio::PARTS)
+    }
+}
+
+impl TryFrom<f64> for SampleRatio {
+    type Error = Error;
+
+    fn try_from(ratio: f64) -> Result<Self, Self::Error> {
+        if ratio > 0.0 && ratio <= 1.0 {
+            Ok(Self(
+                (ratio * f64::from(Self::PARTS)).round().max(1.0) as u32
+            ))
+        } else {
+            Err(anyhow::anyhow!(
+                "sample ratio must be more than 0 and at most 1, not {ratio}"
+            ))
+        }
+    }
+}
+
+impl std::str::FromStr for SampleRatio {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ratio = s
+            .parse::<f64>()
+            .map_err(|_| anyhow::anyhow!("invalid sample ratio '{s}', e.g. 0.1"))?;
+        ratio.try_into()
+    }
+}
+
+impl Display for SampleRatio {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", f64::from(*self))
+    }
+}
+
+impl Metadata {
+    /// Creates metadata for a recording rooted at `root_pid`.
+    pub fn new(root_pid: Option<i32>) -> Self {
+        Self {
+            proctrace_version: env!("CARGO_PKG_VERSION").to_string(),
+            root_pid,
+            command: vec![],
+            adopted_pids: vec![],
+            cgroups: vec![],
+            systemd_unit: None,
+            container: None,
+            ns_pids: BTreeMap::new(),
+            started_at: None,
+            sources: vec![],
+            pruned: BTreeMap::new(),
+            shell_wrapper: false,
+            adjusted_timestamps: 0,
+            cpu_sample_hz: None,
+            cwd: None,
+            reparented: BTreeMap::new(),
+            instrumentation: None,
+            sampled_trees: None,
+            sample_seed: None,
+        }
+    }
+}
+
+/// The wrapper that distinguishes metadata from events when serialized.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum MetadataItem {
+    Metadata(Metadata),
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub enum Event {
@@ -47,6 +628,13 @@ pub enum Event {
         parent_pid: i32,
         child_pid: i32,
         parent_pgid: i32,
+        /// The user and group the child was forked as, missing from older recordings.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        credentials: Option<Credentials>,
+        /// The name (`comm`) the child inherited from its parent, which is the only
+        /// name a process that never execs has. Missing from older recordings.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        comm: Option<String>,
     },
     Exec {
         seq: u128,
@@ -55,11 +643,29 @@ pub enum Event {
         ppid: i32,
         pgid: i32,
         cmdline: Option<ExecArgsKind>,
+        /// The ID of the cgroup the process was in, missing from older recordings.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cgroup: Option<u64>,
+        /// The PID of the process in its own PID namespace, e.g. inside a container.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ns_pid: Option<i32>,
+        /// The user and group the process ran as after the exec, which can change
+        /// e.g. with `sudo`, missing from older recordings.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        credentials: Option<Credentials>,
     },
+    /// An exec that failed, e.g. because the file didn't exist.
     BadExec {
         seq: u128,
         timestamp: u128,
         pid: i32,
+        /// The file that failed to exec, filled in from the preceding `ExecFilename`
+        /// when the events are cleaned up.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        filename: Option<String>,
+        /// The `errno` of the failure, missing from older recordings.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        errno: Option<i32>,
     },
     ExecFilename {
         seq: u128,
@@ -73,6 +679,16 @@ pub enum Event {
         pid: i32,
         args: ExecArgsKind,
     },
+    /// One argument of an exec, for command lines too long for a single `ExecArgs`,
+    /// which are reassembled into an `ExecArgs` when the events are cleaned up.
+    ExecArg {
+        seq: u128,
+        timestamp: u128,
+        pid: i32,
+        /// The position of the argument, starting from 0 for `argv[0]`.
+        index: usize,
+        arg: String,
+    },
     ExecFull {
         seq: u128,
         timestamp: u128,
@@ -81,6 +697,10 @@ pub enum Event {
         pgid: i32,
         filename: String,
         args: ExecArgsKind,
+        /// The user and group the process ran as after the exec, missing from older
+        /// recordings.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        credentials: Option<Credentials>,
     },
     Exit {
         seq: u128,
@@ -88,6 +708,9 @@ pub enum Event {
         pid: i32,
         ppid: i32,
         pgid: i32,
+        /// The exit code in the format of a wait status, missing from older recordings.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        exit_code: Option<i32>,
     },
     SetSID {
         seq: u128,
@@ -97,12 +720,162 @@ pub enum Event {
         pgid: i32,
         sid: i32,
     },
+    /// A process joined a process group. `pid` is the process that joined it, which
+    /// isn't always the process that called `setpgid`.
     SetPGID {
         seq: u128,
         timestamp: u128,
         pid: i32,
         ppid: i32,
         pgid: i32,
+        /// The process that moved `pid` into the group, if it wasn't `pid` itself.
+        ///
+        /// Only a parent can change the group of another process, so this is the
+        /// parent of `pid`, e.g. a shell with job control putting a child in the group
+        /// of its pipeline.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        caller_pid: Option<i32>,
+    },
+    /// A process changed its working directory.
+    Chdir {
+        seq: u128,
+        timestamp: u128,
+        pid: i32,
+        /// The directory as it was given to `chdir`, which is relative to the previous
+        /// one unless it's absolute. Missing if it was changed with `fchdir`, which
+        /// only takes a file descriptor.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+    },
+    /// A process created or entered namespaces, e.g. a sandbox like `bwrap` or a
+    /// container runtime setting up its child.
+    Namespace {
+        seq: u128,
+        timestamp: u128,
+        pid: i32,
+        how: NamespaceChange,
+        /// The kinds of namespaces, e.g. "mnt" or "net". Empty if `setns` was only
+        /// given a file descriptor, which could be any kind of namespace.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        namespaces: Vec<String>,
+    },
+    /// A process mounted or unmounted a filesystem, e.g. a sandbox setting up bind
+    /// mounts. Only recorded with `record --trace-mounts`.
+    Mount {
+        seq: u128,
+        timestamp: u128,
+        pid: i32,
+        /// Where the filesystem was mounted, as it was given to `mount`.
+        target: String,
+        /// The type of the filesystem, e.g. "overlay", missing for bind mounts and
+        /// remounts.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        fstype: Option<String>,
+        /// Whether it was unmounted rather than mounted.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        unmount: bool,
+    },
+    /// A process started tracing another one with `ptrace`, or asked to be traced,
+    /// e.g. a debugger, a tool that injects code, or a program checking whether it's
+    /// being debugged.
+    Ptrace {
+        seq: u128,
+        timestamp: u128,
+        pid: i32,
+        request: PtraceRequest,
+        /// The process that was traced, missing for `traceme`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        target_pid: Option<i32>,
+        /// The error the call failed with, missing if it succeeded.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        errno: Option<i32>,
+    },
+    /// A process restricted itself, and every process it starts after, with seccomp
+    /// or Landlock, e.g. a hardened launcher.
+    Sandbox {
+        seq: u128,
+        timestamp: u128,
+        pid: i32,
+        mechanism: SandboxMechanism,
+        /// The mode of seccomp, "strict" or "filter". Missing for Landlock, which
+        /// only has one.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mode: Option<String>,
+        /// The flags it was applied with, e.g. "tsync" to apply a seccomp filter to
+        /// every thread.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        flags: Vec<String>,
+    },
+    /// A process made itself a child subreaper, e.g. `tini` or `systemd --user`, so
+    /// that its descendants are reparented to it instead of init when their parent
+    /// exits.
+    Subreaper {
+        seq: u128,
+        timestamp: u128,
+        pid: i32,
+    },
+    /// The page faults and context switches of a process, recorded right before it
+    /// exits.
+    Usage {
+        seq: u128,
+        timestamp: u128,
+        pid: i32,
+        usage: ResourceUsage,
+    },
+    /// A process was running on a CPU when it was sampled, see
+    /// [`Metadata::cpu_sample_hz`] for how often that was.
+    CpuSample {
+        seq: u128,
+        timestamp: u128,
+        pid: i32,
+    },
+    /// A periodic sample of how much memory a process was using.
+    RssSample {
+        seq: u128,
+        timestamp: u128,
+        pid: i32,
+        /// The resident set size of the process, `VmRSS` in `/proc/<pid>/status`.
+        rss_kb: u64,
+    },
+    /// A periodic sample of how busy the host was, which isn't tied to a process.
+    HostSample {
+        seq: u128,
+        timestamp: u128,
+        /// The 1, 5, and 15 minute load averages multiplied by 100.
+        load: [u32; 3],
+        mem_total_kb: u64,
+        mem_available_kb: u64,
+    },
+    /// A warning that the subtree rooted at `pid` forked faster than the storm
+    /// threshold, which isn't stored with the events of `pid`.
+    ForkStorm {
+        seq: u128,
+        timestamp: u128,
+        pid: i32,
+        /// The number of forks in the subtree during the window.
+        forks: u64,
+        window_ms: u64,
+    },
+    /// The resources that one of the recorder's own processes (see
+    /// [`Metadata::instrumentation`]) had used by the end of the recording, which isn't
+    /// stored with the events of `pid`.
+    Overhead {
+        seq: u128,
+        timestamp: u128,
+        pid: i32,
+        /// The name of the process, e.g. "bpftrace".
+        command: String,
+        usage: ResourceUsage,
+    },
+    /// An event that proctrace doesn't know about, e.g. from an extra probe or a
+    /// custom collector, which is kept and rendered as a marker on its process.
+    Custom {
+        seq: u128,
+        timestamp: u128,
+        pid: i32,
+        name: String,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        fields: BTreeMap<String, String>,
     },
 }
 
@@ -133,10 +906,28 @@ impl std::fmt::Display for Event {
             Event::BadExec { seq, pid, .. } => write!(f, "BadExec(seq:{seq},pid:{pid})"),
             Event::ExecFilename { seq, pid, .. } => write!(f, "ExecFilename(seq:{seq},pid:{pid})"),
             Event::ExecArgs { seq, pid, .. } => write!(f, "ExecArgs(seq:{seq},pid:{pid})"),
+            Event::ExecArg {
+                seq, pid, index, ..
+            } => write!(f, "ExecArg(seq:{seq},pid:{pid},index:{index})"),
             Event::ExecFull { seq, pid, .. } => write!(f, "ExecFull(seq:{seq},pid:{pid})"),
             Event::Exit { seq, pid, .. } => write!(f, "Exit(seq:{seq},pid:{pid})"),
             Event::SetSID { seq, pid, .. } => write!(f, "SetSID(seq:{seq},pid:{pid})"),
             Event::SetPGID { seq, pid, .. } => write!(f, "SetPGID(seq:{seq},pid:{pid})"),
+            Event::Chdir { seq, pid, .. } => write!(f, "Chdir(seq:{seq},pid:{pid})"),
+            Event::Namespace { seq, pid, .. } => write!(f, "Namespace(seq:{seq},pid:{pid})"),
+            Event::Mount { seq, pid, .. } => write!(f, "Mount(seq:{seq},pid:{pid})"),
+            Event::Ptrace { seq, pid, .. } => write!(f, "Ptrace(seq:{seq},pid:{pid})"),
+            Event::Sandbox { seq, pid, .. } => write!(f, "Sandbox(seq:{seq},pid:{pid})"),
+            Event::Subreaper { seq, pid, .. } => write!(f, "Subreaper(seq:{seq},pid:{pid})"),
+            Event::Usage { seq, pid, .. } => write!(f, "Usage(seq:{seq},pid:{pid})"),
+            Event::CpuSample { seq, pid, .. } => write!(f, "CpuSample(seq:{seq},pid:{pid})"),
+            Event::RssSample { seq, pid, .. } => write!(f, "RssSample(seq:{seq},pid:{pid})"),
+            Event::HostSample { seq, .. } => write!(f, "HostSample(seq:{seq})"),
+            Event::ForkStorm { seq, pid, .. } => write!(f, "ForkStorm(seq:{seq},pid:{pid})"),
+            Event::Overhead { seq, pid, .. } => write!(f, "Overhead(seq:{seq},pid:{pid})"),
+            Event::Custom { seq, pid, name, .. } => {
+                write!(f, "Custom(seq:{seq},pid:{pid},name:{name})")
+            }
         }
     }
 }
@@ -150,9 +941,23 @@ impl Event {
             Event::ExecFilename { timestamp, .. } => *timestamp,
             Event::ExecFull { timestamp, .. } => *timestamp,
             Event::ExecArgs { timestamp, .. } => *timestamp,
+            Event::ExecArg { timestamp, .. } => *timestamp,
             Event::Exit { timestamp, .. } => *timestamp,
             Event::SetSID { timestamp, .. } => *timestamp,
             Event::SetPGID { timestamp, .. } => *timestamp,
+            Event::Chdir { timestamp, .. } => *timestamp,
+            Event::Namespace { timestamp, .. } => *timestamp,
+            Event::Mount { timestamp, .. } => *timestamp,
+            Event::Ptrace { timestamp, .. } => *timestamp,
+            Event::Sandbox { timestamp, .. } => *timestamp,
+            Event::Subreaper { timestamp, .. } => *timestamp,
+            Event::Usage { timestamp, .. } => *timestamp,
+            Event::CpuSample { timestamp, .. } => *timestamp,
+            Event::RssSample { timestamp, .. } => *timestamp,
+            Event::HostSample { timestamp, .. } => *timestamp,
+            Event::ForkStorm { timestamp, .. } => *timestamp,
+            Event::Overhead { timestamp, .. } => *timestamp,
+            Event::Custom { timestamp, .. } => *timestamp,
         }
     }
 
@@ -164,12 +969,143 @@ impl Event {
             Event::ExecFilename { seq, .. } => *seq,
             Event::ExecFull { seq, .. } => *seq,
             Event::ExecArgs { seq, .. } => *seq,
+            Event::ExecArg { seq, .. } => *seq,
             Event::Exit { seq, .. } => *seq,
             Event::SetSID { seq, .. } => *seq,
             Event::SetPGID { seq, .. } => *seq,
+            Event::Chdir { seq, .. } => *seq,
+            Event::Namespace { seq, .. } => *seq,
+            Event::Mount { seq, .. } => *seq,
+            Event::Ptrace { seq, .. } => *seq,
+            Event::Sandbox { seq, .. } => *seq,
+            Event::Subreaper { seq, .. } => *seq,
+            Event::Usage { seq, .. } => *seq,
+            Event::CpuSample { seq, .. } => *seq,
+            Event::RssSample { seq, .. } => *seq,
+            Event::HostSample { seq, .. } => *seq,
+            Event::ForkStorm { seq, .. } => *seq,
+            Event::Overhead { seq, .. } => *seq,
+            Event::Custom { seq, .. } => *seq,
         }
     }
 
+    /// Where the event falls in the recording: its timestamp, with its sequence number
+    /// breaking ties so that events that happened at the same time keep their order
+    /// when a recording is rendered and read back in.
+    pub fn order_key(&self) -> (u128, u128) {
+        (self.timestamp(), self.seq())
+    }
+
+    /// Sets the sequence number and timestamp of the event, e.g. to move it onto the
+    /// timeline of another recording.
+    pub fn set_seq_and_timestamp(&mut self, new_seq: u128, new_timestamp: u128) {
+        match self {
+            Event::Fork { seq, timestamp, .. }
+            | Event::Exec { seq, timestamp, .. }
+            | Event::BadExec { seq, timestamp, .. }
+            | Event::ExecFilename { seq, timestamp, .. }
+            | Event::ExecFull { seq, timestamp, .. }
+            | Event::ExecArgs { seq, timestamp, .. }
+            | Event::ExecArg { seq, timestamp, .. }
+            | Event::Exit { seq, timestamp, .. }
+            | Event::SetSID { seq, timestamp, .. }
+            | Event::SetPGID { seq, timestamp, .. }
+            | Event::Chdir { seq, timestamp, .. }
+            | Event::Namespace { seq, timestamp, .. }
+            | Event::Mount { seq, timestamp, .. }
+            | Event::Ptrace { seq, timestamp, .. }
+            | Event::Sandbox { seq, timestamp, .. }
+            | Event::Subreaper { seq, timestamp, .. }
+            | Event::Usage { seq, timestamp, .. }
+            | Event::CpuSample { seq, timestamp, .. }
+            | Event::RssSample { seq, timestamp, .. }
+            | Event::HostSample { seq, timestamp, .. }
+            | Event::ForkStorm { seq, timestamp, .. }
+            | Event::Overhead { seq, timestamp, .. }
+            | Event::Custom { seq, timestamp, .. } => {
+                *seq = new_seq;
+                *timestamp = new_timestamp;
+            }
+        }
+    }
+
+    /// Replaces every PID, process group, and session ID in the event with `f` of it.
+    pub fn map_pids(&mut self, mut f: impl FnMut(i32) -> i32) {
+        match self {
+            Event::Fork {
+                parent_pid,
+                child_pid,
+                parent_pgid,
+                ..
+            } => {
+                *parent_pid = f(*parent_pid);
+                *child_pid = f(*child_pid);
+                *parent_pgid = f(*parent_pgid);
+            }
+            Event::Exec {
+                pid, ppid, pgid, ..
+            }
+            | Event::ExecFull {
+                pid, ppid, pgid, ..
+            }
+            | Event::Exit {
+                pid, ppid, pgid, ..
+            } => {
+                *pid = f(*pid);
+                *ppid = f(*ppid);
+                *pgid = f(*pgid);
+            }
+            Event::SetPGID {
+                pid,
+                ppid,
+                pgid,
+                caller_pid,
+                ..
+            } => {
+                *pid = f(*pid);
+                *ppid = f(*ppid);
+                *pgid = f(*pgid);
+                *caller_pid = caller_pid.map(&mut f);
+            }
+            Event::SetSID {
+                pid,
+                ppid,
+                pgid,
+                sid,
+                ..
+            } => {
+                *pid = f(*pid);
+                *ppid = f(*ppid);
+                *pgid = f(*pgid);
+                *sid = f(*sid);
+            }
+            Event::BadExec { pid, .. }
+            | Event::ExecFilename { pid, .. }
+            | Event::ExecArgs { pid, .. }
+            | Event::ExecArg { pid, .. }
+            | Event::ForkStorm { pid, .. }
+            | Event::Chdir { pid, .. }
+            | Event::Namespace { pid, .. }
+            | Event::Mount { pid, .. }
+            | Event::Sandbox { pid, .. }
+            | Event::Subreaper { pid, .. }
+            | Event::Usage { pid, .. }
+            | Event::CpuSample { pid, .. }
+            | Event::RssSample { pid, .. }
+            | Event::Custom { pid, .. } => *pid = f(*pid),
+            Event::Ptrace {
+                pid, target_pid, ..
+            } => {
+                *pid = f(*pid);
+                *target_pid = target_pid.map(&mut f);
+            }
+            // Not a PID in the recording
+            Event::HostSample { .. } | Event::Overhead { .. } => {}
+        }
+    }
+
+    /// Returns the PID of the process the event belongs to, which is 0 for events that
+    /// don't belong to a process (see [`Event::is_process_event`]).
     pub fn pid(&self) -> i32 {
         match self {
             Event::Fork { child_pid, .. } => *child_pid,
@@ -178,12 +1114,44 @@ impl Event {
             Event::ExecFilename { pid, .. } => *pid,
             Event::ExecFull { pid, .. } => *pid,
             Event::ExecArgs { pid, .. } => *pid,
+            Event::ExecArg { pid, .. } => *pid,
             Event::Exit { pid, .. } => *pid,
             Event::SetSID { pid, .. } => *pid,
             Event::SetPGID { pid, .. } => *pid,
+            Event::Chdir { pid, .. } => *pid,
+            Event::Namespace { pid, .. } => *pid,
+            Event::Mount { pid, .. } => *pid,
+            Event::Ptrace { pid, .. } => *pid,
+            Event::Sandbox { pid, .. } => *pid,
+            Event::Subreaper { pid, .. } => *pid,
+            Event::Usage { pid, .. } => *pid,
+            Event::CpuSample { pid, .. } => *pid,
+            Event::RssSample { pid, .. } => *pid,
+            Event::HostSample { .. } => 0,
+            Event::ForkStorm { .. } => 0,
+            Event::Overhead { .. } => 0,
+            Event::Custom { pid, .. } => *pid,
         }
     }
 
+    pub fn is_host_sample(&self) -> bool {
+        matches!(self, Event::HostSample { .. })
+    }
+
+    pub fn is_fork_storm(&self) -> bool {
+        matches!(self, Event::ForkStorm { .. })
+    }
+
+    pub fn is_overhead(&self) -> bool {
+        matches!(self, Event::Overhead { .. })
+    }
+
+    /// Returns `false` for events that are about the whole recording rather than one
+    /// process, like host samples, fork storm warnings, and the recorder's overhead.
+    pub fn is_process_event(&self) -> bool {
+        !self.is_host_sample() && !self.is_fork_storm() && !self.is_overhead()
+    }
+
     pub fn is_fork(&self) -> bool {
         matches!(self, Event::Fork { .. })
     }
@@ -205,6 +1173,14 @@ impl Event {
     }
 
     #[allow(dead_code)]
+    /// Returns the exit code if this is an exit event that recorded one.
+    pub fn exit_code(&self) -> Option<i32> {
+        match self {
+            Event::Exit { exit_code, .. } => *exit_code,
+            _ => None,
+        }
+    }
+
     pub fn is_exit(&self) -> bool {
         matches!(self, Event::Exit { .. })
     }
@@ -230,19 +1206,45 @@ impl EventStore {
         }
     }
 
+    /// Creates an event store from the events of any number of processes, each
+    /// stored under the PID it belongs to.
+    pub fn from_events(events: impl IntoIterator<Item = Event>) -> Self {
+        let mut store = Self::new();
+        for event in events {
+            store.add(event.pid(), event);
+        }
+        store
+    }
+
     /// Store a new event for a given PID.
-    pub fn add(&mut self, pid: i32, event: &Event) {
+    pub fn add(&mut self, pid: i32, event: Event) {
         let events = self.inner.entry(pid).or_default();
         // Events are stored in timestamp-sorted order
         let insert_point = match events.binary_search_by_key(&event.seq(), |event| event.seq()) {
             Ok(found_idx) => found_idx + 1,
             Err(candidate_idx) => candidate_idx,
         };
-        events.insert(insert_point, event.clone());
+        events.insert(insert_point, event);
+    }
+
+    /// Store only the latest event for a given PID, discarding any earlier events.
+    ///
+    /// This is enough to keep track of which PIDs are known and whether they've
+    /// finished without holding on to every event.
+    pub fn replace(&mut self, pid: i32, event: Event) {
+        let events = self.inner.entry(pid).or_default();
+        if events
+            .back()
+            .is_some_and(|latest| latest.seq() > event.seq())
+        {
+            return;
+        }
+        events.clear();
+        events.push_back(event);
     }
 
     /// Add several events from the same PID.
-    pub fn add_many<'a>(&mut self, pid: i32, new_events: impl Iterator<Item = &'a Event>) {
+    pub fn add_many(&mut self, pid: i32, new_events: impl IntoIterator<Item = Event>) {
         for event in new_events {
             self.add(pid, event);
         }
@@ -278,11 +1280,26 @@ impl EventStore {
             })
     }
 
+    /// Returns an iterator over the PID and buffer of events for each stored PID.
+    pub fn iter(&self) -> impl Iterator<Item = (i32, &VecDeque<Event>)> {
+        self.inner.iter().map(|(pid, buffer)| (*pid, buffer))
+    }
+
+    /// Returns the buffer of events for a PID, if it's stored.
+    pub fn events_of(&self, pid: i32) -> Option<&VecDeque<Event>> {
+        self.inner.get(&pid)
+    }
+
     /// Returns `true` if no PIDs have been registered.
     pub fn is_empty(&self) -> bool {
         self.inner.is_empty()
     }
 
+    /// Returns the number of PIDs that have been registered.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
     /// Returns the set of currently tracked PIDs.
     pub fn pids(&self) -> HashSet<i32> {
         self.inner.keys().cloned().collect::<HashSet<_>>()
@@ -297,6 +1314,310 @@ impl EventStore {
             .and_then(|event| event.fork_parent())
     }
 
+    /// Returns the ancestors of a PID from its parent upwards, as far as the forks in
+    /// the store go.
+    ///
+    /// The last one may not be stored itself, e.g. the parent of the root. PIDs are
+    /// reused, so a recording can make a process its own ancestor, and the walk stops
+    /// before visiting any PID twice.
+    pub fn ancestors(&self, pid: i32) -> Ancestors<'_> {
+        Ancestors {
+            store: self,
+            current: pid,
+            visited: HashSet::from([pid]),
+        }
+    }
+
+    /// Returns the PIDs of processes that daemonized, along with all of their
+    /// descendants.
+    ///
+    /// A process daemonized if it's in a session that was started within the tree
+    /// (by itself or an ancestor, as with a double fork) and it outlived its parent.
+    /// These are detached from the process tree, so their spans look like orphans.
+    /// Processes that were reparented to a child subreaper in the tree instead (see
+    /// [`EventStore::reaper_of`]) never left it, so they aren't daemons.
+    pub fn daemon_pids(&self) -> HashSet<i32> {
+        let session_leaders = self
+            .inner
+            .iter()
+            .filter(|(_, buffer)| {
+                buffer
+                    .iter()
+                    .any(|event| matches!(event, Event::SetSID { .. }))
+            })
+            .map(|(pid, _)| *pid)
+            .collect::<HashSet<_>>();
+        if session_leaders.is_empty() {
+            return HashSet::new();
+        }
+        let mut daemons = HashSet::new();
+        for (pid, buffer) in self.inner.iter() {
+            let in_new_session = session_leaders.contains(pid)
+                || self
+                    .ancestors(*pid)
+                    .any(|ppid| session_leaders.contains(&ppid));
+            let Some(parent) = self
+                .parent_of_pid_if_stored(*pid)
+                .and_then(|ppid| self.inner.get(&ppid))
+            else {
+                continue;
+            };
+            let parent_exit = parent.back().filter(|event| event.is_exit());
+            let exit = buffer.back().filter(|event| event.is_exit());
+            let outlived_parent = match (parent_exit, exit) {
+                (Some(parent_exit), Some(exit)) => parent_exit.seq() < exit.seq(),
+                (Some(_), None) => true,
+                _ => false,
+            };
+            if in_new_session && outlived_parent && self.reaper_of(*pid).is_none() {
+                daemons.insert(*pid);
+            }
+        }
+        self.inner
+            .keys()
+            .filter(|pid| {
+                daemons.contains(pid) || self.ancestors(**pid).any(|ppid| daemons.contains(&ppid))
+            })
+            .copied()
+            .collect()
+    }
+
+    /// Returns the process group that a PID was last seen in, if any of its events
+    /// say so.
+    ///
+    /// The last group it joined with `setsid` or `setpgid` (including calls made by its
+    /// parent) wins over the group of later events, since some backends only report
+    /// the group leader of a process on exec and exit.
+    pub fn pgid_of(&self, pid: i32) -> Option<i32> {
+        let buffer = self.inner.get(&pid)?;
+        let joined = buffer.iter().rev().find_map(|event| match event {
+            Event::SetSID { pgid, .. } | Event::SetPGID { pgid, .. } => Some(*pgid),
+            _ => None,
+        });
+        joined.or_else(|| {
+            buffer.iter().rev().find_map(|event| match event {
+                Event::Exec { pgid, .. }
+                | Event::ExecFull { pgid, .. }
+                | Event::Exit { pgid, .. }
+                    if *pgid != UNKNOWN_PGID =>
+                {
+                    Some(*pgid)
+                }
+                _ => None,
+            })
+        })
+    }
+
+    /// Returns the kinds of namespaces that a PID is in that the recording saw being
+    /// created or entered, by the process itself or by an ancestor before it forked
+    /// the next process on the way down to this one.
+    ///
+    /// Processes inherit the namespaces of their parent, so this is how a sandbox
+    /// (e.g. `bwrap` or a container runtime) is told apart from its children.
+    pub fn namespaces_of(&self, pid: i32) -> BTreeSet<String> {
+        let entered = |buffer: &VecDeque<Event>, before: Option<u128>| {
+            buffer
+                .iter()
+                .filter(move |event| before.is_none_or(|seq| event.seq() < seq))
+                .filter_map(|event| match event {
+                    Event::Namespace { namespaces, .. } => Some(namespaces.iter().cloned()),
+                    _ => None,
+                })
+                .flatten()
+                .collect::<Vec<_>>()
+        };
+        let mut namespaces = BTreeSet::new();
+        let mut before = None;
+        for current in std::iter::once(pid).chain(self.ancestors(pid)) {
+            let Some(buffer) = self.inner.get(&current) else {
+                break;
+            };
+            namespaces.extend(entered(buffer, before));
+            // Only what an ancestor did before forking the next process down counts
+            before = buffer.front().map(|fork| fork.seq());
+        }
+        namespaces
+    }
+
+    /// Returns the directory that a process was last in, following the `chdir`s of the
+    /// process and of its ancestors from `start`, the directory the recording started
+    /// in.
+    ///
+    /// Returns `None` if it isn't known, e.g. after an `fchdir` or a relative `chdir`
+    /// from an unknown directory.
+    pub fn directory_of(&self, pid: i32, start: Option<&str>) -> Option<String> {
+        let changed = |buffer: &VecDeque<Event>, before: Option<u128>| {
+            buffer
+                .iter()
+                .filter(|event| before.is_none_or(|seq| event.seq() < seq))
+                .filter_map(|event| match event {
+                    Event::Chdir { path, .. } => Some(path.clone()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        // Collected from the process up to the root, so they're applied in reverse
+        let mut changes = vec![];
+        let mut before = None;
+        for current in std::iter::once(pid).chain(self.ancestors(pid)) {
+            let Some(buffer) = self.inner.get(&current) else {
+                break;
+            };
+            changes.extend(changed(buffer, before).into_iter().rev());
+            before = buffer.front().map(|fork| fork.seq());
+        }
+        let mut directory = start.map(PathBuf::from);
+        for change in changes.into_iter().rev() {
+            directory = match change {
+                Some(path) if Path::new(&path).is_absolute() => Some(PathBuf::from(path)),
+                Some(path) => directory.map(|directory| directory.join(path)),
+                None => None,
+            };
+        }
+        directory.map(|directory| normalize_path(&directory))
+    }
+
+    /// Returns the user and group that a process last ran as, inheriting them from its
+    /// ancestors if none of its own events say.
+    ///
+    /// Returns `None` for recordings that don't have credentials.
+    pub fn credentials_of(&self, pid: i32) -> Option<Credentials> {
+        std::iter::once(pid)
+            .chain(self.ancestors(pid))
+            .map_while(|current| self.inner.get(&current))
+            .find_map(|buffer| {
+                buffer.iter().rev().find_map(|event| match event {
+                    Event::Fork { credentials, .. }
+                    | Event::Exec { credentials, .. }
+                    | Event::ExecFull { credentials, .. } => *credentials,
+                    _ => None,
+                })
+            })
+    }
+
+    /// Removes the processes that `filter` leaves out.
+    ///
+    /// The root and its ancestors are always kept so that there's a tree to render.
+    /// Processes whose user isn't known, e.g. from older recordings, never match.
+    pub fn retain_users(&mut self, root_pid: i32, filter: &UserFilter) {
+        if filter.is_empty() {
+            return;
+        }
+        let is_user = |pid: i32, uids: &[u32]| {
+            self.credentials_of(pid)
+                .is_some_and(|credentials| uids.contains(&credentials.uid))
+        };
+        let mut kept = HashSet::from([root_pid]);
+        kept.extend(self.ancestors(root_pid));
+        for pid in self.inner.keys().copied() {
+            if !filter.include.is_empty() && !is_user(pid, &filter.include) {
+                continue;
+            }
+            let lineage = self.ancestors(pid).collect::<Vec<_>>();
+            let excluded = std::iter::once(pid)
+                .chain(lineage.iter().copied())
+                .take_while(|pid| *pid != root_pid)
+                .any(|pid| is_user(pid, &filter.exclude));
+            if excluded {
+                continue;
+            }
+            kept.insert(pid);
+            if !filter.include.is_empty() {
+                kept.extend(lineage);
+            }
+        }
+        self.inner.retain(|pid, _| kept.contains(pid));
+    }
+
+    /// Returns the process that a PID was reparented to when its parent exited, if it
+    /// was a child subreaper in the recording rather than init.
+    ///
+    /// Daemon managers (e.g. `tini` or `systemd --user`) make themselves subreapers so
+    /// that orphans are handed to them, which keeps the orphans in the process tree.
+    pub fn reaper_of(&self, pid: i32) -> Option<i32> {
+        let parent = self.parent_of_pid_if_stored(pid)?;
+        let orphaned = self
+            .inner
+            .get(&parent)?
+            .back()
+            .filter(|event| event.is_exit())?
+            .seq();
+        let exited_first = self
+            .inner
+            .get(&pid)?
+            .back()
+            .is_some_and(|event| event.is_exit() && event.seq() < orphaned);
+        if exited_first {
+            return None;
+        }
+        self.subreaper_above(parent, orphaned)
+    }
+
+    /// Returns the nearest ancestor of a PID that had made itself a child subreaper
+    /// and was still running when the event with `seq` happened.
+    pub fn subreaper_above(&self, pid: i32, seq: u128) -> Option<i32> {
+        self.ancestors(pid)
+            .map_while(|ancestor| Some((ancestor, self.inner.get(&ancestor)?)))
+            .find(|(_, buffer)| {
+                let reaping = buffer
+                    .iter()
+                    .any(|event| matches!(event, Event::Subreaper { .. }) && event.seq() < seq);
+                let exited = buffer
+                    .back()
+                    .is_some_and(|event| event.is_exit() && event.seq() < seq);
+                reaping && !exited
+            })
+            .map(|(ancestor, _)| ancestor)
+    }
+
+    /// Returns the shell pipelines in the process tree, each as its PIDs in fork order.
+    ///
+    /// A shell with job control puts every command of a pipeline in a new process
+    /// group, so a pipeline is a run of siblings that were forked back-to-back and
+    /// share a process group that their parent isn't in. Shells without job control
+    /// leave pipelines in their own process group, so they aren't detected.
+    pub fn pipelines(&self) -> Vec<Vec<i32>> {
+        let mut children: BTreeMap<i32, Vec<i32>> = BTreeMap::new();
+        for (pid, buffer) in self.inner.iter() {
+            if let Some(Event::Fork { parent_pid, .. }) = buffer.front() {
+                children.entry(*parent_pid).or_default().push(*pid);
+            }
+        }
+        let fresh_pgid = |pid: i32| {
+            let Some(Event::Fork { parent_pgid, .. }) = self.inner.get(&pid)?.front() else {
+                return None;
+            };
+            if *parent_pgid == UNKNOWN_PGID {
+                return None;
+            }
+            self.pgid_of(pid).filter(|pgid| pgid != parent_pgid)
+        };
+        let mut pipelines = vec![];
+        for siblings in children.values_mut() {
+            siblings.sort_by_key(|pid| (self.pid_start_key(*pid), *pid));
+            let mut run: Vec<i32> = vec![];
+            let mut run_pgid = None;
+            for pid in siblings.iter().copied() {
+                let pgid = fresh_pgid(pid);
+                if pgid.is_none() || pgid != run_pgid {
+                    let finished = std::mem::take(&mut run);
+                    if finished.len() > 1 {
+                        pipelines.push(finished);
+                    }
+                    run_pgid = pgid;
+                }
+                if pgid.is_some() {
+                    run.push(pid);
+                }
+            }
+            if run.len() > 1 {
+                pipelines.push(run);
+            }
+        }
+        pipelines.sort_by_key(|pipeline| self.pid_start_key(pipeline[0]));
+        pipelines
+    }
+
     /// Returns an iterator over stored events in order.
     pub fn events_ordered(self) -> impl Iterator<Item = Event> {
         let mut all_events = self
@@ -308,24 +1629,30 @@ impl EventStore {
         all_events.into_iter()
     }
 
+    /// Returns an iterator over references to the stored events in order.
+    pub fn iter_ordered(&self) -> impl Iterator<Item = &Event> {
+        let mut all_events = self
+            .inner
+            .values()
+            .flat_map(|buffer| buffer.iter())
+            .collect::<Vec<_>>();
+        all_events.sort();
+        all_events.into_iter()
+    }
+
     /// Returns an iterator over the PID and buffer for each tracked PID
-    /// in order of the timestamp of the earliest event for each PID.
-    pub fn into_pid_buffers_ordered(mut self) -> impl Iterator<Item = (i32, VecDeque<Event>)> {
-        let mut pid_to_ts = self
+    /// in order of the earliest event for each PID (see [`Event::order_key`]).
+    pub fn pid_buffers_ordered(&self) -> impl Iterator<Item = (i32, &VecDeque<Event>)> {
+        let mut pids_and_buffers = self
             .inner
             .iter()
-            .filter_map(|(&pid, buffer)| {
-                // It shouldn't be possible for a buffer to be here and be empty,
-                // so if we find an empty buffer we just drop it for now.
-                // TODO: write some kind of log about the bad PID
-                buffer.front().map(|event| (pid, event.timestamp()))
-            })
+            // It shouldn't be possible for a buffer to be here and be empty,
+            // so if we find an empty buffer we just drop it for now.
+            // TODO: write some kind of log about the bad PID
+            .filter(|(_, buffer)| !buffer.is_empty())
+            .map(|(&pid, buffer)| (pid, buffer))
             .collect::<Vec<_>>();
-        pid_to_ts.sort_by_key(|(_, ts)| *ts);
-        let mut pids_and_buffers = vec![];
-        for (pid, _) in pid_to_ts.into_iter() {
-            pids_and_buffers.push((pid, self.inner.remove(&pid).unwrap()));
-        }
+        pids_and_buffers.sort_by_key(|(_, buffer)| buffer.front().map(Event::order_key));
         pids_and_buffers.into_iter()
     }
 
@@ -340,22 +1667,24 @@ impl EventStore {
         });
     }
 
-    /// Returns an iterator over the buffers in depth-first fork order.
+    /// Returns an iterator over the buffers in depth-first fork order, one root
+    /// after another.
     pub fn buffers_depth_first_fork_order(
-        mut self,
-        root_pid: i32,
-    ) -> Result<impl Iterator<Item = (i32, VecDeque<Event>)>, Error> {
+        &self,
+        roots: &[i32],
+    ) -> Result<impl Iterator<Item = (i32, &VecDeque<Event>)>, Error> {
         let mut pids_ordered = vec![];
-        pids_ordered.push(root_pid);
-        pids_ordered.extend_from_slice(&self.find_child_pids(root_pid));
+        for root_pid in roots.iter() {
+            pids_ordered.push(*root_pid);
+            pids_ordered.extend_from_slice(&self.find_child_pids(*root_pid));
+        }
+        // A root may also be a descendant of an earlier root, in which case its
+        // buffer has already been listed.
+        let mut seen = HashSet::new();
         let pids_and_buffers = pids_ordered
             .into_iter()
-            .map(|pid| {
-                (
-                    pid,
-                    self.inner.remove(&pid).expect("failed to remove buffer"),
-                )
-            })
+            .filter(|pid| seen.insert(*pid))
+            .filter_map(|pid| self.inner.get(&pid).map(|buffer| (pid, buffer)))
             .collect::<Vec<_>>();
         Ok(pids_and_buffers.into_iter())
     }
@@ -370,7 +1699,7 @@ impl EventStore {
             })
             .copied()
             .collect::<Vec<_>>();
-        direct_children.sort_by_key(|pid| self.pid_start_time(*pid));
+        direct_children.sort_by_key(|pid| self.pid_start_key(*pid));
         let mut all_children = vec![];
         for pid in direct_children.into_iter() {
             all_children.push(pid);
@@ -387,26 +1716,105 @@ impl EventStore {
             .map(|event| event.timestamp())
     }
 
+    /// Returns the [`Event::order_key`] of the first event of `pid`, which orders
+    /// processes that started at the same time by when they were seen.
+    pub fn pid_start_key(&self, pid: i32) -> Option<(u128, u128)> {
+        self.inner
+            .get(&pid)
+            .and_then(|buffer| buffer.front())
+            .map(Event::order_key)
+    }
+
     /// Fills out the `cmdline` field of all `Exec` events from `ExecArgs` events,
     /// removing the `ExecArgs` events in the process.
     pub(crate) fn collapse_execs(&mut self) {
         let collapsed = BTreeMap::new();
         let original = std::mem::replace(&mut self.inner, collapsed);
         for (pid, buffer) in original.into_iter() {
-            let new_buffer = collapse_buffer_execs(buffer.iter());
+            let new_buffer = collapse_buffer_execs(buffer);
             self.inner.insert(pid, new_buffer);
         }
     }
 
-    /// Performs any necessary post processing of the stored events.
-    pub(crate) fn post_process_buffers(&mut self) {
+    /// Rewrites the file names and arguments of every exec event with `f`.
+    pub fn rewrite_exec_strings(&mut self, f: impl Fn(&str) -> String) {
+        for event in self.inner.values_mut().flat_map(|buffer| buffer.iter_mut()) {
+            match event {
+                Event::Exec {
+                    cmdline: Some(args),
+                    ..
+                }
+                | Event::ExecArgs { args, .. } => *args = args.map(&f),
+                Event::ExecFilename { filename, .. } => *filename = f(filename),
+                Event::ExecFull { filename, args, .. } => {
+                    *filename = f(filename);
+                    *args = args.map(&f);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Replaces the commands of every exec that `labels` has a name for.
+    pub fn label_execs(&mut self, labels: &Labels) {
+        if labels.is_empty() {
+            return;
+        }
+        for event in self.inner.values_mut().flat_map(|buffer| buffer.iter_mut()) {
+            labels.apply(event);
+        }
+    }
+
+    /// Performs any necessary post processing of the stored events, returning the
+    /// number of timestamps that were repaired.
+    pub(crate) fn post_process_buffers(&mut self) -> u64 {
         for buffer in self.inner.values_mut() {
-            *buffer = clean_exec_sequences(buffer.make_contiguous());
+            *buffer = clean_exec_sequences(std::mem::take(buffer));
+        }
+        self.normalize_timestamps()
+    }
+
+    /// Moves timestamps that go backwards up to the timestamp of the event before them,
+    /// returning the number of events that were moved.
+    ///
+    /// Events are numbered in the order they happened, but the CPUs they happened on
+    /// don't always agree on the time, so an event can be stamped a little earlier
+    /// than the one before it. Spans that start before the root process or end before
+    /// they start would break the math of charts and analyses.
+    fn normalize_timestamps(&mut self) -> u64 {
+        let mut order = self
+            .inner
+            .iter()
+            .flat_map(|(pid, buffer)| {
+                buffer
+                    .iter()
+                    .enumerate()
+                    .map(move |(idx, event)| (event.seq(), *pid, idx))
+            })
+            .collect::<Vec<_>>();
+        order.sort();
+        let mut latest = 0;
+        let mut adjusted = 0;
+        for (_, pid, idx) in order {
+            let Some(event) = self
+                .inner
+                .get_mut(&pid)
+                .and_then(|buffer| buffer.get_mut(idx))
+            else {
+                continue;
+            };
+            if event.timestamp() < latest {
+                event.set_seq_and_timestamp(event.seq(), latest);
+                adjusted += 1;
+            } else {
+                latest = event.timestamp();
+            }
         }
+        adjusted
     }
 }
 
-fn collapse_buffer_execs<'a>(events: impl Iterator<Item = &'a Event>) -> VecDeque<Event> {
+pub(crate) fn collapse_buffer_execs(events: impl IntoIterator<Item = Event>) -> VecDeque<Event> {
     use Event::*;
 
     let mut buffer = VecDeque::new();
@@ -422,8 +1830,7 @@ fn collapse_buffer_execs<'a>(events: impl Iterator<Item = &'a Event>) -> VecDequ
                     // so we need to unbuffer the existing events and start buffering again.
 
                     // Unbuffer the existing events.
-                    let exec = fill_in_exec_args(&execs);
-                    execs.clear();
+                    let exec = fill_in_exec_args(std::mem::take(&mut execs));
 
                     // Store the exec that was previously buffered.
                     if let Some(exec) = exec {
@@ -448,16 +1855,15 @@ fn collapse_buffer_execs<'a>(events: impl Iterator<Item = &'a Event>) -> VecDequ
                     // so we need to unbuffer the existing events.
 
                     // Unbuffer the existing events.
-                    let exec = fill_in_exec_args(&execs);
-                    execs.clear();
+                    let exec = fill_in_exec_args(std::mem::take(&mut execs));
                     if let Some(exec) = exec {
                         // Store the exec that was previously buffered.
                         buffer.push_back(exec);
                     }
 
-                    buffer.push_back(event.clone());
+                    buffer.push_back(event);
                 } else {
-                    buffer.push_back(event.clone());
+                    buffer.push_back(event);
                 }
             }
         }
@@ -465,7 +1871,7 @@ fn collapse_buffer_execs<'a>(events: impl Iterator<Item = &'a Event>) -> VecDequ
 
     // The last few events may have been execs, so we need to unbuffer them before returning.
     if !execs.is_empty() {
-        let exec = fill_in_exec_args(&execs);
+        let exec = fill_in_exec_args(execs);
         if let Some(exec) = exec {
             buffer.push_back(exec);
         }
@@ -479,35 +1885,54 @@ fn collapse_buffer_execs<'a>(events: impl Iterator<Item = &'a Event>) -> VecDequ
 /// Note that because the exec args come from two different sources, sometimes you get more
 /// information from one vs. the other. When they don't match we just take the longer of
 /// the two since it probably has more information.
-fn fill_in_exec_args(execs: &[&Event]) -> Option<Event> {
+fn fill_in_exec_args(execs: Vec<Event>) -> Option<Event> {
     use Event::*;
 
-    match execs {
-        [] => None,
-        [event @ Exec { .. }] => Some((*event).clone()),
-        [Exec {
+    let mut execs = execs.into_iter();
+    match (execs.next(), execs.next(), execs.next(), execs.next()) {
+        (Some(event @ Exec { .. }), None, None, None) => Some(event),
+        (
+            Some(Exec {
+                seq,
+                pid,
+                timestamp,
+                ppid,
+                pgid,
+                cgroup,
+                ns_pid,
+                credentials,
+                ..
+            }),
+            Some(ExecArgs { args, .. }),
+            None,
+            None,
+        ) => Some(Exec {
             seq,
-            pid,
+            cmdline: Some(args),
             timestamp,
-            ppid,
-            pgid,
-            ..
-        }, ExecArgs { args, .. }] => Some(Exec {
-            seq: *seq,
-            cmdline: Some(args.clone()),
-            timestamp: *timestamp,
-            pid: *pid,
-            ppid: *ppid,
-            pgid: *pgid,
-        }),
-        [Exec {
-            seq,
             pid,
-            timestamp,
             ppid,
             pgid,
-            ..
-        }, ExecArgs { args: args1, .. }, ExecArgs { args: args2, .. }] => {
+            cgroup,
+            ns_pid,
+            credentials,
+        }),
+        (
+            Some(Exec {
+                seq,
+                pid,
+                timestamp,
+                ppid,
+                pgid,
+                cgroup,
+                ns_pid,
+                credentials,
+                ..
+            }),
+            Some(ExecArgs { args: args1, .. }),
+            Some(ExecArgs { args: args2, .. }),
+            None,
+        ) => {
             let joined1 = args1.to_string();
             let joined2 = args2.to_string();
             let args = if joined1.len() > joined2.len() {
@@ -516,12 +1941,15 @@ fn fill_in_exec_args(execs: &[&Event]) -> Option<Event> {
                 args2
             };
             Some(Exec {
-                seq: *seq,
-                pid: *pid,
-                ppid: *ppid,
-                pgid: *pgid,
-                timestamp: *timestamp,
-                cmdline: Some(args.clone()),
+                seq,
+                pid,
+                ppid,
+                pgid,
+                timestamp,
+                cmdline: Some(args),
+                cgroup,
+                ns_pid,
+                credentials,
             })
         }
         _ => None,
@@ -553,7 +1981,7 @@ mod test {
         shuffled.swap(2, 3);
 
         let mut store = EventStore::new();
-        store.add_many(1, shuffled.iter());
+        store.add_many(1, shuffled.iter().cloned());
 
         let stored = store
             .inner
@@ -580,15 +2008,266 @@ mod test {
             ],
         );
 
-        let mut store = EventStore::new();
-        for event in events.iter() {
-            store.add(event.pid(), event);
-        }
+        let store = EventStore::from_events(events);
 
         let unfinished = store.unfinished_pids().collect::<Vec<_>>();
         assert_eq!(unfinished, vec![1, 2]);
     }
 
+    #[test]
+    fn walks_ancestors_without_looping() {
+        let events = make_simple_events(
+            0,
+            0,
+            &[
+                ("fork", 1, 0),
+                ("fork", 2, 1),
+                ("fork", 3, 2),
+                // A reused PID makes these two each other's parent
+                ("fork", 5, 4),
+                ("fork", 4, 5),
+            ],
+        );
+        let store = EventStore::from_events(events);
+
+        assert_eq!(store.ancestors(3).collect::<Vec<_>>(), vec![2, 1, 0]);
+        assert_eq!(store.ancestors(1).collect::<Vec<_>>(), vec![0]);
+        assert_eq!(store.ancestors(5).collect::<Vec<_>>(), vec![4]);
+    }
+
+    #[test]
+    fn finds_daemons() {
+        let events = make_simple_events(
+            0,
+            0,
+            &[
+                ("fork", 1, 0),
+                // Double fork, where the middle process exits right away
+                ("fork", 2, 1),
+                ("setsid", 2, 1),
+                ("fork", 3, 2),
+                ("exit", 2, 1),
+                ("fork", 4, 3),
+                // A new session whose parent waits for it isn't a daemon
+                ("fork", 5, 1),
+                ("setsid", 5, 1),
+                ("exit", 5, 1),
+                ("exit", 1, 0),
+            ],
+        );
+        let store = EventStore::from_events(events);
+        let mut daemons = store.daemon_pids().into_iter().collect::<Vec<_>>();
+        daemons.sort();
+        assert_eq!(daemons, vec![3, 4]);
+    }
+
+    #[test]
+    fn reattaches_orphans_to_subreapers() {
+        let events = make_simple_events(
+            0,
+            0,
+            &[
+                ("fork", 1, 0),
+                ("subreaper", 1, 0),
+                ("fork", 2, 1),
+                ("fork", 3, 2),
+                ("setsid", 3, 2),
+                ("fork", 4, 3),
+                ("exit", 3, 2),
+                // Exits before its parent, so it's never orphaned
+                ("fork", 5, 2),
+                ("exit", 5, 2),
+                ("exit", 2, 1),
+                ("exit", 4, 1),
+                ("exit", 1, 0),
+            ],
+        );
+        let store = EventStore::from_events(events);
+        assert_eq!(store.reaper_of(4), Some(1));
+        assert_eq!(store.reaper_of(5), None);
+        assert_eq!(store.reaper_of(2), None);
+        // The double fork didn't get away from the subreaper
+        assert!(store.daemon_pids().is_empty());
+    }
+
+    #[test]
+    fn tracks_groups_set_by_the_parent() {
+        let events = [
+            Event::Fork {
+                seq: 0,
+                timestamp: 0,
+                parent_pid: 1,
+                child_pid: 3,
+                parent_pgid: 1,
+                credentials: None,
+                comm: None,
+            },
+            // The shell moves its child into the group of the pipeline
+            Event::SetPGID {
+                seq: 1,
+                timestamp: 1,
+                pid: 3,
+                ppid: 1,
+                pgid: 2,
+                caller_pid: Some(1),
+            },
+            // Exits only report the group leader of the process
+            Event::Exit {
+                seq: 2,
+                timestamp: 2,
+                pid: 3,
+                ppid: 1,
+                pgid: 3,
+                exit_code: Some(0),
+            },
+        ];
+        let store = EventStore::from_events(events);
+        assert_eq!(store.pgid_of(3), Some(2));
+        assert_eq!(store.pgid_of(1), None);
+
+        // A group the backend couldn't read isn't known
+        let store = EventStore::from_events([Event::Exit {
+            seq: 0,
+            timestamp: 0,
+            pid: 4,
+            ppid: 1,
+            pgid: UNKNOWN_PGID,
+            exit_code: Some(0),
+        }]);
+        assert_eq!(store.pgid_of(4), None);
+    }
+
+    #[test]
+    fn tags_subtrees_with_namespaces() {
+        use crate::builder::TraceBuilder;
+
+        // A sandbox that unshares the network after forking 3, then forks 4 into a
+        // new PID namespace, which forks 5
+        let events = TraceBuilder::new()
+            .fork(0, 1)
+            .fork(1, 2)
+            .fork(2, 3)
+            .namespace(2, NamespaceChange::Unshare, &["mnt", "net"])
+            .fork(2, 4)
+            .namespace(4, NamespaceChange::Clone, &["pid"])
+            .fork(4, 5)
+            .build();
+        let store = EventStore::from_events(events);
+        let namespaces = |pid| store.namespaces_of(pid).into_iter().collect::<Vec<_>>();
+        assert!(namespaces(1).is_empty());
+        assert_eq!(namespaces(2), vec!["mnt", "net"]);
+        assert!(namespaces(3).is_empty());
+        assert_eq!(namespaces(4), vec!["mnt", "net", "pid"]);
+        assert_eq!(namespaces(5), vec!["mnt", "net", "pid"]);
+        assert_eq!(namespace_names(0x2000_0080), vec!["pid", "time"]);
+    }
+
+    #[test]
+    fn filters_processes_by_user() {
+        use crate::builder::TraceBuilder;
+
+        // Root's shell 2 runs 3, and a shared parent 4 forks 5 for another user, who
+        // runs 6, and 7 back as root
+        let events = TraceBuilder::new()
+            .fork_as(0, 1, 0, 0)
+            .fork(1, 2)
+            .fork(2, 3)
+            .fork(1, 4)
+            .fork_as(4, 5, 1000, 100)
+            .fork(5, 6)
+            .fork_as(4, 7, 0, 0)
+            .build();
+        let store = || EventStore::from_events(events.iter().cloned());
+        let pids = |store: &EventStore| {
+            let mut pids = store.pids().into_iter().collect::<Vec<_>>();
+            pids.sort();
+            pids
+        };
+        assert_eq!(
+            store().credentials_of(6),
+            Some(Credentials {
+                uid: 1000,
+                gid: 100
+            })
+        );
+
+        let mut excluded = store();
+        excluded.retain_users(
+            1,
+            &UserFilter {
+                exclude: vec![1000],
+                ..Default::default()
+            },
+        );
+        assert_eq!(pids(&excluded), vec![1, 2, 3, 4, 7]);
+
+        let mut included = store();
+        included.retain_users(
+            1,
+            &UserFilter {
+                include: vec![1000],
+                ..Default::default()
+            },
+        );
+        assert_eq!(pids(&included), vec![1, 4, 5, 6]);
+
+        // The root is kept even if it's one of the excluded users, unlike the rest
+        let mut root_excluded = store();
+        root_excluded.retain_users(
+            1,
+            &UserFilter {
+                exclude: vec![0],
+                ..Default::default()
+            },
+        );
+        assert_eq!(pids(&root_excluded), vec![1]);
+    }
+
+    #[test]
+    fn finds_pipelines() {
+        let mut store = EventStore::new();
+        let mut seq = 0;
+        // The shell is 1 and its pipelines are forked into new process groups
+        let mut process = |store: &mut EventStore, pid: i32, ppid: i32, pgid: i32| {
+            seq += 1;
+            store.add(
+                pid,
+                Event::Fork {
+                    seq,
+                    timestamp: seq,
+                    parent_pid: ppid,
+                    child_pid: pid,
+                    parent_pgid: 1,
+                    credentials: None,
+                    comm: None,
+                },
+            );
+            store.add(
+                pid,
+                Event::SetPGID {
+                    seq: seq + 100,
+                    timestamp: seq + 100,
+                    pid,
+                    ppid,
+                    pgid,
+                    caller_pid: None,
+                },
+            );
+        };
+        // `cat | grep | sort`, then a command on its own, then `ls | wc`
+        process(&mut store, 2, 1, 2);
+        process(&mut store, 3, 1, 2);
+        process(&mut store, 4, 1, 2);
+        process(&mut store, 5, 1, 5);
+        process(&mut store, 6, 1, 6);
+        process(&mut store, 7, 1, 6);
+        // Siblings that stay in the shell's process group aren't a pipeline
+        process(&mut store, 8, 1, 1);
+        process(&mut store, 9, 1, 1);
+
+        assert_eq!(store.pipelines(), vec![vec![2, 3, 4], vec![6, 7]]);
+    }
+
     #[test]
     fn returns_ordered_events() {
         let events = make_simple_events(
@@ -607,7 +2286,7 @@ mod test {
         shuffled.swap(2, 3);
 
         let mut store = EventStore::new();
-        store.add_many(1, shuffled.iter());
+        store.add_many(1, shuffled.iter().cloned());
 
         let stored = store.events_ordered().collect::<Vec<_>>();
 
@@ -630,18 +2309,50 @@ mod test {
             ],
         );
 
-        let mut store = EventStore::new();
-        for event in events.iter() {
-            store.add(event.pid(), event);
-        }
+        let store = EventStore::from_events(events);
 
         let ordered_pids = store
-            .into_pid_buffers_ordered()
+            .pid_buffers_ordered()
             .map(|(pid, _)| pid)
             .collect::<Vec<_>>();
         assert_eq!(ordered_pids, vec![1, 2, 3, 4]);
     }
 
+    #[test]
+    fn repairs_backwards_timestamps() {
+        let fork = |seq: u128, timestamp: u128, child_pid: i32| Event::Fork {
+            seq,
+            timestamp,
+            parent_pid: 1,
+            child_pid,
+            parent_pgid: 1,
+            credentials: None,
+            comm: None,
+        };
+        let exit = |seq: u128, timestamp: u128, pid: i32| Event::Exit {
+            seq,
+            timestamp,
+            pid,
+            ppid: 1,
+            pgid: 1,
+            exit_code: Some(0),
+        };
+        let mut store = EventStore::from_events([
+            fork(0, 100, 1),
+            // Stamped by a CPU whose clock is behind
+            fork(1, 90, 2),
+            exit(2, 95, 2),
+            exit(3, 120, 1),
+        ]);
+        assert_eq!(store.post_process_buffers(), 2);
+        let timestamps = store
+            .iter_ordered()
+            .map(|event| event.timestamp())
+            .collect::<Vec<_>>();
+        assert_eq!(timestamps, vec![100, 100, 100, 120]);
+        assert_eq!(store.post_process_buffers(), 0);
+    }
+
     #[test]
     fn exec_args_when_no_args_provided() {
         let event = Event::Exec {
@@ -651,9 +2362,12 @@ mod test {
             ppid: 0,
             pgid: 1,
             cmdline: Some(ExecArgsKind::Joined("args".to_string())),
+            cgroup: None,
+            ns_pid: None,
+            credentials: None,
         };
-        let events = [&event];
-        let filled_in = fill_in_exec_args(&events);
+        let events = vec![event.clone()];
+        let filled_in = fill_in_exec_args(events);
         assert!(filled_in.is_some());
         let filled_in = filled_in.unwrap();
         let Event::Exec {
@@ -682,6 +2396,9 @@ mod test {
             ppid: 0,
             pgid: 1,
             cmdline: None,
+            cgroup: None,
+            ns_pid: None,
+            credentials: None,
         };
         let args = ExecArgsKind::Joined("args".to_string());
         let exec_args = Event::ExecArgs {
@@ -690,8 +2407,8 @@ mod test {
             pid: 1,
             args: args.clone(),
         };
-        let events = [&exec, &exec_args];
-        let filled_in = fill_in_exec_args(&events);
+        let events = vec![exec.clone(), exec_args.clone()];
+        let filled_in = fill_in_exec_args(events);
         assert!(filled_in.is_some());
         let filled_in = filled_in.unwrap();
         let Event::Exec {
@@ -713,6 +2430,9 @@ mod test {
             ppid: 0,
             pgid: 1,
             cmdline: None,
+            cgroup: None,
+            ns_pid: None,
+            credentials: None,
         };
         let shorter_args = ExecArgsKind::Joined("args".to_string());
         let longer_args = ExecArgsKind::Joined("longer args".to_string());
@@ -728,8 +2448,8 @@ mod test {
             pid: 1,
             args: longer_args.clone(),
         };
-        let events = [&exec, &exec_args1, &exec_args2];
-        let filled_in = fill_in_exec_args(&events);
+        let events = vec![exec.clone(), exec_args1.clone(), exec_args2.clone()];
+        let filled_in = fill_in_exec_args(events);
         assert!(filled_in.is_some());
         let filled_in = filled_in.unwrap();
         let Event::Exec {
@@ -744,7 +2464,7 @@ mod test {
 
     #[test]
     fn exec_args_not_filled_from_bad_number_of_events() {
-        assert!(fill_in_exec_args(&[]).is_none());
+        assert!(fill_in_exec_args(vec![]).is_none());
 
         let exec = Event::Exec {
             seq: 0,
@@ -753,8 +2473,11 @@ mod test {
             ppid: 0,
             pgid: 1,
             cmdline: None,
+            cgroup: None,
+            ns_pid: None,
+            credentials: None,
         };
-        assert!(fill_in_exec_args(&[&exec, &exec]).is_none());
+        assert!(fill_in_exec_args(vec![exec.clone(), exec.clone()]).is_none());
 
         let args = ExecArgsKind::Joined("args".to_string());
         let exec_args = Event::ExecArgs {
@@ -763,7 +2486,13 @@ mod test {
             pid: 1,
             args: args.clone(),
         };
-        assert!(fill_in_exec_args(&[&exec, &exec_args, &exec_args, &exec_args]).is_none());
+        assert!(fill_in_exec_args(vec![
+            exec.clone(),
+            exec_args.clone(),
+            exec_args.clone(),
+            exec_args.clone()
+        ])
+        .is_none());
     }
 
     #[test]
@@ -781,7 +2510,7 @@ mod test {
             buffer.push_back(event.clone());
         }
 
-        let collapsed = collapse_buffer_execs(buffer.iter());
+        let collapsed = collapse_buffer_execs(buffer);
         assert_eq!(collapsed.len(), 3);
         assert!(matches!(collapsed.back().unwrap(), Event::Exec { .. }));
     }
@@ -805,7 +2534,7 @@ mod test {
             buffer.push_back(event.clone());
         }
 
-        let collapsed = collapse_buffer_execs(buffer.iter());
+        let collapsed = collapse_buffer_execs(buffer);
         assert_eq!(collapsed.len(), 4); // events.len() - 2 exec_args
         assert!(matches!(collapsed.back().unwrap(), Event::SetSID { .. }));
     }
@@ -824,12 +2553,9 @@ mod test {
                 ("fork", 6, 1),
             ],
         );
-        let mut store = EventStore::new();
-        for event in events.iter() {
-            store.add(event.pid(), event);
-        }
+        let store = EventStore::from_events(events);
         let ordered = store
-            .buffers_depth_first_fork_order(1)
+            .buffers_depth_first_fork_order(&[1])
             .unwrap()
             .map(|(pid, _)| pid)
             .collect::<Vec<_>>();
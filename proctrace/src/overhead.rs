@@ -0,0 +1,181 @@
+//! Reports how much the recorder perturbed what it recorded: how long the command was
+//! held back while `bpftrace` started, and how much CPU time the recorder's own
+//! processes used while the command ran.
+//!
+//! The time spent in the probes themselves is charged to the recorded processes that
+//! hit them, so it shows up in their timings rather than here.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    analyze::format_nanos,
+    models::{Event, EventStore, Metadata},
+};
+
+/// One of the recorder's own processes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecorderProcess {
+    pub pid: i32,
+    pub command: String,
+    /// The CPU time it used by the end of the recording, in nanoseconds.
+    pub cpu: u128,
+}
+
+/// The overhead of recording a command.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OverheadReport {
+    /// How long the command was held back while the instrumentation started, in
+    /// nanoseconds, if the recording knows.
+    pub startup_delay: Option<u128>,
+    /// How long the recording lasted, from the first event to the last, in
+    /// nanoseconds.
+    pub recording: u128,
+    /// The recorder's own processes, e.g. `proctrace`, `sudo`, and `bpftrace`, in the
+    /// order they were measured.
+    pub processes: Vec<RecorderProcess>,
+}
+
+impl OverheadReport {
+    /// Collects the overhead from the metadata and the [`Event::Overhead`] events of a
+    /// recording, which are only there if it was taken by a version of `proctrace` that
+    /// measured them.
+    pub fn from_store<'a>(
+        metadata: Option<&Metadata>,
+        store: &EventStore,
+        overhead: impl IntoIterator<Item = &'a Event>,
+    ) -> Self {
+        let timestamps = store
+            .iter()
+            .flat_map(|(_, buffer)| buffer.iter())
+            .map(|event| event.timestamp())
+            .collect::<Vec<_>>();
+        let recording = match (timestamps.iter().min(), timestamps.iter().max()) {
+            (Some(first), Some(last)) => last - first,
+            _ => 0,
+        };
+        let processes = overhead
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Overhead {
+                    pid,
+                    command,
+                    usage,
+                    ..
+                } => Some(RecorderProcess {
+                    pid: *pid,
+                    command: command.clone(),
+                    cpu: usage.cpu_ns(),
+                }),
+                _ => None,
+            })
+            .collect();
+        Self {
+            startup_delay: metadata
+                .and_then(|metadata| metadata.instrumentation.as_ref())
+                .map(|instrumentation| instrumentation.startup_delay()),
+            recording,
+            processes,
+        }
+    }
+
+    /// The CPU time used by all of the recorder's processes, in nanoseconds.
+    pub fn cpu(&self) -> u128 {
+        self.processes.iter().map(|process| process.cpu).sum()
+    }
+
+    /// Formats the report as a summary followed by a table of the recorder's processes.
+    pub fn to_table(&self) -> String {
+        let mut table = match self.startup_delay {
+            Some(delay) => format!("startup delay: {}\n", format_nanos(delay)),
+            None => "startup delay: unknown\n".to_string(),
+        };
+        if self.processes.is_empty() {
+            table.push_str("the recording doesn't have the CPU time of the recorder\n");
+            return table;
+        }
+        table.push_str(&format!(
+            "\n{:>8}  {:<16}  {:>12}  {:>9}\n",
+            "pid", "command", "cpu", "of wall"
+        ));
+        let share = |cpu: u128| match self.recording {
+            0 => "-".to_string(),
+            wall => format!("{}%", cpu * 100 / wall),
+        };
+        for process in self.processes.iter() {
+            table.push_str(&format!(
+                "{:>8}  {:<16}  {:>12}  {:>9}\n",
+                process.pid,
+                process.command,
+                format_nanos(process.cpu),
+                share(process.cpu)
+            ));
+        }
+        table.push_str(&format!(
+            "{:>8}  {:<16}  {:>12}  {:>9}\n",
+            "",
+            "total",
+            format_nanos(self.cpu()),
+            share(self.cpu())
+        ));
+        table
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        builder::TraceBuilder,
+        models::{Instrumentation, ResourceUsage},
+        render::read_events,
+        writers::{EventWrite, JsonWriter},
+    };
+
+    use super::*;
+
+    #[test]
+    fn reports_the_recorders_cpu_time() {
+        let ms = 1_000_000;
+        let usage = |cpu_ms: u64| ResourceUsage {
+            user_ns: cpu_ms * 1_000_000 / 2,
+            system_ns: cpu_ms * 1_000_000 / 2,
+            ..Default::default()
+        };
+        let builder = TraceBuilder::new()
+            .fork(0, 1)
+            .exec(1, "make")
+            .advance(200 * ms)
+            .exit(1, 0)
+            .overhead(100, "proctrace", usage(4))
+            .overhead(101, "sudo", usage(2))
+            .overhead(102, "bpftrace", usage(20));
+        let mut metadata = builder.metadata();
+        metadata.instrumentation = Some(Instrumentation {
+            recorder_pid: 100,
+            pids: [(101, "sudo".to_string()), (102, "bpftrace".to_string())].into(),
+            spawned_at: 1_000 * ms,
+            command_spawned_at: 2_000 * ms,
+        });
+        let mut bytes = vec![];
+        let mut writer = JsonWriter::new(&mut bytes);
+        writer.write_metadata(&metadata).unwrap();
+        for event in builder.build().iter() {
+            writer.write_event(event).unwrap();
+        }
+        let ingester = read_events(bytes.as_slice()).unwrap();
+        // The recorder's processes aren't part of the recorded tree
+        assert_eq!(ingester.tracked_events().pids().len(), 1);
+        let report = OverheadReport::from_store(
+            ingester.metadata(),
+            ingester.tracked_events(),
+            ingester.overhead(),
+        );
+        assert_eq!(report.startup_delay, Some(1_000 * ms));
+        assert_eq!(report.recording, 200 * ms);
+        assert_eq!(report.processes.len(), 3);
+        assert_eq!(report.cpu(), 26 * ms);
+        let table = report.to_table();
+        assert!(table.starts_with("startup delay: 1.000s\n"), "{table}");
+        assert!(table.contains("bpftrace"), "{table}");
+        assert!(table.ends_with("26.000ms        13%\n"), "{table}");
+    }
+}
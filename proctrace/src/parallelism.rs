@@ -0,0 +1,480 @@
+//! Reconstructs how many jobs a `make -jN` or `ninja` build was running over time, and
+//! how many processes were alive over time in general.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    analyze::{command_name, format_nanos},
+    models::{Event, EventStore},
+};
+
+/// The executables that schedule jobs.
+const COORDINATORS: &[&str] = &["make", "gmake", "ninja", "samu"];
+
+/// The default number of columns in the concurrency timeline.
+pub const TIMELINE_BUCKETS: usize = 40;
+
+/// A job started by `make` or `ninja`, including all of its descendants.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Job {
+    pub pid: i32,
+    pub command: String,
+    pub start: u128,
+    pub stop: u128,
+}
+
+impl Job {
+    pub fn duration(&self) -> u128 {
+        self.stop.saturating_sub(self.start)
+    }
+}
+
+/// How well a build used the job slots that it had.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParallelismReport {
+    /// The time from the start of the first job to the end of the last job.
+    pub wall: u128,
+    /// The number of jobs that were run.
+    pub jobs: usize,
+    /// The number of job slots, from `--jobs` or the `-j` argument of the build tool.
+    pub slots: Option<usize>,
+    /// The average number of jobs running at once.
+    pub average: f64,
+    /// The most jobs that were running at once.
+    pub peak: usize,
+    /// The time spent with each number of jobs running, in nanoseconds.
+    pub time_at: BTreeMap<usize, u128>,
+    /// The average number of jobs running during each slice of the build.
+    pub timeline: Vec<f64>,
+    /// The longest chain of jobs where each one started right after the previous one
+    /// finished, which is likely to be a chain of dependencies.
+    pub chain: Vec<Job>,
+}
+
+impl ParallelismReport {
+    /// Analyzes the jobs run by every `make`, `ninja`, or `samu` in the recording.
+    ///
+    /// A job is a direct child of one of these, unless it runs another one of them
+    /// (e.g. a recursive `make`) in which case that one's children are the jobs.
+    /// A job is considered to follow another if it starts at most `max_gap`
+    /// nanoseconds after the other one finished.
+    pub fn from_store(
+        store: &EventStore,
+        slots: Option<usize>,
+        max_gap: u128,
+        buckets: usize,
+    ) -> Self {
+        let mut commands = HashMap::new();
+        let mut coordinators = HashSet::new();
+        let mut jobs_flag = None;
+        for (pid, buffer) in store.iter() {
+            let last_exec = buffer.iter().rev().find_map(|event| match event {
+                Event::ExecFull { filename, args, .. } => Some((filename, args.joined())),
+                _ => None,
+            });
+            if let Some((filename, args)) = last_exec {
+                if COORDINATORS.contains(&command_name(filename).as_str()) {
+                    coordinators.insert(pid);
+                    jobs_flag = jobs_flag.max(parse_jobs_flag(&args));
+                }
+                commands.insert(pid, args);
+            }
+        }
+        // Processes between two coordinators are part of the scheduling, not jobs
+        let mut has_coordinator_below = HashSet::new();
+        for pid in coordinators.iter() {
+            for ppid in store.ancestors(*pid) {
+                if !has_coordinator_below.insert(ppid) {
+                    break;
+                }
+            }
+        }
+        let mut jobs = store
+            .iter()
+            .filter(|(pid, _)| !coordinators.contains(pid) && !has_coordinator_below.contains(pid))
+            .filter_map(|(pid, buffer)| {
+                let ppid = store
+                    .parent_of_pid_if_stored(pid)
+                    .filter(|ppid| coordinators.contains(ppid))?;
+                Some(Job {
+                    pid,
+                    command: commands
+                        .get(&pid)
+                        .cloned()
+                        .unwrap_or_else(|| format!("(fork of {ppid})")),
+                    start: buffer.front()?.timestamp(),
+                    stop: buffer.back()?.timestamp(),
+                })
+            })
+            .collect::<Vec<_>>();
+        jobs.sort_by_key(|job| (job.start, job.pid));
+
+        let spans = jobs
+            .iter()
+            .map(|job| (job.start, job.stop))
+            .collect::<Vec<_>>();
+        let Concurrency {
+            wall,
+            average,
+            peak,
+            time_at,
+            timeline,
+        } = Concurrency::from_spans(&spans, buckets);
+
+        Self {
+            wall,
+            jobs: jobs.len(),
+            slots: slots.or(jobs_flag),
+            average,
+            peak,
+            time_at,
+            timeline,
+            chain: longest_chain(&jobs, max_gap),
+        }
+    }
+
+    /// Formats the report as text.
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "{} jobs over {}, {:.2} running on average, peak {}\n",
+            self.jobs,
+            format_nanos(self.wall),
+            self.average,
+            self.peak
+        );
+        let capacity = self.slots.unwrap_or(self.peak).max(1);
+        text.push_str(&format!(
+            "slot utilization: {:.1}% of {capacity} slots{}\n",
+            self.average / capacity as f64 * 100.0,
+            if self.slots.is_none() {
+                " (the peak, since the slot count is unknown)"
+            } else {
+                ""
+            }
+        ));
+
+        let concurrency = Concurrency {
+            wall: self.wall,
+            average: self.average,
+            peak: self.peak,
+            time_at: self.time_at.clone(),
+            timeline: self.timeline.clone(),
+        };
+        text.push_str(&concurrency.to_text("build"));
+
+        let chain_time = self.chain.iter().map(|job| job.duration()).sum::<u128>();
+        text.push_str(&format!(
+            "\nLongest serial chain: {} jobs, {}\n",
+            self.chain.len(),
+            format_nanos(chain_time)
+        ));
+        for job in self.chain.iter() {
+            text.push_str(&format!(
+                "{:>12}  {} (PID {})\n",
+                format_nanos(job.duration()),
+                job.command,
+                job.pid
+            ));
+        }
+        text
+    }
+}
+
+/// How many things were running at once over a stretch of time.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Concurrency {
+    /// The time from the first start to the last stop.
+    pub wall: u128,
+    /// The average number running at once.
+    pub average: f64,
+    /// The most that were running at once.
+    pub peak: usize,
+    /// The time spent with each number running, in nanoseconds.
+    pub time_at: BTreeMap<usize, u128>,
+    /// The average number running during each slice of the time.
+    pub timeline: Vec<f64>,
+}
+
+impl Concurrency {
+    /// Sweeps over the `(start, stop)` spans of the things that were running,
+    /// splitting the timeline into `buckets` slices.
+    pub fn from_spans(spans: &[(u128, u128)], buckets: usize) -> Self {
+        let first = spans.iter().map(|span| span.0).min().unwrap_or(0);
+        let last = spans.iter().map(|span| span.1).max().unwrap_or(0);
+        let wall = last - first;
+
+        // Sweep over the starts and stops, with stops first so that back-to-back
+        // spans don't count as running at the same time
+        let mut changes = spans
+            .iter()
+            .flat_map(|(start, stop)| [(*start, 1i64), (*stop, -1i64)])
+            .collect::<Vec<_>>();
+        changes.sort();
+        let mut time_at = BTreeMap::new();
+        let mut running = 0i64;
+        let mut peak = 0;
+        let mut prev = first;
+        for (ts, change) in changes {
+            if ts > prev && running > 0 {
+                *time_at.entry(running as usize).or_insert(0) += ts - prev;
+            }
+            prev = ts;
+            running += change;
+            peak = peak.max(running as usize);
+        }
+        let busy = spans.iter().map(|(start, stop)| stop - start).sum::<u128>();
+        let average = if wall == 0 {
+            0.0
+        } else {
+            busy as f64 / wall as f64
+        };
+
+        let mut timeline = vec![0.0; buckets];
+        if wall > 0 && buckets > 0 {
+            let width = wall as f64 / buckets as f64;
+            for (start, stop) in spans.iter() {
+                let start = (start - first) as f64;
+                let stop = (stop - first) as f64;
+                let first_bucket = ((start / width) as usize).min(buckets - 1);
+                let last_bucket = ((stop / width) as usize).min(buckets - 1);
+                for (idx, bucket) in timeline
+                    .iter_mut()
+                    .enumerate()
+                    .take(last_bucket + 1)
+                    .skip(first_bucket)
+                {
+                    let lo = start.max(idx as f64 * width);
+                    let hi = stop.min((idx + 1) as f64 * width);
+                    *bucket += (hi - lo).max(0.0) / width;
+                }
+            }
+        }
+
+        Self {
+            wall,
+            average,
+            peak,
+            time_at,
+            timeline,
+        }
+    }
+
+    /// Formats the time at each level and the timeline as text.
+    fn to_text(&self, slice: &str) -> String {
+        let mut text = "\nTime at each concurrency level:\n".to_string();
+        for (running, nanos) in self.time_at.iter() {
+            let share = if self.wall == 0 {
+                0.0
+            } else {
+                *nanos as f64 / self.wall as f64 * 100.0
+            };
+            text.push_str(&format!(
+                "{running:>4}  {:>12}  {share:>5.1}%\n",
+                format_nanos(*nanos)
+            ));
+        }
+
+        text.push_str(&format!(
+            "\nConcurrency over time (each row is a slice of the {slice}):\n"
+        ));
+        let max = (self.peak.max(1)) as f64;
+        for average in self.timeline.iter() {
+            let bar = "#".repeat((average / max * 40.0).round() as usize);
+            text.push_str(&format!("{average:>6.2}  {bar}\n"));
+        }
+        text
+    }
+}
+
+/// How many processes were alive at once over the whole recording.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConcurrencyReport {
+    /// The number of processes in the recording.
+    pub processes: usize,
+    /// The number of cores to compare against.
+    pub cores: Option<usize>,
+    pub concurrency: Concurrency,
+}
+
+impl ConcurrencyReport {
+    /// Sweeps over the lifetime of every process in the recording.
+    ///
+    /// A process is alive from its fork until its exit, or its last event if its
+    /// exit wasn't recorded. Scheduler events aren't recorded, so a process that's
+    /// waiting (e.g. a shell waiting on its child) counts as much as one that's
+    /// running.
+    pub fn from_store(store: &EventStore, cores: Option<usize>, buckets: usize) -> Self {
+        let spans = store
+            .iter()
+            .filter_map(|(_, buffer)| {
+                Some((buffer.front()?.timestamp(), buffer.back()?.timestamp()))
+            })
+            .collect::<Vec<_>>();
+        Self {
+            processes: spans.len(),
+            cores,
+            concurrency: Concurrency::from_spans(&spans, buckets),
+        }
+    }
+
+    /// Formats the report as text.
+    pub fn to_text(&self) -> String {
+        let concurrency = &self.concurrency;
+        let mut text = format!(
+            "{} processes over {}, {:.2} alive on average, peak {}\n",
+            self.processes,
+            format_nanos(concurrency.wall),
+            concurrency.average,
+            concurrency.peak
+        );
+        if let Some(cores) = self.cores.filter(|cores| *cores > 0) {
+            let saturated = concurrency
+                .time_at
+                .range(cores..)
+                .map(|(_, nanos)| nanos)
+                .sum::<u128>();
+            text.push_str(&format!(
+                "core utilization: at most {:.1}% of {cores} cores, all cores busy for {}\n",
+                (concurrency.average / cores as f64 * 100.0).min(100.0),
+                format_nanos(saturated)
+            ));
+        }
+        text.push_str(&concurrency.to_text("recording"));
+        text
+    }
+}
+
+/// Returns the value of a `-jN`, `-j N`, `--jobs=N`, or `--jobs N` argument.
+fn parse_jobs_flag(args: &str) -> Option<usize> {
+    let args = args.split_whitespace().collect::<Vec<_>>();
+    args.iter().enumerate().find_map(|(idx, arg)| {
+        let value = if *arg == "-j" || *arg == "--jobs" {
+            args.get(idx + 1).copied()
+        } else {
+            arg.strip_prefix("--jobs=")
+                .or_else(|| arg.strip_prefix("-j"))
+        };
+        value.and_then(|value| value.parse().ok())
+    })
+}
+
+/// Finds the chain of jobs with the longest total duration where each job started at
+/// most `max_gap` nanoseconds after the previous job finished.
+///
+/// Each job follows the job that finished most recently before it started, which is
+/// usually the job that it was waiting on. `jobs` must be sorted by start time.
+fn longest_chain(jobs: &[Job], max_gap: u128) -> Vec<Job> {
+    let mut by_stop = (0..jobs.len()).collect::<Vec<_>>();
+    by_stop.sort_by_key(|idx| jobs[*idx].stop);
+    let mut length = vec![0u128; jobs.len()];
+    let mut previous = vec![None; jobs.len()];
+    for (idx, job) in jobs.iter().enumerate() {
+        let finished = by_stop.partition_point(|other| jobs[*other].stop <= job.start);
+        // The job that finished last could be this job itself if it's instantaneous
+        let pred = by_stop[..finished]
+            .iter()
+            .rev()
+            .find(|other| **other != idx)
+            .filter(|other| job.start - jobs[**other].stop <= max_gap);
+        previous[idx] = pred.copied();
+        length[idx] = job.duration() + pred.map_or(0, |other| length[*other]);
+    }
+    let Some(mut idx) = (0..jobs.len()).max_by_key(|idx| length[*idx]) else {
+        return vec![];
+    };
+    let mut chain = vec![jobs[idx].clone()];
+    while let Some(pred) = previous[idx] {
+        chain.push(jobs[pred].clone());
+        idx = pred;
+    }
+    chain.reverse();
+    chain
+}
+
+#[cfg(test)]
+mod test {
+    use crate::models::ExecArgsKind;
+
+    use super::*;
+
+    fn process(store: &mut EventStore, pid: i32, ppid: i32, start: u128, stop: u128, args: &str) {
+        let events = [
+            Event::Fork {
+                seq: start,
+                timestamp: start,
+                parent_pid: ppid,
+                child_pid: pid,
+                parent_pgid: ppid,
+                credentials: None,
+                comm: None,
+            },
+            Event::ExecFull {
+                seq: start,
+                timestamp: start,
+                pid,
+                ppid,
+                pgid: pid,
+                filename: args.split_whitespace().next().unwrap().to_string(),
+                args: ExecArgsKind::Joined(args.to_string()),
+                credentials: None,
+            },
+            Event::Exit {
+                seq: stop,
+                timestamp: stop,
+                pid,
+                ppid,
+                pgid: pid,
+                exit_code: Some(0),
+            },
+        ];
+        store.add_many(pid, events);
+    }
+
+    #[test]
+    fn parses_jobs_flag() {
+        assert_eq!(parse_jobs_flag("make -j16 all"), Some(16));
+        assert_eq!(parse_jobs_flag("make -j 4"), Some(4));
+        assert_eq!(parse_jobs_flag("ninja --jobs=8"), Some(8));
+        assert_eq!(parse_jobs_flag("make -j"), None);
+    }
+
+    #[test]
+    fn reconstructs_concurrency() {
+        let mut store = EventStore::new();
+        process(&mut store, 1, 0, 0, 100, "/bin/make -j2");
+        process(&mut store, 2, 1, 0, 40, "/bin/cc a.c");
+        process(&mut store, 3, 1, 0, 20, "/bin/cc b.c");
+        process(&mut store, 4, 1, 41, 100, "/bin/cc main.c");
+        // Part of a job, not a job itself
+        process(&mut store, 5, 4, 50, 60, "/bin/as main.s");
+        let report = ParallelismReport::from_store(&store, None, 5, 4);
+        assert_eq!(report.jobs, 3);
+        assert_eq!(report.slots, Some(2));
+        assert_eq!(report.peak, 2);
+        assert_eq!(report.time_at.get(&2), Some(&20));
+        assert_eq!(report.time_at.get(&1), Some(&79));
+        assert_eq!(report.timeline.len(), 4);
+        let chain = report.chain.iter().map(|job| job.pid).collect::<Vec<_>>();
+        assert_eq!(chain, vec![2, 4]);
+    }
+
+    #[test]
+    fn counts_alive_processes() {
+        let mut store = EventStore::new();
+        process(&mut store, 1, 0, 0, 100, "/bin/make -j2");
+        process(&mut store, 2, 1, 0, 40, "/bin/cc a.c");
+        process(&mut store, 3, 1, 0, 20, "/bin/cc b.c");
+        process(&mut store, 4, 1, 41, 100, "/bin/cc main.c");
+        let report = ConcurrencyReport::from_store(&store, Some(3), 2);
+        assert_eq!(report.processes, 4);
+        let concurrency = &report.concurrency;
+        assert_eq!(concurrency.peak, 3);
+        assert_eq!(concurrency.time_at.get(&3), Some(&20));
+        assert_eq!(concurrency.time_at.get(&2), Some(&79));
+        assert_eq!(concurrency.time_at.get(&1), Some(&1));
+        assert!((concurrency.average - 2.19).abs() < 1e-9);
+        assert!(report.to_text().contains("all cores busy for 0.020us"));
+    }
+}
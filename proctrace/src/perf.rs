@@ -0,0 +1,675 @@
+//! Recording with `perf_event_open`, for when `bpftrace` isn't available.
+//!
+//! A dummy software event is opened on every CPU for the recording process with
+//! `inherit` set, so the kernel reports the forks, execs, and exits of every process
+//! it goes on to start. These are read from the ring buffers in-process and turned into
+//! lines in the same format as the output of `bpftrace`, so they go through the same
+//! parsing and ingestion. This needs no more than the default `perf_event_paranoid`
+//! setting, but exit codes aren't reported and exec'd file names, arguments, and
+//! process groups are read from `/proc`, so they're missing for processes that exit
+//! right away.
+
+use std::{
+    collections::HashMap,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{channel, Receiver},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context};
+use nix::libc;
+
+use crate::poll::{read_exec, read_pgid, ProcExec};
+
+type Error = anyhow::Error;
+
+const PERF_TYPE_SOFTWARE: u32 = 1;
+const PERF_COUNT_SW_DUMMY: u64 = 9;
+const PERF_SAMPLE_TID: u64 = 1 << 1;
+const PERF_SAMPLE_TIME: u64 = 1 << 2;
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+
+// Bits of the flags in `perf_event_attr`
+const ATTR_INHERIT: u64 = 1 << 1;
+const ATTR_EXCLUDE_KERNEL: u64 = 1 << 5;
+const ATTR_EXCLUDE_HV: u64 = 1 << 6;
+const ATTR_COMM: u64 = 1 << 9;
+const ATTR_TASK: u64 = 1 << 13;
+const ATTR_SAMPLE_ID_ALL: u64 = 1 << 18;
+const ATTR_COMM_EXEC: u64 = 1 << 24;
+const ATTR_USE_CLOCKID: u64 = 1 << 25;
+
+const PERF_RECORD_LOST: u32 = 2;
+const PERF_RECORD_COMM: u32 = 3;
+const PERF_RECORD_EXIT: u32 = 4;
+const PERF_RECORD_FORK: u32 = 7;
+const PERF_RECORD_MISC_COMM_EXEC: u16 = 1 << 13;
+
+/// The number of data pages in each ring buffer, which must be a power of two.
+const DATA_PAGES: usize = 8;
+/// The offsets of `data_head` and `data_tail` in `perf_event_mmap_page`.
+const DATA_HEAD_OFFSET: usize = 1024;
+const DATA_TAIL_OFFSET: usize = 1032;
+/// How long to wait for new records before checking whether to stop.
+const POLL_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The version of `perf_event_attr` that added `clockid`, which is every field that's
+/// needed here.
+#[repr(C)]
+#[derive(Debug, Default)]
+struct PerfEventAttr {
+    type_: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+    config2: u64,
+    branch_sample_type: u64,
+    sample_regs_user: u64,
+    sample_stack_user: u32,
+    clockid: i32,
+}
+
+/// A fork, exec, or exit reported by the kernel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskRecord {
+    Fork {
+        pid: i32,
+        ppid: i32,
+        time: u64,
+    },
+    Exec {
+        pid: i32,
+        /// What was exec'd, which is the truncated command name for perf and the
+        /// file name for ftrace. It's only used if `/proc` can't be read.
+        program: String,
+        time: u64,
+    },
+    Exit {
+        pid: i32,
+        ppid: i32,
+        time: u64,
+    },
+    /// The number of records that were dropped because a ring buffer was full.
+    Lost(u64),
+}
+
+impl TaskRecord {
+    /// Parses a record from a ring buffer, including its header.
+    ///
+    /// Records for threads and records of other kinds are skipped.
+    pub fn parse(record: &[u8]) -> Option<Self> {
+        let u32_at = |offset: usize| {
+            Some(u32::from_ne_bytes(
+                record.get(offset..offset + 4)?.try_into().ok()?,
+            ))
+        };
+        let u64_at = |offset: usize| {
+            Some(u64::from_ne_bytes(
+                record.get(offset..offset + 8)?.try_into().ok()?,
+            ))
+        };
+        let kind = u32_at(0)?;
+        let misc = u16::from_ne_bytes(record.get(4..6)?.try_into().ok()?);
+        match kind {
+            PERF_RECORD_FORK | PERF_RECORD_EXIT => {
+                let pid = u32_at(8)? as i32;
+                let ppid = u32_at(12)? as i32;
+                let tid = u32_at(16)? as i32;
+                let time = u64_at(24)?;
+                match kind {
+                    // Threads are created in the same process as their parent
+                    PERF_RECORD_FORK if pid != ppid => Some(TaskRecord::Fork { pid, ppid, time }),
+                    PERF_RECORD_EXIT if pid == tid => Some(TaskRecord::Exit { pid, ppid, time }),
+                    _ => None,
+                }
+            }
+            PERF_RECORD_COMM if misc & PERF_RECORD_MISC_COMM_EXEC != 0 => {
+                let pid = u32_at(8)? as i32;
+                let comm = record.get(16..)?;
+                let len = comm.iter().position(|b| *b == 0)?;
+                let program = String::from_utf8_lossy(&comm[..len]).to_string();
+                // The time is the last field of the trailing sample ID
+                let time = u64_at(record.len().checked_sub(8)?)?;
+                Some(TaskRecord::Exec { pid, program, time })
+            }
+            PERF_RECORD_LOST => Some(TaskRecord::Lost(u64_at(16)?)),
+            _ => None,
+        }
+    }
+
+    pub fn time(&self) -> u64 {
+        match self {
+            TaskRecord::Fork { time, .. }
+            | TaskRecord::Exec { time, .. }
+            | TaskRecord::Exit { time, .. } => *time,
+            TaskRecord::Lost(_) => 0,
+        }
+    }
+}
+
+/// Turns task records into lines of raw output.
+#[derive(Debug, Default)]
+pub struct Converter {
+    seq: u128,
+    start: u64,
+    parents: HashMap<i32, i32>,
+    pgids: HashMap<i32, i32>,
+}
+
+impl Converter {
+    /// Creates a converter whose timestamps are relative to `start`, in nanoseconds of
+    /// `CLOCK_MONOTONIC`.
+    pub fn new(start: u64) -> Self {
+        Self {
+            start,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the lines for a record, using `read_exec` to look up what an exec'd
+    /// process is running and `read_pgid` to look up the process group of a process.
+    ///
+    /// The process group of a process is read when it's forked and when it execs. A
+    /// process that's already gone by then stays in the group it was forked into, and
+    /// the group is left out of the line if even that isn't known.
+    pub fn convert(
+        &mut self,
+        record: &TaskRecord,
+        read_exec: impl Fn(i32) -> Option<ProcExec>,
+        read_pgid: impl Fn(i32) -> Option<i32>,
+    ) -> Vec<String> {
+        let ts = record.time().saturating_sub(self.start);
+        match record {
+            TaskRecord::Fork { pid, ppid, .. } => {
+                self.parents.insert(*pid, *ppid);
+                let parent_pgid = self.pgids.get(ppid).copied().or_else(|| read_pgid(*ppid));
+                if let Some(pgid) = read_pgid(*pid).or(parent_pgid) {
+                    self.pgids.insert(*pid, pgid);
+                }
+                vec![format!(
+                    "FORK: seq={},ts={ts},parent_pid={ppid},child_pid={pid}{}",
+                    self.next_seq(),
+                    pgid_field("parent_pgid", parent_pgid)
+                )]
+            }
+            TaskRecord::Exec { pid, program, .. } => {
+                let exec = read_exec(*pid).unwrap_or_else(|| ProcExec {
+                    filename: program.clone(),
+                    args: vec![program.clone()],
+                });
+                let ppid = self.parents.get(pid).copied().unwrap_or(0);
+                let pgid = read_pgid(*pid).or_else(|| self.pgids.get(pid).copied());
+                if let Some(pgid) = pgid {
+                    self.pgids.insert(*pid, pgid);
+                }
+                vec![
+                    format!(
+                        "EXEC_FILENAME: seq={},ts={ts},pid={pid},filename={}",
+                        self.next_seq(),
+                        exec.filename
+                    ),
+                    format!(
+                        "EXEC_ARGS: seq={},ts={ts},pid={pid},{}",
+                        self.next_seq(),
+                        exec.args.join(" ")
+                    ),
+                    format!(
+                        "EXEC: seq={},ts={ts},pid={pid},ppid={ppid}{}",
+                        self.next_seq(),
+                        pgid_field("pgid", pgid)
+                    ),
+                ]
+            }
+            TaskRecord::Exit { pid, ppid, .. } => {
+                self.parents.remove(pid);
+                let pgid = self.pgids.remove(pid);
+                vec![format!(
+                    "EXIT: seq={},ts={ts},pid={pid},ppid={ppid}{}",
+                    self.next_seq(),
+                    pgid_field("pgid", pgid)
+                )]
+            }
+            TaskRecord::Lost(_) => vec![],
+        }
+    }
+
+    /// Returns a `TICK` line so that host samples can be taken while it's quiet.
+    pub fn tick(&mut self, now: u64) -> String {
+        format!(
+            "TICK: seq={},ts={}",
+            self.next_seq(),
+            now.saturating_sub(self.start)
+        )
+    }
+
+    fn next_seq(&mut self) -> u128 {
+        self.seq += 1;
+        self.seq
+    }
+}
+
+/// Formats a process group as a field of a line, which is left out if it isn't known.
+fn pgid_field(name: &str, pgid: Option<i32>) -> String {
+    pgid.map(|pgid| format!(",{name}={pgid}"))
+        .unwrap_or_default()
+}
+
+/// Reads the clock that perf timestamps are taken from.
+pub(crate) fn monotonic_nanos() -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `ts` is a valid pointer to a `timespec`
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+/// A perf event and its memory mapped ring buffer.
+struct RingBuffer {
+    fd: OwnedFd,
+    base: *mut u8,
+    page_size: usize,
+}
+
+// SAFETY: the mapping is only accessed through `&mut self`
+unsafe impl Send for RingBuffer {}
+
+impl RingBuffer {
+    /// Opens a task event for the calling thread and its future children on `cpu`.
+    fn open(cpu: i32, page_size: usize) -> Result<Self, Error> {
+        let attr = PerfEventAttr {
+            type_: PERF_TYPE_SOFTWARE,
+            size: std::mem::size_of::<PerfEventAttr>() as u32,
+            config: PERF_COUNT_SW_DUMMY,
+            sample_type: PERF_SAMPLE_TID | PERF_SAMPLE_TIME,
+            flags: ATTR_INHERIT
+                | ATTR_EXCLUDE_KERNEL
+                | ATTR_EXCLUDE_HV
+                | ATTR_COMM
+                | ATTR_TASK
+                | ATTR_SAMPLE_ID_ALL
+                | ATTR_COMM_EXEC
+                | ATTR_USE_CLOCKID,
+            clockid: libc::CLOCK_MONOTONIC,
+            ..Default::default()
+        };
+        // SAFETY: `attr` is a valid `perf_event_attr` with its size filled in
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_perf_event_open,
+                &attr as *const PerfEventAttr,
+                0,
+                cpu,
+                -1,
+                PERF_FLAG_FD_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error()).context("perf_event_open failed");
+        }
+        // SAFETY: the file descriptor was just opened and isn't owned by anything else
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+        // SAFETY: mapping a perf event with a metadata page and a power of two data pages
+        let base = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                (1 + DATA_PAGES) * page_size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if base == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error()).context("failed to map perf ring buffer");
+        }
+        Ok(Self {
+            fd,
+            base: base as *mut u8,
+            page_size,
+        })
+    }
+
+    /// Moves every record that's available out of the ring buffer.
+    fn drain(&mut self, records: &mut Vec<Vec<u8>>) {
+        let data_size = DATA_PAGES * self.page_size;
+        // SAFETY: the head and tail are aligned `u64`s in the metadata page, and the
+        // kernel only reads the tail and writes the head
+        let (head, tail_ptr) = unsafe {
+            let head = &*(self.base.add(DATA_HEAD_OFFSET) as *const AtomicU64);
+            let tail = &*(self.base.add(DATA_TAIL_OFFSET) as *const AtomicU64);
+            (head.load(Ordering::Acquire), tail)
+        };
+        let tail = tail_ptr.load(Ordering::Relaxed);
+        // SAFETY: the data pages follow the metadata page
+        let data = unsafe { std::slice::from_raw_parts(self.base.add(self.page_size), data_size) };
+        let tail = read_records(data, head, tail, records);
+        tail_ptr.store(tail, Ordering::Release);
+    }
+}
+
+/// Copies the records between `tail` and `head` out of the data pages of a ring
+/// buffer, returning the new tail.
+///
+/// The head and tail only ever grow, so they're wrapped around the data pages here,
+/// and a record can start at the end of the pages and continue at the start.
+fn read_records(data: &[u8], head: u64, mut tail: u64, records: &mut Vec<Vec<u8>>) -> u64 {
+    let data_size = data.len() as u64;
+    let read = |start: u64, len: u64| {
+        (start..start + len)
+            .map(|idx| data[(idx % data_size) as usize])
+            .collect::<Vec<_>>()
+    };
+    while tail + 8 <= head {
+        let header = read(tail, 8);
+        let size = u16::from_ne_bytes([header[6], header[7]]) as u64;
+        // A record that hasn't been fully written yet is read on the next drain
+        if size == 0 || tail + size > head {
+            break;
+        }
+        records.push(read(tail, size));
+        tail += size;
+    }
+    tail
+}
+
+impl Drop for RingBuffer {
+    fn drop(&mut self) {
+        // SAFETY: this is the mapping created in `open`
+        unsafe {
+            libc::munmap(
+                self.base as *mut libc::c_void,
+                (1 + DATA_PAGES) * self.page_size,
+            );
+        }
+    }
+}
+
+/// Starts collecting task events, sending lines of raw output from a background thread.
+///
+/// This must be called from the thread that will spawn the command being recorded,
+/// since only the calling thread and the processes it starts afterwards are followed.
+/// A line announcing the collector is sent immediately, like `bpftrace` does when it
+/// starts. The thread stops once the receiver is dropped or `shutdown_flag` is set.
+pub fn spawn_collector(shutdown_flag: Arc<AtomicBool>) -> Result<Receiver<String>, Error> {
+    // SAFETY: sysconf has no preconditions
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    // SAFETY: sysconf has no preconditions
+    let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) }.max(1) as i32;
+    let mut buffers = vec![];
+    let mut last_err = None;
+    for cpu in 0..cpus {
+        // Offline CPUs can't be opened, but they won't have any events either
+        match RingBuffer::open(cpu, page_size) {
+            Ok(buffer) => buffers.push(buffer),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    if buffers.is_empty() {
+        return Err(last_err.unwrap_or_else(|| anyhow!("no CPUs found")))
+            .context("failed to open perf events, check /proc/sys/kernel/perf_event_paranoid");
+    }
+
+    let start = monotonic_nanos();
+    let (tx, rx) = channel();
+    std::thread::spawn(move || {
+        if tx
+            .send(format!(
+                "Reading perf task events on {} CPUs",
+                buffers.len()
+            ))
+            .is_err()
+        {
+            return;
+        }
+        let mut converter = Converter::new(start);
+        let mut pollfds = buffers
+            .iter()
+            .map(|buffer| libc::pollfd {
+                fd: buffer.fd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect::<Vec<_>>();
+        while !shutdown_flag.load(Ordering::SeqCst) {
+            // SAFETY: `pollfds` is a valid array of `pollfd`s of the given length
+            unsafe {
+                libc::poll(
+                    pollfds.as_mut_ptr(),
+                    pollfds.len() as libc::nfds_t,
+                    POLL_TIMEOUT.as_millis() as i32,
+                )
+            };
+            let mut raw = vec![];
+            for buffer in buffers.iter_mut() {
+                buffer.drain(&mut raw);
+            }
+            let mut records = raw
+                .iter()
+                .filter_map(|record| TaskRecord::parse(record))
+                .collect::<Vec<_>>();
+            // Each CPU has its own buffer, so put the records back in order
+            records.sort_by_key(|record| record.time());
+            let mut lines = vec![];
+            for record in records.iter() {
+                if let TaskRecord::Lost(count) = record {
+                    eprintln!("perf ring buffer overflowed, {count} events were lost");
+                }
+                lines.extend(converter.convert(record, read_exec, read_pgid));
+            }
+            lines.push(converter.tick(monotonic_nanos()));
+            if lines.into_iter().any(|line| tx.send(line).is_err()) {
+                return;
+            }
+        }
+    });
+    Ok(rx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(kind: u32, misc: u16, body: &[u8]) -> Vec<u8> {
+        let mut record = kind.to_ne_bytes().to_vec();
+        record.extend(misc.to_ne_bytes());
+        record.extend(((body.len() + 8) as u16).to_ne_bytes());
+        record.extend(body);
+        record
+    }
+
+    fn task_body(pid: u32, ppid: u32, tid: u32, time: u64) -> Vec<u8> {
+        [pid, ppid, tid, tid]
+            .iter()
+            .flat_map(|n| n.to_ne_bytes())
+            .chain(time.to_ne_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn parses_task_records() {
+        let fork = record(PERF_RECORD_FORK, 0, &task_body(20, 10, 20, 500));
+        assert_eq!(
+            TaskRecord::parse(&fork),
+            Some(TaskRecord::Fork {
+                pid: 20,
+                ppid: 10,
+                time: 500
+            })
+        );
+        // A new thread is in the same process as its parent
+        let thread = record(PERF_RECORD_FORK, 0, &task_body(20, 20, 21, 600));
+        assert_eq!(TaskRecord::parse(&thread), None);
+        let thread_exit = record(PERF_RECORD_EXIT, 0, &task_body(20, 20, 21, 700));
+        assert_eq!(TaskRecord::parse(&thread_exit), None);
+
+        let mut comm = [20u32, 20u32]
+            .iter()
+            .flat_map(|n| n.to_ne_bytes())
+            .collect::<Vec<_>>();
+        comm.extend(b"make\0\0\0\0");
+        comm.extend(task_body(20, 20, 0, 800)[..8].iter());
+        comm.extend(800u64.to_ne_bytes());
+        let exec = record(PERF_RECORD_COMM, PERF_RECORD_MISC_COMM_EXEC, &comm);
+        let exec = TaskRecord::parse(&exec).unwrap();
+        assert_eq!(
+            exec,
+            TaskRecord::Exec {
+                pid: 20,
+                program: "make".to_string(),
+                time: 800
+            }
+        );
+        // Renaming a thread isn't an exec
+        assert_eq!(TaskRecord::parse(&record(PERF_RECORD_COMM, 0, &comm)), None);
+
+        let mut converter = Converter::new(400);
+        let lines = [TaskRecord::parse(&fork).unwrap(), exec]
+            .iter()
+            .flat_map(|record| converter.convert(record, |_| None, |pid| Some(pid / 2)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                "FORK: seq=1,ts=100,parent_pid=10,child_pid=20,parent_pgid=5",
+                "EXEC_FILENAME: seq=2,ts=400,pid=20,filename=make",
+                "EXEC_ARGS: seq=3,ts=400,pid=20,make",
+                "EXEC: seq=4,ts=400,pid=20,ppid=10,pgid=10",
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_records_that_wrap_around_the_buffer() {
+        let first = record(PERF_RECORD_FORK, 0, &task_body(20, 10, 20, 500));
+        let second = record(PERF_RECORD_EXIT, 0, &task_body(20, 10, 20, 900));
+        assert_eq!(first.len(), 32);
+        // The buffer has already been filled and read twice, and the second record
+        // starts 16 bytes before the end of it
+        let mut data = vec![0; 64];
+        let tail = 2 * 64 + 64 - 16 - 32;
+        for (offset, byte) in first.iter().chain(second.iter()).enumerate() {
+            data[(tail + offset) % 64] = *byte;
+        }
+        let head = (tail + 64) as u64;
+
+        let mut records = vec![];
+        let new_tail = read_records(&data, head, tail as u64, &mut records);
+        assert_eq!(new_tail, head);
+        assert_eq!(records, vec![first, second]);
+        let parsed = records
+            .iter()
+            .filter_map(|record| TaskRecord::parse(record))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            parsed,
+            vec![
+                TaskRecord::Fork {
+                    pid: 20,
+                    ppid: 10,
+                    time: 500
+                },
+                TaskRecord::Exit {
+                    pid: 20,
+                    ppid: 10,
+                    time: 900
+                },
+            ]
+        );
+
+        // Nothing is read once the tail has caught up with the head
+        records.clear();
+        assert_eq!(read_records(&data, head, head, &mut records), head);
+        assert!(records.is_empty());
+        // A record that's only partly written is left for later
+        assert_eq!(
+            read_records(&data, head - 8, tail as u64 + 32, &mut records),
+            tail as u64 + 32
+        );
+        assert!(records.is_empty());
+    }
+
+    #[test]
+    fn converts_times_relative_to_the_start() {
+        let mut converter = Converter::new(1_000);
+        let lines = [
+            // Reported before the converter was created, e.g. by another CPU
+            TaskRecord::Fork {
+                pid: 20,
+                ppid: 10,
+                time: 900,
+            },
+            TaskRecord::Lost(3),
+            TaskRecord::Exit {
+                pid: 20,
+                ppid: 10,
+                time: 1_500,
+            },
+        ]
+        .iter()
+        .flat_map(|record| converter.convert(record, |_| None, |_| Some(10)))
+        .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                "FORK: seq=1,ts=0,parent_pid=10,child_pid=20,parent_pgid=10",
+                "EXIT: seq=2,ts=500,pid=20,ppid=10,pgid=10",
+            ]
+        );
+        assert_eq!(converter.tick(2_000), "TICK: seq=3,ts=1000");
+    }
+
+    #[test]
+    fn inherits_or_leaves_out_unknown_process_groups() {
+        let mut converter = Converter::new(0);
+        let records = [
+            TaskRecord::Fork {
+                pid: 20,
+                ppid: 10,
+                time: 100,
+            },
+            // Gone before its group could be read, so it's in the group of its parent
+            TaskRecord::Fork {
+                pid: 30,
+                ppid: 20,
+                time: 200,
+            },
+            TaskRecord::Exit {
+                pid: 30,
+                ppid: 20,
+                time: 300,
+            },
+            // Its parent was never seen either
+            TaskRecord::Fork {
+                pid: 50,
+                ppid: 40,
+                time: 400,
+            },
+        ];
+        let lines = records
+            .iter()
+            .flat_map(|record| converter.convert(record, |_| None, |pid| (pid == 20).then_some(7)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                "FORK: seq=1,ts=100,parent_pid=10,child_pid=20",
+                "FORK: seq=2,ts=200,parent_pid=20,child_pid=30,parent_pgid=7",
+                "EXIT: seq=3,ts=300,pid=30,ppid=20,pgid=7",
+                "FORK: seq=4,ts=400,parent_pid=40,child_pid=50",
+            ]
+        );
+        assert!(!converter.pgids.contains_key(&30));
+    }
+}
@@ -0,0 +1,167 @@
+//! Renders recordings with external programs, so that a renderer can be written in any
+//! language without changing `proctrace`.
+//!
+//! `render --display-mode exec:<PROGRAM>` runs PROGRAM and writes the recording to its
+//! stdin the same way as a processed recording in the "json" format: a line with the
+//! metadata of the recording, if it had any, followed by a line of JSON for each event
+//! in the order they happened. Exec events have already been merged and labeled, just
+//! like for the built-in display modes. Whatever PROGRAM writes to stdout is the output
+//! of `render`, and the render fails if PROGRAM exits with a non-zero status.
+
+use std::{
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    process::{ChildStdin, Command, Stdio},
+};
+
+use anyhow::{anyhow, Context};
+
+use crate::{
+    render::{write_ordered, ProcessTree, Renderer},
+    writers::{EventWrite, JsonWriter},
+};
+
+type Error = anyhow::Error;
+
+/// Renders a recording with an external program.
+#[derive(Debug, Clone)]
+pub struct ExecRenderer {
+    pub program: PathBuf,
+}
+
+impl ExecRenderer {
+    pub fn new(program: impl AsRef<Path>) -> Self {
+        Self {
+            program: program.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl Renderer for ExecRenderer {
+    fn render(&self, tree: &ProcessTree, writer: &mut dyn Write) -> Result<(), Error> {
+        let program = self.program.display();
+        let mut child = Command::new(&self.program)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("failed to run renderer {program}"))?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        // The events are written from another thread so that neither side can block
+        // the other by filling up a pipe
+        let (written, copied) = std::thread::scope(|scope| {
+            let writing = scope.spawn(move || write_recording(tree, stdin));
+            let copied = std::io::copy(&mut stdout, writer);
+            let written = writing.join().expect("thread writing to renderer panicked");
+            (written, copied)
+        });
+        let status = child
+            .wait()
+            .with_context(|| format!("failed to wait for renderer {program}"))?;
+        if !status.success() {
+            return Err(anyhow!("renderer {program} failed: {status}"));
+        }
+        copied.with_context(|| format!("failed to write the output of renderer {program}"))?;
+        // A renderer that doesn't need the whole recording may exit without reading it
+        match written {
+            Err(err) if !is_broken_pipe(&err) => {
+                Err(err.context(format!("failed to write events to renderer {program}")))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Writes the metadata and events of a recording to the stdin of a renderer.
+fn write_recording(tree: &ProcessTree, stdin: ChildStdin) -> Result<(), Error> {
+    let mut writer = JsonWriter::new(BufWriter::new(stdin));
+    if let Some(ref metadata) = tree.metadata {
+        writer.write_metadata(metadata)?;
+    }
+    write_ordered(&tree.store, &tree.host_samples, &tree.fork_storms, writer)
+}
+
+/// Returns `true` if the error was caused by the other end of a pipe being closed.
+fn is_broken_pipe(err: &Error) -> bool {
+    err.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|err| err.kind() == std::io::ErrorKind::BrokenPipe)
+    })
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use crate::{
+        cli::DisplayMode,
+        ingest::test::make_simple_events,
+        labels::Labels,
+        models::Metadata,
+        render::{read_events, render_events, RenderOptions},
+    };
+
+    use super::*;
+
+    fn recording() -> Vec<u8> {
+        let events = make_simple_events(
+            0,
+            0,
+            &[
+                ("fork", 1, 0),
+                ("exec_full", 1, 0),
+                ("fork", 2, 1),
+                ("exit", 2, 1),
+                ("exit", 1, 0),
+            ],
+        );
+        let mut bytes = vec![];
+        let mut writer = JsonWriter::new(&mut bytes);
+        writer.write_metadata(&Metadata::new(Some(1))).unwrap();
+        for event in events {
+            writer.write_event(&event).unwrap();
+        }
+        bytes
+    }
+
+    fn tree() -> ProcessTree {
+        let ingester = read_events(recording().as_slice()).unwrap();
+        ProcessTree::prepare(ingester, &Labels::default()).unwrap()
+    }
+
+    #[test]
+    fn forwards_output_of_renderer() {
+        let mut output = vec![];
+        ExecRenderer::new("cat")
+            .render(&tree(), &mut output)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let mut lines = output.lines();
+        assert!(lines.next().unwrap().starts_with(r#"{"Metadata":"#));
+        assert_eq!(lines.count(), 5);
+
+        let mut output = vec![];
+        render_events(
+            read_events(recording().as_slice()).unwrap(),
+            &mut output,
+            DisplayMode::Exec("wc".into()),
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        assert!(!output.is_empty());
+    }
+
+    #[test]
+    fn fails_with_renderer() {
+        // Exiting without reading anything is fine
+        ExecRenderer::new("true")
+            .render(&tree(), &mut vec![])
+            .unwrap();
+        let err = ExecRenderer::new("false")
+            .render(&tree(), &mut vec![])
+            .unwrap_err();
+        assert!(err.to_string().contains("renderer false failed"), "{err}");
+        assert!(ExecRenderer::new("/does/not/exist")
+            .render(&tree(), &mut vec![])
+            .is_err());
+    }
+}
@@ -0,0 +1,441 @@
+//! Recording by polling `/proc`, for when `bpftrace` isn't available.
+//!
+//! Each poll is compared with the previous one, and the differences are turned into
+//! lines in the same format as the output of `bpftrace` so that they go through the
+//! same parsing and ingestion (and can be stored in raw recordings). Processes that
+//! start and exit between two polls are never seen, and exit codes aren't known.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{
+        atomic::{AtomicBool, AtomicI32, Ordering},
+        mpsc::{channel, Receiver},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// The fields of `/proc/<pid>/stat` that matter for recording.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcStat {
+    pub ppid: i32,
+    pub pgid: i32,
+    pub sid: i32,
+    /// When the process started, which tells a reused PID apart from the original.
+    pub starttime: u64,
+}
+
+/// The program that a process is running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcExec {
+    pub filename: String,
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Tracked {
+    stat: ProcStat,
+    exec: Option<ProcExec>,
+}
+
+/// Turns successive snapshots of `/proc` into lines of raw output.
+#[derive(Debug, Default)]
+pub struct Poller {
+    seq: u128,
+    polled: bool,
+    tracked: BTreeMap<i32, Tracked>,
+}
+
+impl Poller {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the lines describing how the process tree under `root_pid` changed since
+    /// the last snapshot.
+    ///
+    /// `procs` contains every live process, and `read_exec` looks up what a process is
+    /// running, which is only done for processes in the process tree.
+    pub fn diff(
+        &mut self,
+        root_pid: i32,
+        procs: &BTreeMap<i32, ProcStat>,
+        read_exec: impl Fn(i32) -> Option<ProcExec>,
+        ts: u128,
+    ) -> Vec<String> {
+        self.polled = true;
+        let mut lines = vec![];
+        let still_running = |pid: &i32, tracked: &Tracked| {
+            procs
+                .get(pid)
+                .is_some_and(|stat| stat.starttime == tracked.stat.starttime)
+        };
+
+        // Existing processes stay in the tree even if they're reparented
+        let mut in_tree = self
+            .tracked
+            .iter()
+            .filter(|(pid, tracked)| still_running(pid, tracked))
+            .map(|(pid, _)| *pid)
+            .collect::<BTreeSet<_>>();
+        // Processes that exited go first, since their PIDs may have been reused already
+        let exited = self
+            .tracked
+            .iter()
+            .filter(|(pid, tracked)| !still_running(pid, tracked))
+            .map(|(pid, _)| *pid)
+            .collect::<Vec<_>>();
+        for pid in exited {
+            let tracked = self.tracked.remove(&pid).unwrap();
+            lines.push(format!(
+                "EXIT: seq={},ts={ts},pid={pid},ppid={},pgid={}",
+                self.next_seq(),
+                tracked.stat.ppid,
+                tracked.stat.pgid
+            ));
+        }
+        let mut new_pids = vec![];
+        if procs.contains_key(&root_pid) && in_tree.insert(root_pid) {
+            new_pids.push(root_pid);
+        }
+        let mut children: BTreeMap<i32, Vec<i32>> = BTreeMap::new();
+        for (pid, stat) in procs.iter() {
+            children.entry(stat.ppid).or_default().push(*pid);
+        }
+        // Visit parents before their children so that forks are in a sensible order
+        let mut worklist = in_tree.iter().copied().collect::<Vec<_>>();
+        while let Some(pid) = worklist.pop() {
+            for child in children.get(&pid).into_iter().flatten() {
+                if in_tree.insert(*child) {
+                    new_pids.push(*child);
+                    worklist.push(*child);
+                }
+            }
+        }
+
+        for pid in new_pids {
+            let stat = procs[&pid];
+            let exec = read_exec(pid);
+            let parent = self.tracked.get(&stat.ppid);
+            let parent_pgid = parent.map(|parent| parent.stat.pgid).unwrap_or(stat.pgid);
+            let new_session = parent.is_some_and(|parent| parent.stat.sid != stat.sid);
+            // A child that's running something else than its parent must have exec'd
+            let exec_changed =
+                pid == root_pid || exec.as_ref() != parent.and_then(|parent| parent.exec.as_ref());
+            lines.push(format!(
+                "FORK: seq={},ts={ts},parent_pid={},child_pid={pid},parent_pgid={parent_pgid}",
+                self.next_seq(),
+                stat.ppid,
+            ));
+            if let Some(exec) = exec.as_ref().filter(|_| exec_changed) {
+                self.push_exec(&mut lines, pid, &stat, exec, ts);
+            }
+            // A child that started its own session before it was first seen
+            if new_session {
+                lines.push(format!(
+                    "SETSID: seq={},ts={ts},pid={pid},ppid={},pgid={},sid={}",
+                    self.next_seq(),
+                    stat.ppid,
+                    stat.pgid,
+                    stat.sid
+                ));
+            }
+            self.tracked.insert(pid, Tracked { stat, exec });
+        }
+
+        let pids = self.tracked.keys().copied().collect::<Vec<_>>();
+        for pid in pids {
+            let tracked = self.tracked[&pid].clone();
+            let stat = procs[&pid];
+            if stat.sid != tracked.stat.sid {
+                lines.push(format!(
+                    "SETSID: seq={},ts={ts},pid={pid},ppid={},pgid={},sid={}",
+                    self.next_seq(),
+                    stat.ppid,
+                    stat.pgid,
+                    stat.sid
+                ));
+            } else if stat.pgid != tracked.stat.pgid {
+                lines.push(format!(
+                    "SETPGID: seq={},ts={ts},pid={pid},ppid={},pgid={}",
+                    self.next_seq(),
+                    stat.ppid,
+                    stat.pgid
+                ));
+            }
+            let mut exec = tracked.exec;
+            if let Some(current) = read_exec(pid).filter(|current| Some(current) != exec.as_ref()) {
+                self.push_exec(&mut lines, pid, &stat, &current, ts);
+                exec = Some(current);
+            }
+            self.tracked.insert(pid, Tracked { stat, exec });
+        }
+        lines
+    }
+
+    /// Returns a `TICK` line so that host samples can be taken between polls.
+    pub fn tick(&mut self, ts: u128) -> String {
+        format!("TICK: seq={},ts={ts}", self.next_seq())
+    }
+
+    /// Returns `true` once a snapshot has been taken and every process in the tree has
+    /// exited, or the root process had already exited when the first one was taken.
+    pub fn is_finished(&self) -> bool {
+        self.polled && self.tracked.is_empty()
+    }
+
+    fn push_exec(
+        &mut self,
+        lines: &mut Vec<String>,
+        pid: i32,
+        stat: &ProcStat,
+        exec: &ProcExec,
+        ts: u128,
+    ) {
+        lines.push(format!(
+            "EXEC_FILENAME: seq={},ts={ts},pid={pid},filename={}",
+            self.next_seq(),
+            exec.filename
+        ));
+        lines.push(format!(
+            "EXEC_ARGS: seq={},ts={ts},pid={pid},{}",
+            self.next_seq(),
+            exec.args.join(" ")
+        ));
+        lines.push(format!(
+            "EXEC: seq={},ts={ts},pid={pid},ppid={},pgid={}",
+            self.next_seq(),
+            stat.ppid,
+            stat.pgid
+        ));
+    }
+
+    fn next_seq(&mut self) -> u128 {
+        self.seq += 1;
+        self.seq
+    }
+}
+
+/// Reads the stat of every live process, skipping zombies since they've already exited.
+pub fn read_procs() -> BTreeMap<i32, ProcStat> {
+    let Ok(procs) = procfs::process::all_processes() else {
+        return BTreeMap::new();
+    };
+    procs
+        .filter_map(|proc| proc.ok()?.stat().ok())
+        .filter(|stat| stat.state != 'Z')
+        .map(|stat| {
+            let proc_stat = ProcStat {
+                ppid: stat.ppid,
+                pgid: stat.pgrp,
+                sid: stat.session,
+                starttime: stat.starttime,
+            };
+            (stat.pid, proc_stat)
+        })
+        .collect()
+}
+
+/// Reads what a process is running from `/proc/<pid>/exe` and `/proc/<pid>/cmdline`.
+///
+/// The executable can only be read for processes owned by the same user, so the first
+/// argument is used for the others.
+pub fn read_exec(pid: i32) -> Option<ProcExec> {
+    let proc = procfs::process::Process::new(pid).ok()?;
+    let args = proc.cmdline().ok()?;
+    let filename = match proc.exe() {
+        Ok(path) => path.display().to_string(),
+        Err(_) => args.first()?.clone(),
+    };
+    Some(ProcExec { filename, args })
+}
+
+/// Reads the process group of a process from `/proc/<pid>/stat`.
+pub fn read_pgid(pid: i32) -> Option<i32> {
+    let stat = procfs::process::Process::new(pid).ok()?.stat().ok()?;
+    Some(stat.pgrp)
+}
+
+/// Polls `/proc` every `interval` on a background thread, sending lines of raw output.
+///
+/// A line announcing the poller is sent immediately, like `bpftrace` does when it
+/// starts, and polling begins once `root_pid` is set to something other than zero.
+/// The thread stops once the process tree has exited, the receiver is dropped, or
+/// `shutdown_flag` is set.
+pub fn spawn_poller(
+    root_pid: Arc<AtomicI32>,
+    interval: Duration,
+    shutdown_flag: Arc<AtomicBool>,
+) -> Receiver<String> {
+    let (tx, rx) = channel();
+    std::thread::spawn(move || {
+        if tx
+            .send(format!("Polling /proc every {}ms", interval.as_millis()))
+            .is_err()
+        {
+            return;
+        }
+        let start = Instant::now();
+        let mut poller = Poller::new();
+        while !shutdown_flag.load(Ordering::SeqCst) {
+            let pid = root_pid.load(Ordering::SeqCst);
+            if pid != 0 {
+                let ts = start.elapsed().as_nanos();
+                let mut lines = poller.diff(pid, &read_procs(), read_exec, ts);
+                lines.push(poller.tick(ts));
+                if lines.into_iter().any(|line| tx.send(line).is_err()) {
+                    return;
+                }
+                if poller.is_finished() {
+                    return;
+                }
+            }
+            std::thread::sleep(interval);
+        }
+    });
+    rx
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        ingest::{EventIngester, EventParser},
+        models::Event,
+        writers::test::MockWriter,
+    };
+
+    use super::*;
+
+    fn stat(ppid: i32, pgid: i32, starttime: u64) -> ProcStat {
+        ProcStat {
+            ppid,
+            pgid,
+            sid: 1,
+            starttime,
+        }
+    }
+
+    fn exec(args: &[&str]) -> Option<ProcExec> {
+        Some(ProcExec {
+            filename: format!("/bin/{}", args[0]),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        })
+    }
+
+    #[test]
+    fn diffs_snapshots() {
+        let mut poller = Poller::new();
+        let read_exec = |pid| match pid {
+            10 => exec(&["sh", "-c", "make"]),
+            11 => exec(&["make"]),
+            12 => exec(&["sh", "-c", "make"]),
+            _ => None,
+        };
+        let snapshots = [
+            BTreeMap::from([(1, stat(0, 1, 0)), (10, stat(1, 1, 5))]),
+            BTreeMap::from([
+                (1, stat(0, 1, 0)),
+                (10, stat(1, 1, 5)),
+                (11, stat(10, 1, 6)),
+                (12, stat(10, 12, 7)),
+            ]),
+            BTreeMap::from([
+                (1, stat(0, 1, 0)),
+                (10, stat(1, 1, 5)),
+                (12, stat(1, 12, 7)),
+            ]),
+            BTreeMap::from([(1, stat(0, 1, 0)), (12, stat(1, 12, 99))]),
+        ];
+        let mut lines = vec![];
+        for (i, procs) in snapshots.iter().enumerate() {
+            lines.extend(poller.diff(10, procs, read_exec, i as u128));
+        }
+        assert!(poller.is_finished());
+
+        let parser = EventParser::new();
+        let mut ingester = EventIngester::new(Some(10), Some(MockWriter::new()));
+        for line in lines.iter() {
+            ingester
+                .observe_event(parser.parse_line(line).unwrap())
+                .unwrap();
+        }
+        ingester.post_process_buffers();
+        let mut store = ingester.into_tracked_events();
+        // The child that exec'd `make` gets an exec, the one that didn't is a plain fork
+        let make = store.remove(11).unwrap();
+        assert!(matches!(&make[1], Event::ExecFull { filename, .. } if filename == "/bin/make"));
+        let sh = store.remove(12).unwrap();
+        assert_eq!(sh.len(), 2);
+        assert!(sh[1].is_exit());
+        // A reused PID is an exit of the original process
+        assert!(store.remove(10).unwrap().back().unwrap().is_exit());
+    }
+
+    #[test]
+    fn exits_processes_whose_pid_is_reused() {
+        let mut poller = Poller::new();
+        let first = BTreeMap::from([(10, stat(1, 10, 5)), (11, stat(10, 10, 6))]);
+        let read_make = |pid| match pid {
+            10 => exec(&["make"]),
+            _ => exec(&["cc", "-c", "a.c"]),
+        };
+        poller.diff(10, &first, read_make, 0);
+        // Between polls the child exited and another one started with the same PID
+        let second = BTreeMap::from([(10, stat(1, 10, 5)), (11, stat(10, 10, 50))]);
+        let read_make = |pid| match pid {
+            10 => exec(&["make"]),
+            _ => exec(&["cc", "-c", "b.c"]),
+        };
+        let lines = poller.diff(10, &second, read_make, 1);
+        assert_eq!(
+            lines,
+            vec![
+                "EXIT: seq=9,ts=1,pid=11,ppid=10,pgid=10",
+                "FORK: seq=10,ts=1,parent_pid=10,child_pid=11,parent_pgid=10",
+                "EXEC_FILENAME: seq=11,ts=1,pid=11,filename=/bin/cc",
+                "EXEC_ARGS: seq=12,ts=1,pid=11,cc -c b.c",
+                "EXEC: seq=13,ts=1,pid=11,ppid=10,pgid=10",
+            ]
+        );
+    }
+
+    #[test]
+    fn follows_processes_that_cant_be_read() {
+        let mut poller = Poller::new();
+        let procs = BTreeMap::from([
+            (10, stat(1, 10, 5)),
+            (11, stat(10, 10, 6)),
+            (12, stat(11, 10, 7)),
+        ]);
+        // The command line of another user's process can't be read
+        let read_exec = |pid| match pid {
+            10 => exec(&["sudo", "make"]),
+            11 => None,
+            _ => exec(&["make"]),
+        };
+        let lines = poller.diff(10, &procs, read_exec, 0);
+        let kinds = lines
+            .iter()
+            .map(|line| line.split_once(": seq=").unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                "FORK",
+                "EXEC_FILENAME",
+                "EXEC_ARGS",
+                "EXEC",
+                "FORK",
+                "FORK",
+                "EXEC_FILENAME",
+                "EXEC_ARGS",
+                "EXEC"
+            ]
+        );
+        assert!(lines[5].contains("parent_pid=11,child_pid=12"));
+        // It still can't be read, which isn't a change
+        assert!(poller.diff(10, &procs, read_exec, 1).is_empty());
+        // Nothing can be read about a process that's already gone
+        assert_eq!(super::read_exec(i32::MAX), None);
+    }
+}
@@ -0,0 +1,138 @@
+//! Settings for recording well-known kinds of commands.
+
+use regex_lite::Regex;
+
+use crate::{analyze::Phase, cli::Preset};
+
+/// The settings that a preset applies to a recording.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PresetSettings {
+    /// Processes that exec something matching one of these patterns are adopted into
+    /// the recording, along with their descendants.
+    pub adopt_exec: Vec<&'static str>,
+    /// Whether to shorten Nix store paths in exec filenames and arguments.
+    pub rewrite_store_paths: bool,
+    /// The names and patterns of the phases that the report for this preset breaks
+    /// the recording into.
+    pub phases: Vec<(&'static str, &'static str)>,
+    /// Whether to report how long was spent building each crate.
+    pub crate_report: bool,
+}
+
+impl PresetSettings {
+    /// Returns the phases of the report for this preset, or `None` if it doesn't
+    /// have a report.
+    pub fn phases(&self) -> Option<Vec<Phase>> {
+        if self.phases.is_empty() {
+            return None;
+        }
+        let phases = self
+            .phases
+            .iter()
+            .map(|(name, pattern)| Phase::new(name, pattern).expect("preset patterns are valid"))
+            .collect();
+        Some(phases)
+    }
+}
+
+/// Matches the builder script that `nix-daemon` runs for a derivation, e.g.
+/// `bash -e /nix/store/<hash>-default-builder.sh`.
+///
+/// The daemon forks builders itself, so they aren't descendants of `nix build`.
+const NIX_BUILDER: &str = r"/nix/store/[0-9a-z]{32}-[^/ ]*builder\.sh";
+
+/// The phases of activating a Flox environment or another kind of dev shell, in the
+/// order they're checked. A process is tagged with the first phase that matches.
+const ACTIVATION_PHASES: &[(&str, &str)] = &[
+    (
+        "env build",
+        r"\b(pkgdb|buildenv|nix-build|nix build|nix-store --realise)\b",
+    ),
+    (
+        "env eval",
+        r"\b(direnv|nix-shell|nix develop|nix print-dev-env)\b",
+    ),
+    ("hooks", r"hook-on-activate|/hooks?/|hook\.(ba|z)?sh\b"),
+    (
+        "shell rc",
+        r"--rcfile|\.bashrc|\.bash_profile|\.zshrc|\.zshenv|\.profile|config\.fish",
+    ),
+    ("activate", r"/activate(\.d/|\s|$)|flox activate"),
+];
+
+impl Preset {
+    /// Returns the settings that this preset applies.
+    pub fn settings(&self) -> PresetSettings {
+        match self {
+            Preset::NixBuild => PresetSettings {
+                adopt_exec: vec![NIX_BUILDER],
+                rewrite_store_paths: true,
+                ..Default::default()
+            },
+            Preset::Activation => PresetSettings {
+                rewrite_store_paths: true,
+                phases: ACTIVATION_PHASES.to_vec(),
+                ..Default::default()
+            },
+            Preset::Cargo => PresetSettings {
+                crate_report: true,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Replaces the hash in Nix store paths with `…` so that the paths stay readable,
+/// e.g. `/nix/store/<hash>-hello-2.12` becomes `/nix/store/…-hello-2.12`.
+pub fn rewrite_store_paths(s: &str) -> String {
+    // Compiling this on every call is fine, it's only applied once per exec
+    let re = Regex::new(r"/nix/store/[0-9a-z]{32}-").unwrap();
+    re.replace_all(s, "/nix/store/…-").into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use clap::ValueEnum;
+
+    use super::*;
+
+    #[test]
+    fn rewrites_store_paths() {
+        let hash = "0c4ivnbxgqnwd6c9hmvbmmkl0yhlrhp8";
+        let cmd =
+            format!("/nix/store/{hash}-bash-5.2/bin/bash -e /nix/store/{hash}-default-builder.sh");
+        assert_eq!(
+            rewrite_store_paths(&cmd),
+            "/nix/store/…-bash-5.2/bin/bash -e /nix/store/…-default-builder.sh"
+        );
+        assert_eq!(
+            rewrite_store_paths("/nix/store/short-path"),
+            "/nix/store/short-path"
+        );
+        let builder = Regex::new(NIX_BUILDER).unwrap();
+        assert!(builder.is_match(&cmd));
+        assert!(!builder.is_match("/usr/bin/make"));
+    }
+
+    #[test]
+    fn preset_patterns_compile() {
+        for preset in Preset::value_variants() {
+            let settings = preset.settings();
+            for pattern in settings.adopt_exec.iter() {
+                assert!(Regex::new(pattern).is_ok(), "{pattern}");
+            }
+            assert_eq!(settings.phases().is_some(), !settings.phases.is_empty());
+        }
+        let phases = Preset::Activation.settings().phases().unwrap();
+        let tag = |cmd: &str| {
+            phases
+                .iter()
+                .find(|phase| phase.pattern.is_match(cmd))
+                .map(|phase| phase.name.as_str())
+        };
+        assert_eq!(tag("bash --rcfile /tmp/activate.bash"), Some("shell rc"));
+        assert_eq!(tag("bash /env/activate.d/hook-on-activate"), Some("hooks"));
+        assert_eq!(tag("direnv export bash"), Some("env eval"));
+        assert_eq!(tag("ls -la"), None);
+    }
+}
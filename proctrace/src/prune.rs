@@ -0,0 +1,152 @@
+//! Removes trivial processes from a processed recording, e.g. the hundreds of
+//! `sed`s and `dirname`s of a shell-heavy build, so that the recording is smaller and
+//! its chart is readable.
+//!
+//! Only leaves of the process tree are removed, but a process whose children were all
+//! removed is a leaf too, so whole trivial subtrees go away. The number of processes
+//! that were removed below each remaining process is kept in the metadata.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use regex_lite::Regex;
+
+use crate::models::{Event, EventStore};
+
+/// Which processes to remove.
+#[derive(Debug, Clone, Default)]
+pub struct PruneOptions {
+    /// Remove processes that exited sooner than this after they were forked, in
+    /// nanoseconds.
+    pub min_duration: Option<u128>,
+    /// Remove processes whose last command line matches any of these.
+    pub drop_cmd: Vec<Regex>,
+}
+
+impl PruneOptions {
+    /// Returns `true` if the process would be removed if it were a leaf.
+    fn is_trivial(&self, buffer: &std::collections::VecDeque<Event>) -> bool {
+        let short = self.min_duration.is_some_and(|min| {
+            let start = buffer.front().map(|event| event.timestamp()).unwrap_or(0);
+            // Processes that are still running aren't known to be short
+            buffer
+                .back()
+                .filter(|event| event.is_exit())
+                .is_some_and(|exit| exit.timestamp().saturating_sub(start) < min)
+        });
+        let dropped = !self.drop_cmd.is_empty()
+            && buffer
+                .iter()
+                .rev()
+                .find_map(|event| match event {
+                    Event::ExecFull { args, .. } => Some(args.joined()),
+                    _ => None,
+                })
+                .is_some_and(|command| self.drop_cmd.iter().any(|re| re.is_match(&command)));
+        short || dropped
+    }
+}
+
+/// Removes the trivial leaves of the process tree from `store`, repeatedly, and returns
+/// the number of processes that were removed below each remaining process.
+///
+/// The processes in `keep` (e.g. the root) are never removed.
+pub fn prune(store: &mut EventStore, keep: &[i32], options: &PruneOptions) -> BTreeMap<i32, u64> {
+    let mut children: HashMap<i32, usize> = HashMap::new();
+    for (pid, _) in store.iter() {
+        if let Some(parent) = store.parent_of_pid_if_stored(pid) {
+            *children.entry(parent).or_default() += 1;
+        }
+    }
+    let trivial = store
+        .iter()
+        .filter(|(pid, buffer)| !keep.contains(pid) && options.is_trivial(buffer))
+        .map(|(pid, _)| pid)
+        .collect::<HashSet<_>>();
+    let mut pruned_below: HashMap<i32, u64> = HashMap::new();
+    let mut leaves = trivial
+        .iter()
+        .copied()
+        .filter(|pid| !children.contains_key(pid))
+        .collect::<Vec<_>>();
+    while let Some(pid) = leaves.pop() {
+        let parent = store.parent_of_pid_if_stored(pid);
+        store.remove(pid);
+        let count = pruned_below.remove(&pid).unwrap_or(0) + 1;
+        let Some(parent) = parent.filter(|parent| store.pid_is_tracked(*parent)) else {
+            continue;
+        };
+        *pruned_below.entry(parent).or_default() += count;
+        let remaining = children.entry(parent).or_default();
+        *remaining -= 1;
+        if *remaining == 0 && trivial.contains(&parent) {
+            leaves.push(parent);
+        }
+    }
+    pruned_below.into_iter().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::models::ExecArgsKind;
+
+    use super::*;
+
+    fn process(store: &mut EventStore, pid: i32, ppid: i32, command: &str, duration: u128) {
+        let events = [
+            Event::Fork {
+                seq: 0,
+                timestamp: 0,
+                parent_pid: ppid,
+                child_pid: pid,
+                parent_pgid: ppid,
+                credentials: None,
+                comm: None,
+            },
+            Event::ExecFull {
+                seq: 1,
+                timestamp: 0,
+                pid,
+                ppid,
+                pgid: ppid,
+                filename: command.to_string(),
+                args: ExecArgsKind::Joined(command.to_string()),
+                credentials: None,
+            },
+            Event::Exit {
+                seq: 2,
+                timestamp: duration,
+                pid,
+                ppid,
+                pgid: ppid,
+                exit_code: Some(0),
+            },
+        ];
+        for event in events {
+            store.add(pid, event);
+        }
+    }
+
+    #[test]
+    fn prunes_trivial_subtrees() {
+        let mut store = EventStore::new();
+        process(&mut store, 1, 0, "make", 1_000);
+        // A short shell that only ran short commands goes away with them
+        process(&mut store, 2, 1, "sh", 4);
+        process(&mut store, 3, 2, "sed", 1);
+        process(&mut store, 4, 2, "dirname", 1);
+        // A short shell that ran something long stays
+        process(&mut store, 5, 1, "sh", 4);
+        process(&mut store, 6, 5, "cc", 500);
+        process(&mut store, 7, 5, "sed", 1);
+        process(&mut store, 8, 1, "[kworker/0:1]", 100);
+        let options = PruneOptions {
+            min_duration: Some(5),
+            drop_cmd: vec![Regex::new(r"^\[kworker").unwrap()],
+        };
+        let pruned = prune(&mut store, &[1], &options);
+        let mut pids = store.pids().into_iter().collect::<Vec<_>>();
+        pids.sort();
+        assert_eq!(pids, vec![1, 5, 6]);
+        assert_eq!(pruned, BTreeMap::from([(1, 4), (5, 1)]));
+    }
+}
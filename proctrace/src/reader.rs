@@ -0,0 +1,292 @@
+use std::io::{BufRead, Read};
+
+use serde_json::{de::IoRead, Deserializer, StreamDeserializer};
+
+use crate::{
+    format::{ChunkReader, MAGIC},
+    models::{Event, Metadata, MetadataItem},
+    writers::TraceFormat,
+};
+
+type Error = anyhow::Error;
+
+/// Reads the events from a processed recording.
+///
+/// This is the single entry point for anything that consumes recordings, so that
+/// consumers don't need to know how the recording was encoded.
+pub struct TraceReader<R: Read> {
+    inner: Inner<R>,
+    metadata: Option<Metadata>,
+}
+
+enum Inner<R: Read> {
+    Json(StreamDeserializer<'static, IoRead<R>, Event>),
+    Cbor { reader: R, failed: bool },
+    Indexed(ChunkReader<R>),
+}
+
+impl<R: Read> TraceReader<R> {
+    /// Returns the format of the recording being read, which is "indexed" for both
+    /// kinds of indexed recordings.
+    pub fn format(&self) -> TraceFormat {
+        match self.inner {
+            Inner::Json(_) => TraceFormat::Json,
+            Inner::Cbor { .. } => TraceFormat::Cbor,
+            Inner::Indexed(_) => TraceFormat::Indexed,
+        }
+    }
+
+    /// Returns the metadata at the start of the recording, if there was any.
+    ///
+    /// Metadata is only recognized by [`TraceReader::new`] and [`TraceReader::detect`].
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+}
+
+impl<R: BufRead> TraceReader<R> {
+    /// Creates a reader for a JSON-lines recording.
+    ///
+    /// If the recording starts with metadata it's read here and made available via
+    /// [`TraceReader::metadata`].
+    pub fn new(mut reader: R) -> Result<Self, Error> {
+        let metadata = read_metadata(&mut reader, TraceFormat::Json)?;
+        let mut trace_reader = Self::with_format(reader, TraceFormat::Json);
+        trace_reader.metadata = metadata;
+        Ok(trace_reader)
+    }
+
+    /// Creates a reader for a recording in the specified format.
+    ///
+    /// The reader is used as is rather than buffered again, so that a recording
+    /// that's already in memory (e.g. a `&[u8]` of a memory mapped file) isn't copied.
+    pub fn with_format(reader: R, format: TraceFormat) -> Self {
+        let inner = match format {
+            TraceFormat::Json => {
+                Inner::Json(Deserializer::from_reader(reader).into_iter::<Event>())
+            }
+            TraceFormat::Cbor => Inner::Cbor {
+                reader,
+                failed: false,
+            },
+            TraceFormat::Indexed | TraceFormat::IndexedZstd => {
+                Inner::Indexed(ChunkReader::new(reader))
+            }
+        };
+        Self {
+            inner,
+            metadata: None,
+        }
+    }
+
+    /// Creates a reader for a recording, detecting its format from the first byte.
+    ///
+    /// Indexed recordings start with [MAGIC], and every JSON event is an object, so
+    /// anything else that doesn't start with a `{` is assumed to be CBOR. If the
+    /// recording starts with metadata it's read here and made available via
+    /// [`TraceReader::metadata`].
+    pub fn detect(mut reader: R) -> Result<Self, Error> {
+        let format = loop {
+            let buf = reader.fill_buf()?;
+            if buf.starts_with(MAGIC) {
+                reader.consume(MAGIC.len());
+                break TraceFormat::Indexed;
+            }
+            match buf.iter().position(|b| !b.is_ascii_whitespace()) {
+                Some(idx) if buf[idx] == b'{' => break TraceFormat::Json,
+                Some(_) => break TraceFormat::Cbor,
+                // Empty input, the format doesn't matter
+                None if buf.is_empty() => break TraceFormat::Json,
+                None => {
+                    let n = buf.len();
+                    reader.consume(n);
+                }
+            }
+        };
+        let metadata = read_metadata(&mut reader, format)?;
+        let mut trace_reader = Self::with_format(reader, format);
+        trace_reader.metadata = metadata;
+        Ok(trace_reader)
+    }
+}
+
+/// The encoding of the `{"Metadata": ...}` map key in CBOR: a map with one entry
+/// whose key is an 8 byte text string.
+pub(crate) const CBOR_METADATA_PREFIX: &[u8] = b"\xa1\x68Metadata";
+const JSON_METADATA_PREFIX: &[u8] = b"{\"Metadata\"";
+
+/// Reads the metadata item at the start of a recording if it's present, leaving
+/// the reader positioned at the first event.
+fn read_metadata<R: BufRead>(
+    reader: &mut R,
+    format: TraceFormat,
+) -> Result<Option<Metadata>, Error> {
+    let buf = reader.fill_buf()?;
+    let item = match format {
+        TraceFormat::Json => {
+            if !buf.starts_with(JSON_METADATA_PREFIX) {
+                return Ok(None);
+            }
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            serde_json::from_str::<MetadataItem>(&line)?
+        }
+        TraceFormat::Cbor | TraceFormat::Indexed | TraceFormat::IndexedZstd => {
+            if !buf.starts_with(CBOR_METADATA_PREFIX) {
+                return Ok(None);
+            }
+            ciborium::from_reader::<MetadataItem, _>(reader)?
+        }
+    };
+    let MetadataItem::Metadata(metadata) = item;
+    Ok(Some(metadata))
+}
+
+impl<R: BufRead> Iterator for TraceReader<R> {
+    type Item = Result<Event, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.inner {
+            Inner::Json(ref mut de) => de.next().map(|res| res.map_err(Error::from)),
+            Inner::Cbor {
+                ref mut reader,
+                ref mut failed,
+            } => {
+                if *failed {
+                    return None;
+                }
+                match reader.fill_buf() {
+                    Ok([]) => return None,
+                    Ok(_) => {}
+                    Err(err) => {
+                        *failed = true;
+                        return Some(Err(err.into()));
+                    }
+                }
+                let event = ciborium::from_reader::<Event, _>(reader);
+                // The stream can't be resynchronized after a bad item
+                *failed = event.is_err();
+                Some(event.map_err(Error::from))
+            }
+            Inner::Indexed(ref mut chunks) => chunks.next(),
+        }
+    }
+}
+
+impl<R: Read> std::fmt::Debug for TraceReader<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TraceReader")
+            .field("format", &self.format())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        format::IndexedWriter,
+        ingest::test::make_simple_events,
+        writers::{CborWriter, EventWrite, JsonWriter},
+    };
+
+    use super::*;
+
+    fn encode(events: &[Event], format: TraceFormat) -> Vec<u8> {
+        let mut buf = vec![];
+        let mut writer: Box<dyn EventWrite + '_> = match format {
+            TraceFormat::Json => Box::new(JsonWriter::new(&mut buf)),
+            TraceFormat::Cbor => Box::new(CborWriter::new(&mut buf)),
+            TraceFormat::Indexed => Box::new(IndexedWriter::new(&mut buf)),
+            TraceFormat::IndexedZstd => Box::new(IndexedWriter::new(&mut buf).with_zstd_chunks(0)),
+        };
+        for event in events.iter() {
+            writer.write_event(event).unwrap();
+        }
+        writer.finalize().unwrap();
+        drop(writer);
+        buf
+    }
+
+    #[test]
+    fn reads_json_lines() {
+        let events =
+            make_simple_events(0, 0, &[("fork", 1, 0), ("exec_full", 1, 0), ("exit", 1, 0)]);
+        let input = encode(&events, TraceFormat::Json);
+        let read = TraceReader::new(input.as_slice())
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(read, events);
+    }
+
+    #[test]
+    fn reads_cbor() {
+        let events =
+            make_simple_events(0, 0, &[("fork", 1, 0), ("exec_full", 1, 0), ("exit", 1, 0)]);
+        let input = encode(&events, TraceFormat::Cbor);
+        let read = TraceReader::with_format(input.as_slice(), TraceFormat::Cbor)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(read, events);
+    }
+
+    #[test]
+    fn detects_format() {
+        let events = make_simple_events(0, 0, &[("fork", 1, 0), ("exit", 1, 0)]);
+        for format in [TraceFormat::Json, TraceFormat::Cbor, TraceFormat::Indexed] {
+            let input = encode(&events, format);
+            let reader = TraceReader::detect(input.as_slice()).unwrap();
+            assert_eq!(reader.format(), format);
+            assert_eq!(reader.collect::<Result<Vec<_>, _>>().unwrap(), events);
+        }
+    }
+
+    #[test]
+    fn reads_leading_metadata() {
+        let events = make_simple_events(0, 0, &[("fork", 1, 0), ("exit", 1, 0)]);
+        let metadata = Metadata::new(Some(1));
+        for format in [TraceFormat::Json, TraceFormat::Cbor, TraceFormat::Indexed] {
+            let mut buf = vec![];
+            let mut writer: Box<dyn EventWrite + '_> = match format {
+                TraceFormat::Json => Box::new(JsonWriter::new(&mut buf)),
+                TraceFormat::Cbor => Box::new(CborWriter::new(&mut buf)),
+                TraceFormat::Indexed => Box::new(IndexedWriter::new(&mut buf)),
+                TraceFormat::IndexedZstd => {
+                    Box::new(IndexedWriter::new(&mut buf).with_zstd_chunks(0))
+                }
+            };
+            writer.write_metadata(&metadata).unwrap();
+            for event in events.iter() {
+                writer.write_event(event).unwrap();
+            }
+            writer.finalize().unwrap();
+            drop(writer);
+            let reader = TraceReader::detect(buf.as_slice()).unwrap();
+            assert_eq!(reader.metadata(), Some(&metadata));
+            assert_eq!(reader.collect::<Result<Vec<_>, _>>().unwrap(), events);
+        }
+    }
+
+    #[test]
+    fn reports_malformed_events() {
+        let input = b"{\"NotAnEvent\":{}}\n";
+        let mut reader = TraceReader::new(input.as_slice()).unwrap();
+        assert!(reader.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn reads_json_lines_with_a_header() {
+        let events = make_simple_events(0, 0, &[("fork", 1, 0), ("exit", 1, 0)]);
+        let metadata = Metadata::new(Some(1));
+        let mut input = vec![];
+        let mut writer = JsonWriter::new(&mut input);
+        writer.write_metadata(&metadata).unwrap();
+        for event in events.iter() {
+            writer.write_event(event).unwrap();
+        }
+        writer.finalize().unwrap();
+        let reader = TraceReader::new(input.as_slice()).unwrap();
+        assert_eq!(reader.metadata(), Some(&metadata));
+        assert_eq!(reader.collect::<Result<Vec<_>, _>>().unwrap(), events);
+    }
+}
@@ -1,93 +1,455 @@
-#[cfg(target_os = "linux")]
+#[cfg(all(target_os = "linux", feature = "record"))]
 pub use has_record_support::*;
 
-#[cfg(target_os = "linux")]
+#[cfg(all(target_os = "linux", feature = "record"))]
 mod has_record_support {
 
     use std::{
-        io::{BufRead, BufReader, Write},
+        collections::BTreeMap,
+        io::{BufRead, BufReader},
         path::PathBuf,
         process::{Command, Stdio},
         sync::{
-            atomic::{AtomicBool, Ordering},
+            atomic::{AtomicBool, AtomicI32, Ordering},
+            mpsc::{channel, Receiver, RecvTimeoutError},
             Arc,
         },
+        time::Duration,
     };
 
-    use anyhow::Context;
+    use anyhow::{anyhow, Context};
+    use nix::{
+        sys::signal::{kill, killpg, Signal},
+        unistd::{getpgid, getpgrp, Pid},
+    };
 
     use crate::{
-        ingest::{EventIngester, EventParser},
-        writers::JsonWriter,
-        SCRIPT,
+        cli::{Backend, EventKind},
+        control::{mark_line, ControlCommand, ControlSocket},
+        dashboard::Dashboard,
+        ftrace::spawn_tracer,
+        host::{host_sample_line, overhead_line, rss_sample_line},
+        ingest::{Adoption, EventIngester, EventParser},
+        metrics::RecorderMetrics,
+        models::{Event, EventStore, Instrumentation, Metadata, ResourceUsage, SampleRatio},
+        perf::spawn_collector,
+        poll::spawn_poller,
+        script::{bpftrace_script, KernelFilter, ScriptOptions},
+        storm::StormDetector,
+        writers::EventWrite,
     };
 
     type Error = anyhow::Error;
 
-    pub fn record(
+    /// How long to wait for a line before checking for control commands.
+    const CONTROL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// How to run a recording.
+    #[derive(Debug, Clone)]
+    pub struct RecordOptions {
+        /// Where the events come from.
+        pub backend: Backend,
+        /// The path to a `bpftrace` executable.
+        pub bpftrace_path: PathBuf,
+        /// How often `/proc` is polled by the `procfs` backend.
+        pub poll_interval: Duration,
+        /// Print each line of output from `bpftrace` and the PIDs that are still running.
+        pub debug: bool,
+        /// Write every line of output from `bpftrace` to the writer as well.
+        pub record_raw: bool,
+        /// Which processes outside of the process tree are recorded too.
+        pub adoption: Adoption,
+        /// Counters to keep up to date while recording.
+        pub metrics: Option<Arc<RecorderMetrics>>,
+        /// How often to sample the load and memory use of the host, if at all.
+        pub host_sample_interval: Option<Duration>,
+        /// How often to sample the memory use of each recorded process, if at all.
+        pub rss_sample_interval: Option<Duration>,
+        /// Warns about subtrees that fork too quickly, if set.
+        pub storm_detector: Option<StormDetector>,
+        /// Kill subtrees that the storm detector warns about.
+        pub kill_on_storm: bool,
+        /// Keep recording processes after they start a new session.
+        pub follow_daemons: bool,
+        /// How long, in nanoseconds, processes outside of the process tree are
+        /// buffered before their events are discarded, if there's a limit.
+        pub buffer_window: Option<u128>,
+        /// The fraction of the command's children whose subtrees are recorded, if
+        /// only a sample of them is.
+        pub sample_trees: Option<SampleRatio>,
+        /// The seed that chooses which subtrees are sampled, or a random one if it's
+        /// not set.
+        pub sample_seed: Option<u64>,
+        /// Show a live dashboard of the recording on stderr.
+        pub tui: bool,
+        /// Record mounts and unmounts, which only the `bpftrace` backend can do.
+        pub trace_mounts: bool,
+        /// How many times a second to sample which recorded processes are on a CPU, if
+        /// at all, which only the `bpftrace` backend can do.
+        pub sample_cpu: Option<u32>,
+        /// The kinds of events to record, or every kind if it's empty, which only the
+        /// `bpftrace` backend can choose from.
+        pub events: Vec<EventKind>,
+        /// Only send the events of the recorder's descendants (and of adopted cgroups)
+        /// to userspace, which only the `bpftrace` backend can do. Ignored when
+        /// processes are adopted by what they exec or by the cgroup of the root, since
+        /// those can be anywhere on the machine.
+        pub filter_in_kernel: bool,
+        /// The wall-clock time that the recording starts at, in nanoseconds since the
+        /// Unix epoch, which is written to the metadata so that recordings from
+        /// different hosts can be merged.
+        pub started_at: Option<u128>,
+        /// Commands to handle while recording, see [`crate::control`].
+        pub control: Option<Arc<ControlSocket>>,
+        /// Set to start a new segment of the output written while recording, if it's
+        /// segmented.
+        pub rotation_requested: Option<Arc<AtomicBool>>,
+    }
+
+    /// The current wall-clock time in nanoseconds since the Unix epoch, which is close
+    /// enough to timestamp 0 of a recording that's about to start.
+    pub fn wall_clock_now() -> u128 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos())
+            .unwrap_or(0)
+    }
+
+    impl RecordOptions {
+        pub fn new(bpftrace_path: PathBuf) -> Self {
+            Self {
+                backend: Backend::Bpftrace,
+                bpftrace_path,
+                poll_interval: Duration::from_millis(10),
+                debug: false,
+                record_raw: false,
+                adoption: Adoption::default(),
+                metrics: None,
+                host_sample_interval: None,
+                rss_sample_interval: None,
+                storm_detector: None,
+                kill_on_storm: false,
+                follow_daemons: true,
+                buffer_window: None,
+                sample_trees: None,
+                sample_seed: None,
+                tui: false,
+                trace_mounts: false,
+                sample_cpu: None,
+                events: vec![],
+                filter_in_kernel: true,
+                started_at: None,
+                control: None,
+                rotation_requested: None,
+            }
+        }
+    }
+
+    /// Records the process tree started by `user_cmd`.
+    ///
+    /// Events are written to `writer` as soon as they're known to be part of the process
+    /// tree, and if `record_raw` is set every line of output from `bpftrace` is written
+    /// to it as well. Processes outside of the process tree are recorded too if they
+    /// match `adoption`.
+    pub fn record<W: EventWrite>(
         mut user_cmd: Command,
-        bpftrace_path: PathBuf,
         shutdown_flag: Arc<AtomicBool>,
-        debug: bool,
-        record_raw: bool,
-        output: impl Write,
-    ) -> Result<EventIngester<JsonWriter<impl Write>>, Error> {
-        let mut bpf_cmd = Command::new("sudo")
-            .arg(&bpftrace_path)
-            .arg("-e")
-            .arg(SCRIPT)
-            .stdout(Stdio::piped())
-            .spawn()
-            .context("failed to spawn bpftrace")?;
-        let bpf_stdout = bpf_cmd.stdout.take().unwrap();
-        // Sleep for just a bit to let bpftrace start up
-        std::thread::sleep(std::time::Duration::from_millis(1000));
-
-        let reader = BufReader::new(bpf_stdout);
+        options: RecordOptions,
+        writer: W,
+    ) -> Result<EventIngester<W>, Error> {
+        let RecordOptions {
+            backend,
+            bpftrace_path,
+            poll_interval,
+            debug,
+            record_raw,
+            adoption,
+            metrics,
+            host_sample_interval,
+            rss_sample_interval,
+            storm_detector,
+            kill_on_storm,
+            follow_daemons,
+            buffer_window,
+            sample_trees,
+            sample_seed,
+            tui,
+            trace_mounts,
+            sample_cpu,
+            events,
+            filter_in_kernel,
+            started_at,
+            control,
+            rotation_requested,
+        } = options;
+        if trace_mounts && backend != Backend::Bpftrace {
+            return Err(anyhow!(
+                "mounts can only be traced with the bpftrace backend, not {backend}"
+            ));
+        }
+        if sample_cpu.is_some() && backend != Backend::Bpftrace {
+            return Err(anyhow!(
+                "CPUs can only be sampled with the bpftrace backend, not {backend}"
+            ));
+        }
+        if !events.is_empty() && backend != Backend::Bpftrace {
+            return Err(anyhow!(
+                "events can only be left out with the bpftrace backend, not {backend}"
+            ));
+        }
+        let mut last_host_sample: Option<u128> = None;
+        let mut last_rss_sample: Option<u128> = None;
+        // The poller waits for the root PID before it starts polling
+        let poller_root_pid = Arc::new(AtomicI32::new(0));
+        let instrumentation_spawned_at = wall_clock_now();
+        // The processes spawned to record, whose descendants are found once they're ready
+        let mut instrumentation_pids = vec![];
+        let lines: Receiver<String> = match backend {
+            Backend::Bpftrace => {
+                let adopts_anywhere = !adoption.exec.is_empty() || adoption.follow_root_cgroup;
+                let filter = (filter_in_kernel && !adopts_anywhere).then(|| KernelFilter {
+                    cgroups: adoption.cgroups.clone(),
+                });
+                let script = bpftrace_script(&ScriptOptions {
+                    events,
+                    trace_mounts,
+                    sample_cpu,
+                    filter,
+                    recorder_pid: std::process::id(),
+                })?;
+                let mut bpf_cmd = Command::new("sudo")
+                    .arg(&bpftrace_path)
+                    .arg("-e")
+                    .arg(script)
+                    .stdout(Stdio::piped())
+                    .spawn()
+                    .context("failed to spawn bpftrace")?;
+                instrumentation_pids.push(bpf_cmd.id() as i32);
+                let bpf_stdout = bpf_cmd.stdout.take().unwrap();
+                // Sleep for just a bit to let bpftrace start up
+                std::thread::sleep(std::time::Duration::from_millis(1000));
+                // Read on another thread so that control commands aren't stuck waiting
+                // for output
+                let (tx, rx) = channel();
+                std::thread::spawn(move || {
+                    for line in BufReader::new(bpf_stdout).lines() {
+                        let Ok(line) = line else {
+                            eprintln!("failed to read line");
+                            continue;
+                        };
+                        if tx.send(line).is_err() {
+                            break;
+                        }
+                    }
+                });
+                rx
+            }
+            Backend::Perf => spawn_collector(shutdown_flag.clone())?,
+            Backend::Ftrace => spawn_tracer(shutdown_flag.clone())?,
+            Backend::Procfs => spawn_poller(
+                poller_root_pid.clone(),
+                poll_interval,
+                shutdown_flag.clone(),
+            ),
+        };
         let event_parser = EventParser::new();
-        let mut ingester = EventIngester::new(None, Some(JsonWriter::new(output)));
+        let mut ingester = EventIngester::new(None, Some(writer));
+        ingester.set_adoption(adoption);
+        if let Some(detector) = storm_detector {
+            ingester.detect_storms(detector);
+        }
+        if !follow_daemons {
+            ingester.stop_following_daemons();
+        }
+        if let Some(window) = buffer_window {
+            ingester.set_buffer_window(window);
+        }
+        let sample_seed = sample_trees.map(|ratio| {
+            let seed = sample_seed.unwrap_or_else(|| wall_clock_now() as u64);
+            ingester.sample_trees(ratio, seed);
+            seed
+        });
+        let mut storms_seen = 0;
+        let mut dashboard = tui.then(Dashboard::new);
 
         let mut user_cmd_started = false;
         let mut child = None;
+        // The sequence number and timestamp of the last line, for marks
+        let mut last_seen = (0, 0);
 
-        for line in reader.lines() {
+        loop {
             // TODO: we can probably merge this implementation with `ingest_raw` if
             // we create a wrapper around the reader that checks this shutdown flag.
             if shutdown_flag.load(Ordering::SeqCst) {
                 break;
             }
+            // Control commands are handled when the wait times out too, since the
+            // backend may not have anything to say for a while
+            let line = match lines.recv_timeout(CONTROL_POLL_INTERVAL) {
+                Ok(line) => Some(line),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
             // We need the reader started before the process, otherwise we might not catch it starting
             if !user_cmd_started {
+                if line.is_none() {
+                    continue;
+                }
+                let command_spawned_at = wall_clock_now();
                 let proc = user_cmd.spawn().context("failed to spawn user command")?;
                 let user_cmd_pid = proc.id() as i32; // it should fit
                 child = Some(proc);
                 ingester.set_root_pid(user_cmd_pid)?;
+                poller_root_pid.store(user_cmd_pid, Ordering::SeqCst);
+                let mut metadata = Metadata::new(Some(user_cmd_pid));
+                metadata.started_at = started_at;
+                metadata.cpu_sample_hz = sample_cpu;
+                metadata.sampled_trees = sample_trees;
+                metadata.sample_seed = sample_seed;
+                metadata.instrumentation = Some(Instrumentation {
+                    recorder_pid: std::process::id() as i32,
+                    pids: instrumentation_processes(&instrumentation_pids),
+                    spawned_at: instrumentation_spawned_at,
+                    command_spawned_at,
+                });
+                metadata.command = std::iter::once(user_cmd.get_program())
+                    .chain(user_cmd.get_args())
+                    .map(|arg| arg.to_string_lossy().to_string())
+                    .collect();
+                ingester
+                    .write_metadata(&metadata)
+                    .context("failed to write metadata")?;
                 user_cmd_started = true;
                 continue;
             }
-            if line.is_err() {
-                eprintln!("failed to read line");
-                continue;
+            while let Some(request) = control.as_ref().and_then(|control| control.try_next()) {
+                let result = match request.command {
+                    ControlCommand::Flush => ingester.flush_writer(),
+                    ControlCommand::Mark(ref label) => {
+                        let root_pid = ingester.root_pid().unwrap_or_default();
+                        let line = mark_line(last_seen.0, last_seen.1, root_pid, label);
+                        event_parser.parse_line(&line).and_then(|event| {
+                            if record_raw {
+                                ingester.write_raw(&line)?;
+                            }
+                            ingester.observe_event(event)
+                        })
+                    }
+                    ControlCommand::Rotate => match rotation_requested {
+                        Some(ref requested) => {
+                            requested.store(true, Ordering::SeqCst);
+                            Ok(())
+                        }
+                        None => Err(anyhow!(
+                            "the recording isn't segmented, use --rotate-size or --rotate-every"
+                        )),
+                    },
+                    ControlCommand::Stop => {
+                        shutdown_flag.store(true, Ordering::SeqCst);
+                        Ok(())
+                    }
+                };
+                request.reply(result);
             }
-            let line = line.unwrap();
+            let Some(mut line) = line else {
+                continue;
+            };
             if debug {
                 eprintln!("RX: {}", line);
             }
+            // Redrawn on ticks too so that it keeps up while nothing is happening
+            if let Some(ref mut dashboard) = dashboard {
+                dashboard
+                    .refresh(&ingester, last_seen.1, &mut std::io::stderr().lock())
+                    .context("failed to draw dashboard")?;
+            }
+            if let Some((seq, ts)) = event_parser.parse_tick(&line) {
+                last_seen = (seq, ts);
+                if let Some(interval) = rss_sample_interval {
+                    if last_rss_sample.is_none_or(|last| ts >= last + interval.as_nanos()) {
+                        last_rss_sample = Some(ts);
+                        for pid in ingester.unfinished_pids() {
+                            // The process may have exited since its last event
+                            let Some(sample) = sample_rss(seq, ts, pid) else {
+                                continue;
+                            };
+                            if record_raw {
+                                ingester
+                                    .write_raw(&sample)
+                                    .context("failed to write raw output")?;
+                            }
+                            let event = event_parser.parse_line(&sample)?;
+                            ingester
+                                .observe_event(event)
+                                .with_context(|| format!("failed to ingest event: {sample}"))?;
+                        }
+                    }
+                }
+                // Ticks are replaced with a host sample when one is due
+                let Some(interval) = host_sample_interval else {
+                    continue;
+                };
+                if last_host_sample.is_some_and(|last| ts < last + interval.as_nanos()) {
+                    continue;
+                }
+                last_host_sample = Some(ts);
+                match sample_host(seq, ts) {
+                    Some(sample) => line = sample,
+                    None => continue,
+                }
+            }
             match event_parser.parse_line(&line) {
                 Ok(event) => {
+                    last_seen = (event.seq(), event.timestamp());
                     if record_raw {
                         ingester
                             .write_raw(&line)
                             .context("failed to write raw output")?;
                     }
                     ingester
-                        .observe_event(&event)
-                        .with_context(|| format!("failed to ingest event: {event:?}"))?;
+                        .observe_event(event)
+                        .with_context(|| format!("failed to ingest event: {line}"))?;
+                    if let Some(ref metrics) = metrics {
+                        metrics.events_ingested.fetch_add(1, Ordering::Relaxed);
+                    }
+                    if let Some(ref mut dashboard) = dashboard {
+                        dashboard.count_event();
+                    }
                 }
                 Err(err) => {
                     eprintln!("failed to parse line: {}", err);
+                    if let Some(ref metrics) = metrics {
+                        metrics.events_dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                    if let Some(ref mut dashboard) = dashboard {
+                        dashboard.count_dropped();
+                    }
                 }
             }
+            for storm in ingester.fork_storms()[storms_seen..].iter() {
+                let Event::ForkStorm {
+                    pid,
+                    forks,
+                    window_ms,
+                    ..
+                } = storm
+                else {
+                    continue;
+                };
+                eprintln!("warning: fork storm under PID {pid}, {forks} forks in {window_ms}ms");
+                if kill_on_storm {
+                    kill_subtree(ingester.tracked_events(), *pid);
+                }
+            }
+            storms_seen = ingester.fork_storms().len();
+            if let Some(ref metrics) = metrics {
+                metrics
+                    .tracked_pids
+                    .store(ingester.tracked_pid_count() as u64, Ordering::Relaxed);
+                metrics
+                    .buffered_pids
+                    .store(ingester.buffered_pid_count() as u64, Ordering::Relaxed);
+            }
 
             // Reap the child process if possible
             if let Some(ref mut proc) = child {
@@ -96,18 +458,195 @@ mod has_record_support {
                 }
             }
 
-            let unfinished = ingester
-                .tracked_events()
-                .unfinished_pids()
-                .collect::<Vec<_>>();
+            let unfinished = ingester.unfinished_pids();
             if debug {
                 eprintln!("STILL_RUNNING: {unfinished:?}");
             }
+
             if !ingester.is_empty() && unfinished.is_empty() {
                 break;
             }
         }
 
+        // What the recorder itself used, measured before `bpftrace` is stopped
+        let mut overhead_pids = vec![std::process::id() as i32];
+        overhead_pids.extend(instrumentation_processes(&instrumentation_pids).into_keys());
+        for pid in overhead_pids {
+            let Some((comm, usage)) = read_overhead(pid) else {
+                continue;
+            };
+            let line = overhead_line(last_seen.0, last_seen.1, pid, &comm, &usage);
+            if record_raw {
+                ingester
+                    .write_raw(&line)
+                    .context("failed to write raw output")?;
+            }
+            let event = event_parser.parse_line(&line)?;
+            ingester
+                .observe_event(event)
+                .with_context(|| format!("failed to ingest event: {line}"))?;
+        }
+
+        // Make sure everything written so far is durable, even if we were interrupted
+        ingester
+            .finalize_writer()
+            .context("failed to finalize writer")?;
+
         Ok(ingester)
     }
+
+    /// Returns the command names of `pids` and all of their descendants, keyed by PID,
+    /// e.g. `sudo` and the `bpftrace` that it ran.
+    fn instrumentation_processes(pids: &[i32]) -> BTreeMap<i32, String> {
+        let mut processes = BTreeMap::new();
+        let mut to_visit = pids.to_vec();
+        while let Some(pid) = to_visit.pop() {
+            let Ok(proc) = procfs::process::Process::new(pid) else {
+                continue;
+            };
+            let Ok(stat) = proc.stat() else {
+                continue;
+            };
+            if processes.insert(pid, stat.comm).is_some() {
+                continue;
+            }
+            if let Ok(children) = proc.task_main_thread().and_then(|task| task.children()) {
+                to_visit.extend(children.into_iter().map(|child| child as i32));
+            }
+        }
+        processes
+    }
+
+    /// Reads the command name and resource usage of a process so far from `/proc`.
+    fn read_overhead(pid: i32) -> Option<(String, ResourceUsage)> {
+        let proc = procfs::process::Process::new(pid).ok()?;
+        let stat = proc.stat().ok()?;
+        let status = proc.status().ok();
+        let tick_ns = 1_000_000_000 / procfs::ticks_per_second().max(1);
+        let usage = ResourceUsage {
+            minor_faults: stat.minflt,
+            major_faults: stat.majflt,
+            voluntary_switches: status
+                .as_ref()
+                .and_then(|status| status.voluntary_ctxt_switches)
+                .unwrap_or(0),
+            involuntary_switches: status
+                .as_ref()
+                .and_then(|status| status.nonvoluntary_ctxt_switches)
+                .unwrap_or(0),
+            user_ns: stat.utime * tick_ns,
+            system_ns: stat.stime * tick_ns,
+        };
+        Some((stat.comm, usage))
+    }
+
+    /// How a subtree is killed.
+    #[derive(Debug, PartialEq, Eq)]
+    enum KillPlan {
+        /// Kill the whole process group.
+        Group(i32),
+        /// Kill these processes one by one.
+        Each(Vec<i32>),
+    }
+
+    /// Chooses how to kill the unfinished processes in a `subtree` whose root is in
+    /// process group `pgid`, where `members` are the live processes in that group.
+    ///
+    /// The group is only killed as a whole if it's not the recorder's own group and
+    /// every member of it is in the subtree, since a group can be shared with
+    /// processes outside of the subtree, e.g. ones that joined it with `setpgid`.
+    fn kill_plan(subtree: Vec<i32>, pgid: Option<i32>, own_pgid: i32, members: &[i32]) -> KillPlan {
+        match pgid {
+            Some(pgid)
+                if pgid != own_pgid && members.iter().all(|member| subtree.contains(member)) =>
+            {
+                KillPlan::Group(pgid)
+            }
+            _ => KillPlan::Each(subtree),
+        }
+    }
+
+    /// Returns the live processes in process group `pgid`.
+    fn process_group_members(pgid: i32) -> Vec<i32> {
+        let Ok(processes) = procfs::process::all_processes() else {
+            return vec![];
+        };
+        processes
+            .filter_map(|proc| proc.ok()?.stat().ok())
+            .filter(|stat| stat.pgrp == pgid)
+            .map(|stat| stat.pid)
+            .collect()
+    }
+
+    /// Kills the subtree rooted at `pid`, by killing its process group if nothing else
+    /// is in it and otherwise by killing its unfinished processes one by one.
+    fn kill_subtree(store: &EventStore, pid: i32) {
+        let in_subtree = |candidate: i32| {
+            candidate == pid || store.ancestors(candidate).any(|ancestor| ancestor == pid)
+        };
+        let subtree = store
+            .unfinished_pids()
+            .filter(|candidate| in_subtree(*candidate))
+            .collect::<Vec<_>>();
+        let pgid = getpgid(Some(Pid::from_raw(pid)))
+            .ok()
+            .map(|pgid| pgid.as_raw());
+        let members = pgid.map(process_group_members).unwrap_or_default();
+        match kill_plan(subtree, pgid, getpgrp().as_raw(), &members) {
+            KillPlan::Group(pgid) => {
+                eprintln!("killing process group {pgid}");
+                if let Err(err) = killpg(Pid::from_raw(pgid), Signal::SIGKILL) {
+                    eprintln!("failed to kill process group {pgid}: {err}");
+                }
+            }
+            KillPlan::Each(pids) => {
+                eprintln!("killing {} processes under PID {pid}", pids.len());
+                for pid in pids {
+                    // The process may have exited without its exit being observed yet
+                    let _ = kill(Pid::from_raw(pid), Signal::SIGKILL);
+                }
+            }
+        }
+    }
+
+    /// Samples the load and memory use of the host as a line of raw output.
+    fn sample_host(seq: u128, ts: u128) -> Option<String> {
+        let loadavg = std::fs::read_to_string("/proc/loadavg").ok()?;
+        let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+        host_sample_line(seq, ts, &loadavg, &meminfo)
+    }
+
+    /// Samples the memory use of a process as a line of raw output.
+    fn sample_rss(seq: u128, ts: u128, pid: i32) -> Option<String> {
+        let status = std::fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+        rss_sample_line(seq, ts, pid, &status)
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[test]
+        fn only_kills_groups_inside_the_subtree() {
+            let subtree = vec![10, 11, 12];
+            assert_eq!(
+                kill_plan(subtree.clone(), Some(10), 1, &[10, 11]),
+                KillPlan::Group(10)
+            );
+            // A daemon outside of the subtree joined the group
+            assert_eq!(
+                kill_plan(subtree.clone(), Some(10), 1, &[10, 11, 99]),
+                KillPlan::Each(subtree.clone())
+            );
+            // The subtree is in the recorder's own group
+            assert_eq!(
+                kill_plan(subtree.clone(), Some(1), 1, &[1, 10]),
+                KillPlan::Each(subtree.clone())
+            );
+            assert_eq!(
+                kill_plan(subtree.clone(), None, 1, &[]),
+                KillPlan::Each(subtree)
+            );
+        }
+    }
 }
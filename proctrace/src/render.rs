@@ -1,43 +1,543 @@
 use std::{
-    collections::VecDeque,
-    io::{Read, Write},
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, VecDeque},
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
     path::Path,
 };
 
 use anyhow::{anyhow, Context};
 use regex_lite::Regex;
-use serde_json::Deserializer;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    cli::DisplayMode,
-    ingest::EventIngester,
-    models::{Event, ExecArgsKind},
-    writers::NoOpWriter,
+    analyze::{command_line, command_name, format_nanos, RunSummary},
+    cli::{DisplayMode, TableSort},
+    compare::{comparison_table, FailIf},
+    format::{select_events, IndexedReader, Selection},
+    groups::{Tagger, UNTAGGED},
+    ingest::{clean_exec_sequences, EventIngester},
+    labels::Labels,
+    models::{
+        collapse_buffer_execs, describe_crash, describe_exit_code, describe_failed_exec,
+        describe_mount, describe_ptrace, describe_sandbox, errno_name, signal_name, Event,
+        EventStore, ExecArgsKind, Metadata, ResourceUsage,
+    },
+    reader::TraceReader,
+    writers::{EventWrite, JsonWriter, NoOpWriter, TraceFormat},
 };
 
+#[cfg(feature = "fs")]
+use crate::plugin::ExecRenderer;
+
 type Error = anyhow::Error;
 
-pub fn render(reader: impl Read, writer: impl Write, mode: DisplayMode) -> Result<(), Error> {
+/// Options that change how a recording is rendered.
+#[derive(Debug, Default, Clone)]
+pub struct RenderOptions {
+    /// Render a process that exec'd several times as a single span labeled with the
+    /// last thing it exec'd, instead of a section with a span for each exec.
+    pub collapse_exec_chains: bool,
+    /// Link every span to the detail page of its process in `pids/`, and truncate
+    /// labels since nothing is lost by doing so.
+    pub link_details: bool,
+    /// Highlight the spans that lasted longer than this many nanoseconds.
+    pub highlight_over: Option<u128>,
+    /// Friendly names for commands, which replace them in every display mode.
+    pub labels: Labels,
+    /// Leave the shell that `record --shell` ran the command with out of charts.
+    pub hide_shell_wrapper: bool,
+    /// Start a fenced chart ("mermaid-md") with a heading that describes the recording.
+    pub heading: bool,
+    /// The order of the rows of a table ("table").
+    pub sort_by: TableSort,
+    /// The most rows to show in a table ("table"), or all of them if unset.
+    pub limit: Option<usize>,
+    /// Group the processes by tag ("by-process").
+    pub tagger: Option<Tagger>,
+}
+
+/// The longest label shown for a span that links to a detail page.
+const MAX_LINKED_LABEL_LEN: usize = 60;
+
+/// How many CPU samples long a span has to be to be shaded by its CPU use.
+const MIN_SAMPLE_PERIODS: u128 = 10;
+
+/// Spans that were on a CPU for less than this share of their time are drawn as done.
+const IDLE_CPU_PERCENT: u128 = 10;
+
+/// Everything that's known about a single process, which is written next to a chart
+/// so that nothing is lost when its label is truncated.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessDetail {
+    pub pid: i32,
+    pub parent_pid: Option<i32>,
+    /// When the first event of the process happened, relative to the start of the
+    /// recording.
+    pub start: u128,
+    /// When the last event of the process happened, relative to the start of the
+    /// recording.
+    pub stop: u128,
+    pub execs: Vec<ExecDetail>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failed_execs: Vec<FailedExecDetail>,
+    /// Events that proctrace doesn't know about, e.g. from extra probes.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub custom_events: Vec<CustomDetail>,
+    /// Changes of the working directory, in the order they happened.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub working_directories: Vec<ChdirDetail>,
+    /// The kinds of namespaces that the process or its ancestors were seen creating
+    /// or entering, e.g. "mnt" or "net".
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub namespaces: Vec<String>,
+    /// The child subreaper that the process was reparented to when its parent exited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reaper_pid: Option<i32>,
+    /// The page faults and context switches of the process, if they were recorded
+    /// when it exited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<ResourceUsage>,
+    pub exited: bool,
+    /// The exit code in the format of a wait status, missing if it wasn't recorded.
+    pub exit_code: Option<i32>,
+}
+
+/// A single exec of a process.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecDetail {
+    /// Relative to the start of the recording.
+    pub timestamp: u128,
+    pub filename: String,
+    pub args: ExecArgsKind,
+}
+
+/// An exec of a process that failed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FailedExecDetail {
+    /// Relative to the start of the recording.
+    pub timestamp: u128,
+    pub filename: Option<String>,
+    pub errno: Option<i32>,
+}
+
+/// A custom event of a process.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CustomDetail {
+    /// Relative to the start of the recording.
+    pub timestamp: u128,
+    pub name: String,
+    pub fields: BTreeMap<String, String>,
+}
+
+/// A change of the working directory of a process.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChdirDetail {
+    /// Relative to the start of the recording.
+    pub timestamp: u128,
+    /// Missing if the directory was changed with `fchdir`.
+    pub path: Option<String>,
+}
+
+impl ProcessDetail {
+    /// Collects the details of a process from its events.
+    ///
+    /// Only the namespaces that the process entered itself are known from its events,
+    /// see [`EventStore::namespaces_of`] for the ones it inherited. Likewise the
+    /// subreaper it was reparented to is left out, see [`EventStore::reaper_of`].
+    pub fn from_buffer(pid: i32, buffer: &VecDeque<Event>, initial_time: u128) -> Self {
+        let relative = |event: Option<&Event>| {
+            event
+                .map(|event| event.timestamp().saturating_sub(initial_time))
+                .unwrap_or(0)
+        };
+        let execs = buffer
+            .iter()
+            .filter_map(|event| match event {
+                Event::ExecFull {
+                    timestamp,
+                    filename,
+                    args,
+                    ..
+                } => Some(ExecDetail {
+                    timestamp: timestamp.saturating_sub(initial_time),
+                    filename: filename.clone(),
+                    args: args.clone(),
+                }),
+                _ => None,
+            })
+            .collect();
+        let failed_execs = buffer
+            .iter()
+            .filter_map(|event| match event {
+                Event::BadExec {
+                    timestamp,
+                    filename,
+                    errno,
+                    ..
+                } => Some(FailedExecDetail {
+                    timestamp: timestamp.saturating_sub(initial_time),
+                    filename: filename.clone(),
+                    errno: *errno,
+                }),
+                _ => None,
+            })
+            .collect();
+        let custom_events = buffer
+            .iter()
+            .filter_map(|event| match event {
+                Event::Custom {
+                    timestamp,
+                    name,
+                    fields,
+                    ..
+                } => Some(CustomDetail {
+                    timestamp: timestamp.saturating_sub(initial_time),
+                    name: name.clone(),
+                    fields: fields.clone(),
+                }),
+                _ => None,
+            })
+            .collect();
+        let working_directories = buffer
+            .iter()
+            .filter_map(|event| match event {
+                Event::Chdir {
+                    timestamp, path, ..
+                } => Some(ChdirDetail {
+                    timestamp: timestamp.saturating_sub(initial_time),
+                    path: path.clone(),
+                }),
+                _ => None,
+            })
+            .collect();
+        let namespaces = buffer
+            .iter()
+            .filter_map(|event| match event {
+                Event::Namespace { namespaces, .. } => Some(namespaces.iter().cloned()),
+                _ => None,
+            })
+            .flatten()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let usage = buffer.iter().find_map(|event| match event {
+            Event::Usage { usage, .. } => Some(*usage),
+            _ => None,
+        });
+        let exit = buffer.back().filter(|event| event.is_exit());
+        Self {
+            pid,
+            parent_pid: buffer.front().and_then(|event| event.fork_parent()),
+            start: relative(buffer.front()),
+            stop: relative(buffer.back()),
+            execs,
+            failed_execs,
+            custom_events,
+            working_directories,
+            namespaces,
+            reaper_pid: None,
+            usage,
+            exited: exit.is_some(),
+            exit_code: exit.and_then(|event| event.exit_code()),
+        }
+    }
+
+    /// Formats the details as a Markdown page.
+    pub fn to_markdown(&self) -> String {
+        let mut page = format!("# PID {}\n\n", self.pid);
+        if let Some(ppid) = self.parent_pid {
+            page.push_str(&format!("- Parent: PID {ppid}\n"));
+        }
+        if let Some(reaper) = self.reaper_pid {
+            page.push_str(&format!("- Reparented to: PID {reaper} (subreaper)\n"));
+        }
+        page.push_str(&format!(
+            "- Ran from {} to {} ({})\n",
+            format_nanos(self.start),
+            format_nanos(self.stop),
+            format_nanos(self.stop - self.start)
+        ));
+        let exit = match (self.exited, self.exit_code) {
+            (true, Some(code)) => describe_exit_code(code),
+            (true, None) => "exited".to_string(),
+            (false, _) => "still running when the recording ended".to_string(),
+        };
+        page.push_str(&format!("- Status: {exit}\n"));
+        if let Some(usage) = self.usage {
+            let share = usage
+                .cpu_percent(self.stop - self.start)
+                .map(|share| format!(" ({share}% of the time it ran)"))
+                .unwrap_or_default();
+            page.push_str(&format!(
+                "- CPU time: {} user, {} system{share}\n",
+                format_nanos(usage.user_ns as u128),
+                format_nanos(usage.system_ns as u128)
+            ));
+            page.push_str(&format!(
+                "- Page faults: {} minor, {} major\n- Context switches: {} voluntary, {} involuntary\n",
+                usage.minor_faults,
+                usage.major_faults,
+                usage.voluntary_switches,
+                usage.involuntary_switches
+            ));
+        }
+        if !self.namespaces.is_empty() {
+            page.push_str(&format!("- Namespaces: {}\n", self.namespaces.join(", ")));
+        }
+        if self.execs.is_empty() {
+            page.push_str("\nNever exec'd.\n");
+        }
+        for exec in self.execs.iter() {
+            page.push_str(&format!(
+                "\n## {} at {}\n\n```\n",
+                markdown_code(&exec.filename),
+                format_nanos(exec.timestamp)
+            ));
+            // One argument per line so that it's clear where each one ends
+            match &exec.args {
+                ExecArgsKind::Joined(args) => page.push_str(args),
+                ExecArgsKind::Args(args) => page.push_str(&args.join("\n")),
+            }
+            page.push_str("\n```\n");
+        }
+        if !self.failed_execs.is_empty() {
+            page.push_str("\n## Failed execs\n\n");
+        }
+        for exec in self.failed_execs.iter() {
+            let filename = exec.filename.as_deref().unwrap_or("<unknown>");
+            let error = exec
+                .errno
+                .map(|errno| format!(" ({})", errno_name(errno)))
+                .unwrap_or_default();
+            page.push_str(&format!(
+                "- {}{error} at {}\n",
+                markdown_code(filename),
+                format_nanos(exec.timestamp)
+            ));
+        }
+        if !self.working_directories.is_empty() {
+            page.push_str("\n## Working directory changes\n\n");
+        }
+        for chdir in self.working_directories.iter() {
+            let path = chdir
+                .path
+                .as_deref()
+                .map(markdown_code)
+                .unwrap_or_else(|| "(with fchdir)".to_string());
+            page.push_str(&format!("- {path} at {}\n", format_nanos(chdir.timestamp)));
+        }
+        if !self.custom_events.is_empty() {
+            page.push_str("\n## Other events\n\n");
+        }
+        for event in self.custom_events.iter() {
+            page.push_str(&format!(
+                "- {} at {}\n",
+                markdown_code(&custom_label(&event.name, &event.fields)),
+                format_nanos(event.timestamp)
+            ));
+        }
+        page
+    }
+}
+
+pub fn render(
+    reader: impl Read,
+    writer: impl Write,
+    mode: DisplayMode,
+    options: &RenderOptions,
+) -> Result<(), Error> {
     let ingester = read_events(reader).context("failed to read events from input")?;
-    render_events(ingester, writer, mode)
+    render_events(ingester, writer, mode, options)
 }
 
-pub fn read_events(reader: impl Read) -> Result<EventIngester<NoOpWriter>, Error> {
-    let mut de = Deserializer::from_reader(reader).into_iter::<Event>();
-    let first_event = match de.next() {
+/// Renders a chart into `dir` as `chart.mmd`, along with a Markdown and JSON detail
+/// page for every process in `dir/pids/` that the spans of the chart link to.
+///
+/// Only the "mermaid" display mode can be rendered to a directory.
+pub fn render_to_dir(
+    ingester: EventIngester<NoOpWriter>,
+    dir: &Path,
+    mode: DisplayMode,
+    options: &RenderOptions,
+) -> Result<(), Error> {
+    if mode != DisplayMode::Mermaid {
+        return Err(anyhow!(
+            "the {mode} display mode can't be rendered to a directory"
+        ));
+    }
+    let tree = ProcessTree::prepare(ingester, &options.labels)?;
+    let initial_time = tree.start_time()?;
+    let pids_dir = dir.join("pids");
+    std::fs::create_dir_all(&pids_dir)
+        .with_context(|| format!("failed to create directory {}", pids_dir.display()))?;
+    for (pid, buffer) in tree.store.iter() {
+        let mut detail = ProcessDetail::from_buffer(pid, buffer, initial_time);
+        // Namespaces are inherited, which the buffer of the process alone can't say
+        detail.namespaces = tree.store.namespaces_of(pid).into_iter().collect();
+        detail.reaper_pid = tree.store.reaper_of(pid);
+        let json = serde_json::to_string_pretty(&detail).context("failed to serialize details")?;
+        std::fs::write(pids_dir.join(format!("{pid}.json")), json)
+            .context("failed to write details")?;
+        std::fs::write(pids_dir.join(format!("{pid}.md")), detail.to_markdown())
+            .context("failed to write details")?;
+    }
+    let chart_path = dir.join("chart.mmd");
+    let mut chart = std::io::BufWriter::new(
+        std::fs::File::create(&chart_path)
+            .with_context(|| format!("failed to create {}", chart_path.display()))?,
+    );
+    let options = RenderOptions {
+        link_details: true,
+        ..options.clone()
+    };
+    render_mermaid(&tree, &mut chart, &options)?;
+    chart.flush().context("write failed")
+}
+
+/// The default number of events held back to restore their order when streaming.
+pub const DEFAULT_REORDER_WINDOW: usize = 4096;
+
+/// The default number of events held back when following a recording that's still
+/// being written, which is already close to being in order.
+pub const FOLLOW_REORDER_WINDOW: usize = 64;
+
+/// Reads the first event of a recording, which belongs to the root process, and
+/// returns the root PID along with the events that start the recording.
+///
+/// The first event is usually the fork of the root process. Recordings that start
+/// partway through the life of the root process (e.g. because it was attached to, or
+/// the recording was filtered) start with some other event instead, so a fork is
+/// made up for the root process at the time of that event.
+fn read_root_start(
+    events: &mut impl Iterator<Item = Result<Event, Error>>,
+) -> Result<(i32, Vec<Event>), Error> {
+    let first_event = match events.next() {
         Some(Ok(event)) => event,
-        Some(Err(err)) => return Err(err.into()),
+        Some(Err(err)) => return Err(err),
         None => return Err(anyhow!("input was empty")),
     };
-    let Event::Fork { ref child_pid, .. } = first_event else {
-        return Err(anyhow!("first event was not a fork"));
+    if let Event::Fork { child_pid, .. } = first_event {
+        return Ok((child_pid, vec![first_event]));
+    }
+    if !first_event.is_process_event() {
+        return Err(anyhow!("first event was not from a process"));
+    }
+    let (parent_pid, parent_pgid) = match first_event {
+        Event::Exec { ppid, pgid, .. }
+        | Event::ExecFull { ppid, pgid, .. }
+        | Event::Exit { ppid, pgid, .. }
+        | Event::SetSID { ppid, pgid, .. }
+        | Event::SetPGID { ppid, pgid, .. } => (ppid, pgid),
+        _ => (0, 0),
+    };
+    let start = Event::Fork {
+        // Before the first event, so that it stays first when events are sorted
+        seq: first_event.seq().saturating_sub(1),
+        timestamp: first_event.timestamp(),
+        parent_pid,
+        child_pid: first_event.pid(),
+        parent_pgid,
+        credentials: None,
+        comm: None,
     };
-    let mut ingester: EventIngester<NoOpWriter> = EventIngester::new(Some(*child_pid), None);
-    ingester.observe_event(&first_event)?;
-    for maybe_event in de {
+    Ok((first_event.pid(), vec![start, first_event]))
+}
+
+/// Reads a recording and post-processes it, see [`read_unprocessed_events`].
+pub fn read_events(reader: impl Read) -> Result<EventIngester<NoOpWriter>, Error> {
+    read_unprocessed_events(reader).map(post_processed)
+}
+
+/// Reads a recording as it was written.
+///
+/// Recordings that were streamed while they were taken haven't had their exec events
+/// merged yet, and timestamps can go backwards, which
+/// [`EventIngester::post_process_buffers`] fixes if the caller wants it to.
+pub fn read_unprocessed_events(reader: impl Read) -> Result<EventIngester<NoOpWriter>, Error> {
+    let events = TraceReader::detect(BufReader::new(reader))?;
+    let metadata = events.metadata().cloned();
+    ingest_events(metadata.as_ref(), events)
+}
+
+fn post_processed<W>(mut ingester: EventIngester<W>) -> EventIngester<W> {
+    ingester.post_process_buffers();
+    ingester
+}
+
+/// Reads a recording that's already in memory, e.g. a memory mapped file, without
+/// copying it, and post-processes it.
+pub fn read_events_from_slice(bytes: &[u8]) -> Result<EventIngester<NoOpWriter>, Error> {
+    let mut rest = bytes;
+    let events = TraceReader::detect(&mut rest)?;
+    let metadata = events.metadata().cloned();
+    if events.format() != TraceFormat::Json {
+        return ingest_events(metadata.as_ref(), events).map(post_processed);
+    }
+    // Parsing JSON from a slice is much faster than parsing it from a reader, and
+    // nothing has been read past the metadata yet
+    drop(events);
+    let events = serde_json::Deserializer::from_slice(rest)
+        .into_iter::<Event>()
+        .map(|event| event.map_err(Error::from));
+    ingest_events(metadata.as_ref(), events).map(post_processed)
+}
+
+/// Reads the part of a recording in `selection` and post-processes it.
+///
+/// Indexed recordings are read by seeking to the chunks that contain the selected
+/// processes, anything else is read from start to end (see [scan_selected_events]).
+pub fn read_selected_events(
+    mut reader: impl Read + Seek,
+    selection: &Selection,
+) -> Result<EventIngester<NoOpWriter>, Error> {
+    if let Ok(mut indexed) = IndexedReader::open(&mut reader) {
+        let events = indexed.read_selection(selection)?;
+        let metadata = indexed.metadata().cloned();
+        return ingest_events(metadata.as_ref(), events.into_iter().map(Ok)).map(post_processed);
+    }
+    reader.seek(SeekFrom::Start(0))?;
+    scan_selected_events(reader, selection)
+}
+
+/// Reads a whole recording, keeping only the part in `selection`, and post-processes it.
+pub fn scan_selected_events(
+    reader: impl Read,
+    selection: &Selection,
+) -> Result<EventIngester<NoOpWriter>, Error> {
+    let events = TraceReader::detect(BufReader::new(reader))?;
+    let metadata = events.metadata().cloned();
+    let selected = select_events(events, selection)?;
+    ingest_events(metadata.as_ref(), selected.into_iter().map(Ok)).map(post_processed)
+}
+
+/// Ingests processed events, the first of which must belong to the root process,
+/// without post-processing them.
+fn ingest_events(
+    metadata: Option<&Metadata>,
+    events: impl IntoIterator<Item = Result<Event, Error>>,
+) -> Result<EventIngester<NoOpWriter>, Error> {
+    let mut events = events.into_iter();
+    let adopted_pids = metadata
+        .map(|metadata| metadata.adopted_pids.clone())
+        .unwrap_or_default();
+    let (root_pid, first_events) = read_root_start(&mut events)?;
+    let mut ingester: EventIngester<NoOpWriter> = EventIngester::new(Some(root_pid), None);
+    for pid in adopted_pids {
+        ingester.adopt_pid(pid);
+    }
+    if let Some(metadata) = metadata {
+        if metadata.shell_wrapper {
+            ingester.mark_shell_wrapper();
+        }
+        ingester.set_metadata(metadata.clone());
+    }
+    for event in first_events {
+        ingester.observe_event(event)?;
+    }
+    for maybe_event in events {
         match maybe_event {
             Ok(event) => {
-                ingester.observe_event(&event)?;
+                ingester.observe_event(event)?;
             }
             Err(err) => {
                 eprintln!("failed to parse event: {err}");
@@ -47,45 +547,461 @@ pub fn read_events(reader: impl Read) -> Result<EventIngester<NoOpWriter>, Error
     Ok(ingester)
 }
 
-/// Render ingested events.
-pub fn render_events<T>(
-    mut ingester: EventIngester<T>,
+/// Renders a recording as it's read instead of reading the whole recording first.
+///
+/// Events are emitted as soon as their PID is known to be part of the process tree,
+/// so memory use doesn't grow with the length of the recording. Up to `window` events
+/// ("sequential") or finished processes ("by-process") are held back so that events
+/// that arrive out of order can be put back in order. Processes are held until they
+/// exit, and "by-process" also holds them until every process that started before them
+/// has been written, so that the output is the same as rendering the whole recording.
+/// Only the "sequential" and "by-process" display modes can be streamed.
+pub fn render_streaming(
+    reader: impl Read,
     writer: impl Write,
     mode: DisplayMode,
+    window: usize,
+    options: &RenderOptions,
 ) -> Result<(), Error> {
-    ingester.prepare_for_rendering();
+    let mut events = TraceReader::detect(BufReader::new(reader))?;
+    let (root_pid, first_events) = read_root_start(&mut events)?;
+    let labels = &options.labels;
     match mode {
-        DisplayMode::Sequential => render_sequential(ingester, writer),
-        DisplayMode::ByProcess => render_by_process(ingester, writer),
-        DisplayMode::Mermaid => render_mermaid(ingester, writer),
+        DisplayMode::Sequential => {
+            let writer = ReorderWriter::new(JsonWriter::new(writer), window);
+            stream_events(root_pid, first_events, events, writer, labels)
+        }
+        DisplayMode::ByProcess => {
+            // The commands are labeled once each block is prepared, like a full render
+            let writer = ProcessBlockWriter::new(writer, window, labels.clone());
+            stream_events(root_pid, first_events, events, writer, &Labels::default())
+        }
+        _ => Err(anyhow!("the {mode} display mode can't be streamed")),
+    }
+}
+
+fn stream_events<R: BufRead>(
+    root_pid: i32,
+    first_events: Vec<Event>,
+    events: TraceReader<R>,
+    writer: impl EventWrite,
+    labels: &Labels,
+) -> Result<(), Error> {
+    let mut ingester = EventIngester::streaming(Some(root_pid), writer);
+    for mut event in first_events {
+        labels.apply(&mut event);
+        ingester.observe_event(event)?;
+    }
+    for maybe_event in events {
+        match maybe_event {
+            Ok(mut event) => {
+                labels.apply(&mut event);
+                ingester.observe_event(event)?;
+            }
+            Err(err) => {
+                eprintln!("failed to parse event: {err}");
+            }
+        }
+    }
+    ingester.finalize_writer()
+}
+
+/// Holds back a window of events so that they're written in order even if they
+/// arrive slightly out of order.
+struct ReorderWriter<W> {
+    inner: W,
+    window: usize,
+    pending: BinaryHeap<Reverse<Event>>,
+}
+
+impl<W: EventWrite> ReorderWriter<W> {
+    fn new(inner: W, window: usize) -> Self {
+        Self {
+            inner,
+            window,
+            pending: BinaryHeap::new(),
+        }
+    }
+}
+
+impl<W: EventWrite> EventWrite for ReorderWriter<W> {
+    fn write_raw(&mut self, _line: &[u8]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn write_event(&mut self, event: &Event) -> Result<(), Error> {
+        self.pending.push(Reverse(event.clone()));
+        if self.pending.len() > self.window {
+            if let Some(Reverse(event)) = self.pending.pop() {
+                self.inner.write_event(&event)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush()
+    }
+
+    fn finalize(&mut self) -> Result<(), Error> {
+        while let Some(Reverse(event)) = self.pending.pop() {
+            self.inner.write_event(&event)?;
+        }
+        self.inner.finalize()
+    }
+}
+
+/// Collects the events of each process and writes them as a block once the process
+/// exits, in the same order and prepared the same way as [`ByProcessRenderer`].
+///
+/// A finished process is held back until no running process started before it, and
+/// until there are more than a window of finished processes, so that processes whose
+/// first events arrive late still get written in order.
+struct ProcessBlockWriter<W> {
+    inner: W,
+    window: usize,
+    labels: Labels,
+    running: HashMap<i32, Vec<Event>>,
+    /// Finished processes keyed by the [`Event::order_key`] of their first event.
+    finished: BTreeMap<((u128, u128), i32), VecDeque<Event>>,
+}
+
+impl<W: Write> ProcessBlockWriter<W> {
+    fn new(inner: W, window: usize, labels: Labels) -> Self {
+        Self {
+            inner,
+            window,
+            labels,
+            running: HashMap::new(),
+            finished: BTreeMap::new(),
+        }
+    }
+
+    fn finish_process(&mut self, pid: i32) {
+        let Some(mut events) = self.running.remove(&pid) else {
+            return;
+        };
+        events.sort();
+        // The same steps as reading and preparing a whole recording
+        let mut events = collapse_buffer_execs(clean_exec_sequences(events));
+        for event in events.iter_mut() {
+            self.labels.apply(event);
+        }
+        if let Some(start) = events.front().map(Event::order_key) {
+            self.finished.insert((start, pid), events);
+        }
+    }
+
+    /// Writes the earliest finished process if none of the running processes started
+    /// before it, returning whether it was written.
+    fn write_earliest(&mut self) -> Result<bool, Error> {
+        let Some(((start, _), _)) = self.finished.first_key_value() else {
+            return Ok(false);
+        };
+        let running_since = self
+            .running
+            .values()
+            .filter_map(|events| events.iter().map(Event::order_key).min())
+            .min();
+        if running_since.is_some_and(|running_since| running_since < *start) {
+            return Ok(false);
+        }
+        if let Some(((_, pid), events)) = self.finished.pop_first() {
+            write_process_block(pid, &events, &mut self.inner)?;
+        }
+        Ok(true)
+    }
+}
+
+impl<W: Write> EventWrite for ProcessBlockWriter<W> {
+    fn write_raw(&mut self, _line: &[u8]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn write_event(&mut self, event: &Event) -> Result<(), Error> {
+        if !event.is_process_event() {
+            // Host samples and warnings don't belong to a process block
+            return Ok(());
+        }
+        let pid = event.pid();
+        self.running.entry(pid).or_default().push(event.clone());
+        if event.is_exit() {
+            self.finish_process(pid);
+            while self.finished.len() > self.window && self.write_earliest()? {}
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush().context("flush failed")
+    }
+
+    fn finalize(&mut self) -> Result<(), Error> {
+        let pids = self.running.keys().copied().collect::<Vec<_>>();
+        for pid in pids {
+            self.finish_process(pid);
+        }
+        while self.write_earliest()? {}
+        self.flush()
+    }
+}
+
+/// A recording that's ready to be rendered: the events of every process in the tree,
+/// along with everything else that's drawn next to them.
+#[derive(Debug)]
+pub struct ProcessTree {
+    pub root_pid: i32,
+    /// Processes outside of the tree of the root that were adopted into the
+    /// recording, which are rendered as roots of their own.
+    pub adopted_pids: Vec<i32>,
+    pub store: EventStore,
+    pub host_samples: Vec<Event>,
+    pub fork_storms: Vec<Event>,
+    /// Whether the root process is a shell that only wraps the recorded command.
+    pub shell_wrapper: bool,
+    /// The metadata of the recording, if it had any.
+    pub metadata: Option<Metadata>,
+}
+
+impl ProcessTree {
+    /// Prepares ingested events for rendering, replacing the commands that `labels`
+    /// has names for.
+    pub fn prepare<T>(mut ingester: EventIngester<T>, labels: &Labels) -> Result<Self, Error> {
+        ingester.prepare_for_rendering();
+        ingester.label_execs(labels);
+        let root_pid = ingester
+            .root_pid()
+            .ok_or(anyhow!("tried to render without a root PID"))?;
+        Ok(Self {
+            root_pid,
+            adopted_pids: ingester.adopted_pids().to_vec(),
+            host_samples: ingester.host_samples().to_vec(),
+            fork_storms: ingester.fork_storms().to_vec(),
+            shell_wrapper: ingester.has_shell_wrapper(),
+            metadata: ingester.metadata().cloned(),
+            store: ingester.into_tracked_events(),
+        })
+    }
+
+    /// Returns the processes that charts start from, the root first.
+    pub fn roots(&self) -> Vec<i32> {
+        let mut roots = vec![self.root_pid];
+        roots.extend_from_slice(&self.adopted_pids);
+        roots
     }
+
+    /// Returns when the root process started, which charts are relative to.
+    pub fn start_time(&self) -> Result<u128, Error> {
+        self.store
+            .pid_start_time(self.root_pid)
+            .ok_or(anyhow!("no events tracked for root PID"))
+    }
+}
+
+/// Renders a whole recording in a display mode.
+pub trait Renderer {
+    fn render(&self, tree: &ProcessTree, writer: &mut dyn Write) -> Result<(), Error>;
 }
 
-pub(crate) fn render_sequential<T>(
+/// Creates the renderer of a display mode with the options it was given.
+type NewRenderer = fn(&RenderOptions) -> Box<dyn Renderer>;
+
+/// The renderer of each display mode, keyed by the name of the display mode.
+const RENDERERS: &[(&str, NewRenderer)] = &[
+    ("sequential", |_| Box::new(SequentialRenderer)),
+    ("by-process", |options| {
+        Box::new(ByProcessRenderer {
+            tagger: options.tagger.clone(),
+        })
+    }),
+    ("mermaid", |options| {
+        Box::new(MermaidRenderer {
+            options: options.clone(),
+        })
+    }),
+    ("mermaid-md", |options| {
+        Box::new(MermaidMdRenderer {
+            options: options.clone(),
+        })
+    }),
+    ("gha-summary", |options| {
+        Box::new(GhaSummaryRenderer {
+            options: options.clone(),
+            baseline: None,
+        })
+    }),
+    ("d2", |options| {
+        Box::new(D2Renderer {
+            options: options.clone(),
+        })
+    }),
+    ("plantuml", |options| {
+        Box::new(PlantUmlRenderer {
+            options: options.clone(),
+        })
+    }),
+    ("table", |options| {
+        Box::new(TableRenderer {
+            sort_by: options.sort_by,
+            limit: options.limit,
+        })
+    }),
+];
+
+/// Returns the renderer of the display mode called `name`, if there is one.
+pub fn renderer(name: &str, options: &RenderOptions) -> Option<Box<dyn Renderer>> {
+    RENDERERS
+        .iter()
+        .find(|(other, _)| *other == name)
+        .map(|(_, new)| new(options))
+}
+
+/// Render ingested events.
+pub fn render_events<T>(
     ingester: EventIngester<T>,
     mut writer: impl Write,
+    mode: DisplayMode,
+    options: &RenderOptions,
 ) -> Result<(), Error> {
-    for event in ingester.into_tracked_events().events_ordered() {
-        serde_json::to_writer(&mut writer, &event).context("failed to write event")?;
-        writer.write(b"\n").context("write failed")?;
+    let renderer: Box<dyn Renderer> = match mode {
+        #[cfg(feature = "fs")]
+        DisplayMode::Exec(ref program) => Box::new(ExecRenderer::new(program)),
+        _ => renderer(&mode.to_string(), options)
+            .ok_or(anyhow!("the {mode} display mode has no renderer"))?,
+    };
+    let tree = ProcessTree::prepare(ingester, &options.labels)?;
+    renderer.render(&tree, &mut writer)
+}
+
+/// Writes every event as a line of JSON in the order they were received ("sequential").
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SequentialRenderer;
+
+impl Renderer for SequentialRenderer {
+    fn render(&self, tree: &ProcessTree, writer: &mut dyn Write) -> Result<(), Error> {
+        write_ordered(
+            &tree.store,
+            &tree.host_samples,
+            &tree.fork_storms,
+            JsonWriter::new(writer),
+        )
     }
-    Ok(())
 }
 
-fn render_by_process<T>(ingester: EventIngester<T>, mut writer: impl Write) -> Result<(), Error> {
-    for (pid, buffer) in ingester.into_tracked_events().into_pid_buffers_ordered() {
-        let header = extract_displayable_buffer_header(pid, &buffer)
-            .context("failed to extract header for PID {pid}")?;
-        writer
-            .write_all(header.as_bytes())
-            .context("write failed")?;
-        writer.write(b"\n").context("write failed")?;
-        for event in buffer.iter() {
-            serde_json::to_writer(&mut writer, event).context("failed to write event")?;
-            writer.write(b"\n").context("write failed")?;
+/// Writes the tracked events in the order they were received, then finalizes the writer.
+pub fn render_sequential<T>(
+    ingester: EventIngester<T>,
+    writer: impl EventWrite,
+) -> Result<(), Error> {
+    write_ordered(
+        ingester.tracked_events(),
+        ingester.host_samples(),
+        ingester.fork_storms(),
+        writer,
+    )
+}
+
+/// Writes the events in `store` in order, with the host samples and fork storm
+/// warnings between them, then finalizes the writer.
+pub(crate) fn write_ordered(
+    store: &EventStore,
+    host_samples: &[Event],
+    fork_storms: &[Event],
+    mut writer: impl EventWrite,
+) -> Result<(), Error> {
+    let mut extra_events = host_samples.iter().chain(fork_storms).collect::<Vec<_>>();
+    extra_events.sort();
+    let mut extra_events = extra_events.into_iter().peekable();
+    let mut events = store.iter_ordered().peekable();
+    // A recording has to start with the fork of the root process, so samples and
+    // warnings from before it are dropped
+    if let Some(first) = events.peek() {
+        let first_seq = first.seq();
+        while extra_events
+            .next_if(|extra| extra.seq() < first_seq)
+            .is_some()
+        {}
+    }
+    for event in events {
+        while let Some(extra) = extra_events.next_if(|extra| extra.seq() < event.seq()) {
+            writer.write_event(extra)?;
+        }
+        writer.write_event(event)?;
+    }
+    for extra in extra_events {
+        writer.write_event(extra)?;
+    }
+    writer.finalize()
+}
+
+/// Writes the events of each process after a header that describes it, in the order
+/// the processes started ("by-process").
+#[derive(Debug, Clone, Default)]
+pub struct ByProcessRenderer {
+    /// Write the processes of each tag together, under a "=== <tag> ===" line.
+    pub tagger: Option<Tagger>,
+}
+
+impl Renderer for ByProcessRenderer {
+    fn render(&self, tree: &ProcessTree, mut writer: &mut dyn Write) -> Result<(), Error> {
+        let Some(ref tagger) = self.tagger else {
+            for (pid, buffer) in tree.store.pid_buffers_ordered() {
+                write_process_block(pid, buffer, &mut writer)?;
+            }
+            return Ok(());
+        };
+        let cwd = tree.metadata.as_ref().and_then(|m| m.cwd.as_deref());
+        let tags = tagger.tags(&tree.store, cwd);
+        let mut groups: BTreeMap<&str, Vec<(i32, &VecDeque<Event>)>> = BTreeMap::new();
+        for (pid, buffer) in tree.store.pid_buffers_ordered() {
+            let tag = tags.get(&pid).map(String::as_str).unwrap_or(UNTAGGED);
+            groups.entry(tag).or_default().push((pid, buffer));
+        }
+        for (tag, processes) in groups {
+            writeln!(writer, "=== {tag} ===\n").context("write failed")?;
+            for (pid, buffer) in processes {
+                write_process_block(pid, buffer, &mut writer)?;
+            }
         }
+        Ok(())
+    }
+}
+
+/// Writes a header describing the process followed by its events and a blank line.
+fn write_process_block(
+    pid: i32,
+    buffer: &VecDeque<Event>,
+    mut writer: impl Write,
+) -> Result<(), Error> {
+    let mut header = extract_displayable_buffer_header(pid, buffer)
+        .context("failed to extract header for PID {pid}")?;
+    if !buffer.back().is_some_and(|event| event.is_exit()) {
+        header.push_str(" (still running)");
+    }
+    // Whether it was slow because it was computing or because it was waiting
+    let usage = buffer.iter().find_map(|event| match event {
+        Event::Usage { usage, .. } => Some(usage),
+        _ => None,
+    });
+    if let (Some(usage), Some(first), Some(last)) = (usage, buffer.front(), buffer.back()) {
+        let wall = last.timestamp().saturating_sub(first.timestamp());
+        if let Some(share) = usage.cpu_percent(wall) {
+            header.push_str(&format!(
+                " (cpu {} of {}, {share}%)",
+                format_nanos(usage.cpu_ns()),
+                format_nanos(wall)
+            ));
+        }
+    }
+    writer
+        .write_all(header.as_bytes())
+        .context("write failed")?;
+    writer.write(b"\n").context("write failed")?;
+    for event in buffer.iter() {
+        serde_json::to_writer(&mut writer, event).context("failed to write event")?;
         writer.write(b"\n").context("write failed")?;
     }
+    writer.write(b"\n").context("write failed")?;
     Ok(())
 }
 
@@ -109,14 +1025,9 @@ fn extract_displayable_buffer_header(pid: i32, events: &VecDeque<Event>) -> Resu
     if n_events == 0 {
         Err(anyhow!("buffer had no events"))
     } else if n_events == 1 {
-        if let Event::Fork {
-            parent_pid,
-            child_pid,
-            ..
-        } = events[0]
-        {
+        if let Event::Fork { .. } = events[0] {
             // A single fork event, display the fork info
-            Ok(format!("PID {child_pid}, forked from {parent_pid}"))
+            Ok(fork_header(&events[0]))
         } else if let Event::ExecFull {
             ref filename,
             ref args,
@@ -126,7 +1037,8 @@ fn extract_displayable_buffer_header(pid: i32, events: &VecDeque<Event>) -> Resu
             // A single exec event, display the exec args
             Ok(format!("PID {pid}: {}", exec_command(filename, args)))
         } else {
-            unreachable!("all event buffers should begin with either fork or exec");
+            // e.g. an adopted process that was only seen leaving its session
+            Ok(format!("PID {pid}"))
         }
     } else if matches!(events[0], Event::Fork { .. }) && matches!(events[1], Event::ExecFull { .. })
     {
@@ -141,32 +1053,179 @@ fn extract_displayable_buffer_header(pid: i32, events: &VecDeque<Event>) -> Resu
         Ok(format!("PID {pid}: {}", exec_command(filename, args)))
     } else if matches!(events[0], Event::Fork { .. }) {
         // A fork followed by something other than exec, display the fork info
-        let Event::Fork {
-            parent_pid,
-            child_pid,
-            ..
-        } = events[0]
-        else {
-            unreachable!("just checked that this was a fork");
-        };
-        Ok(format!("PID {child_pid}, forked from {parent_pid}"))
+        Ok(fork_header(&events[0]))
     } else {
         // No idea what happened here
         Ok(format!("PID {pid}"))
     }
 }
 
-fn render_mermaid<T>(ingester: EventIngester<T>, mut writer: impl Write) -> Result<(), Error> {
-    // Get anything out of the ingester or event store ahead of time because we're about
-    // to consume it
-    let root_pid = ingester
-        .root_pid()
-        .ok_or(anyhow!("tried to render without a root PID"))?;
-    let initial_time = ingester
-        .tracked_events()
-        .pid_start_time(root_pid)
-        .ok_or(anyhow!("no events tracked for root PID"))?;
+/// Describes a fork for the header of a process that didn't exec right away, with the
+/// name it inherited from its parent if it's known.
+fn fork_header(fork: &Event) -> String {
+    match fork {
+        Event::Fork {
+            parent_pid,
+            child_pid,
+            comm: Some(comm),
+            ..
+        } => format!("PID {child_pid} ({comm}), forked from {parent_pid}"),
+        Event::Fork {
+            parent_pid,
+            child_pid,
+            ..
+        } => format!("PID {child_pid}, forked from {parent_pid}"),
+        event => format!("PID {}", event.pid()),
+    }
+}
+
+/// Names a process that never exec'd, e.g. `<postgres>` for a worker forked by
+/// `postgres`, or `<fork>` if the name it inherited isn't known.
+fn fork_name(fork: Option<&Event>) -> String {
+    match fork {
+        Some(Event::Fork {
+            comm: Some(comm), ..
+        }) => format!("<{comm}>"),
+        _ => "<fork>".to_string(),
+    }
+}
+
+/// Renders a Gantt chart in Mermaid syntax ("mermaid").
+#[derive(Debug, Clone, Default)]
+pub struct MermaidRenderer {
+    pub options: RenderOptions,
+}
+
+impl Renderer for MermaidRenderer {
+    fn render(&self, tree: &ProcessTree, writer: &mut dyn Write) -> Result<(), Error> {
+        render_mermaid(tree, writer, &self.options)
+    }
+}
+
+fn render_mermaid(
+    tree: &ProcessTree,
+    mut writer: impl Write,
+    options: &RenderOptions,
+) -> Result<(), Error> {
+    let initial_time = tree.start_time()?;
+    let hidden_pid = hidden_wrapper_pid(tree, options);
+    let mut host_lanes = host_sample_lanes(&tree.host_samples);
+    let memory = rss_lane(&tree.store);
+    if !memory.is_empty() {
+        host_lanes.push(("process memory", memory));
+    }
+    let daemon_pids = tree.store.daemon_pids();
+    let pipelines = tree
+        .store
+        .pipelines()
+        .into_iter()
+        .map(|pids| (pipeline_label(&tree.store, &pids), pids))
+        .collect::<Vec<_>>();
+
+    write_mermaid_header(&mut writer)?;
+
+    // Sections are only needed to tell the processes apart from the other lanes
+    let sectioned = !host_lanes.is_empty() || !daemon_pids.is_empty();
+    if sectioned {
+        for (name, spans) in host_lanes.iter() {
+            writer
+                .write_all(format!("    section {name}\n").as_bytes())
+                .context("write failed")?;
+            for span in spans.iter() {
+                render_single_span(span, &mut writer, initial_time, None)?;
+            }
+        }
+        writer
+            .write_all("    section processes\n".as_bytes())
+            .context("write failed")?;
+    }
+
+    let items = mermaid_items(tree, &tree.roots(), options)?;
+    // The commands of a pipeline run side by side, so they're grouped together in a
+    // section of their own where the first of them would have been
+    let item_of = |pid: i32| {
+        items
+            .iter()
+            .find(|(other, _)| *other == pid)
+            .map(|(_, item)| item)
+    };
+    // Daemons are detached from the tree, so they get a lane of their own instead of
+    // dangling off of the process that started them
+    let mut daemons = vec![];
+    for (pid, item) in items.iter() {
+        if Some(*pid) == hidden_pid {
+            continue;
+        }
+        if let Some((label, pids)) = pipelines.iter().find(|(_, pids)| pids.contains(pid)) {
+            if pids[0] != *pid {
+                continue;
+            }
+            writer
+                .write_all(format!("    section {label}\n").as_bytes())
+                .context("failed writing pipeline header")?;
+            for item in pids.iter().filter_map(|pid| item_of(*pid)) {
+                render_item(item, &mut writer, initial_time, options.link_details)?;
+            }
+            writer
+                .write_all("    section other\n".as_bytes())
+                .context("write failed")?;
+        } else if daemon_pids.contains(pid) {
+            daemons.push(item);
+        } else {
+            render_item(item, &mut writer, initial_time, options.link_details)?;
+        }
+    }
+    if !daemons.is_empty() {
+        writer
+            .write_all("    section detached\n".as_bytes())
+            .context("write failed")?;
+        for item in daemons {
+            render_item(item, &mut writer, initial_time, options.link_details)?;
+        }
+    }
 
+    Ok(())
+}
+
+/// Renders a Mermaid chart in a ```mermaid fence, ready to be pasted into a GitHub
+/// issue or pull request, optionally after a heading that describes the recording
+/// ("mermaid-md").
+#[derive(Debug, Clone, Default)]
+pub struct MermaidMdRenderer {
+    pub options: RenderOptions,
+}
+
+impl Renderer for MermaidMdRenderer {
+    fn render(&self, tree: &ProcessTree, mut writer: &mut dyn Write) -> Result<(), Error> {
+        let mut markdown = String::new();
+        if self.options.heading {
+            let store = &tree.store;
+            let command = store
+                .events_of(tree.root_pid)
+                .and_then(|buffer| {
+                    buffer.iter().find_map(|event| match event {
+                        Event::ExecFull { args, .. } => Some(args.joined()),
+                        _ => None,
+                    })
+                })
+                .unwrap_or_else(|| "(unknown)".to_string());
+            markdown.push_str(&format!("## proctrace: {}\n\n", markdown_code(&command)));
+            markdown.push_str(&format!(
+                "Ran for **{}** across **{}** processes.\n\n",
+                format_nanos(RunSummary::from_store(store).total),
+                store.len()
+            ));
+        }
+        markdown.push_str("```mermaid\n");
+        writer
+            .write_all(markdown.as_bytes())
+            .context("write failed")?;
+        render_mermaid(tree, &mut writer, &self.options)?;
+        writer.write_all(b"```\n").context("write failed")
+    }
+}
+
+fn write_mermaid_header(mut writer: impl Write) -> Result<(), Error> {
     writer
         .write_all("gantt\n".as_bytes())
         .context("write failed")?;
@@ -182,23 +1241,714 @@ fn render_mermaid<T>(ingester: EventIngester<T>, mut writer: impl Write) -> Resu
     writer
         .write_all("    todayMarker off\n\n".as_bytes())
         .context("write failed")?; // time has no meaning
+    Ok(())
+}
 
-    for (pid, mut buffer) in ingester
-        .into_tracked_events()
-        .buffers_depth_first_fork_order(root_pid)?
-    {
-        let item = parse_buffer(buffer.make_contiguous())
-            .with_context(|| format!("failed to parse buffer for PID {pid}"))?;
-        render_item(&item, &mut writer, initial_time)?;
+/// Turns the buffer of each process into spans, in depth-first fork order.
+/// Returns the shell that `record --shell` ran the command with if it should be left
+/// out of a chart.
+///
+/// A shell that exec'd the command in place of itself (e.g. `sh -c 'make'`) is the
+/// command, so it's only hidden if it stayed a shell.
+fn hidden_wrapper_pid(tree: &ProcessTree, options: &RenderOptions) -> Option<i32> {
+    (options.hide_shell_wrapper && tree.shell_wrapper)
+        .then_some(tree.root_pid)
+        .filter(|pid| {
+            tree.store.events_of(*pid).is_some_and(|buffer| {
+                buffer.iter().filter(|event| event.is_exec_full()).count() <= 1
+            })
+        })
+}
+
+/// Returns the spans of every process in the order they're charted, for the chart
+/// formats that only draw bars.
+fn gantt_spans(tree: &ProcessTree, options: &RenderOptions) -> Result<Vec<Span>, Error> {
+    let hidden_pid = hidden_wrapper_pid(tree, options);
+    let spans = mermaid_items(tree, &tree.roots(), options)?
+        .into_iter()
+        .filter(|(pid, _)| Some(*pid) != hidden_pid)
+        .flat_map(|(_, item)| match item {
+            MermaidItem::Single(span) | MermaidItem::Collapsed(span, _) => vec![span],
+            MermaidItem::ExecGroup(spans) => spans,
+        })
+        .collect();
+    Ok(spans)
+}
+
+fn mermaid_items(
+    tree: &ProcessTree,
+    roots: &[i32],
+    options: &RenderOptions,
+) -> Result<Vec<(i32, MermaidItem)>, Error> {
+    let mut items = vec![];
+    // Processes that never exited run until the end of the recording
+    let end_of_trace = tree
+        .store
+        .iter()
+        .flat_map(|(_, buffer)| buffer.iter())
+        .chain(&tree.host_samples)
+        .map(|event| event.timestamp())
+        .max()
+        .unwrap_or(0);
+    let root_credentials = tree.store.credentials_of(tree.root_pid);
+    for (pid, buffer) in tree.store.buffers_depth_first_fork_order(roots)? {
+        let events = buffer.iter().collect::<Vec<_>>();
+        let mut item = if options.collapse_exec_chains {
+            collapse_exec_chain(&events)
+        } else {
+            parse_buffer(&events)
+        }
+        .with_context(|| format!("failed to parse buffer for PID {pid}"))?;
+        if !events.last().is_some_and(|event| event.is_exit()) {
+            item.run_until(end_of_trace);
+        }
+        if let Some(threshold) = options.highlight_over {
+            item.highlight_over(threshold);
+        }
+        if let Some(hz) = tree
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.cpu_sample_hz)
+        {
+            let samples = events
+                .iter()
+                .filter(|event| matches!(event, Event::CpuSample { .. }))
+                .map(|event| event.timestamp())
+                .collect::<Vec<_>>();
+            item.shade_cpu(&samples, hz);
+        } else if let Some(usage) = events.iter().find_map(|event| match event {
+            Event::Usage { usage, .. } => Some(usage),
+            _ => None,
+        }) {
+            item.shade_cpu_time(usage);
+        }
+        let peak_rss_kb = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::RssSample { rss_kb, .. } => Some(*rss_kb),
+                _ => None,
+            })
+            .max();
+        if let Some(rss_kb) = peak_rss_kb {
+            item.tag(&format!("[rss {}]", format_kb(rss_kb)));
+        }
+        // Sandboxes otherwise look like any other fork, as does everything in them
+        let namespaces = tree.store.namespaces_of(pid);
+        if !namespaces.is_empty() {
+            item.tag(&format!(
+                "[ns: {}]",
+                namespaces.into_iter().collect::<Vec<_>>().join(",")
+            ));
+        }
+        // Other users' processes, e.g. under `sudo` or children of a shared parent
+        if let Some(credentials) = tree
+            .store
+            .credentials_of(pid)
+            .filter(|credentials| root_credentials.is_some_and(|root| root != *credentials))
+        {
+            item.tag(&format!("[user {credentials}]"));
+        }
+        items.push((pid, item));
     }
+    Ok(items)
+}
 
+/// Renders two recordings on the same Mermaid timeline for comparing them before and
+/// after a change, with each one aligned so that its root process starts at t=0.
+///
+/// Processes are paired up by their command lines in the order they were forked, and
+/// each pair is rendered in adjacent lanes. The spans of `before` are marked as done so
+/// that they're drawn in a different color.
+pub fn render_overlay(
+    before: impl Read,
+    after: impl Read,
+    mut writer: impl Write,
+    options: &RenderOptions,
+) -> Result<(), Error> {
+    let (before_time, before) = overlay_processes(before, "before", options)
+        .context("failed to read the recording from before")?;
+    let (after_time, mut after) = overlay_processes(after, "after", options)
+        .context("failed to read the recording from after")?;
+    write_mermaid_header(&mut writer)?;
+    for (command, spans) in before {
+        for span in spans.iter() {
+            render_single_span(span, &mut writer, before_time, None)?;
+        }
+        if let Some(idx) = after.iter().position(|(other, _)| *other == command) {
+            for span in after.remove(idx).1.iter() {
+                render_single_span(span, &mut writer, after_time, None)?;
+            }
+        }
+    }
+    // Processes that only exist after the change
+    for (_, spans) in after {
+        for span in spans.iter() {
+            render_single_span(span, &mut writer, after_time, None)?;
+        }
+    }
     Ok(())
 }
 
+/// The spans of each process in a recording, keyed by its command line.
+type CommandSpans = Vec<(String, Vec<Span>)>;
+
+/// Reads a recording for an overlay, returning the start time of its root process
+/// and the spans of each process keyed by its command line.
+fn overlay_processes(
+    reader: impl Read,
+    name: &str,
+    options: &RenderOptions,
+) -> Result<(u128, CommandSpans), Error> {
+    let tree = ProcessTree::prepare(read_events(reader)?, &options.labels)?;
+    let initial_time = tree.start_time()?;
+    let processes = mermaid_items(&tree, &tree.roots(), options)?
+        .into_iter()
+        .map(|(_, item)| {
+            let mut spans = match item {
+                MermaidItem::Single(span) | MermaidItem::Collapsed(span, _) => vec![span],
+                MermaidItem::ExecGroup(spans) => spans,
+            };
+            // PIDs differ between recordings, so they're left out of the key
+            let command = spans
+                .iter()
+                .map(|span| {
+                    span.label
+                        .split_once("] ")
+                        .map(|(_, command)| command)
+                        .unwrap_or(&span.label)
+                })
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            for span in spans.iter_mut() {
+                span.label = format!("{name} {}", span.label);
+                span.dimmed = name == "before";
+            }
+            (command, spans)
+        })
+        .collect();
+    Ok((initial_time, processes))
+}
+
+/// Labels a pipeline with the commands it's made of, e.g. `cat | grep | sort`.
+fn pipeline_label(store: &EventStore, pids: &[i32]) -> String {
+    pids.iter()
+        .map(|pid| {
+            store
+                .iter()
+                .find(|(other, _)| other == pid)
+                .map(|(_, buffer)| {
+                    buffer
+                        .iter()
+                        .rev()
+                        .find_map(|event| match event {
+                            Event::ExecFull { filename, .. } => Some(command_name(filename)),
+                            _ => None,
+                        })
+                        .unwrap_or_else(|| fork_name(buffer.front()))
+                })
+                .unwrap_or_else(|| fork_name(None))
+        })
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
+
+/// The number of processes listed in the "Slowest processes" table of a summary.
+const SUMMARY_SLOWEST_PROCESSES: usize = 10;
+
+/// Renders a Markdown summary suitable for `$GITHUB_STEP_SUMMARY` ("gha-summary").
+#[derive(Debug, Clone, Default)]
+pub struct GhaSummaryRenderer {
+    pub options: RenderOptions,
+    /// A summary of an earlier run to compare against, and the conditions under which
+    /// a `::warning::` annotation is printed to stderr.
+    pub baseline: Option<(RunSummary, Option<FailIf>)>,
+}
+
+impl Renderer for GhaSummaryRenderer {
+    fn render(&self, tree: &ProcessTree, writer: &mut dyn Write) -> Result<(), Error> {
+        let baseline = self
+            .baseline
+            .as_ref()
+            .map(|(baseline, warn_if)| (baseline, warn_if.as_ref()));
+        render_gha_summary(tree, writer, baseline, &self.options)
+    }
+}
+
+fn render_gha_summary(
+    tree: &ProcessTree,
+    mut writer: impl Write,
+    baseline: Option<(&RunSummary, Option<&FailIf>)>,
+    options: &RenderOptions,
+) -> Result<(), Error> {
+    let current = RunSummary::from_store(&tree.store);
+    let mut processes = vec![];
+    let mut failures = vec![];
+    for (pid, buffer) in tree.store.iter() {
+        let (Some(first), Some(last)) = (buffer.front(), buffer.back()) else {
+            continue;
+        };
+        let command = buffer
+            .iter()
+            .rev()
+            .find_map(|event| match event {
+                Event::ExecFull { args, .. } => Some(args.joined()),
+                _ => None,
+            })
+            .or_else(|| first.fork_parent().map(|ppid| format!("(fork of {ppid})")))
+            .unwrap_or_else(|| "(unknown)".to_string());
+        let command = markdown_code(&command);
+        if let Some(code) = last.exit_code().filter(|code| *code != 0) {
+            failures.push((pid, command.clone(), describe_exit_code(code)));
+        }
+        processes.push((
+            pid,
+            command,
+            last.timestamp().saturating_sub(first.timestamp()),
+        ));
+    }
+    processes.sort_by_key(|(_, _, duration)| std::cmp::Reverse(*duration));
+
+    let mut summary = String::from("## proctrace summary\n\n");
+    summary.push_str(&format!(
+        "Ran for **{}** across **{}** processes.\n\n",
+        format_nanos(current.total),
+        processes.len()
+    ));
+    summary
+        .push_str("### Slowest processes\n\n| PID | Command | Duration |\n| --- | --- | --- |\n");
+    for (pid, command, duration) in processes.iter().take(SUMMARY_SLOWEST_PROCESSES) {
+        summary.push_str(&format!(
+            "| {pid} | {command} | {} |\n",
+            format_nanos(*duration)
+        ));
+    }
+    summary.push_str("\n### Failures\n\n");
+    if failures.is_empty() {
+        summary.push_str("No processes failed.\n");
+    } else {
+        summary.push_str("| PID | Command | Status |\n| --- | --- | --- |\n");
+        for (pid, command, status) in failures.iter() {
+            summary.push_str(&format!("| {pid} | {command} | {status} |\n"));
+        }
+    }
+    if let Some((baseline, _)) = baseline {
+        summary.push_str("\n### Compared to baseline\n\n```\n");
+        summary.push_str(&comparison_table(baseline, &current));
+        summary.push_str("```\n");
+    }
+    summary.push_str("\n### Timeline\n\n```mermaid\n");
+    writer
+        .write_all(summary.as_bytes())
+        .context("write failed")?;
+    render_mermaid(tree, &mut writer, options)?;
+    writer.write_all(b"```\n").context("write failed")?;
+
+    if let Some((baseline, Some(warn_if))) = baseline {
+        for condition in warn_if.failures(baseline, &current) {
+            eprintln!("::warning title=proctrace::Regression exceeded threshold: {condition}");
+        }
+    }
+    Ok(())
+}
+
+/// Renders a PlantUML Gantt chart ("plantuml").
+///
+/// PlantUML can't schedule anything shorter than a day, so every day of the chart is
+/// a millisecond of the recording.
+#[derive(Debug, Clone, Default)]
+pub struct PlantUmlRenderer {
+    pub options: RenderOptions,
+}
+
+impl Renderer for PlantUmlRenderer {
+    fn render(&self, tree: &ProcessTree, writer: &mut dyn Write) -> Result<(), Error> {
+        let initial_time = tree.start_time()?;
+        let mut chart = String::from("@startgantt\n' One day is one millisecond\n");
+        for span in gantt_spans(tree, &self.options)? {
+            let start = span.start.saturating_sub(initial_time) / 1_000_000;
+            let duration = (span.duration() / 1_000_000).max(1);
+            // Square brackets delimit the name of a task
+            let mut name = clean_mermaid_label(&span.label)
+                .replace('[', "(")
+                .replace(']', ")");
+            if span.running {
+                name.push_str(" (still running)");
+            }
+            let mut line = format!("[{name}] starts D+{start} and lasts {duration} days");
+            if span.highlight {
+                line.push_str(" and is colored in Red");
+            } else if span.dimmed {
+                line.push_str(" and is colored in LightGray");
+            }
+            chart.push_str(&line);
+            chart.push('\n');
+        }
+        chart.push_str("@endgantt\n");
+        writer.write_all(chart.as_bytes()).context("write failed")
+    }
+}
+
+/// How wide the longest span of a D2 chart is, in pixels.
+const D2_CHART_WIDTH: u128 = 1000;
+
+/// Renders a timeline in D2 ("d2").
+///
+/// D2 doesn't have Gantt charts, so each span is a row of a grid made of an invisible
+/// box as wide as the time before the span started and a box as wide as the span.
+#[derive(Debug, Clone, Default)]
+pub struct D2Renderer {
+    pub options: RenderOptions,
+}
+
+impl Renderer for D2Renderer {
+    fn render(&self, tree: &ProcessTree, writer: &mut dyn Write) -> Result<(), Error> {
+        let initial_time = tree.start_time()?;
+        let spans = gantt_spans(tree, &self.options)?;
+        let end = spans
+            .iter()
+            .map(|span| span.stop.saturating_sub(initial_time))
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        let pixels = |nanos: u128| (nanos * D2_CHART_WIDTH / end).max(1);
+        let mut chart = format!(
+            "# {} of the recording per {D2_CHART_WIDTH}px\ntimeline: {{\n  label: \"\"\n  grid-columns: 1\n  grid-gap: 4\n",
+            format_nanos(end)
+        );
+        for (idx, span) in spans.iter().enumerate() {
+            let mut label = clean_mermaid_label(&span.label);
+            if span.running {
+                label.push_str(" (still running)");
+            }
+            label.push_str(&format!(" ({})", format_nanos(span.duration())));
+            let fill = if span.highlight {
+                "#f4a4a4"
+            } else if span.dimmed {
+                "#d9d9d9"
+            } else {
+                "#a4c8f4"
+            };
+            let start = span.start.saturating_sub(initial_time);
+            chart.push_str(&format!(
+                "  row{idx}: {{\n    label: \"\"\n    grid-columns: 2\n    grid-gap: 0\n    style.stroke-width: 0\n    style.fill: transparent\n"
+            ));
+            chart.push_str(&format!(
+                "    before: {{label: \"\"; width: {}; height: 20; style.opacity: 0}}\n",
+                pixels(start)
+            ));
+            chart.push_str(&format!(
+                "    span: {{label: {}; label.near: outside-right-center; width: {}; height: 20; style.fill: \"{fill}\"}}\n  }}\n",
+                d2_string(&label),
+                pixels(span.duration())
+            ));
+        }
+        chart.push_str("}\n");
+        writer.write_all(chart.as_bytes()).context("write failed")
+    }
+}
+
+/// Quotes a label for D2.
+fn d2_string(label: &str) -> String {
+    format!("\"{}\"", label.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Renders a row of aligned columns for each process ("table").
+///
+/// Times are in milliseconds without a unit and the command line is the last column,
+/// so that the rows can be split on whitespace, e.g. with `awk`.
+#[derive(Debug, Clone, Default)]
+pub struct TableRenderer {
+    pub sort_by: TableSort,
+    pub limit: Option<usize>,
+}
+
+/// A row of the "table" display mode.
+struct TableRow {
+    pid: i32,
+    ppid: Option<i32>,
+    start: u128,
+    duration: u128,
+    cpu: Option<u128>,
+    exit: String,
+    command: String,
+}
+
+impl Renderer for TableRenderer {
+    fn render(&self, tree: &ProcessTree, writer: &mut dyn Write) -> Result<(), Error> {
+        let initial_time = tree.start_time()?;
+        let sample_period = tree
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.cpu_sample_hz)
+            .map(|hz| 1_000_000_000 / hz.max(1) as u128);
+        let mut rows = vec![];
+        for (pid, buffer) in tree.store.iter() {
+            let (Some(first), Some(last)) = (buffer.front(), buffer.back()) else {
+                continue;
+            };
+            // The CPU time that was recorded when the process exited is exact, samples
+            // are the next best thing
+            let usage = buffer.iter().find_map(|event| match event {
+                Event::Usage { usage, .. } => Some(usage.cpu_ns()),
+                _ => None,
+            });
+            let sampled = sample_period.map(|period| {
+                let samples = buffer
+                    .iter()
+                    .filter(|event| matches!(event, Event::CpuSample { .. }))
+                    .count() as u128;
+                samples * period
+            });
+            let exit = match last.exit_code() {
+                Some(code) if code & 0x7f == 0 => ((code >> 8) & 0xff).to_string(),
+                Some(code) => signal_name(code & 0x7f),
+                None if last.is_exit() => "?".to_string(),
+                None => "running".to_string(),
+            };
+            rows.push(TableRow {
+                pid,
+                ppid: first.fork_parent(),
+                start: first.timestamp().saturating_sub(initial_time),
+                duration: last.timestamp().saturating_sub(first.timestamp()),
+                cpu: usage.or(sampled),
+                exit,
+                command: command_line(buffer),
+            });
+        }
+        match self.sort_by {
+            TableSort::Duration => {
+                rows.sort_by_key(|row| (std::cmp::Reverse(row.duration), row.start, row.pid))
+            }
+            TableSort::Start => rows.sort_by_key(|row| (row.start, row.pid)),
+            TableSort::Pid => rows.sort_by_key(|row| row.pid),
+        }
+        rows.truncate(self.limit.unwrap_or(usize::MAX));
+
+        let millis = |nanos: u128| format!("{:.3}", nanos as f64 / 1_000_000.0);
+        let mut table = format!(
+            "{:>8}  {:>8}  {:>12}  {:>12}  {:>12}  {:>8}  {}\n",
+            "pid", "ppid", "start_ms", "duration_ms", "cpu_ms", "exit", "command"
+        );
+        for row in rows {
+            table.push_str(&format!(
+                "{:>8}  {:>8}  {:>12}  {:>12}  {:>12}  {:>8}  {}\n",
+                row.pid,
+                row.ppid.map_or("-".to_string(), |ppid| ppid.to_string()),
+                millis(row.start),
+                millis(row.duration),
+                row.cpu.map_or("-".to_string(), millis),
+                row.exit,
+                row.command.trim()
+            ));
+        }
+        writer.write_all(table.as_bytes()).context("write failed")
+    }
+}
+
+/// Formats text as inline code that's safe to put in a Markdown table.
+fn markdown_code(text: &str) -> String {
+    let text = text.trim().replace('`', "'").replace('|', "\\|");
+    format!("`{text}`")
+}
+
 #[derive(Debug)]
 enum MermaidItem {
     Single(Span),
     ExecGroup(Vec<Span>),
+    /// The last exec of a process, along with the names of the wrappers that
+    /// exec'd it.
+    Collapsed(Span, Vec<String>),
+}
+
+impl MermaidItem {
+    /// Stretches the last span to `end` for a process that never exited.
+    fn run_until(&mut self, end: u128) {
+        let span = match self {
+            MermaidItem::Single(span) | MermaidItem::Collapsed(span, _) => Some(span),
+            MermaidItem::ExecGroup(spans) => spans.last_mut(),
+        };
+        if let Some(span) = span {
+            span.stop = span.stop.max(end);
+            span.running = true;
+        }
+    }
+
+    /// Highlights the spans that lasted longer than `threshold` nanoseconds.
+    fn highlight_over(&mut self, threshold: u128) {
+        let spans = match self {
+            MermaidItem::Single(span) | MermaidItem::Collapsed(span, _) => {
+                std::slice::from_mut(span)
+            }
+            MermaidItem::ExecGroup(spans) => spans.as_mut_slice(),
+        };
+        for span in spans {
+            span.highlight = span.duration() > threshold;
+        }
+    }
+
+    /// Labels every span with the share of its time that the process spent on a CPU,
+    /// going by `samples` that were taken `hz` times a second, and dims the spans that
+    /// were mostly waiting.
+    ///
+    /// Spans too short to have been sampled a few times are left alone, since a sample
+    /// more or less would change their share a lot.
+    fn shade_cpu(&mut self, samples: &[u128], hz: u32) {
+        let period = 1_000_000_000 / hz.max(1) as u128;
+        let spans = match self {
+            MermaidItem::Single(span) | MermaidItem::Collapsed(span, _) => {
+                std::slice::from_mut(span)
+            }
+            MermaidItem::ExecGroup(spans) => spans.as_mut_slice(),
+        };
+        for span in spans {
+            if span.duration() < MIN_SAMPLE_PERIODS * period {
+                continue;
+            }
+            let taken = samples
+                .iter()
+                .filter(|timestamp| (span.start..=span.stop).contains(*timestamp))
+                .count() as u128;
+            // Threads are sampled separately, so a busy process can use over 100%
+            let share = taken * period * 100 / span.duration();
+            span.label.push_str(&format!(" [cpu {share}%]"));
+            span.dimmed = share < IDLE_CPU_PERCENT;
+        }
+    }
+
+    /// Labels every span with the share of the time the process ran that it spent on a
+    /// CPU, going by the CPU time it had when it exited, and dims the spans of processes
+    /// that were mostly waiting.
+    ///
+    /// The CPU time is only known for the whole process, so every span of a process
+    /// that exec'd several times gets the same share.
+    fn shade_cpu_time(&mut self, usage: &ResourceUsage) {
+        let spans = match self {
+            MermaidItem::Single(span) | MermaidItem::Collapsed(span, _) => {
+                std::slice::from_mut(span)
+            }
+            MermaidItem::ExecGroup(spans) => spans.as_mut_slice(),
+        };
+        let (Some(start), Some(stop)) = (
+            spans.iter().map(|span| span.start).min(),
+            spans.iter().map(|span| span.stop).max(),
+        ) else {
+            return;
+        };
+        let Some(share) = usage.cpu_percent(stop - start) else {
+            return;
+        };
+        for span in spans {
+            span.label.push_str(&format!(" [cpu {share}%]"));
+            span.dimmed = share < IDLE_CPU_PERCENT;
+        }
+    }
+
+    /// Adds `tag` to the end of the label of every span.
+    fn tag(&mut self, tag: &str) {
+        let spans = match self {
+            MermaidItem::Single(span) | MermaidItem::Collapsed(span, _) => {
+                std::slice::from_mut(span)
+            }
+            MermaidItem::ExecGroup(spans) => spans.as_mut_slice(),
+        };
+        for span in spans {
+            span.label.push_str(&format!(" {tag}"));
+        }
+    }
+}
+
+/// Turns host samples into lanes of spans that each last until the next sample.
+///
+/// The last sample isn't shown since there's nothing to say how long it lasted.
+fn host_sample_lanes(samples: &[Event]) -> Vec<(&'static str, Vec<Span>)> {
+    let mut load = vec![];
+    let mut memory = vec![];
+    for pair in samples.windows(2) {
+        let Event::HostSample {
+            timestamp,
+            load: [load1, ..],
+            mem_total_kb,
+            mem_available_kb,
+            ..
+        } = pair[0]
+        else {
+            continue;
+        };
+        let stop = pair[1].timestamp();
+        let used = if mem_total_kb == 0 {
+            0.0
+        } else {
+            (mem_total_kb - mem_available_kb.min(mem_total_kb)) as f64 / mem_total_kb as f64 * 100.0
+        };
+        load.push(Span {
+            pid: 0,
+            label: format!("load {:.2}", load1 as f64 / 100.0),
+            start: timestamp,
+            stop,
+            highlight: false,
+            dimmed: false,
+            markers: vec![],
+            running: false,
+        });
+        memory.push(Span {
+            pid: 0,
+            label: format!("mem {used:.0}% used"),
+            start: timestamp,
+            stop,
+            highlight: false,
+            dimmed: false,
+            markers: vec![],
+            running: false,
+        });
+    }
+    if load.is_empty() {
+        return vec![];
+    }
+    vec![("host load", load), ("host memory", memory)]
+}
+
+/// Turns the memory samples of the recorded processes into a lane of spans that each
+/// show how much they used between them, until the next round of samples.
+///
+/// Every process is sampled on the same tick, so the samples of a round share a
+/// timestamp.
+fn rss_lane(store: &EventStore) -> Vec<Span> {
+    let mut rounds = BTreeMap::<u128, u64>::new();
+    for (_, buffer) in store.iter() {
+        for event in buffer.iter() {
+            if let Event::RssSample {
+                timestamp, rss_kb, ..
+            } = event
+            {
+                *rounds.entry(*timestamp).or_default() += rss_kb;
+            }
+        }
+    }
+    let rounds = rounds.into_iter().collect::<Vec<_>>();
+    rounds
+        .windows(2)
+        .map(|pair| Span {
+            pid: 0,
+            label: format!("rss {}", format_kb(pair[0].1)),
+            start: pair[0].0,
+            stop: pair[1].0,
+            highlight: false,
+            dimmed: false,
+            markers: vec![],
+            running: false,
+        })
+        .collect()
+}
+
+/// Formats a size in KiB with the largest unit that keeps it above 1, e.g. "1.5 GiB".
+fn format_kb(kb: u64) -> String {
+    if kb >= 1024 * 1024 {
+        format!("{:.1} GiB", kb as f64 / (1024.0 * 1024.0))
+    } else if kb >= 1024 {
+        format!("{:.1} MiB", kb as f64 / 1024.0)
+    } else {
+        format!("{kb} KiB")
+    }
 }
 
 #[derive(Debug)]
@@ -207,9 +1957,135 @@ struct Span {
     pub label: String,
     pub start: u128,
     pub stop: u128,
+    /// Whether the span should stand out, e.g. because it was slow.
+    pub highlight: bool,
+    /// Whether the span should be drawn as done, e.g. because it's from the earlier
+    /// recording of an overlay.
+    pub dimmed: bool,
+    /// Events that happened during the span.
+    pub markers: Vec<Marker>,
+    /// Whether the process was still running when the recording ended, in which case
+    /// the span stops at the end of the recording.
+    pub running: bool,
+}
+
+/// A point in time on a span, e.g. a custom event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Marker {
+    pub timestamp: u128,
+    pub label: String,
+    /// Whether the marker is for something that went wrong, e.g. a failed exec.
+    pub warning: bool,
 }
 
-fn parse_buffer(events: &[Event]) -> Result<MermaidItem, Error> {
+impl Span {
+    fn duration(&self) -> u128 {
+        self.stop.saturating_sub(self.start)
+    }
+}
+
+/// Labels a custom event with its name and fields, e.g. `cache-miss key=abc`.
+fn custom_label(name: &str, fields: &BTreeMap<String, String>) -> String {
+    let mut label = name.to_string();
+    for (key, value) in fields.iter() {
+        label.push_str(&format!(" {key}={value}"));
+    }
+    label
+}
+
+/// Turns the custom events, failed execs, crashes, namespace changes, mounts,
+/// sandboxes, subreapers, and calls to `ptrace` in `events` into markers.
+fn markers(events: &[&Event]) -> Vec<Marker> {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Custom {
+                timestamp,
+                name,
+                fields,
+                ..
+            } => Some(Marker {
+                timestamp: *timestamp,
+                label: custom_label(name, fields),
+                warning: false,
+            }),
+            Event::BadExec {
+                timestamp,
+                filename,
+                errno,
+                ..
+            } => Some(Marker {
+                timestamp: *timestamp,
+                label: describe_failed_exec(filename.as_deref(), *errno),
+                warning: true,
+            }),
+            // A crash is marked where it happened, unlike a clean exit
+            Event::Exit {
+                timestamp,
+                exit_code: Some(code),
+                ..
+            } => describe_crash(*code).map(|label| Marker {
+                timestamp: *timestamp,
+                label,
+                warning: true,
+            }),
+            Event::Namespace {
+                timestamp,
+                how,
+                namespaces,
+                ..
+            } => Some(Marker {
+                timestamp: *timestamp,
+                label: format!("{how} {}", namespaces.join(","))
+                    .trim_end()
+                    .to_string(),
+                warning: false,
+            }),
+            Event::Mount {
+                timestamp,
+                target,
+                fstype,
+                unmount,
+                ..
+            } => Some(Marker {
+                timestamp: *timestamp,
+                label: describe_mount(target, fstype.as_deref(), *unmount),
+                warning: false,
+            }),
+            Event::Sandbox {
+                timestamp,
+                mechanism,
+                mode,
+                flags,
+                ..
+            } => Some(Marker {
+                timestamp: *timestamp,
+                label: describe_sandbox(*mechanism, mode.as_deref(), flags),
+                warning: false,
+            }),
+            Event::Subreaper { timestamp, .. } => Some(Marker {
+                timestamp: *timestamp,
+                label: "subreaper".to_string(),
+                warning: false,
+            }),
+            // Tracing other processes is unusual enough that it should stand out
+            Event::Ptrace {
+                timestamp,
+                request,
+                target_pid,
+                errno,
+                ..
+            } => Some(Marker {
+                timestamp: *timestamp,
+                label: describe_ptrace(*request, *target_pid, *errno),
+                warning: true,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+fn parse_buffer(events: &[&Event]) -> Result<MermaidItem, Error> {
     if events.is_empty() {
         return Err(anyhow!("tried to parse empty buffer"));
     }
@@ -227,8 +2103,35 @@ fn parse_buffer(events: &[Event]) -> Result<MermaidItem, Error> {
     }
 }
 
+/// Extracts a single span labeled with the last `exec` of a buffer, so that the
+/// wrappers that led up to it are hidden.
+fn collapse_exec_chain(events: &[&Event]) -> Result<MermaidItem, Error> {
+    let exec_indices = events
+        .iter()
+        .enumerate()
+        .filter_map(|(i, event)| event.is_exec_full().then_some(i))
+        .collect::<Vec<_>>();
+    let Some((last, wrappers)) = exec_indices
+        .split_last()
+        .filter(|(_, rest)| !rest.is_empty())
+    else {
+        return parse_buffer(events);
+    };
+    let MermaidItem::Single(span) = extract_single_exec_span(events, *last)? else {
+        unreachable!("single exec span returned more than one span");
+    };
+    let wrappers = wrappers
+        .iter()
+        .filter_map(|idx| match &events[*idx] {
+            Event::ExecFull { filename, .. } => Some(command_name(filename)),
+            _ => None,
+        })
+        .collect();
+    Ok(MermaidItem::Collapsed(span, wrappers))
+}
+
 /// Extracts a [RenderItem] from a buffer that doesn't contain any `exec` events.
-fn extract_fork_span(events: &[Event]) -> Result<MermaidItem, Error> {
+fn extract_fork_span(events: &[&Event]) -> Result<MermaidItem, Error> {
     let start = events
         .first()
         .ok_or(anyhow!("buffer was empty after checking"))?
@@ -238,18 +2141,22 @@ fn extract_fork_span(events: &[Event]) -> Result<MermaidItem, Error> {
         .last()
         .ok_or(anyhow!("buffer was empty after checking"))?
         .timestamp();
-    let label = format!("[{pid}] <fork>");
+    let label = format!("[{pid}] {}", fork_name(events.first().copied()));
     let span = Span {
         pid,
         start,
         stop,
         label,
+        highlight: false,
+        dimmed: false,
+        markers: markers(events),
+        running: false,
     };
     Ok(MermaidItem::Single(span))
 }
 
 /// Extracts a [RenderItem] from a buffer that contains a single `exec` event.
-fn extract_single_exec_span(events: &[Event], exec_index: usize) -> Result<MermaidItem, Error> {
+fn extract_single_exec_span(events: &[&Event], exec_index: usize) -> Result<MermaidItem, Error> {
     let start = events
         .first()
         .ok_or(anyhow!("buffer was empty after checking"))?
@@ -273,13 +2180,17 @@ fn extract_single_exec_span(events: &[Event], exec_index: usize) -> Result<Merma
         start,
         stop,
         label,
+        highlight: false,
+        dimmed: false,
+        markers: markers(events),
+        running: false,
     };
     Ok(MermaidItem::Single(span))
 }
 
 /// Extracts a [RenderItem] from a buffer that contains multiple `exec` events
 fn extract_multiple_exec_spans(
-    events: &[Event],
+    events: &[&Event],
     exec_indices: &[usize],
 ) -> Result<MermaidItem, Error> {
     let mut spans = vec![];
@@ -313,41 +2224,94 @@ fn render_item(
     item: &MermaidItem,
     mut writer: impl Write,
     initial_time: u128,
+    link: bool,
 ) -> Result<(), Error> {
+    // Spans need an ID to be linked, which has to be unique across the chart
+    let id = |pid: i32, idx: usize| link.then(|| format!("p{pid}_{idx}"));
     match item {
         MermaidItem::Single(span) => {
-            render_single_span(span, &mut writer, initial_time).context("failed rendering span")?;
+            render_single_span(span, &mut writer, initial_time, id(span.pid, 0))
+                .context("failed rendering span")?;
         }
         MermaidItem::ExecGroup(spans) => {
             writer
                 .write_all(format!("    section {} execs\n", spans[0].pid).as_bytes())
                 .context("failed writing exec group header")?;
-            for span in spans.iter() {
-                render_single_span(span, &mut writer, initial_time)
+            for (idx, span) in spans.iter().enumerate() {
+                render_single_span(span, &mut writer, initial_time, id(span.pid, idx))
                     .context("failed rendering span")?;
             }
             writer
                 .write_all("    section other\n".as_bytes())
                 .context("write failed")?;
         }
+        MermaidItem::Collapsed(span, wrappers) => {
+            render_single_span(span, &mut writer, initial_time, id(span.pid, 0))
+                .context("failed rendering span")?;
+            // Mermaid doesn't have anywhere to put details, so they're left in a comment
+            writer
+                .write_all(
+                    format!("    %% [{}] via {}\n", span.pid, wrappers.join(" -> ")).as_bytes(),
+                )
+                .context("write failed")?;
+        }
     }
     Ok(())
 }
 
+/// Writes a span, and if it has an ID, a link to the detail page of its process.
 fn render_single_span(
     span: &Span,
     mut writer: impl Write,
     initial_time: u128,
+    id: Option<String>,
 ) -> Result<(), Error> {
-    let start = (span.start - initial_time) / 1_000_000;
-    let duration = (span.stop - span.start) / 1_000_000;
-    let line = format!(
-        "    {} :active, {}, {}ms\n",
-        clean_mermaid_label(&span.label),
-        start,
-        duration.max(1)
-    );
+    let start = span.start.saturating_sub(initial_time) / 1_000_000;
+    let duration = span.duration() / 1_000_000;
+    let mut label = clean_mermaid_label(&span.label);
+    // Mermaid draws critical tasks in red, done tasks in gray, and tasks that are
+    // neither done nor active in the default color, which is used for processes that
+    // never exited
+    let mut fields = vec![];
+    if span.highlight {
+        fields.push("crit".to_string());
+    }
+    if span.dimmed {
+        fields.push("done".to_string());
+    } else if !span.running {
+        fields.push("active".to_string());
+    }
+    if let Some(id) = id.as_ref() {
+        if let Some((idx, _)) = label.char_indices().nth(MAX_LINKED_LABEL_LEN) {
+            label.truncate(idx);
+            label.push_str("...");
+        }
+        fields.push(id.clone());
+    }
+    if span.running {
+        label.push_str(" (still running)");
+    }
+    fields.push(start.to_string());
+    fields.push(format!("{}ms", duration.max(1)));
+    let line = format!("    {label} :{}\n", fields.join(", "));
     writer.write_all(line.as_bytes()).context("write failed")?;
+    for marker in span.markers.iter() {
+        let at = marker.timestamp.saturating_sub(initial_time) / 1_000_000;
+        let label = clean_mermaid_label(format!("[{}] {}", span.pid, marker.label));
+        let tags = if marker.warning {
+            "crit, milestone"
+        } else {
+            "milestone"
+        };
+        writer
+            .write_all(format!("    {label} :{tags}, {at}, 0ms\n").as_bytes())
+            .context("write failed")?;
+    }
+    if let Some(id) = id {
+        writer
+            .write_all(format!("    click {id} href \"pids/{}.md\"\n", span.pid).as_bytes())
+            .context("write failed")?;
+    }
     Ok(())
 }
 
@@ -363,20 +2327,28 @@ fn clean_mermaid_label(label: impl AsRef<str>) -> String {
 
 #[cfg(test)]
 mod test {
-    use crate::ingest::test::make_simple_events;
+    use clap::ValueEnum;
+
+    use crate::{
+        ingest::test::make_simple_events,
+        models::{NamespaceChange, PtraceRequest, SandboxMechanism},
+    };
 
     use super::*;
 
     #[test]
     fn extracts_fork_span() {
         let events = make_simple_events(0, 0, &[("fork", 1, 0), ("exit", 1, 0)]);
+        let events = events.iter().collect::<Vec<_>>();
         let item = extract_fork_span(&events).unwrap();
         assert!(matches!(item, MermaidItem::Single(_)));
     }
 
     #[test]
     fn extracts_single_exec_span() {
-        let events = make_simple_events(0, 0, &[("fork", 1, 0), ("exec", 1, 0), ("exit", 1, 0)]);
+        let events =
+            make_simple_events(0, 0, &[("fork", 1, 0), ("exec_full", 1, 0), ("exit", 1, 0)]);
+        let events = events.iter().collect::<Vec<_>>();
         let item = extract_single_exec_span(&events, 1).unwrap();
         assert!(matches!(item, MermaidItem::Single(_)));
     }
@@ -388,12 +2360,13 @@ mod test {
             0,
             &[
                 ("fork", 1, 0),
-                ("exec", 1, 0),
-                ("exec", 1, 0),
-                ("exec", 1, 0),
+                ("exec_full", 1, 0),
+                ("exec_full", 1, 0),
+                ("exec_full", 1, 0),
                 ("exit", 1, 0),
             ],
         );
+        let events = events.iter().collect::<Vec<_>>();
         let item = extract_multiple_exec_spans(&events, &[1, 2, 3]).unwrap();
         assert!(matches!(item, MermaidItem::ExecGroup(_)));
         let MermaidItem::ExecGroup(spans) = item else {
@@ -401,4 +2374,1208 @@ mod test {
         };
         assert_eq!(spans.len(), 3);
     }
+
+    #[test]
+    fn highlights_slow_spans() {
+        let span = |start: u128, stop: u128| Span {
+            pid: 1,
+            label: "[1] make".to_string(),
+            start,
+            stop,
+            highlight: false,
+            dimmed: false,
+            markers: vec![],
+            running: false,
+        };
+        let mut item = MermaidItem::ExecGroup(vec![span(0, 5_000_000), span(5_000_000, 6_000_000)]);
+        item.highlight_over(2_000_000);
+        let MermaidItem::ExecGroup(spans) = &item else {
+            panic!("expected an exec group")
+        };
+        let highlighted = spans.iter().map(|span| span.highlight).collect::<Vec<_>>();
+        assert_eq!(highlighted, vec![true, false]);
+
+        let mut output = vec![];
+        render_item(&item, &mut output, 0, false).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("    [1] make :crit, active, 0, 5ms\n"));
+        assert!(output.contains("    [1] make :active, 5, 1ms\n"));
+    }
+
+    #[test]
+    fn collapses_exec_chains() {
+        let events = make_simple_events(
+            0,
+            0,
+            &[
+                ("fork", 1, 0),
+                ("exec_full", 1, 0),
+                ("exec_full", 1, 0),
+                ("exec_full", 1, 0),
+                ("exit", 1, 0),
+            ],
+        );
+        let events = events.iter().collect::<Vec<_>>();
+        let MermaidItem::Collapsed(span, wrappers) = collapse_exec_chain(&events).unwrap() else {
+            panic!("expected a collapsed span")
+        };
+        assert_eq!((span.start, span.stop), (0, 4));
+        assert_eq!(span.label, "[1] /foo/bar");
+        assert_eq!(wrappers, vec!["bar", "bar"]);
+
+        // Nothing to collapse with a single exec
+        let item = collapse_exec_chain(&events[..2]).unwrap();
+        assert!(matches!(item, MermaidItem::Single(_)));
+    }
+
+    fn encode_events(events: &[Event]) -> Vec<u8> {
+        let mut buf = vec![];
+        let mut writer = JsonWriter::new(&mut buf);
+        for event in events.iter() {
+            writer.write_event(event).unwrap();
+        }
+        buf
+    }
+
+    #[test]
+    fn renders_processes_that_never_exit() {
+        let ms = 1_000_000;
+        let fork = |timestamp: u128, parent_pid: i32, child_pid: i32| Event::Fork {
+            seq: timestamp,
+            timestamp,
+            parent_pid,
+            child_pid,
+            parent_pgid: 1,
+            credentials: None,
+            comm: None,
+        };
+        let events = [
+            fork(0, 0, 1),
+            fork(5 * ms, 1, 2),
+            fork(10 * ms, 1, 3),
+            Event::Exit {
+                seq: 50 * ms,
+                timestamp: 50 * ms,
+                pid: 3,
+                ppid: 1,
+                pgid: 1,
+                exit_code: Some(0),
+            },
+        ];
+        let mut output = vec![];
+        render(
+            encode_events(&events).as_slice(),
+            &mut output,
+            DisplayMode::Mermaid,
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("    [1] <fork> (still running) :0, 50ms\n"));
+        assert!(output.contains("    [2] <fork> (still running) :5, 45ms\n"));
+        assert!(output.contains("    [3] <fork> :active, 10, 40ms\n"));
+
+        let mut output = vec![];
+        render(
+            encode_events(&events).as_slice(),
+            &mut output,
+            DisplayMode::ByProcess,
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("PID 2, forked from 1 (still running)\n"));
+        assert!(output.contains("PID 3, forked from 1\n"));
+    }
+
+    #[test]
+    fn reads_recordings_that_start_with_an_exec() {
+        let events = make_simple_events(
+            10,
+            10,
+            &[
+                ("exec_full", 1, 0),
+                ("fork", 2, 1),
+                ("exit", 2, 1),
+                ("exit", 1, 0),
+            ],
+        );
+        let ingester = read_events(encode_events(&events).as_slice()).unwrap();
+        assert_eq!(ingester.root_pid(), Some(1));
+        assert_eq!(ingester.tracked_events().pids().len(), 2);
+        let buffer = ingester
+            .tracked_events()
+            .iter()
+            .find_map(|(pid, buffer)| (pid == 1).then_some(buffer))
+            .unwrap();
+        assert!(matches!(
+            buffer.front(),
+            Some(Event::Fork {
+                seq: 9,
+                timestamp: 10,
+                child_pid: 1,
+                ..
+            })
+        ));
+
+        let mut output = vec![];
+        render(
+            encode_events(&events).as_slice(),
+            &mut output,
+            DisplayMode::Mermaid,
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        assert!(String::from_utf8(output).unwrap().contains("[1] "));
+        let host_sample = Event::HostSample {
+            seq: 0,
+            timestamp: 0,
+            load: [0, 0, 0],
+            mem_total_kb: 1,
+            mem_available_kb: 1,
+        };
+        assert!(read_events(encode_events(&[host_sample]).as_slice()).is_err());
+    }
+
+    #[test]
+    fn reads_events_from_slice() {
+        let events = make_simple_events(0, 0, &[("fork", 1, 0), ("fork", 2, 1), ("exit", 2, 1)]);
+        let mut metadata = Metadata::new(Some(1));
+        metadata.adopted_pids = vec![5];
+        let mut buf = vec![];
+        let mut writer = JsonWriter::new(&mut buf);
+        writer.write_metadata(&metadata).unwrap();
+        for event in events.iter() {
+            writer.write_event(event).unwrap();
+        }
+        let from_slice = read_events_from_slice(&buf).unwrap();
+        let from_reader = read_events(buf.as_slice()).unwrap();
+        assert_eq!(from_slice.root_pid(), Some(1));
+        assert_eq!(
+            from_slice.tracked_events().pids(),
+            from_reader.tracked_events().pids()
+        );
+    }
+
+    #[test]
+    fn hides_shell_wrapper() {
+        let events = make_simple_events(
+            0,
+            0,
+            &[
+                ("fork", 1, 0),
+                ("exec_full", 1, 0),
+                ("fork", 2, 1),
+                ("exec_full", 2, 1),
+                ("exit", 2, 1),
+                ("exit", 1, 0),
+            ],
+        );
+        let mut metadata = Metadata::new(Some(1));
+        metadata.shell_wrapper = true;
+        let mut buf = vec![];
+        let mut writer = JsonWriter::new(&mut buf);
+        writer.write_metadata(&metadata).unwrap();
+        for event in events.iter() {
+            writer.write_event(event).unwrap();
+        }
+        let chart = |hide_shell_wrapper| {
+            let options = RenderOptions {
+                hide_shell_wrapper,
+                ..Default::default()
+            };
+            let mut chart = vec![];
+            render(buf.as_slice(), &mut chart, DisplayMode::Mermaid, &options).unwrap();
+            String::from_utf8(chart).unwrap()
+        };
+        assert!(chart(false).contains("[1] /foo/bar"));
+        let hidden = chart(true);
+        assert!(!hidden.contains("[1] /foo/bar"));
+        assert!(hidden.contains("[2] /foo/bar"));
+    }
+
+    #[test]
+    fn renders_detail_pages_to_dir() {
+        let events = make_simple_events(
+            0,
+            0,
+            &[
+                ("fork", 1, 0),
+                ("exec_full", 1, 0),
+                ("fork", 2, 1),
+                ("exit", 2, 1),
+                ("exit", 1, 0),
+            ],
+        );
+        let dir = tempfile::tempdir().unwrap();
+        render_to_dir(
+            read_events(encode_events(&events).as_slice()).unwrap(),
+            dir.path(),
+            DisplayMode::Mermaid,
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        let chart = std::fs::read_to_string(dir.path().join("chart.mmd")).unwrap();
+        assert!(chart.contains("    [1] /foo/bar :active, p1_0, 0, 1ms\n"));
+        assert!(chart.contains("    click p2_0 href \"pids/2.md\"\n"));
+        let page = std::fs::read_to_string(dir.path().join("pids/1.md")).unwrap();
+        assert!(page.contains("## `/foo/bar` at"));
+        let json = std::fs::read_to_string(dir.path().join("pids/2.json")).unwrap();
+        let detail: ProcessDetail = serde_json::from_str(&json).unwrap();
+        assert_eq!((detail.parent_pid, detail.exited), (Some(1), true));
+    }
+
+    #[test]
+    fn overlays_recordings() {
+        let protos = [
+            ("fork", 1, 0),
+            ("exec_full", 1, 0),
+            ("fork", 2, 1),
+            ("exit", 2, 1),
+            ("exit", 1, 0),
+        ];
+        let before = encode_events(&make_simple_events(0, 0, &protos));
+        // Recorded later, but aligned with the first one
+        let after = encode_events(&make_simple_events(5_000_000_000, 0, &protos));
+        let mut output = vec![];
+        render_overlay(
+            before.as_slice(),
+            after.as_slice(),
+            &mut output,
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lanes = output
+            .lines()
+            .filter(|line| line.contains(":done") || line.contains(":active"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lanes,
+            vec![
+                "    before [1] /foo/bar :done, 0, 1ms",
+                "    after [1] /foo/bar :active, 0, 1ms",
+                "    before [2] <fork> :done, 0, 1ms",
+                "    after [2] <fork> :active, 0, 1ms",
+            ]
+        );
+    }
+
+    #[test]
+    fn streams_sequential_events_like_a_full_render() {
+        let mut events = make_simple_events(
+            0,
+            0,
+            &[
+                ("fork", 1, 0),
+                ("exec_full", 1, 0),
+                ("fork", 2, 1),
+                ("exec_full", 2, 1),
+                ("fork", 3, 1),
+                ("exit", 2, 1),
+                ("exit", 3, 1),
+                ("exit", 1, 0),
+            ],
+        );
+        // Events slightly out of order, but within the window
+        events.swap(3, 4);
+        let input = encode_events(&events);
+        let mut expected = vec![];
+        render(
+            input.as_slice(),
+            &mut expected,
+            DisplayMode::Sequential,
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        let mut streamed = vec![];
+        render_streaming(
+            input.as_slice(),
+            &mut streamed,
+            DisplayMode::Sequential,
+            4,
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(streamed).unwrap(),
+            String::from_utf8(expected).unwrap()
+        );
+    }
+
+    #[test]
+    fn streams_nested_processes_like_a_full_render() {
+        // The execs are split into their parts, and the first child outlives
+        // processes that started after it
+        let events = make_simple_events(
+            0,
+            0,
+            &[
+                ("fork", 1, 0),
+                ("exec", 1, 0),
+                ("exec_args", 1, 0),
+                ("fork", 2, 1),
+                ("exec", 2, 1),
+                ("exec_args", 2, 1),
+                ("fork", 3, 2),
+                ("exec", 3, 2),
+                ("exec_args", 3, 2),
+                ("fork", 4, 3),
+                ("fork", 5, 1),
+                ("exec", 5, 1),
+                ("exec_args", 5, 1),
+                ("exit", 4, 3),
+                ("exit", 5, 1),
+                ("fork", 6, 2),
+                ("exit", 6, 2),
+                ("exit", 3, 2),
+                ("exit", 2, 1),
+                ("exit", 1, 0),
+            ],
+        );
+        let input = encode_events(&events);
+        let options = RenderOptions::default();
+        let mut expected = vec![];
+        render(
+            input.as_slice(),
+            &mut expected,
+            DisplayMode::ByProcess,
+            &options,
+        )
+        .unwrap();
+        let expected = String::from_utf8(expected).unwrap();
+        for window in [0, 1, 16] {
+            let mut streamed = vec![];
+            render_streaming(
+                input.as_slice(),
+                &mut streamed,
+                DisplayMode::ByProcess,
+                window,
+                &options,
+            )
+            .unwrap();
+            assert_eq!(
+                String::from_utf8(streamed).unwrap(),
+                expected,
+                "window {window}"
+            );
+        }
+    }
+
+    #[test]
+    fn post_processes_only_when_asked_to() {
+        let mut events =
+            make_simple_events(100, 0, &[("fork", 1, 0), ("fork", 2, 1), ("exit", 2, 1)]);
+        // Stamped by a CPU whose clock is behind
+        let seq = events[1].seq();
+        events[1].set_seq_and_timestamp(seq, 50);
+        let input = encode_events(&events);
+
+        let unprocessed = read_unprocessed_events(input.as_slice()).unwrap();
+        assert_eq!(unprocessed.adjusted_timestamps(), 0);
+        assert_eq!(
+            unprocessed.tracked_events().pid_start_time(2),
+            Some(50),
+            "timestamps are kept as they were recorded"
+        );
+
+        let processed = read_events(input.as_slice()).unwrap();
+        assert_eq!(processed.adjusted_timestamps(), 1);
+        assert_eq!(processed.tracked_events().pid_start_time(2), Some(100));
+    }
+
+    #[test]
+    fn renders_custom_events_as_markers() {
+        let mut events = make_simple_events(0, 0, &[("fork", 1, 0), ("exit", 1, 0)]);
+        events.insert(
+            1,
+            Event::Custom {
+                seq: 10,
+                timestamp: 0,
+                pid: 1,
+                name: "cache-miss".to_string(),
+                fields: BTreeMap::from([("key".to_string(), "abc".to_string())]),
+            },
+        );
+        let mut output = vec![];
+        render(
+            encode_events(&events).as_slice(),
+            &mut output,
+            DisplayMode::Mermaid,
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("    [1] cache-miss key=abc :milestone, 0, 0ms\n"));
+    }
+
+    #[test]
+    fn renders_failed_execs_as_warnings() {
+        let mut events = make_simple_events(0, 0, &[("fork", 1, 0), ("exit", 1, 0)]);
+        events.insert(
+            1,
+            Event::BadExec {
+                seq: 10,
+                timestamp: 0,
+                pid: 1,
+                filename: Some("/usr/local/bin/git".to_string()),
+                errno: Some(2),
+            },
+        );
+        let mut output = vec![];
+        render(
+            encode_events(&events).as_slice(),
+            &mut output,
+            DisplayMode::Mermaid,
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(
+            "    [1] exec failed: /usr/local/bin/git (ENOENT) :crit, milestone, 0, 0ms\n"
+        ));
+
+        let buffer = events.into_iter().collect::<VecDeque<_>>();
+        let detail = ProcessDetail::from_buffer(1, &buffer, 0);
+        assert!(detail
+            .to_markdown()
+            .contains("## Failed execs\n\n- `/usr/local/bin/git` (ENOENT) at"));
+    }
+
+    #[test]
+    fn lists_working_directory_changes() {
+        let events = crate::builder::TraceBuilder::new()
+            .fork(0, 1)
+            .chdir(1, "/src/app")
+            .advance(5_000_000)
+            .chdir(1, "build")
+            .exit(1, 0)
+            .build();
+        let buffer = events.into_iter().collect::<VecDeque<_>>();
+        let detail = ProcessDetail::from_buffer(1, &buffer, 0);
+        assert_eq!(detail.working_directories.len(), 2);
+        assert!(detail.to_markdown().contains(
+            "## Working directory changes\n\n- `/src/app` at 0.000us\n- `build` at 5.000ms\n"
+        ));
+    }
+
+    #[test]
+    fn lists_resource_usage() {
+        let usage = ResourceUsage {
+            minor_faults: 1200,
+            major_faults: 7,
+            voluntary_switches: 30,
+            involuntary_switches: 4,
+            user_ns: 3_000_000,
+            system_ns: 1_000_000,
+        };
+        let events = crate::builder::TraceBuilder::new()
+            .fork(0, 1)
+            .advance(10_000_000)
+            .usage(1, usage)
+            .exit(1, 0)
+            .build();
+        let buffer = events.into_iter().collect::<VecDeque<_>>();
+        let detail = ProcessDetail::from_buffer(1, &buffer, 0);
+        assert_eq!(detail.usage, Some(usage));
+        assert!(detail.exited);
+        assert!(detail.to_markdown().contains(
+            "- CPU time: 3.000ms user, 1.000ms system (40% of the time it ran)\n- Page faults: 1200 minor, 7 major\n- Context switches: 30 voluntary, 4 involuntary\n"
+        ));
+    }
+
+    #[test]
+    fn shows_cpu_time_versus_wall_time() {
+        let ms = 1_000_000;
+        let usage = |user_ms| ResourceUsage {
+            user_ns: user_ms * ms as u64,
+            ..ResourceUsage::default()
+        };
+        let builder = crate::builder::TraceBuilder::new()
+            .fork(0, 1)
+            .exec(1, "make")
+            .fork(1, 2)
+            .exec(2, "cc -c a.c")
+            .advance(100 * ms)
+            .usage(2, usage(90))
+            .exit(2, 0)
+            .usage(1, usage(1))
+            .exit(1, 0);
+        let mut bytes = vec![];
+        builder.clone().write(JsonWriter::new(&mut bytes)).unwrap();
+        let mut output = vec![];
+        render(
+            bytes.as_slice(),
+            &mut output,
+            DisplayMode::Mermaid,
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains("    [2] cc -c a.c [cpu 90%] :active, 0, 100ms\n"),
+            "{output}"
+        );
+        // make only waited for it
+        assert!(output.contains("    [1] make [cpu 1%] :done, 0, 100ms\n"));
+
+        let buffer = builder.build().into_iter().filter(|event| event.pid() == 2);
+        let mut block = vec![];
+        write_process_block(2, &buffer.collect(), &mut block).unwrap();
+        let block = String::from_utf8(block).unwrap();
+        let header = block.lines().next().unwrap();
+        assert!(
+            header.ends_with("a.c (cpu 90.000ms of 100.000ms, 90%)"),
+            "{header}"
+        );
+    }
+
+    #[test]
+    fn renders_mounts_as_markers() {
+        let mut events = make_simple_events(0, 0, &[("fork", 1, 0), ("exit", 1, 0)]);
+        for (seq, target, fstype, unmount) in [
+            (10, "/build/root", Some("overlay"), false),
+            (11, "/build/root/nix/store", None, false),
+            (12, "/build/root", None, true),
+        ] {
+            events.insert(
+                1,
+                Event::Mount {
+                    seq,
+                    timestamp: 0,
+                    pid: 1,
+                    target: target.to_string(),
+                    fstype: fstype.map(String::from),
+                    unmount,
+                },
+            );
+        }
+        let mut output = vec![];
+        render(
+            encode_events(&events).as_slice(),
+            &mut output,
+            DisplayMode::Mermaid,
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("    [1] mount overlay on /build/root :milestone, 0, 0ms\n"));
+        assert!(output.contains("    [1] mount /build/root/nix/store :milestone, 0, 0ms\n"));
+        assert!(output.contains("    [1] umount /build/root :milestone, 0, 0ms\n"));
+    }
+
+    #[test]
+    fn renders_ptrace_as_warnings() {
+        let mut events = make_simple_events(0, 0, &[("fork", 1, 0), ("exit", 1, 0)]);
+        events.insert(
+            1,
+            Event::Ptrace {
+                seq: 10,
+                timestamp: 0,
+                pid: 1,
+                request: PtraceRequest::Attach,
+                target_pid: Some(2),
+                errno: Some(1),
+            },
+        );
+        let mut output = vec![];
+        render(
+            encode_events(&events).as_slice(),
+            &mut output,
+            DisplayMode::Mermaid,
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains("    [1] ptrace attach to 2 failed (EPERM) :crit, milestone, 0, 0ms\n")
+        );
+    }
+
+    #[test]
+    fn renders_sandboxes_as_markers() {
+        let mut events = make_simple_events(0, 0, &[("fork", 1, 0), ("exit", 1, 0)]);
+        events.insert(
+            1,
+            Event::Sandbox {
+                seq: 10,
+                timestamp: 0,
+                pid: 1,
+                mechanism: SandboxMechanism::Seccomp,
+                mode: Some("filter".to_string()),
+                flags: vec!["tsync".to_string()],
+            },
+        );
+        let mut output = vec![];
+        render(
+            encode_events(&events).as_slice(),
+            &mut output,
+            DisplayMode::Mermaid,
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("    [1] seccomp filter (tsync) :milestone, 0, 0ms\n"));
+    }
+
+    #[test]
+    fn tags_processes_in_namespaces() {
+        let mut bytes = vec![];
+        crate::builder::TraceBuilder::new()
+            .fork(0, 1)
+            .exec(1, "bwrap --unshare-net make")
+            .namespace(1, NamespaceChange::Unshare, &["net"])
+            .fork(1, 2)
+            .exec(2, "make")
+            .exit(2, 0)
+            .exit(1, 0)
+            .write(JsonWriter::new(&mut bytes))
+            .unwrap();
+        let mut output = vec![];
+        render(
+            bytes.as_slice(),
+            &mut output,
+            DisplayMode::Mermaid,
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("    [1] unshare net :milestone, 0, 0ms\n"));
+        assert!(output.contains("    [2] make [ns: net] :"));
+    }
+
+    #[test]
+    fn names_forks_that_never_exec() {
+        let mut bytes = vec![];
+        crate::builder::TraceBuilder::new()
+            .fork(0, 1)
+            .exec(1, "postgres -D data")
+            .fork_named(1, 2, "postgres")
+            .exit(2, 0)
+            .exit(1, 0)
+            .write(JsonWriter::new(&mut bytes))
+            .unwrap();
+        let render_as = |mode: DisplayMode| {
+            let mut output = vec![];
+            render(
+                bytes.as_slice(),
+                &mut output,
+                mode,
+                &RenderOptions::default(),
+            )
+            .unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        assert!(render_as(DisplayMode::Mermaid).contains("    [2] <postgres> :"));
+        assert!(render_as(DisplayMode::ByProcess).contains("PID 2 (postgres), forked from 1"));
+    }
+
+    #[test]
+    fn tags_processes_of_other_users() {
+        let mut bytes = vec![];
+        crate::builder::TraceBuilder::new()
+            .fork_as(0, 1, 1000, 100)
+            .exec(1, "make")
+            .fork(1, 2)
+            .exec(2, "cc -c a.c")
+            .fork_as(1, 3, 0, 0)
+            .exec(3, "sudo install a.out")
+            .exit(3, 0)
+            .exit(2, 0)
+            .exit(1, 0)
+            .write(JsonWriter::new(&mut bytes))
+            .unwrap();
+        let mut output = vec![];
+        render(
+            bytes.as_slice(),
+            &mut output,
+            DisplayMode::Mermaid,
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("    [2] cc -c a.c :"));
+        assert!(output.contains("    [3] sudo install a.out [user 0:0] :"));
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn round_trips_events_with_the_same_timestamp() {
+        let mut bytes = vec![];
+        // Every event happens at the same time, and the later PID is forked first
+        crate::builder::TraceBuilder::new()
+            .fork(0, 1)
+            .exec(1, "make")
+            .fork(1, 3)
+            .fork(1, 2)
+            .exec(2, "cc -c b.c")
+            .exec(3, "cc -c a.c")
+            .exit(2, 0)
+            .exit(3, 0)
+            .exit(1, 0)
+            .write(JsonWriter::new(&mut bytes))
+            .unwrap();
+        let render_as = |input: &[u8], mode| {
+            let mut output = vec![];
+            render(input, &mut output, mode, &RenderOptions::default()).unwrap();
+            output
+        };
+        let once = render_as(&bytes, DisplayMode::Sequential);
+        let twice = render_as(&once, DisplayMode::Sequential);
+        assert_eq!(
+            String::from_utf8(once.clone()).unwrap(),
+            String::from_utf8(twice).unwrap()
+        );
+        let mut sorted = vec![];
+        crate::sort::external_sort(
+            TraceReader::new(once.as_slice()).unwrap(),
+            JsonWriter::new(&mut sorted),
+            1,
+        )
+        .unwrap();
+        assert_eq!(sorted, once);
+
+        let by_process = String::from_utf8(render_as(&once, DisplayMode::ByProcess)).unwrap();
+        let pids = by_process
+            .lines()
+            .filter_map(|line| line.strip_prefix("PID "))
+            .map(|line| line.split([',', ':']).next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(pids, vec!["1", "3", "2"]);
+    }
+
+    #[test]
+    fn groups_processes_by_directory() {
+        let mut bytes = vec![];
+        crate::builder::TraceBuilder::new()
+            .fork(0, 1)
+            .exec(1, "make -C packages")
+            .fork(1, 2)
+            .chdir(2, "/src/packages/foo")
+            .exec(2, "make")
+            .fork(1, 3)
+            .chdir(3, "/src/packages/bar")
+            .exec(3, "make")
+            .fork(2, 4)
+            .exec(4, "cc -c foo.c")
+            .exit(4, 0)
+            .exit(3, 0)
+            .exit(2, 0)
+            .exit(1, 0)
+            .write(JsonWriter::new(&mut bytes))
+            .unwrap();
+        let mut output = vec![];
+        render(
+            bytes.as_slice(),
+            &mut output,
+            DisplayMode::ByProcess,
+            &RenderOptions {
+                tagger: Some(Tagger::Directory),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let headers = output
+            .lines()
+            .filter(|line| line.starts_with("===") || line.starts_with("PID"))
+            .map(|line| line.split([',', ':']).next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            headers,
+            vec![
+                "=== (untagged) ===",
+                "PID 1",
+                "=== /src/packages/bar ===",
+                "PID 3",
+                "=== /src/packages/foo ===",
+                "PID 2",
+                "PID 4",
+            ]
+        );
+    }
+
+    #[test]
+    fn shades_spans_by_cpu_use() {
+        let ms = 1_000_000;
+        let mut builder = crate::builder::TraceBuilder::new()
+            .fork(0, 1)
+            .exec(1, "make")
+            .fork(1, 2)
+            .exec(2, "cc -c a.c");
+        // The compiler was on a CPU for 10 of the 20 samples that it ran for
+        for _ in 0..10 {
+            builder = builder.on_cpu(2).advance(20 * ms);
+        }
+        let builder = builder.exit(2, 0).exit(1, 0);
+        let mut metadata = builder.metadata();
+        metadata.cpu_sample_hz = Some(100);
+        let mut bytes = vec![];
+        let mut writer = JsonWriter::new(&mut bytes);
+        writer.write_metadata(&metadata).unwrap();
+        for event in builder.build().iter() {
+            writer.write_event(event).unwrap();
+        }
+        let mut output = vec![];
+        render(
+            bytes.as_slice(),
+            &mut output,
+            DisplayMode::Mermaid,
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains("    [2] cc -c a.c [cpu 50%] :active, 0, 200ms\n"),
+            "{output}"
+        );
+        // make only waited for it
+        assert!(output.contains("    [1] make [cpu 0%] :done, 0, 200ms\n"));
+    }
+
+    #[test]
+    fn marks_crashes() {
+        let mut bytes = vec![];
+        crate::builder::TraceBuilder::new()
+            .fork(0, 1)
+            .exec(1, "make test")
+            .fork(1, 2)
+            .exec(2, "./flaky")
+            .fork(1, 3)
+            .advance(2_000_000)
+            .dumped_core(2, 6)
+            .killed(3, 15)
+            .exit(1, 2)
+            .write(JsonWriter::new(&mut bytes))
+            .unwrap();
+        let mut output = vec![];
+        render(
+            bytes.as_slice(),
+            &mut output,
+            DisplayMode::Mermaid,
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output
+            .contains("    [2] crashed with SIGABRT (core dumped) :crit, milestone, 2, 0ms\n"));
+        // Being killed with SIGTERM isn't a crash
+        assert!(!output.contains("[3] crashed"));
+        assert_eq!(
+            describe_exit_code(6 | 0x80),
+            "killed by signal 6 (core dumped)"
+        );
+    }
+
+    #[test]
+    fn keeps_orphans_of_subreapers_in_the_tree() {
+        let mut bytes = vec![];
+        crate::builder::TraceBuilder::new()
+            .fork(0, 1)
+            .exec(1, "tini -- sh")
+            .subreaper(1)
+            .fork(1, 2)
+            .exec(2, "sh")
+            .fork(2, 3)
+            .setsid(3)
+            .fork(3, 4)
+            .exec(4, "sleep 1")
+            .exit(3, 0)
+            .exit(2, 0)
+            .advance(1_000_000)
+            .exit(4, 0)
+            .exit(1, 0)
+            .write(JsonWriter::new(&mut bytes))
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        render_to_dir(
+            read_events(bytes.as_slice()).unwrap(),
+            dir.path(),
+            DisplayMode::Mermaid,
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        let chart = std::fs::read_to_string(dir.path().join("chart.mmd")).unwrap();
+        assert!(chart.contains("    [1] subreaper :milestone, 0, 0ms\n"));
+        assert!(!chart.contains("section detached"));
+        let page = std::fs::read_to_string(dir.path().join("pids/4.md")).unwrap();
+        assert!(page.contains("- Parent: PID 3\n- Reparented to: PID 1 (subreaper)\n"));
+    }
+
+    #[test]
+    fn renders_process_memory() {
+        let ms = 1_000_000;
+        let mut bytes = vec![];
+        crate::builder::TraceBuilder::new()
+            .fork(0, 1)
+            .exec(1, "make")
+            .fork(1, 2)
+            .exec(2, "cc -c a.c")
+            .rss(1, 2048)
+            .rss(2, 512)
+            .advance(100 * ms)
+            .rss(1, 2048)
+            .rss(2, 3 * 1024 * 1024)
+            .advance(100 * ms)
+            .rss(1, 1024)
+            .exit(2, 0)
+            .exit(1, 0)
+            .write(JsonWriter::new(&mut bytes))
+            .unwrap();
+        let mut output = vec![];
+        render(
+            bytes.as_slice(),
+            &mut output,
+            DisplayMode::Mermaid,
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains(
+                "    section process memory\n    rss 2.5 MiB :active, 0, 100ms\n    rss 3.0 GiB :active, 100, 100ms\n"
+            ),
+            "{output}"
+        );
+        assert!(output.contains("    [2] cc -c a.c [rss 3.0 GiB] :active, 0, 200ms\n"));
+        assert!(output.contains("    [1] make [rss 2.0 MiB] :"));
+        assert_eq!(format_kb(100), "100 KiB");
+    }
+
+    #[test]
+    fn renders_host_sample_lanes() {
+        let mut events = make_simple_events(0, 0, &[("fork", 1, 0), ("exit", 1, 0)]);
+        for (seq, timestamp) in [(10, 0), (11, 5_000_000)] {
+            events.push(Event::HostSample {
+                seq,
+                timestamp,
+                load: [150, 100, 50],
+                mem_total_kb: 1000,
+                mem_available_kb: 250,
+            });
+        }
+        let mut output = vec![];
+        render(
+            encode_events(&events).as_slice(),
+            &mut output,
+            DisplayMode::Mermaid,
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("    section host load\n    load 1.50 :active, 0, 5ms\n"));
+        assert!(output.contains("    mem 75% used :active, 0, 5ms\n"));
+        assert!(output.contains("    section processes\n"));
+
+        let mut output = vec![];
+        render(
+            encode_events(&events).as_slice(),
+            &mut output,
+            DisplayMode::Sequential,
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        let lines = String::from_utf8(output).unwrap();
+        assert_eq!(lines.lines().count(), 4);
+    }
+
+    #[test]
+    fn every_display_mode_obeys_the_argv_style() {
+        let mut bytes = vec![];
+        crate::builder::TraceBuilder::new()
+            .fork(0, 1)
+            .exec(1, "/usr/bin/make -C src -j8 all install")
+            .exit(1, 0)
+            .write(JsonWriter::new(&mut bytes))
+            .unwrap();
+        let options = RenderOptions {
+            labels: Labels::default().with_argv_style(crate::cli::ArgvStyle::Smart),
+            ..RenderOptions::default()
+        };
+        for mode in [
+            DisplayMode::Mermaid,
+            DisplayMode::Table,
+            DisplayMode::ByProcess,
+        ] {
+            let mut output = vec![];
+            render(bytes.as_slice(), &mut output, mode.clone(), &options).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains("make -C src -j8 ..."), "{mode}: {output}");
+            assert!(!output.contains("install"), "{mode}: {output}");
+        }
+    }
+
+    #[test]
+    fn renders_d2_and_plantuml_charts() {
+        let ms = 1_000_000;
+        let mut bytes = vec![];
+        crate::builder::TraceBuilder::new()
+            .fork(0, 1)
+            .exec(1, "make")
+            .fork(1, 2)
+            .exec(2, "echo \"[done]\"")
+            .advance(5 * ms)
+            .exit(2, 0)
+            .advance(15 * ms)
+            .exit(1, 0)
+            .write(JsonWriter::new(&mut bytes))
+            .unwrap();
+        let options = RenderOptions {
+            highlight_over: Some(10 * ms),
+            ..RenderOptions::default()
+        };
+        let chart = |mode| {
+            let mut output = vec![];
+            render(bytes.as_slice(), &mut output, mode, &options).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        let plantuml = chart(DisplayMode::Plantuml);
+        assert_eq!(
+            plantuml,
+            "@startgantt\n' One day is one millisecond\n\
+             [(1) make] starts D+0 and lasts 20 days and is colored in Red\n\
+             [(2) echo \"(done)\"] starts D+0 and lasts 5 days\n\
+             @endgantt\n"
+        );
+
+        let d2 = chart(DisplayMode::D2);
+        assert!(d2.starts_with("# 20.000ms of the recording per 1000px\ntimeline: {\n"));
+        assert!(d2.contains(
+            "    span: {label: \"[1] make (20.000ms)\"; label.near: outside-right-center; width: 1000; height: 20; style.fill: \"#f4a4a4\"}\n"
+        ), "{d2}");
+        assert!(d2.contains("label: \"[2] echo \\\"[done]\\\" (5.000ms)\"; label.near: outside-right-center; width: 250;"), "{d2}");
+        assert!(d2.ends_with("  }\n}\n"));
+    }
+
+    #[test]
+    fn renders_process_table() {
+        let ms = 1_000_000;
+        let mut bytes = vec![];
+        crate::builder::TraceBuilder::new()
+            .fork(0, 1)
+            .exec(1, "make -j2")
+            .advance(ms)
+            .fork(1, 2)
+            .exec(2, "cc -c a.c")
+            .fork(1, 3)
+            .advance(4 * ms)
+            .usage(
+                2,
+                ResourceUsage {
+                    user_ns: 3 * ms as u64,
+                    ..ResourceUsage::default()
+                },
+            )
+            .exit(2, 1)
+            .advance(5 * ms)
+            .killed(3, 9)
+            .write(JsonWriter::new(&mut bytes))
+            .unwrap();
+        let table = |sort_by, limit| {
+            let options = RenderOptions {
+                sort_by,
+                limit,
+                ..RenderOptions::default()
+            };
+            let mut output = vec![];
+            render(bytes.as_slice(), &mut output, DisplayMode::Table, &options).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        let output = table(TableSort::Start, None);
+        let rows = output.lines().collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            vec![
+                "     pid      ppid      start_ms   duration_ms        cpu_ms      exit  command",
+                "       1         0         0.000         0.000             -   running  make -j2",
+                "       2         1         1.000         4.000         3.000         1  cc -c a.c",
+                "       3         1         1.000         9.000             -   SIGKILL  (fork of 1)",
+            ]
+        );
+        // Every row splits into the same columns, up to the command
+        assert!(rows
+            .iter()
+            .all(|row| row.split_whitespace().nth(5).is_some()));
+
+        let output = table(TableSort::Duration, Some(1));
+        assert_eq!(output.lines().count(), 2);
+        assert!(output.lines().nth(1).unwrap().ends_with("(fork of 1)"));
+        let output = table(TableSort::Pid, None);
+        let pids = output
+            .lines()
+            .skip(1)
+            .map(|row| row.split_whitespace().next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(pids, vec!["1", "2", "3"]);
+    }
+
+    #[test]
+    fn every_display_mode_has_a_renderer() {
+        // External renderers aren't listed since they aren't known ahead of time
+        for mode in DisplayMode::value_variants() {
+            assert!(
+                renderer(&mode.to_string(), &RenderOptions::default()).is_some(),
+                "{mode}"
+            );
+        }
+        assert!(renderer("nope", &RenderOptions::default()).is_none());
+    }
+
+    #[test]
+    fn gha_summary_lists_failures() {
+        let events = make_simple_events(
+            0,
+            0,
+            &[
+                ("fork", 1, 0),
+                ("exec_full", 1, 0),
+                ("fork", 2, 1),
+                ("exit_fail", 2, 1),
+                ("exit", 1, 0),
+            ],
+        );
+        let ingester = read_events(encode_events(&events).as_slice()).unwrap();
+        let tree = ProcessTree::prepare(ingester, &Labels::default()).unwrap();
+        let mut output = vec![];
+        GhaSummaryRenderer::default()
+            .render(&tree, &mut output)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("across **2** processes"));
+        assert!(output.contains("| 2 | `(fork of 1)` | exited with status 1 |"));
+        assert!(output.contains("```mermaid\ngantt\n"));
+    }
+
+    #[test]
+    fn fences_mermaid_for_markdown() {
+        let events = make_simple_events(
+            0,
+            0,
+            &[
+                ("fork", 1, 0),
+                ("exec_full", 1, 0),
+                ("fork", 2, 1),
+                ("exit", 2, 1),
+                ("exit", 1, 0),
+            ],
+        );
+        let options = RenderOptions {
+            heading: true,
+            ..Default::default()
+        };
+        let mut output = vec![];
+        render(
+            encode_events(&events).as_slice(),
+            &mut output,
+            DisplayMode::MermaidMd,
+            &options,
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("## proctrace: `"));
+        assert!(output.contains("across **2** processes.\n\n```mermaid\ngantt\n"));
+        assert!(output.ends_with("```\n"));
+    }
 }
@@ -0,0 +1,156 @@
+//! Finds commands that were run over and over with the same arguments, which are
+//! candidates for caching their results.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    analyze::format_nanos,
+    models::{Event, EventStore},
+};
+
+/// The default number of times a command has to be run to be reported.
+pub const DEFAULT_MIN_REPEATS: usize = 3;
+
+/// A command line that was run more than once.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RepeatedCommand {
+    pub filename: String,
+    pub args: String,
+    /// The number of times it was exec'd.
+    pub count: usize,
+    /// The combined time spent running it, in nanoseconds.
+    pub total: u128,
+}
+
+impl RepeatedCommand {
+    /// The time that would be saved if every invocation after the first was free,
+    /// e.g. because its result was cached.
+    pub fn savings(&self) -> u128 {
+        let mean = self.total / self.count as u128;
+        self.total.saturating_sub(mean)
+    }
+}
+
+/// The command lines in a recording that were run at least a minimum number of times.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RepeatReport {
+    /// Sorted by how much time caching them would save, most first.
+    pub commands: Vec<RepeatedCommand>,
+}
+
+impl RepeatReport {
+    /// Groups the execs in a recording by their file name and arguments.
+    ///
+    /// An invocation runs from its exec until the process exits or execs something
+    /// else. Invocations that never finished aren't counted.
+    pub fn from_store(store: &EventStore, min_count: usize) -> Self {
+        let mut groups: HashMap<(String, String), (usize, u128)> = HashMap::new();
+        for (_, buffer) in store.iter() {
+            for (idx, event) in buffer.iter().enumerate() {
+                let Event::ExecFull { filename, args, .. } = event else {
+                    continue;
+                };
+                let Some(stop) = buffer
+                    .iter()
+                    .skip(idx + 1)
+                    .find(|event| event.is_exec_full() || event.is_exit())
+                else {
+                    continue;
+                };
+                let group = groups.entry((filename.clone(), args.joined())).or_default();
+                group.0 += 1;
+                group.1 += stop.timestamp().saturating_sub(event.timestamp());
+            }
+        }
+        let mut commands = groups
+            .into_iter()
+            .filter(|(_, (count, _))| *count >= min_count.max(2))
+            .map(|((filename, args), (count, total))| RepeatedCommand {
+                filename,
+                args,
+                count,
+                total,
+            })
+            .collect::<Vec<_>>();
+        commands.sort_by(|a, b| {
+            b.savings()
+                .cmp(&a.savings())
+                .then_with(|| a.args.cmp(&b.args))
+        });
+        Self { commands }
+    }
+
+    /// Formats the first `limit` commands as a table.
+    pub fn to_table(&self, limit: usize) -> String {
+        if self.commands.is_empty() {
+            return "no repeated commands\n".to_string();
+        }
+        let mut table = format!(
+            "{:>6}  {:>12}  {:>12}  {:>12}  {}\n",
+            "runs", "total", "mean", "cacheable", "command"
+        );
+        for command in self.commands.iter().take(limit) {
+            table.push_str(&format!(
+                "{:>6}  {:>12}  {:>12}  {:>12}  {}\n",
+                command.count,
+                format_nanos(command.total),
+                format_nanos(command.total / command.count as u128),
+                format_nanos(command.savings()),
+                command.args
+            ));
+        }
+        if self.commands.len() > limit {
+            table.push_str(&format!("... and {} more\n", self.commands.len() - limit));
+        }
+        let savings = self
+            .commands
+            .iter()
+            .map(|command| command.savings())
+            .sum::<u128>();
+        table.push_str(&format!(
+            "caching every repeated command would save up to {}\n",
+            format_nanos(savings)
+        ));
+        table
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::builder::TraceBuilder;
+
+    use super::*;
+
+    #[test]
+    fn groups_identical_command_lines() {
+        let mut builder = TraceBuilder::new();
+        let runs = [
+            (2, "/bin/git rev-parse HEAD", 10),
+            (3, "/bin/git rev-parse HEAD", 20),
+            (4, "/bin/git rev-parse HEAD", 30),
+            (5, "/bin/git status", 3),
+            (6, "/bin/git status", 3),
+            (7, "/bin/git status", 3),
+            (8, "/bin/git log", 3),
+        ];
+        for (pid, args, duration) in runs {
+            builder = builder
+                .fork(1, pid)
+                .exec(pid, args)
+                .advance(duration)
+                .exit(pid, 0)
+                .advance(10);
+        }
+        let store = EventStore::from_events(builder.build());
+
+        let report = RepeatReport::from_store(&store, 3);
+        assert_eq!(report.commands.len(), 2);
+        let rev_parse = &report.commands[0];
+        assert_eq!(rev_parse.args, "/bin/git rev-parse HEAD");
+        assert_eq!((rev_parse.count, rev_parse.total), (3, 60));
+        assert_eq!(rev_parse.savings(), 40);
+        assert_eq!(report.commands[1].args, "/bin/git status");
+    }
+}
@@ -0,0 +1,249 @@
+//! Generating the `bpftrace` script that `record` runs.
+//!
+//! The script is put together from a template for each [EventKind], so the probes for
+//! events that weren't asked for are never attached, followed by the optional probes
+//! for mounts and CPU samples. Every template prints to the same `@seq` counter.
+//!
+//! Unless every process on the machine is asked for, the probes are filtered in the
+//! kernel so that only the descendants of the recorder (and the processes in adopted
+//! cgroups) make it to userspace, since on a busy host the rest can be most of the
+//! events and can cause events to be lost.
+
+use anyhow::anyhow;
+
+use crate::cli::EventKind;
+
+type Error = anyhow::Error;
+
+/// The ticks that every script has, whichever events are recorded.
+const COMMON_PROBES: &str = include_str!("../assets/probes/common.bt");
+
+/// The probes that record each kind of event, in the order they're added to the script.
+const EVENT_PROBES: [(EventKind, &str); 10] = [
+    (EventKind::Fork, include_str!("../assets/probes/fork.bt")),
+    (EventKind::Exec, include_str!("../assets/probes/exec.bt")),
+    (EventKind::Exit, include_str!("../assets/probes/exit.bt")),
+    (
+        EventKind::Setsid,
+        include_str!("../assets/probes/setsid.bt"),
+    ),
+    (
+        EventKind::Setpgid,
+        include_str!("../assets/probes/setpgid.bt"),
+    ),
+    (EventKind::Chdir, include_str!("../assets/probes/chdir.bt")),
+    (
+        EventKind::Namespace,
+        include_str!("../assets/probes/namespace.bt"),
+    ),
+    (
+        EventKind::Ptrace,
+        include_str!("../assets/probes/ptrace.bt"),
+    ),
+    (
+        EventKind::Sandbox,
+        include_str!("../assets/probes/sandbox.bt"),
+    ),
+    (
+        EventKind::Subreaper,
+        include_str!("../assets/probes/subreaper.bt"),
+    ),
+];
+
+/// The probes that keep track of the descendants of the recorder when events are
+/// filtered in the kernel, once the PID of the recorder is filled in.
+const FILTER_PROBES: &str = include_str!("../assets/probes/filter.bt");
+
+/// The probes that `record --trace-mounts` adds to the script.
+const MOUNTS_PROBES: &str = include_str!("../assets/mounts.bt");
+
+/// The probes that `record --sample-cpu` adds to the script, once the rate and the PID
+/// of the recorder are filled in.
+const CPU_PROBES: &str = include_str!("../assets/cpu.bt");
+
+/// What the generated script records.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ScriptOptions {
+    /// The kinds of events to record, or every kind if it's empty.
+    pub events: Vec<EventKind>,
+    /// Record mounts and unmounts.
+    pub trace_mounts: bool,
+    /// How many times a second to sample which recorded processes are on a CPU, if at
+    /// all.
+    pub sample_cpu: Option<u32>,
+    /// Which processes the probes fire for, or every process if it's `None`.
+    pub filter: Option<KernelFilter>,
+    /// The PID of the recorder, whose descendants are the ones sampled on a CPU and
+    /// the ones that pass the filter.
+    pub recorder_pid: u32,
+}
+
+/// Which processes the probes fire for when events are filtered in the kernel.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct KernelFilter {
+    /// The IDs of cgroups whose processes are recorded too, whether or not they're
+    /// descendants of the recorder.
+    pub cgroups: Vec<u64>,
+}
+
+impl KernelFilter {
+    /// The predicate that's added to every probe that records events.
+    fn predicate(&self) -> String {
+        let mut predicate = "@traced[tid]".to_string();
+        for id in self.cgroups.iter() {
+            predicate.push_str(&format!(" || cgroup == {id}"));
+        }
+        predicate
+    }
+
+    /// The predicate that's added to the probes that record forks, which also fire for
+    /// the recorder itself so that the fork of the command it starts is recorded.
+    fn fork_predicate(&self, recorder_pid: u32) -> String {
+        format!("pid == {recorder_pid} || {}", self.predicate())
+    }
+}
+
+impl ScriptOptions {
+    /// Returns `true` if events of this kind are recorded.
+    pub fn records(&self, kind: EventKind) -> bool {
+        self.events.is_empty() || self.events.contains(&kind)
+    }
+}
+
+/// Generates the `bpftrace` script that records what `options` asks for.
+///
+/// Fails if fork or exit events are left out, since the process tree can't be followed
+/// without them.
+pub fn bpftrace_script(options: &ScriptOptions) -> Result<String, Error> {
+    for required in [EventKind::Fork, EventKind::Exit] {
+        if !options.records(required) {
+            return Err(anyhow!(
+                "{required} events are needed to follow the process tree, add them to --events"
+            ));
+        }
+    }
+    let filtered = |kind: Option<EventKind>, probes: &str| match options.filter {
+        Some(ref filter) => {
+            let predicate = if kind == Some(EventKind::Fork) {
+                filter.fork_predicate(options.recorder_pid)
+            } else {
+                filter.predicate()
+            };
+            // Every probe body starts on a line of its own, right after the probe
+            probes.replace("\n{\n", &format!("\n/{predicate}/\n{{\n"))
+        }
+        None => probes.to_string(),
+    };
+    let mut script = String::new();
+    if options.filter.is_some() {
+        script.push_str(
+            &FILTER_PROBES.replace("PROCTRACE_RECORDER_PID", &options.recorder_pid.to_string()),
+        );
+        script.push('\n');
+    }
+    for (kind, probes) in EVENT_PROBES.iter() {
+        if !options.records(*kind) {
+            continue;
+        }
+        let probes = filtered(Some(*kind), probes).replace(
+            "PROCTRACE_TRACE_NAMESPACES",
+            if options.records(EventKind::Namespace) {
+                "1"
+            } else {
+                "0"
+            },
+        );
+        script.push_str(&probes);
+        script.push('\n');
+    }
+    script.push_str(COMMON_PROBES);
+    if options.trace_mounts {
+        script.push('\n');
+        script.push_str(&filtered(None, MOUNTS_PROBES));
+    }
+    if let Some(hz) = options.sample_cpu {
+        let cpu_probes = CPU_PROBES
+            .replace("PROCTRACE_CPU_HZ", &hz.to_string())
+            .replace("PROCTRACE_RECORDER_PID", &options.recorder_pid.to_string());
+        script.push('\n');
+        script.push_str(&cpu_probes);
+    }
+    Ok(script)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn records_every_kind_by_default() {
+        let script = bpftrace_script(&ScriptOptions::default()).unwrap();
+        for probe in [
+            "sys_exit_clone3",
+            "sys_exit_setpgid",
+            "sys_exit_prctl",
+            "TICK:",
+        ] {
+            assert!(script.contains(probe), "missing {probe}");
+        }
+        assert!(!script.contains("PROCTRACE_"));
+        assert!(!script.contains("sys_exit_mount"));
+        assert!(!script.contains("@traced"));
+    }
+
+    #[test]
+    fn leaves_out_probes_that_werent_asked_for() {
+        let options = ScriptOptions {
+            events: vec![EventKind::Fork, EventKind::Exec, EventKind::Exit],
+            sample_cpu: Some(99),
+            recorder_pid: 42,
+            ..Default::default()
+        };
+        let script = bpftrace_script(&options).unwrap();
+        assert!(script.contains("sys_exit_execve"));
+        assert!(!script.contains("setpgid"));
+        assert!(!script.contains("setsid"));
+        assert!(!script.contains("SUBREAPER"));
+        assert!(script.contains("if (0 && $namespaces != 0"));
+        assert!(script.contains("profile:hz:99"));
+        assert!(script.contains("pid == 42"));
+
+        let options = ScriptOptions {
+            events: vec![EventKind::Fork, EventKind::Exec],
+            ..Default::default()
+        };
+        assert!(bpftrace_script(&options).is_err());
+    }
+
+    #[test]
+    fn filters_probes_in_the_kernel() {
+        let options = ScriptOptions {
+            events: vec![EventKind::Fork, EventKind::Exec, EventKind::Exit],
+            trace_mounts: true,
+            filter: Some(KernelFilter { cgroups: vec![7] }),
+            recorder_pid: 42,
+            ..Default::default()
+        };
+        let script = bpftrace_script(&options).unwrap();
+        assert!(script.contains("/pid == 42 || @traced[tid]/"));
+        // The recorder isn't traced itself, but the fork of the command it starts is
+        for probe in [
+            "sys_enter_clone",
+            "sys_enter_clone3",
+            "sys_exit_clone,\ntracepoint:syscalls:sys_exit_clone3",
+        ] {
+            assert!(
+                script.contains(&format!(
+                    "tracepoint:syscalls:{probe}\n/pid == 42 || @traced[tid] || cgroup == 7/\n{{"
+                )),
+                "{probe}"
+            );
+        }
+        assert!(script
+            .contains("tracepoint:sched:sched_process_exit\n/@traced[tid] || cgroup == 7/\n{"));
+        assert!(script
+            .contains("tracepoint:syscalls:sys_exit_umount\n/@traced[tid] || cgroup == 7/\n{"));
+        // The ticks keep coming whichever processes are recorded
+        assert!(script.contains("interval:ms:100\n{"));
+    }
+}
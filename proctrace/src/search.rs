@@ -0,0 +1,147 @@
+//! Finds the processes in a recording that ran a command, along with the chain of
+//! processes that started them, e.g. to find out who launched a `curl`.
+
+use regex_lite::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    analyze::command_name,
+    models::{Event, EventStore},
+};
+
+/// A process in the chain of ancestors of a match.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ancestor {
+    pub pid: i32,
+    /// The file name of the last thing the process exec'd, or "fork" if it never
+    /// exec'd anything.
+    pub name: String,
+}
+
+/// A process that exec'd a command matching the pattern.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchMatch {
+    pub pid: i32,
+    /// The command line of the exec that matched, the last one if several did.
+    pub command: String,
+    /// The processes that led to this one, starting from the root and ending with
+    /// this process, which is named after the exec that matched.
+    pub ancestry: Vec<Ancestor>,
+}
+
+impl SearchMatch {
+    /// Formats the match as its ancestry followed by its command line, e.g.
+    /// `make[1] -> sh[12] -> curl[42]: curl -s https://example.com`.
+    pub fn to_line(&self) -> String {
+        let chain = self
+            .ancestry
+            .iter()
+            .map(|ancestor| format!("{}[{}]", ancestor.name, ancestor.pid))
+            .collect::<Vec<_>>()
+            .join(" -> ");
+        format!("{chain}: {}", self.command.trim())
+    }
+}
+
+/// Returns the processes whose execs match `pattern` in the order they started.
+///
+/// The pattern is matched against the file name and the command line of every exec
+/// of a process, so a process that ran a matching command and then exec'd something
+/// else still matches.
+pub fn search(store: &EventStore, pattern: &Regex) -> Vec<SearchMatch> {
+    let mut matches = vec![];
+    for (pid, buffer) in store.iter() {
+        let matched = buffer.iter().rev().find_map(|event| match event {
+            Event::ExecFull { filename, args, .. } => {
+                let command = args.joined();
+                (pattern.is_match(filename) || pattern.is_match(&command))
+                    .then(|| (command_name(filename), command))
+            }
+            _ => None,
+        });
+        let Some((name, command)) = matched else {
+            continue;
+        };
+        let start = buffer.front().map(|event| event.timestamp());
+        let mut ancestry = ancestry(store, pid);
+        if let Some(last) = ancestry.last_mut() {
+            last.name = name;
+        }
+        let search_match = SearchMatch {
+            pid,
+            command,
+            ancestry,
+        };
+        matches.push((start, search_match));
+    }
+    matches.sort_by_key(|(start, search_match)| (*start, search_match.pid));
+    matches
+        .into_iter()
+        .map(|(_, search_match)| search_match)
+        .collect()
+}
+
+/// Returns `pid` and the ancestors of it that are in the recording, root first.
+fn ancestry(store: &EventStore, pid: i32) -> Vec<Ancestor> {
+    let mut ancestry = std::iter::once(pid)
+        .chain(store.ancestors(pid))
+        .map_while(|pid| {
+            let name = store
+                .events_of(pid)?
+                .iter()
+                .rev()
+                .find_map(|event| match event {
+                    Event::ExecFull { filename, .. } => Some(command_name(filename)),
+                    _ => None,
+                })
+                .unwrap_or_else(|| "fork".to_string());
+            Some(Ancestor { pid, name })
+        })
+        .collect::<Vec<_>>();
+    ancestry.reverse();
+    ancestry
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{builder::TraceBuilder, render::read_events, writers::JsonWriter};
+
+    use super::*;
+
+    #[test]
+    fn finds_who_launched_a_command() {
+        let mut bytes = vec![];
+        TraceBuilder::new()
+            .fork(0, 1)
+            .exec(1, "make -j2")
+            .fork(1, 2)
+            .exec(2, "/bin/sh -c ./fetch.sh")
+            .fork(2, 3)
+            .fork(3, 4)
+            .exec(4, "/usr/bin/curl -s https://example.com")
+            .fork(1, 5)
+            .exec(5, "/usr/bin/curl -O https://example.org/b.tar")
+            .exec(5, "tar xf b.tar")
+            .fork(1, 6)
+            .exec(6, "cc -c curl.c")
+            .write(JsonWriter::new(&mut bytes))
+            .unwrap();
+        let ingester = read_events(bytes.as_slice()).unwrap();
+        let store = ingester.tracked_events();
+
+        let matches = search(store, &Regex::new(r"^/usr/bin/curl").unwrap());
+        let lines = matches
+            .iter()
+            .map(|search_match| search_match.to_line())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                "make[1] -> sh[2] -> fork[3] -> curl[4]: /usr/bin/curl -s https://example.com",
+                "make[1] -> curl[5]: /usr/bin/curl -O https://example.org/b.tar",
+            ]
+        );
+        assert_eq!(search(store, &Regex::new("curl").unwrap()).len(), 3);
+        assert!(search(store, &Regex::new("wget").unwrap()).is_empty());
+    }
+}
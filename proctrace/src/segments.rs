@@ -0,0 +1,384 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cli::Compression,
+    utils::{compressed_output_stream, make_path_absolute, new_output_file},
+    writers::OutputStream,
+};
+
+type Error = anyhow::Error;
+
+/// The suffix used for manifest files.
+pub const MANIFEST_SUFFIX: &str = ".manifest.json";
+
+/// The name of the manifest that `proctrace bench` writes to its output directory.
+pub const BENCH_MANIFEST: &str = "runs.manifest.json";
+
+/// When to start a new segment of a segmented recording.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RotationPolicy {
+    /// Start a new segment once this many (uncompressed) bytes have been written.
+    pub max_bytes: Option<u64>,
+    /// Start a new segment once the current one has been open for this long.
+    pub max_age: Option<Duration>,
+}
+
+impl RotationPolicy {
+    /// Returns `true` if this policy would ever rotate.
+    pub fn is_enabled(&self) -> bool {
+        self.max_bytes.is_some() || self.max_age.is_some()
+    }
+}
+
+/// The list of segments that make up a segmented recording, in order, or of the
+/// recordings made by `proctrace bench`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub segments: Vec<Segment>,
+    /// The runs of `proctrace bench`, which unlike segments are separate recordings.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub runs: Vec<Run>,
+}
+
+/// A single file of a segmented recording.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Segment {
+    /// The path of the segment, relative to the manifest.
+    pub path: PathBuf,
+}
+
+/// The recording of a single run of `proctrace bench`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Run {
+    /// The number of the run, starting from 1.
+    pub index: usize,
+    /// The path of the recording, relative to the manifest.
+    pub path: PathBuf,
+    /// When the run started, in nanoseconds since the Unix epoch.
+    pub started_at: Option<u128>,
+    /// The exit code of the command in the format of a wait status, the same as in
+    /// its exit event, if it was recorded.
+    pub exit_code: Option<i32>,
+}
+
+impl Manifest {
+    /// Reads a manifest from disk.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let file = std::fs::File::open(path.as_ref()).context("failed to open manifest")?;
+        serde_json::from_reader(file).context("failed to parse manifest")
+    }
+
+    /// Writes the manifest to disk, replacing any existing manifest.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let file = new_output_file(path)?;
+        serde_json::to_writer_pretty(file, self).context("failed to write manifest")
+    }
+
+    /// Returns the paths of the segments resolved relative to the manifest's location.
+    pub fn segment_paths(&self, manifest_path: impl AsRef<Path>) -> Vec<PathBuf> {
+        let dir = manifest_dir(manifest_path.as_ref());
+        self.segments.iter().map(|s| dir.join(&s.path)).collect()
+    }
+
+    /// Returns the paths of the runs resolved relative to the manifest's location.
+    pub fn run_paths(&self, manifest_path: impl AsRef<Path>) -> Vec<PathBuf> {
+        let dir = manifest_dir(manifest_path.as_ref());
+        self.runs.iter().map(|run| dir.join(&run.path)).collect()
+    }
+}
+
+/// Returns the directory that the paths in a manifest are relative to.
+fn manifest_dir(manifest_path: &Path) -> PathBuf {
+    manifest_path
+        .parent()
+        .map(|p| p.to_path_buf())
+        .unwrap_or_default()
+}
+
+/// Returns `true` if the path refers to a manifest of a segmented recording.
+pub fn is_manifest_path(path: impl AsRef<Path>) -> bool {
+    path.as_ref()
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.ends_with(MANIFEST_SUFFIX))
+}
+
+/// Splits a file name like "trace.json.zst" into ("trace", ".json.zst").
+fn split_file_name(path: &Path) -> Result<(String, String), Error> {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or(anyhow!("output path has no file name"))?;
+    match name.split_once('.') {
+        Some((stem, rest)) => Ok((stem.to_string(), format!(".{rest}"))),
+        None => Ok((name.to_string(), String::new())),
+    }
+}
+
+/// A writer that splits its output into numbered segments (`trace.0001.json`, ...)
+/// and keeps a manifest (`trace.manifest.json`) listing them in order.
+///
+/// Segments are only rotated after a newline has been written so that line-based
+/// recordings are never split in the middle of an event.
+pub struct SegmentedWriter {
+    dir: PathBuf,
+    stem: String,
+    suffix: String,
+    compression: Compression,
+    policy: RotationPolicy,
+    manifest: Manifest,
+    current: Option<Box<dyn OutputStream>>,
+    bytes_written: u64,
+    opened_at: Instant,
+    /// Set once the current segment is full, the next segment is opened on the next write.
+    rotation_pending: bool,
+    /// Set from elsewhere to rotate at the end of the next line regardless of the policy.
+    rotation_requested: Option<Arc<AtomicBool>>,
+}
+
+impl SegmentedWriter {
+    /// Creates a new writer for segments named after `path`.
+    ///
+    /// The compression of each segment is guessed from the extension of `path`
+    /// if it isn't specified.
+    pub fn new(
+        path: impl AsRef<Path>,
+        compression: Option<Compression>,
+        policy: RotationPolicy,
+    ) -> Result<Self, Error> {
+        let path = make_path_absolute(path)?;
+        let (stem, suffix) = split_file_name(&path)?;
+        let dir = path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+        let mut writer = Self {
+            dir,
+            stem,
+            suffix,
+            compression: compression.unwrap_or_else(|| Compression::from_path(&path)),
+            policy,
+            manifest: Manifest::default(),
+            current: None,
+            bytes_written: 0,
+            opened_at: Instant::now(),
+            rotation_pending: false,
+            rotation_requested: None,
+        };
+        writer.open_next_segment()?;
+        Ok(writer)
+    }
+
+    /// Also rotates after the next line that's written once `requested` is set, which
+    /// is cleared again when that happens.
+    pub fn with_rotation_requests(mut self, requested: Arc<AtomicBool>) -> Self {
+        self.rotation_requested = Some(requested);
+        self
+    }
+
+    /// Returns the path of the manifest for this recording.
+    pub fn manifest_path(&self) -> PathBuf {
+        self.dir.join(format!("{}{MANIFEST_SUFFIX}", self.stem))
+    }
+
+    /// Closes the current segment and starts writing to a new one.
+    pub fn rotate(&mut self) -> Result<(), Error> {
+        if let Some(mut current) = self.current.take() {
+            current.finish().context("failed to finish segment")?;
+        }
+        self.open_next_segment()
+    }
+
+    fn open_next_segment(&mut self) -> Result<(), Error> {
+        let index = self.manifest.segments.len() + 1;
+        let name = format!("{}.{index:04}{}", self.stem, self.suffix);
+        let file = new_output_file(self.dir.join(&name))?;
+        let writer = compressed_output_stream(std::io::BufWriter::new(file), self.compression)?;
+        self.current = Some(writer);
+        self.bytes_written = 0;
+        self.opened_at = Instant::now();
+        self.rotation_pending = false;
+        self.manifest.segments.push(Segment { path: name.into() });
+        // Keep the manifest up to date so that it's usable even if we're killed
+        self.manifest.write(self.manifest_path())
+    }
+
+    fn should_rotate(&self) -> bool {
+        let too_big = self
+            .policy
+            .max_bytes
+            .is_some_and(|max| self.bytes_written >= max);
+        let too_old = self
+            .policy
+            .max_age
+            .is_some_and(|max| self.opened_at.elapsed() >= max);
+        let requested = self
+            .rotation_requested
+            .as_ref()
+            .is_some_and(|requested| requested.swap(false, Ordering::SeqCst));
+        too_big || too_old || requested
+    }
+}
+
+impl Write for SegmentedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.rotation_pending {
+            self.rotate().map_err(std::io::Error::other)?;
+        }
+        let current = self
+            .current
+            .as_mut()
+            .ok_or(std::io::Error::other("no open segment"))?;
+        let n = current.write(buf)?;
+        self.bytes_written += n as u64;
+        if n > 0 && buf[n - 1] == b'\n' && self.should_rotate() {
+            self.rotation_pending = true;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.current.as_mut() {
+            Some(current) => current.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl OutputStream for SegmentedWriter {
+    /// Finishes the current segment, after which nothing can be written.
+    fn finish(&mut self) -> std::io::Result<()> {
+        match self.current.take() {
+            Some(mut current) => current.finish(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::fmt::Debug for SegmentedWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SegmentedWriter")
+            .field("dir", &self.dir)
+            .field("stem", &self.stem)
+            .field("policy", &self.policy)
+            .field("segments", &self.manifest.segments.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use crate::{
+        builder::TraceBuilder, render::read_events, utils::new_buffered_input_stream,
+        writers::JsonWriter,
+    };
+
+    use super::*;
+
+    #[test]
+    fn rotates_by_size_on_line_boundaries() {
+        let dir = tempfile::tempdir().unwrap();
+        let policy = RotationPolicy {
+            max_bytes: Some(10),
+            max_age: None,
+        };
+        let mut writer = SegmentedWriter::new(dir.path().join("trace.json"), None, policy).unwrap();
+        for i in 0..5 {
+            writer.write_all(format!("line {i} ").as_bytes()).unwrap();
+            writer.write_all(b"is long\n").unwrap();
+        }
+        let manifest_path = writer.manifest_path();
+        drop(writer);
+
+        let manifest = Manifest::read(&manifest_path).unwrap();
+        // Every line fills a segment
+        assert_eq!(manifest.segments.len(), 5);
+        assert_eq!(manifest.segments[0].path, PathBuf::from("trace.0001.json"));
+
+        let mut contents = String::new();
+        new_buffered_input_stream(&manifest_path)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        let expected = (0..5)
+            .map(|i| format!("line {i} is long\n"))
+            .collect::<String>();
+        assert_eq!(contents, expected);
+    }
+
+    #[test]
+    fn rotates_on_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let policy = RotationPolicy {
+            max_bytes: Some(1_000_000),
+            max_age: None,
+        };
+        let requested = Arc::new(AtomicBool::new(false));
+        let mut writer = SegmentedWriter::new(dir.path().join("trace.json"), None, policy)
+            .unwrap()
+            .with_rotation_requests(requested.clone());
+        writer.write_all(b"first\n").unwrap();
+        requested.store(true, Ordering::SeqCst);
+        // The current line is finished first
+        writer.write_all(b"second ").unwrap();
+        writer.write_all(b"line\n").unwrap();
+        writer.write_all(b"third\n").unwrap();
+        let manifest_path = writer.manifest_path();
+        drop(writer);
+
+        let manifest = Manifest::read(&manifest_path).unwrap();
+        assert_eq!(manifest.segments.len(), 2);
+        assert!(!requested.load(Ordering::SeqCst));
+        let second = std::fs::read_to_string(dir.path().join("trace.0002.json")).unwrap();
+        assert_eq!(second, "third\n");
+    }
+
+    #[test]
+    fn splits_file_names() {
+        let (stem, suffix) = split_file_name(Path::new("/tmp/trace.json.zst")).unwrap();
+        assert_eq!(stem, "trace");
+        assert_eq!(suffix, ".json.zst");
+    }
+
+    #[test]
+    fn merges_bench_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manifest = Manifest::default();
+        for index in 1..=2 {
+            let path = PathBuf::from(format!("run-{index:04}.json"));
+            let file = std::fs::File::create(dir.path().join(&path)).unwrap();
+            TraceBuilder::new()
+                .fork(0, 1)
+                .exec(1, "make")
+                .advance(1_000_000)
+                .exit(1, 0)
+                .write(JsonWriter::new(file))
+                .unwrap();
+            manifest.runs.push(Run {
+                index,
+                path,
+                started_at: None,
+                exit_code: Some(0),
+            });
+        }
+        let manifest_path = dir.path().join(BENCH_MANIFEST);
+        manifest.write(&manifest_path).unwrap();
+
+        let reader = new_buffered_input_stream(&manifest_path).unwrap();
+        let ingester = read_events(reader).unwrap();
+        // Both runs used the same PID, so the second one is renumbered
+        assert_eq!(ingester.tracked_events().len(), 2);
+        assert!(is_manifest_path(&manifest_path));
+    }
+}
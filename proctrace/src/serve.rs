@@ -0,0 +1,222 @@
+//! Streams events to HTTP clients as server-sent events.
+//!
+//! Events are published to a [`Broadcaster`] by a source, either a finished recording or
+//! a recorder shipping its events with `record --ship`, and every client that connects
+//! to `/events` receives all of the events published so far followed by new ones as
+//! they arrive.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+};
+
+use anyhow::Context;
+
+use crate::{models::MetadataItem, reader::TraceReader};
+
+type Error = anyhow::Error;
+
+/// A message sent to subscribers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// A line of JSON, either an event or the recording's metadata.
+    Line(String),
+    /// The recording finished.
+    End,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    history: Vec<Message>,
+    subscribers: Vec<Sender<Message>>,
+}
+
+/// Sends each published message to every subscriber, replaying the messages that were
+/// published before a subscriber joined.
+#[derive(Debug, Default)]
+pub struct Broadcaster {
+    state: Mutex<State>,
+}
+
+impl Broadcaster {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sends a message to every subscriber.
+    ///
+    /// Once a recording ends the next message starts a new recording, so the
+    /// history is cleared and subscribers only see the new recording.
+    pub fn publish(&self, message: Message) {
+        let mut state = self.state.lock().expect("broadcaster lock was poisoned");
+        if state.history.last() == Some(&Message::End) {
+            state.history.clear();
+        }
+        state
+            .subscribers
+            .retain(|subscriber| subscriber.send(message.clone()).is_ok());
+        state.history.push(message);
+    }
+
+    /// Returns a receiver for the messages published so far and all future messages.
+    pub fn subscribe(&self) -> Receiver<Message> {
+        let (sender, receiver) = channel();
+        let mut state = self.state.lock().expect("broadcaster lock was poisoned");
+        for message in state.history.iter() {
+            // The receiver is still in scope, so this can't fail
+            let _ = sender.send(message.clone());
+        }
+        state.subscribers.push(sender);
+        receiver
+    }
+}
+
+/// Publishes the events of a recording, followed by the end of the recording.
+pub fn publish_recording(reader: impl BufRead, broadcaster: &Broadcaster) -> Result<(), Error> {
+    let events = TraceReader::detect(reader)?;
+    if let Some(metadata) = events.metadata() {
+        let item = MetadataItem::Metadata(metadata.clone());
+        let line = serde_json::to_string(&item).context("failed to serialize metadata")?;
+        broadcaster.publish(Message::Line(line));
+    }
+    for event in events {
+        let event = event.context("failed to read event")?;
+        let line = serde_json::to_string(&event).context("failed to serialize event")?;
+        broadcaster.publish(Message::Line(line));
+    }
+    broadcaster.publish(Message::End);
+    Ok(())
+}
+
+/// Accepts recorders shipping events to `listener` (see `record --ship`) one at a time,
+/// publishing each line they send.
+pub fn publish_shipped(listener: TcpListener, broadcaster: &Broadcaster) -> Result<(), Error> {
+    for stream in listener.incoming() {
+        let stream = stream.context("failed to accept connection from recorder")?;
+        publish_lines(stream, broadcaster);
+    }
+    Ok(())
+}
+
+/// Publishes each line of `reader`, followed by the end of the recording once the
+/// reader is exhausted or fails.
+pub fn publish_lines(reader: impl Read, broadcaster: &Broadcaster) {
+    for line in BufReader::new(reader).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if !line.trim().is_empty() {
+            broadcaster.publish(Message::Line(line));
+        }
+    }
+    broadcaster.publish(Message::End);
+}
+
+/// Serves the published events at `/events` on `listener`, with a thread per client.
+pub fn serve(listener: TcpListener, broadcaster: Arc<Broadcaster>) -> Result<(), Error> {
+    for stream in listener.incoming() {
+        let stream = stream.context("failed to accept connection")?;
+        let broadcaster = broadcaster.clone();
+        std::thread::spawn(move || {
+            // The client going away isn't an error worth reporting
+            let _ = respond(stream, &broadcaster);
+        });
+    }
+    Ok(())
+}
+
+/// Answers a single HTTP request, streaming events until the recording ends or the
+/// client disconnects.
+fn respond(stream: TcpStream, broadcaster: &Broadcaster) -> Result<(), Error> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let mut stream = reader.into_inner();
+    let mut parts = request_line.split_whitespace();
+    if (parts.next(), parts.next()) != (Some("GET"), Some("/events")) {
+        stream.write_all(
+            b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        )?;
+        return Ok(());
+    }
+    stream.write_all(
+        b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+    )?;
+    for message in broadcaster.subscribe() {
+        match message {
+            Message::Line(line) => write!(stream, "data: {line}\n\n")?,
+            Message::End => {
+                stream.write_all(b"event: end\ndata:\n\n")?;
+                break;
+            }
+        }
+        stream.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        ingest::test::make_simple_events,
+        writers::{EventWrite, JsonWriter},
+    };
+
+    use super::*;
+
+    #[test]
+    fn replays_history_to_late_subscribers() {
+        let broadcaster = Broadcaster::new();
+        let early = broadcaster.subscribe();
+        broadcaster.publish(Message::Line("a".to_string()));
+        let late = broadcaster.subscribe();
+        broadcaster.publish(Message::End);
+        let expected = vec![Message::Line("a".to_string()), Message::End];
+        assert_eq!(early.try_iter().collect::<Vec<_>>(), expected);
+        assert_eq!(late.try_iter().collect::<Vec<_>>(), expected);
+
+        // A new recording replaces the old one
+        broadcaster.publish(Message::Line("b".to_string()));
+        let next = broadcaster.subscribe();
+        assert_eq!(
+            next.try_iter().collect::<Vec<_>>(),
+            vec![Message::Line("b".to_string())]
+        );
+    }
+
+    #[test]
+    fn streams_events_over_http() {
+        let events = make_simple_events(0, 0, &[("fork", 1, 0), ("exit", 1, 0)]);
+        let mut recording = vec![];
+        let mut writer = JsonWriter::new(&mut recording);
+        for event in events.iter() {
+            writer.write_event(event).unwrap();
+        }
+        let broadcaster = Arc::new(Broadcaster::new());
+        publish_recording(recording.as_slice(), &broadcaster).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || serve(listener, broadcaster));
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /events HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.contains("Content-Type: text/event-stream"));
+        let received = response
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect::<Vec<crate::models::Event>>();
+        assert_eq!(received, events);
+        assert!(response.ends_with("event: end\ndata:\n\n"));
+    }
+}
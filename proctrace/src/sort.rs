@@ -0,0 +1,151 @@
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter},
+};
+
+use anyhow::Context;
+
+use crate::{
+    models::Event,
+    reader::TraceReader,
+    writers::{CborWriter, EventWrite, TraceFormat},
+};
+
+type Error = anyhow::Error;
+
+/// The default number of events that are sorted in memory at a time.
+pub const DEFAULT_CHUNK_SIZE: usize = 1_000_000;
+
+/// Sorts the events of a recording by sequence number in bounded memory.
+///
+/// At most `chunk_size` events are held in memory at a time. Each chunk is sorted and
+/// spilled to a temporary file, then the chunks are merged back together as they're
+/// written to `writer`. Recordings that fit in a single chunk never touch the disk.
+/// The writer is finalized once every event has been written.
+pub fn external_sort<R: BufRead>(
+    events: TraceReader<R>,
+    mut writer: impl EventWrite,
+    chunk_size: usize,
+) -> Result<(), Error> {
+    let chunk_size = chunk_size.max(1);
+    let spill_dir = tempfile::tempdir().context("failed to create directory for sorting")?;
+    let mut spills = vec![];
+    let mut chunk = Vec::with_capacity(chunk_size.min(DEFAULT_CHUNK_SIZE));
+    for event in events {
+        chunk.push(event.context("failed to read event")?);
+        if chunk.len() >= chunk_size {
+            let path = spill_dir
+                .path()
+                .join(format!("chunk-{}.cbor", spills.len()));
+            spill_chunk(&mut chunk, &path)?;
+            spills.push(path);
+        }
+    }
+    chunk.sort();
+
+    // Nothing was spilled, so there's nothing to merge
+    if spills.is_empty() {
+        for event in chunk.iter() {
+            writer.write_event(event)?;
+        }
+        return writer.finalize();
+    }
+
+    let mut sources = spills
+        .iter()
+        .map(|path| {
+            let file = File::open(path).context("failed to open sorted chunk")?;
+            let reader = TraceReader::with_format(BufReader::new(file), TraceFormat::Cbor);
+            Ok(Box::new(reader) as Box<dyn Iterator<Item = Result<Event, Error>>>)
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    sources.push(Box::new(chunk.into_iter().map(Ok)));
+
+    // The source index breaks ties so that events with the same sequence number
+    // stay in the order they were read.
+    let mut heap = BinaryHeap::new();
+    for (idx, source) in sources.iter_mut().enumerate() {
+        if let Some(event) = source.next() {
+            heap.push(Reverse((event?, idx)));
+        }
+    }
+    while let Some(Reverse((event, idx))) = heap.pop() {
+        writer.write_event(&event)?;
+        if let Some(next) = sources[idx].next() {
+            heap.push(Reverse((next.context("failed to read sorted chunk")?, idx)));
+        }
+    }
+    writer.finalize()
+}
+
+/// Sorts a chunk of events and writes it to a temporary file, leaving the chunk empty.
+fn spill_chunk(chunk: &mut Vec<Event>, path: &std::path::Path) -> Result<(), Error> {
+    chunk.sort();
+    let file = File::create(path).context("failed to create temporary file for sorting")?;
+    let mut writer = CborWriter::new(BufWriter::new(file));
+    for event in chunk.drain(..) {
+        writer.write_event(&event)?;
+    }
+    writer.finalize()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{ingest::test::make_simple_events, writers::test::MockWriter};
+
+    use super::*;
+
+    fn shuffled_events() -> Vec<Event> {
+        let mut events = make_simple_events(
+            0,
+            0,
+            &[
+                ("fork", 1, 0),
+                ("exec_full", 1, 0),
+                ("fork", 2, 1),
+                ("exec_full", 2, 1),
+                ("exit", 2, 1),
+                ("fork", 3, 1),
+                ("exit", 3, 1),
+                ("exit", 1, 0),
+            ],
+        );
+        events.reverse();
+        events.swap(1, 5);
+        events
+    }
+
+    fn sort_with_chunk_size(chunk_size: usize) -> Vec<Event> {
+        let mut input = vec![];
+        let mut json = crate::writers::JsonWriter::new(&mut input);
+        for event in shuffled_events().iter() {
+            json.write_event(event).unwrap();
+        }
+        let mut writer = MockWriter::new();
+        external_sort(
+            TraceReader::new(input.as_slice()).unwrap(),
+            &mut writer,
+            chunk_size,
+        )
+        .unwrap();
+        writer.events
+    }
+
+    #[test]
+    fn sorts_in_memory() {
+        let mut expected = shuffled_events();
+        expected.sort();
+        assert_eq!(sort_with_chunk_size(DEFAULT_CHUNK_SIZE), expected);
+    }
+
+    #[test]
+    fn sorts_with_spilled_chunks() {
+        let mut expected = shuffled_events();
+        expected.sort();
+        for chunk_size in 1..=4 {
+            assert_eq!(sort_with_chunk_size(chunk_size), expected);
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! Detects fork storms, where part of the process tree forks at a pathological rate.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    analyze::{command_name, format_nanos},
+    models::{Event, EventStore},
+};
+
+/// The default number of forks per window that counts as a storm.
+pub const DEFAULT_STORM_THRESHOLD: usize = 1000;
+/// The default length of the window that forks are counted over, in milliseconds.
+pub const DEFAULT_STORM_WINDOW_MS: u64 = 1000;
+
+/// Counts forks per subtree over a sliding window as they're observed.
+///
+/// Each fork counts towards the process that forked and every one of its ancestors,
+/// so a fork bomb where every child forks again is caught at the process that
+/// started it. A storm is reported for the deepest subtree that exceeds the
+/// threshold, and subtrees aren't reported again until their rate drops back down.
+#[derive(Debug, Clone)]
+pub struct StormDetector {
+    threshold: usize,
+    window: u128,
+    /// The parent of each live process that has been seen to fork.
+    parents: HashMap<i32, i32>,
+    /// The timestamps of the forks in each subtree during the current window.
+    forks: HashMap<i32, VecDeque<u128>>,
+    /// The subtrees that are over the threshold.
+    storming: HashSet<i32>,
+}
+
+/// A subtree that forked faster than the threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Storm {
+    /// The process at the root of the subtree.
+    pub pid: i32,
+    /// The number of forks in the subtree during the window.
+    pub forks: usize,
+    /// When the threshold was exceeded.
+    pub timestamp: u128,
+}
+
+impl StormDetector {
+    /// Creates a detector that reports more than `threshold` forks in `window_ms`.
+    pub fn new(threshold: usize, window_ms: u64) -> Self {
+        Self {
+            threshold,
+            window: window_ms as u128 * 1_000_000,
+            parents: HashMap::new(),
+            forks: HashMap::new(),
+            storming: HashSet::new(),
+        }
+    }
+
+    /// The length of the window that forks are counted over, in milliseconds.
+    pub fn window_ms(&self) -> u64 {
+        (self.window / 1_000_000) as u64
+    }
+
+    /// Counts a fork, returning a storm if it pushed a subtree over the threshold.
+    pub fn observe_fork(&mut self, parent_pid: i32, child_pid: i32, ts: u128) -> Option<Storm> {
+        let mut storm = None;
+        // Only the closest subtree that's over the threshold is reported, so an
+        // ancestor isn't reported while one of its descendants is storming
+        let mut found = false;
+        let mut visited = HashSet::new();
+        // Only processes whose own fork was seen are part of the tree, the parent of
+        // its root isn't
+        let mut pid = Some(parent_pid).filter(|pid| self.parents.contains_key(pid));
+        // A reused PID could make the ancestry loop, so stop at anything seen before
+        while let Some(ancestor) = pid.filter(|pid| visited.insert(*pid)) {
+            let forks = self.forks.entry(ancestor).or_default();
+            forks.push_back(ts);
+            while forks
+                .front()
+                .is_some_and(|first| ts.saturating_sub(*first) > self.window)
+            {
+                forks.pop_front();
+            }
+            let count = forks.len();
+            if count <= self.threshold {
+                self.storming.remove(&ancestor);
+            } else {
+                let is_new = self.storming.insert(ancestor);
+                if !found && is_new {
+                    storm = Some(Storm {
+                        pid: ancestor,
+                        forks: count,
+                        timestamp: ts,
+                    });
+                }
+                found = true;
+            }
+            pid = self
+                .parents
+                .get(&ancestor)
+                .copied()
+                .filter(|pid| self.parents.contains_key(pid));
+        }
+        self.parents.insert(child_pid, parent_pid);
+        storm
+    }
+
+    /// Forgets about a process once it has exited.
+    pub fn observe_exit(&mut self, pid: i32) {
+        self.parents.remove(&pid);
+        self.forks.remove(&pid);
+        self.storming.remove(&pid);
+    }
+
+    /// Returns the warning event for a storm.
+    pub fn storm_event(&self, storm: &Storm, seq: u128) -> Event {
+        Event::ForkStorm {
+            seq,
+            timestamp: storm.timestamp,
+            pid: storm.pid,
+            forks: storm.forks as u64,
+            window_ms: self.window_ms(),
+        }
+    }
+}
+
+/// The fork storms in a recording.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StormReport {
+    /// The time of the first event in the recording.
+    pub start: u128,
+    pub window_ms: u64,
+    pub storms: Vec<ReportedStorm>,
+}
+
+/// A fork storm along with the command of the process at the root of its subtree.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReportedStorm {
+    #[serde(flatten)]
+    pub storm: Storm,
+    /// The file name of the first thing the process exec'd.
+    pub command: String,
+}
+
+impl StormReport {
+    /// Replays the forks in a recording through a detector, so that a recording can be
+    /// checked with a different threshold than the one it was recorded with.
+    pub fn from_store(store: &EventStore, mut detector: StormDetector) -> Self {
+        let mut commands = HashMap::new();
+        let mut events = vec![];
+        for (pid, buffer) in store.iter() {
+            for event in buffer.iter() {
+                match event {
+                    Event::Fork { .. } | Event::Exit { .. } => events.push(event),
+                    Event::ExecFull { filename, .. } => {
+                        commands
+                            .entry(pid)
+                            .or_insert_with(|| command_name(filename));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        events.sort();
+        let start = events.first().map(|event| event.timestamp()).unwrap_or(0);
+        let mut storms = vec![];
+        for event in events {
+            match event {
+                Event::Fork {
+                    parent_pid,
+                    child_pid,
+                    timestamp,
+                    ..
+                } => {
+                    if let Some(storm) = detector.observe_fork(*parent_pid, *child_pid, *timestamp)
+                    {
+                        let command = commands.get(&storm.pid).cloned().unwrap_or_default();
+                        storms.push(ReportedStorm { storm, command });
+                    }
+                }
+                Event::Exit { pid, .. } => detector.observe_exit(*pid),
+                _ => {}
+            }
+        }
+        Self {
+            start,
+            window_ms: detector.window_ms(),
+            storms,
+        }
+    }
+
+    /// Formats the report as a table with a row for each storm.
+    pub fn to_table(&self) -> String {
+        if self.storms.is_empty() {
+            return "no fork storms\n".to_string();
+        }
+        let mut table = format!(
+            "{:>12}  {:>8}  {:>12}  {}\n",
+            "at",
+            "pid",
+            format!("forks/{}ms", self.window_ms),
+            "command"
+        );
+        for ReportedStorm { storm, command } in self.storms.iter() {
+            table.push_str(&format!(
+                "{:>12}  {:>8}  {:>12}  {}\n",
+                format_nanos(storm.timestamp.saturating_sub(self.start)),
+                storm.pid,
+                storm.forks,
+                command
+            ));
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reports_deepest_storming_subtree() {
+        let mut detector = StormDetector::new(3, 1000);
+        // 1 -> 10, which starts forking quickly
+        assert_eq!(detector.observe_fork(0, 1, 0), None);
+        assert_eq!(detector.observe_fork(1, 10, 0), None);
+        let mut storms = vec![];
+        for (i, child) in (100..106).enumerate() {
+            let ts = 2_000_000_000 + i as u128 * 1_000_000;
+            storms.extend(detector.observe_fork(10, child, ts));
+        }
+        // Only the subtree of 10 is reported, not the root's as well
+        assert_eq!(
+            storms,
+            vec![Storm {
+                pid: 10,
+                forks: 4,
+                timestamp: 2_003_000_000
+            }]
+        );
+
+        // Once the rate drops it can be reported again
+        let later = 5_000_000_000;
+        assert_eq!(detector.observe_fork(10, 200, later), None);
+        let storms = (201..205)
+            .filter_map(|child| detector.observe_fork(10, child, later))
+            .collect::<Vec<_>>();
+        assert_eq!(storms.len(), 1);
+
+        detector.observe_exit(10);
+        assert_eq!(detector.observe_fork(10, 300, later), None);
+    }
+
+    #[test]
+    fn reports_storms_with_stable_keys() {
+        let mut builder = crate::builder::TraceBuilder::new()
+            .fork(0, 1)
+            .exec(1, "make all");
+        for child in 100..110 {
+            builder = builder.advance(1_000_000).fork(1, child);
+        }
+        let mut bytes = vec![];
+        builder
+            .write(crate::writers::JsonWriter::new(&mut bytes))
+            .unwrap();
+        let ingester = crate::render::read_events(bytes.as_slice()).unwrap();
+        let report =
+            StormReport::from_store(ingester.tracked_events(), StormDetector::new(3, 1000));
+        let json = serde_json::to_value(&report).unwrap();
+        let storm = json["storms"][0].as_object().unwrap();
+        assert_eq!(
+            storm.keys().collect::<Vec<_>>(),
+            vec!["command", "forks", "pid", "timestamp"]
+        );
+        assert_eq!(storm["command"], "make");
+        assert_eq!(storm["pid"], 1);
+    }
+}
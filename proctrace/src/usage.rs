@@ -0,0 +1,180 @@
+//! Lists the CPU time, page faults, and context switches of each process, which helps
+//! tell why a process was slow without tracing the scheduler: a low share of CPU time
+//! means it was waiting, lots of major faults mean it was waiting on the disk, and lots
+//! of involuntary switches mean it was fighting for a CPU.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    analyze::{command_line, format_nanos},
+    models::{Event, EventStore, ResourceUsage},
+};
+
+/// The usage of a process that exited.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessUsage {
+    pub pid: i32,
+    /// The command line of the last thing the process exec'd, or "(fork of <ppid>)" if
+    /// it never exec'd anything.
+    pub command: String,
+    /// How long the process ran, in nanoseconds.
+    pub runtime: u128,
+    #[serde(flatten)]
+    pub usage: ResourceUsage,
+}
+
+/// The usage of the processes in a recording, slowest first.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsageReport {
+    pub processes: Vec<ProcessUsage>,
+}
+
+impl UsageReport {
+    /// Collects the usage of the processes in a recording that it was recorded for.
+    pub fn from_store(store: &EventStore) -> Self {
+        let mut processes = vec![];
+        for (pid, buffer) in store.iter() {
+            let Some(usage) = buffer.iter().find_map(|event| match event {
+                Event::Usage { usage, .. } => Some(*usage),
+                _ => None,
+            }) else {
+                continue;
+            };
+            let (Some(first), Some(last)) = (buffer.front(), buffer.back()) else {
+                continue;
+            };
+            processes.push(ProcessUsage {
+                pid,
+                command: command_line(buffer),
+                runtime: last.timestamp().saturating_sub(first.timestamp()),
+                usage,
+            });
+        }
+        processes.sort_by_key(|process| (std::cmp::Reverse(process.runtime), process.pid));
+        Self { processes }
+    }
+
+    /// Formats the report as a table of the `limit` slowest processes.
+    pub fn to_table(&self, limit: usize) -> String {
+        if self.processes.is_empty() {
+            return "no usage was recorded\n".to_string();
+        }
+        let mut table = format!(
+            "{:>8}  {:>12}  {:>12}  {:>5}  {:>10}  {:>8}  {:>10}  {:>10}  {}\n",
+            "pid",
+            "ran for",
+            "on cpu",
+            "cpu%",
+            "minflt",
+            "majflt",
+            "voluntary",
+            "involuntary",
+            "command"
+        );
+        for process in self.processes.iter().take(limit) {
+            let usage = process.usage;
+            let share = usage
+                .cpu_percent(process.runtime)
+                .map(|share| share.to_string())
+                .unwrap_or_else(|| "-".to_string());
+            table.push_str(&format!(
+                "{:>8}  {:>12}  {:>12}  {:>5}  {:>10}  {:>8}  {:>10}  {:>10}  {}\n",
+                process.pid,
+                format_nanos(process.runtime),
+                format_nanos(usage.cpu_ns()),
+                share,
+                usage.minor_faults,
+                usage.major_faults,
+                usage.voluntary_switches,
+                usage.involuntary_switches,
+                process.command
+            ));
+        }
+        if self.processes.len() > limit {
+            table.push_str(&format!("... and {} more\n", self.processes.len() - limit));
+        }
+        table
+    }
+
+    /// Formats the report as CSV with a header row, with every process and the
+    /// runtime in nanoseconds.
+    pub fn to_csv(&self) -> String {
+        let mut csv = "pid,runtime_ns,user_ns,system_ns,minor_faults,major_faults,voluntary_switches,involuntary_switches,command\n".to_string();
+        for process in self.processes.iter() {
+            let usage = process.usage;
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{},\"{}\"\n",
+                process.pid,
+                process.runtime,
+                usage.user_ns,
+                usage.system_ns,
+                usage.minor_faults,
+                usage.major_faults,
+                usage.voluntary_switches,
+                usage.involuntary_switches,
+                process.command.replace('"', "\"\"")
+            ));
+        }
+        csv
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{builder::TraceBuilder, render::read_events, writers::JsonWriter};
+
+    use super::*;
+
+    #[test]
+    fn lists_usage_of_slowest_processes() {
+        let ms = 1_000_000;
+        let usage = |minor_faults, major_faults| ResourceUsage {
+            minor_faults,
+            major_faults,
+            voluntary_switches: 10,
+            involuntary_switches: 2,
+            user_ns: 2_000_000,
+            system_ns: 500_000,
+        };
+        let mut bytes = vec![];
+        TraceBuilder::new()
+            .fork(0, 1)
+            .exec(1, "make")
+            .fork(1, 2)
+            .exec(2, "sh -c \"cc -c a.c\"")
+            .fork(1, 3)
+            .advance(5 * ms)
+            .usage(3, usage(100, 0))
+            .exit(3, 0)
+            .advance(5 * ms)
+            .usage(2, usage(5000, 40))
+            .exit(2, 0)
+            .usage(1, usage(300, 1))
+            .exit(1, 0)
+            .write(JsonWriter::new(&mut bytes))
+            .unwrap();
+        let ingester = read_events(bytes.as_slice()).unwrap();
+        let report = UsageReport::from_store(ingester.tracked_events());
+        let pids = report
+            .processes
+            .iter()
+            .map(|process| process.pid)
+            .collect::<Vec<_>>();
+        assert_eq!(pids, vec![1, 2, 3]);
+        assert_eq!(report.processes[1].usage, usage(5000, 40));
+        assert_eq!(report.processes[2].command, "(fork of 1)");
+
+        let table = report.to_table(2);
+        assert!(table.contains("2.500ms     25"), "{table}");
+        assert!(table.ends_with("... and 1 more\n"));
+        let csv = report.to_csv();
+        assert!(
+            csv.contains("\n2,10000000,2000000,500000,5000,40,10,2,\"sh -c \"\"cc -c a.c\"\"\"\n")
+        );
+        assert_eq!(csv.lines().count(), 4);
+        assert_eq!(
+            UsageReport::default().to_table(10),
+            "no usage was recorded\n"
+        );
+    }
+}
@@ -6,6 +6,13 @@ use std::{
 
 use anyhow::Context;
 
+use crate::{
+    cli::{parse_env_var, Compression},
+    merge::{merge, Source},
+    segments::{is_manifest_path, Manifest, RotationPolicy, SegmentedWriter},
+    writers::{EventWrite, JsonWriter, OutputStream},
+};
+
 type Error = anyhow::Error;
 
 /// Returns an absolute path from a path that may not be absolute.
@@ -33,33 +40,419 @@ pub fn new_output_file(path: impl AsRef<Path>) -> Result<File, Error> {
 }
 
 /// Returns a generic buffered output stream, either `stdout` or a file.
+///
+/// If `compression` isn't specified it's guessed from the extension of the path,
+/// and `stdout` is left uncompressed. A compressed stream has to be finished with
+/// [`OutputStream::finish`] once everything is written, otherwise it's finished when
+/// it's dropped and errors are lost.
 pub fn new_buffered_output_stream<T: AsRef<Path>>(
     path: &Option<T>,
-) -> Result<Box<dyn Write>, Error> {
+    compression: Option<Compression>,
+) -> Result<Box<dyn OutputStream>, Error> {
     if let Some(path) = path {
         let path = path.as_ref();
+        let compression = compression.unwrap_or_else(|| Compression::from_path(path));
         let real_path = make_path_absolute(path)?;
         let file = new_output_file(real_path)?;
         let writer = BufWriter::new(file);
-        Ok(Box::new(writer))
+        compressed_output_stream(writer, compression)
     } else {
         let stdout = stdout().lock();
         let writer = BufWriter::new(stdout);
-        Ok(Box::new(writer))
+        compressed_output_stream(writer, compression.unwrap_or_default())
+    }
+}
+
+/// Returns a generic output stream that is split into segments according to `policy`.
+///
+/// Segmenting requires an output path, and if the policy never rotates this is the
+/// same as [new_buffered_output_stream].
+pub fn new_output_stream<T: AsRef<Path>>(
+    path: &Option<T>,
+    compression: Option<Compression>,
+    policy: RotationPolicy,
+) -> Result<Box<dyn OutputStream>, Error> {
+    if !policy.is_enabled() {
+        return new_buffered_output_stream(path, compression);
+    }
+    let path = path
+        .as_ref()
+        .context("an output path is required to write segments")?;
+    let writer = SegmentedWriter::new(path, compression, policy)?;
+    Ok(Box::new(writer))
+}
+
+/// Wraps a writer so that everything written to it is compressed.
+pub fn compressed_output_stream(
+    writer: impl Write + 'static,
+    compression: Compression,
+) -> Result<Box<dyn OutputStream>, Error> {
+    let encoder = match compression {
+        Compression::None => Encoder::None(writer),
+        Compression::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(
+            writer,
+            flate2::Compression::default(),
+        )),
+        Compression::Zstd => {
+            let encoder = zstd::Encoder::new(writer, 0).context("failed to create zstd encoder")?;
+            Encoder::Zstd(encoder)
+        }
+    };
+    Ok(Box::new(CompressedStream {
+        encoder: Some(encoder),
+    }))
+}
+
+/// A stream that compresses everything written to it, see [compressed_output_stream].
+///
+/// The end of the compressed stream is written by [`OutputStream::finish`], or when
+/// the stream is dropped if it wasn't finished.
+struct CompressedStream<W: Write> {
+    /// Taken once the stream is finished.
+    encoder: Option<Encoder<W>>,
+}
+
+enum Encoder<W: Write> {
+    None(W),
+    Gzip(flate2::write::GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> CompressedStream<W> {
+    fn encoder(&mut self) -> std::io::Result<&mut dyn Write> {
+        match self.encoder.as_mut() {
+            Some(Encoder::None(writer)) => Ok(writer),
+            Some(Encoder::Gzip(encoder)) => Ok(encoder),
+            Some(Encoder::Zstd(encoder)) => Ok(encoder),
+            None => Err(std::io::Error::other("the stream was already finished")),
+        }
+    }
+}
+
+impl<W: Write> Write for CompressedStream<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.encoder()?.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.encoder.is_none() {
+            return Ok(());
+        }
+        self.encoder()?.flush()
+    }
+}
+
+impl<W: Write> OutputStream for CompressedStream<W> {
+    fn finish(&mut self) -> std::io::Result<()> {
+        let mut writer = match self.encoder.take() {
+            Some(Encoder::None(writer)) => writer,
+            Some(Encoder::Gzip(encoder)) => encoder.finish()?,
+            Some(Encoder::Zstd(encoder)) => encoder.finish()?,
+            None => return Ok(()),
+        };
+        writer.flush()
+    }
+}
+
+impl<W: Write> Drop for CompressedStream<W> {
+    fn drop(&mut self) {
+        // Gzip streams finish themselves when they're dropped, zstd streams don't
+        if let Some(Encoder::Zstd(encoder)) = self.encoder.take() {
+            let _ = encoder.finish();
+        }
     }
 }
 
 /// Returns a generic buffered input stream, either `stdin` or a file.
+///
+/// Input that's compressed with gzip or zstd is decompressed as it's read, whatever
+/// it's called, and the segments listed in a manifest (".manifest.json") are read in
+/// order as if they were a single file. The runs listed in the manifest of `proctrace
+/// bench` are merged into a single recording instead, see [merged_runs_stream].
 pub fn new_buffered_input_stream(path: impl AsRef<Path>) -> Result<Box<dyn Read>, Error> {
     let path = path.as_ref();
     if path == Path::new("-") {
         let stdin = stdin();
-        let reader = BufReader::new(stdin);
-        Ok(Box::new(reader))
+        sniffed_input_stream(BufReader::new(stdin))
+    } else if is_manifest_path(path) {
+        let real_path = make_path_absolute(path)?;
+        let manifest = Manifest::read(&real_path)?;
+        if !manifest.runs.is_empty() {
+            return merged_runs_stream(&manifest, &real_path);
+        }
+        let mut reader: Box<dyn Read> = Box::new(std::io::empty());
+        for segment_path in manifest.segment_paths(&real_path) {
+            let segment = new_buffered_input_stream(&segment_path)
+                .with_context(|| format!("failed to open segment {}", segment_path.display()))?;
+            reader = Box::new(reader.chain(segment));
+        }
+        Ok(reader)
     } else {
         let real_path = make_path_absolute(path)?;
         let file = std::fs::File::open(real_path).context("failed to open input file")?;
-        let reader = BufReader::new(file);
-        Ok(Box::new(reader))
+        sniffed_input_stream(BufReader::new(file))
+    }
+}
+
+/// Merges the runs listed in the manifest of `proctrace bench` onto one timeline, the
+/// same as `proctrace merge` would, and returns them as a single processed recording.
+///
+/// Each run starts where it started in time, so they're laid out one after another,
+/// and the root of every run but the first is adopted.
+fn merged_runs_stream(manifest: &Manifest, manifest_path: &Path) -> Result<Box<dyn Read>, Error> {
+    let mut sources = vec![];
+    for (run, path) in manifest.runs.iter().zip(manifest.run_paths(manifest_path)) {
+        let reader = new_buffered_input_stream(&path)
+            .with_context(|| format!("failed to open run {}", path.display()))?;
+        let source = Source::read(format!("run {}", run.index), BufReader::new(reader), 0)
+            .with_context(|| format!("failed to read run {}", path.display()))?;
+        sources.push(source);
+    }
+    // The warnings are about recordings that don't overlap, which runs never do
+    let merged = merge(sources)?;
+    let mut bytes = vec![];
+    let mut writer = JsonWriter::new(&mut bytes);
+    writer.write_metadata(&merged.metadata)?;
+    for event in merged.events.iter() {
+        writer.write_event(event)?;
+    }
+    writer.finalize()?;
+    Ok(Box::new(std::io::Cursor::new(bytes)))
+}
+
+/// Reads the `KEY=VALUE` pairs of an env file, in order.
+///
+/// Lines may start with `export`, blank lines and comments are skipped, and values in
+/// matching single or double quotes have them removed.
+pub fn read_env_file(path: impl AsRef<Path>) -> Result<Vec<(String, String)>, Error> {
+    let path = path.as_ref();
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read env file {}", path.display()))?;
+    let mut vars = vec![];
+    for (idx, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+        let (key, value) = parse_env_var(line)
+            .map_err(|err| anyhow::anyhow!("{}:{}: {err}", path.display(), idx + 1))?;
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|quote| {
+                value
+                    .strip_prefix(*quote)
+                    .and_then(|value| value.strip_suffix(*quote))
+            })
+            .unwrap_or(&value);
+        vars.push((key.trim_end().to_string(), value.to_string()));
+    }
+    Ok(vars)
+}
+
+/// Returns how a file is compressed, judging by its contents.
+pub fn file_compression(path: impl AsRef<Path>) -> Result<Compression, Error> {
+    let file = std::fs::File::open(path).context("failed to open input file")?;
+    let mut magic = Vec::with_capacity(4);
+    file.take(4)
+        .read_to_end(&mut magic)
+        .context("failed to read input file")?;
+    Ok(Compression::from_magic(&magic))
+}
+
+/// Wraps a reader so that its contents are decompressed as they're read if they
+/// start with the magic bytes of gzip or zstd.
+pub fn sniffed_input_stream(mut reader: impl Read + 'static) -> Result<Box<dyn Read>, Error> {
+    let mut magic = Vec::with_capacity(4);
+    // Reads until there are enough bytes to tell, which a pipe may not have yet
+    (&mut reader)
+        .take(4)
+        .read_to_end(&mut magic)
+        .context("failed to read input")?;
+    let compression = Compression::from_magic(&magic);
+    let reader = Unread {
+        prefix: magic,
+        pos: 0,
+        reader,
+    };
+    decompressed_input_stream(reader, compression)
+}
+
+/// A reader that returns bytes that were already read from `reader` before the rest.
+///
+/// Unlike [`Read::chain`], the prefix and the start of the rest are returned from the
+/// same read, so that a `BufReader` on top sees the whole first line and not just the
+/// prefix (see [`TraceReader::detect`](crate::reader::TraceReader::detect)).
+struct Unread<R> {
+    prefix: Vec<u8>,
+    pos: usize,
+    reader: R,
+}
+
+impl<R: Read> Read for Unread<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let remaining = &self.prefix[self.pos..];
+        if remaining.is_empty() {
+            return self.reader.read(buf);
+        }
+        let n = remaining.len().min(buf.len());
+        buf[..n].copy_from_slice(&remaining[..n]);
+        self.pos += n;
+        if n == buf.len() {
+            return Ok(n);
+        }
+        Ok(n + self.reader.read(&mut buf[n..])?)
+    }
+}
+
+/// Wraps a reader so that its contents are decompressed as they're read.
+pub fn decompressed_input_stream(
+    reader: impl Read + 'static,
+    compression: Compression,
+) -> Result<Box<dyn Read>, Error> {
+    match compression {
+        Compression::None => Ok(Box::new(reader)),
+        Compression::Gzip => Ok(Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(
+            reader,
+        )))),
+        Compression::Zstd => {
+            let decoder = zstd::Decoder::new(reader).context("failed to create zstd decoder")?;
+            Ok(Box::new(BufReader::new(decoder)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::BufRead;
+
+    use super::*;
+
+    fn round_trip(compression: Compression) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.json");
+        {
+            let mut writer = new_buffered_output_stream(&Some(&path), Some(compression)).unwrap();
+            writer.write_all(b"hello\n").unwrap();
+        }
+        let file = std::fs::File::open(&path).unwrap();
+        let mut reader = decompressed_input_stream(BufReader::new(file), compression).unwrap();
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "hello\n");
+    }
+
+    #[test]
+    fn finishes_compressed_streams_without_dropping_them() {
+        let dir = tempfile::tempdir().unwrap();
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let path = dir.path().join(format!("trace-{compression}"));
+            let mut writer = new_buffered_output_stream(&Some(&path), Some(compression)).unwrap();
+            writer.write_all(b"hello\n").unwrap();
+            writer.finish().unwrap();
+            // Nothing is left for the drop to write
+            std::mem::forget(writer);
+            let file = std::fs::File::open(&path).unwrap();
+            let mut contents = String::new();
+            decompressed_input_stream(BufReader::new(file), compression)
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            assert_eq!(contents, "hello\n");
+        }
+    }
+
+    #[test]
+    fn reports_errors_finishing_compressed_streams() {
+        struct FullDisk;
+
+        impl Write for FullDisk {
+            fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::from(std::io::ErrorKind::StorageFull))
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let mut writer = compressed_output_stream(FullDisk, compression).unwrap();
+            assert!(writer.finish().is_err(), "{compression}");
+        }
+    }
+
+    #[test]
+    fn round_trips_gzip() {
+        round_trip(Compression::Gzip);
+    }
+
+    #[test]
+    fn round_trips_zstd() {
+        round_trip(Compression::Zstd);
+    }
+
+    #[test]
+    fn detects_compression_from_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        for compression in [Compression::None, Compression::Gzip, Compression::Zstd] {
+            // Named so that the extension doesn't give it away
+            let path = dir.path().join(format!("trace-{compression}"));
+            {
+                let mut writer =
+                    new_buffered_output_stream(&Some(&path), Some(compression)).unwrap();
+                writer.write_all(b"hello\n").unwrap();
+            }
+            assert_eq!(file_compression(&path).unwrap(), compression);
+            let mut contents = String::new();
+            new_buffered_input_stream(&path)
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            assert_eq!(contents, "hello\n");
+        }
+        // The sniffed bytes don't end up in a read of their own
+        let mut reader = BufReader::new(sniffed_input_stream(&b"{\"Metadata\":{}}\n"[..]).unwrap());
+        assert_eq!(reader.fill_buf().unwrap(), b"{\"Metadata\":{}}\n");
+        // Too short to be compressed
+        let mut contents = String::new();
+        sniffed_input_stream(&b"{"[..])
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "{");
+    }
+
+    #[test]
+    fn reads_env_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".env");
+        std::fs::write(
+            &path,
+            "# comment\nCI=true\n\nexport NAME=\"a b\"\nEMPTY=\nURL='x=y'\n",
+        )
+        .unwrap();
+        assert_eq!(
+            read_env_file(&path).unwrap(),
+            vec![
+                ("CI".to_string(), "true".to_string()),
+                ("NAME".to_string(), "a b".to_string()),
+                ("EMPTY".to_string(), "".to_string()),
+                ("URL".to_string(), "x=y".to_string()),
+            ]
+        );
+        std::fs::write(&path, "CI=true\noops\n").unwrap();
+        let err = read_env_file(&path).unwrap_err().to_string();
+        assert!(
+            err.ends_with(":2: expected KEY=VALUE, found 'oops'"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn guesses_compression_from_extension() {
+        assert_eq!(Compression::from_path("a.json.gz"), Compression::Gzip);
+        assert_eq!(Compression::from_path("a.json.zst"), Compression::Zstd);
+        assert_eq!(Compression::from_path("a.json"), Compression::None);
     }
 }
@@ -1,11 +1,209 @@
 use std::io::Write;
 
+use anyhow::{anyhow, Context};
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::{
+    format::IndexedWriter,
+    models::{Event, Metadata, MetadataItem},
+};
+
 type Error = anyhow::Error;
 
 pub trait EventWrite {
-    fn write_raw(&mut self, line: impl AsRef<[u8]>) -> Result<(), Error>;
+    /// Write a line of raw output from the script.
+    fn write_raw(&mut self, line: &[u8]) -> Result<(), Error>;
+
+    /// Write a single processed event.
+    fn write_event(&mut self, event: &Event) -> Result<(), Error>;
+
+    /// Write the metadata for the recording.
+    ///
+    /// This is called at most once, before any events are written. Formats that
+    /// need a header should write it here.
+    fn write_metadata(&mut self, _metadata: &Metadata) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Flush anything that has been buffered so it's durable.
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Write any trailing output and flush, nothing should be written afterwards.
+    fn finalize(&mut self) -> Result<(), Error> {
+        self.flush()
+    }
+}
+
+impl<W: EventWrite + ?Sized> EventWrite for Box<W> {
+    fn write_raw(&mut self, line: &[u8]) -> Result<(), Error> {
+        (**self).write_raw(line)
+    }
+
+    fn write_event(&mut self, event: &Event) -> Result<(), Error> {
+        (**self).write_event(event)
+    }
+
+    fn write_metadata(&mut self, metadata: &Metadata) -> Result<(), Error> {
+        (**self).write_metadata(metadata)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        (**self).flush()
+    }
+
+    fn finalize(&mut self) -> Result<(), Error> {
+        (**self).finalize()
+    }
+}
+
+impl<W: EventWrite + ?Sized> EventWrite for &mut W {
+    fn write_raw(&mut self, line: &[u8]) -> Result<(), Error> {
+        (**self).write_raw(line)
+    }
+
+    fn write_event(&mut self, event: &Event) -> Result<(), Error> {
+        (**self).write_event(event)
+    }
+
+    fn write_metadata(&mut self, metadata: &Metadata) -> Result<(), Error> {
+        (**self).write_metadata(metadata)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        (**self).flush()
+    }
+
+    fn finalize(&mut self) -> Result<(), Error> {
+        (**self).finalize()
+    }
+}
+
+/// The encoding of a processed recording.
+#[derive(Debug, Default, ValueEnum, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TraceFormat {
+    /// One JSON object per line.
+    #[default]
+    Json,
+    /// A sequence of CBOR items, which is smaller and faster to parse.
+    Cbor,
+    /// CBOR events in chunks followed by an index, so that parts of the recording
+    /// (e.g. `render --pid`) can be read without reading all of it.
+    Indexed,
+    /// Like "indexed", but every chunk is compressed with zstd on its own, so the
+    /// recording is small and only the chunks that are read are decompressed.
+    IndexedZstd,
 }
 
+impl std::fmt::Display for TraceFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TraceFormat::Json => write!(f, "json"),
+            TraceFormat::Cbor => write!(f, "cbor"),
+            TraceFormat::Indexed => write!(f, "indexed"),
+            TraceFormat::IndexedZstd => write!(f, "indexed-zstd"),
+        }
+    }
+}
+
+/// Returns a writer that writes processed events in the specified format.
+pub fn new_event_writer(
+    inner: impl OutputStream + 'static,
+    format: TraceFormat,
+) -> Box<dyn EventWrite> {
+    match format {
+        TraceFormat::Json => Box::new(FinishingWriter::new(
+            JsonWriter::new(inner),
+            JsonWriter::get_mut,
+        )),
+        TraceFormat::Cbor => Box::new(FinishingWriter::new(
+            CborWriter::new(inner),
+            CborWriter::get_mut,
+        )),
+        TraceFormat::Indexed => Box::new(FinishingWriter::new(
+            IndexedWriter::new(inner),
+            IndexedWriter::get_mut,
+        )),
+        TraceFormat::IndexedZstd => Box::new(FinishingWriter::new(
+            IndexedWriter::new(inner).with_zstd_chunks(0),
+            IndexedWriter::get_mut,
+        )),
+    }
+}
+
+/// Finishes the [`OutputStream`] of a writer once the writer has been finalized, so
+/// that a failure to write the end of the stream is reported rather than lost when
+/// the stream is dropped.
+pub struct FinishingWriter<W, S> {
+    inner: W,
+    stream: fn(&mut W) -> &mut S,
+}
+
+impl<W, S> FinishingWriter<W, S> {
+    /// Wraps `inner`, whose stream is returned by `stream`.
+    pub fn new(inner: W, stream: fn(&mut W) -> &mut S) -> Self {
+        Self { inner, stream }
+    }
+}
+
+impl<W: EventWrite, S: OutputStream> EventWrite for FinishingWriter<W, S> {
+    fn write_raw(&mut self, line: &[u8]) -> Result<(), Error> {
+        self.inner.write_raw(line)
+    }
+
+    fn write_event(&mut self, event: &Event) -> Result<(), Error> {
+        self.inner.write_event(event)
+    }
+
+    fn write_metadata(&mut self, metadata: &Metadata) -> Result<(), Error> {
+        self.inner.write_metadata(metadata)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush()
+    }
+
+    fn finalize(&mut self) -> Result<(), Error> {
+        self.inner.finalize()?;
+        (self.stream)(&mut self.inner)
+            .finish()
+            .context("failed to finish output")
+    }
+}
+
+impl<W: std::fmt::Debug, S> std::fmt::Debug for FinishingWriter<W, S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FinishingWriter")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+/// A stream that recordings are written to, which may have to be finished once
+/// everything has been written to it.
+pub trait OutputStream: Write {
+    /// Writes whatever ends the stream, e.g. the end of a compressed stream, and
+    /// flushes it. Nothing should be written afterwards.
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.flush()
+    }
+}
+
+impl<S: OutputStream + ?Sized> OutputStream for Box<S> {
+    fn finish(&mut self) -> std::io::Result<()> {
+        (**self).finish()
+    }
+}
+
+impl<W: Write> OutputStream for std::io::BufWriter<W> {}
+
+impl OutputStream for std::fs::File {}
+
+impl OutputStream for Vec<u8> {}
+
 #[derive(Debug)]
 pub struct JsonWriter<T> {
     inner: T,
@@ -15,31 +213,909 @@ impl<T> JsonWriter<T> {
     pub fn new(inner: T) -> Self {
         Self { inner }
     }
+
+    /// Returns the stream that events are written to.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
 }
 
 impl<T: Write> EventWrite for JsonWriter<T> {
-    fn write_raw(&mut self, line: impl AsRef<[u8]>) -> Result<(), Error> {
-        if let Err(err) = self.inner.write_all(line.as_ref()) {
+    fn write_raw(&mut self, line: &[u8]) -> Result<(), Error> {
+        if let Err(err) = self.inner.write_all(line) {
             eprintln!("failed to write raw event: {err}");
         }
         let _ = self.inner.write(b"\n");
         Ok(())
     }
+
+    fn write_event(&mut self, event: &Event) -> Result<(), Error> {
+        serde_json::to_writer(&mut self.inner, event).context("failed to write event")?;
+        self.inner.write_all(b"\n").context("write failed")?;
+        Ok(())
+    }
+
+    fn write_metadata(&mut self, metadata: &Metadata) -> Result<(), Error> {
+        let item = MetadataItem::Metadata(metadata.clone());
+        serde_json::to_writer(&mut self.inner, &item).context("failed to write metadata")?;
+        self.inner.write_all(b"\n").context("write failed")?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush().context("flush failed")
+    }
+}
+
+/// Writes processed events as lines of JSON and flushes after each one, so that a
+/// reader on the other end of a pipe sees them while they're recorded.
+///
+/// Raw lines are dropped, so this can be used next to a raw recording.
+#[derive(Debug)]
+pub struct StreamWriter<T> {
+    inner: JsonWriter<T>,
+}
+
+impl<T> StreamWriter<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner: JsonWriter::new(inner),
+        }
+    }
+}
+
+impl<T: Write> EventWrite for StreamWriter<T> {
+    fn write_raw(&mut self, _line: &[u8]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn write_event(&mut self, event: &Event) -> Result<(), Error> {
+        self.inner.write_event(event)?;
+        self.inner.flush()
+    }
+
+    fn write_metadata(&mut self, metadata: &Metadata) -> Result<(), Error> {
+        self.inner.write_metadata(metadata)?;
+        self.inner.flush()
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush()
+    }
+}
+
+/// Writes processed events as a CBOR sequence (RFC 8742).
+///
+/// Raw lines can't be written, since they'd make the sequence unreadable, so this
+/// can only be used for processed recordings.
+#[derive(Debug)]
+pub struct CborWriter<T> {
+    inner: T,
+}
+
+impl<T> CborWriter<T> {
+    pub fn new(inner: T) -> Self {
+        Self { inner }
+    }
+
+    /// Returns the stream that events are written to.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: Write> EventWrite for CborWriter<T> {
+    fn write_raw(&mut self, _line: &[u8]) -> Result<(), Error> {
+        Err(anyhow!("raw output can't be written as CBOR"))
+    }
+
+    fn write_event(&mut self, event: &Event) -> Result<(), Error> {
+        ciborium::into_writer(event, &mut self.inner).context("failed to write event")
+    }
+
+    fn write_metadata(&mut self, metadata: &Metadata) -> Result<(), Error> {
+        let item = MetadataItem::Metadata(metadata.clone());
+        ciborium::into_writer(&item, &mut self.inner).context("failed to write metadata")
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush().context("flush failed")
+    }
+}
+
+/// Writes only the raw output from the script, discarding processed events.
+#[derive(Debug)]
+pub struct RawWriter<T> {
+    inner: T,
+}
+
+impl<T> RawWriter<T> {
+    pub fn new(inner: T) -> Self {
+        Self { inner }
+    }
+
+    /// Returns the stream that the raw output is written to.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: Write> EventWrite for RawWriter<T> {
+    fn write_raw(&mut self, line: &[u8]) -> Result<(), Error> {
+        self.inner
+            .write_all(line)
+            .context("failed to write raw event")?;
+        self.inner.write_all(b"\n").context("write failed")?;
+        Ok(())
+    }
+
+    fn write_event(&mut self, _event: &Event) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush().context("flush failed")
+    }
+}
+
+/// Writes everything to two writers, e.g. to keep the raw output in one place while
+/// events are sent somewhere else.
+#[derive(Debug)]
+pub struct TeeWriter<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A, B> TeeWriter<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+
+    /// Returns the two writers.
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+}
+
+impl<A: EventWrite, B: EventWrite> EventWrite for TeeWriter<A, B> {
+    fn write_raw(&mut self, line: &[u8]) -> Result<(), Error> {
+        self.first.write_raw(line)?;
+        self.second.write_raw(line)
+    }
+
+    fn write_event(&mut self, event: &Event) -> Result<(), Error> {
+        self.first.write_event(event)?;
+        self.second.write_event(event)
+    }
+
+    fn write_metadata(&mut self, metadata: &Metadata) -> Result<(), Error> {
+        self.first.write_metadata(metadata)?;
+        self.second.write_metadata(metadata)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.first.flush()?;
+        self.second.flush()
+    }
+
+    fn finalize(&mut self) -> Result<(), Error> {
+        self.first.finalize()?;
+        self.second.finalize()
+    }
 }
 
 #[derive(Debug)]
 pub struct NoOpWriter;
 
 impl EventWrite for NoOpWriter {
-    fn write_raw(&mut self, _line: impl AsRef<[u8]>) -> Result<(), Error> {
+    fn write_raw(&mut self, _line: &[u8]) -> Result<(), Error> {
         Ok(())
     }
+
+    fn write_event(&mut self, _event: &Event) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(all(unix, feature = "fs"))]
+pub use socket::*;
+
+#[cfg(all(unix, feature = "fs"))]
+mod socket {
+    use std::{
+        io::Write,
+        net::{TcpStream, ToSocketAddrs, UdpSocket},
+        os::unix::net::UnixStream,
+        path::PathBuf,
+        str::FromStr,
+    };
+
+    use anyhow::{anyhow, Context};
+
+    use super::{Error, EventWrite};
+    use crate::models::{Event, Metadata, MetadataItem};
+
+    /// Where to send events over the network.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum ShipTarget {
+        Tcp(String),
+        Udp(String),
+        Unix(PathBuf),
+    }
+
+    impl FromStr for ShipTarget {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            if let Some(addr) = s.strip_prefix("tcp://") {
+                Ok(ShipTarget::Tcp(addr.to_string()))
+            } else if let Some(addr) = s.strip_prefix("udp://") {
+                Ok(ShipTarget::Udp(addr.to_string()))
+            } else if let Some(path) = s.strip_prefix("unix://") {
+                Ok(ShipTarget::Unix(PathBuf::from(path)))
+            } else {
+                Err(format!(
+                    "expected a tcp://<host>:<port>, udp://<host>:<port>, or unix://<path> URL, found '{s}'"
+                ))
+            }
+        }
+    }
+
+    impl std::fmt::Display for ShipTarget {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                ShipTarget::Tcp(addr) => write!(f, "tcp://{addr}"),
+                ShipTarget::Udp(addr) => write!(f, "udp://{addr}"),
+                ShipTarget::Unix(path) => write!(f, "unix://{}", path.display()),
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    enum Socket {
+        Tcp(TcpStream),
+        Udp(UdpSocket),
+        Unix(UnixStream),
+    }
+
+    /// Sends each event as a line of JSON to a socket.
+    ///
+    /// Stream sockets receive newline-delimited JSON, and datagram sockets receive
+    /// one event per datagram. If the connection fails partway through a recording
+    /// a warning is printed and events are no longer sent, since the recording itself
+    /// is more important than shipping it.
+    #[derive(Debug)]
+    pub struct SocketWriter {
+        target: ShipTarget,
+        socket: Option<Socket>,
+    }
+
+    impl SocketWriter {
+        /// Connects to the target.
+        pub fn connect(target: &ShipTarget) -> Result<Self, Error> {
+            let socket = match target {
+                ShipTarget::Tcp(addr) => Socket::Tcp(
+                    TcpStream::connect(addr)
+                        .with_context(|| format!("failed to connect to {target}"))?,
+                ),
+                ShipTarget::Udp(addr) => {
+                    let remote = addr
+                        .to_socket_addrs()
+                        .with_context(|| format!("failed to resolve {target}"))?
+                        .next()
+                        .ok_or(anyhow!("no addresses found for {target}"))?;
+                    let local = if remote.is_ipv4() {
+                        "0.0.0.0:0"
+                    } else {
+                        "[::]:0"
+                    };
+                    let socket = UdpSocket::bind(local).context("failed to bind UDP socket")?;
+                    socket
+                        .connect(remote)
+                        .with_context(|| format!("failed to connect to {target}"))?;
+                    Socket::Udp(socket)
+                }
+                ShipTarget::Unix(path) => Socket::Unix(
+                    UnixStream::connect(path)
+                        .with_context(|| format!("failed to connect to {target}"))?,
+                ),
+            };
+            Ok(Self {
+                target: target.clone(),
+                socket: Some(socket),
+            })
+        }
+
+        fn send_line(&mut self, mut line: Vec<u8>) {
+            line.push(b'\n');
+            let res = match self.socket {
+                Some(Socket::Tcp(ref mut stream)) => stream.write_all(&line),
+                Some(Socket::Unix(ref mut stream)) => stream.write_all(&line),
+                Some(Socket::Udp(ref socket)) => socket.send(&line).map(|_| ()),
+                None => return,
+            };
+            if let Err(err) = res {
+                eprintln!(
+                    "failed to send to {}, no longer shipping events: {err}",
+                    self.target
+                );
+                self.socket = None;
+            }
+        }
+    }
+
+    impl EventWrite for SocketWriter {
+        fn write_raw(&mut self, line: &[u8]) -> Result<(), Error> {
+            self.send_line(line.to_vec());
+            Ok(())
+        }
+
+        fn write_event(&mut self, event: &Event) -> Result<(), Error> {
+            let line = serde_json::to_vec(event).context("failed to serialize event")?;
+            self.send_line(line);
+            Ok(())
+        }
+
+        fn write_metadata(&mut self, metadata: &Metadata) -> Result<(), Error> {
+            let item = MetadataItem::Metadata(metadata.clone());
+            let line = serde_json::to_vec(&item).context("failed to serialize metadata")?;
+            self.send_line(line);
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use std::io::{BufRead, BufReader};
+
+        use super::*;
+        use crate::ingest::test::make_simple_events;
+
+        #[test]
+        fn parses_ship_targets() {
+            assert_eq!(
+                "tcp://localhost:9000".parse::<ShipTarget>().unwrap(),
+                ShipTarget::Tcp("localhost:9000".to_string())
+            );
+            assert_eq!(
+                "unix:///tmp/sock".parse::<ShipTarget>().unwrap(),
+                ShipTarget::Unix(PathBuf::from("/tmp/sock"))
+            );
+            assert!("localhost:9000".parse::<ShipTarget>().is_err());
+        }
+
+        #[test]
+        fn ships_events_over_tcp() {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let target = ShipTarget::Tcp(listener.local_addr().unwrap().to_string());
+            let mut writer = SocketWriter::connect(&target).unwrap();
+            let (stream, _) = listener.accept().unwrap();
+
+            let events = make_simple_events(0, 0, &[("fork", 1, 0), ("exit", 1, 0)]);
+            for event in events.iter() {
+                writer.write_event(event).unwrap();
+            }
+            drop(writer);
+
+            let received = BufReader::new(stream)
+                .lines()
+                .map(|line| serde_json::from_str::<Event>(&line.unwrap()).unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(received, events);
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "fs"))]
+pub use journal::*;
+
+#[cfg(all(target_os = "linux", feature = "fs"))]
+mod journal {
+    use std::{
+        collections::HashMap,
+        os::unix::net::UnixDatagram,
+        path::{Path, PathBuf},
+    };
+
+    use anyhow::Context;
+
+    use super::{Error, EventWrite};
+    use crate::models::{
+        crash_signal, describe_exit_code, describe_ptrace, describe_sandbox, Event, ExecArgsKind,
+        Metadata,
+    };
+
+    /// The socket that journald accepts native protocol datagrams on.
+    pub const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+    /// The `MESSAGE_ID`s of each kind of entry, so they can be queried with
+    /// `journalctl MESSAGE_ID=<id>`.
+    pub const MESSAGE_ID_START: &str = "6c1b3d0b5a8e4f6f9d2c7e41a3b5f801";
+    pub const MESSAGE_ID_FORK: &str = "6c1b3d0b5a8e4f6f9d2c7e41a3b5f802";
+    pub const MESSAGE_ID_EXEC: &str = "6c1b3d0b5a8e4f6f9d2c7e41a3b5f803";
+    pub const MESSAGE_ID_EXIT: &str = "6c1b3d0b5a8e4f6f9d2c7e41a3b5f804";
+    pub const MESSAGE_ID_SESSION: &str = "6c1b3d0b5a8e4f6f9d2c7e41a3b5f805";
+    pub const MESSAGE_ID_FORK_STORM: &str = "6c1b3d0b5a8e4f6f9d2c7e41a3b5f806";
+    pub const MESSAGE_ID_CUSTOM: &str = "6c1b3d0b5a8e4f6f9d2c7e41a3b5f807";
+    pub const MESSAGE_ID_CHDIR: &str = "6c1b3d0b5a8e4f6f9d2c7e41a3b5f808";
+    pub const MESSAGE_ID_NAMESPACE: &str = "6c1b3d0b5a8e4f6f9d2c7e41a3b5f809";
+    pub const MESSAGE_ID_MOUNT: &str = "6c1b3d0b5a8e4f6f9d2c7e41a3b5f80a";
+    pub const MESSAGE_ID_PTRACE: &str = "6c1b3d0b5a8e4f6f9d2c7e41a3b5f80b";
+    pub const MESSAGE_ID_SANDBOX: &str = "6c1b3d0b5a8e4f6f9d2c7e41a3b5f80c";
+    pub const MESSAGE_ID_SUBREAPER: &str = "6c1b3d0b5a8e4f6f9d2c7e41a3b5f80d";
+    pub const MESSAGE_ID_USAGE: &str = "6c1b3d0b5a8e4f6f9d2c7e41a3b5f80e";
+
+    /// The syslog priorities that entries are logged with.
+    const PRIORITY_WARNING: u8 = 4;
+    const PRIORITY_INFO: u8 = 6;
+
+    /// Logs events to the systemd journal with structured fields.
+    ///
+    /// Each fork, exec, and exit is an entry with a `MESSAGE_ID` for its kind and
+    /// `PID`, `PPID`, `CMDLINE`, and `EXIT_STATUS` fields where they apply. Events
+    /// are written as they're observed, so the parts of an exec are merged here
+    /// before they're logged. Like `SocketWriter`, a warning is printed and logging
+    /// stops if the journal can't be reached partway through a recording.
+    #[derive(Debug)]
+    pub struct JournalWriter {
+        path: PathBuf,
+        socket: Option<UnixDatagram>,
+        pending_execs: HashMap<i32, (Option<String>, Option<String>)>,
+    }
+
+    impl JournalWriter {
+        /// Connects to the journal.
+        pub fn connect() -> Result<Self, Error> {
+            Self::with_socket(JOURNAL_SOCKET)
+        }
+
+        /// Connects to a journal listening at `path`.
+        pub fn with_socket(path: impl AsRef<Path>) -> Result<Self, Error> {
+            let path = path.as_ref().to_path_buf();
+            let socket = UnixDatagram::unbound().context("failed to create journal socket")?;
+            socket
+                .connect(&path)
+                .with_context(|| format!("failed to connect to journal at {}", path.display()))?;
+            Ok(Self {
+                path,
+                socket: Some(socket),
+                pending_execs: HashMap::new(),
+            })
+        }
+
+        fn send(&mut self, message_id: &str, message: String, fields: &[(&str, String)]) {
+            self.send_with_priority(PRIORITY_INFO, message_id, message, fields);
+        }
+
+        fn send_with_priority(
+            &mut self,
+            priority: u8,
+            message_id: &str,
+            message: String,
+            fields: &[(&str, String)],
+        ) {
+            let Some(ref socket) = self.socket else {
+                return;
+            };
+            let mut entry = vec![];
+            let common = [
+                ("MESSAGE", message),
+                ("MESSAGE_ID", message_id.to_string()),
+                ("PRIORITY", priority.to_string()),
+                ("SYSLOG_IDENTIFIER", "proctrace".to_string()),
+            ];
+            for (name, value) in common.iter().chain(fields) {
+                encode_field(&mut entry, name, value);
+            }
+            if let Err(err) = socket.send(&entry) {
+                eprintln!(
+                    "failed to log to journal at {}, no longer logging events: {err}",
+                    self.path.display()
+                );
+                self.socket = None;
+            }
+        }
+
+        fn send_exec(&mut self, pid: i32, ppid: i32, filename: &str, args: &str, seq: u128) {
+            self.send(
+                MESSAGE_ID_EXEC,
+                format!("{pid} exec'd {args}"),
+                &[
+                    ("PID", pid.to_string()),
+                    ("PPID", ppid.to_string()),
+                    ("EXE", filename.to_string()),
+                    ("CMDLINE", args.to_string()),
+                    ("PROCTRACE_SEQ", seq.to_string()),
+                ],
+            );
+        }
+    }
+
+    /// Encodes a field in the journal's native protocol, which needs a length prefix
+    /// for values that span several lines.
+    fn encode_field(entry: &mut Vec<u8>, name: &str, value: &str) {
+        entry.extend(name.as_bytes());
+        if value.contains('\n') {
+            entry.push(b'\n');
+            entry.extend((value.len() as u64).to_le_bytes());
+        } else {
+            entry.push(b'=');
+        }
+        entry.extend(value.as_bytes());
+        entry.push(b'\n');
+    }
+
+    impl EventWrite for JournalWriter {
+        fn write_raw(&mut self, _line: &[u8]) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn write_event(&mut self, event: &Event) -> Result<(), Error> {
+            match event {
+                Event::Fork {
+                    seq,
+                    parent_pid,
+                    child_pid,
+                    ..
+                } => self.send(
+                    MESSAGE_ID_FORK,
+                    format!("{parent_pid} forked {child_pid}"),
+                    &[
+                        ("PID", child_pid.to_string()),
+                        ("PPID", parent_pid.to_string()),
+                        ("PROCTRACE_SEQ", seq.to_string()),
+                    ],
+                ),
+                Event::ExecFilename { pid, filename, .. } => {
+                    self.pending_execs
+                        .insert(*pid, (Some(filename.clone()), None));
+                }
+                Event::ExecArgs { pid, args, .. } => {
+                    if let Some(pending) = self.pending_execs.get_mut(pid) {
+                        pending.1 = Some(args.joined());
+                    }
+                }
+                Event::ExecArg { pid, arg, .. } => {
+                    if let Some(pending) = self.pending_execs.get_mut(pid) {
+                        match pending.1 {
+                            Some(ref mut args) => {
+                                args.push(' ');
+                                args.push_str(arg);
+                            }
+                            None => pending.1 = Some(arg.clone()),
+                        }
+                    }
+                }
+                Event::Exec {
+                    seq,
+                    pid,
+                    ppid,
+                    cmdline,
+                    ..
+                } => {
+                    if let Some((Some(filename), args)) = self.pending_execs.remove(pid) {
+                        let args = args
+                            .or_else(|| cmdline.as_ref().map(|cmdline| cmdline.joined()))
+                            .unwrap_or_default();
+                        self.send_exec(*pid, *ppid, &filename, &args, *seq);
+                    }
+                }
+                Event::BadExec { pid, .. } => {
+                    self.pending_execs.remove(pid);
+                }
+                Event::ExecFull {
+                    seq,
+                    pid,
+                    ppid,
+                    filename,
+                    args,
+                    ..
+                } => self.send_exec(*pid, *ppid, filename, &args.joined(), *seq),
+                Event::Exit {
+                    seq,
+                    pid,
+                    ppid,
+                    exit_code,
+                    ..
+                } => {
+                    let mut fields = vec![
+                        ("PID", pid.to_string()),
+                        ("PPID", ppid.to_string()),
+                        ("PROCTRACE_SEQ", seq.to_string()),
+                    ];
+                    let message = match exit_code {
+                        Some(code) => {
+                            fields.push(("EXIT_STATUS", code.to_string()));
+                            format!("{pid} {}", describe_exit_code(*code))
+                        }
+                        None => format!("{pid} exited"),
+                    };
+                    // Crashes are worth a look, unlike processes that exited on their own
+                    let priority = if exit_code.and_then(crash_signal).is_some() {
+                        PRIORITY_WARNING
+                    } else {
+                        PRIORITY_INFO
+                    };
+                    self.send_with_priority(priority, MESSAGE_ID_EXIT, message, &fields);
+                }
+                Event::SetSID {
+                    seq,
+                    pid,
+                    ppid,
+                    sid,
+                    ..
+                } => self.send(
+                    MESSAGE_ID_SESSION,
+                    format!("{pid} started session {sid}"),
+                    &[
+                        ("PID", pid.to_string()),
+                        ("PPID", ppid.to_string()),
+                        ("PROCTRACE_SEQ", seq.to_string()),
+                    ],
+                ),
+                Event::SetPGID {
+                    seq,
+                    pid,
+                    ppid,
+                    pgid,
+                    caller_pid,
+                    ..
+                } => {
+                    let mut fields = vec![
+                        ("PID", pid.to_string()),
+                        ("PPID", ppid.to_string()),
+                        ("PROCTRACE_SEQ", seq.to_string()),
+                    ];
+                    let message = match caller_pid {
+                        Some(caller) => {
+                            fields.push(("CALLER_PID", caller.to_string()));
+                            format!("{caller} moved {pid} into process group {pgid}")
+                        }
+                        None => format!("{pid} joined process group {pgid}"),
+                    };
+                    self.send(MESSAGE_ID_SESSION, message, &fields);
+                }
+                Event::Chdir { seq, pid, path, .. } => {
+                    let mut fields =
+                        vec![("PID", pid.to_string()), ("PROCTRACE_SEQ", seq.to_string())];
+                    let message = match path {
+                        Some(path) => {
+                            fields.push(("DIRECTORY", path.clone()));
+                            format!("{pid} changed directory to {path}")
+                        }
+                        None => format!("{pid} changed directory"),
+                    };
+                    self.send(MESSAGE_ID_CHDIR, message, &fields);
+                }
+                Event::Namespace {
+                    seq,
+                    pid,
+                    how,
+                    namespaces,
+                    ..
+                } => {
+                    let namespaces = namespaces.join(",");
+                    let message = if namespaces.is_empty() {
+                        format!("{pid} entered a namespace with {how}")
+                    } else {
+                        format!("{pid} entered namespaces {namespaces} with {how}")
+                    };
+                    self.send(
+                        MESSAGE_ID_NAMESPACE,
+                        message,
+                        &[
+                            ("PID", pid.to_string()),
+                            ("NAMESPACES", namespaces.clone()),
+                            ("PROCTRACE_SEQ", seq.to_string()),
+                        ],
+                    )
+                }
+                Event::Mount {
+                    seq,
+                    pid,
+                    target,
+                    fstype,
+                    unmount,
+                    ..
+                } => {
+                    let mut fields = vec![
+                        ("PID", pid.to_string()),
+                        ("MOUNT_TARGET", target.clone()),
+                        ("PROCTRACE_SEQ", seq.to_string()),
+                    ];
+                    if let Some(fstype) = fstype {
+                        fields.push(("FSTYPE", fstype.clone()));
+                    }
+                    let message = if *unmount {
+                        format!("{pid} unmounted {target}")
+                    } else {
+                        format!("{pid} mounted {target}")
+                    };
+                    self.send(MESSAGE_ID_MOUNT, message, &fields);
+                }
+                Event::Ptrace {
+                    seq,
+                    pid,
+                    request,
+                    target_pid,
+                    errno,
+                    ..
+                } => {
+                    let mut fields = vec![
+                        ("PID", pid.to_string()),
+                        ("PTRACE_REQUEST", request.to_string()),
+                        ("PROCTRACE_SEQ", seq.to_string()),
+                    ];
+                    if let Some(target) = target_pid {
+                        fields.push(("TARGET_PID", target.to_string()));
+                    }
+                    self.send_with_priority(
+                        PRIORITY_WARNING,
+                        MESSAGE_ID_PTRACE,
+                        format!(
+                            "{pid} called {}",
+                            describe_ptrace(*request, *target_pid, *errno)
+                        ),
+                        &fields,
+                    );
+                }
+                Event::Sandbox {
+                    seq,
+                    pid,
+                    mechanism,
+                    mode,
+                    flags,
+                    ..
+                } => self.send(
+                    MESSAGE_ID_SANDBOX,
+                    format!(
+                        "{pid} applied {}",
+                        describe_sandbox(*mechanism, mode.as_deref(), flags)
+                    ),
+                    &[
+                        ("PID", pid.to_string()),
+                        ("SANDBOX", mechanism.to_string()),
+                        ("PROCTRACE_SEQ", seq.to_string()),
+                    ],
+                ),
+                Event::Subreaper { seq, pid, .. } => self.send(
+                    MESSAGE_ID_SUBREAPER,
+                    format!("{pid} became a child subreaper"),
+                    &[("PID", pid.to_string()), ("PROCTRACE_SEQ", seq.to_string())],
+                ),
+                Event::Usage {
+                    seq, pid, usage, ..
+                } => self.send(
+                    MESSAGE_ID_USAGE,
+                    format!(
+                        "{pid} had {} page faults and {} context switches",
+                        usage.minor_faults + usage.major_faults,
+                        usage.voluntary_switches + usage.involuntary_switches
+                    ),
+                    &[
+                        ("PID", pid.to_string()),
+                        ("MINOR_FAULTS", usage.minor_faults.to_string()),
+                        ("MAJOR_FAULTS", usage.major_faults.to_string()),
+                        ("VOLUNTARY_SWITCHES", usage.voluntary_switches.to_string()),
+                        (
+                            "INVOLUNTARY_SWITCHES",
+                            usage.involuntary_switches.to_string(),
+                        ),
+                        ("PROCTRACE_SEQ", seq.to_string()),
+                    ],
+                ),
+                Event::ForkStorm {
+                    seq,
+                    pid,
+                    forks,
+                    window_ms,
+                    ..
+                } => self.send_with_priority(
+                    PRIORITY_WARNING,
+                    MESSAGE_ID_FORK_STORM,
+                    format!("fork storm under {pid}: {forks} forks in {window_ms}ms"),
+                    &[
+                        ("PID", pid.to_string()),
+                        ("FORKS", forks.to_string()),
+                        ("PROCTRACE_SEQ", seq.to_string()),
+                    ],
+                ),
+                Event::Custom {
+                    seq,
+                    pid,
+                    name,
+                    fields,
+                    ..
+                } => {
+                    // Journal field names can only contain uppercase letters, digits,
+                    // and underscores
+                    let custom_fields = fields
+                        .iter()
+                        .map(|(key, value)| {
+                            let key = key
+                                .chars()
+                                .map(|c| {
+                                    if c.is_ascii_alphanumeric() {
+                                        c.to_ascii_uppercase()
+                                    } else {
+                                        '_'
+                                    }
+                                })
+                                .collect::<String>();
+                            (format!("PROCTRACE_FIELD_{key}"), value.clone())
+                        })
+                        .collect::<Vec<_>>();
+                    let mut entry_fields = vec![
+                        ("PID", pid.to_string()),
+                        ("PROCTRACE_EVENT", name.clone()),
+                        ("PROCTRACE_SEQ", seq.to_string()),
+                    ];
+                    entry_fields.extend(
+                        custom_fields
+                            .iter()
+                            .map(|(key, value)| (key.as_str(), value.clone())),
+                    );
+                    self.send(MESSAGE_ID_CUSTOM, format!("{pid} {name}"), &entry_fields);
+                }
+                // There are far too many of these to log each one
+                Event::HostSample { .. } | Event::CpuSample { .. } | Event::RssSample { .. } => {}
+                // The recorder's own processes aren't part of what was recorded
+                Event::Overhead { .. } => {}
+            }
+            Ok(())
+        }
+
+        fn write_metadata(&mut self, metadata: &Metadata) -> Result<(), Error> {
+            let cmdline = ExecArgsKind::Args(metadata.command.clone()).joined();
+            let mut fields = vec![("CMDLINE", cmdline.clone())];
+            if let Some(pid) = metadata.root_pid {
+                fields.push(("PID", pid.to_string()));
+            }
+            self.send(
+                MESSAGE_ID_START,
+                format!("started recording {cmdline}"),
+                &fields,
+            );
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+        use crate::ingest::test::make_simple_events;
+
+        #[test]
+        fn logs_structured_entries() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("journal.socket");
+            let journal = UnixDatagram::bind(&path).unwrap();
+            let mut writer = JournalWriter::with_socket(&path).unwrap();
+
+            let events = make_simple_events(0, 0, &[("fork", 2, 1), ("exec_full", 2, 1)]);
+            for event in events.iter() {
+                writer.write_event(event).unwrap();
+            }
+            let mut buf = [0u8; 4096];
+            let n = journal.recv(&mut buf).unwrap();
+            let fork = String::from_utf8_lossy(&buf[..n]).to_string();
+            assert!(fork.contains(&format!("MESSAGE_ID={MESSAGE_ID_FORK}\n")));
+            assert!(fork.contains("PID=2\nPPID=1\n"));
+            let n = journal.recv(&mut buf).unwrap();
+            let exec = String::from_utf8_lossy(&buf[..n]).to_string();
+            assert!(exec.contains("EXE=/foo/bar\n"));
+
+            let mut entry = vec![];
+            encode_field(&mut entry, "CMDLINE", "a\nb");
+            assert_eq!(entry, b"CMDLINE\n\x03\0\0\0\0\0\0\0a\nb\n");
+        }
+    }
 }
 
 #[cfg(test)]
 pub mod test {
     use super::*;
-    use crate::models::Event;
+    use crate::ingest::test::make_simple_events;
 
     #[derive(Debug)]
     pub(crate) struct MockWriter {
@@ -57,9 +1133,69 @@ pub mod test {
     }
 
     impl EventWrite for MockWriter {
-        fn write_raw(&mut self, line: impl AsRef<[u8]>) -> Result<(), Error> {
-            self.raw.write_all(line.as_ref())?;
+        fn write_raw(&mut self, line: &[u8]) -> Result<(), Error> {
+            self.raw.write_all(line)?;
+            Ok(())
+        }
+
+        fn write_event(&mut self, event: &Event) -> Result<(), Error> {
+            self.events.push(event.clone());
             Ok(())
         }
     }
+
+    #[test]
+    fn tee_writes_to_both() {
+        let events = make_simple_events(0, 0, &[("fork", 1, 0), ("exit", 1, 0)]);
+        let mut tee = TeeWriter::new(MockWriter::new(), RawWriter::new(vec![]));
+        tee.write_raw(b"FORK: ...").unwrap();
+        for event in events.iter() {
+            tee.write_event(event).unwrap();
+        }
+        let (mock, raw) = tee.into_inner();
+        assert_eq!(mock.events, events);
+        assert_eq!(mock.raw, b"FORK: ...");
+        assert_eq!(raw.inner, b"FORK: ...\n");
+    }
+
+    #[test]
+    fn streams_events_without_raw_lines() {
+        let events = make_simple_events(0, 0, &[("fork", 1, 0), ("exit", 1, 0)]);
+        let mut writer = StreamWriter::new(vec![]);
+        writer.write_metadata(&Metadata::new(Some(1))).unwrap();
+        writer.write_raw(b"FORK: ...").unwrap();
+        for event in events.iter() {
+            writer.write_event(event).unwrap();
+        }
+        let output = String::from_utf8(writer.inner.inner).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("{\"Metadata\""));
+        assert!(lines[1].starts_with("{\"Fork\""));
+    }
+
+    #[test]
+    fn cbor_rejects_raw_lines() {
+        let mut writer = CborWriter::new(vec![]);
+        assert!(writer.write_raw(b"FORK: ...").is_err());
+        assert!(writer.inner.is_empty());
+    }
+
+    #[test]
+    fn raw_writer_reports_write_errors() {
+        struct FullDisk;
+
+        impl Write for FullDisk {
+            fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::from(std::io::ErrorKind::StorageFull))
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut writer = RawWriter::new(FullDisk);
+        assert!(writer.write_raw(b"FORK: ...").is_err());
+    }
 }
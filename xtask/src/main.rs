@@ -1,12 +1,33 @@
-use std::io::{stderr, Write};
+use std::{
+    fs::File,
+    io::{stderr, BufReader, BufWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
 
 use anyhow::Context;
 use clap::{CommandFactory, Parser, Subcommand};
+use proctrace::{
+    ingest::EventIngester,
+    models::Event,
+    render::read_events,
+    writers::{new_event_writer, NoOpWriter, TraceFormat},
+};
 
 type Error = anyhow::Error;
 
 const DOCS_DIR: &str = "docs/src/content/docs/reference";
 
+/// The feature sets that `proctrace` has to build with, besides the default one.
+///
+/// Without default features the core modules are built for targets without a
+/// filesystem (e.g. `wasm32-unknown-unknown`), and with only `fs` recordings can be
+/// read and written on hosts that can't record.
+const FEATURE_SETS: &[&[&str]] = &[
+    &["--no-default-features"],
+    &["--no-default-features", "--features", "fs"],
+];
+
 #[derive(Debug, Parser)]
 #[command(author, version)]
 #[command(max_term_width = 80)]
@@ -22,6 +43,32 @@ pub enum Command {
     GenManpages,
     #[command(name = "docs")]
     BuildDocs,
+    #[command(name = "check-features")]
+    #[command(about = "Check that proctrace builds without its default features.")]
+    CheckFeatures,
+    #[command(name = "bench-ingest")]
+    #[command(about = "Time ingesting a synthetic trace with many untracked processes.")]
+    BenchIngest {
+        /// The number of processes outside of the traced process tree.
+        #[arg(long, default_value_t = 20_000)]
+        noise: usize,
+        /// The number of processes in the traced process tree.
+        #[arg(long, default_value_t = 1_000)]
+        tracked: usize,
+    },
+    #[command(name = "bench-read")]
+    #[command(about = "Time reading a large recording through a buffer and with mmap.")]
+    BenchRead {
+        /// The number of processes in the recording, each of which has two events.
+        #[arg(long, default_value_t = 200_000)]
+        processes: usize,
+        /// How many times to read the recording with each method.
+        #[arg(long, default_value_t = 5)]
+        iterations: usize,
+        /// The format of the recording.
+        #[arg(long, default_value_t = TraceFormat::Json)]
+        format: TraceFormat,
+    },
 }
 
 fn main() -> Result<(), Error> {
@@ -29,7 +76,137 @@ fn main() -> Result<(), Error> {
     match args.command {
         Command::GenManpages => generate_manpages(),
         Command::BuildDocs => todo!(),
+        Command::CheckFeatures => check_features(),
+        Command::BenchIngest { noise, tracked } => bench_ingest(noise, tracked),
+        Command::BenchRead {
+            processes,
+            iterations,
+            format,
+        } => bench_read(processes, iterations, format),
+    }
+}
+
+/// Builds a trace where the traced process tree is interleaved with `noise` processes
+/// that are never tracked, which all stay in the ingester's buffer.
+fn synthetic_trace(root_pid: i32, noise: usize, tracked: usize) -> Vec<Event> {
+    let mut events = vec![];
+    let mut seq = 0;
+    let mut next_seq = || {
+        seq += 1;
+        seq
+    };
+    let fork = |seq: u128, parent_pid: i32, child_pid: i32| Event::Fork {
+        seq,
+        timestamp: seq,
+        parent_pid,
+        child_pid,
+        parent_pgid: parent_pid,
+        credentials: None,
+        comm: None,
+    };
+    let exit = |seq: u128, pid: i32, ppid: i32| Event::Exit {
+        seq,
+        timestamp: seq,
+        pid,
+        ppid,
+        pgid: ppid,
+        exit_code: Some(0),
+    };
+    events.push(fork(next_seq(), 1, root_pid));
+    let noise_per_tracked = noise / tracked.max(1);
+    let mut noise_pid = 1_000_000;
+    for i in 0..tracked {
+        for _ in 0..noise_per_tracked {
+            events.push(fork(next_seq(), 2, noise_pid));
+            events.push(exit(next_seq(), noise_pid, 2));
+            noise_pid += 1;
+        }
+        let child_pid = root_pid + 1 + i as i32;
+        events.push(fork(next_seq(), root_pid, child_pid));
+        events.push(exit(next_seq(), child_pid, root_pid));
     }
+    events.push(exit(next_seq(), root_pid, 1));
+    events
+}
+
+fn bench_ingest(noise: usize, tracked: usize) -> Result<(), Error> {
+    let root_pid = 10;
+    let events = synthetic_trace(root_pid, noise, tracked);
+    let n_events = events.len();
+    let mut ingester = EventIngester::new(Some(root_pid), Some(NoOpWriter));
+    let start = Instant::now();
+    for event in events.into_iter() {
+        ingester.observe_event(event)?;
+    }
+    let elapsed = start.elapsed();
+    let n_tracked = ingester.tracked_events().pids().len();
+    println!(
+        "ingested {n_events} events ({n_tracked} tracked PIDs) in {:.3}s ({:.0} events/s)",
+        elapsed.as_secs_f64(),
+        n_events as f64 / elapsed.as_secs_f64()
+    );
+    Ok(())
+}
+
+fn bench_read(processes: usize, iterations: usize, format: TraceFormat) -> Result<(), Error> {
+    let dir = tempfile::tempdir().context("failed to create tempdir")?;
+    let path = dir.path().join(format!("events.{format}"));
+    let file = File::create(&path).context("failed to create recording")?;
+    let mut writer = new_event_writer(BufWriter::new(file), format);
+    for event in synthetic_trace(10, 0, processes).iter() {
+        writer.write_event(event)?;
+    }
+    writer.finalize()?;
+    drop(writer);
+    let size = std::fs::metadata(&path)?.len();
+    println!(
+        "reading {} MiB {format} recording {iterations} times",
+        size / (1024 * 1024)
+    );
+
+    let time = |read: &dyn Fn(&Path) -> Result<(), Error>| -> Result<Duration, Error> {
+        // Once to get the file into the page cache
+        read(&path)?;
+        let start = Instant::now();
+        for _ in 0..iterations {
+            read(&path)?;
+        }
+        Ok(start.elapsed() / iterations.max(1) as u32)
+    };
+    let buffered = time(&|path| {
+        read_events(BufReader::new(File::open(path)?))?;
+        Ok(())
+    })?;
+    println!("buffered: {:.3}s per read", buffered.as_secs_f64());
+    #[cfg(target_os = "linux")]
+    {
+        let mapped = time(&|path| {
+            let mapped = proctrace::mmap::MappedFile::open(path)?;
+            proctrace::render::read_events_from_slice(mapped.as_slice())?;
+            Ok(())
+        })?;
+        println!("mapped:   {:.3}s per read", mapped.as_secs_f64());
+    }
+    Ok(())
+}
+
+fn check_features() -> Result<(), Error> {
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    for features in FEATURE_SETS {
+        eprintln!("checking proctrace with {}", features.join(" "));
+        let status = std::process::Command::new(&cargo)
+            .args(["check", "--package", "proctrace", "--all-targets"])
+            .args(*features)
+            .status()
+            .context("failed to run cargo check")?;
+        if !status.success() {
+            return Err(anyhow::anyhow!(
+                "proctrace doesn't build with {}",
+                features.join(" ")
+            ));
+        }
+    }
+    Ok(())
 }
 
 fn generate_manpages() -> Result<(), Error> {